@@ -0,0 +1 @@
+ALTER TABLE users_organizations ADD COLUMN sensitive_notifications INTEGER NOT NULL DEFAULT 15;
@@ -0,0 +1,15 @@
+CREATE TABLE login_events (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    user_uuid       VARCHAR(36),
+    username        TEXT,
+    grant_type      TEXT NOT NULL,
+    ip_address      TEXT NOT NULL,
+    device_type     INTEGER,
+    device_name     TEXT,
+    user_agent      TEXT,
+    country         TEXT,
+    success         BOOLEAN NOT NULL,
+    created_at      DATETIME NOT NULL
+);
+
+CREATE INDEX login_events_user_uuid_idx ON login_events (user_uuid);
@@ -0,0 +1,3 @@
+DROP TABLE send_accesses;
+
+ALTER TABLE sends DROP COLUMN expiration_notified;
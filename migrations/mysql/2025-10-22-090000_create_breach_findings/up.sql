@@ -0,0 +1,13 @@
+CREATE TABLE breach_findings (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    org_uuid        VARCHAR(36) NOT NULL,
+    user_uuid       VARCHAR(36) NOT NULL,
+    breach_name     TEXT NOT NULL,
+    title           TEXT NOT NULL,
+    domain          TEXT NOT NULL,
+    breach_date     TEXT,
+    data_classes    TEXT NOT NULL,
+    created_at      DATETIME NOT NULL
+);
+
+CREATE INDEX breach_findings_org_uuid_idx ON breach_findings (org_uuid);
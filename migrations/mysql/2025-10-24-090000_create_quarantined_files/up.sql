@@ -0,0 +1,12 @@
+CREATE TABLE quarantined_files (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    user_uuid       VARCHAR(36) NOT NULL,
+    org_uuid        VARCHAR(36),
+    cipher_uuid     VARCHAR(36),
+    send_uuid       VARCHAR(36),
+    file_size       BIGINT NOT NULL,
+    signature       TEXT NOT NULL,
+    created_at      DATETIME NOT NULL
+);
+
+CREATE INDEX quarantined_files_org_uuid_idx ON quarantined_files (org_uuid);
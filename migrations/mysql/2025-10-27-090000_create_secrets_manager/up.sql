@@ -0,0 +1,55 @@
+CREATE TABLE projects (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    org_uuid        VARCHAR(36) NOT NULL REFERENCES organizations (uuid),
+    name            TEXT NOT NULL,
+    creation_date   DATETIME NOT NULL,
+    revision_date   DATETIME NOT NULL
+);
+
+CREATE TABLE secrets (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    org_uuid        VARCHAR(36) NOT NULL REFERENCES organizations (uuid),
+    akey            TEXT NOT NULL,
+    value           TEXT NOT NULL,
+    note            TEXT NOT NULL,
+    creation_date   DATETIME NOT NULL,
+    revision_date   DATETIME NOT NULL
+);
+
+CREATE TABLE projects_secrets (
+    project_uuid    VARCHAR(36) NOT NULL REFERENCES projects (uuid),
+    secret_uuid     VARCHAR(36) NOT NULL REFERENCES secrets (uuid),
+    PRIMARY KEY (project_uuid, secret_uuid)
+);
+
+CREATE TABLE service_accounts (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    org_uuid        VARCHAR(36) NOT NULL REFERENCES organizations (uuid),
+    name            TEXT NOT NULL,
+    creation_date   DATETIME NOT NULL,
+    revision_date   DATETIME NOT NULL
+);
+
+CREATE TABLE projects_service_accounts (
+    project_uuid            VARCHAR(36) NOT NULL REFERENCES projects (uuid),
+    service_account_uuid    VARCHAR(36) NOT NULL REFERENCES service_accounts (uuid),
+    can_read                BOOLEAN NOT NULL DEFAULT FALSE,
+    can_write               BOOLEAN NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (project_uuid, service_account_uuid)
+);
+
+CREATE TABLE service_account_tokens (
+    uuid                    VARCHAR(36) NOT NULL PRIMARY KEY,
+    service_account_uuid    VARCHAR(36) NOT NULL REFERENCES service_accounts (uuid),
+    name                    TEXT NOT NULL,
+    client_secret_hash      TEXT NOT NULL,
+    encrypted_payload       TEXT NOT NULL,
+    akey                    TEXT NOT NULL,
+    expire_at               DATETIME,
+    creation_date           DATETIME NOT NULL,
+    revision_date           DATETIME NOT NULL
+);
+
+CREATE INDEX projects_org_uuid_idx ON projects (org_uuid);
+CREATE INDEX secrets_org_uuid_idx ON secrets (org_uuid);
+CREATE INDEX service_accounts_org_uuid_idx ON service_accounts (org_uuid);
@@ -0,0 +1,2 @@
+DROP TABLE key_connector_keys;
+ALTER TABLE users DROP COLUMN uses_key_connector;
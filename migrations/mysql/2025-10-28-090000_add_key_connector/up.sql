@@ -0,0 +1,8 @@
+ALTER TABLE users ADD COLUMN uses_key_connector BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE key_connector_keys (
+    user_uuid       VARCHAR(36) NOT NULL PRIMARY KEY REFERENCES users (uuid),
+    akey            TEXT NOT NULL,
+    creation_date   DATETIME NOT NULL,
+    revision_date   DATETIME NOT NULL
+);
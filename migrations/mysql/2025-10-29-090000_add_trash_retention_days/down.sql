@@ -0,0 +1,2 @@
+ALTER TABLE organizations DROP COLUMN trash_retention_days;
+ALTER TABLE users DROP COLUMN trash_retention_days;
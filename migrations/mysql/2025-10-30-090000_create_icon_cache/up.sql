@@ -0,0 +1,10 @@
+CREATE TABLE icon_cache (
+    domain                  VARCHAR(255) NOT NULL PRIMARY KEY,
+    icon_type               TEXT,
+    etag                    TEXT,
+    upstream_url            TEXT,
+    upstream_etag           TEXT,
+    upstream_last_modified  TEXT,
+    miss                    BOOLEAN NOT NULL DEFAULT FALSE,
+    fetched_at              DATETIME NOT NULL
+);
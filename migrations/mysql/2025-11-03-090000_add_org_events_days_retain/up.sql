@@ -0,0 +1 @@
+ALTER TABLE organizations ADD COLUMN events_days_retain INTEGER;
@@ -0,0 +1,11 @@
+CREATE TABLE cipher_shares (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    cipher_uuid     VARCHAR(40) NOT NULL REFERENCES ciphers (uuid),
+    owner_uuid      VARCHAR(40) NOT NULL REFERENCES users (uuid),
+    grantee_uuid    VARCHAR(40) NOT NULL REFERENCES users (uuid),
+    akey            TEXT NOT NULL,
+    data            TEXT NOT NULL,
+    created_at      DATETIME NOT NULL,
+    revision_date   DATETIME NOT NULL,
+    UNIQUE (cipher_uuid, grantee_uuid)
+);
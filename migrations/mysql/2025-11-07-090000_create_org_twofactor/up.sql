@@ -0,0 +1,9 @@
+CREATE TABLE org_twofactor (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    org_uuid        VARCHAR(40) NOT NULL REFERENCES organizations (uuid),
+    atype           INTEGER NOT NULL,
+    enabled         BOOLEAN NOT NULL,
+    data            TEXT NOT NULL,
+    revision_date   DATETIME NOT NULL,
+    UNIQUE (org_uuid, atype)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE organizations DROP COLUMN ip_allowlist;
+ALTER TABLE organizations DROP COLUMN ip_denylist;
@@ -0,0 +1,17 @@
+CREATE TABLE cipher_history (
+    uuid             VARCHAR(36) NOT NULL PRIMARY KEY,
+    cipher_uuid      VARCHAR(40) NOT NULL REFERENCES ciphers (uuid),
+    user_uuid        VARCHAR(40),
+    atype            INTEGER NOT NULL,
+    akey             TEXT,
+    name             TEXT NOT NULL,
+    notes            TEXT,
+    fields           TEXT,
+    data             TEXT NOT NULL,
+    password_history TEXT,
+    reprompt         INTEGER,
+    revision_date    DATETIME NOT NULL,
+    created_at       DATETIME NOT NULL
+);
+
+CREATE INDEX cipher_history_cipher_uuid_idx ON cipher_history (cipher_uuid);
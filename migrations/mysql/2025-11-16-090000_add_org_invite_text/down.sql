@@ -0,0 +1,4 @@
+ALTER TABLE organizations DROP COLUMN invite_subject;
+ALTER TABLE organizations DROP COLUMN invite_body;
+ALTER TABLE organizations DROP COLUMN invite_reply_to;
+ALTER TABLE mail_queue DROP COLUMN reply_to;
@@ -0,0 +1,29 @@
+CREATE TABLE providers (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    name            TEXT NOT NULL,
+    business_name   TEXT,
+    billing_email   TEXT NOT NULL,
+    enabled         BOOLEAN NOT NULL,
+    created_at      DATETIME NOT NULL,
+    updated_at      DATETIME NOT NULL
+);
+
+CREATE TABLE provider_users (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    provider_uuid   VARCHAR(40) NOT NULL REFERENCES providers (uuid),
+    user_uuid       VARCHAR(40) NOT NULL REFERENCES users (uuid),
+    atype           INTEGER NOT NULL,
+    status          INTEGER NOT NULL,
+    akey            TEXT,
+    created_at      DATETIME NOT NULL,
+    UNIQUE (provider_uuid, user_uuid)
+);
+
+CREATE TABLE provider_organizations (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    provider_uuid   VARCHAR(40) NOT NULL REFERENCES providers (uuid),
+    org_uuid        VARCHAR(40) NOT NULL REFERENCES organizations (uuid),
+    akey            TEXT,
+    created_at      DATETIME NOT NULL,
+    UNIQUE (org_uuid)
+);
@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN new_device_email BOOLEAN NOT NULL DEFAULT TRUE;
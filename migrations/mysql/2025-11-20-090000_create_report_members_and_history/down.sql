@@ -0,0 +1,2 @@
+DROP TABLE report_history;
+DROP TABLE report_members;
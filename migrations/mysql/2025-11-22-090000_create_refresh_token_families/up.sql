@@ -0,0 +1,12 @@
+CREATE TABLE refresh_token_families (
+    user_uuid       VARCHAR(36) NOT NULL,
+    device_uuid     VARCHAR(36) NOT NULL,
+    family_uuid     VARCHAR(36) NOT NULL,
+    generation      INTEGER NOT NULL,
+    created_at      DATETIME NOT NULL,
+    rotated_at      DATETIME NOT NULL,
+    last_ip         TEXT,
+
+    PRIMARY KEY (user_uuid, device_uuid),
+    FOREIGN KEY (user_uuid) REFERENCES users (uuid)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE attachment_uploads (
+    attachment_id   VARCHAR(36) NOT NULL PRIMARY KEY,
+    cipher_uuid     VARCHAR(36) NOT NULL,
+    user_uuid       VARCHAR(36) NOT NULL,
+    file_size       BIGINT NOT NULL,
+    created_at      DATETIME NOT NULL,
+    updated_at      DATETIME NOT NULL
+);
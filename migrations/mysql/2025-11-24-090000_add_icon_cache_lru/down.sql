@@ -0,0 +1,4 @@
+DROP INDEX icon_cache_accessed_at_idx ON icon_cache;
+
+ALTER TABLE icon_cache DROP COLUMN file_size;
+ALTER TABLE icon_cache DROP COLUMN accessed_at;
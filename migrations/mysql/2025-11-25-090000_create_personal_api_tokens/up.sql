@@ -0,0 +1,12 @@
+CREATE TABLE personal_api_tokens (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    user_uuid       VARCHAR(36) NOT NULL,
+    name            TEXT NOT NULL,
+    scopes          TEXT NOT NULL,
+    token_hash      TEXT NOT NULL,
+    expire_at       DATETIME,
+    last_used_at    DATETIME,
+    creation_date   DATETIME NOT NULL,
+
+    FOREIGN KEY (user_uuid) REFERENCES users (uuid)
+);
@@ -0,0 +1,15 @@
+CREATE TABLE policy_violations (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    org_uuid        VARCHAR(36) NOT NULL,
+    user_uuid       VARCHAR(36) NOT NULL,
+    policy_type     INTEGER NOT NULL,
+    action          INTEGER NOT NULL,
+    reason          TEXT NOT NULL,
+    actor_uuid      VARCHAR(36) NOT NULL,
+    created_at      DATETIME NOT NULL,
+
+    FOREIGN KEY (org_uuid) REFERENCES organizations (uuid),
+    FOREIGN KEY (user_uuid) REFERENCES users (uuid)
+);
+
+CREATE INDEX policy_violations_org_uuid_idx ON policy_violations (org_uuid);
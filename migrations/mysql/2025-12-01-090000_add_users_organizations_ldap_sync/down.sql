@@ -0,0 +1,2 @@
+ALTER TABLE users_organizations DROP COLUMN ldap_dn;
+ALTER TABLE users_organizations DROP COLUMN ldap_revoked;
@@ -0,0 +1 @@
+ALTER TABLE personal_api_tokens DROP COLUMN security_stamp;
@@ -0,0 +1,2 @@
+ALTER TABLE personal_api_tokens ADD COLUMN security_stamp VARCHAR(36) NOT NULL DEFAULT '';
+UPDATE personal_api_tokens SET security_stamp = (SELECT security_stamp FROM users WHERE users.uuid = personal_api_tokens.user_uuid);
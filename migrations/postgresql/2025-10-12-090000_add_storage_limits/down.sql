@@ -0,0 +1,3 @@
+ALTER TABLE users DROP COLUMN attachment_limit;
+ALTER TABLE users DROP COLUMN send_limit;
+ALTER TABLE organizations DROP COLUMN attachment_limit;
@@ -0,0 +1,13 @@
+CREATE TABLE org_domains (
+    uuid                VARCHAR(36) NOT NULL PRIMARY KEY,
+    org_uuid            VARCHAR(40) NOT NULL REFERENCES organizations (uuid),
+    domain_name         VARCHAR(255) NOT NULL,
+    verified_at         TIMESTAMP,
+    member_type         INTEGER NOT NULL DEFAULT 2,
+    collections         TEXT NOT NULL,
+    auto_confirm        BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at          TIMESTAMP NOT NULL,
+    UNIQUE (org_uuid, domain_name)
+);
+
+CREATE INDEX org_domains_domain_name_idx ON org_domains (domain_name);
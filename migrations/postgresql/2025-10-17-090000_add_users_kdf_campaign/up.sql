@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN kdf_campaign_at TIMESTAMP;
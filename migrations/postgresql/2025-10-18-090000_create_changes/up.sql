@@ -0,0 +1,9 @@
+CREATE TABLE changes (
+    seq             BIGSERIAL NOT NULL PRIMARY KEY,
+    entity_type     VARCHAR(16) NOT NULL,
+    entity_uuid     VARCHAR(36) NOT NULL,
+    action          VARCHAR(16) NOT NULL,
+    created_at      TIMESTAMP NOT NULL
+);
+
+CREATE INDEX changes_created_at_idx ON changes (created_at);
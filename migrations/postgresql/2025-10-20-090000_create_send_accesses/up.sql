@@ -0,0 +1,11 @@
+CREATE TABLE send_accesses (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    send_uuid       CHAR(36) NOT NULL REFERENCES sends (uuid),
+    ip_hash         TEXT NOT NULL,
+    success         BOOLEAN NOT NULL,
+    created_at      TIMESTAMP NOT NULL
+);
+
+CREATE INDEX send_accesses_send_uuid_idx ON send_accesses (send_uuid);
+
+ALTER TABLE sends ADD COLUMN expiration_notified BOOLEAN NOT NULL DEFAULT FALSE;
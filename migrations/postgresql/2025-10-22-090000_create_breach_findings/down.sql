@@ -0,0 +1 @@
+DROP TABLE breach_findings;
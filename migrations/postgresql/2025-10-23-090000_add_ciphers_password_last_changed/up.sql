@@ -0,0 +1 @@
+ALTER TABLE ciphers ADD COLUMN password_last_changed TIMESTAMP;
@@ -0,0 +1,4 @@
+ALTER TABLE users_organizations ADD COLUMN permissions INTEGER NOT NULL DEFAULT 0;
+
+-- Custom members with access to all collections had the three collection permissions checked
+UPDATE users_organizations SET permissions = 56 WHERE atype = 3 AND access_all = TRUE;
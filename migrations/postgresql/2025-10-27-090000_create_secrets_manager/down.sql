@@ -0,0 +1,6 @@
+DROP TABLE service_account_tokens;
+DROP TABLE projects_service_accounts;
+DROP TABLE service_accounts;
+DROP TABLE projects_secrets;
+DROP TABLE secrets;
+DROP TABLE projects;
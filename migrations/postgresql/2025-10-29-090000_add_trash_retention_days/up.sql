@@ -0,0 +1,2 @@
+ALTER TABLE organizations ADD COLUMN trash_retention_days INTEGER;
+ALTER TABLE users ADD COLUMN trash_retention_days INTEGER;
@@ -0,0 +1,3 @@
+ALTER TABLE org_domains ADD COLUMN txt TEXT;
+ALTER TABLE org_domains ADD COLUMN last_checked_at TIMESTAMP;
+ALTER TABLE org_domains ADD COLUMN check_count INTEGER NOT NULL DEFAULT 0;
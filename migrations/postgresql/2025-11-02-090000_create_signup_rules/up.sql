@@ -0,0 +1,10 @@
+CREATE TABLE signup_rules (
+    uuid                VARCHAR(36) NOT NULL PRIMARY KEY,
+    pattern             VARCHAR(255) NOT NULL,
+    action              INTEGER NOT NULL,
+    position            INTEGER NOT NULL,
+    org_uuid            VARCHAR(40) REFERENCES organizations (uuid),
+    collection_uuid     VARCHAR(40),
+    bypass_invitation   BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at          TIMESTAMP NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE reports DROP COLUMN delta_notified_at;
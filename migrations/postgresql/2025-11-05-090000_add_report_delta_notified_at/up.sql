@@ -0,0 +1 @@
+ALTER TABLE reports ADD COLUMN delta_notified_at TIMESTAMP;
@@ -0,0 +1,2 @@
+ALTER TABLE organizations ADD COLUMN ip_allowlist TEXT;
+ALTER TABLE organizations ADD COLUMN ip_denylist TEXT;
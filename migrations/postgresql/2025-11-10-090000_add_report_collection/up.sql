@@ -0,0 +1 @@
+ALTER TABLE reports ADD COLUMN collection_uuid VARCHAR(36);
@@ -0,0 +1,14 @@
+CREATE TABLE mail_queue (
+    uuid            VARCHAR(36) NOT NULL PRIMARY KEY,
+    address         TEXT NOT NULL,
+    subject         TEXT NOT NULL,
+    body_html       TEXT NOT NULL,
+    body_text       TEXT NOT NULL,
+    attempts        INTEGER NOT NULL,
+    last_error      TEXT,
+    created_at      TIMESTAMP NOT NULL,
+    next_attempt_at TIMESTAMP NOT NULL,
+    failed_at       TIMESTAMP
+);
+
+CREATE INDEX mail_queue_next_attempt_at_idx ON mail_queue (next_attempt_at);
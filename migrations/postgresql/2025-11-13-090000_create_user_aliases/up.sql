@@ -0,0 +1,7 @@
+CREATE TABLE user_aliases (
+    email      VARCHAR(255) NOT NULL PRIMARY KEY,
+    user_uuid  VARCHAR(40) NOT NULL REFERENCES users (uuid),
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX user_aliases_user_uuid_idx ON user_aliases (user_uuid);
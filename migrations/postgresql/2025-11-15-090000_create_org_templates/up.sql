@@ -0,0 +1,6 @@
+CREATE TABLE org_templates (
+    name       VARCHAR(255) NOT NULL PRIMARY KEY,
+    data       TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
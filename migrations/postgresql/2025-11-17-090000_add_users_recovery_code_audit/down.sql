@@ -0,0 +1,2 @@
+ALTER TABLE users DROP COLUMN totp_recover_viewed_at;
+ALTER TABLE users DROP COLUMN totp_recover_used_at;
@@ -0,0 +1,23 @@
+CREATE TABLE report_members (
+    org_uuid        VARCHAR(36) NOT NULL,
+    user_uuid       VARCHAR(36) NOT NULL,
+    exposed_count   INTEGER NOT NULL,
+    weak_count      INTEGER NOT NULL,
+    reused_count    INTEGER NOT NULL,
+    updated_at      TIMESTAMP NOT NULL,
+
+    PRIMARY KEY (org_uuid, user_uuid),
+    FOREIGN KEY (org_uuid) REFERENCES organizations (uuid),
+    FOREIGN KEY (user_uuid) REFERENCES users (uuid)
+);
+
+CREATE TABLE report_history (
+    org_uuid        VARCHAR(36) NOT NULL,
+    day             DATE NOT NULL,
+    exposed_count   INTEGER NOT NULL,
+    weak_count      INTEGER NOT NULL,
+    reused_count    INTEGER NOT NULL,
+
+    PRIMARY KEY (org_uuid, day),
+    FOREIGN KEY (org_uuid) REFERENCES organizations (uuid)
+);
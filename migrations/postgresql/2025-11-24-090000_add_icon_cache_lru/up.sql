@@ -0,0 +1,6 @@
+ALTER TABLE icon_cache ADD COLUMN file_size BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE icon_cache ADD COLUMN accessed_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+
+UPDATE icon_cache SET accessed_at = fetched_at;
+
+CREATE INDEX icon_cache_accessed_at_idx ON icon_cache (accessed_at);
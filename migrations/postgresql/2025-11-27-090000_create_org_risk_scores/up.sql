@@ -0,0 +1,18 @@
+CREATE TABLE org_risk_scores (
+    org_uuid                VARCHAR(36) NOT NULL,
+    day                     DATE NOT NULL,
+    score                   INTEGER NOT NULL,
+    exposure_points         INTEGER NOT NULL,
+    trend_points            INTEGER NOT NULL,
+    two_factor_points       INTEGER NOT NULL,
+    staleness_points        INTEGER NOT NULL,
+    exposed_count           INTEGER NOT NULL,
+    previous_exposed_count  INTEGER,
+    member_count            INTEGER NOT NULL,
+    two_factor_count        INTEGER NOT NULL,
+    report_age_days         INTEGER,
+    created_at              TIMESTAMP NOT NULL,
+
+    PRIMARY KEY (org_uuid, day),
+    FOREIGN KEY (org_uuid) REFERENCES organizations (uuid)
+);
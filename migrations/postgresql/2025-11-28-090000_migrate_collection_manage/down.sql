@@ -0,0 +1 @@
+-- The stored permissions can't be told apart from the migrated ones, nothing to revert
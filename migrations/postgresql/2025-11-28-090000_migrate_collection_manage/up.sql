@@ -0,0 +1,23 @@
+-- Managers used to manage every collection they could edit, keep that as the stored `manage` permission.
+-- A membership type 3 is a Manager.
+UPDATE users_collections
+SET manage = TRUE
+WHERE read_only = FALSE AND hide_passwords = FALSE AND EXISTS (
+    SELECT 1 FROM users_organizations uo
+    JOIN collections c ON c.org_uuid = uo.org_uuid
+    WHERE uo.user_uuid = users_collections.user_uuid
+    AND c.uuid = users_collections.collection_uuid
+    AND uo.atype = 3
+);
+
+-- The collections a Manager can edit through a group aren't stored for the member,
+-- `CollectionGroup::manage_for` derives the permission from the group instead.
+
+-- Managing a collection includes editing its items and seeing their passwords
+UPDATE users_collections
+SET read_only = FALSE, hide_passwords = FALSE
+WHERE manage = TRUE;
+
+UPDATE collections_groups
+SET read_only = FALSE, hide_passwords = FALSE
+WHERE manage = TRUE;
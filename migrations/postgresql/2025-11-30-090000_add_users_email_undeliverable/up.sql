@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN email_undeliverable_at TIMESTAMP;
+ALTER TABLE users ADD COLUMN email_undeliverable_reason TEXT;
@@ -0,0 +1 @@
+ALTER TABLE user_aliases ADD COLUMN verification_attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,5 @@
+-- The last change of any secret, project or access policy of an organization, deletes included
+CREATE TABLE secrets_revisions (
+    org_uuid        VARCHAR(36) NOT NULL PRIMARY KEY REFERENCES organizations (uuid),
+    revision_date   TIMESTAMP NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE reports ADD COLUMN threshold_exceeded_since DATETIME;
@@ -0,0 +1,15 @@
+CREATE TABLE login_events (
+    uuid            TEXT NOT NULL PRIMARY KEY,
+    user_uuid       TEXT,
+    username        TEXT,
+    grant_type      TEXT NOT NULL,
+    ip_address      TEXT NOT NULL,
+    device_type     INTEGER,
+    device_name     TEXT,
+    user_agent      TEXT,
+    country         TEXT,
+    success         BOOLEAN NOT NULL,
+    created_at      DATETIME NOT NULL
+);
+
+CREATE INDEX login_events_user_uuid_idx ON login_events (user_uuid);
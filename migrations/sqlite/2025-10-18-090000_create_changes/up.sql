@@ -0,0 +1,9 @@
+CREATE TABLE changes (
+    seq             INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    entity_type     TEXT NOT NULL,
+    entity_uuid     TEXT NOT NULL,
+    action          TEXT NOT NULL,
+    created_at      DATETIME NOT NULL
+);
+
+CREATE INDEX changes_created_at_idx ON changes (created_at);
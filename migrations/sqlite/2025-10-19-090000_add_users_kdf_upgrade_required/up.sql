@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN kdf_upgrade_required BOOLEAN NOT NULL DEFAULT 0;
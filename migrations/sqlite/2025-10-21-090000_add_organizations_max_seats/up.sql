@@ -0,0 +1 @@
+ALTER TABLE organizations ADD COLUMN max_seats INTEGER;
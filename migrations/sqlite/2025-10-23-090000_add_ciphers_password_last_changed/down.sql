@@ -0,0 +1 @@
+ALTER TABLE ciphers DROP COLUMN password_last_changed;
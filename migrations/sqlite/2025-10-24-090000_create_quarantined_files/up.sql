@@ -0,0 +1,12 @@
+CREATE TABLE quarantined_files (
+    uuid            TEXT NOT NULL PRIMARY KEY,
+    user_uuid       TEXT NOT NULL,
+    org_uuid        TEXT,
+    cipher_uuid     TEXT,
+    send_uuid       TEXT,
+    file_size       BIGINT NOT NULL,
+    signature       TEXT NOT NULL,
+    created_at      DATETIME NOT NULL
+);
+
+CREATE INDEX quarantined_files_org_uuid_idx ON quarantined_files (org_uuid);
@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN self_deleted BOOLEAN NOT NULL DEFAULT 0;
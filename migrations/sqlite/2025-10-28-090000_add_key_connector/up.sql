@@ -0,0 +1,8 @@
+ALTER TABLE users ADD COLUMN uses_key_connector BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE TABLE key_connector_keys (
+    user_uuid       TEXT NOT NULL PRIMARY KEY REFERENCES users (uuid),
+    akey            TEXT NOT NULL,
+    creation_date   DATETIME NOT NULL,
+    revision_date   DATETIME NOT NULL
+);
@@ -0,0 +1,3 @@
+ALTER TABLE org_domains DROP COLUMN txt;
+ALTER TABLE org_domains DROP COLUMN last_checked_at;
+ALTER TABLE org_domains DROP COLUMN check_count;
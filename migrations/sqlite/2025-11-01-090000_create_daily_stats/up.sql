@@ -0,0 +1,10 @@
+CREATE TABLE daily_stats (
+    day                 DATE NOT NULL PRIMARY KEY,
+    users               BIGINT NOT NULL,
+    active_users        BIGINT NOT NULL,
+    ciphers             BIGINT NOT NULL,
+    attachments_size    BIGINT NOT NULL,
+    exposed_personal    BIGINT NOT NULL,
+    exposed_org         BIGINT NOT NULL,
+    created_at          DATETIME NOT NULL
+);
@@ -0,0 +1 @@
+DROP TABLE signup_rules;
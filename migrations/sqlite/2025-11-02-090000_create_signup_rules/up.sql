@@ -0,0 +1,10 @@
+CREATE TABLE signup_rules (
+    uuid                TEXT NOT NULL PRIMARY KEY,
+    pattern             TEXT NOT NULL,
+    action              INTEGER NOT NULL,
+    position            INTEGER NOT NULL,
+    org_uuid            TEXT REFERENCES organizations (uuid),
+    collection_uuid     TEXT,
+    bypass_invitation   BOOLEAN NOT NULL DEFAULT 0,
+    created_at          DATETIME NOT NULL
+);
@@ -0,0 +1,11 @@
+CREATE TABLE cipher_shares (
+    uuid            TEXT NOT NULL PRIMARY KEY,
+    cipher_uuid     TEXT NOT NULL REFERENCES ciphers (uuid),
+    owner_uuid      TEXT NOT NULL REFERENCES users (uuid),
+    grantee_uuid    TEXT NOT NULL REFERENCES users (uuid),
+    akey            TEXT NOT NULL,
+    data            TEXT NOT NULL,
+    created_at      DATETIME NOT NULL,
+    revision_date   DATETIME NOT NULL,
+    UNIQUE (cipher_uuid, grantee_uuid)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE user_aliases (
+    email      TEXT NOT NULL PRIMARY KEY,
+    user_uuid  TEXT NOT NULL REFERENCES users (uuid),
+    created_at DATETIME NOT NULL
+);
+
+CREATE INDEX user_aliases_user_uuid_idx ON user_aliases (user_uuid);
@@ -0,0 +1,6 @@
+CREATE TABLE org_templates (
+    name       TEXT NOT NULL PRIMARY KEY,
+    data       TEXT NOT NULL,
+    created_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL
+);
@@ -0,0 +1,4 @@
+ALTER TABLE organizations ADD COLUMN invite_subject TEXT;
+ALTER TABLE organizations ADD COLUMN invite_body TEXT;
+ALTER TABLE organizations ADD COLUMN invite_reply_to TEXT;
+ALTER TABLE mail_queue ADD COLUMN reply_to TEXT;
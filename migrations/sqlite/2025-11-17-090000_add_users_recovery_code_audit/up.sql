@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN totp_recover_viewed_at DATETIME;
+ALTER TABLE users ADD COLUMN totp_recover_used_at DATETIME;
@@ -0,0 +1,29 @@
+CREATE TABLE providers (
+    uuid            TEXT NOT NULL PRIMARY KEY,
+    name            TEXT NOT NULL,
+    business_name   TEXT,
+    billing_email   TEXT NOT NULL,
+    enabled         BOOLEAN NOT NULL,
+    created_at      DATETIME NOT NULL,
+    updated_at      DATETIME NOT NULL
+);
+
+CREATE TABLE provider_users (
+    uuid            TEXT NOT NULL PRIMARY KEY,
+    provider_uuid   TEXT NOT NULL REFERENCES providers (uuid),
+    user_uuid       TEXT NOT NULL REFERENCES users (uuid),
+    atype           INTEGER NOT NULL,
+    status          INTEGER NOT NULL,
+    akey            TEXT,
+    created_at      DATETIME NOT NULL,
+    UNIQUE (provider_uuid, user_uuid)
+);
+
+CREATE TABLE provider_organizations (
+    uuid            TEXT NOT NULL PRIMARY KEY,
+    provider_uuid   TEXT NOT NULL REFERENCES providers (uuid),
+    org_uuid        TEXT NOT NULL REFERENCES organizations (uuid),
+    akey            TEXT,
+    created_at      DATETIME NOT NULL,
+    UNIQUE (org_uuid)
+);
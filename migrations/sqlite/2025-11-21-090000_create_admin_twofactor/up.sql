@@ -0,0 +1,12 @@
+CREATE TABLE admin_twofactor (
+    atype           INTEGER NOT NULL PRIMARY KEY,
+    data            TEXT NOT NULL,
+    last_used       BIGINT NOT NULL,
+    created_at      DATETIME NOT NULL
+);
+
+CREATE TABLE admin_recovery_codes (
+    uuid            TEXT NOT NULL PRIMARY KEY,
+    code_hash       TEXT NOT NULL,
+    created_at      DATETIME NOT NULL
+);
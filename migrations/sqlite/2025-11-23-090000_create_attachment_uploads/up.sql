@@ -0,0 +1,8 @@
+CREATE TABLE attachment_uploads (
+    attachment_id   TEXT NOT NULL PRIMARY KEY,
+    cipher_uuid     TEXT NOT NULL,
+    user_uuid       TEXT NOT NULL,
+    file_size       BIGINT NOT NULL,
+    created_at      DATETIME NOT NULL,
+    updated_at      DATETIME NOT NULL
+);
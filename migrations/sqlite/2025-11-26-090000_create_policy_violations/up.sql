@@ -0,0 +1,15 @@
+CREATE TABLE policy_violations (
+    uuid            TEXT NOT NULL PRIMARY KEY,
+    org_uuid        TEXT NOT NULL,
+    user_uuid       TEXT NOT NULL,
+    policy_type     INTEGER NOT NULL,
+    action          INTEGER NOT NULL,
+    reason          TEXT NOT NULL,
+    actor_uuid      TEXT NOT NULL,
+    created_at      DATETIME NOT NULL,
+
+    FOREIGN KEY (org_uuid) REFERENCES organizations (uuid),
+    FOREIGN KEY (user_uuid) REFERENCES users (uuid)
+);
+
+CREATE INDEX policy_violations_org_uuid_idx ON policy_violations (org_uuid);
@@ -0,0 +1 @@
+DROP TABLE org_risk_scores;
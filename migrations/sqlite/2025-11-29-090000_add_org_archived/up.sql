@@ -0,0 +1 @@
+ALTER TABLE organizations ADD COLUMN archived_at DATETIME;
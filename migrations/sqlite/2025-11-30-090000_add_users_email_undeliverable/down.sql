@@ -0,0 +1,2 @@
+ALTER TABLE users DROP COLUMN email_undeliverable_at;
+ALTER TABLE users DROP COLUMN email_undeliverable_reason;
@@ -0,0 +1,2 @@
+ALTER TABLE users_organizations ADD COLUMN ldap_dn TEXT;
+ALTER TABLE users_organizations ADD COLUMN ldap_revoked BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE personal_api_tokens ADD COLUMN security_stamp TEXT NOT NULL DEFAULT '';
+UPDATE personal_api_tokens SET security_stamp = (SELECT security_stamp FROM users WHERE users.uuid = personal_api_tokens.user_uuid);
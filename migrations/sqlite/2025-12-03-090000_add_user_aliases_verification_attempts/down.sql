@@ -0,0 +1 @@
+ALTER TABLE user_aliases DROP COLUMN verification_attempts;
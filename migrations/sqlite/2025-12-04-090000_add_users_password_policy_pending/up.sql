@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN password_policy_pending BOOLEAN NOT NULL DEFAULT 0;
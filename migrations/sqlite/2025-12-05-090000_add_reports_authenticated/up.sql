@@ -0,0 +1 @@
+ALTER TABLE reports ADD COLUMN authenticated BOOLEAN NOT NULL DEFAULT 0;
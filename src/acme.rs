@@ -0,0 +1,523 @@
+//
+// Obtains and renews the TLS certificate of the server with ACME (RFC 8555), for example from Let's Encrypt.
+// The domain is validated with the HTTP-01 or the TLS-ALPN-01 challenge (RFC 8737), the latter on the server port.
+// Rocket can't swap its certificate while running, so serving a renewed one takes a restart of the server within the
+// process. This is not a graceful reload: open requests get the shutdown grace period and the port is closed in between.
+//
+use std::{
+    net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener as StdTcpListener},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use data_encoding::BASE64URL_NOPAD;
+use openssl::{
+    asn1::{Asn1Object, Asn1OctetString, Asn1Time},
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey},
+    ecdsa::EcdsaSig,
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{PKey, Private},
+    sha::sha256,
+    ssl::{select_next_proto, AlpnError, SslAcceptor, SslMethod},
+    stack::Stack,
+    x509::{extension::SubjectAlternativeName, X509Extension, X509NameBuilder, X509ReqBuilder, X509},
+};
+use reqwest::{header, Client, Response, StatusCode};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+
+use crate::{error::Error, http_client::get_reqwest_client_builder, CONFIG};
+
+// The protocol the TLS-ALPN-01 challenge is answered on, in the wire format of ALPN
+const ACME_TLS_ALPN: &[u8] = b"\x0aacme-tls/1";
+// The id-pe-acmeIdentifier extension of the TLS-ALPN-01 challenge certificate
+const ACME_IDENTIFIER_OID: &str = "1.3.6.1.5.5.7.1.31";
+
+// The end of the headers of the HTTP-01 responses, each connection answers a single request
+const HTTP_CLOSE: &str = "Connection: close\r\n\r\n";
+
+const POLL_ATTEMPTS: u32 = 30;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Set when the server is shut down to serve a renewed certificate, instead of to exit
+static RESTART: AtomicBool = AtomicBool::new(false);
+
+/// Whether the server was shut down to be restarted with a renewed certificate, this resets the flag.
+pub fn take_restart() -> bool {
+    RESTART.swap(false, Ordering::Relaxed)
+}
+
+fn cert_path() -> PathBuf {
+    PathBuf::from(CONFIG.acme_folder()).join("cert.pem")
+}
+
+fn key_path() -> PathBuf {
+    PathBuf::from(CONFIG.acme_folder()).join("key.pem")
+}
+
+fn account_key_path() -> PathBuf {
+    PathBuf::from(CONFIG.acme_folder()).join("account.pem")
+}
+
+fn account_url_path() -> PathBuf {
+    PathBuf::from(CONFIG.acme_folder()).join("account_url")
+}
+
+/// The TLS settings of Rocket for the certificate obtained with ACME, when it is enabled.
+pub fn tls_config() -> Option<rocket::config::TlsConfig> {
+    CONFIG.acme_enabled().then(|| rocket::config::TlsConfig::from_paths(cert_path(), key_path()))
+}
+
+/// Whether there is no certificate for `ACME_DOMAIN` yet, or it expires within `ACME_RENEW_DAYS`.
+fn renewal_due() -> bool {
+    let Some(cert) = std::fs::read(cert_path()).ok().and_then(|pem| X509::from_pem(&pem).ok()) else {
+        return true;
+    };
+
+    let domain = CONFIG.acme_domain();
+    let for_domain =
+        cert.subject_alt_names().is_some_and(|names| names.iter().any(|name| name.dnsname() == Some(domain.as_str())));
+    if !for_domain {
+        return true;
+    }
+
+    match Asn1Time::days_from_now(CONFIG.acme_renew_days() as u32) {
+        Ok(threshold) => cert.not_after() < threshold,
+        Err(_) => true,
+    }
+}
+
+/// Obtains a certificate before the server is launched, when there is none yet or it is due for renewal.
+/// A failed renewal keeps the current certificate, without any certificate the server can't be launched.
+pub async fn ensure_certificate() -> Result<(), Error> {
+    if !CONFIG.acme_enabled() || !renewal_due() {
+        return Ok(());
+    }
+
+    info!("Obtaining a certificate for {} with ACME", CONFIG.acme_domain());
+    match obtain_certificate().await {
+        Err(e) if cert_path().exists() && key_path().exists() => {
+            error!("Error renewing the certificate with ACME, the current certificate is kept: {e:?}");
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Renews the certificate once it is due and restarts the server to serve it, which drops the connections that
+/// outlive the shutdown grace period.
+/// The TLS-ALPN-01 challenge is answered on the port of the server, with it the certificate is renewed while the server
+/// is stopped.
+pub async fn acme_renew_job() {
+    if !CONFIG.acme_enabled() || !renewal_due() {
+        return;
+    }
+
+    if CONFIG.acme_challenge() == "http-01" {
+        info!("Renewing the certificate for {} with ACME", CONFIG.acme_domain());
+        if let Err(e) = obtain_certificate().await {
+            error!("Error renewing the certificate with ACME: {e:?}");
+            return;
+        }
+    }
+
+    info!("Restarting the server to serve the renewed certificate");
+    RESTART.store(true, Ordering::Relaxed);
+    crate::shutdown_rocket();
+}
+
+async fn obtain_certificate() -> Result<(), Error> {
+    let domain = CONFIG.acme_domain();
+    tokio::fs::create_dir_all(CONFIG.acme_folder()).await?;
+
+    let mut acme = AcmeClient::new().await?;
+    acme.register().await?;
+
+    let new_order = acme.directory_url("newOrder")?;
+    let response = acme.post(&new_order, Some(&json!({ "identifiers": [{ "type": "dns", "value": domain }] }))).await?;
+    let order_url = location(&response)?;
+    let order: Value = response.json().await?;
+
+    let authorizations: Vec<String> = order["authorizations"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|a| a.as_str().map(str::to_string))
+        .collect();
+    for authorization in authorizations {
+        acme.authorize(&authorization, &domain).await?;
+    }
+    let order = acme.poll(&order_url, "ready").await?;
+
+    let key = new_key()?;
+    let Some(finalize_url) = order["finalize"].as_str() else {
+        err!("The ACME order has no finalize URL")
+    };
+    let csr = certificate_request(&domain, &key)?;
+    acme.post(finalize_url, Some(&json!({ "csr": BASE64URL_NOPAD.encode(&csr) }))).await?;
+
+    let order = acme.poll(&order_url, "valid").await?;
+    let Some(certificate_url) = order["certificate"].as_str() else {
+        err!("The ACME order has no certificate URL")
+    };
+    let chain = acme.post(certificate_url, None).await?.text().await?;
+
+    // Write the key first, a certificate without its key is never loaded
+    write_atomically(key_path(), &key.private_key_to_pem_pkcs8()?).await?;
+    write_atomically(cert_path(), chain.as_bytes()).await?;
+
+    info!("Obtained a certificate for {domain} with ACME");
+    Ok(())
+}
+
+async fn write_atomically(path: PathBuf, contents: &[u8]) -> Result<(), Error> {
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, contents).await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    Ok(())
+}
+
+fn location(response: &Response) -> Result<String, Error> {
+    match response.headers().get(header::LOCATION).and_then(|l| l.to_str().ok()) {
+        Some(location) => Ok(location.to_string()),
+        None => err!("The ACME response has no Location header"),
+    }
+}
+
+fn new_key() -> Result<PKey<Private>, Error> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    Ok(PKey::from_ec_key(EcKey::generate(&group)?)?)
+}
+
+fn certificate_request(domain: &str, key: &PKey<Private>) -> Result<Vec<u8>, Error> {
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, domain)?;
+
+    let mut builder = X509ReqBuilder::new()?;
+    builder.set_subject_name(&name.build())?;
+    builder.set_pubkey(key)?;
+    let mut extensions = Stack::new()?;
+    extensions.push(SubjectAlternativeName::new().dns(domain).build(&builder.x509v3_context(None))?)?;
+    builder.add_extensions(&extensions)?;
+    builder.sign(key, MessageDigest::sha256())?;
+
+    Ok(builder.build().to_der()?)
+}
+
+/// The self-signed certificate of the TLS-ALPN-01 challenge, with the digest of the key authorization in the critical
+/// acmeIdentifier extension.
+fn challenge_certificate(domain: &str, key_authorization: &str) -> Result<(X509, PKey<Private>), Error> {
+    let key = new_key()?;
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, domain)?;
+    let name = name.build();
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&BigNum::from_u32(1)?.to_asn1_integer()?)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+    builder.set_not_after(&Asn1Time::days_from_now(1)?)?;
+
+    let san = SubjectAlternativeName::new().dns(domain).build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+    // The value is a DER encoded OCTET STRING of the 32 bytes SHA-256 digest
+    let mut identifier = vec![0x04, 0x20];
+    identifier.extend(sha256(key_authorization.as_bytes()));
+    let oid = Asn1Object::from_str(ACME_IDENTIFIER_OID)?;
+    builder.append_extension(X509Extension::new_from_der(
+        &oid,
+        true,
+        &Asn1OctetString::new_from_bytes(&identifier)?,
+    )?)?;
+    builder.sign(&key, MessageDigest::sha256())?;
+
+    Ok((builder.build(), key))
+}
+
+struct AcmeClient {
+    client: Client,
+    directory: Value,
+    key: EcKey<Private>,
+    // The URL of the account, used as key id once the account is registered
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl AcmeClient {
+    async fn new() -> Result<Self, Error> {
+        let client = get_reqwest_client_builder().timeout(Duration::from_secs(30)).build()?;
+        let directory = client.get(CONFIG.acme_directory_url()).send().await?.error_for_status()?.json().await?;
+
+        let key = match tokio::fs::read(account_key_path()).await {
+            Ok(pem) => EcKey::private_key_from_pem(&pem)?,
+            Err(_) => {
+                let key = new_key()?.ec_key()?;
+                write_atomically(account_key_path(), &key.private_key_to_pem()?).await?;
+                key
+            }
+        };
+        let kid = tokio::fs::read_to_string(account_url_path()).await.ok().map(|u| u.trim().to_string());
+
+        Ok(Self {
+            client,
+            directory,
+            key,
+            kid,
+            nonce: None,
+        })
+    }
+
+    fn directory_url(&self, resource: &str) -> Result<String, Error> {
+        match self.directory[resource].as_str() {
+            Some(url) => Ok(url.to_string()),
+            None => err!(format!("The ACME directory has no {resource} URL")),
+        }
+    }
+
+    /// Registers the account of the key, or finds it when it was already registered.
+    async fn register(&mut self) -> Result<(), Error> {
+        if self.kid.is_some() {
+            return Ok(());
+        }
+
+        let contact: Vec<String> = CONFIG.acme_email().into_iter().map(|e| format!("mailto:{e}")).collect();
+        let new_account = self.directory_url("newAccount")?;
+        let response =
+            self.post(&new_account, Some(&json!({ "termsOfServiceAgreed": true, "contact": contact }))).await?;
+        let kid = location(&response)?;
+
+        write_atomically(account_url_path(), kid.as_bytes()).await?;
+        self.kid = Some(kid);
+        Ok(())
+    }
+
+    /// Answers the challenge of an authorization and waits until the certificate authority validated it.
+    async fn authorize(&mut self, authorization_url: &str, domain: &str) -> Result<(), Error> {
+        let authorization: Value = self.post(authorization_url, None).await?.json().await?;
+        if authorization["status"] == "valid" {
+            return Ok(());
+        }
+
+        let challenge_type = CONFIG.acme_challenge();
+        let challenge = authorization["challenges"]
+            .as_array()
+            .and_then(|challenges| challenges.iter().find(|c| c["type"] == challenge_type.as_str()));
+        let (Some(token), Some(challenge_url)) = (
+            challenge.and_then(|c| c["token"].as_str()),
+            challenge.and_then(|c| c["url"].as_str().map(str::to_string)),
+        ) else {
+            err!(format!("The certificate authority doesn't offer the {challenge_type} challenge for {domain}"))
+        };
+        let key_authorization = format!("{token}.{}", self.thumbprint()?);
+
+        let responder = if challenge_type == "tls-alpn-01" {
+            Responder::tls_alpn_01(domain, &key_authorization)?
+        } else {
+            Responder::http_01(token, key_authorization).await?
+        };
+
+        // An empty object tells the certificate authority the challenge can be validated
+        let result = match self.post(&challenge_url, Some(&json!({}))).await {
+            Ok(_) => self.poll(authorization_url, "valid").await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        responder.stop();
+        result
+    }
+
+    /// Polls an order or an authorization until it has the status, and fails once it became invalid.
+    async fn poll(&mut self, url: &str, status: &str) -> Result<Value, Error> {
+        for _ in 0..POLL_ATTEMPTS {
+            let object: Value = self.post(url, None).await?.json().await?;
+            match object["status"].as_str() {
+                Some(s) if s == status => return Ok(object),
+                Some("invalid") => err!(format!("The ACME validation failed: {object}")),
+                _ => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+        err!(format!("The ACME validation didn't finish in time, it was expected to become {status}"))
+    }
+
+    /// Sends a signed request, without a payload it is a POST-as-GET request.
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Response, Error> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.new_nonce().await?,
+            };
+            let body = self.sign(url, nonce, payload)?;
+            let response = self
+                .client
+                .post(url)
+                .header(header::CONTENT_TYPE, "application/jose+json")
+                .body(body.to_string())
+                .send()
+                .await?;
+            self.nonce = replay_nonce(&response);
+
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let status = response.status();
+            let problem: Value = response.json().await.unwrap_or_default();
+            // A nonce can be rejected at any time, the request is then retried with the nonce of the error response
+            if status == StatusCode::BAD_REQUEST && problem["type"] == "urn:ietf:params:acme:error:badNonce" && !retried
+            {
+                retried = true;
+                continue;
+            }
+            err!(format!("The ACME request to {url} failed with {status}: {problem}"))
+        }
+    }
+
+    async fn new_nonce(&self) -> Result<String, Error> {
+        let response = self.client.head(self.directory_url("newNonce")?).send().await?;
+        match replay_nonce(&response) {
+            Some(nonce) => Ok(nonce),
+            None => err!("The ACME server didn't return a nonce"),
+        }
+    }
+
+    /// The flattened JWS of a request, signed with ES256 by the account key.
+    fn sign(&self, url: &str, nonce: String, payload: Option<&Value>) -> Result<Value, Error> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk()?,
+        }
+        let protected = BASE64URL_NOPAD.encode(protected.to_string().as_bytes());
+        let payload = payload.map(|p| BASE64URL_NOPAD.encode(p.to_string().as_bytes())).unwrap_or_default();
+
+        let signature = EcdsaSig::sign(&sha256(format!("{protected}.{payload}").as_bytes()), &self.key)?;
+        let mut signature_bytes = signature.r().to_vec_padded(32)?;
+        signature_bytes.extend(signature.s().to_vec_padded(32)?);
+
+        Ok(json!({ "protected": protected, "payload": payload, "signature": BASE64URL_NOPAD.encode(&signature_bytes) }))
+    }
+
+    fn public_coordinates(&self) -> Result<(String, String), Error> {
+        let mut ctx = BigNumContext::new()?;
+        let mut x = BigNum::new()?;
+        let mut y = BigNum::new()?;
+        self.key.public_key().affine_coordinates(self.key.group(), &mut x, &mut y, &mut ctx)?;
+        Ok((BASE64URL_NOPAD.encode(&x.to_vec_padded(32)?), BASE64URL_NOPAD.encode(&y.to_vec_padded(32)?)))
+    }
+
+    fn jwk(&self) -> Result<Value, Error> {
+        let (x, y) = self.public_coordinates()?;
+        Ok(json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y }))
+    }
+
+    /// The JWK thumbprint of RFC 7638, which hashes the members of the key in lexicographic order without whitespace.
+    fn thumbprint(&self) -> Result<String, Error> {
+        let (x, y) = self.public_coordinates()?;
+        let jwk = format!(r#"{{"crv":"P-256","kty":"EC","x":"{x}","y":"{y}"}}"#);
+        Ok(BASE64URL_NOPAD.encode(&sha256(jwk.as_bytes())))
+    }
+}
+
+fn replay_nonce(response: &Response) -> Option<String> {
+    response.headers().get("Replay-Nonce").and_then(|n| n.to_str().ok()).map(str::to_string)
+}
+
+/// Answers a challenge until it is stopped.
+enum Responder {
+    Task(JoinHandle<()>),
+    Thread(Arc<AtomicBool>),
+}
+
+impl Responder {
+    /// Serves the key authorization at `/.well-known/acme-challenge/<token>` on `ACME_HTTP_PORT`.
+    async fn http_01(token: &str, key_authorization: String) -> Result<Self, Error> {
+        let listener = TcpListener::bind(SocketAddr::new(server_address().ip(), CONFIG.acme_http_port())).await?;
+        let path = format!("/.well-known/acme-challenge/{token}");
+
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                let path = path.clone();
+                let key_authorization = key_authorization.clone();
+                tokio::spawn(async move {
+                    let mut buffer = [0u8; 2048];
+                    let Ok(Ok(read)) = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer)).await
+                    else {
+                        return;
+                    };
+                    let request = String::from_utf8_lossy(&buffer[..read]);
+                    let response = if request.split_whitespace().nth(1) == Some(path.as_str()) {
+                        let length = key_authorization.len();
+                        format!("HTTP/1.1 200 OK\r\nContent-Length: {length}\r\n{HTTP_CLOSE}{key_authorization}")
+                    } else {
+                        format!("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n{HTTP_CLOSE}")
+                    };
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        Ok(Self::Task(task))
+    }
+
+    /// Completes the TLS handshakes of the `acme-tls/1` protocol with the challenge certificate on the port of the
+    /// server, which isn't launched while the challenge is answered.
+    fn tls_alpn_01(domain: &str, key_authorization: &str) -> Result<Self, Error> {
+        let (certificate, key) = challenge_certificate(domain, key_authorization)?;
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+        acceptor.set_private_key(&key)?;
+        acceptor.set_certificate(&certificate)?;
+        acceptor.set_alpn_select_callback(|_, client| select_next_proto(ACME_TLS_ALPN, client).ok_or(AlpnError::NOACK));
+        let acceptor = acceptor.build();
+
+        let listener = StdTcpListener::bind(server_address())?;
+        listener.set_nonblocking(true)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+
+        std::thread::Builder::new().name("acme-tls-alpn".to_string()).spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let _ = stream.set_nonblocking(false);
+                        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+                        // The validation only needs the handshake
+                        if let Ok(mut tls) = acceptor.accept(stream) {
+                            let _ = tls.shutdown();
+                        }
+                    }
+                    Err(_) => std::thread::sleep(Duration::from_millis(100)),
+                }
+            }
+        })?;
+        Ok(Self::Thread(stop))
+    }
+
+    fn stop(self) {
+        match self {
+            Self::Task(task) => task.abort(),
+            Self::Thread(stop) => stop.store(true, Ordering::Relaxed),
+        }
+    }
+}
+
+/// The address Rocket is configured to listen on.
+fn server_address() -> SocketAddr {
+    match rocket::Config::figment().extract::<rocket::Config>() {
+        Ok(config) => SocketAddr::new(config.address, config.port),
+        Err(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 443),
+    }
+}
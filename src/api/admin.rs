@@ -15,12 +15,24 @@ use rocket::{
 
 use crate::{
     api::{
-        core::{log_event, two_factor},
-        unregister_push_device, ApiResult, EmptyResult, JsonResult, Notify,
+        check_file_replication,
+        core::{
+            check_user_allowed, export_user_vault as _export_user_vault, is_valid_domain_name, log_event,
+            notify_role_escalation, set_membership_defaults, set_org_archived, two_factor,
+        },
+        icon_cache_metrics,
+        ldap::{last_ldap_sync_report, run_ldap_sync},
+        run_file_replication, unregister_push_device, ApiResult, EmptyResult, JsonResult, Notify,
+    },
+    auth::{
+        decode_admin, decode_admin_two_factor, encode_jwt, generate_admin_claims, generate_admin_two_factor_claims,
+        ClientIp, Secure,
     },
-    auth::{decode_admin, encode_jwt, generate_admin_claims, ClientIp, Secure},
     config::ConfigBuilder,
-    db::{backup_database, get_sql_server_version, models::*, DbConn, DbConnType},
+    db::{
+        backup_database, begin_transaction, commit_transaction, get_sql_server_version, metrics, models::*,
+        rollback_transaction, DbConn, DbConnType, DbPool,
+    },
     error::{Error, MapResult},
     http_client::make_http_request,
     mail,
@@ -40,20 +52,57 @@ pub fn routes() -> Vec<Route> {
         get_users_json,
         get_user_json,
         get_user_by_mail_json,
+        get_unverified_users,
         post_admin_login,
+        post_admin_two_factor,
+        post_admin_two_factor_setup,
+        admin_webauthn_challenge,
+        admin_webauthn_login,
         admin_page,
         admin_page_login,
         invite_user,
         logout,
         delete_user,
+        restore_user,
+        get_user_logins,
+        get_user_token_families,
         delete_sso_user,
         deauth_user,
+        unlock_user,
+        clear_email_undeliverable,
         disable_user,
         enable_user,
+        bulk_user_action,
         remove_2fa,
         update_membership_type,
+        update_user_storage_limits,
+        export_user_vault,
+        merge_users,
+        get_user_aliases,
+        post_user_alias,
+        delete_user_alias,
+        update_org_storage_limits,
+        update_org_seats,
+        update_org_invite_text,
+        update_org_archived,
+        claim_org_domain,
+        delete_org_domain,
         update_revision_users,
+        get_kdf_campaign,
+        start_kdf_campaign,
+        get_ldap_sync,
+        post_ldap_sync,
+        get_mail_queue,
+        retry_queued_mail,
+        delete_queued_mail,
+        get_file_replication,
+        post_file_replication,
+        get_db_integrity,
+        post_db_integrity_repair,
+        get_password_hints,
+        post_password_hints_clear,
         post_config,
+        validate_config_candidate,
         delete_config,
         backup_db,
         test_smtp,
@@ -64,6 +113,25 @@ pub fn routes() -> Vec<Route> {
         get_diagnostics_config,
         resend_user_invite,
         get_diagnostics_http,
+        api_get_users,
+        api_invite_user,
+        api_get_user,
+        api_delete_user,
+        api_restore_user,
+        api_deauth_user,
+        api_disable_user,
+        api_enable_user,
+        api_bulk_user_action,
+        api_remove_2fa,
+        api_update_revision_users,
+        api_get_organizations,
+        api_delete_organization,
+        api_get_stats,
+        api_get_metrics,
+        api_get_signup_rules,
+        api_post_signup_rule,
+        api_put_signup_rule,
+        api_delete_signup_rule,
     ]
 }
 
@@ -94,12 +162,13 @@ fn admin_disabled() -> &'static str {
 }
 
 const COOKIE_NAME: &str = "VW_ADMIN";
+const TWO_FACTOR_COOKIE_NAME: &str = "VW_ADMIN_2FA";
 const ADMIN_PATH: &str = "/admin";
 const DT_FMT: &str = "%Y-%m-%d %H:%M:%S %Z";
 
 const BASE_TEMPLATE: &str = "admin/base";
 
-const ACTING_ADMIN_USER: &str = "vaultwarden-admin-00000-000000000000";
+pub const ACTING_ADMIN_USER: &str = "vaultwarden-admin-00000-000000000000";
 pub const FAKE_ADMIN_UUID: &str = "00000000-0000-0000-0000-000000000000";
 
 fn admin_path() -> String {
@@ -173,11 +242,12 @@ struct LoginForm {
 }
 
 #[post("/", format = "application/x-www-form-urlencoded", data = "<data>")]
-fn post_admin_login(
+async fn post_admin_login(
     data: Form<LoginForm>,
     cookies: &CookieJar<'_>,
     ip: ClientIp,
     secure: Secure,
+    mut conn: DbConn,
 ) -> Result<Redirect, AdminResponse> {
     let data = data.into_inner();
     let redirect = data.redirect;
@@ -193,25 +263,229 @@ fn post_admin_login(
     if !_validate_token(&data.token) {
         error!("Invalid admin token. IP: {}", ip.ip);
         Err(AdminResponse::Unauthorized(render_admin_login(Some("Invalid admin token, please try again."), redirect)))
-    } else {
-        // If the token received is valid, generate JWT and save it as a cookie
-        let claims = generate_admin_claims();
+    } else if CONFIG.admin_two_factor() {
+        // The token only gives access to the second step, which is also where the factors are set up the first time
+        let claims = generate_admin_two_factor_claims();
         let jwt = encode_jwt(&claims);
 
-        let cookie = Cookie::build((COOKIE_NAME, jwt))
+        let cookie = Cookie::build((TWO_FACTOR_COOKIE_NAME, jwt))
             .path(admin_path())
-            .max_age(time::Duration::minutes(CONFIG.admin_session_lifetime()))
+            .max_age(time::Duration::minutes(10))
             .same_site(SameSite::Strict)
             .http_only(true)
             .secure(secure.https);
 
         cookies.add(cookie);
-        if let Some(redirect) = redirect {
-            Ok(Redirect::to(format!("{}{redirect}", admin_path())))
-        } else {
-            Err(AdminResponse::Ok(render_admin_page()))
+        Err(AdminResponse::Ok(render_admin_two_factor(None, redirect, &mut conn).await))
+    } else {
+        admin_login_success(cookies, secure, redirect)
+    }
+}
+
+// Generates the session JWT and saves it as a cookie
+fn admin_login_success(
+    cookies: &CookieJar<'_>,
+    secure: Secure,
+    redirect: Option<String>,
+) -> Result<Redirect, AdminResponse> {
+    let claims = generate_admin_claims();
+    let jwt = encode_jwt(&claims);
+
+    let cookie = Cookie::build((COOKIE_NAME, jwt))
+        .path(admin_path())
+        .max_age(time::Duration::minutes(CONFIG.admin_session_lifetime()))
+        .same_site(SameSite::Strict)
+        .http_only(true)
+        .secure(secure.https);
+
+    cookies.add(cookie);
+    if let Some(redirect) = redirect {
+        Ok(Redirect::to(format!("{}{redirect}", admin_path())))
+    } else {
+        Err(AdminResponse::Ok(render_admin_page()))
+    }
+}
+
+async fn render_admin_two_factor(
+    msg: Option<&str>,
+    redirect: Option<String>,
+    conn: &mut DbConn,
+) -> ApiResult<Html<String>> {
+    let factors = AdminTwoFactor::find_all(conn).await;
+    let has_type = |atype: TwoFactorType| factors.iter().any(|tf| tf.atype == atype as i32);
+
+    // Without any factor yet, offer a new TOTP secret, the admin can also choose to register a security key instead
+    let totp_secret = factors.is_empty().then(|| crate::crypto::encode_random_bytes::<20>(data_encoding::BASE32));
+    let json = json!({
+        "page_content": "admin/two_factor",
+        "error": msg.map(|msg| format!("Error: {msg}")),
+        "redirect": redirect,
+        "setup": factors.is_empty(),
+        "totp_secret": totp_secret,
+        "totp_enabled": has_type(TwoFactorType::Authenticator),
+        "webauthn_enabled": has_type(TwoFactorType::Webauthn),
+        "webauthn_available": CONFIG.domain_set(),
+        "recovery_codes_left": AdminRecoveryCode::count(conn).await,
+        "urlpath": CONFIG.domain_path()
+    });
+
+    let text = CONFIG.render_template(BASE_TEMPLATE, &json)?;
+    Ok(Html(text))
+}
+
+fn render_admin_recovery_codes(codes: &[String], redirect: Option<String>) -> ApiResult<Html<String>> {
+    let json = json!({
+        "page_content": "admin/two_factor",
+        "recovery_codes": codes,
+        "continue_url": format!("{}{}", admin_path(), redirect.unwrap_or_default()),
+        "urlpath": CONFIG.domain_path()
+    });
+
+    let text = CONFIG.render_template(BASE_TEMPLATE, &json)?;
+    Ok(Html(text))
+}
+
+#[derive(FromForm)]
+struct TwoFactorForm {
+    code: Option<String>,
+    webauthn: Option<String>,
+    recovery_code: Option<String>,
+    redirect: Option<String>,
+}
+
+#[post("/two-factor", format = "application/x-www-form-urlencoded", data = "<data>")]
+async fn post_admin_two_factor(
+    data: Form<TwoFactorForm>,
+    _pending: AdminTwoFactorPending,
+    cookies: &CookieJar<'_>,
+    ip: ClientIp,
+    secure: Secure,
+    mut conn: DbConn,
+) -> Result<Redirect, AdminResponse> {
+    let data = data.into_inner();
+    let redirect = data.redirect;
+
+    if crate::ratelimit::check_limit_admin(&ip.ip).is_err() {
+        return Err(AdminResponse::TooManyRequests(
+            render_admin_two_factor(Some("Too many requests, try again later."), redirect, &mut conn).await,
+        ));
+    }
+
+    let result = if let Some(recovery_code) = data.recovery_code.filter(|c| !c.trim().is_empty()) {
+        match AdminRecoveryCode::redeem(&recovery_code, &mut conn).await {
+            Ok(true) => {
+                // A recovery code is used when a factor got lost, so they all have to be set up again
+                warn!("Admin recovery code used, the admin second factors are removed. IP: {}", ip.ip);
+                if let Err(e) = AdminTwoFactor::delete_all(&mut conn).await {
+                    return Err(AdminResponse::Ok(Err(e)));
+                }
+                return Err(AdminResponse::Ok(render_admin_two_factor(None, redirect, &mut conn).await));
+            }
+            Ok(false) => Err(Error::new("Invalid recovery code", "Invalid admin recovery code")),
+            Err(e) => Err(e),
+        }
+    } else if let Some(response) = data.webauthn.filter(|r| !r.is_empty()) {
+        two_factor::webauthn::validate_admin_webauthn_login(&response, &mut conn).await
+    } else if let Some(code) = data.code {
+        match AdminTwoFactor::find_by_type(TwoFactorType::Authenticator, &mut conn).await {
+            Some(tf) => two_factor::authenticator::validate_admin_totp_code(tf, code.trim(), &ip, &mut conn).await,
+            None => Err(Error::new("No authenticator set up", "No admin authenticator set up")),
+        }
+    } else {
+        Err(Error::new("No two-step login code given", "No admin two-step login code given"))
+    };
+
+    if let Err(e) = result {
+        error!("Invalid admin two-step login. IP: {}", ip.ip);
+        let msg = format!("{}, please try again.", e.message());
+        return Err(AdminResponse::Unauthorized(render_admin_two_factor(Some(&msg), redirect, &mut conn).await));
+    }
+
+    cookies.remove(Cookie::build(TWO_FACTOR_COOKIE_NAME).path(admin_path()));
+    admin_login_success(cookies, secure, redirect)
+}
+
+#[derive(FromForm)]
+struct TwoFactorSetupForm {
+    totp_secret: Option<String>,
+    code: Option<String>,
+    webauthn: Option<String>,
+    webauthn_name: Option<String>,
+    redirect: Option<String>,
+}
+
+#[post("/two-factor/setup", format = "application/x-www-form-urlencoded", data = "<data>")]
+async fn post_admin_two_factor_setup(
+    data: Form<TwoFactorSetupForm>,
+    _pending: AdminTwoFactorPending,
+    cookies: &CookieJar<'_>,
+    ip: ClientIp,
+    secure: Secure,
+    mut conn: DbConn,
+) -> AdminResponse {
+    let data = data.into_inner();
+    let redirect = data.redirect;
+
+    if crate::ratelimit::check_limit_admin(&ip.ip).is_err() {
+        return AdminResponse::TooManyRequests(
+            render_admin_two_factor(Some("Too many requests, try again later."), redirect, &mut conn).await,
+        );
+    }
+
+    // Once a factor is configured, knowing the admin token must not be enough to add another one
+    if !AdminTwoFactor::find_all(&mut conn).await.is_empty() {
+        error!("Admin two-step login is already set up. IP: {}", ip.ip);
+        return AdminResponse::Unauthorized(
+            render_admin_two_factor(Some("Two-step login is already set up."), redirect, &mut conn).await,
+        );
+    }
+
+    let result = if let Some(response) = data.webauthn.filter(|r| !r.is_empty()) {
+        let name = data.webauthn_name.filter(|n| !n.trim().is_empty()).unwrap_or_else(|| String::from("Admin key"));
+        two_factor::webauthn::activate_admin_webauthn(name, &response, &mut conn).await
+    } else if let (Some(secret), Some(code)) = (data.totp_secret, data.code) {
+        let secret = secret.trim().to_uppercase();
+        match data_encoding::BASE32.decode(secret.as_bytes()) {
+            Ok(decoded) if decoded.len() == 20 => {
+                let twofactor = AdminTwoFactor::new(TwoFactorType::Authenticator, secret);
+                two_factor::authenticator::validate_admin_totp_code(twofactor, code.trim(), &ip, &mut conn).await
+            }
+            _ => Err(Error::new("Invalid TOTP secret", "Invalid admin TOTP secret")),
         }
+    } else {
+        Err(Error::new("No second factor given", "No admin second factor given"))
+    };
+
+    if let Err(e) = result {
+        error!("Admin two-step login setup failed. IP: {}", ip.ip);
+        let msg = format!("{}, please try again.", e.message());
+        return AdminResponse::Ok(render_admin_two_factor(Some(&msg), redirect, &mut conn).await);
     }
+    info!("Admin two-step login set up. IP: {}", ip.ip);
+
+    let codes = match AdminRecoveryCode::generate(&mut conn).await {
+        Ok(codes) => codes,
+        Err(e) => return AdminResponse::Ok(Err(e)),
+    };
+
+    cookies.remove(Cookie::build(TWO_FACTOR_COOKIE_NAME).path(admin_path()));
+    // The recovery codes are only shown on this page, the session is already valid to continue from it
+    let _ = admin_login_success(cookies, secure, None);
+    AdminResponse::Ok(render_admin_recovery_codes(&codes, redirect))
+}
+
+#[post("/two-factor/webauthn-challenge")]
+async fn admin_webauthn_challenge(_pending: AdminTwoFactorPending, ip: ClientIp, mut conn: DbConn) -> JsonResult {
+    crate::ratelimit::check_limit_admin(&ip.ip)?;
+    if !AdminTwoFactor::find_all(&mut conn).await.is_empty() {
+        err!("Two-step login is already set up")
+    }
+    two_factor::webauthn::generate_admin_webauthn_challenge(&mut conn).await
+}
+
+#[post("/two-factor/webauthn-login")]
+async fn admin_webauthn_login(_pending: AdminTwoFactorPending, mut conn: DbConn) -> JsonResult {
+    two_factor::webauthn::generate_admin_webauthn_login(&mut conn).await
 }
 
 fn _validate_token(token: &str) -> bool {
@@ -305,7 +579,7 @@ async fn invite_user(data: Json<InviteData>, _token: AdminToken, mut conn: DbCon
         if CONFIG.mail_enabled() {
             let org_id: OrganizationId = FAKE_ADMIN_UUID.to_string().into();
             let member_id: MembershipId = FAKE_ADMIN_UUID.to_string().into();
-            mail::send_invite(user, org_id, member_id, &CONFIG.invitation_org_name(), None).await
+            mail::send_invite(user, org_id, member_id, &CONFIG.invitation_org_name(), None, None).await
         } else {
             let invitation = Invitation::new(&user.email);
             invitation.save(conn).await
@@ -342,7 +616,12 @@ async fn get_users_json(_token: AdminToken, mut conn: DbConn) -> Json<Value> {
     for (u, _) in users {
         let mut usr = u.to_json(&mut conn).await;
         usr["userEnabled"] = json!(u.enabled);
+        usr["lockoutSeconds"] = json!(crate::ratelimit::login_lockout_seconds(&u.uuid));
         usr["createdAt"] = json!(format_naive_datetime_local(&u.created_at, DT_FMT));
+        usr["deletedAt"] = json!(u.deleted_at.map(|dt| format_naive_datetime_local(&dt, DT_FMT)));
+        usr["emailUndeliverableAt"] =
+            json!(u.email_undeliverable_at.map(|dt| format_naive_datetime_local(&dt, DT_FMT)));
+        usr["emailUndeliverableReason"] = json!(u.email_undeliverable_reason);
         usr["lastActive"] = match u.last_active(&mut conn).await {
             Some(dt) => json!(format_naive_datetime_local(&dt, DT_FMT)),
             None => json!(None::<String>),
@@ -362,8 +641,15 @@ async fn users_overview(_token: AdminToken, mut conn: DbConn) -> ApiResult<Html<
         usr["cipher_count"] = json!(Cipher::count_owned_by_user(&u.uuid, &mut conn).await);
         usr["attachment_count"] = json!(Attachment::count_by_user(&u.uuid, &mut conn).await);
         usr["attachment_size"] = json!(get_display_size(Attachment::size_by_user(&u.uuid, &mut conn).await));
+        usr["attachment_limit"] = json!(u.attachment_limit);
+        usr["send_limit"] = json!(u.send_limit);
         usr["user_enabled"] = json!(u.enabled);
+        usr["locked_out"] = json!(crate::ratelimit::login_lockout_seconds(&u.uuid) > 0);
         usr["created_at"] = json!(format_naive_datetime_local(&u.created_at, DT_FMT));
+        usr["deleted_at"] = json!(u.deleted_at.map(|dt| format_naive_datetime_local(&dt, DT_FMT)));
+        usr["email_undeliverable_at"] =
+            json!(u.email_undeliverable_at.map(|dt| format_naive_datetime_local(&dt, DT_FMT)));
+        usr["email_undeliverable_reason"] = json!(u.email_undeliverable_reason);
         usr["last_active"] = match u.last_active(&mut conn).await {
             Some(dt) => json!(format_naive_datetime_local(&dt, DT_FMT)),
             None => json!("Never"),
@@ -390,22 +676,90 @@ async fn get_user_by_mail_json(mail: &str, _token: AdminToken, mut conn: DbConn)
     }
 }
 
+// The accounts whose email address was never verified, and from when they can't log in if verification is enforced
+#[get("/users/unverified")]
+async fn get_unverified_users(_token: AdminToken, mut conn: DbConn) -> Json<Value> {
+    let now = chrono::Utc::now().naive_utc();
+    let mut users = Vec::new();
+    for (user, _) in User::get_all(&mut conn).await {
+        // Invited users without an account yet verify their address when accepting the invitation
+        if user.verified_at.is_some() || user.password_hash.is_empty() {
+            continue;
+        }
+        let deadline = user.email_verify_deadline(&mut conn).await;
+        users.push(json!({
+            "id": user.uuid,
+            "email": user.email,
+            "name": user.name,
+            "enabled": user.enabled,
+            "createdAt": format_naive_datetime_local(&user.created_at, DT_FMT),
+            "lastVerifyingAt": user.last_verifying_at.map(|dt| format_naive_datetime_local(&dt, DT_FMT)),
+            "verifyEmailsSent": user.login_verify_count,
+            "blockedAt": deadline.map(|dt| format_naive_datetime_local(&dt, DT_FMT)),
+            "blocked": deadline.is_some_and(|dt| dt <= now),
+        }));
+    }
+
+    Json(json!({
+        "enforced": CONFIG.mail_enabled() && CONFIG.email_verify_enforce(),
+        "graceDays": CONFIG.email_verify_grace_days(),
+        "users": users,
+    }))
+}
+
 #[get("/users/<user_id>")]
 async fn get_user_json(user_id: UserId, _token: AdminToken, mut conn: DbConn) -> JsonResult {
     let u = get_user_or_404(&user_id, &mut conn).await?;
     let mut usr = u.to_json(&mut conn).await;
     usr["userEnabled"] = json!(u.enabled);
     usr["createdAt"] = json!(format_naive_datetime_local(&u.created_at, DT_FMT));
+    usr["deletedAt"] = json!(u.deleted_at.map(|dt| format_naive_datetime_local(&dt, DT_FMT)));
     Ok(Json(usr))
 }
 
-#[post("/users/<user_id>/delete", format = "application/json")]
-async fn delete_user(user_id: UserId, token: AdminToken, mut conn: DbConn) -> EmptyResult {
+#[get("/users/<user_id>/logins")]
+async fn get_user_logins(user_id: UserId, _token: AdminToken, mut conn: DbConn) -> JsonResult {
     let user = get_user_or_404(&user_id, &mut conn).await?;
+    let logins: Vec<Value> =
+        LoginEvent::find_by_user(&user.uuid, &mut conn).await.iter().map(LoginEvent::to_json).collect();
+    Ok(Json(Value::Array(logins)))
+}
+
+#[get("/users/<user_id>/token-families")]
+async fn get_user_token_families(user_id: UserId, _token: AdminToken, mut conn: DbConn) -> JsonResult {
+    let user = get_user_or_404(&user_id, &mut conn).await?;
+    let mut families = Vec::new();
+    for family in RefreshTokenFamily::find_by_user(&user.uuid, &mut conn).await {
+        let mut family_json = family.to_json();
+        if let Some(device) = Device::find_by_uuid_and_user(&family.device_uuid, &user.uuid, &mut conn).await {
+            family_json["deviceName"] = json!(device.name);
+            family_json["deviceType"] = json!(DeviceType::from_i32(device.atype).to_string());
+        }
+        family_json["creationDate"] = json!(format_naive_datetime_local(&family.created_at, DT_FMT));
+        family_json["rotationDate"] = json!(format_naive_datetime_local(&family.rotated_at, DT_FMT));
+        families.push(family_json);
+    }
+    Ok(Json(Value::Array(families)))
+}
+
+#[post("/users/<user_id>/delete", format = "application/json")]
+async fn delete_user(user_id: UserId, token: AdminToken, mut conn: DbConn, nt: Notify<'_>) -> EmptyResult {
+    _delete_user(&user_id, &token, &mut conn, nt).await
+}
+
+async fn _delete_user(user_id: &UserId, token: &AdminToken, conn: &mut DbConn, nt: Notify<'_>) -> EmptyResult {
+    let mut user = get_user_or_404(user_id, conn).await?;
+
+    // Keep the user during the grace period, deleting an already deleted user purges it right away
+    if CONFIG.user_deletion_grace_days() > 0 && !user.is_soft_deleted() {
+        user.soft_delete(false, conn).await?;
+        nt.send_logout(&user, None, conn).await;
+        return Ok(());
+    }
 
     // Get the membership records before deleting the actual user
-    let memberships = Membership::find_any_state_by_user(&user_id, &mut conn).await;
-    let res = user.delete(&mut conn).await;
+    let memberships = Membership::find_any_state_by_user(user_id, conn).await;
+    let res = user.delete(conn).await;
 
     for membership in memberships {
         log_event(
@@ -415,7 +769,7 @@ async fn delete_user(user_id: UserId, token: AdminToken, mut conn: DbConn) -> Em
             &ACTING_ADMIN_USER.into(),
             14, // Use UnknownBrowser type
             &token.ip.ip,
-            &mut conn,
+            conn,
         )
         .await;
     }
@@ -423,6 +777,12 @@ async fn delete_user(user_id: UserId, token: AdminToken, mut conn: DbConn) -> Em
     res
 }
 
+#[post("/users/<user_id>/restore", format = "application/json")]
+async fn restore_user(user_id: UserId, _token: AdminToken, mut conn: DbConn) -> EmptyResult {
+    let mut user = get_user_or_404(&user_id, &mut conn).await?;
+    user.restore(&mut conn).await
+}
+
 #[delete("/users/<user_id>/sso", format = "application/json")]
 async fn delete_sso_user(user_id: UserId, token: AdminToken, mut conn: DbConn) -> EmptyResult {
     let memberships = Membership::find_any_state_by_user(&user_id, &mut conn).await;
@@ -446,12 +806,16 @@ async fn delete_sso_user(user_id: UserId, token: AdminToken, mut conn: DbConn) -
 
 #[post("/users/<user_id>/deauth", format = "application/json")]
 async fn deauth_user(user_id: UserId, _token: AdminToken, mut conn: DbConn, nt: Notify<'_>) -> EmptyResult {
-    let mut user = get_user_or_404(&user_id, &mut conn).await?;
+    _deauth_user(&user_id, &mut conn, nt).await
+}
+
+async fn _deauth_user(user_id: &UserId, conn: &mut DbConn, nt: Notify<'_>) -> EmptyResult {
+    let mut user = get_user_or_404(user_id, conn).await?;
 
-    nt.send_logout(&user, None, &mut conn).await;
+    nt.send_logout(&user, None, conn).await;
 
     if CONFIG.push_enabled() {
-        for device in Device::find_push_devices_by_user(&user.uuid, &mut conn).await {
+        for device in Device::find_push_devices_by_user(&user.uuid, conn).await {
             match unregister_push_device(&device.push_uuid).await {
                 Ok(r) => r,
                 Err(e) => error!("Unable to unregister devices from Bitwarden server: {e}"),
@@ -459,32 +823,122 @@ async fn deauth_user(user_id: UserId, _token: AdminToken, mut conn: DbConn, nt:
         }
     }
 
-    Device::delete_all_by_user(&user.uuid, &mut conn).await?;
+    Device::delete_all_by_user(&user.uuid, conn).await?;
     user.reset_security_stamp();
 
+    user.save(conn).await
+}
+
+/// Clears the failed logins of the user, which also lifts a lockout.
+#[post("/users/<user_id>/unlock", format = "application/json")]
+async fn unlock_user(user_id: UserId, _token: AdminToken, mut conn: DbConn) -> EmptyResult {
+    let user = get_user_or_404(&user_id, &mut conn).await?;
+    crate::ratelimit::clear_failed_logins(&user.uuid);
+    Ok(())
+}
+
+/// Sends mails to the address of the user again, after the mailbox was fixed. Changing the address clears it as well.
+#[post("/users/<user_id>/email-deliverable", format = "application/json")]
+async fn clear_email_undeliverable(user_id: UserId, _token: AdminToken, mut conn: DbConn) -> EmptyResult {
+    let mut user = get_user_or_404(&user_id, &mut conn).await?;
+    user.clear_email_undeliverable();
     user.save(&mut conn).await
 }
 
 #[post("/users/<user_id>/disable", format = "application/json")]
 async fn disable_user(user_id: UserId, _token: AdminToken, mut conn: DbConn, nt: Notify<'_>) -> EmptyResult {
-    let mut user = get_user_or_404(&user_id, &mut conn).await?;
-    Device::delete_all_by_user(&user.uuid, &mut conn).await?;
+    _disable_user(&user_id, &mut conn, nt).await
+}
+
+async fn _disable_user(user_id: &UserId, conn: &mut DbConn, nt: Notify<'_>) -> EmptyResult {
+    let mut user = get_user_or_404(user_id, conn).await?;
+    Device::delete_all_by_user(&user.uuid, conn).await?;
     user.reset_security_stamp();
     user.enabled = false;
 
-    let save_result = user.save(&mut conn).await;
+    let save_result = user.save(conn).await;
 
-    nt.send_logout(&user, None, &mut conn).await;
+    nt.send_logout(&user, None, conn).await;
 
     save_result
 }
 
 #[post("/users/<user_id>/enable", format = "application/json")]
 async fn enable_user(user_id: UserId, _token: AdminToken, mut conn: DbConn) -> EmptyResult {
-    let mut user = get_user_or_404(&user_id, &mut conn).await?;
+    _enable_user(&user_id, &mut conn).await
+}
+
+async fn _enable_user(user_id: &UserId, conn: &mut DbConn) -> EmptyResult {
+    let mut user = get_user_or_404(user_id, conn).await?;
     user.enabled = true;
 
-    user.save(&mut conn).await
+    user.save(conn).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum BulkUserAction {
+    Disable,
+    Enable,
+    Deauth,
+    ForcePasswordReset,
+    Delete,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BulkUserActionData {
+    action: BulkUserAction,
+    user_ids: Vec<UserId>,
+}
+
+// Applies the action to every user, one failing user doesn't stop the others.
+// The result of every user is reported, `error` is empty when the action succeeded.
+#[post("/users/bulk", format = "application/json", data = "<data>")]
+async fn bulk_user_action(
+    data: Json<BulkUserActionData>,
+    token: AdminToken,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> Json<Value> {
+    let data: BulkUserActionData = data.into_inner();
+
+    let mut bulk_response = Vec::new();
+    for user_id in data.user_ids {
+        let res = match data.action {
+            BulkUserAction::Disable => _disable_user(&user_id, &mut conn, nt).await,
+            BulkUserAction::Enable => _enable_user(&user_id, &mut conn).await,
+            BulkUserAction::Deauth => _deauth_user(&user_id, &mut conn, nt).await,
+            BulkUserAction::ForcePasswordReset => _force_password_reset(&user_id, &mut conn, nt).await,
+            BulkUserAction::Delete => _delete_user(&user_id, &token, &mut conn, nt).await,
+        };
+
+        let err_msg = match res {
+            Ok(()) => String::new(),
+            Err(e) => format!("{e:?}"),
+        };
+
+        bulk_response.push(json!({
+            "userId": user_id,
+            "success": err_msg.is_empty(),
+            "error": err_msg,
+        }));
+    }
+
+    Json(json!({
+        "data": bulk_response,
+        "object": "list",
+    }))
+}
+
+// Same as the custom API, refreshing tokens and API key logins fail until the user chose a new master password
+async fn _force_password_reset(user_id: &UserId, conn: &mut DbConn, nt: Notify<'_>) -> EmptyResult {
+    let mut user = get_user_or_404(user_id, conn).await?;
+    user.force_password_reset = true;
+    user.save(conn).await?;
+
+    nt.send_logout(&user, None, conn).await;
+    Ok(())
 }
 
 #[post("/users/<user_id>/remove-2fa", format = "application/json")]
@@ -500,14 +954,14 @@ async fn remove_2fa(user_id: UserId, token: AdminToken, mut conn: DbConn) -> Emp
 async fn resend_user_invite(user_id: UserId, _token: AdminToken, mut conn: DbConn) -> EmptyResult {
     if let Some(user) = User::find_by_uuid(&user_id, &mut conn).await {
         //TODO: replace this with user.status check when it will be available (PR#3397)
-        if !user.password_hash.is_empty() {
+        if !user.password_hash.is_empty() || user.uses_key_connector {
             err_code!("User already accepted invitation", Status::BadRequest.code);
         }
 
         if CONFIG.mail_enabled() {
             let org_id: OrganizationId = FAKE_ADMIN_UUID.to_string().into();
             let member_id: MembershipId = FAKE_ADMIN_UUID.to_string().into();
-            mail::send_invite(&user, org_id, member_id, &CONFIG.invitation_org_name(), None).await
+            mail::send_invite(&user, org_id, member_id, &CONFIG.invitation_org_name(), None, None).await
         } else {
             Ok(())
         }
@@ -547,18 +1001,23 @@ async fn update_membership_type(data: Json<MembershipTypeData>, token: AdminToke
     // This check is also done at api::organizations::{accept_invite, _confirm_invite, _activate_member, edit_member}, update_membership_type
     // It returns different error messages per function.
     if new_type < MembershipType::Admin {
-        match OrgPolicy::is_user_allowed(&member_to_edit.user_uuid, &member_to_edit.org_uuid, true, &mut conn).await {
-            Ok(_) => {}
+        match check_user_allowed(
+            &member_to_edit.user_uuid,
+            &member_to_edit.org_uuid,
+            true,
+            "Changing the type of the member",
+            &ACTING_ADMIN_USER.into(),
+            &mut conn,
+        )
+        .await?
+        {
+            Ok(()) => {}
             Err(OrgPolicyErr::TwoFactorMissing) => {
-                if CONFIG.email_2fa_auto_fallback() {
-                    two_factor::email::find_and_activate_email_2fa(&member_to_edit.user_uuid, &mut conn).await?;
-                } else {
-                    err!("You cannot modify this user to this type because they have not setup 2FA");
-                }
-            }
-            Err(OrgPolicyErr::SingleOrgEnforced) => {
-                err!("You cannot modify this user to this type because it is a member of an organization which forbids it");
+                err!("You cannot modify this user to this type because they have not setup 2FA")
             }
+            Err(OrgPolicyErr::SingleOrgEnforced) => err!(
+                "You cannot modify this user to this type because it is a member of an organization which forbids it"
+            ),
         }
     }
 
@@ -573,8 +1032,404 @@ async fn update_membership_type(data: Json<MembershipTypeData>, token: AdminToke
     )
     .await;
 
+    let old_type = member_to_edit.atype;
     member_to_edit.atype = new_type;
-    member_to_edit.save(&mut conn).await
+    member_to_edit.save(&mut conn).await?;
+
+    notify_role_escalation(&member_to_edit, old_type, "Vaultwarden admin", None, &mut conn).await;
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct UserStorageLimitsData {
+    // Limits in KB, None falls back to the global limit
+    attachment_limit: Option<i64>,
+    send_limit: Option<i64>,
+}
+
+#[post("/users/<user_id>/storage", format = "application/json", data = "<data>")]
+async fn update_user_storage_limits(
+    user_id: UserId,
+    data: Json<UserStorageLimitsData>,
+    _token: AdminToken,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let data: UserStorageLimitsData = data.into_inner();
+    if data.attachment_limit.is_some_and(|l| l < 0) || data.send_limit.is_some_and(|l| l < 0) {
+        err!("Storage limits can't be negative")
+    }
+
+    let mut user = get_user_or_404(&user_id, &mut conn).await?;
+    user.attachment_limit = data.attachment_limit;
+    user.send_limit = data.send_limit;
+    user.save(&mut conn).await
+}
+
+#[derive(Debug, Deserialize)]
+struct VaultExportData {
+    // Protects the export file, it is needed together with the master password of the user to read the items
+    password: String,
+}
+
+#[post("/users/<user_id>/export", format = "application/json", data = "<data>")]
+async fn export_user_vault(
+    user_id: UserId,
+    data: Json<VaultExportData>,
+    token: AdminToken,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: VaultExportData = data.into_inner();
+    let user = get_user_or_404(&user_id, &mut conn).await?;
+    let (path, ciphers, folders) = _export_user_vault(&user, &data.password, &token.ip.ip, &mut conn).await?;
+    Ok(Json(json!({
+        "path": path,
+        "ciphers": ciphers,
+        "folders": folders,
+    })))
+}
+
+// What happens to a kind of data of the secondary account when merging it, there is no default on purpose
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum MergeDecision {
+    Move,
+    Export,
+    Delete,
+}
+
+#[derive(Debug, Deserialize)]
+struct MergeUsersData {
+    secondary_user_id: UserId,
+    // `move` or `delete`, moved memberships have to be confirmed again as the organization keys can't be re-encrypted
+    memberships: MergeDecision,
+    // `move` or `delete`, moved devices have to log in again
+    devices: MergeDecision,
+    // `export` or `delete` for the personal items and folders, they are encrypted with the key of the secondary account
+    vault: MergeDecision,
+    export_password: Option<String>,
+}
+
+/// Merges the secondary account into the primary one `user_id`, for people who ended up with two accounts.
+/// The secondary account is deleted afterwards and its email address becomes an alias of the primary account.
+#[post("/users/<user_id>/merge", format = "application/json", data = "<data>")]
+async fn merge_users(
+    user_id: UserId,
+    data: Json<MergeUsersData>,
+    token: AdminToken,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> JsonResult {
+    let data: MergeUsersData = data.into_inner();
+    let primary = get_user_or_404(&user_id, &mut conn).await?;
+    let secondary = get_user_or_404(&data.secondary_user_id, &mut conn).await?;
+    if primary.uuid == secondary.uuid {
+        err!("An account can't be merged into itself")
+    }
+    if primary.is_soft_deleted() {
+        err!("The primary account is deleted")
+    }
+    if data.memberships == MergeDecision::Export || data.devices == MergeDecision::Export {
+        err!("Memberships and devices can only be moved or deleted")
+    }
+    if data.vault == MergeDecision::Move {
+        err!("The vault of the secondary account can only be exported or deleted, the primary account can't decrypt it")
+    }
+    // Both keeping and dropping the memberships remove the owner from the organization
+    secondary.check_not_last_owner(&mut conn).await?;
+
+    let export_path = if data.vault == MergeDecision::Export {
+        let Some(password) = data.export_password.as_deref() else {
+            err!("An export password is needed to export the vault of the secondary account")
+        };
+        Some(_export_user_vault(&secondary, password, &token.ip.ip, &mut conn).await?.0)
+    } else {
+        None
+    };
+
+    // Before the devices are moved, the push notification goes to the devices of the account
+    nt.send_logout(&secondary, None, &mut conn).await;
+
+    let mut moved_memberships = 0;
+    let mut moved_devices = 0;
+    let mut events = Vec::new();
+    let mut push_uuids = Vec::new();
+    let alias = UserAlias::new(&secondary.email, primary.uuid.clone());
+    begin_transaction(&mut conn).await?;
+    let result: EmptyResult = async {
+        for mut membership in Membership::find_any_state_by_user(&secondary.uuid, &mut conn).await {
+            let org_id = membership.org_uuid.clone();
+            if data.memberships == MergeDecision::Delete
+                || Membership::find_by_user_and_org(&primary.uuid, &org_id, &mut conn).await.is_some()
+            {
+                events.push((EventType::OrganizationUserRemoved, membership.uuid.clone(), org_id));
+                membership.delete(&mut conn).await?;
+                continue;
+            }
+
+            for col_user in
+                CollectionUser::find_by_organization_and_user_uuid(&org_id, &secondary.uuid, &mut conn).await
+            {
+                CollectionUser::save(
+                    &primary.uuid,
+                    &col_user.collection_uuid,
+                    col_user.read_only,
+                    col_user.hide_passwords,
+                    col_user.manage,
+                    &mut conn,
+                )
+                .await?;
+            }
+            CollectionUser::delete_all_by_user_and_org(&secondary.uuid, &org_id, &mut conn).await?;
+
+            // The organization key and the account recovery key were encrypted for the secondary account
+            let revoked = membership.status < MembershipStatus::Invited as i32;
+            if membership.get_unrevoked_status() == MembershipStatus::Confirmed as i32 {
+                membership.status = MembershipStatus::Accepted as i32;
+                if revoked {
+                    membership.revoke();
+                }
+            }
+            membership.akey = String::new();
+            membership.reset_password_key = None;
+            membership.user_uuid = primary.uuid.clone();
+            membership.save(&mut conn).await?;
+            moved_memberships += 1;
+            events.push((EventType::OrganizationUserUpdated, membership.uuid, org_id));
+        }
+
+        if data.devices == MergeDecision::Move {
+            for device in Device::find_by_user(&secondary.uuid, &mut conn).await {
+                // The push registration belongs to the secondary account, the device registers again after logging in
+                push_uuids.push((device.uuid.clone(), device.push_uuid.clone()));
+                if Device::find_by_uuid_and_user(&device.uuid, &primary.uuid, &mut conn).await.is_some() {
+                    device.delete(&mut conn).await?;
+                } else {
+                    device.transfer(&primary.uuid, &mut conn).await?;
+                    moved_devices += 1;
+                }
+            }
+        }
+
+        UserAlias::transfer_all(&secondary.uuid, &primary.uuid, &mut conn).await?;
+        // Saved first, so the email of the secondary account is never free for a new account in between
+        alias.save(&mut conn).await?;
+        secondary.delete(&mut conn).await
+    }
+    .await;
+    if let Err(e) = result {
+        if let Err(rollback_err) = rollback_transaction(&mut conn).await {
+            error!("Error rolling back the merge of the accounts: {rollback_err:#?}");
+        }
+        return Err(e);
+    }
+    commit_transaction(&mut conn).await?;
+    User::update_uuid_revision(&primary.uuid, &mut conn).await;
+
+    for (event_type, member_id, org_id) in events {
+        log_event(
+            event_type as i32,
+            &member_id,
+            &org_id,
+            &ACTING_ADMIN_USER.into(),
+            14, // Use UnknownBrowser type
+            &token.ip.ip,
+            &mut conn,
+        )
+        .await;
+    }
+    for (device_id, push_uuid) in push_uuids {
+        if let Err(e) = unregister_push_device(&push_uuid).await {
+            warn!("Unable to unregister device {device_id} from the push relay: {e:#?}");
+        }
+    }
+
+    info!("Merged account {} into {}, its email {} is now an alias", data.secondary_user_id, primary.uuid, alias.email);
+    Ok(Json(json!({
+        "alias": alias.email,
+        "memberships": moved_memberships,
+        "devices": moved_devices,
+        "exportPath": export_path,
+    })))
+}
+
+#[get("/users/<user_id>/aliases")]
+async fn get_user_aliases(user_id: UserId, _token: AdminToken, mut conn: DbConn) -> JsonResult {
+    let user = get_user_or_404(&user_id, &mut conn).await?;
+    let aliases: Vec<Value> =
+        UserAlias::find_by_user(&user.uuid, &mut conn).await.iter().map(UserAlias::to_json).collect();
+    Ok(Json(Value::Array(aliases)))
+}
+
+#[derive(Debug, Deserialize)]
+struct UserAliasData {
+    email: String,
+}
+
+// The aliases added by an admin are verified right away
+#[post("/users/<user_id>/aliases", format = "application/json", data = "<data>")]
+async fn post_user_alias(
+    user_id: UserId,
+    data: Json<UserAliasData>,
+    _token: AdminToken,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: UserAliasData = data.into_inner();
+    let user = get_user_or_404(&user_id, &mut conn).await?;
+    UserAlias::check_available(&data.email, &mut conn).await?;
+
+    let alias = UserAlias::new(&data.email, user.uuid.clone());
+    alias.save(&mut conn).await?;
+    Ok(Json(alias.to_json()))
+}
+
+#[delete("/users/<user_id>/aliases/<email>")]
+async fn delete_user_alias(user_id: UserId, email: &str, _token: AdminToken, mut conn: DbConn) -> EmptyResult {
+    let Some(alias) = UserAlias::find_by_mail_and_user(email, &user_id, &mut conn).await else {
+        err_code!("Alias doesn't exist", Status::NotFound.code)
+    };
+    alias.delete(&mut conn).await
+}
+
+#[derive(Debug, Deserialize)]
+struct OrgStorageLimitsData {
+    // Limit in KB, None falls back to the global limit
+    attachment_limit: Option<i64>,
+}
+
+#[post("/organizations/<org_id>/storage", format = "application/json", data = "<data>")]
+async fn update_org_storage_limits(
+    org_id: OrganizationId,
+    data: Json<OrgStorageLimitsData>,
+    _token: AdminToken,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let data: OrgStorageLimitsData = data.into_inner();
+    if data.attachment_limit.is_some_and(|l| l < 0) {
+        err!("Storage limits can't be negative")
+    }
+
+    let mut org = Organization::find_by_uuid(&org_id, &mut conn).await.map_res("Organization doesn't exist")?;
+    org.attachment_limit = data.attachment_limit;
+    org.save(&mut conn).await
+}
+
+#[derive(Debug, Deserialize)]
+struct OrgSeatsData {
+    // None falls back to the global limit
+    max_seats: Option<i32>,
+}
+
+#[post("/organizations/<org_id>/seats", format = "application/json", data = "<data>")]
+async fn update_org_seats(
+    org_id: OrganizationId,
+    data: Json<OrgSeatsData>,
+    _token: AdminToken,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let data: OrgSeatsData = data.into_inner();
+    if data.max_seats.is_some_and(|s| s < 1) {
+        err!("The seat limit must be at least 1")
+    }
+
+    let mut org = Organization::find_by_uuid(&org_id, &mut conn).await.map_res("Organization doesn't exist")?;
+    org.max_seats = data.max_seats;
+    org.save(&mut conn).await
+}
+
+#[derive(Debug, Deserialize)]
+struct OrgInviteTextData {
+    // None or empty falls back to the default text of the invitation mails
+    invite_subject: Option<String>,
+    invite_body: Option<String>,
+    invite_reply_to: Option<String>,
+}
+
+#[post("/organizations/<org_id>/invite-text", format = "application/json", data = "<data>")]
+async fn update_org_invite_text(
+    org_id: OrganizationId,
+    data: Json<OrgInviteTextData>,
+    _token: AdminToken,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let data: OrgInviteTextData = data.into_inner();
+    let mut org = Organization::find_by_uuid(&org_id, &mut conn).await.map_res("Organization doesn't exist")?;
+    org.set_invite_text(data.invite_subject, data.invite_body, data.invite_reply_to)?;
+    org.save(&mut conn).await
+}
+
+#[derive(Debug, Deserialize)]
+struct OrgArchivedData {
+    archived: bool,
+}
+
+#[post("/organizations/<org_id>/archive", format = "application/json", data = "<data>")]
+async fn update_org_archived(
+    org_id: OrganizationId,
+    data: Json<OrgArchivedData>,
+    _token: AdminToken,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
+    let mut org = Organization::find_by_uuid(&org_id, &mut conn).await.map_res("Organization doesn't exist")?;
+    set_org_archived(&mut org, data.archived, &nt, &mut conn).await
+}
+
+#[derive(Debug, Deserialize)]
+struct OrgDomainData {
+    domain_name: String,
+    member_type: Option<i32>,
+    collections: Option<Vec<CollectionId>>,
+    auto_confirm: Option<bool>,
+}
+
+// Domains claimed by the admin are trusted right away, they don't need to be verified by the organization.
+#[post("/organizations/<org_id>/domains", format = "application/json", data = "<data>")]
+async fn claim_org_domain(
+    org_id: OrganizationId,
+    data: Json<OrgDomainData>,
+    _token: AdminToken,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: OrgDomainData = data.into_inner();
+    if !is_valid_domain_name(&data.domain_name) {
+        err!("Invalid domain name")
+    }
+    let org = Organization::find_by_uuid(&org_id, &mut conn).await.map_res("Organization doesn't exist")?;
+
+    let mut domain = OrgDomain::new(org.uuid.clone(), &data.domain_name);
+    if let Some(claimed) = OrgDomain::find_verified_by_domain(&domain.domain_name, &mut conn).await {
+        if claimed.org_uuid != org.uuid {
+            err!("Domain is already claimed by another organization")
+        }
+        domain = claimed;
+    } else if let Some(existing) =
+        OrgDomain::find_by_org(&org.uuid, &mut conn).await.into_iter().find(|d| d.domain_name == domain.domain_name)
+    {
+        domain = existing;
+    }
+
+    let member_type = data.member_type.unwrap_or(domain.member_type);
+    let collections = data.collections.unwrap_or_else(|| domain.get_collections());
+    let auto_confirm = data.auto_confirm.unwrap_or(domain.auto_confirm);
+    set_membership_defaults(&mut domain, member_type, collections, auto_confirm, &mut conn).await?;
+    domain.verified_at.get_or_insert_with(|| chrono::Utc::now().naive_utc());
+    domain.save(&mut conn).await?;
+
+    Ok(Json(domain.to_json()))
+}
+
+#[delete("/organizations/<org_id>/domains/<domain_id>", format = "application/json")]
+async fn delete_org_domain(
+    org_id: OrganizationId,
+    domain_id: OrgDomainId,
+    _token: AdminToken,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let Some(domain) = OrgDomain::find_by_uuid_and_org(&domain_id, &org_id, &mut conn).await else {
+        err_code!("Domain doesn't exist", Status::NotFound.code);
+    };
+    domain.delete(&mut conn).await
 }
 
 #[post("/users/update_revision", format = "application/json")]
@@ -582,24 +1437,272 @@ async fn update_revision_users(_token: AdminToken, mut conn: DbConn) -> EmptyRes
     User::update_all_revisions(&mut conn).await
 }
 
+pub fn kdf_description(kdf_type: i32, iterations: i32) -> String {
+    if kdf_type == UserKdfType::Argon2id as i32 {
+        format!("Argon2id with {iterations} iterations")
+    } else {
+        format!("PBKDF2 with {iterations} iterations")
+    }
+}
+
+pub fn kdf_minimum_description() -> String {
+    if CONFIG.kdf_require_argon2id() {
+        String::from("Argon2id")
+    } else {
+        format!("PBKDF2 with {} iterations or Argon2id", CONFIG.kdf_min_pbkdf2_iterations())
+    }
+}
+
+#[get("/kdf-campaign")]
+async fn get_kdf_campaign(_token: AdminToken, mut conn: DbConn) -> Json<Value> {
+    let users: Vec<User> =
+        User::get_all(&mut conn).await.into_iter().map(|(u, _)| u).filter(|u| !u.password_hash.is_empty()).collect();
+
+    let below_minimum = users.iter().filter(|u| u.is_kdf_below_minimum()).count();
+    let notified = users.iter().filter(|u| u.kdf_campaign_at.is_some()).count();
+    let migrated = users.iter().filter(|u| u.kdf_campaign_at.is_some() && !u.is_kdf_below_minimum()).count();
+    let last_campaign = users.iter().filter_map(|u| u.kdf_campaign_at).max();
+
+    Json(json!({
+        "total_users": users.len(),
+        "below_minimum": below_minimum,
+        "notified": notified,
+        "migrated": migrated,
+        "minimum": kdf_minimum_description(),
+        "last_campaign": last_campaign.map(|dt| format_naive_datetime_local(&dt, DT_FMT)),
+    }))
+}
+
+// Flags and mails all users below the configured KDF minimum, starting it again sends a reminder to those who didn't update yet.
+#[post("/kdf-campaign", format = "application/json")]
+async fn start_kdf_campaign(_token: AdminToken, mut conn: DbConn) -> JsonResult {
+    let minimum = kdf_minimum_description();
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut flagged = 0;
+    for (mut user, _) in User::get_all(&mut conn).await {
+        if user.password_hash.is_empty() || !user.enabled || user.is_soft_deleted() || !user.is_kdf_below_minimum() {
+            continue;
+        }
+
+        user.kdf_campaign_at = Some(now);
+        user.save(&mut conn).await?;
+        flagged += 1;
+
+        if CONFIG.mail_enabled() {
+            let current = kdf_description(user.client_kdf_type, user.client_kdf_iter);
+            if let Err(e) = mail::send_kdf_migration(&user.email, &current, &minimum).await {
+                error!("Error sending KDF migration email to {}: {e:#?}", user.email);
+            }
+        }
+    }
+
+    Ok(Json(json!({
+        "flagged": flagged,
+    })))
+}
+
+#[get("/ldap-sync")]
+fn get_ldap_sync(_token: AdminToken) -> Json<Value> {
+    let (report, running) = last_ldap_sync_report();
+    Json(json!({
+        "enabled": CONFIG.ldap_sync_enabled(),
+        "running": running,
+        "report": report,
+    }))
+}
+
+// Runs the LDAP sync right away, with `dry_run` the changes are only reported
+#[post("/ldap-sync?<dry_run>", format = "application/json")]
+async fn post_ldap_sync(dry_run: Option<bool>, _token: AdminToken, mut conn: DbConn) -> JsonResult {
+    if !CONFIG.ldap_sync_enabled() {
+        err!("The LDAP sync is not enabled")
+    }
+    let dry_run = dry_run.unwrap_or(false) || CONFIG.ldap_sync_dry_run();
+    Ok(Json(run_ldap_sync(dry_run, &mut conn).await))
+}
+
+// The state of the last replication and how the replica storage compared with the primary one, see `REPLICA_FOLDER`
+#[get("/file-replication")]
+async fn get_file_replication(_token: AdminToken) -> JsonResult {
+    if CONFIG.replica_folder().is_none() {
+        err!("`REPLICA_FOLDER` is not configured")
+    }
+    Ok(Json(check_file_replication().await))
+}
+
+// Copies the missing files right away, like before switching over to the replica. The replica is listed again.
+#[post("/file-replication", format = "application/json")]
+async fn post_file_replication(_token: AdminToken, mut conn: DbConn) -> JsonResult {
+    if CONFIG.replica_folder().is_none() {
+        err!("`REPLICA_FOLDER` is not configured")
+    }
+    Ok(Json(run_file_replication(true, &mut conn).await))
+}
+
+// The rows pointing to users, organizations or items which don't exist anymore, see the `check-db` command
+#[get("/db-integrity")]
+async fn get_db_integrity(_token: AdminToken, mut conn: DbConn) -> JsonResult {
+    let issues = check_integrity(&mut conn).await?;
+    Ok(Json(json!({
+        "rows": issues.iter().map(|issue| issue.rows).sum::<usize>(),
+        "issues": issues.iter().map(IntegrityIssue::to_json).collect::<Vec<_>>(),
+    })))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DbIntegrityRepairData {
+    // The `rows` of the reviewed check, nothing is removed when the check finds something else now
+    expected_rows: usize,
+}
+
+// Removes the rows found by the check, SQLite databases are backed up first
+#[post("/db-integrity/repair", format = "application/json", data = "<data>")]
+async fn post_db_integrity_repair(
+    data: Json<DbIntegrityRepairData>,
+    _token: AdminToken,
+    mut conn: DbConn,
+) -> JsonResult {
+    let expected_rows = data.into_inner().expected_rows;
+    let rows: usize = check_integrity(&mut conn).await?.iter().map(|issue| issue.rows).sum();
+    if rows != expected_rows {
+        err!(format!("The check now finds {rows} rows instead of {expected_rows}, review them again before the repair"))
+    }
+
+    let backup_file = if *CAN_BACKUP && rows > 0 {
+        Some(backup_database(&mut conn).await?)
+    } else {
+        None
+    };
+    let removed = repair_integrity(&mut conn).await?;
+    Ok(Json(json!({
+        "removed": removed,
+        "backupFile": backup_file,
+    })))
+}
+
+/// The users which still have a master password hint, each with the organizations whose
+/// DisablePasswordHints policy applies to them. Revoked memberships don't count.
+async fn find_password_hints(conn: &mut DbConn) -> Vec<(User, Vec<OrganizationId>)> {
+    let policy_orgs: std::collections::HashSet<OrganizationId> =
+        OrgPolicy::find_enabled_by_type(OrgPolicyType::DisablePasswordHints, conn)
+            .await
+            .into_iter()
+            .map(|policy| policy.org_uuid)
+            .collect();
+
+    let mut hints = Vec::new();
+    for user in User::find_with_password_hint(conn).await {
+        let orgs = Membership::find_by_user(&user.uuid, conn)
+            .await
+            .into_iter()
+            .filter(|member| {
+                member.status != MembershipStatus::Revoked as i32 && policy_orgs.contains(&member.org_uuid)
+            })
+            .map(|member| member.org_uuid)
+            .collect();
+        hints.push((user, orgs));
+    }
+    hints
+}
+
+// The accounts which still have a password hint, the hints themselves aren't listed
+#[get("/password-hints")]
+async fn get_password_hints(_token: AdminToken, mut conn: DbConn) -> Json<Value> {
+    let hints_allowed = CONFIG.password_hints_allowed();
+    let users: Vec<Value> = find_password_hints(&mut conn)
+        .await
+        .iter()
+        .map(|(user, orgs)| {
+            json!({
+                "id": user.uuid,
+                "email": user.email,
+                "name": user.name,
+                "disallowed": !hints_allowed || !orgs.is_empty(),
+                "disallowedByOrganizations": orgs,
+            })
+        })
+        .collect();
+    Json(json!({
+        "hintsAllowed": hints_allowed,
+        "count": users.len(),
+        "disallowedCount": users.iter().filter(|user| user["disallowed"] == true).count(),
+        "users": users,
+    }))
+}
+
+// Removes the hints which aren't allowed anymore, all of them when `PASSWORD_HINTS_ALLOWED` is disabled
+#[post("/password-hints/clear", format = "application/json")]
+async fn post_password_hints_clear(_token: AdminToken, mut conn: DbConn) -> JsonResult {
+    let hints_allowed = CONFIG.password_hints_allowed();
+    let mut cleared = 0;
+    for (mut user, orgs) in find_password_hints(&mut conn).await {
+        if hints_allowed && orgs.is_empty() {
+            continue;
+        }
+        user.clear_password_hint(&mut conn).await?;
+        cleared += 1;
+    }
+    Ok(Json(json!({
+        "cleared": cleared,
+    })))
+}
+
+// The mails which failed every attempt, and the number of mails which are still waiting to be sent
+#[get("/mail-queue")]
+async fn get_mail_queue(_token: AdminToken, mut conn: DbConn) -> Json<Value> {
+    let failed = QueuedMail::find_failed(&mut conn).await;
+    Json(json!({
+        "enabled": CONFIG.mail_queue_enabled(),
+        "pending": QueuedMail::count_pending(&mut conn).await,
+        "failed": failed.iter().map(QueuedMail::to_json).collect::<Vec<Value>>(),
+    }))
+}
+
+#[post("/mail-queue/<mail_id>/retry", format = "application/json")]
+async fn retry_queued_mail(mail_id: QueuedMailId, _token: AdminToken, mut conn: DbConn) -> EmptyResult {
+    let Some(mut mail) = QueuedMail::find_by_uuid(&mail_id, &mut conn).await else {
+        err_code!("Mail doesn't exist", Status::NotFound.code);
+    };
+    mail.reset();
+    mail.save(&mut conn).await
+}
+
+#[delete("/mail-queue/<mail_id>", format = "application/json")]
+async fn delete_queued_mail(mail_id: QueuedMailId, _token: AdminToken, mut conn: DbConn) -> EmptyResult {
+    let Some(mail) = QueuedMail::find_by_uuid(&mail_id, &mut conn).await else {
+        err_code!("Mail doesn't exist", Status::NotFound.code);
+    };
+    mail.delete(&mut conn).await
+}
+
 #[get("/organizations/overview")]
 async fn organizations_overview(_token: AdminToken, mut conn: DbConn) -> ApiResult<Html<String>> {
-    let organizations = Organization::get_all(&mut conn).await;
+    let organizations_json = get_organizations_json(&mut conn).await;
+    let text = AdminTemplateData::new("admin/organizations", json!(organizations_json)).render()?;
+    Ok(Html(text))
+}
+
+async fn get_organizations_json(conn: &mut DbConn) -> Vec<Value> {
+    let organizations = Organization::get_all(conn).await;
     let mut organizations_json = Vec::with_capacity(organizations.len());
     for o in organizations {
         let mut org = o.to_json();
-        org["user_count"] = json!(Membership::count_by_org(&o.uuid, &mut conn).await);
-        org["cipher_count"] = json!(Cipher::count_by_org(&o.uuid, &mut conn).await);
-        org["collection_count"] = json!(Collection::count_by_org(&o.uuid, &mut conn).await);
-        org["group_count"] = json!(Group::count_by_org(&o.uuid, &mut conn).await);
-        org["event_count"] = json!(Event::count_by_org(&o.uuid, &mut conn).await);
-        org["attachment_count"] = json!(Attachment::count_by_org(&o.uuid, &mut conn).await);
-        org["attachment_size"] = json!(get_display_size(Attachment::size_by_org(&o.uuid, &mut conn).await));
+        org["user_count"] = json!(Membership::count_by_org(&o.uuid, conn).await);
+        org["cipher_count"] = json!(Cipher::count_by_org(&o.uuid, conn).await);
+        org["collection_count"] = json!(Collection::count_by_org(&o.uuid, conn).await);
+        org["group_count"] = json!(Group::count_by_org(&o.uuid, conn).await);
+        org["event_count"] = json!(Event::count_by_org(&o.uuid, conn).await);
+        org["attachment_count"] = json!(Attachment::count_by_org(&o.uuid, conn).await);
+        org["attachment_size"] = json!(get_display_size(Attachment::size_by_org(&o.uuid, conn).await));
+        org["attachment_limit"] = json!(o.attachment_limit);
+        org["max_seats"] = json!(o.max_seats);
+        org["effective_max_seats"] = json!(o.effective_max_seats());
+        org["seat_count"] = json!(Membership::count_seats_by_org(&o.uuid, conn).await);
         organizations_json.push(org);
     }
-
-    let text = AdminTemplateData::new("admin/organizations", json!(organizations_json)).render()?;
-    Ok(Html(text))
+    organizations_json
 }
 
 #[post("/organizations/<org_id>/delete", format = "application/json")]
@@ -608,6 +1711,216 @@ async fn delete_organization(org_id: OrganizationId, _token: AdminToken, mut con
     org.delete(&mut conn).await
 }
 
+// The REST API below offers the same actions as the admin panel, for infrastructure-as-code tools.
+// Besides the session cookie, `AdminApiToken` accepts the admin token as bearer token or the `x-vaultwarden-api` key.
+
+#[get("/api/users")]
+async fn api_get_users(token: AdminApiToken, conn: DbConn) -> Json<Value> {
+    get_users_json(token.0, conn).await
+}
+
+#[post("/api/users", format = "application/json", data = "<data>")]
+async fn api_invite_user(data: Json<InviteData>, token: AdminApiToken, conn: DbConn) -> JsonResult {
+    invite_user(data, token.0, conn).await
+}
+
+#[get("/api/users/<user_id>")]
+async fn api_get_user(user_id: UserId, token: AdminApiToken, conn: DbConn) -> JsonResult {
+    get_user_json(user_id, token.0, conn).await
+}
+
+#[delete("/api/users/<user_id>")]
+async fn api_delete_user(user_id: UserId, token: AdminApiToken, conn: DbConn, nt: Notify<'_>) -> EmptyResult {
+    delete_user(user_id, token.0, conn, nt).await
+}
+
+#[post("/api/users/<user_id>/restore")]
+async fn api_restore_user(user_id: UserId, token: AdminApiToken, conn: DbConn) -> EmptyResult {
+    restore_user(user_id, token.0, conn).await
+}
+
+#[post("/api/users/<user_id>/deauth")]
+async fn api_deauth_user(user_id: UserId, token: AdminApiToken, conn: DbConn, nt: Notify<'_>) -> EmptyResult {
+    deauth_user(user_id, token.0, conn, nt).await
+}
+
+#[post("/api/users/<user_id>/disable")]
+async fn api_disable_user(user_id: UserId, token: AdminApiToken, conn: DbConn, nt: Notify<'_>) -> EmptyResult {
+    disable_user(user_id, token.0, conn, nt).await
+}
+
+#[post("/api/users/<user_id>/enable")]
+async fn api_enable_user(user_id: UserId, token: AdminApiToken, conn: DbConn) -> EmptyResult {
+    enable_user(user_id, token.0, conn).await
+}
+
+#[post("/api/users/bulk", format = "application/json", data = "<data>")]
+async fn api_bulk_user_action(
+    data: Json<BulkUserActionData>,
+    token: AdminApiToken,
+    conn: DbConn,
+    nt: Notify<'_>,
+) -> Json<Value> {
+    bulk_user_action(data, token.0, conn, nt).await
+}
+
+#[delete("/api/users/<user_id>/2fa")]
+async fn api_remove_2fa(user_id: UserId, token: AdminApiToken, conn: DbConn) -> EmptyResult {
+    remove_2fa(user_id, token.0, conn).await
+}
+
+#[post("/api/users/update-revision")]
+async fn api_update_revision_users(token: AdminApiToken, conn: DbConn) -> EmptyResult {
+    update_revision_users(token.0, conn).await
+}
+
+#[get("/api/organizations")]
+async fn api_get_organizations(_token: AdminApiToken, mut conn: DbConn) -> Json<Value> {
+    Json(Value::Array(get_organizations_json(&mut conn).await))
+}
+
+#[delete("/api/organizations/<org_id>")]
+async fn api_delete_organization(org_id: OrganizationId, token: AdminApiToken, conn: DbConn) -> EmptyResult {
+    delete_organization(org_id, token.0, conn).await
+}
+
+// The daily snapshots of the instance totals, used by the charts of the diagnostics page
+#[get("/api/stats?<days>")]
+async fn api_get_stats(days: Option<u32>, _token: AdminApiToken, mut conn: DbConn) -> Json<Value> {
+    let days = days.unwrap_or(90).clamp(1, 3650);
+    let since = chrono::Utc::now().date_naive() - chrono::TimeDelta::days(i64::from(days));
+    let stats = DailyStat::find_since(since, &mut conn).await;
+
+    Json(json!({
+        "object": "list",
+        "data": stats.iter().map(DailyStat::to_json).collect::<Vec<Value>>(),
+    }))
+}
+
+// The state of the database connection pool and the time requests waited for a connection, and the icon cache counters
+#[get("/api/metrics")]
+fn api_get_metrics(_token: AdminApiToken, pool: &rocket::State<DbPool>) -> Json<Value> {
+    Json(json!({
+        "databasePool": pool.state_json(),
+        "databaseUsage": metrics::to_json(),
+        "iconCache": icon_cache_metrics(),
+    }))
+}
+
+// Signup rules decide which email domains can register, the first matching rule by `position` wins.
+// Without a matching rule, `SIGNUPS_ALLOWED` and `SIGNUPS_DOMAINS_WHITELIST` still apply.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignupRuleData {
+    pattern: String,
+    action: String,
+    position: Option<i32>,
+    organization_id: Option<OrganizationId>,
+    collection_id: Option<CollectionId>,
+    bypass_invitation: Option<bool>,
+}
+
+#[get("/api/signup-rules")]
+async fn api_get_signup_rules(_token: AdminApiToken, mut conn: DbConn) -> Json<Value> {
+    let rules = SignupRule::get_all(&mut conn).await;
+    Json(json!({
+        "object": "list",
+        "data": rules.iter().map(SignupRule::to_json).collect::<Vec<Value>>(),
+    }))
+}
+
+#[post("/api/signup-rules", format = "application/json", data = "<data>")]
+async fn api_post_signup_rule(data: Json<SignupRuleData>, _token: AdminApiToken, mut conn: DbConn) -> JsonResult {
+    let data: SignupRuleData = data.into_inner();
+    // New rules are added after the existing ones, unless a position is given
+    let position = match data.position {
+        Some(position) => position,
+        None => SignupRule::get_all(&mut conn).await.last().map_or(0, |rule| rule.position.saturating_add(1)),
+    };
+
+    let mut rule = SignupRule::new(String::new(), SignupRuleAction::Allow, position);
+    update_signup_rule(&mut rule, data, &mut conn).await?;
+    rule.save(&mut conn).await?;
+
+    Ok(Json(rule.to_json()))
+}
+
+#[put("/api/signup-rules/<rule_id>", format = "application/json", data = "<data>")]
+async fn api_put_signup_rule(
+    rule_id: SignupRuleId,
+    data: Json<SignupRuleData>,
+    _token: AdminApiToken,
+    mut conn: DbConn,
+) -> JsonResult {
+    let Some(mut rule) = SignupRule::find_by_uuid(&rule_id, &mut conn).await else {
+        err_code!("Signup rule doesn't exist", Status::NotFound.code);
+    };
+    let data: SignupRuleData = data.into_inner();
+    if let Some(position) = data.position {
+        rule.position = position;
+    }
+
+    update_signup_rule(&mut rule, data, &mut conn).await?;
+    rule.save(&mut conn).await?;
+
+    Ok(Json(rule.to_json()))
+}
+
+#[delete("/api/signup-rules/<rule_id>")]
+async fn api_delete_signup_rule(rule_id: SignupRuleId, _token: AdminApiToken, mut conn: DbConn) -> EmptyResult {
+    let Some(rule) = SignupRule::find_by_uuid(&rule_id, &mut conn).await else {
+        err_code!("Signup rule doesn't exist", Status::NotFound.code);
+    };
+    rule.delete(&mut conn).await
+}
+
+async fn update_signup_rule(rule: &mut SignupRule, data: SignupRuleData, conn: &mut DbConn) -> EmptyResult {
+    let pattern = data.pattern.trim().to_lowercase();
+    if !SignupRule::is_valid_pattern(&pattern) {
+        err!("Invalid pattern, use a domain name, `*.` followed by a domain name or `*`")
+    }
+    let Some(action) = SignupRuleAction::from_str(&data.action) else {
+        err!("Invalid action, use `allow` or `deny`")
+    };
+
+    if let Some(org_id) = &data.organization_id {
+        if action == SignupRuleAction::Deny {
+            err!("Only allowing rules can add users to an organization")
+        }
+        if Organization::find_by_uuid(org_id, conn).await.is_none() {
+            err!("Organization doesn't exist")
+        }
+    }
+    if let Some(col_id) = &data.collection_id {
+        let Some(org_id) = &data.organization_id else {
+            err!("A collection requires an organization")
+        };
+        if Collection::find_by_uuid_and_org(col_id, org_id, conn).await.is_none() {
+            err!("Collection not found in Organization")
+        }
+    }
+
+    rule.pattern = pattern;
+    rule.action = action as i32;
+    rule.org_uuid = data.organization_id;
+    rule.collection_uuid = data.collection_id;
+    rule.bypass_invitation = data.bypass_invitation.unwrap_or(false);
+    Ok(())
+}
+
+pub async fn daily_stats_job(pool: DbPool) {
+    debug!("Start daily stats job");
+    let Ok(mut conn) = pool.get().await else {
+        error!("Failed to get DB connection while running the daily stats job");
+        return;
+    };
+
+    let stats = DailyStat::collect(chrono::Utc::now().date_naive(), &mut conn).await;
+    if let Err(e) = stats.save(&mut conn).await {
+        error!("Failed to save the daily stats: {e:?}");
+    }
+}
+
 #[derive(Deserialize)]
 struct GitRelease {
     tag_name: String,
@@ -782,6 +2095,36 @@ async fn post_config(data: Json<ConfigBuilder>, _token: AdminToken) -> EmptyResu
     Ok(())
 }
 
+/// Validates a candidate config without applying it, so deployment pipelines can check changes beforehand.
+/// With `smtp=true` the configured SMTP server is also checked for reachability.
+#[post("/api/config/validate?<smtp>", format = "application/json", data = "<data>")]
+async fn validate_config_candidate(smtp: Option<bool>, data: Json<Value>, _token: AdminApiToken) -> JsonResult {
+    let Value::Object(candidate) = data.into_inner() else {
+        err!("The config must be a JSON object")
+    };
+    let mut check = CONFIG.check_config(candidate);
+
+    if smtp.unwrap_or(false) {
+        match &check.smtp_server {
+            Some((host, port)) => {
+                let timeout = std::time::Duration::from_secs(check.smtp_timeout);
+                match tokio::time::timeout(timeout, tokio::net::TcpStream::connect((host.as_str(), *port))).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => check.errors.push(format!("SMTP server `{host}:{port}` is not reachable: {e}")),
+                    Err(_) => check.errors.push(format!("SMTP server `{host}:{port}` did not respond in time")),
+                }
+            }
+            None => check.warnings.push(String::from("SMTP is not configured, the reachability check was skipped")),
+        }
+    }
+
+    Ok(Json(json!({
+        "valid": check.errors.is_empty(),
+        "errors": check.errors,
+        "warnings": check.warnings,
+    })))
+}
+
 #[post("/config/delete", format = "application/json")]
 async fn delete_config(_token: AdminToken) -> EmptyResult {
     if let Err(e) = CONFIG.delete_user_config().await {
@@ -802,6 +2145,28 @@ async fn backup_db(_token: AdminToken, mut conn: DbConn) -> ApiResult<String> {
     }
 }
 
+// Holds the JWT issued for a valid admin token while the second step of the admin login is pending
+pub struct AdminTwoFactorPending;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminTwoFactorPending {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let cookies = request.cookies();
+        let Some(cookie) = cookies.get(TWO_FACTOR_COOKIE_NAME) else {
+            return Outcome::Error((Status::Unauthorized, "Unauthorized"));
+        };
+
+        if decode_admin_two_factor(cookie.value()).is_err() {
+            cookies.remove(Cookie::build(TWO_FACTOR_COOKIE_NAME).path(admin_path()));
+            return Outcome::Error((Status::Unauthorized, "Two-step login expired"));
+        }
+
+        Outcome::Success(Self)
+    }
+}
+
 pub struct AdminToken {
     ip: ClientIp,
 }
@@ -851,3 +2216,53 @@ impl<'r> FromRequest<'r> for AdminToken {
         }
     }
 }
+
+/// The guard of the JSON API under `/admin/api`. Besides the session of the admin panel, API clients can send the
+/// admin token as bearer token or the `x-vaultwarden-api` key, which are not accepted by the rest of the admin panel.
+pub struct AdminApiToken(AdminToken);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminApiToken {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let ip = match ClientIp::from_request(request).await {
+            Outcome::Success(ip) => ip,
+            _ => err_handler!("Error getting Client IP"),
+        };
+
+        if CONFIG.disable_admin_token() {
+            return AdminToken::from_request(request).await.map(Self);
+        }
+
+        let valid = if let Some(auth) = request.headers().get_one("Authorization") {
+            // The admin token alone is not enough with a second factor required, API clients use `x-vaultwarden-api`
+            if CONFIG.admin_two_factor() {
+                error!("Admin token in authorization header refused, two-step login is required. IP: {}", ip.ip);
+                return Outcome::Error((Status::Unauthorized, "Admin two-step login is required"));
+            }
+            if crate::ratelimit::check_admin_api_blocked(&ip.ip).is_err() {
+                return Outcome::Error((Status::TooManyRequests, "Too many admin requests"));
+            }
+            auth.strip_prefix("Bearer ").is_some_and(_validate_token)
+        } else if let Some(api_key) = request.headers().get_one("x-vaultwarden-api") {
+            if crate::ratelimit::check_admin_api_blocked(&ip.ip).is_err() {
+                return Outcome::Error((Status::TooManyRequests, "Too many admin requests"));
+            }
+            CONFIG.x_vaultwarden_api().is_some_and(|expected| crate::crypto::ct_eq(api_key, expected))
+        } else {
+            return AdminToken::from_request(request).await.map(Self);
+        };
+
+        if valid {
+            Outcome::Success(Self(AdminToken {
+                ip,
+            }))
+        } else {
+            // Only the failed requests count, API clients call it far more often than the login form is used
+            error!("Invalid admin API credentials. IP: {}", ip.ip);
+            crate::ratelimit::register_failed_admin_api(&ip.ip);
+            Outcome::Error((Status::Unauthorized, "Invalid admin API credentials"))
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{collections::HashMap, time::Duration};
+
+use once_cell::sync::Lazy;
+use reqwest::Method;
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use crate::{
+    api::ApiResult,
+    db::{models::*, DbConn, DbPool},
+    http_client::make_http_request,
+    CONFIG,
+};
+
+// With the request delay a check can take longer than the schedule interval, this prevents overlapping runs
+static BREACH_CHECK_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+pub async fn breach_check_job(pool: DbPool) {
+    debug!("Start breach check job");
+    let Some(api_key) = CONFIG.hibp_api_key() else {
+        debug!("HIBP API key is not set, abort");
+        return;
+    };
+    let Ok(_lock) = BREACH_CHECK_LOCK.try_lock() else {
+        warn!("The previous breach check is still running, skipping this one");
+        return;
+    };
+
+    if let Ok(mut conn) = pool.get().await {
+        check_organizations(&api_key, &mut conn).await;
+    } else {
+        error!("Failed to get DB connection while running the breach check")
+    }
+}
+
+/// Checks the confirmed members of every organization with the breach monitoring policy enabled.
+/// The findings of each member are replaced by the latest result, an email address is only queried once per run.
+async fn check_organizations(api_key: &str, conn: &mut DbConn) {
+    let delay = Duration::from_millis(CONFIG.hibp_request_delay());
+    let mut checked: HashMap<String, Vec<Value>> = HashMap::new();
+
+    for policy in OrgPolicy::find_enabled_by_type(OrgPolicyType::BreachMonitoring, conn).await {
+        let org_id = policy.org_uuid;
+        let mut user_ids = Vec::new();
+
+        for member in Membership::find_confirmed_by_org(&org_id, conn).await {
+            let Some(user) = User::find_by_uuid(&member.user_uuid, conn).await else {
+                continue;
+            };
+            user_ids.push(user.uuid.clone());
+
+            let breaches = match checked.get(&user.email) {
+                Some(breaches) => breaches.clone(),
+                None => {
+                    if !checked.is_empty() {
+                        tokio::time::sleep(delay).await;
+                    }
+                    match query_breaches(&user.email, api_key).await {
+                        Ok(breaches) => {
+                            checked.insert(user.email.clone(), breaches.clone());
+                            breaches
+                        }
+                        Err(e) => {
+                            // Invalid keys and rate limits affect every following request as well
+                            error!("Breach check aborted, querying HIBP failed: {e:?}");
+                            return;
+                        }
+                    }
+                }
+            };
+
+            if let Err(e) = BreachFinding::delete_all_by_org_and_user(&org_id, &user.uuid, conn).await {
+                error!("Failed to clear the breach findings of {}: {e:?}", user.uuid);
+                continue;
+            }
+            for breach in &breaches {
+                if let Err(e) = BreachFinding::from_hibp(org_id.clone(), user.uuid.clone(), breach).save(conn).await {
+                    error!("Failed to save a breach finding of {}: {e:?}", user.uuid);
+                }
+            }
+        }
+
+        if let Err(e) = BreachFinding::delete_all_by_org_except_users(&org_id, &user_ids, conn).await {
+            error!("Failed to clear the breach findings of former members of {org_id}: {e:?}");
+        }
+    }
+}
+
+/// Returns the breaches the email address is part of, an address without any breaches gives a 404.
+async fn query_breaches(email: &str, api_key: &str) -> ApiResult<Vec<Value>> {
+    let email: String = url::form_urlencoded::byte_serialize(email.as_bytes()).collect();
+    let url = format!("https://haveibeenpwned.com/api/v3/breachedaccount/{email}?truncateResponse=false");
+
+    let res = make_http_request(Method::GET, &url)?.header("hibp-api-key", api_key).send().await?;
+    if res.status() == 404 {
+        return Ok(Vec::new());
+    }
+    Ok(res.error_for_status()?.json().await?)
+}
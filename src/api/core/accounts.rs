@@ -1,17 +1,21 @@
 use std::collections::HashSet;
 
 use crate::db::DbPool;
-use chrono::Utc;
+use chrono::{TimeDelta, Utc};
 use rocket::serde::json::Json;
 use serde_json::Value;
 
 use crate::{
     api::{
-        core::{accept_org_invite, log_user_event, two_factor::email},
+        admin::ACTING_ADMIN_USER,
+        check_report_invite_gate,
+        core::{
+            accept_org_invite, capture_domain_user, check_claimed_account, log_event, log_user_event, two_factor::email,
+        },
         master_password_policy, register_push_device, unregister_push_device, AnonymousNotify, ApiResult, EmptyResult,
         JsonResult, Notify, PasswordOrOtpData, UpdateType,
     },
-    auth::{decode_delete, decode_invite, decode_verify_email, ClientHeaders, Headers},
+    auth::{decode_delete, decode_invite, decode_verify_email, ClientHeaders, ClientIp, Headers},
     crypto,
     db::{models::*, DbConn},
     mail,
@@ -31,15 +35,23 @@ pub fn routes() -> Vec<rocket::Route> {
         put_profile,
         post_profile,
         put_avatar,
+        put_trash_retention,
         get_public_keys,
         post_keys,
         post_password,
+        put_update_temp_password,
         post_set_password,
+        post_set_key_connector_key,
+        post_convert_to_key_connector,
         post_kdf,
         post_rotatekey,
         post_sstamp,
         post_email_token,
         post_email,
+        get_aliases,
+        post_alias,
+        post_alias_verify,
+        delete_alias,
         post_verify_email,
         post_verify_email_token,
         post_delete_recover,
@@ -55,6 +67,10 @@ pub fn routes() -> Vec<rocket::Route> {
         get_known_device,
         get_all_devices,
         get_device,
+        put_device_name,
+        post_device_name,
+        post_deactivate_device,
+        delete_device,
         post_device_token,
         put_device_token,
         put_clear_device_token,
@@ -64,6 +80,7 @@ pub fn routes() -> Vec<rocket::Route> {
         put_auth_request,
         get_auth_request_response,
         get_auth_requests,
+        get_pending_auth_requests,
     ]
 }
 
@@ -136,10 +153,23 @@ fn clean_password_hint(password_hint: &Option<String>) -> Option<String> {
     }
 }
 
-fn enforce_password_hint_setting(password_hint: &Option<String>) -> EmptyResult {
-    if password_hint.is_some() && !CONFIG.password_hints_allowed() {
+/// Checks `PASSWORD_HINTS_ALLOWED`, and the DisablePasswordHints policy of the organizations of an existing user.
+async fn enforce_password_hint_setting(
+    password_hint: &Option<String>,
+    user_uuid: Option<&UserId>,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    if password_hint.is_none() {
+        return Ok(());
+    }
+    if !CONFIG.password_hints_allowed() {
         err!("Password hints have been disabled by the administrator. Remove the hint and try again.");
     }
+    if let Some(user_uuid) = user_uuid {
+        if OrgPolicy::is_password_hint_disabled(user_uuid, conn).await {
+            err!("Password hints have been disabled by an organization you are a member of. Remove the hint and try again.");
+        }
+    }
     Ok(())
 }
 async fn is_email_2fa_required(member_id: Option<MembershipId>, conn: &mut DbConn) -> bool {
@@ -156,11 +186,18 @@ async fn is_email_2fa_required(member_id: Option<MembershipId>, conn: &mut DbCon
 }
 
 #[post("/accounts/register", data = "<data>")]
-async fn register(data: Json<RegisterData>, conn: DbConn) -> JsonResult {
-    _register(data, false, conn).await
+async fn register(data: Json<RegisterData>, ip: ClientIp, conn: DbConn) -> JsonResult {
+    _register(data, false, &ip, conn).await
 }
 
-pub async fn _register(data: Json<RegisterData>, email_verification: bool, mut conn: DbConn) -> JsonResult {
+pub async fn _register(
+    data: Json<RegisterData>,
+    email_verification: bool,
+    ip: &ClientIp,
+    mut conn: DbConn,
+) -> JsonResult {
+    crate::ratelimit::check_limit_register(&ip.ip)?;
+
     let mut data: RegisterData = data.into_inner();
     let email = data.email.to_lowercase();
 
@@ -240,9 +277,10 @@ pub async fn _register(data: Json<RegisterData>, email_verification: bool, mut c
     // Check against the password hint setting here so if it fails, the user
     // can retry without losing their invitation below.
     let password_hint = clean_password_hint(&data.master_password_hint);
-    enforce_password_hint_setting(&password_hint)?;
+    let existing_user = User::find_by_mail(&email, &mut conn).await;
+    enforce_password_hint_setting(&password_hint, existing_user.as_ref().map(|user| &user.uuid), &mut conn).await?;
 
-    let mut user = match User::find_by_mail(&email, &mut conn).await {
+    let mut user = match existing_user {
         Some(user) => {
             if !user.password_hash.is_empty() {
                 err!("Registration not allowed or user already exists")
@@ -260,7 +298,7 @@ pub async fn _register(data: Json<RegisterData>, email_verification: bool, mut c
             } else if Invitation::take(&email, &mut conn).await {
                 Membership::accept_user_invitations(&user.uuid, &mut conn).await?;
                 user
-            } else if CONFIG.is_signup_allowed(&email)
+            } else if SignupRule::is_signup_allowed(&email, &mut conn).await
                 || (CONFIG.emergency_access_allowed()
                     && EmergencyAccess::find_invited_by_grantee_email(&email, &mut conn).await.is_some())
             {
@@ -274,7 +312,7 @@ pub async fn _register(data: Json<RegisterData>, email_verification: bool, mut c
             // because the vaultwarden admin can invite anyone, regardless
             // of other signup restrictions.
             if Invitation::take(&email, &mut conn).await
-                || CONFIG.is_signup_allowed(&email)
+                || SignupRule::is_signup_allowed(&email, &mut conn).await
                 || pending_emergency_access.is_some()
             {
                 User::new(email.clone(), None)
@@ -306,6 +344,8 @@ pub async fn _register(data: Json<RegisterData>, email_verification: bool, mut c
         user.verified_at = Some(Utc::now().naive_utc());
     }
 
+    user.update_password_policy_pending(&mut conn).await;
+
     if CONFIG.mail_enabled() {
         if CONFIG.signups_verify() && !email_verified {
             if let Err(e) = mail::send_welcome_must_verify(&user.email, &user.uuid).await {
@@ -323,6 +363,15 @@ pub async fn _register(data: Json<RegisterData>, email_verification: bool, mut c
 
     user.save(&mut conn).await?;
 
+    if let Err(e) = capture_domain_user(&user, &mut conn).await {
+        error!("Error adding user to the organization of a claimed domain: {e:#?}");
+    }
+    if let Some(rule) = SignupRule::find_matching(&user.email, &mut conn).await {
+        if let Err(e) = join_signup_rule_org(&user, &rule, &mut conn).await {
+            error!("Error adding user to the organization of a signup rule: {e:#?}");
+        }
+    }
+
     // accept any open emergency access invitations
     if !CONFIG.mail_enabled() && CONFIG.emergency_access_allowed() {
         for mut emergency_invite in EmergencyAccess::find_all_invited_by_grantee_email(&user.email, &mut conn).await {
@@ -336,6 +385,62 @@ pub async fn _register(data: Json<RegisterData>, email_verification: bool, mut c
     })))
 }
 
+/// Adds a newly registered user to the organization, and collection, of the allowing signup rule which matched their email.
+/// Like with claimed domains, the member is either invited or, when the rule bypasses the invitation, created as accepted.
+async fn join_signup_rule_org(user: &User, rule: &SignupRule, conn: &mut DbConn) -> EmptyResult {
+    let Some(org_id) = rule.org_uuid.as_ref().filter(|_| rule.is_allowing()) else {
+        return Ok(());
+    };
+    if Membership::find_by_user_and_org(&user.uuid, org_id, conn).await.is_some()
+        || check_claimed_account(user, Some(org_id), conn).await.is_err()
+    {
+        return Ok(());
+    }
+    let Some(org) = Organization::find_by_uuid(org_id, conn).await else {
+        return Ok(());
+    };
+    if let Err(e) = org.check_available_seats(1, conn).await {
+        warn!("Not adding {} to the organization of the signup rule {}: {}", user.email, rule.pattern, e.message());
+        return Ok(());
+    }
+    if let Err(e) = check_report_invite_gate(org_id, false, conn).await {
+        warn!("Not adding {} to the organization of the signup rule {}: {}", user.email, rule.pattern, e.message());
+        return Ok(());
+    }
+
+    let mut member = Membership::new(user.uuid.clone(), org.uuid.clone(), None);
+    member.status = if rule.bypass_invitation || !CONFIG.mail_enabled() {
+        MembershipStatus::Accepted as i32
+    } else {
+        MembershipStatus::Invited as i32
+    };
+    member.save(conn).await?;
+
+    if let Some(col_id) = &rule.collection_uuid {
+        // The collection might have been deleted since the rule was created
+        if Collection::find_by_uuid_and_org(col_id, &org.uuid, conn).await.is_some() {
+            CollectionUser::save(&user.uuid, col_id, false, false, false, conn).await?;
+        }
+    }
+
+    if member.status == MembershipStatus::Invited as i32 {
+        mail::send_invite(user, org.uuid.clone(), member.uuid.clone(), &org.name, None, Some(&org)).await?;
+    }
+
+    log_event(
+        EventType::OrganizationUserInvited as i32,
+        &member.uuid,
+        &org.uuid,
+        &user.uuid,
+        14, // Use UnknownBrowser type
+        &std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+        conn,
+    )
+    .await;
+
+    Ok(())
+}
+
 #[post("/accounts/set-password", data = "<data>")]
 async fn post_set_password(data: Json<SetPasswordData>, headers: Headers, mut conn: DbConn) -> JsonResult {
     let data: SetPasswordData = data.into_inner();
@@ -348,7 +453,7 @@ async fn post_set_password(data: Json<SetPasswordData>, headers: Headers, mut co
     // Check against the password hint setting here so if it fails,
     // the user can retry without losing their invitation below.
     let password_hint = clean_password_hint(&data.master_password_hint);
-    enforce_password_hint_setting(&password_hint)?;
+    enforce_password_hint_setting(&password_hint, Some(&user.uuid), &mut conn).await?;
 
     set_kdf_data(&mut user, data.kdf)?;
 
@@ -387,6 +492,8 @@ async fn post_set_password(data: Json<SetPasswordData>, headers: Headers, mut co
         Membership::accept_user_invitations(&user.uuid, &mut conn).await?;
     }
 
+    user.update_password_policy_pending(&mut conn).await;
+
     log_user_event(EventType::UserChangedPassword as i32, &user.uuid, headers.device.atype, &headers.ip.ip, &mut conn)
         .await;
 
@@ -398,6 +505,89 @@ async fn post_set_password(data: Json<SetPasswordData>, headers: Headers, mut co
     })))
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetKeyConnectorKeyData {
+    #[serde(flatten)]
+    kdf: KDFData,
+
+    key: String,
+    keys: KeysData,
+    org_identifier: String,
+}
+
+// Used instead of `set-password` by new SSO users of an organization with the Key Connector policy
+#[post("/accounts/set-key-connector-key", data = "<data>")]
+async fn post_set_key_connector_key(
+    data: Json<SetKeyConnectorKeyData>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let data: SetKeyConnectorKeyData = data.into_inner();
+    let mut user = headers.user;
+
+    if user.private_key.is_some() || user.uses_key_connector {
+        err!("Account already initialized, cannot set key")
+    }
+
+    let Some(org) = Organization::find_by_name(&data.org_identifier, &mut conn).await else {
+        err!("Failed to retrieve the associated organization")
+    };
+    if OrgPolicy::find_key_connector_org(&user.uuid, &mut conn).await.as_ref() != Some(&org.uuid) {
+        err!("Key Connector is not enabled for this organization")
+    }
+    super::key_connector::check_key_stored(&user, &mut conn).await?;
+
+    set_kdf_data(&mut user, data.kdf)?;
+    user.akey = data.key;
+    user.private_key = Some(data.keys.encrypted_private_key);
+    user.public_key = Some(data.keys.public_key);
+    user.uses_key_connector = true;
+
+    if let Some(membership) = Membership::find_by_user_and_org(&user.uuid, &org.uuid, &mut conn).await {
+        if membership.status == MembershipStatus::Invited as i32 {
+            accept_org_invite(&user, membership, None, &mut conn).await?;
+        }
+    }
+
+    user.save(&mut conn).await
+}
+
+// Used by existing members of an organization with the Key Connector policy, their master password is removed
+#[post("/accounts/convert-to-key-connector")]
+async fn post_convert_to_key_connector(headers: Headers, mut conn: DbConn) -> EmptyResult {
+    let mut user = headers.user;
+
+    if user.uses_key_connector {
+        err!("Already uses Key Connector")
+    }
+    if OrgPolicy::find_key_connector_org(&user.uuid, &mut conn).await.is_none() {
+        err!("Key Connector is not enabled for this user")
+    }
+    // Owners and Admins need to keep their master password, like Bitwarden
+    if Membership::find_confirmed_by_user(&user.uuid, &mut conn).await.iter().any(|m| m.atype >= MembershipType::Admin)
+    {
+        err!("Cannot use Key Connector when admin or owner of an organization")
+    }
+    super::key_connector::check_key_stored(&user, &mut conn).await?;
+
+    user.password_hash = Vec::new();
+    user.password_hint = None;
+    user.uses_key_connector = true;
+    user.save(&mut conn).await?;
+
+    log_user_event(
+        EventType::UserMigratedKeyToKeyConnector as i32,
+        &user.uuid,
+        headers.device.atype,
+        &headers.ip.ip,
+        &mut conn,
+    )
+    .await;
+
+    Ok(())
+}
+
 #[get("/accounts/profile")]
 async fn profile(headers: Headers, mut conn: DbConn) -> Json<Value> {
     Json(headers.user.to_json(&mut conn).await)
@@ -432,6 +622,28 @@ async fn post_profile(data: Json<ProfileData>, headers: Headers, mut conn: DbCon
     Ok(Json(user.to_json(&mut conn).await))
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrashRetentionData {
+    // None falls back to `TRASH_AUTO_DELETE_DAYS`
+    trash_retention_days: Option<i32>,
+}
+
+// Vaultwarden specific, the number of days trashed personal items are kept, organization items use their own
+#[put("/accounts/trash-retention", data = "<data>")]
+async fn put_trash_retention(data: Json<TrashRetentionData>, headers: Headers, mut conn: DbConn) -> JsonResult {
+    let data: TrashRetentionData = data.into_inner();
+    // Limited to 100 years, so the purge can always compute its cutoff date
+    if data.trash_retention_days.is_some_and(|days| !(1..=36500).contains(&days)) {
+        err!("The trash retention has to be between 1 and 36500 days")
+    }
+
+    let mut user = headers.user;
+    user.trash_retention_days = data.trash_retention_days;
+    user.save(&mut conn).await?;
+    Ok(Json(user.to_json(&mut conn).await))
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct AvatarData {
@@ -510,11 +722,15 @@ async fn post_password(data: Json<ChangePassData>, headers: Headers, mut conn: D
     }
 
     user.password_hint = clean_password_hint(&data.master_password_hint);
-    enforce_password_hint_setting(&user.password_hint)?;
+    enforce_password_hint_setting(&user.password_hint, Some(&user.uuid), &mut conn).await?;
 
     log_user_event(EventType::UserChangedPassword as i32, &user.uuid, headers.device.atype, &headers.ip.ip, &mut conn)
         .await;
 
+    if user.force_password_reset && !user.check_valid_password(&data.new_master_password_hash) {
+        complete_forced_password_reset(&mut user, headers.device.atype, &headers.ip, &mut conn).await;
+    }
+
     user.set_password(
         &data.new_master_password_hash,
         Some(data.key),
@@ -537,6 +753,52 @@ async fn post_password(data: Json<ChangePassData>, headers: Headers, mut conn: D
     save_result
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateTempPasswordData {
+    new_master_password_hash: String,
+    master_password_hint: Option<String>,
+    key: String,
+}
+
+/// Used by the clients to set the new master password when `ForcePasswordReset` is returned on login.
+/// The current master password isn't asked again, the user just logged in with it.
+#[put("/accounts/update-temp-password", data = "<data>")]
+async fn put_update_temp_password(
+    data: Json<UpdateTempPasswordData>,
+    headers: Headers,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
+    let data: UpdateTempPasswordData = data.into_inner();
+    let mut user = headers.user;
+
+    if !user.force_password_reset {
+        err!("The master password doesn't have to be changed")
+    }
+    if user.check_valid_password(&data.new_master_password_hash) {
+        err!("The new master password has to be different from the current one")
+    }
+
+    user.password_hint = clean_password_hint(&data.master_password_hint);
+    enforce_password_hint_setting(&user.password_hint, Some(&user.uuid), &mut conn).await?;
+
+    complete_forced_password_reset(&mut user, headers.device.atype, &headers.ip, &mut conn).await;
+    user.set_password(&data.new_master_password_hash, Some(data.key), true, None);
+
+    let save_result = user.save(&mut conn).await;
+
+    nt.send_logout(&user, Some(headers.device.uuid.clone()), &mut conn).await;
+
+    save_result
+}
+
+/// Clears the flag set through the custom API, once a new master password has been chosen.
+async fn complete_forced_password_reset(user: &mut User, device_type: i32, ip: &ClientIp, conn: &mut DbConn) {
+    user.force_password_reset = false;
+    log_user_event(EventType::UserUpdatedTempPassword as i32, &user.uuid, device_type, &ip.ip, conn).await;
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct ChangeKdfData {
@@ -593,6 +855,7 @@ async fn post_kdf(data: Json<ChangeKdfData>, headers: Headers, mut conn: DbConn,
     }
 
     set_kdf_data(&mut user, data.kdf)?;
+    user.kdf_upgrade_required = false;
 
     user.set_password(&data.new_master_password_hash, Some(data.key), true, None);
     let save_result = user.save(&mut conn).await;
@@ -838,6 +1101,9 @@ async fn post_rotatekey(data: Json<KeyData>, headers: Headers, mut conn: DbConn,
     // Update cipher data
     use super::ciphers::update_cipher_from_data;
 
+    // The previous revisions of the personal items are encrypted with the old key
+    CipherHistory::delete_all_by_user(&headers.user.uuid, &mut conn).await?;
+
     for cipher_data in data.account_data.ciphers {
         if cipher_data.organization_id.is_none() {
             let Some(saved_cipher) = existing_ciphers.iter_mut().find(|c| &c.uuid == cipher_data.id.as_ref().unwrap())
@@ -855,6 +1121,11 @@ async fn post_rotatekey(data: Json<KeyData>, headers: Headers, mut conn: DbConn,
     // Update user data
     let mut user = headers.user;
 
+    let new_password_hash = &data.account_unlock_data.master_password_unlock_data.master_key_authentication_hash;
+    if user.force_password_reset && !user.check_valid_password(new_password_hash) {
+        complete_forced_password_reset(&mut user, headers.device.atype, &headers.ip, &mut conn).await;
+    }
+
     user.private_key = Some(data.account_keys.user_key_encrypted_account_private_key);
     user.set_password(
         &data.account_unlock_data.master_password_unlock_data.master_key_authentication_hash,
@@ -992,6 +1263,8 @@ async fn post_email(data: Json<ChangeEmailData>, headers: Headers, mut conn: DbC
     user.email = data.new_email;
     user.email_new = None;
     user.email_new_token = None;
+    // The new address wasn't reported by the mail provider
+    user.clear_email_undeliverable();
 
     user.set_password(&data.new_master_password_hash, Some(data.key), true, None);
 
@@ -1002,6 +1275,100 @@ async fn post_email(data: Json<ChangeEmailData>, headers: Headers, mut conn: DbC
     save_result
 }
 
+#[get("/accounts/aliases")]
+async fn get_aliases(headers: Headers, mut conn: DbConn) -> Json<Value> {
+    let aliases = UserAlias::find_by_user(&headers.user.uuid, &mut conn).await;
+    Json(json!({
+        "data": aliases.iter().map(UserAlias::to_json).collect::<Vec<Value>>(),
+        "object": "list",
+        "continuationToken": null,
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AliasData {
+    master_password_hash: String,
+    email: String,
+}
+
+// Sends a code to the address, the alias only resolves to the account once verified with it
+#[post("/accounts/aliases", data = "<data>")]
+async fn post_alias(data: Json<AliasData>, headers: Headers, mut conn: DbConn) -> JsonResult {
+    let data: AliasData = data.into_inner();
+    let user = headers.user;
+
+    if !user.check_valid_password(&data.master_password_hash) {
+        err!("Invalid password")
+    }
+
+    if !CONFIG.mail_enabled() {
+        err!("Aliases can't be verified without mail, an administrator has to add them")
+    }
+
+    if !CONFIG.is_email_domain_allowed(&data.email) {
+        err!("Email domain not allowed");
+    }
+
+    UserAlias::check_available(&data.email, &mut conn).await?;
+
+    let alias = UserAlias::new_unverified(&data.email, user.uuid.clone());
+    alias.save(&mut conn).await?;
+    mail::send_verify_alias(&alias.email, &user.email, alias.verification_token.as_deref().unwrap_or_default()).await?;
+
+    Ok(Json(alias.to_json()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyAliasData {
+    email: String,
+    token: NumberOrString,
+}
+
+#[post("/accounts/aliases/verify", data = "<data>")]
+async fn post_alias_verify(data: Json<VerifyAliasData>, headers: Headers, mut conn: DbConn) -> JsonResult {
+    let data: VerifyAliasData = data.into_inner();
+
+    let Some(mut alias) = UserAlias::find_by_mail_and_user(&data.email, &headers.user.uuid, &mut conn).await else {
+        err!("Alias not found")
+    };
+    let Some(token) = alias.verification_token.as_deref() else {
+        err!("The alias is already verified")
+    };
+    if alias.is_verification_expired() {
+        alias.delete(&mut conn).await?;
+        err!("The verification code has expired, add the alias again")
+    }
+    if !crypto::ct_eq(token, data.token.into_string()) {
+        alias.verification_attempts += 1;
+        if alias.verification_attempts as u64 >= CONFIG.email_attempts_limit() {
+            alias.delete(&mut conn).await?;
+            err!("Too many attempts, add the alias again")
+        }
+        alias.save(&mut conn).await?;
+        err!("Token mismatch")
+    }
+
+    // The address might have been registered or verified by another account since the code was sent
+    if User::find_by_mail(&alias.email, &mut conn).await.is_some() {
+        err!("Email already in use")
+    }
+
+    alias.verification_token = None;
+    alias.save(&mut conn).await?;
+
+    Ok(Json(alias.to_json()))
+}
+
+#[delete("/accounts/aliases/<email>")]
+async fn delete_alias(email: &str, headers: Headers, mut conn: DbConn) -> EmptyResult {
+    let Some(alias) = UserAlias::find_by_mail_and_user(email, &headers.user.uuid, &mut conn).await else {
+        err!("Alias not found")
+    };
+    alias.delete(&mut conn).await
+}
+
 #[post("/accounts/verify-email")]
 async fn post_verify_email(headers: Headers) -> EmptyResult {
     let user = headers.user;
@@ -1082,7 +1449,11 @@ struct DeleteRecoverTokenData {
 }
 
 #[post("/accounts/delete-recover-token", data = "<data>")]
-async fn post_delete_recover_token(data: Json<DeleteRecoverTokenData>, mut conn: DbConn) -> EmptyResult {
+async fn post_delete_recover_token(
+    data: Json<DeleteRecoverTokenData>,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
     let data: DeleteRecoverTokenData = data.into_inner();
 
     let Ok(claims) = decode_delete(&data.token) else {
@@ -1096,22 +1467,54 @@ async fn post_delete_recover_token(data: Json<DeleteRecoverTokenData>, mut conn:
     if claims.sub != *user.uuid {
         err!("Invalid claim");
     }
-    user.delete(&mut conn).await
+    delete_own_account(user, &nt, &mut conn).await
 }
 
 #[post("/accounts/delete", data = "<data>")]
-async fn post_delete_account(data: Json<PasswordOrOtpData>, headers: Headers, conn: DbConn) -> EmptyResult {
-    delete_account(data, headers, conn).await
+async fn post_delete_account(
+    data: Json<PasswordOrOtpData>,
+    headers: Headers,
+    conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
+    delete_account(data, headers, conn, nt).await
 }
 
 #[delete("/accounts", data = "<data>")]
-async fn delete_account(data: Json<PasswordOrOtpData>, headers: Headers, mut conn: DbConn) -> EmptyResult {
+async fn delete_account(
+    data: Json<PasswordOrOtpData>,
+    headers: Headers,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
     let data: PasswordOrOtpData = data.into_inner();
     let user = headers.user;
 
     data.validate(&user, true, &mut conn).await?;
 
-    user.delete(&mut conn).await
+    delete_own_account(user, &nt, &mut conn).await
+}
+
+/// With a deletion grace period the account is only marked as deleted, logging in before it is purged restores it.
+async fn delete_own_account(mut user: User, nt: &Notify<'_>, conn: &mut DbConn) -> EmptyResult {
+    if CONFIG.user_deletion_grace_days() <= 0 {
+        return user.delete(conn).await;
+    }
+    if user.is_soft_deleted() {
+        err!("This account is already scheduled for deletion")
+    }
+
+    user.soft_delete(true, conn).await?;
+    nt.send_logout(&user, None, conn).await;
+
+    if CONFIG.mail_enabled() {
+        if let Some(purge_date) = user.purge_date() {
+            if let Err(e) = mail::send_delete_account_scheduled(&user.email, &purge_date).await {
+                error!("Error sending the account deletion email: {e:#?}");
+            }
+        }
+    }
+    Ok(())
 }
 
 #[get("/accounts/revision-date")]
@@ -1155,7 +1558,11 @@ async fn password_hint(data: Json<PasswordHintData>, mut conn: DbConn) -> EmptyR
             }
         }
         Some(user) => {
-            let hint: Option<String> = user.password_hint;
+            // Hints disabled by a policy are removed, this only covers the time until they are
+            let hint: Option<String> = match OrgPolicy::is_password_hint_disabled(&user.uuid, &mut conn).await {
+                true => None,
+                false => user.password_hint,
+            };
             if CONFIG.mail_enabled() {
                 mail::send_password_hint(email, hint).await?;
                 Ok(())
@@ -1325,6 +1732,68 @@ async fn get_device(device_id: DeviceId, headers: Headers, mut conn: DbConn) ->
     Ok(Json(device.to_json()))
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeviceNameData {
+    name: String,
+}
+
+#[put("/devices/<device_id>", data = "<data>")]
+async fn put_device_name(
+    device_id: DeviceId,
+    data: Json<DeviceNameData>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data = data.into_inner();
+    let name = data.name.trim();
+    if name.is_empty() || name.len() > 255 {
+        err!("The device name has to be between 1 and 255 characters")
+    }
+
+    let Some(mut device) = Device::find_by_uuid_and_user(&device_id, &headers.user.uuid, &mut conn).await else {
+        err!("No device found");
+    };
+    device.name = name.to_string();
+    device.save_name(&mut conn).await?;
+
+    Ok(Json(device.to_json()))
+}
+
+#[post("/devices/<device_id>", data = "<data>")]
+async fn post_device_name(
+    device_id: DeviceId,
+    data: Json<DeviceNameData>,
+    headers: Headers,
+    conn: DbConn,
+) -> JsonResult {
+    put_device_name(device_id, data, headers, conn).await
+}
+
+// Logs out only this device, its refresh token stops working and it has to log in again
+#[post("/devices/<device_id>/deactivate")]
+async fn post_deactivate_device(device_id: DeviceId, headers: Headers, mut conn: DbConn) -> EmptyResult {
+    let Some(device) = Device::find_by_uuid_and_user(&device_id, &headers.user.uuid, &mut conn).await else {
+        err!("No device found");
+    };
+    revoke_device(device, &mut conn).await
+}
+
+#[delete("/devices/<device_id>")]
+async fn delete_device(device_id: DeviceId, headers: Headers, conn: DbConn) -> EmptyResult {
+    post_deactivate_device(device_id, headers, conn).await
+}
+
+/// Removes a single device of the user, unlike a deauth the sessions of the other devices are kept.
+pub async fn revoke_device(device: Device, conn: &mut DbConn) -> EmptyResult {
+    if CONFIG.push_enabled() && device.is_push_device() {
+        if let Err(e) = unregister_push_device(&device.push_uuid).await {
+            error!("Unable to unregister device {} from Bitwarden server: {e}", device.uuid);
+        }
+    }
+    device.delete(conn).await
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct PushToken {
@@ -1520,6 +1989,10 @@ async fn put_auth_request(
         err!("An authentication request with the same device already exists")
     }
 
+    if auth_request.is_expired() {
+        err!("This request has expired")
+    }
+
     let response_date = Utc::now().naive_utc();
     let response_date_utc = format_date(&response_date);
 
@@ -1543,8 +2016,15 @@ async fn put_auth_request(
         )
         .await;
     } else {
-        // If denied, there's no reason to keep the request
-        auth_request.delete(&mut conn).await?;
+        // Keep the denied request until it expires, so the requesting client learns about the denial
+        auth_request.approved = Some(false);
+        auth_request.response_device_id = Some(data.device_identifier.clone());
+        auth_request.response_date = Some(response_date);
+        auth_request.save(&mut conn).await?;
+
+        ant.send_auth_response(&auth_request.user_uuid, &auth_request.uuid).await;
+        nt.send_auth_response(&auth_request.user_uuid, &auth_request.uuid, &headers.device, &mut conn).await;
+
         log_user_event(
             EventType::OrganizationUserRejectedAuthRequest as i32,
             &headers.user.uuid,
@@ -1612,7 +2092,7 @@ async fn get_auth_requests(headers: Headers, mut conn: DbConn) -> JsonResult {
     Ok(Json(json!({
         "data": auth_requests
             .iter()
-            .filter(|request| request.approved.is_none())
+            .filter(|request| request.approved.is_none() && !request.is_expired())
             .map(|request| {
             let response_date_utc = request.response_date.map(|response_date| format_date(&response_date));
 
@@ -1635,6 +2115,44 @@ async fn get_auth_requests(headers: Headers, mut conn: DbConn) -> JsonResult {
     })))
 }
 
+// Newer clients list the requests waiting for an answer with this endpoint, the most recent one per requesting device
+#[get("/auth-requests/pending")]
+async fn get_pending_auth_requests(headers: Headers, mut conn: DbConn) -> JsonResult {
+    let mut auth_requests: Vec<AuthRequest> = AuthRequest::find_by_user(&headers.user.uuid, &mut conn)
+        .await
+        .into_iter()
+        .filter(|request| request.approved.is_none() && !request.is_expired())
+        .collect();
+    auth_requests.sort_by(|a, b| b.creation_date.cmp(&a.creation_date));
+
+    let mut seen_devices = HashSet::new();
+    auth_requests.retain(|request| seen_devices.insert(request.request_device_identifier.clone()));
+
+    Ok(Json(json!({
+        "data": auth_requests
+            .iter()
+            .map(|request| {
+                json!({
+                    "id": request.uuid,
+                    "publicKey": request.public_key,
+                    "requestDeviceId": request.request_device_identifier,
+                    "requestDeviceType": DeviceType::from_i32(request.device_type).to_string(),
+                    "requestIpAddress": request.request_ip,
+                    "key": null,
+                    "masterPasswordHash": null,
+                    "creationDate": format_date(&request.creation_date),
+                    "responseDate": null,
+                    "requestApproved": null,
+                    "origin": CONFIG.domain_origin(),
+                    "object": "auth-request"
+                })
+            })
+            .collect::<Vec<Value>>(),
+        "continuationToken": null,
+        "object": "list"
+    })))
+}
+
 pub async fn purge_auth_requests(pool: DbPool) {
     debug!("Purging auth requests");
     if let Ok(mut conn) = pool.get().await {
@@ -1643,3 +2161,36 @@ pub async fn purge_auth_requests(pool: DbPool) {
         error!("Failed to get DB connection while purging trashed ciphers")
     }
 }
+
+pub async fn purge_deleted_users(pool: DbPool) {
+    debug!("Purging soft-deleted users");
+    let Ok(mut conn) = pool.get().await else {
+        error!("Failed to get DB connection while purging deleted users");
+        return;
+    };
+
+    let grace_period = TimeDelta::try_days(CONFIG.user_deletion_grace_days()).unwrap_or_default();
+    let purge_before = Utc::now().naive_utc() - grace_period;
+    for user in User::find_soft_deleted_before(&purge_before, &mut conn).await {
+        let user_id = user.uuid.clone();
+        // Get the membership records before deleting the actual user
+        let memberships = Membership::find_any_state_by_user(&user_id, &mut conn).await;
+        if let Err(e) = user.delete(&mut conn).await {
+            error!("Error purging deleted user {user_id}: {e:#?}");
+            continue;
+        }
+
+        for membership in memberships {
+            log_event(
+                EventType::OrganizationUserDeleted as i32,
+                &membership.uuid,
+                &membership.org_uuid,
+                &ACTING_ADMIN_USER.into(),
+                14, // Use UnknownBrowser type
+                &std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+                &mut conn,
+            )
+            .await;
+        }
+    }
+}
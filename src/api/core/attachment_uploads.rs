@@ -0,0 +1,409 @@
+//
+// Resumable attachment uploads, for large files over connections which may break halfway.
+// The attachment is created with the v2 API as usual, then its data is sent in chunks with a `Content-Range`
+// header, and verified against the SHA-256 of the encrypted file once all of it was received.
+//
+use std::{collections::HashSet, sync::Mutex};
+
+use chrono::{TimeDelta, Utc};
+use data_encoding::HEXLOWER;
+use once_cell::sync::Lazy;
+use ring::digest;
+use rocket::{
+    data::{Data, ToByteUnit},
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    serde::json::Json,
+    Route,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{
+    ciphers::{attachment_size_limit, quarantine_attachment},
+    log_event,
+};
+use crate::{
+    api::{ApiResult, EmptyResult, JsonResult, Notify, UpdateType},
+    auth::Headers,
+    config::PathType,
+    crypto,
+    db::{models::*, DbConn, DbPool},
+    util::{get_display_size, save_local_file},
+    CONFIG,
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        post_attachment_upload,
+        get_attachment_upload,
+        patch_attachment_upload,
+        post_attachment_upload_complete,
+        delete_attachment_upload,
+    ]
+}
+
+// The uploads receiving a chunk right now, a second connection for the same upload would interleave the data
+static UPLOADS_IN_PROGRESS: Lazy<Mutex<HashSet<AttachmentId>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+struct UploadLock(AttachmentId);
+
+impl UploadLock {
+    fn acquire(attachment_id: &AttachmentId) -> ApiResult<Self> {
+        if !UPLOADS_IN_PROGRESS.lock().unwrap().insert(attachment_id.clone()) {
+            err!("This upload is already receiving data on another connection")
+        }
+        Ok(Self(attachment_id.clone()))
+    }
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        UPLOADS_IN_PROGRESS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// The `Content-Range: bytes <start>-<end>/<size>` header of a chunk, `end` is inclusive.
+struct ChunkRange {
+    start: i64,
+    end: i64,
+    size: i64,
+}
+
+impl ChunkRange {
+    fn parse(header: &str) -> Option<Self> {
+        let (range, size) = header.trim().strip_prefix("bytes ")?.split_once('/')?;
+        let (start, end) = range.split_once('-')?;
+        let range = Self {
+            start: start.trim().parse().ok()?,
+            end: end.trim().parse().ok()?,
+            size: size.trim().parse().ok()?,
+        };
+        (0 <= range.start && range.start <= range.end && range.end < range.size).then_some(range)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ChunkRange {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one("Content-Range").map(ChunkRange::parse) {
+            Some(Some(range)) => Outcome::Success(range),
+            Some(None) => Outcome::Error((Status::BadRequest, "Invalid Content-Range header")),
+            None => Outcome::Error((Status::BadRequest, "Missing Content-Range header")),
+        }
+    }
+}
+
+async fn get_writable_attachment(
+    cipher_id: &CipherId,
+    attachment_id: &AttachmentId,
+    headers: &Headers,
+    conn: &mut DbConn,
+) -> ApiResult<(Cipher, Attachment)> {
+    let Some(cipher) = Cipher::find_by_uuid(cipher_id, conn).await else {
+        err!("Cipher doesn't exist")
+    };
+    if !cipher.is_write_accessible_to_user(&headers.user.uuid, conn).await {
+        err!("Cipher is not write accessible")
+    }
+
+    match Attachment::find_by_id(attachment_id, conn).await {
+        Some(attachment) if attachment.cipher_uuid == cipher.uuid => Ok((cipher, attachment)),
+        Some(_) => err!("Attachment doesn't belong to cipher"),
+        None => err!("Attachment doesn't exist"),
+    }
+}
+
+async fn get_upload(attachment: &Attachment, headers: &Headers, conn: &mut DbConn) -> ApiResult<AttachmentUpload> {
+    match AttachmentUpload::find_by_attachment(&attachment.id, conn).await {
+        Some(upload) if upload.user_uuid == headers.user.uuid => Ok(upload),
+        Some(_) => err!("The upload was started by another user"),
+        None => err!("There is no upload in progress for this attachment"),
+    }
+}
+
+async fn check_size_limit(
+    cipher: &Cipher,
+    attachment: &Attachment,
+    headers: &Headers,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    // The attachment was created with its size already, so it is part of the used storage
+    if let Some(size_limit) = attachment_size_limit(cipher, headers, attachment.file_size, conn).await? {
+        if attachment.file_size > size_limit {
+            err!(format!(
+                "Attachment storage limit exceeded with this file, only {} of storage left",
+                get_display_size(size_limit)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Starts the resumable upload of an attachment created with `/ciphers/<cipher_id>/attachment/v2`.
+/// Starting it again returns the upload in progress, with the offset to continue from.
+#[post("/ciphers/<cipher_id>/attachment/<attachment_id>/upload")]
+async fn post_attachment_upload(
+    cipher_id: CipherId,
+    attachment_id: AttachmentId,
+    headers: Headers,
+    mut conn: DbConn,
+) -> JsonResult {
+    let (cipher, attachment) = get_writable_attachment(&cipher_id, &attachment_id, &headers, &mut conn).await?;
+    if AttachmentUpload::find_by_attachment(&attachment.id, &mut conn).await.is_some() {
+        let upload = get_upload(&attachment, &headers, &mut conn).await?;
+        return Ok(Json(upload.to_json().await));
+    }
+
+    let operator = CONFIG.opendal_operator_for_path_type(PathType::Attachments)?;
+    if operator.exists(&attachment.get_file_path()).await? {
+        err!("The attachment already has its data")
+    }
+    check_size_limit(&cipher, &attachment, &headers, &mut conn).await?;
+
+    let mut upload = AttachmentUpload::new(attachment.id, cipher.uuid, headers.user.uuid.clone(), attachment.file_size);
+    tokio::fs::create_dir_all(AttachmentUpload::temp_folder()).await?;
+    tokio::fs::File::create(upload.temp_path()).await?;
+    upload.save(&mut conn).await?;
+
+    Ok(Json(upload.to_json().await))
+}
+
+#[get("/ciphers/<cipher_id>/attachment/<attachment_id>/upload")]
+async fn get_attachment_upload(
+    cipher_id: CipherId,
+    attachment_id: AttachmentId,
+    headers: Headers,
+    mut conn: DbConn,
+) -> JsonResult {
+    let (_, attachment) = get_writable_attachment(&cipher_id, &attachment_id, &headers, &mut conn).await?;
+    let upload = get_upload(&attachment, &headers, &mut conn).await?;
+    Ok(Json(upload.to_json().await))
+}
+
+/// Appends a chunk to the upload. A chunk which was already received in full is ignored and one which was
+/// partly received is appended from the current offset, so a client can send again what it isn't sure about.
+/// Returns the upload with the new offset. When the connection breaks the request fails, but the part of the
+/// chunk which was received is kept, the client gets the offset to continue from with a GET of the upload.
+#[patch("/ciphers/<cipher_id>/attachment/<attachment_id>/upload", data = "<data>")]
+async fn patch_attachment_upload(
+    cipher_id: CipherId,
+    attachment_id: AttachmentId,
+    range: ChunkRange,
+    data: Data<'_>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> JsonResult {
+    let (_, attachment) = get_writable_attachment(&cipher_id, &attachment_id, &headers, &mut conn).await?;
+    let mut upload = get_upload(&attachment, &headers, &mut conn).await?;
+
+    if range.size != upload.file_size {
+        err!(format!("The size of the upload is {}, not {}", upload.file_size, range.size))
+    }
+    let chunk_size = range.end - range.start + 1;
+    let max_chunk_size = CONFIG.resumable_upload_chunk_size_mb() as i64 * 1024 * 1024;
+    if chunk_size > max_chunk_size {
+        err!(format!("Chunks can't be larger than {}", get_display_size(max_chunk_size)))
+    }
+
+    let _lock = UploadLock::acquire(&upload.attachment_id)?;
+    let offset = upload.offset().await;
+    if range.end < offset {
+        return Ok(Json(upload.to_json().await));
+    }
+    if range.start > offset {
+        err!(format!("The upload continues at offset {offset}, the chunk starts at {}", range.start))
+    }
+
+    let mut stream = data.open((chunk_size as u64).bytes());
+    // The start of the chunk was received before the connection broke
+    let received = (offset - range.start) as u64;
+    tokio::io::copy(&mut (&mut stream).take(received), &mut tokio::io::sink()).await?;
+
+    let mut file = tokio::fs::OpenOptions::new().append(true).open(upload.temp_path()).await?;
+    let copied = tokio::io::copy(&mut stream, &mut file).await;
+    file.flush().await?;
+    upload.save(&mut conn).await?;
+    copied?;
+
+    Ok(Json(upload.to_json().await))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadCompleteData {
+    // Hex encoded SHA-256 of the encrypted file
+    sha256: String,
+}
+
+/// Verifies the received file and stores it as the data of the attachment.
+#[post("/ciphers/<cipher_id>/attachment/<attachment_id>/upload/complete", data = "<data>")]
+async fn post_attachment_upload_complete(
+    cipher_id: CipherId,
+    attachment_id: AttachmentId,
+    data: Json<UploadCompleteData>,
+    headers: Headers,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
+    let (cipher, attachment) = get_writable_attachment(&cipher_id, &attachment_id, &headers, &mut conn).await?;
+    let upload = get_upload(&attachment, &headers, &mut conn).await?;
+
+    let _lock = UploadLock::acquire(&upload.attachment_id)?;
+    let offset = upload.offset().await;
+    if offset != upload.file_size {
+        err!(format!("The upload is incomplete, {offset} of {} bytes were received", upload.file_size))
+    }
+
+    let Ok(expected) = HEXLOWER.decode(data.into_inner().sha256.to_lowercase().as_bytes()) else {
+        err!("Invalid SHA-256 checksum")
+    };
+    let mut file = tokio::fs::File::open(upload.temp_path()).await?;
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buf[..read]);
+    }
+    if !crypto::ct_eq(context.finish(), expected) {
+        // There is no telling which chunk is corrupt, so the upload has to start over
+        upload.delete(&mut conn).await?;
+        err!(
+            "The received file doesn't match its checksum, start the upload again",
+            format!("Checksum mismatch of the upload of attachment {}", attachment.id)
+        )
+    }
+
+    if let Some(signature) = crate::virus_scan::scan_path(&upload.temp_path()).await? {
+        attachment.delete(&mut conn).await?;
+        quarantine_attachment(&cipher, upload.file_size, signature, &headers, &mut conn).await;
+        err!("The file was rejected by the virus scanner")
+    }
+
+    // Other uploads may have finished in the meantime
+    check_size_limit(&cipher, &attachment, &headers, &mut conn).await?;
+
+    save_local_file(PathType::Attachments, &attachment.get_file_path(), &upload.temp_path(), false).await?;
+    upload.delete(&mut conn).await?;
+
+    nt.send_cipher_update(
+        UpdateType::SyncCipherUpdate,
+        &cipher,
+        &cipher.update_users_revision(&mut conn).await,
+        &headers.device,
+        None,
+        &mut conn,
+    )
+    .await;
+
+    if let Some(org_id) = &cipher.organization_uuid {
+        log_event(
+            EventType::CipherAttachmentCreated as i32,
+            &cipher.uuid,
+            org_id,
+            &headers.user.uuid,
+            headers.device.atype,
+            &headers.ip.ip,
+            &mut conn,
+        )
+        .await;
+    }
+
+    Ok(())
+}
+
+/// Cancels the upload, the attachment is removed as well since it doesn't have any data.
+#[delete("/ciphers/<cipher_id>/attachment/<attachment_id>/upload")]
+async fn delete_attachment_upload(
+    cipher_id: CipherId,
+    attachment_id: AttachmentId,
+    headers: Headers,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let (_, attachment) = get_writable_attachment(&cipher_id, &attachment_id, &headers, &mut conn).await?;
+    get_upload(&attachment, &headers, &mut conn).await?;
+
+    let _lock = UploadLock::acquire(&attachment.id)?;
+    attachment.delete(&mut conn).await
+}
+
+/// Removes the uploads which didn't receive a chunk for `RESUMABLE_UPLOAD_EXPIRATION_HOURS`, with their attachment,
+/// and the chunks in the tmp folder which don't belong to an upload anymore.
+pub async fn purge_attachment_uploads(pool: DbPool) {
+    debug!("Purging stale attachment uploads");
+    let Ok(mut conn) = pool.get().await else {
+        error!("Failed to get DB connection while purging attachment uploads");
+        return;
+    };
+
+    let expiration = TimeDelta::try_hours(CONFIG.resumable_upload_expiration_hours()).unwrap_or_default();
+    let expiry_time = Utc::now().naive_utc() - expiration;
+    for upload in AttachmentUpload::find_updated_before(&expiry_time, &mut conn).await {
+        // Receiving a chunk right now after all
+        let Ok(_lock) = UploadLock::acquire(&upload.attachment_id) else {
+            continue;
+        };
+        let res = match Attachment::find_by_id(&upload.attachment_id, &mut conn).await {
+            Some(attachment) => attachment.delete(&mut conn).await,
+            None => upload.delete(&mut conn).await,
+        };
+        if let Err(e) = res {
+            error!("Failed to purge the upload of attachment {}: {e:?}", upload.attachment_id);
+        }
+    }
+
+    // Chunks of uploads which were removed while the file couldn't be, or of a restored database
+    let Ok(mut entries) = tokio::fs::read_dir(AttachmentUpload::temp_folder()).await else {
+        return;
+    };
+    let max_age = expiration.to_std().unwrap_or_default();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let is_stale = entry
+            .metadata()
+            .await
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().is_ok_and(|elapsed| elapsed > max_age));
+        let attachment_id = AttachmentId(entry.file_name().to_string_lossy().into_owned());
+        if is_stale && !AttachmentUpload::exists(&attachment_id, &mut conn).await {
+            if let Err(e) = tokio::fs::remove_file(entry.path()).await {
+                error!("Failed to remove the stale upload chunks {:?}: {e:?}", entry.path());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_range_parses_valid_headers() {
+        let range = ChunkRange::parse("bytes 0-1023/4096").unwrap();
+        assert_eq!((range.start, range.end, range.size), (0, 1023, 4096));
+
+        let range = ChunkRange::parse(" bytes 4095-4095/4096 ").unwrap();
+        assert_eq!((range.start, range.end, range.size), (4095, 4095, 4096));
+    }
+
+    #[test]
+    fn chunk_range_rejects_malformed_headers() {
+        let headers =
+            ["", "bytes", "0-1023/4096", "bytes=0-1023/4096", "bytes 0-1023", "bytes 0/4096", "bytes a-1/4096"];
+        for header in headers {
+            assert!(ChunkRange::parse(header).is_none(), "{header}");
+        }
+        // The unknown size of `*` isn't supported, uploads always know their size
+        assert!(ChunkRange::parse("bytes 0-1023/*").is_none());
+    }
+
+    #[test]
+    fn chunk_range_rejects_out_of_range_headers() {
+        for header in ["bytes 1024-1023/4096", "bytes 4096-4096/4096", "bytes 0-4096/4096", "bytes 0-0/0"] {
+            assert!(ChunkRange::parse(header).is_none(), "{header}");
+        }
+    }
+}
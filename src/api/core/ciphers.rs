@@ -6,14 +6,18 @@ use rocket::fs::TempFile;
 use rocket::serde::json::Json;
 use rocket::{
     form::{Form, FromForm},
-    Route,
+    Route, State,
 };
 use serde_json::Value;
 
 use crate::auth::ClientVersion;
-use crate::util::{save_temp_file, NumberOrString};
+use crate::util::{get_display_size, save_temp_file, NumberOrString};
 use crate::{
-    api::{self, core::log_event, EmptyResult, JsonResult, Notify, PasswordOrOtpData, UpdateType},
+    api::{
+        self,
+        core::{check_org_ip_restrictions, log_event},
+        ApiResult, EmptyResult, JsonResult, Notify, PasswordOrOtpData, UpdateType,
+    },
     auth::Headers,
     config::PathType,
     crypto,
@@ -22,6 +26,7 @@ use crate::{
 };
 
 use super::folders::FolderData;
+use super::imports::{import_ciphers, run_import_job, start_import_job};
 
 pub fn routes() -> Vec<Route> {
     // Note that many routes have an `admin` variant; this seems to be
@@ -79,9 +84,13 @@ pub fn routes() -> Vec<Route> {
         restore_cipher_put_admin,
         restore_cipher_selected,
         restore_cipher_selected_admin,
+        get_cipher_history,
+        restore_cipher_history,
         delete_all,
         move_cipher_selected,
         move_cipher_selected_put,
+        favorite_cipher_selected,
+        favorite_cipher_selected_put,
         put_collections2_update,
         post_collections2_update,
         put_collections_update,
@@ -108,10 +117,7 @@ struct SyncData {
 
 #[get("/sync?<data..>")]
 async fn sync(data: SyncData, headers: Headers, client_version: Option<ClientVersion>, mut conn: DbConn) -> JsonResult {
-    let user_json = headers.user.to_json(&mut conn).await;
-
-    // Get all ciphers which are visible by the user
-    let mut ciphers = Cipher::find_by_user_visible(&headers.user.uuid, &mut conn).await;
+    check_org_ip_restrictions(&headers.user.uuid, headers.device.atype, &headers.ip.ip, &mut conn).await?;
 
     // Filter out SSH keys if the client version is less than 2024.12.0
     let show_ssh_keys = if let Some(client_version) = client_version {
@@ -120,52 +126,84 @@ async fn sync(data: SyncData, headers: Headers, client_version: Option<ClientVer
     } else {
         false
     };
+
+    let mut sync_json = sync_vault_json(&headers.user, &headers.host, show_ssh_keys, &mut conn).await?;
+    sync_json["domains"] = if data.exclude_domains {
+        Value::Null
+    } else {
+        api::core::_get_eq_domains(headers, true).into_inner()
+    };
+
+    Ok(Json(sync_json))
+}
+
+/// The vault of the user as returned by a sync, without the equivalent domains.
+pub async fn sync_vault_json(user: &User, host: &str, show_ssh_keys: bool, conn: &mut DbConn) -> ApiResult<Value> {
+    let user_json = user.to_json(conn).await;
+
+    // Get all ciphers which are visible by the user
+    let mut ciphers = Cipher::find_by_user_visible(&user.uuid, conn).await;
     if !show_ssh_keys {
         ciphers.retain(|c| c.atype != 5);
     }
 
-    let cipher_sync_data = CipherSyncData::new(&headers.user.uuid, CipherSyncType::User, &mut conn).await;
+    let cipher_sync_data = CipherSyncData::new(&user.uuid, CipherSyncType::User, conn).await;
 
     // Lets generate the ciphers_json using all the gathered info
     let mut ciphers_json = Vec::with_capacity(ciphers.len());
     for c in ciphers {
-        ciphers_json.push(
-            c.to_json(&headers.host, &headers.user.uuid, Some(&cipher_sync_data), CipherSyncType::User, &mut conn)
-                .await?,
-        );
+        ciphers_json.push(c.to_json(host, &user.uuid, Some(&cipher_sync_data), CipherSyncType::User, conn).await?);
     }
 
-    let collections = Collection::find_by_user_uuid(headers.user.uuid.clone(), &mut conn).await;
+    let collections = Collection::find_by_user_uuid(user.uuid.clone(), conn).await;
     let mut collections_json = Vec::with_capacity(collections.len());
     for c in collections {
-        collections_json.push(c.to_json_details(&headers.user.uuid, Some(&cipher_sync_data), &mut conn).await);
+        collections_json.push(c.to_json_details(&user.uuid, Some(&cipher_sync_data), conn).await);
     }
 
-    let folders_json: Vec<Value> =
-        Folder::find_by_user(&headers.user.uuid, &mut conn).await.iter().map(Folder::to_json).collect();
+    let folders_json: Vec<Value> = Folder::find_by_user(&user.uuid, conn).await.iter().map(Folder::to_json).collect();
 
-    let sends_json: Vec<Value> =
-        Send::find_by_user(&headers.user.uuid, &mut conn).await.iter().map(Send::to_json).collect();
+    let sends_json: Vec<Value> = Send::find_by_user(&user.uuid, conn).await.iter().map(Send::to_json).collect();
 
-    let policies_json: Vec<Value> =
-        OrgPolicy::find_confirmed_by_user(&headers.user.uuid, &mut conn).await.iter().map(OrgPolicy::to_json).collect();
+    // Vaultwarden specific, the items other users shared directly with this user
+    let mut direct_shares_json = Vec::new();
+    if CONFIG.direct_sharing_allowed() {
+        for share in CipherShare::find_by_grantee(&user.uuid, conn).await {
+            direct_shares_json.push(share.to_json_received(conn).await);
+        }
+    }
 
-    let domains_json = if data.exclude_domains {
-        Value::Null
-    } else {
-        api::core::_get_eq_domains(headers, true).into_inner()
+    // The session lifetime policies are sent as vault timeout policies, which the clients know
+    let policies_json: Vec<Value> = OrgPolicy::find_confirmed_by_user(&user.uuid, conn)
+        .await
+        .iter()
+        .map(|policy| policy.to_vault_timeout_json().unwrap_or_else(|| policy.to_json()))
+        .collect();
+
+    // Vaultwarden specific, the generator options of all the organizations merged, the clients only use the policies
+    let generator_policy_json = match OrgPolicy::find_password_generator_policy(&user.uuid, conn).await {
+        Some(generator_policy) => serde_json::to_value(generator_policy)?,
+        None => Value::Null,
+    };
+    // Vaultwarden specific, the session limits of all the organizations merged
+    let session_lifetime_policy_json = match OrgPolicy::find_session_lifetime_policy(&user.uuid, conn).await {
+        Some(session_policy) => serde_json::to_value(session_policy)?,
+        None => Value::Null,
     };
 
-    Ok(Json(json!({
+    Ok(json!({
         "profile": user_json,
         "folders": folders_json,
         "collections": collections_json,
         "policies": policies_json,
+        "passwordGeneratorPolicy": generator_policy_json,
+        "sessionLifetimePolicy": session_lifetime_policy_json,
         "ciphers": ciphers_json,
-        "domains": domains_json,
+        "domains": Value::Null,
         "sends": sends_json,
+        "directShares": direct_shares_json,
         "object": "sync"
-    })))
+    }))
 }
 
 #[get("/ciphers")]
@@ -342,8 +380,9 @@ async fn post_ciphers(data: Json<CipherData>, headers: Headers, mut conn: DbConn
 /// Enforces the personal ownership policy on user-owned ciphers, if applicable.
 /// A non-owner/admin user belonging to an org with the personal ownership policy
 /// enabled isn't allowed to create new user-owned ciphers or modify existing ones
-/// (that were created before the policy was applicable to the user). The user is
-/// allowed to delete or share such ciphers to an org, however.
+/// (that were created before the policy was applicable to the user), adding attachments
+/// or restoring revisions included. The user is allowed to delete or share such ciphers
+/// to an org however, or to move them into the collection of the policy, see `personal_ownership`.
 ///
 /// Ref: https://bitwarden.com/help/article/policies/#personal-ownership
 async fn enforce_personal_ownership_policy(
@@ -402,6 +441,9 @@ pub async fn update_cipher_from_data(
     let transfer_cipher = cipher.organization_uuid.is_none() && data.organization_id.is_some();
 
     if let Some(org_id) = data.organization_id {
+        if Organization::find_by_uuid(&org_id, conn).await.is_some_and(|org| org.is_archived()) {
+            err!("The organization is archived, its items can't be changed")
+        }
         match Membership::find_by_user_and_org(&headers.user.uuid, &org_id, conn).await {
             None => err!("You don't have permission to add item to organization"),
             Some(member) => {
@@ -416,6 +458,8 @@ pub async fn update_cipher_from_data(
                     // even when the user has hide-passwords configured as there policy.
                     // Removing the line below would fix that, but we have to check which effect this would have on the rest of the code.
                     cipher.user_uuid = None;
+                    // Direct shares are only possible for personal items, the organization manages the access from now on
+                    CipherShare::delete_all_by_cipher(&cipher.uuid, conn).await?;
                 } else {
                     err!("You don't have permission to add cipher directly to organization")
                 }
@@ -496,10 +540,19 @@ pub async fn update_cipher_from_data(
         None => err!("Data missing"),
     };
 
+    // Keep the current revision, so an accidental overwrite can be undone. The revisions of a personal item
+    // are encrypted with the user key, they are useless once it was moved to an organization.
+    if transfer_cipher {
+        CipherHistory::delete_all_by_cipher(&cipher.uuid, conn).await?;
+    } else if ut == UpdateType::SyncCipherUpdate {
+        CipherHistory::record(cipher, &headers.user.uuid, conn).await?;
+    }
+
     cipher.key = data.key;
     cipher.name = data.name;
     cipher.notes = data.notes;
     cipher.fields = data.fields.map(|f| _clean_cipher_data(f).to_string());
+    cipher.track_password_change(&type_data);
     cipher.data = type_data.to_string();
     cipher.password_history = data.password_history.map(|f| f.to_string());
     cipher.reprompt = data.reprompt.filter(|r| *r == RepromptType::None as i32 || *r == RepromptType::Password as i32);
@@ -563,8 +616,9 @@ async fn post_ciphers_import(
     data: Json<ImportData>,
     headers: Headers,
     mut conn: DbConn,
+    pool: &State<DbPool>,
     nt: Notify<'_>,
-) -> EmptyResult {
+) -> JsonResult {
     enforce_personal_ownership_policy(None, &headers, &mut conn).await?;
 
     let data: ImportData = data.into_inner();
@@ -598,20 +652,35 @@ async fn post_ciphers_import(
         relations_map.insert(relation.key, relation.value);
     }
 
-    // Read and create the ciphers
-    for (index, mut cipher_data) in data.ciphers.into_iter().enumerate() {
-        let folder_id = relations_map.get(&index).map(|i| folders[*i].clone());
-        cipher_data.folder_id = folder_id;
-
-        let mut cipher = Cipher::new(cipher_data.r#type, cipher_data.name.clone());
-        update_cipher_from_data(&mut cipher, cipher_data, &headers, None, &mut conn, &nt, UpdateType::None).await?;
+    let mut ciphers = data.ciphers;
+    for (index, cipher_data) in ciphers.iter_mut().enumerate() {
+        cipher_data.folder_id = relations_map.get(&index).map(|i| folders[*i].clone());
     }
+    // The ciphers are created in chunks with their own connections
+    drop(conn);
+
+    // Read and create the ciphers, large imports continue in the background
+    let total = ciphers.len();
+    let job_id = start_import_job(&headers.user.uuid, None, total);
+    let pool = pool.inner().clone();
+    let ws_users = nt.inner().clone();
+    let import_job_id = job_id.clone();
+    run_import_job(&job_id, total, async move {
+        import_ciphers(&import_job_id, ciphers, &headers, None, &pool, &ws_users).await?;
+
+        let mut conn = pool.get().await?;
+        let mut user = headers.user;
+        user.update_revision(&mut conn).await?;
+        let nt: Notify<'_> = (&ws_users).into();
+        nt.send_user_update(UpdateType::SyncVault, &user, &headers.device.push_uuid, &mut conn).await;
+        Ok(())
+    })
+    .await?;
 
-    let mut user = headers.user;
-    user.update_revision(&mut conn).await?;
-    nt.send_user_update(UpdateType::SyncVault, &user, &headers.device.push_uuid, &mut conn).await;
-
-    Ok(())
+    Ok(Json(json!({
+        "id": job_id,
+        "object": "importJob",
+    })))
 }
 
 /// Called when an org admin modifies an existing org cipher.
@@ -1061,7 +1130,22 @@ async fn get_attachment(
     }
 
     match Attachment::find_by_id(&attachment_id, &mut conn).await {
-        Some(attachment) if cipher_id == attachment.cipher_uuid => Ok(Json(attachment.to_json(&headers.host).await?)),
+        Some(attachment) if cipher_id == attachment.cipher_uuid => {
+            // The clients request the download url right before downloading, so this is logged as the download
+            if let Some(ref org_id) = cipher.organization_uuid {
+                log_event(
+                    EventType::CipherAttachmentDownloaded as i32,
+                    &cipher.uuid,
+                    org_id,
+                    &headers.user.uuid,
+                    headers.device.atype,
+                    &headers.ip.ip,
+                    &mut conn,
+                )
+                .await;
+            }
+            Ok(Json(attachment.to_json(&headers.host, cipher.deleted_at.as_ref()).await?))
+        }
         Some(_) => err!("Attachment doesn't belong to cipher"),
         None => err!("Attachment doesn't exist"),
     }
@@ -1099,6 +1183,9 @@ async fn post_attachment_v2(
     if !cipher.is_write_accessible_to_user(&headers.user.uuid, &mut conn).await {
         err!("Cipher is not write accessible")
     }
+    if cipher.organization_uuid.is_none() {
+        enforce_personal_ownership_policy(None, &headers, &mut conn).await?;
+    }
 
     let data: AttachmentRequestData = data.into_inner();
     let file_size = data.file_size.into_i64()?;
@@ -1126,6 +1213,69 @@ async fn post_attachment_v2(
     })))
 }
 
+/// The number of bytes which can still be stored as attachments of the cipher, None when there is no limit.
+/// `size_adjust` is the size of an attachment which was already created but didn't receive its data yet.
+pub async fn attachment_size_limit(
+    cipher: &Cipher,
+    headers: &Headers,
+    size_adjust: i64,
+    conn: &mut DbConn,
+) -> ApiResult<Option<i64>> {
+    let size_limit = if let Some(ref user_id) = cipher.user_uuid {
+        // Personal ciphers are only write accessible to their owner
+        match headers.user.effective_attachment_limit() {
+            Some(0) => err!("Attachments are disabled"),
+            Some(limit_kb) => {
+                let already_used = Attachment::size_by_user(user_id, conn).await;
+                let left = limit_kb
+                    .checked_mul(1024)
+                    .and_then(|l| l.checked_sub(already_used))
+                    .and_then(|l| l.checked_add(size_adjust));
+
+                let Some(left) = left else {
+                    err!("Attachment size overflow");
+                };
+
+                if left <= 0 {
+                    err!("Attachment storage limit reached! Delete some attachments to free up space")
+                }
+
+                Some(left)
+            }
+            None => None,
+        }
+    } else if let Some(ref org_id) = cipher.organization_uuid {
+        let Some(org) = Organization::find_by_uuid(org_id, conn).await else {
+            err!("Organization doesn't exist")
+        };
+        match org.effective_attachment_limit() {
+            Some(0) => err!("Attachments are disabled"),
+            Some(limit_kb) => {
+                let already_used = Attachment::size_by_org(org_id, conn).await;
+                let left = limit_kb
+                    .checked_mul(1024)
+                    .and_then(|l| l.checked_sub(already_used))
+                    .and_then(|l| l.checked_add(size_adjust));
+
+                let Some(left) = left else {
+                    err!("Attachment size overflow");
+                };
+
+                if left <= 0 {
+                    err!("Attachment storage limit reached! Delete some attachments to free up space")
+                }
+
+                Some(left)
+            }
+            None => None,
+        }
+    } else {
+        err!("Cipher is neither owned by a user nor an organization");
+    };
+
+    Ok(size_limit)
+}
+
 #[derive(FromForm)]
 struct UploadData<'f> {
     key: Option<String>,
@@ -1164,6 +1314,9 @@ async fn save_attachment(
     if !cipher.is_write_accessible_to_user(&headers.user.uuid, &mut conn).await {
         err!("Cipher is not write accessible")
     }
+    if cipher.organization_uuid.is_none() {
+        enforce_personal_ownership_policy(None, headers, &mut conn).await?;
+    }
 
     // In the v2 API, the attachment record has already been created,
     // so the size limit needs to be adjusted to account for that.
@@ -1171,59 +1324,23 @@ async fn save_attachment(
         None => 0,              // Legacy API
         Some(a) => a.file_size, // v2 API
     };
-
-    let size_limit = if let Some(ref user_id) = cipher.user_uuid {
-        match CONFIG.user_attachment_limit() {
-            Some(0) => err!("Attachments are disabled"),
-            Some(limit_kb) => {
-                let already_used = Attachment::size_by_user(user_id, &mut conn).await;
-                let left = limit_kb
-                    .checked_mul(1024)
-                    .and_then(|l| l.checked_sub(already_used))
-                    .and_then(|l| l.checked_add(size_adjust));
-
-                let Some(left) = left else {
-                    err!("Attachment size overflow");
-                };
-
-                if left <= 0 {
-                    err!("Attachment storage limit reached! Delete some attachments to free up space")
-                }
-
-                Some(left)
-            }
-            None => None,
-        }
-    } else if let Some(ref org_id) = cipher.organization_uuid {
-        match CONFIG.org_attachment_limit() {
-            Some(0) => err!("Attachments are disabled"),
-            Some(limit_kb) => {
-                let already_used = Attachment::size_by_org(org_id, &mut conn).await;
-                let left = limit_kb
-                    .checked_mul(1024)
-                    .and_then(|l| l.checked_sub(already_used))
-                    .and_then(|l| l.checked_add(size_adjust));
-
-                let Some(left) = left else {
-                    err!("Attachment size overflow");
-                };
-
-                if left <= 0 {
-                    err!("Attachment storage limit reached! Delete some attachments to free up space")
-                }
-
-                Some(left)
-            }
-            None => None,
-        }
-    } else {
-        err!("Cipher is neither owned by a user nor an organization");
-    };
+    let size_limit = attachment_size_limit(&cipher, headers, size_adjust, &mut conn).await?;
 
     if let Some(size_limit) = size_limit {
         if size > size_limit {
-            err!("Attachment storage limit exceeded with this file");
+            err!(format!(
+                "Attachment storage limit exceeded with this file, only {} of storage left",
+                get_display_size(size_limit)
+            ));
+        }
+    }
+
+    if let Some(signature) = crate::virus_scan::scan_temp_file(&data.data).await? {
+        if let Some(attachment) = &attachment {
+            attachment.delete(&mut conn).await?;
         }
+        quarantine_attachment(&cipher, size, signature, headers, &mut conn).await;
+        err!("The file was rejected by the virus scanner")
     }
 
     let file_id = match &attachment {
@@ -1303,6 +1420,35 @@ async fn save_attachment(
     Ok((cipher, conn))
 }
 
+pub async fn quarantine_attachment(
+    cipher: &Cipher,
+    size: i64,
+    signature: String,
+    headers: &Headers,
+    conn: &mut DbConn,
+) {
+    warn!("Attachment upload for cipher {} rejected by the virus scanner: {signature}", cipher.uuid);
+    let mut quarantined =
+        QuarantinedFile::new(headers.user.uuid.clone(), cipher.organization_uuid.clone(), size, signature);
+    quarantined.cipher_uuid = Some(cipher.uuid.clone());
+    if let Err(e) = quarantined.save(conn).await {
+        error!("Failed to save the quarantined attachment of cipher {}: {e:?}", cipher.uuid);
+    }
+
+    if let Some(org_id) = &cipher.organization_uuid {
+        log_event(
+            EventType::CipherAttachmentQuarantined as i32,
+            &cipher.uuid,
+            org_id,
+            &headers.user.uuid,
+            headers.device.atype,
+            &headers.ip.ip,
+            conn,
+        )
+        .await;
+    }
+}
+
 /// v2 API for uploading the actual data content of an attachment.
 /// This route needs a rank specified so that Rocket prioritizes the
 /// /ciphers/<cipher_id>/attachment/v2 route, which would otherwise conflict
@@ -1560,6 +1706,89 @@ async fn restore_cipher_selected(
     _restore_multiple_ciphers(data, &headers, &mut conn, &nt).await
 }
 
+// The previous revisions of the item, the newest first
+#[get("/ciphers/<cipher_id>/history")]
+async fn get_cipher_history(cipher_id: CipherId, headers: Headers, mut conn: DbConn) -> JsonResult {
+    let Some(cipher) = Cipher::find_by_uuid(&cipher_id, &mut conn).await else {
+        err!("Cipher doesn't exist")
+    };
+
+    // The revisions contain the passwords, which can be hidden from users who can't edit the item
+    if !cipher.is_write_accessible_to_user(&headers.user.uuid, &mut conn).await {
+        err!("Cipher is not write accessible")
+    }
+
+    let history = CipherHistory::find_by_cipher(&cipher.uuid, &mut conn).await;
+    Ok(Json(json!({
+        "data": history.iter().map(CipherHistory::to_json).collect::<Vec<Value>>(),
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+#[post("/ciphers/<cipher_id>/history/<history_id>/restore")]
+async fn restore_cipher_history(
+    cipher_id: CipherId,
+    history_id: CipherHistoryId,
+    headers: Headers,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> JsonResult {
+    let Some(mut cipher) = Cipher::find_by_uuid(&cipher_id, &mut conn).await else {
+        err!("Cipher doesn't exist")
+    };
+
+    if !cipher.is_write_accessible_to_user(&headers.user.uuid, &mut conn).await {
+        err!("Cipher is not write accessible")
+    }
+    if cipher.organization_uuid.is_none() {
+        enforce_personal_ownership_policy(None, &headers, &mut conn).await?;
+    }
+
+    let Some(revision) = CipherHistory::find_by_uuid_and_cipher(&history_id, &cipher.uuid, &mut conn).await else {
+        err!("Revision doesn't exist")
+    };
+
+    // The current content becomes a revision as well, so restoring can be undone too
+    CipherHistory::record(&cipher, &headers.user.uuid, &mut conn).await?;
+    revision.restore_to(&mut cipher);
+    cipher.save(&mut conn).await?;
+
+    nt.send_cipher_update(
+        UpdateType::SyncCipherUpdate,
+        &cipher,
+        &cipher.update_users_revision(&mut conn).await,
+        &headers.device,
+        None,
+        &mut conn,
+    )
+    .await;
+
+    if let Some(org_id) = &cipher.organization_uuid {
+        log_event(
+            EventType::CipherUpdated as i32,
+            &cipher.uuid,
+            org_id,
+            &headers.user.uuid,
+            headers.device.atype,
+            &headers.ip.ip,
+            &mut conn,
+        )
+        .await;
+    }
+
+    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, None, CipherSyncType::User, &mut conn).await?))
+}
+
+pub async fn purge_cipher_history(pool: DbPool) {
+    debug!("Purging expired item revisions");
+    if let Ok(mut conn) = pool.get().await {
+        CipherHistory::purge_expired(&mut conn).await;
+    } else {
+        error!("Failed to get DB connection while purging item revisions")
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct MoveCipherData {
@@ -1584,19 +1813,21 @@ async fn move_cipher_selected(
     }
 
     let cipher_count = data.ids.len();
-    let mut single_cipher: Option<Cipher> = None;
 
-    // TODO: Convert this to use a single query (or at least less) to update all items
     // Find all ciphers a user has access to, all others will be ignored
     let accessible_ciphers = Cipher::find_by_user_and_ciphers(user_id, &data.ids, &mut conn).await;
     let accessible_ciphers_count = accessible_ciphers.len();
-    for cipher in accessible_ciphers {
-        cipher.move_to_folder(data.folder_id.clone(), user_id, &mut conn).await?;
-        if cipher_count == 1 {
-            single_cipher = Some(cipher);
-        }
-    }
+    let cipher_ids: Vec<CipherId> = accessible_ciphers.iter().map(|c| c.uuid.clone()).collect();
+
+    // All ciphers are moved at once, with a single revision update of the user
+    FolderCipher::move_ciphers(data.folder_id.as_ref(), &cipher_ids, user_id, &mut conn).await?;
+    User::update_uuid_revision(user_id, &mut conn).await;
 
+    let single_cipher = if cipher_count == 1 {
+        accessible_ciphers.into_iter().next()
+    } else {
+        None
+    };
     if let Some(cipher) = single_cipher {
         nt.send_cipher_update(
             UpdateType::SyncCipherUpdate,
@@ -1631,6 +1862,55 @@ async fn move_cipher_selected_put(
     move_cipher_selected(data, headers, conn, nt).await
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FavoriteCipherData {
+    favorite: bool,
+    ids: Vec<CipherId>,
+}
+
+// Vaultwarden specific, like `/ciphers/move` for the favorite status of the selected ciphers
+#[post("/ciphers/favorite", data = "<data>")]
+async fn favorite_cipher_selected(
+    data: Json<FavoriteCipherData>,
+    headers: Headers,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
+    let data = data.into_inner();
+    let user_id = &headers.user.uuid;
+
+    let cipher_count = data.ids.len();
+
+    // Find all ciphers a user has access to, all others will be ignored
+    let accessible_ciphers = Cipher::find_by_user_and_ciphers(user_id, &data.ids, &mut conn).await;
+    let accessible_ciphers_count = accessible_ciphers.len();
+    let cipher_ids: Vec<CipherId> = accessible_ciphers.iter().map(|c| c.uuid.clone()).collect();
+
+    Favorite::set_favorite_bulk(data.favorite, &cipher_ids, user_id, &mut conn).await?;
+    User::update_uuid_revision(user_id, &mut conn).await;
+
+    nt.send_user_update(UpdateType::SyncCiphers, &headers.user, &headers.device.push_uuid, &mut conn).await;
+
+    if cipher_count != accessible_ciphers_count {
+        err!(format!(
+            "Not all ciphers are updated! {accessible_ciphers_count} of the selected {cipher_count} were updated."
+        ))
+    }
+
+    Ok(())
+}
+
+#[put("/ciphers/favorite", data = "<data>")]
+async fn favorite_cipher_selected_put(
+    data: Json<FavoriteCipherData>,
+    headers: Headers,
+    conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
+    favorite_cipher_selected(data, headers, conn, nt).await
+}
+
 #[derive(FromForm)]
 struct OrganizationIdData {
     #[field(name = "organizationId")]
@@ -1717,7 +1997,7 @@ async fn _delete_cipher_by_uuid(
         err!("Cipher doesn't exist")
     };
 
-    if !cipher.is_write_accessible_to_user(&headers.user.uuid, conn).await {
+    if !cipher.is_manageable_by_user(&headers.user.uuid, conn).await {
         err!("Cipher can't be deleted by user")
     }
 
@@ -1804,7 +2084,7 @@ async fn _restore_cipher_by_uuid(
         err!("Cipher doesn't exist")
     };
 
-    if !cipher.is_write_accessible_to_user(&headers.user.uuid, conn).await {
+    if !cipher.is_manageable_by_user(&headers.user.uuid, conn).await {
         err!("Cipher can't be restored by user")
     }
 
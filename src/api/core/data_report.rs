@@ -0,0 +1,165 @@
+use rocket::{http::Header, serde::json::Json, Route};
+use serde_json::Value;
+
+use crate::{
+    api::ApiResult,
+    auth::Headers,
+    db::{models::*, DbConn},
+    mail,
+    util::format_date,
+    CONFIG,
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![get_data_report]
+}
+
+#[derive(rocket::Responder)]
+struct DataReportResponse {
+    report: Json<Value>,
+    disposition: Header<'static>,
+}
+
+/// Returns a summary of all the personal data stored about the user, as a downloadable JSON file.
+/// With `email=true` a copy of the report is also mailed to the user.
+/// Vault items are end-to-end encrypted and only counted here, the clients export function covers their contents.
+#[get("/accounts/data-report?<email>")]
+async fn get_data_report(email: Option<bool>, headers: Headers, mut conn: DbConn) -> ApiResult<DataReportResponse> {
+    let send_copy = email.unwrap_or(false);
+    if send_copy && !CONFIG.mail_enabled() {
+        err!("Mail is not enabled on this server")
+    }
+
+    let report = generate_data_report(&headers.user, &mut conn).await;
+
+    if send_copy {
+        let content = serde_json::to_vec_pretty(&report)?;
+        mail::send_data_report(&headers.user.email, content).await?;
+    }
+
+    Ok(DataReportResponse {
+        report: Json(report),
+        disposition: Header::new("Content-Disposition", "attachment; filename=\"vaultwarden_data_report.json\""),
+    })
+}
+
+async fn generate_data_report(user: &User, conn: &mut DbConn) -> Value {
+    let profile = json!({
+        "id": user.uuid,
+        "name": user.name,
+        "email": user.email,
+        "pendingEmail": user.email_new,
+        "emailVerifiedDate": user.verified_at.as_ref().map(format_date),
+        "passwordHint": user.password_hint,
+        "avatarColor": user.avatar_color,
+        "kdf": user.client_kdf_type,
+        "kdfIterations": user.client_kdf_iter,
+        "kdfMemory": user.client_kdf_memory,
+        "kdfParallelism": user.client_kdf_parallelism,
+        "enabled": user.enabled,
+        "creationDate": format_date(&user.created_at),
+        "revisionDate": format_date(&user.updated_at),
+        "deletionDate": user.deleted_at.as_ref().map(format_date),
+    });
+
+    let devices: Vec<Value> = Device::find_by_user(&user.uuid, conn)
+        .await
+        .iter()
+        .map(|d| {
+            json!({
+                "id": d.uuid,
+                "name": d.name,
+                "type": d.atype,
+                "creationDate": format_date(&d.created_at),
+                "revisionDate": format_date(&d.updated_at),
+                "pushRegistered": d.push_token.is_some(),
+            })
+        })
+        .collect();
+
+    let two_factor: Vec<Value> =
+        TwoFactor::find_by_user(&user.uuid, conn).await.iter().map(TwoFactor::to_json_provider).collect();
+
+    let mut memberships = Vec::new();
+    for member in Membership::find_by_user(&user.uuid, conn).await {
+        let org_name = Organization::find_by_uuid(&member.org_uuid, conn).await.map(|o| o.name);
+        memberships.push(json!({
+            "organizationId": member.org_uuid,
+            "organizationName": org_name,
+            "type": member.atype,
+            "status": member.status,
+            "externalId": member.external_id,
+        }));
+    }
+
+    let mut emergency_access = Vec::new();
+    for ea in EmergencyAccess::find_all_by_grantor_uuid(&user.uuid, conn).await {
+        emergency_access.push(json!({
+            "role": "grantor",
+            "email": ea.email,
+            "type": ea.atype,
+            "status": ea.status,
+            "waitTimeDays": ea.wait_time_days,
+        }));
+    }
+    for ea in EmergencyAccess::find_all_by_grantee_uuid(&user.uuid, conn).await {
+        let grantor_email = User::find_by_uuid(&ea.grantor_uuid, conn).await.map(|u| u.email);
+        emergency_access.push(json!({
+            "role": "grantee",
+            "email": grantor_email,
+            "type": ea.atype,
+            "status": ea.status,
+            "waitTimeDays": ea.wait_time_days,
+        }));
+    }
+
+    // Sends don't keep a log of every access, only the number of times they were accessed
+    let sends: Vec<Value> = Send::find_by_user(&user.uuid, conn)
+        .await
+        .iter()
+        .map(|s| {
+            json!({
+                "id": s.uuid,
+                "type": s.atype,
+                "accessCount": s.access_count,
+                "maxAccessCount": s.max_access_count,
+                "revisionDate": format_date(&s.revision_date),
+                "expirationDate": s.expiration_date.as_ref().map(format_date),
+                "deletionDate": format_date(&s.deletion_date),
+            })
+        })
+        .collect();
+
+    let events: Vec<Value> =
+        Event::find_all_involving_user(&user.uuid, conn).await.iter().map(Event::to_json).collect();
+    let logins: Vec<Value> =
+        LoginEvent::find_all_by_user(&user.uuid, conn).await.iter().map(LoginEvent::to_json).collect();
+
+    let reports = match Report::find_by_user_personal(&user.uuid, conn).await {
+        Some(report) => vec![json!({
+            "exposedCount": report.exposed_count,
+            "creationDate": format_date(&report.created_at),
+            "revisionDate": format_date(&report.last_updated_at),
+        })],
+        None => Vec::new(),
+    };
+
+    json!({
+        "generatedDate": format_date(&chrono::Utc::now().naive_utc()),
+        "server": CONFIG.domain(),
+        "profile": profile,
+        "devices": devices,
+        "twoFactorProviders": two_factor,
+        "organizations": memberships,
+        "emergencyAccess": emergency_access,
+        "vault": {
+            "ciphers": Cipher::count_owned_by_user(&user.uuid, conn).await,
+            "folders": Folder::find_by_user(&user.uuid, conn).await.len(),
+        },
+        "sends": sends,
+        "events": events,
+        "logins": logins,
+        "reports": reports,
+        "object": "dataReport",
+    })
+}
@@ -0,0 +1,270 @@
+use std::collections::HashSet;
+
+use rocket::{serde::json::Json, Route};
+use serde_json::Value;
+
+use crate::{
+    api::{ApiResult, EmptyResult, JsonResult, Notify, UpdateType},
+    auth::Headers,
+    db::{models::*, DbConn},
+    CONFIG,
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        get_sent_direct_shares,
+        get_received_direct_shares,
+        get_direct_shares_by_cipher,
+        post_direct_share_recipient,
+        post_direct_share,
+        put_direct_share,
+        delete_direct_share,
+        post_delete_direct_share,
+    ]
+}
+
+fn check_direct_sharing_enabled() -> EmptyResult {
+    if !CONFIG.direct_sharing_allowed() {
+        err!("Direct sharing is not enabled.")
+    }
+    Ok(())
+}
+
+// Only the owner of a personal item is able to share it directly
+async fn get_owned_cipher(cipher_id: &CipherId, headers: &Headers, conn: &mut DbConn) -> ApiResult<Cipher> {
+    let Some(cipher) = Cipher::find_by_uuid(cipher_id, conn).await else {
+        err!("Cipher doesn't exist")
+    };
+    if cipher.organization_uuid.is_some() || cipher.user_uuid.as_ref() != Some(&headers.user.uuid) {
+        err!("Only personal items can be shared directly")
+    }
+    Ok(cipher)
+}
+
+#[get("/direct-shares/sent")]
+async fn get_sent_direct_shares(headers: Headers, mut conn: DbConn) -> Json<Value> {
+    let shares = CipherShare::find_by_owner(&headers.user.uuid, &mut conn).await;
+    let mut shares_json = Vec::with_capacity(shares.len());
+    for share in shares {
+        shares_json.push(share.to_json_sent(&mut conn).await);
+    }
+
+    Json(json!({
+        "data": shares_json,
+        "object": "list",
+        "continuationToken": null
+    }))
+}
+
+#[get("/direct-shares/received")]
+async fn get_received_direct_shares(headers: Headers, mut conn: DbConn) -> Json<Value> {
+    let shares = if CONFIG.direct_sharing_allowed() {
+        CipherShare::find_by_grantee(&headers.user.uuid, &mut conn).await
+    } else {
+        Vec::new()
+    };
+    let mut shares_json = Vec::with_capacity(shares.len());
+    for share in shares {
+        shares_json.push(share.to_json_received(&mut conn).await);
+    }
+
+    Json(json!({
+        "data": shares_json,
+        "object": "list",
+        "continuationToken": null
+    }))
+}
+
+#[get("/ciphers/<cipher_id>/direct-shares")]
+async fn get_direct_shares_by_cipher(cipher_id: CipherId, headers: Headers, mut conn: DbConn) -> JsonResult {
+    let cipher = get_owned_cipher(&cipher_id, &headers, &mut conn).await?;
+
+    let shares = CipherShare::find_by_cipher(&cipher.uuid, &mut conn).await;
+    let mut shares_json = Vec::with_capacity(shares.len());
+    for share in shares {
+        shares_json.push(share.to_json_sent(&mut conn).await);
+    }
+
+    Ok(Json(json!({
+        "data": shares_json,
+        "object": "list",
+        "continuationToken": null
+    })))
+}
+
+/// Any user of the server can be a recipient when `DIRECT_SHARING_ANY_USER` is enabled. Otherwise only users who are
+/// in an organization with the sender, or who already share items with each other, can be recipients. Any other
+/// address then gets the same answer as an unknown one, so registered emails can't be looked up.
+async fn find_recipient(email: &str, headers: &Headers, conn: &mut DbConn) -> ApiResult<User> {
+    let user_uuid = &headers.user.uuid;
+    let Some(grantee) = User::find_by_mail(&email.to_lowercase(), conn).await else {
+        err!("User doesn't exist")
+    };
+    if &grantee.uuid == user_uuid {
+        err!("You can not share an item with yourself")
+    }
+    if CONFIG.direct_sharing_any_user() {
+        return Ok(grantee);
+    }
+
+    let shares_with = |shares: Vec<CipherShare>, grantee_uuid: &UserId| {
+        shares.iter().any(|share| &share.grantee_uuid == grantee_uuid)
+    };
+    if shares_with(CipherShare::find_by_owner(user_uuid, conn).await, &grantee.uuid)
+        || shares_with(CipherShare::find_by_owner(&grantee.uuid, conn).await, user_uuid)
+    {
+        return Ok(grantee);
+    }
+
+    let org_ids: HashSet<OrganizationId> =
+        Membership::find_confirmed_by_user(user_uuid, conn).await.into_iter().map(|member| member.org_uuid).collect();
+    let grantee_memberships = Membership::find_confirmed_by_user(&grantee.uuid, conn).await;
+    if grantee_memberships.iter().any(|member| org_ids.contains(&member.org_uuid)) {
+        return Ok(grantee);
+    }
+    err!("User doesn't exist", format!("{} doesn't share an organization or items with {}", grantee.email, user_uuid))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DirectShareRecipientData {
+    email: String,
+}
+
+/// Returns the public key of the recipient, needed by the client to encrypt the key of the shared copy.
+#[post("/direct-shares/recipient", data = "<data>")]
+async fn post_direct_share_recipient(
+    data: Json<DirectShareRecipientData>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> JsonResult {
+    check_direct_sharing_enabled()?;
+
+    let grantee = find_recipient(&data.into_inner().email, &headers, &mut conn).await?;
+    let Some(public_key) = grantee.public_key else {
+        err!("This user is not able to receive shared items yet")
+    };
+
+    Ok(Json(json!({
+        "userId": grantee.uuid,
+        "publicKey": public_key,
+        "object": "directShareRecipient",
+    })))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DirectShareData {
+    email: String,
+    // The key of the copy, encrypted with the public key of the recipient
+    key: String,
+    // The copy of the cipher, encrypted with the key above
+    data: String,
+}
+
+#[post("/ciphers/<cipher_id>/direct-shares", data = "<data>")]
+async fn post_direct_share(
+    cipher_id: CipherId,
+    data: Json<DirectShareData>,
+    headers: Headers,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> JsonResult {
+    check_direct_sharing_enabled()?;
+
+    let data = data.into_inner();
+    let cipher = get_owned_cipher(&cipher_id, &headers, &mut conn).await?;
+
+    let grantee = find_recipient(&data.email, &headers, &mut conn).await?;
+    if grantee.public_key.is_none() {
+        err!("This user is not able to receive shared items yet")
+    }
+
+    // Sharing the same item again with a recipient replaces the previous copy
+    let mut share = match CipherShare::find_by_cipher_and_grantee(&cipher.uuid, &grantee.uuid, &mut conn).await {
+        Some(mut share) => {
+            share.akey = data.key;
+            share.data = data.data;
+            share
+        }
+        None => CipherShare::new(cipher.uuid, headers.user.uuid.clone(), grantee.uuid.clone(), data.key, data.data),
+    };
+    share.save(&mut conn).await?;
+
+    nt.send_user_update(UpdateType::SyncVault, &grantee, &None, &mut conn).await;
+
+    Ok(Json(share.to_json_sent(&mut conn).await))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DirectShareUpdateData {
+    key: String,
+    data: String,
+}
+
+/// Used by the owner to update the copy after the original item was changed.
+#[put("/direct-shares/<share_id>", data = "<data>")]
+async fn put_direct_share(
+    share_id: CipherShareId,
+    data: Json<DirectShareUpdateData>,
+    headers: Headers,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> JsonResult {
+    check_direct_sharing_enabled()?;
+
+    let data = data.into_inner();
+    let Some(mut share) = CipherShare::find_by_uuid(&share_id, &mut conn).await else {
+        err!("Share doesn't exist")
+    };
+    if share.owner_uuid != headers.user.uuid {
+        err!("Share doesn't exist")
+    }
+
+    share.akey = data.key;
+    share.data = data.data;
+    share.save(&mut conn).await?;
+
+    if let Some(grantee) = User::find_by_uuid(&share.grantee_uuid, &mut conn).await {
+        nt.send_user_update(UpdateType::SyncVault, &grantee, &None, &mut conn).await;
+    }
+
+    Ok(Json(share.to_json_sent(&mut conn).await))
+}
+
+/// The owner revokes the share, or the recipient removes the shared item.
+#[delete("/direct-shares/<share_id>")]
+async fn delete_direct_share(
+    share_id: CipherShareId,
+    headers: Headers,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
+    let Some(share) = CipherShare::find_by_uuid(&share_id, &mut conn).await else {
+        err!("Share doesn't exist")
+    };
+    if share.owner_uuid != headers.user.uuid && share.grantee_uuid != headers.user.uuid {
+        err!("Share doesn't exist")
+    }
+
+    let grantee_uuid = share.grantee_uuid.clone();
+    share.delete(&mut conn).await?;
+
+    if grantee_uuid != headers.user.uuid {
+        if let Some(grantee) = User::find_by_uuid(&grantee_uuid, &mut conn).await {
+            nt.send_user_update(UpdateType::SyncVault, &grantee, &None, &mut conn).await;
+        }
+    }
+    Ok(())
+}
+
+#[post("/direct-shares/<share_id>/delete")]
+async fn post_delete_direct_share(
+    share_id: CipherShareId,
+    headers: Headers,
+    conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
+    delete_direct_share(share_id, headers, conn, nt).await
+}
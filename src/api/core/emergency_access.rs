@@ -219,11 +219,6 @@ async fn send_invite(data: Json<EmergencyAccessInviteData>, headers: Headers, mu
 
     let grantor_user = headers.user;
 
-    // avoid setting yourself as emergency contact
-    if email == grantor_user.email {
-        err!("You can not set yourself as an emergency contact.")
-    }
-
     let (grantee_user, new_user) = match User::find_by_mail(&email, &mut conn).await {
         None => {
             if !CONFIG.invitations_allowed() {
@@ -247,6 +242,11 @@ async fn send_invite(data: Json<EmergencyAccessInviteData>, headers: Headers, mu
         Some(user) => (user, false),
     };
 
+    // avoid setting yourself as emergency contact, also through one of your aliases
+    if grantee_user.uuid == grantor_user.uuid {
+        err!("You can not set yourself as an emergency contact.")
+    }
+
     if EmergencyAccess::find_by_grantor_uuid_and_grantee_uuid_or_email(
         &grantor_user.uuid,
         &grantee_user.uuid,
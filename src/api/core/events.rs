@@ -1,14 +1,15 @@
 use std::net::IpAddr;
 
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, TimeDelta, Utc};
 use rocket::{form::FromForm, serde::json::Json, Route};
 use serde_json::Value;
 
 use crate::{
-    api::{EmptyResult, JsonResult},
-    auth::{AdminHeaders, Headers},
+    api::{admin::ACTING_ADMIN_USER, EmptyResult, JsonResult},
+    auth::{AccessEventLogsHeaders, Headers},
+    config::PathType,
     db::{
-        models::{Cipher, CipherId, Event, Membership, MembershipId, OrganizationId, UserId},
+        models::{Cipher, CipherId, Event, Membership, MembershipId, Organization, OrganizationId, UserId},
         DbConn, DbPool,
     },
     util::parse_date,
@@ -18,7 +19,7 @@ use crate::{
 /// ###############################################################################################################
 /// /api routes
 pub fn routes() -> Vec<Route> {
-    routes![get_org_events, get_cipher_events, get_user_events,]
+    routes![get_org_events, get_cipher_events, get_org_cipher_events, get_user_events,]
 }
 
 #[derive(FromForm)]
@@ -34,7 +35,7 @@ struct EventRange {
 async fn get_org_events(
     org_id: OrganizationId,
     data: EventRange,
-    headers: AdminHeaders,
+    headers: AccessEventLogsHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -99,12 +100,50 @@ async fn get_cipher_events(cipher_id: CipherId, data: EventRange, headers: Heade
     })))
 }
 
+// Vaultwarden specific, the events of one item of the organization, including items which have been deleted since
+#[get("/organizations/<org_id>/ciphers/<cipher_id>/events?<data..>")]
+async fn get_org_cipher_events(
+    org_id: OrganizationId,
+    cipher_id: CipherId,
+    data: EventRange,
+    headers: AccessEventLogsHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    // Return an empty vec when we org events are disabled.
+    // This prevents client errors
+    let events_json: Vec<Value> = if !CONFIG.org_events_enabled() {
+        Vec::with_capacity(0)
+    } else {
+        let start_date = parse_date(&data.start);
+        let end_date = if let Some(before_date) = &data.continuation_token {
+            parse_date(before_date)
+        } else {
+            parse_date(&data.end)
+        };
+
+        Event::find_by_org_and_cipher(&org_id, &cipher_id, &start_date, &end_date, &mut conn)
+            .await
+            .iter()
+            .map(|e| e.to_json())
+            .collect()
+    };
+
+    Ok(Json(json!({
+        "data": events_json,
+        "object": "list",
+        "continuationToken": get_continuation_token(&events_json),
+    })))
+}
+
 #[get("/organizations/<org_id>/users/<member_id>/events?<data..>")]
 async fn get_user_events(
     org_id: OrganizationId,
     member_id: MembershipId,
     data: EventRange,
-    headers: AdminHeaders,
+    headers: AccessEventLogsHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -184,6 +223,7 @@ async fn post_events_collect(data: Json<Vec<EventCollection>>, headers: Headers,
                 _log_user_event(
                     event.r#type,
                     &headers.user.uuid,
+                    &headers.user.uuid,
                     headers.device.atype,
                     Some(event_date),
                     &headers.ip.ip,
@@ -234,12 +274,30 @@ pub async fn log_user_event(event_type: i32, user_id: &UserId, device_type: i32,
     if !CONFIG.org_events_enabled() {
         return;
     }
-    _log_user_event(event_type, user_id, device_type, None, ip, conn).await;
+    _log_user_event(event_type, user_id, user_id, device_type, None, ip, conn).await;
+}
+
+/// Logs an event of a user for something the admin did to the account, with the admin as the acting user.
+pub async fn log_admin_user_event(event_type: i32, user_id: &UserId, ip: &IpAddr, conn: &mut DbConn) {
+    if !CONFIG.org_events_enabled() {
+        return;
+    }
+    _log_user_event(
+        event_type,
+        user_id,
+        &ACTING_ADMIN_USER.into(),
+        14, // Use UnknownBrowser type
+        None,
+        ip,
+        conn,
+    )
+    .await;
 }
 
 async fn _log_user_event(
     event_type: i32,
     user_id: &UserId,
+    act_user_id: &UserId,
     device_type: i32,
     event_date: Option<NaiveDateTime>,
     ip: &IpAddr,
@@ -251,7 +309,7 @@ async fn _log_user_event(
     // Upstream saves the event also without any org_id.
     let mut event = Event::new(event_type, event_date);
     event.user_uuid = Some(user_id.clone());
-    event.act_user_uuid = Some(user_id.clone());
+    event.act_user_uuid = Some(act_user_id.clone());
     event.device_type = Some(device_type);
     event.ip_address = Some(ip.to_string());
     events.push(event);
@@ -262,7 +320,7 @@ async fn _log_user_event(
         event.user_uuid = Some(user_id.clone());
         event.org_uuid = Some(membership.org_uuid);
         event.org_user_uuid = Some(membership.uuid);
-        event.act_user_uuid = Some(user_id.clone());
+        event.act_user_uuid = Some(act_user_id.clone());
         event.device_type = Some(device_type);
         event.ip_address = Some(ip.to_string());
         events.push(event);
@@ -335,14 +393,109 @@ async fn _log_event(
 
 pub async fn event_cleanup_job(pool: DbPool) {
     debug!("Start events cleanup job");
-    if CONFIG.events_days_retain().is_none() {
-        debug!("events_days_retain is not configured, abort");
+    let Ok(mut conn) = pool.get().await else {
+        error!("Failed to get DB connection while trying to cleanup the events table");
         return;
+    };
+
+    // Events without an organization follow `EVENTS_DAYS_RETAIN`, organizations can set their own retention
+    let mut retentions = vec![(None, CONFIG.events_days_retain())];
+    for org in Organization::get_all(&mut conn).await {
+        let days = org.effective_events_days_retain();
+        retentions.push((Some(org.uuid), days));
     }
 
-    if let Ok(mut conn) = pool.get().await {
-        Event::clean_events(&mut conn).await.ok();
-    } else {
-        error!("Failed to get DB connection while trying to cleanup the events table")
+    let now = Utc::now().naive_utc();
+    for (org_id, days) in retentions {
+        let Some(days) = days else {
+            continue;
+        };
+        let Some(before) = TimeDelta::try_days(days).and_then(|retain| now.checked_sub_signed(retain)) else {
+            error!("Invalid events retention of {days} days");
+            continue;
+        };
+        if let Err(e) = clean_events(org_id.as_ref(), &before, &mut conn).await {
+            error!("Failed to cleanup the events of {}: {e:?}", org_id.as_ref().map_or("users", |id| id.as_ref()));
+        }
     }
+
+    // Nothing else removes the events of deleted organizations, they are cleaned up all at once
+    for org_id in Event::find_deleted_org_uuids(&mut conn).await {
+        if let Err(e) = clean_events(Some(&org_id), &now, &mut conn).await {
+            error!("Failed to cleanup the events of deleted organization {org_id}: {e:?}");
+        }
+    }
+}
+
+// The number of events the cleanup loads, exports and deletes at once
+const EVENTS_CLEANUP_BATCH: i64 = 1000;
+
+/// Deletes the events which happened before `before`, in batches. When `EVENTS_EXPORT_FORMAT` is set, each batch is
+/// exported first and only deleted when the export succeeded.
+async fn clean_events(org_id: Option<&OrganizationId>, before: &NaiveDateTime, conn: &mut DbConn) -> EmptyResult {
+    let format = CONFIG.events_export_format();
+    let started = Utc::now().format("%Y%m%dT%H%M%S%.3fZ").to_string();
+    for part in 1.. {
+        let events = Event::find_expired(org_id, before, EVENTS_CLEANUP_BATCH, conn).await;
+        if events.is_empty() {
+            break;
+        }
+        if let Some(format) = &format {
+            export_events(org_id, &events, format, &format!("{started}-{part}")).await?;
+        }
+
+        let uuids: Vec<EventId> = events.into_iter().map(|event| event.uuid).collect();
+        let last_batch = (uuids.len() as i64) < EVENTS_CLEANUP_BATCH;
+        Event::delete_all_by_uuids(&uuids, conn).await?;
+        if last_batch {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// The columns of the CSV export, named after the keys of the event JSON
+const EVENTS_CSV_COLUMNS: [&str; 12] = [
+    "date",
+    "type",
+    "userId",
+    "organizationId",
+    "cipherId",
+    "collectionId",
+    "groupId",
+    "organizationUserId",
+    "actingUserId",
+    "policyId",
+    "deviceType",
+    "ipAddress",
+];
+
+/// Writes the events to a new file in `EVENTS_EXPORT_FOLDER`, named after the organization, the time of the cleanup and
+/// the batch. Events without an organization are written to the `users` files.
+async fn export_events(org_id: Option<&OrganizationId>, events: &[Event], format: &str, suffix: &str) -> EmptyResult {
+    let content = if format == "jsonl" {
+        events.iter().map(|event| format!("{}\n", event.to_json())).collect::<String>()
+    } else {
+        // None of the values can contain a comma or a quote, so they don't need to be escaped
+        let mut csv = EVENTS_CSV_COLUMNS.join(",");
+        csv.push('\n');
+        for event in events {
+            let json = event.to_json();
+            let fields = EVENTS_CSV_COLUMNS.map(|column| match &json[column] {
+                Value::Null => String::new(),
+                Value::String(s) => s.clone(),
+                value => value.to_string(),
+            });
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        }
+        csv
+    };
+
+    let name = org_id.map_or("users", |id| id.as_ref());
+    let path = format!("{name}-{suffix}.{format}");
+    let operator = CONFIG.opendal_operator_for_path_type(PathType::EventsExport)?;
+    operator.write(&path, content).await?;
+    info!("Exported {} expiring events to {path}", events.len());
+    Ok(())
 }
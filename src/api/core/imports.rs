@@ -0,0 +1,206 @@
+use std::{future::Future, sync::Arc};
+
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use rocket::{serde::json::Json, Route};
+
+use crate::{
+    api::{EmptyResult, JsonResult, Notify, UpdateType, WebSocketUsers},
+    auth::Headers,
+    db::{
+        models::{Cipher, CipherId, CollectionId, OrganizationId, UserId},
+        DbPool,
+    },
+    error::Error,
+    util::{format_date, get_uuid, spawn_background},
+    CONFIG,
+};
+
+use super::{ciphers::update_cipher_from_data, CipherData};
+
+pub fn routes() -> Vec<Route> {
+    routes![get_import_job]
+}
+
+/// Number of ciphers written with a single database connection before yielding back to the runtime.
+const IMPORT_CHUNK_SIZE: usize = 100;
+
+// Finished jobs are kept for a while so clients can still query their final state.
+static IMPORT_JOB_RETENTION: Lazy<TimeDelta> = Lazy::new(|| TimeDelta::try_hours(1).unwrap());
+
+static IMPORT_JOBS: Lazy<DashMap<String, ImportJob>> = Lazy::new(DashMap::new);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ImportJobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+impl ImportJobStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            ImportJobStatus::Running => "running",
+            ImportJobStatus::Completed => "completed",
+            ImportJobStatus::Failed => "failed",
+        }
+    }
+}
+
+struct ImportJob {
+    user_uuid: UserId,
+    org_uuid: Option<OrganizationId>,
+    status: ImportJobStatus,
+    total: usize,
+    processed: usize,
+    failed: usize,
+    error: Option<String>,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+/// Registers a new import job for the given user and returns its id.
+pub fn start_import_job(user_uuid: &UserId, org_uuid: Option<&OrganizationId>, total: usize) -> String {
+    let now = Utc::now().naive_utc();
+    IMPORT_JOBS.retain(|_, job| job.status == ImportJobStatus::Running || now - job.updated_at < *IMPORT_JOB_RETENTION);
+
+    let job_id = get_uuid();
+    IMPORT_JOBS.insert(
+        job_id.clone(),
+        ImportJob {
+            user_uuid: user_uuid.clone(),
+            org_uuid: org_uuid.cloned(),
+            status: ImportJobStatus::Running,
+            total,
+            processed: 0,
+            failed: 0,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        },
+    );
+    job_id
+}
+
+fn update_import_job(job_id: &str, processed: usize, failed: usize) {
+    if let Some(mut job) = IMPORT_JOBS.get_mut(job_id) {
+        job.processed = processed;
+        job.failed = failed;
+        job.updated_at = Utc::now().naive_utc();
+    }
+}
+
+fn finish_import_job(job_id: &str, result: &EmptyResult) {
+    if let Some(mut job) = IMPORT_JOBS.get_mut(job_id) {
+        match result {
+            Ok(()) => job.status = ImportJobStatus::Completed,
+            Err(e) => {
+                job.status = ImportJobStatus::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+        job.updated_at = Utc::now().naive_utc();
+    }
+}
+
+/// Runs an import and records its outcome on the job.
+/// Imports above `IMPORT_BACKGROUND_THRESHOLD` items are spawned and only report their outcome via the job,
+/// smaller ones are awaited so errors are still returned directly to the client.
+pub async fn run_import_job<F>(job_id: &str, total: usize, import: F) -> EmptyResult
+where
+    F: Future<Output = EmptyResult> + Send + 'static,
+{
+    let job_id = job_id.to_string();
+    let run = async move {
+        let result = import.await;
+        if let Err(ref e) = result {
+            error!("Import job {job_id} failed: {e:#?}");
+        }
+        finish_import_job(&job_id, &result);
+        result
+    };
+
+    if total > CONFIG.import_background_threshold() {
+        spawn_background(run);
+        Ok(())
+    } else {
+        run.await
+    }
+}
+
+/// Creates the given ciphers in chunks, each chunk using its own database connection.
+/// Personal imports abort on the first invalid item, organization imports skip it,
+/// which matches how both imports behaved before they were processed in chunks.
+/// Returns the id of each cipher, in the same order as the import data.
+pub async fn import_ciphers(
+    job_id: &str,
+    ciphers: Vec<CipherData>,
+    headers: &Headers,
+    shared_to_collections: Option<Vec<CollectionId>>,
+    pool: &DbPool,
+    ws_users: &Arc<WebSocketUsers>,
+) -> Result<Vec<CipherId>, Error> {
+    let nt: Notify<'_> = ws_users.into();
+    let skip_failed = shared_to_collections.is_some();
+
+    let mut cipher_ids = Vec::with_capacity(ciphers.len());
+    let mut failed = 0;
+    let mut ciphers = ciphers.into_iter();
+    loop {
+        let chunk: Vec<CipherData> = ciphers.by_ref().take(IMPORT_CHUNK_SIZE).collect();
+        if chunk.is_empty() {
+            break;
+        }
+
+        let mut conn = pool.get().await?;
+        for cipher_data in chunk {
+            let mut cipher = Cipher::new(cipher_data.r#type, cipher_data.name.clone());
+            let result = update_cipher_from_data(
+                &mut cipher,
+                cipher_data,
+                headers,
+                shared_to_collections.clone(),
+                &mut conn,
+                &nt,
+                UpdateType::None,
+            )
+            .await;
+            match result {
+                Ok(()) => {}
+                Err(_) if skip_failed => failed += 1,
+                Err(e) => return Err(e),
+            }
+            cipher_ids.push(cipher.uuid);
+        }
+        drop(conn);
+
+        update_import_job(job_id, cipher_ids.len(), failed);
+        tokio::task::yield_now().await;
+    }
+
+    Ok(cipher_ids)
+}
+
+#[get("/imports/<job_id>")]
+fn get_import_job(job_id: &str, headers: Headers) -> JsonResult {
+    let Some(job) = IMPORT_JOBS.get(job_id) else {
+        err!("Import job not found")
+    };
+    if job.user_uuid != headers.user.uuid {
+        err!("Import job not found", "Import job belongs to another user")
+    }
+
+    Ok(Json(json!({
+        "id": job_id,
+        "organizationId": job.org_uuid,
+        "status": job.status.as_str(),
+        "total": job.total,
+        "processed": job.processed,
+        "failed": job.failed,
+        "error": job.error,
+        "creationDate": format_date(&job.created_at),
+        "revisionDate": format_date(&job.updated_at),
+        "object": "importJob",
+    })))
+}
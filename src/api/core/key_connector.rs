@@ -0,0 +1,82 @@
+use rocket::{serde::json::Json, Route};
+
+use crate::{
+    api::{EmptyResult, JsonResult},
+    auth::Headers,
+    db::{
+        models::{KeyConnectorKey, OrgPolicy, User},
+        DbConn,
+    },
+    CONFIG,
+};
+
+// The built-in Key Connector, implementing the `user-keys` API of the Bitwarden Key Connector.
+// It is used when `SSO_KEY_CONNECTOR_URL` is left at its default, the clients then call it at `DOMAIN/api/key-connector`.
+pub fn routes() -> Vec<Route> {
+    routes![get_user_keys, post_user_keys, put_user_keys]
+}
+
+fn uses_builtin_store() -> bool {
+    CONFIG.sso_key_connector_url() == format!("{}/api/key-connector", CONFIG.domain())
+}
+
+/// Checks that the master key of the user has been stored, which the clients do before they tell the server.
+/// When an external Key Connector is used this can't be checked, and it is assumed to be done.
+pub async fn check_key_stored(user: &User, conn: &mut DbConn) -> EmptyResult {
+    if uses_builtin_store() && KeyConnectorKey::find_by_user(&user.uuid, conn).await.is_none() {
+        err!("The key has not been stored in Key Connector")
+    }
+    Ok(())
+}
+
+async fn check_enabled(user: &User, conn: &mut DbConn) -> EmptyResult {
+    if !uses_builtin_store() {
+        err!("The built-in Key Connector is not used on this server")
+    }
+    if !user.uses_key_connector && OrgPolicy::find_key_connector_org(&user.uuid, conn).await.is_none() {
+        err!("Key Connector is not enabled for this user")
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserKeyData {
+    key: String,
+}
+
+#[get("/key-connector/user-keys")]
+async fn get_user_keys(headers: Headers, mut conn: DbConn) -> JsonResult {
+    check_enabled(&headers.user, &mut conn).await?;
+
+    let Some(key) = KeyConnectorKey::find_by_user(&headers.user.uuid, &mut conn).await else {
+        err!("No key stored for this user")
+    };
+
+    Ok(Json(json!({
+        "key": key.key()?,
+    })))
+}
+
+#[post("/key-connector/user-keys", data = "<data>")]
+async fn post_user_keys(data: Json<UserKeyData>, headers: Headers, mut conn: DbConn) -> EmptyResult {
+    check_enabled(&headers.user, &mut conn).await?;
+
+    // Storing a key happens when the user starts using Key Connector, afterwards it can only be updated
+    if headers.user.uses_key_connector {
+        err!("A key is already stored for this user")
+    }
+
+    KeyConnectorKey::new(headers.user.uuid, &data.into_inner().key)?.save(&mut conn).await
+}
+
+#[put("/key-connector/user-keys", data = "<data>")]
+async fn put_user_keys(data: Json<UserKeyData>, headers: Headers, mut conn: DbConn) -> EmptyResult {
+    check_enabled(&headers.user, &mut conn).await?;
+
+    let Some(mut key) = KeyConnectorKey::find_by_user(&headers.user.uuid, &mut conn).await else {
+        err!("No key stored for this user")
+    };
+    key.set_key(&data.into_inner().key)?;
+    key.save(&mut conn).await
+}
@@ -1,19 +1,43 @@
 pub mod accounts;
+mod attachment_uploads;
 mod ciphers;
+mod data_report;
+mod direct_shares;
 mod emergency_access;
 mod events;
 mod folders;
+mod imports;
+mod key_connector;
+mod org_domains;
+mod org_key_rotation;
 mod organizations;
+mod personal_ownership;
+mod personal_tokens;
+mod policy_violations;
+mod providers;
 mod public;
+mod secrets_manager;
 mod sends;
+mod sensitive_actions;
 pub mod two_factor;
+mod vault_export;
 
-pub use accounts::purge_auth_requests;
-pub use ciphers::{purge_trashed_ciphers, CipherData, CipherSyncData, CipherSyncType};
+pub use accounts::{purge_auth_requests, purge_deleted_users};
+pub use attachment_uploads::purge_attachment_uploads;
+pub use ciphers::{purge_cipher_history, purge_trashed_ciphers, CipherData, CipherSyncData, CipherSyncType};
 pub use emergency_access::{emergency_notification_reminder_job, emergency_request_timeout_job};
-pub use events::{event_cleanup_job, log_event, log_user_event};
+pub use events::{event_cleanup_job, log_admin_user_event, log_event, log_user_event};
+pub use org_domains::{
+    capture_domain_user, check_claimed_account, is_sso_required, is_valid_domain_name, org_domain_verification_job,
+    set_membership_defaults,
+};
+pub use organizations::{check_org_ip_restrictions, purge_archived_organizations, set_org_archived};
+pub use policy_violations::{check_user_allowed, record_master_password_check, record_policy_violation};
+pub use providers::invite_provider_user;
 use reqwest::Method;
 pub use sends::purge_sends;
+pub use sensitive_actions::{notify_role_escalation, notify_sensitive_action};
+pub use vault_export::export_user_vault;
 
 pub fn routes() -> Vec<Route> {
     let mut eq_domains_routes = routes![get_eq_domains, post_eq_domains, put_eq_domains];
@@ -22,13 +46,26 @@ pub fn routes() -> Vec<Route> {
 
     let mut routes = Vec::new();
     routes.append(&mut accounts::routes());
+    routes.append(&mut attachment_uploads::routes());
     routes.append(&mut ciphers::routes());
+    routes.append(&mut data_report::routes());
+    routes.append(&mut direct_shares::routes());
     routes.append(&mut emergency_access::routes());
     routes.append(&mut events::routes());
     routes.append(&mut folders::routes());
+    routes.append(&mut imports::routes());
+    routes.append(&mut key_connector::routes());
+    routes.append(&mut org_domains::routes());
+    routes.append(&mut org_key_rotation::routes());
     routes.append(&mut organizations::routes());
+    routes.append(&mut personal_ownership::routes());
+    routes.append(&mut personal_tokens::routes());
+    routes.append(&mut policy_violations::routes());
+    routes.append(&mut providers::routes());
     routes.append(&mut two_factor::routes());
     routes.append(&mut sends::routes());
+    routes.append(&mut secrets_manager::routes());
+    routes.append(&mut sensitive_actions::routes());
     routes.append(&mut public::routes());
     routes.append(&mut eq_domains_routes);
     routes.append(&mut hibp_routes);
@@ -270,21 +307,27 @@ async fn accept_org_invite(
     if member.status != MembershipStatus::Invited as i32 {
         err!("User already accepted the invitation");
     }
+    check_claimed_account(user, Some(&member.org_uuid), conn).await?;
 
     // This check is also done at accept_invite, _confirm_invite, _activate_member, edit_member, admin::update_membership_type
     // It returns different error messages per function.
     if member.atype < MembershipType::Admin {
-        match OrgPolicy::is_user_allowed(&member.user_uuid, &member.org_uuid, false, conn).await {
-            Ok(_) => {}
+        match check_user_allowed(
+            &member.user_uuid,
+            &member.org_uuid,
+            false,
+            "Joining the organization",
+            &user.uuid,
+            conn,
+        )
+        .await?
+        {
+            Ok(()) => {}
             Err(OrgPolicyErr::TwoFactorMissing) => {
-                if crate::CONFIG.email_2fa_auto_fallback() {
-                    two_factor::email::activate_email_2fa(user, conn).await?;
-                } else {
-                    err!("You cannot join this organization until you enable two-step login on your user account");
-                }
+                err!("You cannot join this organization until you enable two-step login on your user account")
             }
             Err(OrgPolicyErr::SingleOrgEnforced) => {
-                err!("You cannot join this organization because you are a member of an organization which forbids it");
+                err!("You cannot join this organization because you are a member of an organization which forbids it")
             }
         }
     }
@@ -0,0 +1,428 @@
+use chrono::{TimeDelta, Utc};
+use hickory_resolver::{name_server::TokioConnectionProvider, ResolveError, TokioResolver};
+use num_traits::FromPrimitive;
+use rocket::{http::Status, serde::json::Json, Route};
+use serde_json::Value;
+
+use crate::{
+    api::{check_report_invite_gate, core::log_event, ApiResult, EmptyResult, JsonResult},
+    auth::{AdminHeaders, OwnerHeaders},
+    db::{models::*, DbConn, DbPool},
+    mail,
+    util::format_date,
+    CONFIG,
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        get_org_domains,
+        get_org_domain,
+        post_org_domain,
+        verify_org_domain,
+        delete_org_domain,
+        post_delete_org_domain,
+        put_domain_membership_defaults,
+        get_domain_sso_details,
+    ]
+}
+
+// Like Bitwarden, new domains are checked by the verification job during the first three days
+const AUTO_VERIFY_DAYS: i64 = 3;
+
+#[get("/organizations/<org_id>/domain")]
+async fn get_org_domains(org_id: OrganizationId, headers: AdminHeaders, mut conn: DbConn) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let domains: Vec<Value> = OrgDomain::find_by_org(&org_id, &mut conn).await.iter().map(OrgDomain::to_json).collect();
+
+    Ok(Json(json!({
+        "data": domains,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+#[get("/organizations/<org_id>/domain/<domain_id>")]
+async fn get_org_domain(
+    org_id: OrganizationId,
+    domain_id: OrgDomainId,
+    headers: AdminHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let Some(domain) = OrgDomain::find_by_uuid_and_org(&domain_id, &org_id, &mut conn).await else {
+        err!("Domain not found")
+    };
+
+    Ok(Json(domain.to_json()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgDomainData {
+    domain_name: String,
+}
+
+// The returned `txt` value has to be added as a DNS TXT record of the domain, after which it can be verified
+#[post("/organizations/<org_id>/domain", data = "<data>")]
+async fn post_org_domain(
+    org_id: OrganizationId,
+    data: Json<OrgDomainData>,
+    headers: AdminHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let data: OrgDomainData = data.into_inner();
+    if !is_valid_domain_name(&data.domain_name) {
+        err!("Invalid domain name")
+    }
+
+    let domain = OrgDomain::new(org_id.clone(), &data.domain_name);
+    if OrgDomain::find_verified_by_domain(&domain.domain_name, &mut conn).await.is_some() {
+        err!("The domain name has already been claimed")
+    }
+    if OrgDomain::find_by_org(&org_id, &mut conn).await.iter().any(|d| d.domain_name == domain.domain_name) {
+        err!("The domain name has already been added to this organization")
+    }
+    domain.save(&mut conn).await?;
+
+    log_event(
+        EventType::OrganizationDomainAdded as i32,
+        &domain.uuid,
+        &org_id,
+        &headers.user.uuid,
+        headers.device.atype,
+        &headers.ip.ip,
+        &mut conn,
+    )
+    .await;
+
+    Ok(Json(domain.to_json()))
+}
+
+#[post("/organizations/<org_id>/domain/<domain_id>/verify")]
+async fn verify_org_domain(
+    org_id: OrganizationId,
+    domain_id: OrgDomainId,
+    headers: AdminHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let Some(mut domain) = OrgDomain::find_by_uuid_and_org(&domain_id, &org_id, &mut conn).await else {
+        err!("Domain not found")
+    };
+
+    if domain.verified_at.is_none() {
+        let event_type = if verify_domain(&mut domain, &mut conn).await? {
+            EventType::OrganizationDomainVerified
+        } else {
+            EventType::OrganizationDomainNotVerified
+        };
+        log_event(
+            event_type as i32,
+            &domain.uuid,
+            &org_id,
+            &headers.user.uuid,
+            headers.device.atype,
+            &headers.ip.ip,
+            &mut conn,
+        )
+        .await;
+    }
+
+    Ok(Json(domain.to_json()))
+}
+
+#[delete("/organizations/<org_id>/domain/<domain_id>")]
+async fn delete_org_domain(
+    org_id: OrganizationId,
+    domain_id: OrgDomainId,
+    headers: AdminHeaders,
+    mut conn: DbConn,
+) -> EmptyResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let Some(domain) = OrgDomain::find_by_uuid_and_org(&domain_id, &org_id, &mut conn).await else {
+        err!("Domain not found")
+    };
+    domain.delete(&mut conn).await?;
+
+    log_event(
+        EventType::OrganizationDomainRemoved as i32,
+        &domain_id,
+        &org_id,
+        &headers.user.uuid,
+        headers.device.atype,
+        &headers.ip.ip,
+        &mut conn,
+    )
+    .await;
+
+    Ok(())
+}
+
+#[post("/organizations/<org_id>/domain/<domain_id>/remove")]
+async fn post_delete_org_domain(
+    org_id: OrganizationId,
+    domain_id: OrgDomainId,
+    headers: AdminHeaders,
+    conn: DbConn,
+) -> EmptyResult {
+    delete_org_domain(org_id, domain_id, headers, conn).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DomainSsoDetailsData {
+    email: String,
+}
+
+// Used by the clients to route the users of a claimed domain to the SSO login of its organization
+#[post("/organizations/domain/sso/details", data = "<data>")]
+async fn get_domain_sso_details(data: Json<DomainSsoDetailsData>, mut conn: DbConn) -> JsonResult {
+    let data: DomainSsoDetailsData = data.into_inner();
+    let Some(domain) = OrgDomain::find_verified_by_email(&data.email, &mut conn).await else {
+        err_code!("Claimed domain not found", Status::NotFound.code)
+    };
+    let Some(org) = Organization::find_by_uuid(&domain.org_uuid, &mut conn).await else {
+        err_code!("Claimed domain not found", Status::NotFound.code)
+    };
+
+    Ok(Json(json!({
+        "ssoAvailable": CONFIG.sso_enabled(),
+        "domainName": domain.domain_name,
+        "organizationIdentifier": org.name,
+        "verifiedDate": domain.verified_at.as_ref().map(format_date),
+        "object": "organizationDomainSsoDetails",
+    })))
+}
+
+/// Looks up the TXT records of the domain and marks it as verified when one of them matches.
+/// Once verified, the existing users with an email address of the domain are added to the organization.
+async fn verify_domain(domain: &mut OrgDomain, conn: &mut DbConn) -> ApiResult<bool> {
+    if OrgDomain::find_verified_by_domain(&domain.domain_name, conn).await.is_some() {
+        err!("The domain name has already been claimed")
+    }
+    let Some(txt) = domain.txt.clone() else {
+        err!("The domain has no verification record")
+    };
+
+    let now = Utc::now().naive_utc();
+    domain.last_checked_at = Some(now);
+    domain.check_count += 1;
+
+    let verified = match lookup_txt_records(&domain.domain_name).await {
+        Ok(records) => records.iter().any(|record| record.trim() == txt),
+        Err(e) => {
+            debug!("Unable to look up the TXT records of {}: {e}", domain.domain_name);
+            false
+        }
+    };
+    if verified {
+        domain.verified_at = Some(now);
+    }
+    domain.save(conn).await?;
+
+    if verified {
+        for user in User::find_by_email_domain(&domain.domain_name, conn).await {
+            if let Err(e) = capture_domain_user(&user, conn).await {
+                warn!("Failed to add {} to the organization of the claimed domain: {e:?}", user.email);
+            }
+        }
+    }
+
+    Ok(verified)
+}
+
+async fn lookup_txt_records(domain_name: &str) -> Result<Vec<String>, ResolveError> {
+    let resolver = TokioResolver::builder(TokioConnectionProvider::default())?.build();
+    let lookup = resolver.txt_lookup(domain_name).await?;
+    Ok(lookup.iter().map(ToString::to_string).collect())
+}
+
+pub async fn org_domain_verification_job(pool: DbPool) {
+    debug!("Start organization domain verification job");
+    let Ok(mut conn) = pool.get().await else {
+        error!("Failed to get DB connection while running the organization domain verification job");
+        return;
+    };
+
+    let since = Utc::now().naive_utc() - TimeDelta::days(AUTO_VERIFY_DAYS);
+    for mut domain in OrgDomain::find_unverified_since(&since, &mut conn).await {
+        match verify_domain(&mut domain, &mut conn).await {
+            Ok(true) => info!("Verified the domain {} of organization {}", domain.domain_name, domain.org_uuid),
+            Ok(false) => {}
+            Err(e) => warn!("Failed to verify the domain {}: {e:?}", domain.domain_name),
+        }
+    }
+}
+
+/// Accounts with an email address of a verified domain are claimed by its organization,
+/// they can't be a member of any other organization.
+pub async fn check_claimed_account(user: &User, org_id: Option<&OrganizationId>, conn: &mut DbConn) -> EmptyResult {
+    if let Some(domain) = OrgDomain::find_verified_by_user(user, conn).await {
+        if org_id != Some(&domain.org_uuid) {
+            err!("Your account is claimed by an organization, it can't be a member of any other organization")
+        }
+    }
+    Ok(())
+}
+
+/// Returns if the user has to log in with SSO, because the organization which claimed the account requires it.
+/// Like Bitwarden, Owners and Admins can still log in with their master password.
+pub async fn is_sso_required(user: &User, conn: &mut DbConn) -> bool {
+    let Some(domain) = OrgDomain::find_verified_by_user(user, conn).await else {
+        return false;
+    };
+
+    Membership::find_by_user_and_org(&user.uuid, &domain.org_uuid, conn)
+        .await
+        .is_some_and(|member| member.atype < MembershipType::Admin)
+        && OrgPolicy::find_by_org_and_type(&domain.org_uuid, OrgPolicyType::RequireSso, conn)
+            .await
+            .is_some_and(|policy| policy.enabled)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MembershipDefaultsData {
+    r#type: i32,
+    collections: Option<Vec<CollectionId>>,
+    auto_confirm: bool,
+}
+
+#[put("/organizations/<org_id>/domain/<domain_id>/membership-defaults", data = "<data>")]
+async fn put_domain_membership_defaults(
+    org_id: OrganizationId,
+    domain_id: OrgDomainId,
+    data: Json<MembershipDefaultsData>,
+    headers: OwnerHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let data: MembershipDefaultsData = data.into_inner();
+    let Some(mut domain) = OrgDomain::find_by_uuid_and_org(&domain_id, &org_id, &mut conn).await else {
+        err!("Domain not found")
+    };
+
+    set_membership_defaults(
+        &mut domain,
+        data.r#type,
+        data.collections.unwrap_or_default(),
+        data.auto_confirm,
+        &mut conn,
+    )
+    .await?;
+    domain.save(&mut conn).await?;
+
+    Ok(Json(domain.to_json()))
+}
+
+/// Validates and sets the membership settings used for users captured by the domain.
+/// Owners can't be created this way, those should always be invited explicitly.
+pub async fn set_membership_defaults(
+    domain: &mut OrgDomain,
+    member_type: i32,
+    collections: Vec<CollectionId>,
+    auto_confirm: bool,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    match MembershipType::from_i32(member_type) {
+        Some(MembershipType::Owner) => err!("Domain captured users can't be owners"),
+        Some(_) => {}
+        None => err!("Invalid type"),
+    }
+
+    for col_id in &collections {
+        if Collection::find_by_uuid_and_org(col_id, &domain.org_uuid, conn).await.is_none() {
+            err!("Collection not found in Organization")
+        }
+    }
+
+    domain.member_type = member_type;
+    domain.set_collections(&collections);
+    domain.auto_confirm = auto_confirm;
+    Ok(())
+}
+
+/// Adds a newly created user to the organization which verified the domain of their email address, if any.
+/// The organization key can only be shared by an admin's client, so `auto_confirm` can't fully confirm the member.
+/// Instead the invitation is skipped and the member is created as accepted, ready to be confirmed.
+/// Auto acceptance is only done for verified email addresses, others still have to accept the invitation.
+pub async fn capture_domain_user(user: &User, conn: &mut DbConn) -> EmptyResult {
+    let Some(domain) = OrgDomain::find_verified_by_user(user, conn).await else {
+        return Ok(());
+    };
+    let domain_name = &domain.domain_name;
+    if Membership::find_by_user_and_org(&user.uuid, &domain.org_uuid, conn).await.is_some() {
+        return Ok(());
+    }
+    let Some(org) = Organization::find_by_uuid(&domain.org_uuid, conn).await else {
+        return Ok(());
+    };
+    if let Err(e) = org.check_available_seats(1, conn).await {
+        warn!("Not adding {} to the organization of the claimed domain {domain_name}: {}", user.email, e.message());
+        return Ok(());
+    }
+    if let Err(e) = check_report_invite_gate(&org.uuid, false, conn).await {
+        warn!("Not adding {} to the organization of the claimed domain {domain_name}: {}", user.email, e.message());
+        return Ok(());
+    }
+
+    let mut member = Membership::new(user.uuid.clone(), org.uuid.clone(), None);
+    member.atype = domain.member_type;
+    member.access_all = domain.member_type >= MembershipType::Admin;
+    member.status = if (domain.auto_confirm && user.verified_at.is_some()) || !CONFIG.mail_enabled() {
+        MembershipStatus::Accepted as i32
+    } else {
+        MembershipStatus::Invited as i32
+    };
+    member.save(conn).await?;
+
+    if !member.access_all {
+        for col_id in domain.get_collections() {
+            // Collections might have been deleted since the defaults were set
+            if Collection::find_by_uuid_and_org(&col_id, &org.uuid, conn).await.is_some() {
+                CollectionUser::save(&user.uuid, &col_id, false, false, false, conn).await?;
+            }
+        }
+    }
+
+    if member.status == MembershipStatus::Invited as i32 {
+        mail::send_invite(user, org.uuid.clone(), member.uuid.clone(), &org.name, None, Some(&org)).await?;
+    }
+
+    log_event(
+        EventType::OrganizationUserInvited as i32,
+        &member.uuid,
+        &org.uuid,
+        &user.uuid,
+        14, // Use UnknownBrowser type
+        &std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+        conn,
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Checks if the given value looks like a domain name that can be claimed.
+pub fn is_valid_domain_name(domain_name: &str) -> bool {
+    let domain_name = domain_name.trim();
+    !domain_name.is_empty()
+        && domain_name.len() <= 255
+        && domain_name.contains('.')
+        && !domain_name.starts_with('.')
+        && !domain_name.ends_with('.')
+        && domain_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
@@ -0,0 +1,214 @@
+use std::collections::HashSet;
+
+use rocket::{serde::json::Json, Route};
+
+use crate::{
+    api::{
+        core::{ciphers::update_cipher_from_data, log_event, CipherData},
+        EmptyResult, Notify, UpdateType,
+    },
+    auth::{Headers, OwnerHeaders},
+    db::{begin_transaction, commit_transaction, models::*, rollback_transaction, DbConn},
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![post_rotate_org_key]
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgKeyRotationData {
+    master_password_hash: String,
+    // The key pair of the organization, the private key is encrypted with the new organization key.
+    // When the public key changes, the account recovery keys of the members have to be provided again.
+    public_key: Option<String>,
+    encrypted_private_key: Option<String>,
+    members: Vec<OrgKeyRotationMemberData>,
+    collections: Vec<OrgKeyRotationCollectionData>,
+    ciphers: Vec<CipherData>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgKeyRotationMemberData {
+    id: MembershipId,
+    // The new organization key, encrypted with the public key of the member
+    key: String,
+    reset_password_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgKeyRotationCollectionData {
+    id: CollectionId,
+    name: String,
+}
+
+/// Replaces the organization key, re-encrypted by the client for every confirmed member, together with all the
+/// data encrypted with it. The revoked members which were confirmed are included, they get the new key back once
+/// they are restored. Everything is applied in a single transaction, nothing is changed if one of the updates fails.
+#[post("/organizations/<org_id>/keys/rotate", data = "<data>")]
+async fn post_rotate_org_key(
+    org_id: OrganizationId,
+    data: Json<OrgKeyRotationData>,
+    headers: OwnerHeaders,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let data: OrgKeyRotationData = data.into_inner();
+    let headers: Headers = headers.into();
+
+    if !headers.user.check_valid_password(&data.master_password_hash) {
+        err!("Invalid password")
+    }
+
+    let Some(org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err!("Organization not found")
+    };
+
+    Cipher::validate_cipher_data(&data.ciphers)?;
+
+    let members = find_members_with_key(&org_id, &mut conn).await;
+    let collections = Collection::find_by_organization(&org_id, &mut conn).await;
+    let ciphers = Cipher::find_by_org(&org_id, &mut conn).await;
+    validate_rotation_data(&data, &org, &members, &collections, &ciphers)?;
+
+    begin_transaction(&mut conn).await?;
+    match apply_rotation(data, org, members, collections, ciphers, &headers, &mut conn, &nt).await {
+        Ok(()) => commit_transaction(&mut conn).await?,
+        Err(e) => {
+            if let Err(rollback_err) = rollback_transaction(&mut conn).await {
+                error!("Error rolling back the key rotation of organization {org_id}: {rollback_err:#?}");
+            }
+            return Err(e);
+        }
+    }
+
+    log_event(
+        EventType::OrganizationUpdated as i32,
+        org_id.as_ref(),
+        &org_id,
+        &headers.user.uuid,
+        headers.device.atype,
+        &headers.ip.ip,
+        &mut conn,
+    )
+    .await;
+
+    // The clients of the members have to fetch the new organization key
+    for member in Membership::find_confirmed_by_org(&org_id, &mut conn).await {
+        if let Some(user) = User::find_by_uuid(&member.user_uuid, &mut conn).await {
+            nt.send_user_update(UpdateType::SyncOrgKeys, &user, &headers.device.push_uuid, &mut conn).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// The members which hold the organization key, the confirmed ones and the revoked ones which were confirmed.
+async fn find_members_with_key(org_id: &OrganizationId, conn: &mut DbConn) -> Vec<Membership> {
+    Membership::find_by_org(org_id, conn)
+        .await
+        .into_iter()
+        .filter(|member| member.get_unrevoked_status() == MembershipStatus::Confirmed as i32)
+        .collect()
+}
+
+/// Checks that the client re-encrypted everything, else some members or items would become unreadable.
+fn validate_rotation_data(
+    data: &OrgKeyRotationData,
+    org: &Organization,
+    members: &[Membership],
+    collections: &[Collection],
+    ciphers: &[Cipher],
+) -> EmptyResult {
+    let existing_member_ids: HashSet<&MembershipId> = members.iter().map(|m| &m.uuid).collect();
+    let provided_member_ids: HashSet<&MembershipId> = data.members.iter().map(|m| &m.id).collect();
+    if existing_member_ids != provided_member_ids || provided_member_ids.len() != data.members.len() {
+        err!("All confirmed members of the organization, also the revoked ones, must be included in the key rotation.")
+    }
+
+    let existing_collection_ids: HashSet<&CollectionId> = collections.iter().map(|c| &c.uuid).collect();
+    let provided_collection_ids: HashSet<&CollectionId> = data.collections.iter().map(|c| &c.id).collect();
+    if existing_collection_ids != provided_collection_ids || provided_collection_ids.len() != data.collections.len() {
+        err!("All collections of the organization must be included in the key rotation.")
+    }
+
+    let existing_cipher_ids: HashSet<&CipherId> = ciphers.iter().map(|c| &c.uuid).collect();
+    let provided_cipher_ids: HashSet<&CipherId> = data.ciphers.iter().filter_map(|c| c.id.as_ref()).collect();
+    if existing_cipher_ids != provided_cipher_ids || provided_cipher_ids.len() != data.ciphers.len() {
+        err!("All ciphers of the organization must be included in the key rotation.")
+    }
+    if data.ciphers.iter().any(|c| c.organization_id.as_ref() != Some(&org.uuid)) {
+        err!("Organization mismatch. Please resync the client before rotating the key")
+    }
+
+    if org.private_key.is_some() && data.encrypted_private_key.is_none() {
+        err!("The private key of the organization must be included in the key rotation.")
+    }
+    if data.public_key.is_some() && data.encrypted_private_key.is_none() {
+        err!("A new public key requires the matching private key.")
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn apply_rotation(
+    data: OrgKeyRotationData,
+    mut org: Organization,
+    mut members: Vec<Membership>,
+    mut collections: Vec<Collection>,
+    mut ciphers: Vec<Cipher>,
+    headers: &Headers,
+    conn: &mut DbConn,
+    nt: &Notify<'_>,
+) -> EmptyResult {
+    let public_key_changed = data.public_key.is_some() && data.public_key != org.public_key;
+
+    for member_data in data.members {
+        let Some(member) = members.iter_mut().find(|m| m.uuid == member_data.id) else {
+            err!("Member doesn't exist")
+        };
+
+        member.akey = member_data.key;
+        // The recovery keys are encrypted with the public key of the organization, old ones can't be used anymore
+        if public_key_changed || member_data.reset_password_key.is_some() {
+            member.reset_password_key = member_data.reset_password_key;
+        }
+        member.save(conn).await?;
+    }
+
+    for collection_data in data.collections {
+        let Some(collection) = collections.iter_mut().find(|c| c.uuid == collection_data.id) else {
+            err!("Collection doesn't exist")
+        };
+
+        collection.name = collection_data.name;
+        collection.save(conn).await?;
+    }
+
+    // The previous revisions of the items are encrypted with the old key
+    CipherHistory::delete_all_by_organization(&org.uuid, conn).await?;
+    for cipher_data in data.ciphers {
+        let Some(cipher) = ciphers.iter_mut().find(|c| c.uuid == *cipher_data.id.as_ref().unwrap()) else {
+            err!("Cipher doesn't exist")
+        };
+
+        // Same as with the user key rotation, the clients are notified once everything is done
+        update_cipher_from_data(cipher, cipher_data, headers, None, conn, nt, UpdateType::None).await?;
+    }
+
+    if let Some(encrypted_private_key) = data.encrypted_private_key {
+        org.private_key = Some(encrypted_private_key);
+        if data.public_key.is_some() {
+            org.public_key = data.public_key;
+        }
+        org.save(conn).await?;
+    }
+
+    Ok(())
+}
@@ -1,17 +1,30 @@
+use chrono::{TimeDelta, Utc};
 use num_traits::FromPrimitive;
 use rocket::serde::json::Json;
-use rocket::Route;
+use rocket::{Route, State};
 use serde_json::Value;
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+};
 
 use crate::api::admin::FAKE_ADMIN_UUID;
 use crate::{
     api::{
-        core::{accept_org_invite, log_event, two_factor, CipherSyncData, CipherSyncType},
-        EmptyResult, JsonResult, Notify, PasswordOrOtpData, UpdateType,
+        check_report_invite_gate,
+        core::{
+            accept_org_invite, check_claimed_account, check_user_allowed, log_event, notify_role_escalation,
+            notify_sensitive_action, record_policy_violation, two_factor, CipherSyncData, CipherSyncType,
+        },
+        ApiResult, EmptyResult, JsonResult, MasterPasswordPolicy, Notify, PasswordOrOtpData, UpdateType,
+    },
+    auth::{
+        decode_invite, AccessImportExportHeaders, AdminHeaders, Headers, ManageGroupsHeaders, ManagePoliciesHeaders,
+        ManageResetPasswordHeaders, ManageUsersHeaders, ManagerHeaders, ManagerHeadersLoose, OrgMemberHeaders,
+        OwnerHeaders,
     },
-    auth::{decode_invite, AdminHeaders, Headers, ManagerHeaders, ManagerHeadersLoose, OrgMemberHeaders, OwnerHeaders},
-    db::{models::*, DbConn},
+    db::{begin_transaction, commit_transaction, models::*, rollback_transaction, DbConn, DbPool},
+    http_client::parse_ip_ranges,
     mail,
     util::{convert_json_key_lcase_first, get_uuid, NumberOrString},
     CONFIG,
@@ -32,6 +45,10 @@ pub fn routes() -> Vec<Route> {
         put_collection_users,
         put_organization,
         post_organization,
+        put_organization_trash_retention,
+        put_organization_events_retention,
+        put_organization_ip_restrictions,
+        put_organization_invite_text,
         post_organization_collections,
         delete_organization_collection_member,
         post_organization_collection_delete_member,
@@ -77,6 +94,7 @@ pub fn routes() -> Vec<Route> {
         bulk_deactivate_members,
         revoke_member,
         bulk_revoke_members,
+        offboard_member,
         activate_member,
         bulk_activate_members,
         restore_member,
@@ -185,6 +203,7 @@ async fn create_organization(headers: Headers, data: Json<OrgData>, mut conn: Db
             "You may not create an organization. You belong to an organization which has a policy that prohibits you from being a member of any other organization."
         )
     }
+    check_claimed_account(&headers.user, None, &mut conn).await?;
 
     let data: OrgData = data.into_inner();
     let (private_key, public_key) = if data.keys.is_some() {
@@ -254,6 +273,17 @@ async fn leave_organization(org_id: OrganizationId, headers: Headers, mut conn:
                 err!("The last owner can't leave")
             }
 
+            // The Key Connector stays tied to the organization, so these members would be left without a way to unlock
+            if headers.user.uses_key_connector
+                && OrgPolicy::find_key_connector_org(&headers.user.uuid, &mut conn).await.as_ref() == Some(&org_id)
+            {
+                err!("You cannot leave this Organization because you are using Key Connector")
+            }
+
+            if OrgDomain::find_verified_by_user(&headers.user, &mut conn).await.is_some_and(|d| d.org_uuid == org_id) {
+                err!("You cannot leave this Organization because your account is claimed by it")
+            }
+
             log_event(
                 EventType::OrganizationUserLeft as i32,
                 &member.uuid,
@@ -327,6 +357,274 @@ async fn post_organization(
     Ok(Json(org.to_json()))
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrashRetentionData {
+    // None falls back to `TRASH_AUTO_DELETE_DAYS`
+    trash_retention_days: Option<i32>,
+}
+
+// Vaultwarden specific, the number of days trashed items of the organization are kept
+#[put("/organizations/<org_id>/trash-retention", data = "<data>")]
+async fn put_organization_trash_retention(
+    org_id: OrganizationId,
+    headers: OwnerHeaders,
+    data: Json<TrashRetentionData>,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+
+    let data: TrashRetentionData = data.into_inner();
+    // Limited to 100 years, so the purge can always compute its cutoff date
+    if data.trash_retention_days.is_some_and(|days| !(1..=36500).contains(&days)) {
+        err!("The trash retention has to be between 1 and 36500 days")
+    }
+
+    let Some(mut org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err!("Organization not found")
+    };
+    org.trash_retention_days = data.trash_retention_days;
+    org.save(&mut conn).await?;
+
+    log_event(
+        EventType::OrganizationUpdated as i32,
+        org_id.as_ref(),
+        &org_id,
+        &headers.user.uuid,
+        headers.device.atype,
+        &headers.ip.ip,
+        &mut conn,
+    )
+    .await;
+
+    Ok(Json(org.to_json()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventsRetentionData {
+    // None falls back to `EVENTS_DAYS_RETAIN`
+    events_days_retain: Option<i32>,
+}
+
+// Vaultwarden specific, the number of days events of the organization are kept
+#[put("/organizations/<org_id>/events-retention", data = "<data>")]
+async fn put_organization_events_retention(
+    org_id: OrganizationId,
+    headers: OwnerHeaders,
+    data: Json<EventsRetentionData>,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+
+    let data: EventsRetentionData = data.into_inner();
+    if data.events_days_retain.is_some_and(|days| days < 1) {
+        err!("The events retention has to be at least one day")
+    }
+    if let (Some(days), Some(minimum)) = (data.events_days_retain, CONFIG.events_days_retain()) {
+        if i64::from(days) < minimum {
+            err!(format!("The events retention has to be at least {minimum} days"))
+        }
+    }
+
+    let Some(mut org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err!("Organization not found")
+    };
+    org.events_days_retain = data.events_days_retain;
+    org.save(&mut conn).await?;
+
+    log_event(
+        EventType::OrganizationUpdated as i32,
+        org_id.as_ref(),
+        &org_id,
+        &headers.user.uuid,
+        headers.device.atype,
+        &headers.ip.ip,
+        &mut conn,
+    )
+    .await;
+
+    Ok(Json(org.to_json()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IpRestrictionsData {
+    // Comma separated IP ranges in CIDR notation, None or empty removes the list
+    ip_allowlist: Option<String>,
+    ip_denylist: Option<String>,
+}
+
+// Vaultwarden specific, members can only get tokens and sync from the allowed IP ranges
+#[put("/organizations/<org_id>/ip-restrictions", data = "<data>")]
+async fn put_organization_ip_restrictions(
+    org_id: OrganizationId,
+    headers: OwnerHeaders,
+    data: Json<IpRestrictionsData>,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+
+    let data: IpRestrictionsData = data.into_inner();
+    let normalize = |list: Option<String>| -> ApiResult<Option<String>> {
+        let Some(list) = list.filter(|l| !l.trim().is_empty()) else {
+            return Ok(None);
+        };
+        match parse_ip_ranges(&list) {
+            Ok(ranges) => Ok(Some(ranges.iter().map(|range| range.trunc().to_string()).collect::<Vec<_>>().join(","))),
+            Err(_) => err!(format!("`{list}` is not a valid list of IP ranges in CIDR notation")),
+        }
+    };
+
+    let Some(mut org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err!("Organization not found")
+    };
+    org.ip_allowlist = normalize(data.ip_allowlist)?;
+    org.ip_denylist = normalize(data.ip_denylist)?;
+
+    // Don't let the owner lock themselves out
+    if !org.is_ip_allowed(headers.ip.ip) {
+        err!(format!("Your current IP {} would be blocked by these IP restrictions", headers.ip.ip))
+    }
+    org.save(&mut conn).await?;
+
+    log_event(
+        EventType::OrganizationUpdated as i32,
+        org_id.as_ref(),
+        &org_id,
+        &headers.user.uuid,
+        headers.device.atype,
+        &headers.ip.ip,
+        &mut conn,
+    )
+    .await;
+
+    Ok(Json(org.to_json()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InviteTextData {
+    // None or empty falls back to the default text of the invitation mails
+    invite_subject: Option<String>,
+    // Markdown, replaces the default text above the link to join
+    invite_body: Option<String>,
+    invite_reply_to: Option<String>,
+}
+
+// Vaultwarden specific, customizes the invitation mails of the organization
+#[put("/organizations/<org_id>/invite-text", data = "<data>")]
+async fn put_organization_invite_text(
+    org_id: OrganizationId,
+    headers: OwnerHeaders,
+    data: Json<InviteTextData>,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+
+    let data: InviteTextData = data.into_inner();
+    let Some(mut org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err!("Organization not found")
+    };
+    org.set_invite_text(data.invite_subject, data.invite_body, data.invite_reply_to)?;
+    org.save(&mut conn).await?;
+
+    log_event(
+        EventType::OrganizationUpdated as i32,
+        org_id.as_ref(),
+        &org_id,
+        &headers.user.uuid,
+        headers.device.atype,
+        &headers.ip.ip,
+        &mut conn,
+    )
+    .await;
+
+    Ok(Json(org.to_json()))
+}
+
+/// Refuses tokens and syncs from outside the IP ranges of the organizations the user is a confirmed member of.
+/// Every organization blocking the IP gets a failed login event.
+pub async fn check_org_ip_restrictions(
+    user_uuid: &UserId,
+    device_type: i32,
+    ip: &IpAddr,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    let mut blocked_by = Vec::new();
+    for member in Membership::find_confirmed_by_user(user_uuid, conn).await {
+        let Some(org) = Organization::find_by_uuid(&member.org_uuid, conn).await else {
+            continue;
+        };
+        if org.has_ip_restrictions() && !org.is_ip_allowed(*ip) {
+            log_event(EventType::UserFailedLogIn as i32, &member.uuid, &org.uuid, user_uuid, device_type, ip, conn)
+                .await;
+            blocked_by.push(org.name);
+        }
+    }
+
+    if !blocked_by.is_empty() {
+        err!(
+            "Access from this IP address isn't allowed by your organization",
+            format!("IP: {ip}. User: {user_uuid}. Blocked by: {}", blocked_by.join(", "))
+        )
+    }
+    Ok(())
+}
+
+/// Archives or unarchives the organization for the admin panel and the `/custom` API. While it is archived
+/// the members can't use or change its items, which are kept until `ORG_ARCHIVE_RETENTION_DAYS` passed.
+pub async fn set_org_archived(
+    org: &mut Organization,
+    archived: bool,
+    nt: &Notify<'_>,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    if org.is_archived() == archived {
+        return Ok(());
+    }
+    org.archived_at = archived.then(|| Utc::now().naive_utc());
+    org.save(conn).await?;
+
+    // The members sync their whole vault, the items of the organization disappear or come back
+    for member in Membership::find_confirmed_by_org(&org.uuid, conn).await {
+        User::update_uuid_revision(&member.user_uuid, conn).await;
+        if let Some(user) = User::find_by_uuid(&member.user_uuid, conn).await {
+            nt.send_user_update(UpdateType::SyncVault, &user, &None, conn).await;
+        }
+    }
+    Ok(())
+}
+
+pub async fn purge_archived_organizations(pool: DbPool) {
+    debug!("Purging archived organizations");
+    let retention_days = CONFIG.org_archive_retention_days();
+    if retention_days == 0 {
+        return;
+    }
+    let Ok(mut conn) = pool.get().await else {
+        error!("Failed to get DB connection while purging archived organizations");
+        return;
+    };
+
+    let purge_before = Utc::now().naive_utc() - TimeDelta::try_days(retention_days).unwrap_or_default();
+    for org in Organization::find_archived_before(&purge_before, &mut conn).await {
+        let org_id = org.uuid.clone();
+        match org.delete(&mut conn).await {
+            Ok(()) => info!("Deleted organization {org_id}, its archive retention has passed"),
+            Err(e) => error!("Error purging archived organization {org_id}: {e:#?}"),
+        }
+    }
+}
+
 // GET /api/collections?writeOnly=false
 #[get("/collections")]
 async fn get_user_collections(headers: Headers, mut conn: DbConn) -> Json<Value> {
@@ -529,8 +827,9 @@ async fn post_organization_collections(
         .await?;
     }
 
-    if headers.membership.atype == MembershipType::Manager && !headers.membership.access_all {
-        CollectionUser::save(&headers.membership.user_uuid, &collection.uuid, false, false, false, &mut conn).await?;
+    // Like Bitwarden, the member who created the collection can manage it
+    if !headers.membership.has_full_access() {
+        CollectionUser::save(&headers.membership.user_uuid, &collection.uuid, false, false, true, &mut conn).await?;
     }
 
     Ok(Json(collection.to_json_details(&headers.membership.user_uuid, None, &mut conn).await))
@@ -555,6 +854,7 @@ async fn post_bulk_access_collections(
         err!("Organization not found", "Organization id's do not match");
     }
     let data: BulkCollectionAccessData = data.into_inner();
+    let headers = ManagerHeaders::from_loose(headers, &data.collection_ids, &mut conn).await?;
 
     if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
         err!("Can't find organization details")
@@ -977,15 +1277,22 @@ struct OrgDomainDetails {
 async fn get_org_domain_sso_verified(data: Json<OrgDomainDetails>, mut conn: DbConn) -> JsonResult {
     let data: OrgDomainDetails = data.into_inner();
 
-    let identifiers = match Organization::find_org_user_email(&data.email, &mut conn)
+    let mut identifiers = Organization::find_org_user_email(&data.email, &mut conn)
         .await
         .into_iter()
         .map(|o| o.name)
-        .collect::<Vec<String>>()
-    {
-        v if !v.is_empty() => v,
-        _ => vec![crate::sso::FAKE_IDENTIFIER.to_string()],
-    };
+        .collect::<Vec<String>>();
+
+    // The organization which claimed the domain of the email goes first
+    if let Some(domain) = OrgDomain::find_verified_by_email(&data.email, &mut conn).await {
+        if let Some(org) = Organization::find_by_uuid(&domain.org_uuid, &mut conn).await {
+            identifiers.retain(|identifier| *identifier != org.name);
+            identifiers.insert(0, org.name);
+        }
+    }
+    if identifiers.is_empty() {
+        identifiers.push(crate::sso::FAKE_IDENTIFIER.to_string());
+    }
 
     Ok(Json(json!({
         "object": "list",
@@ -1083,7 +1390,7 @@ struct InviteData {
 async fn send_invite(
     org_id: OrganizationId,
     data: Json<InviteData>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     if org_id != headers.org_id {
@@ -1104,14 +1411,25 @@ async fn send_invite(
         err!("Only Owners can invite Managers, Admins or Owners")
     }
 
+    check_report_invite_gate(&org_id, headers.membership_type == MembershipType::Owner, &mut conn).await?;
+
+    let Some(org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err!("Error looking up organization")
+    };
+    org.check_available_seats(data.emails.len() as i64, &mut conn).await?;
+
+    // The Custom role is stored as a manager with the granted permissions as a bit mask
+    let permissions = if raw_type.eq("4") {
+        OrgPermission::from_json(&data.permissions)
+    } else {
+        0
+    };
+
     // HACK: This converts the Custom role which has the `Manage all collections` box checked into an access_all flag
     // Since the parent checkbox is not sent to the server we need to check and verify the child checkboxes
     // If the box is not checked, the user will still be a manager, but not with the access_all permission
     let access_all = new_type >= MembershipType::Admin
-        || (raw_type.eq("4")
-            && data.permissions.get("editAnyCollection") == Some(&json!(true))
-            && data.permissions.get("deleteAnyCollection") == Some(&json!(true))
-            && data.permissions.get("createNewCollections") == Some(&json!(true)));
+        || permissions & OrgPermission::ALL_COLLECTIONS == OrgPermission::ALL_COLLECTIONS;
 
     let mut user_created: bool = false;
     for email in data.emails.iter() {
@@ -1151,21 +1469,22 @@ async fn send_invite(
         let mut new_member = Membership::new(user.uuid.clone(), org_id.clone(), Some(headers.user.email.clone()));
         new_member.access_all = access_all;
         new_member.atype = new_type;
+        new_member.permissions = permissions;
         new_member.status = member_status;
         new_member.save(&mut conn).await?;
 
         if CONFIG.mail_enabled() {
-            let org_name = match Organization::find_by_uuid(&org_id, &mut conn).await {
-                Some(org) => org.name,
-                None => err!("Error looking up organization"),
+            let Some(org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+                err!("Error looking up organization")
             };
 
             if let Err(e) = mail::send_invite(
                 &user,
                 org_id.clone(),
                 new_member.uuid.clone(),
-                &org_name,
+                &org.name,
                 Some(headers.user.email.clone()),
+                Some(&org),
             )
             .await
             {
@@ -1224,7 +1543,7 @@ async fn send_invite(
 async fn bulk_reinvite_members(
     org_id: OrganizationId,
     data: Json<BulkMembershipIds>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -1234,7 +1553,7 @@ async fn bulk_reinvite_members(
 
     let mut bulk_response = Vec::new();
     for member_id in data.ids {
-        let err_msg = match _reinvite_member(&org_id, &member_id, &headers.user.email, &mut conn).await {
+        let err_msg = match _reinvite_member(&org_id, &member_id, &headers, &mut conn).await {
             Ok(_) => String::new(),
             Err(e) => format!("{e:?}"),
         };
@@ -1259,19 +1578,19 @@ async fn bulk_reinvite_members(
 async fn reinvite_member(
     org_id: OrganizationId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     if org_id != headers.org_id {
         err!("Organization not found", "Organization id's do not match");
     }
-    _reinvite_member(&org_id, &member_id, &headers.user.email, &mut conn).await
+    _reinvite_member(&org_id, &member_id, &headers, &mut conn).await
 }
 
 async fn _reinvite_member(
     org_id: &OrganizationId,
     member_id: &MembershipId,
-    invited_by_email: &str,
+    headers: &ManageUsersHeaders,
     conn: &mut DbConn,
 ) -> EmptyResult {
     let Some(member) = Membership::find_by_uuid_and_org(member_id, org_id, conn).await else {
@@ -1290,13 +1609,15 @@ async fn _reinvite_member(
         err!("Invitations are not allowed.")
     }
 
-    let org_name = match Organization::find_by_uuid(org_id, conn).await {
-        Some(org) => org.name,
-        None => err!("Error looking up organization."),
+    check_report_invite_gate(org_id, headers.membership_type == MembershipType::Owner, conn).await?;
+
+    let Some(org) = Organization::find_by_uuid(org_id, conn).await else {
+        err!("Error looking up organization.")
     };
 
     if CONFIG.mail_enabled() {
-        mail::send_invite(&user, org_id.clone(), member.uuid, &org_name, Some(invited_by_email.to_string())).await?;
+        let invited_by_email = Some(headers.user.email.clone());
+        mail::send_invite(&user, org_id.clone(), member.uuid, &org.name, invited_by_email, Some(&org)).await?;
     } else if user.password_hash.is_empty() {
         let invitation = Invitation::new(&user.email);
         invitation.save(conn).await?;
@@ -1389,7 +1710,7 @@ struct BulkConfirmData {
 async fn bulk_confirm_invite(
     org_id: OrganizationId,
     data: Json<BulkConfirmData>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
     nt: Notify<'_>,
 ) -> JsonResult {
@@ -1433,7 +1754,7 @@ async fn confirm_invite(
     org_id: OrganizationId,
     member_id: MembershipId,
     data: Json<ConfirmData>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
     nt: Notify<'_>,
 ) -> EmptyResult {
@@ -1469,20 +1790,28 @@ async fn _confirm_invite(
         err!("User in invalid state")
     }
 
+    // The member already takes a seat, but the seat limit could have been lowered after the invitation
+    if let Some(org) = Organization::find_by_uuid(org_id, conn).await {
+        org.check_available_seats(0, conn).await?;
+    }
+
     // This check is also done at accept_invite, _confirm_invite, _activate_member, edit_member, admin::update_membership_type
     // It returns different error messages per function.
     if member_to_confirm.atype < MembershipType::Admin {
-        match OrgPolicy::is_user_allowed(&member_to_confirm.user_uuid, org_id, true, conn).await {
-            Ok(_) => {}
-            Err(OrgPolicyErr::TwoFactorMissing) => {
-                if CONFIG.email_2fa_auto_fallback() {
-                    two_factor::email::find_and_activate_email_2fa(&member_to_confirm.user_uuid, conn).await?;
-                } else {
-                    err!("You cannot confirm this user because they have not setup 2FA");
-                }
-            }
+        match check_user_allowed(
+            &member_to_confirm.user_uuid,
+            org_id,
+            true,
+            "Confirming the member",
+            &headers.user.uuid,
+            conn,
+        )
+        .await?
+        {
+            Ok(()) => {}
+            Err(OrgPolicyErr::TwoFactorMissing) => err!("You cannot confirm this user because they have not setup 2FA"),
             Err(OrgPolicyErr::SingleOrgEnforced) => {
-                err!("You cannot confirm this user because they are a member of an organization which forbids it");
+                err!("You cannot confirm this user because they are a member of an organization which forbids it")
             }
         }
     }
@@ -1515,7 +1844,15 @@ async fn _confirm_invite(
 
     let save_result = member_to_confirm.save(conn).await;
 
-    if let Some(user) = User::find_by_uuid(&member_to_confirm.user_uuid, conn).await {
+    if let Some(mut user) = User::find_by_uuid(&member_to_confirm.user_uuid, conn).await {
+        // A hint stored before joining isn't allowed anymore when the organization disables them
+        if save_result.is_ok()
+            && OrgPolicy::find_by_org_and_type(org_id, OrgPolicyType::DisablePasswordHints, conn)
+                .await
+                .is_some_and(|p| p.enabled)
+        {
+            user.clear_password_hint(conn).await?;
+        }
         nt.send_user_update(UpdateType::SyncOrgKeys, &user, &headers.device.push_uuid, conn).await;
     }
 
@@ -1548,7 +1885,7 @@ async fn get_user(
     org_id: OrganizationId,
     member_id: MembershipId,
     data: GetOrgUserData,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -1581,7 +1918,7 @@ async fn put_member(
     org_id: OrganizationId,
     member_id: MembershipId,
     data: Json<EditUserData>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     conn: DbConn,
 ) -> EmptyResult {
     edit_member(org_id, member_id, data, headers, conn).await
@@ -1592,7 +1929,7 @@ async fn edit_member(
     org_id: OrganizationId,
     member_id: MembershipId,
     data: Json<EditUserData>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     if org_id != headers.org_id {
@@ -1608,14 +1945,18 @@ async fn edit_member(
         err!("Invalid type")
     };
 
+    // The Custom role is stored as a manager with the granted permissions as a bit mask
+    let permissions = if raw_type.eq("4") {
+        OrgPermission::from_json(&data.permissions)
+    } else {
+        0
+    };
+
     // HACK: This converts the Custom role which has the `Manage all collections` box checked into an access_all flag
     // Since the parent checkbox is not sent to the server we need to check and verify the child checkboxes
     // If the box is not checked, the user will still be a manager, but not with the access_all permission
     let access_all = new_type >= MembershipType::Admin
-        || (raw_type.eq("4")
-            && data.permissions.get("editAnyCollection") == Some(&json!(true))
-            && data.permissions.get("deleteAnyCollection") == Some(&json!(true))
-            && data.permissions.get("createNewCollections") == Some(&json!(true)));
+        || permissions & OrgPermission::ALL_COLLECTIONS == OrgPermission::ALL_COLLECTIONS;
 
     let mut member_to_edit = match Membership::find_by_uuid_and_org(&member_id, &org_id, &mut conn).await {
         Some(member) => member,
@@ -1633,6 +1974,13 @@ async fn edit_member(
         err!("Only Owners can edit Owner users")
     }
 
+    // Custom members with the manage users permission can not grant any permissions
+    if headers.membership_type < MembershipType::Admin
+        && (member_to_edit.atype != MembershipType::User || new_type != MembershipType::User)
+    {
+        err!("Only Owners and Admins can edit Custom, Admin or Owner users")
+    }
+
     if member_to_edit.atype == MembershipType::Owner
         && new_type != MembershipType::Owner
         && member_to_edit.status == MembershipStatus::Confirmed as i32
@@ -1646,23 +1994,30 @@ async fn edit_member(
     // This check is also done at accept_invite, _confirm_invite, _activate_member, edit_member, admin::update_membership_type
     // It returns different error messages per function.
     if new_type < MembershipType::Admin {
-        match OrgPolicy::is_user_allowed(&member_to_edit.user_uuid, &org_id, true, &mut conn).await {
-            Ok(_) => {}
+        match check_user_allowed(
+            &member_to_edit.user_uuid,
+            &org_id,
+            true,
+            "Changing the type of the member",
+            &headers.user.uuid,
+            &mut conn,
+        )
+        .await?
+        {
+            Ok(()) => {}
             Err(OrgPolicyErr::TwoFactorMissing) => {
-                if CONFIG.email_2fa_auto_fallback() {
-                    two_factor::email::find_and_activate_email_2fa(&member_to_edit.user_uuid, &mut conn).await?;
-                } else {
-                    err!("You cannot modify this user to this type because they have not setup 2FA");
-                }
+                err!("You cannot modify this user to this type because they have not setup 2FA")
             }
             Err(OrgPolicyErr::SingleOrgEnforced) => {
-                err!("You cannot modify this user to this type because they are a member of an organization which forbids it");
+                err!("You cannot modify this user to this type because they are a member of an organization which forbids it")
             }
         }
     }
 
+    let old_type = member_to_edit.atype;
     member_to_edit.access_all = access_all;
     member_to_edit.atype = new_type as i32;
+    member_to_edit.permissions = permissions;
 
     // Delete all the odd collections
     for c in CollectionUser::find_by_organization_and_user_uuid(&org_id, &member_to_edit.user_uuid, &mut conn).await {
@@ -1707,14 +2062,18 @@ async fn edit_member(
     )
     .await;
 
-    member_to_edit.save(&mut conn).await
+    member_to_edit.save(&mut conn).await?;
+
+    notify_role_escalation(&member_to_edit, old_type, &headers.user.email, Some(&headers.user.uuid), &mut conn).await;
+
+    Ok(())
 }
 
 #[delete("/organizations/<org_id>/users", data = "<data>")]
 async fn bulk_delete_member(
     org_id: OrganizationId,
     data: Json<BulkMembershipIds>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
     nt: Notify<'_>,
 ) -> JsonResult {
@@ -1750,7 +2109,7 @@ async fn bulk_delete_member(
 async fn delete_member(
     org_id: OrganizationId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
     nt: Notify<'_>,
 ) -> EmptyResult {
@@ -1761,7 +2120,7 @@ async fn delete_member(
 async fn post_delete_member(
     org_id: OrganizationId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
     nt: Notify<'_>,
 ) -> EmptyResult {
@@ -1816,7 +2175,7 @@ async fn _delete_member(
 async fn bulk_public_keys(
     org_id: OrganizationId,
     data: Json<BulkMembershipIds>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -1852,8 +2211,8 @@ async fn bulk_public_keys(
     })))
 }
 
-use super::ciphers::update_cipher_from_data;
 use super::ciphers::CipherData;
+use super::imports::{import_ciphers, run_import_job, start_import_job};
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -1879,8 +2238,9 @@ async fn post_org_import(
     data: Json<ImportData>,
     headers: OrgMemberHeaders,
     mut conn: DbConn,
+    pool: &State<DbPool>,
     nt: Notify<'_>,
-) -> EmptyResult {
+) -> JsonResult {
     let org_id = query.organization_id;
     if org_id != headers.membership.org_uuid {
         err!("Organization not found", "Organization id's do not match");
@@ -1929,34 +2289,44 @@ async fn post_org_import(
 
     let headers: Headers = headers.into();
 
-    let mut ciphers: Vec<CipherId> = Vec::with_capacity(data.ciphers.len());
-    for mut cipher_data in data.ciphers {
+    let mut ciphers = data.ciphers;
+    for cipher_data in ciphers.iter_mut() {
         // Always clear folder_id's via an organization import
         cipher_data.folder_id = None;
-        let mut cipher = Cipher::new(cipher_data.r#type, cipher_data.name.clone());
-        update_cipher_from_data(
-            &mut cipher,
-            cipher_data,
-            &headers,
-            Some(collections.clone()),
-            &mut conn,
-            &nt,
-            UpdateType::None,
-        )
-        .await
-        .ok();
-        ciphers.push(cipher.uuid);
     }
+    // The ciphers are created in chunks with their own connections
+    drop(conn);
+
+    // Read and create the ciphers, large imports continue in the background
+    let total = ciphers.len();
+    let job_id = start_import_job(&headers.user.uuid, Some(&org_id), total);
+    let pool = pool.inner().clone();
+    let ws_users = nt.inner().clone();
+    let import_job_id = job_id.clone();
+    run_import_job(&job_id, total, async move {
+        let ciphers =
+            import_ciphers(&import_job_id, ciphers, &headers, Some(collections.clone()), &pool, &ws_users).await?;
+
+        let mut conn = pool.get().await?;
+        // Assign the collections
+        for (cipher_index, col_index) in relations {
+            let cipher_id = &ciphers[cipher_index];
+            let col_id = &collections[col_index];
+            CollectionCipher::save(cipher_id, col_id, &mut conn).await?;
+        }
 
-    // Assign the collections
-    for (cipher_index, col_index) in relations {
-        let cipher_id = &ciphers[cipher_index];
-        let col_id = &collections[col_index];
-        CollectionCipher::save(cipher_id, col_id, &mut conn).await?;
-    }
+        let mut user = headers.user;
+        user.update_revision(&mut conn).await?;
+        let nt: Notify<'_> = (&ws_users).into();
+        nt.send_user_update(UpdateType::SyncVault, &user, &headers.device.push_uuid, &mut conn).await;
+        Ok(())
+    })
+    .await?;
 
-    let mut user = headers.user;
-    user.update_revision(&mut conn).await
+    Ok(Json(json!({
+        "id": job_id,
+        "object": "importJob",
+    })))
 }
 
 #[derive(Deserialize)]
@@ -2022,7 +2392,7 @@ async fn post_bulk_collections(data: Json<BulkCollectionsData>, headers: Headers
 }
 
 #[get("/organizations/<org_id>/policies")]
-async fn list_policies(org_id: OrganizationId, headers: AdminHeaders, mut conn: DbConn) -> JsonResult {
+async fn list_policies(org_id: OrganizationId, headers: ManagePoliciesHeaders, mut conn: DbConn) -> JsonResult {
     if org_id != headers.org_id {
         err!("Organization not found", "Organization id's do not match");
     }
@@ -2078,7 +2448,12 @@ async fn get_master_password_policy(org_id: OrganizationId, _headers: Headers, m
 }
 
 #[get("/organizations/<org_id>/policies/<pol_type>", rank = 2)]
-async fn get_policy(org_id: OrganizationId, pol_type: i32, headers: AdminHeaders, mut conn: DbConn) -> JsonResult {
+async fn get_policy(
+    org_id: OrganizationId,
+    pol_type: i32,
+    headers: ManagePoliciesHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
     if org_id != headers.org_id {
         err!("Organization not found", "Organization id's do not match");
     }
@@ -2108,7 +2483,7 @@ async fn put_policy(
     org_id: OrganizationId,
     pol_type: i32,
     data: Json<PolicyData>,
-    headers: AdminHeaders,
+    headers: ManagePoliciesHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -2152,6 +2527,109 @@ async fn put_policy(
         }
     }
 
+    // Like Bitwarden, the Key Connector requires the Single Org policy, and can't be turned off while members use it
+    if pol_type_enum == OrgPolicyType::KeyConnector {
+        if data.enabled {
+            if !CONFIG.sso_key_connector_enabled() {
+                err!("Key Connector is not enabled on this server")
+            }
+            if !OrgPolicy::find_by_org_and_type(&org_id, OrgPolicyType::SingleOrg, &mut conn)
+                .await
+                .is_some_and(|p| p.enabled)
+            {
+                err!("Single Organization policy is not enabled. It is mandatory for this policy to be enabled.")
+            }
+        } else {
+            for member in Membership::find_by_org(&org_id, &mut conn).await {
+                if User::find_by_uuid(&member.user_uuid, &mut conn).await.is_some_and(|u| u.uses_key_connector) {
+                    err!("Members of this organization use Key Connector. It is not allowed to disable this policy.")
+                }
+            }
+        }
+    }
+    if pol_type_enum == OrgPolicyType::SingleOrg
+        && !data.enabled
+        && OrgPolicy::find_by_org_and_type(&org_id, OrgPolicyType::KeyConnector, &mut conn)
+            .await
+            .is_some_and(|p| p.enabled)
+    {
+        err!("Key Connector policy is enabled. It is not allowed to disable this policy.")
+    }
+
+    if pol_type_enum == OrgPolicyType::PasswordGenerator && data.enabled {
+        let generator_data = data.data.clone().unwrap_or_else(|| json!({}));
+        let Ok(generator_data) = serde_json::from_value::<PasswordGeneratorPolicyData>(generator_data) else {
+            err!("Invalid password generator policy options")
+        };
+        generator_data.validate()?;
+    }
+    if pol_type_enum == OrgPolicyType::MasterPassword && data.enabled {
+        let policy_data = data.data.clone().unwrap_or_else(|| json!({}));
+        let Ok(policy_data) = serde_json::from_value::<MasterPasswordPolicy>(policy_data) else {
+            err!("Invalid master password policy options")
+        };
+        policy_data.validate()?;
+    }
+    if pol_type_enum == OrgPolicyType::CipherHistory && data.enabled {
+        let policy_data = data.data.clone().unwrap_or_else(|| json!({}));
+        let Ok(policy_data) = serde_json::from_value::<CipherHistoryPolicyData>(policy_data) else {
+            err!("Invalid item history policy options")
+        };
+        policy_data.validate()?;
+    }
+    if pol_type_enum == OrgPolicyType::VerifiedEmail && data.enabled {
+        let policy_data = data.data.clone().unwrap_or_else(|| json!({}));
+        let Ok(policy_data) = serde_json::from_value::<VerifiedEmailPolicyData>(policy_data) else {
+            err!("Invalid verified email policy options")
+        };
+        policy_data.validate()?;
+    }
+    if pol_type_enum == OrgPolicyType::SessionLifetime && data.enabled {
+        let policy_data = data.data.clone().unwrap_or_else(|| json!({}));
+        let Ok(policy_data) = serde_json::from_value::<SessionLifetimePolicyData>(policy_data) else {
+            err!("Invalid session lifetime policy options")
+        };
+        policy_data.validate()?;
+    }
+    if pol_type_enum == OrgPolicyType::OrgSends && data.enabled {
+        let policy_data = data.data.clone().unwrap_or_else(|| json!({}));
+        let Ok(policy_data) = serde_json::from_value::<OrgSendsPolicyData>(policy_data) else {
+            err!("Invalid organization Sends policy options")
+        };
+        policy_data.validate()?;
+    }
+    if pol_type_enum == OrgPolicyType::PersonalOwnership && data.enabled {
+        let policy_data = data.data.clone().unwrap_or_else(|| json!({}));
+        let Ok(policy_data) = serde_json::from_value::<PersonalOwnershipPolicyData>(policy_data) else {
+            err!("Invalid personal ownership policy options")
+        };
+        if let Some(collection_id) = &policy_data.migration_collection_id {
+            if Collection::find_by_uuid_and_org(collection_id, &org_id, &mut conn).await.is_none() {
+                err!("The migration collection doesn't belong to the organization")
+            }
+        }
+    }
+    // Requiring SSO applies to the members with an email address of a verified domain, it needs the Single Org policy
+    if pol_type_enum == OrgPolicyType::RequireSso && data.enabled {
+        if !CONFIG.sso_enabled() {
+            err!("SSO is not enabled on this server")
+        }
+        if !OrgPolicy::find_by_org_and_type(&org_id, OrgPolicyType::SingleOrg, &mut conn)
+            .await
+            .is_some_and(|p| p.enabled)
+        {
+            err!("Single Organization policy is not enabled. It is mandatory for this policy to be enabled.")
+        }
+    }
+    if pol_type_enum == OrgPolicyType::SingleOrg
+        && !data.enabled
+        && OrgPolicy::find_by_org_and_type(&org_id, OrgPolicyType::RequireSso, &mut conn)
+            .await
+            .is_some_and(|p| p.enabled)
+    {
+        err!("Require single sign-on authentication policy is enabled. It is not allowed to disable this policy.")
+    }
+
     // When enabling the TwoFactorAuthentication policy, revoke all members that do not have 2FA
     if pol_type_enum == OrgPolicyType::TwoFactorAuthentication && data.enabled {
         two_factor::enforce_2fa_policy_for_org(
@@ -2192,16 +2670,36 @@ async fn put_policy(
                     &mut conn,
                 )
                 .await;
+                record_policy_violation(
+                    &org_id,
+                    &member.user_uuid,
+                    OrgPolicyType::SingleOrg,
+                    PolicyViolationAction::Removed,
+                    "The single organization policy was enabled, the user is also a member of another organization",
+                    &headers.user.uuid,
+                    &mut conn,
+                )
+                .await;
 
                 member.delete(&mut conn).await?;
             }
         }
     }
 
+    // When enabling the DisablePasswordHints policy, remove the hints the confirmed members already stored
+    if pol_type_enum == OrgPolicyType::DisablePasswordHints && data.enabled {
+        for member in Membership::find_confirmed_by_org(&org_id, &mut conn).await {
+            if let Some(mut user) = User::find_by_uuid(&member.user_uuid, &mut conn).await {
+                user.clear_password_hint(&mut conn).await?;
+            }
+        }
+    }
+
     let mut policy = match OrgPolicy::find_by_org_and_type(&org_id, pol_type_enum, &mut conn).await {
         Some(p) => p,
         None => OrgPolicy::new(org_id.clone(), pol_type_enum, false, "{}".to_string()),
     };
+    let disabled = policy.enabled && !data.enabled;
 
     policy.enabled = data.enabled;
     policy.data = serde_json::to_string(&data.data)?;
@@ -2218,6 +2716,18 @@ async fn put_policy(
     )
     .await;
 
+    if disabled {
+        notify_sensitive_action(
+            &org_id,
+            SensitiveAction::PolicyDisabled,
+            &format!("The {pol_type_enum:?} policy was disabled"),
+            &headers.user.email,
+            Some(&headers.user.uuid),
+            &mut conn,
+        )
+        .await;
+    }
+
     Ok(Json(policy.to_json()))
 }
 
@@ -2270,10 +2780,27 @@ fn get_plans_tax_rates(_headers: Headers) -> Json<Value> {
     Json(_empty_data_json())
 }
 
-#[get("/organizations/<_org_id>/billing/metadata")]
-fn get_billing_metadata(_org_id: OrganizationId, _headers: Headers) -> Json<Value> {
-    // Prevent a 404 error, which also causes Javascript errors.
-    Json(_empty_data_json())
+#[get("/organizations/<org_id>/billing/metadata")]
+async fn get_billing_metadata(org_id: OrganizationId, headers: Headers, mut conn: DbConn) -> Json<Value> {
+    // The occupied seats are shown together with the `seats` of the organization
+    if Membership::find_confirmed_by_user_and_org(&headers.user.uuid, &org_id, &mut conn).await.is_none() {
+        // Prevent a 404 error, which also causes Javascript errors.
+        return Json(_empty_data_json());
+    }
+
+    Json(json!({
+        "isEligibleForSelfHost": true,
+        "isManaged": false,
+        "isOnSecretsManagerStandalone": false,
+        "isSubscriptionUnpaid": false,
+        "hasSubscription": false,
+        "hasOpenInvoice": false,
+        "isSubscriptionCanceled": false,
+        "invoiceDueDate": null,
+        "invoiceCreatedDate": null,
+        "subPeriodEndDate": null,
+        "organizationOccupiedSeats": Membership::count_seats_by_org(&org_id, &mut conn).await,
+    }))
 }
 
 fn _empty_data_json() -> Value {
@@ -2326,8 +2853,8 @@ async fn import(org_id: OrganizationId, data: Json<OrgImportData>, headers: Head
     // as opposed to upstream which only removes auto-imported users.
 
     // User needs to be admin or owner to use the Directory Connector
-    match Membership::find_by_user_and_org(&headers.user.uuid, &org_id, &mut conn).await {
-        Some(member) if member.atype >= MembershipType::Admin => { /* Okay, nothing to do */ }
+    let is_owner = match Membership::find_by_user_and_org(&headers.user.uuid, &org_id, &mut conn).await {
+        Some(member) if member.atype >= MembershipType::Admin => member.atype == MembershipType::Owner,
         Some(_) => err!("User has insufficient permissions to use Directory Connector"),
         None => err!("User not part of organization"),
     };
@@ -2359,6 +2886,11 @@ async fn import(org_id: OrganizationId, data: Json<OrgImportData>, headers: Head
                     MembershipStatus::Accepted as i32 // Automatically mark user as accepted if no email invites
                 };
 
+                check_report_invite_gate(&org_id, is_owner, &mut conn).await?;
+                if let Some(org) = Organization::find_by_uuid(&org_id, &mut conn).await {
+                    org.check_available_seats(1, &mut conn).await?;
+                }
+
                 let mut new_member =
                     Membership::new(user.uuid.clone(), org_id.clone(), Some(headers.user.email.clone()));
                 new_member.access_all = false;
@@ -2366,17 +2898,17 @@ async fn import(org_id: OrganizationId, data: Json<OrgImportData>, headers: Head
                 new_member.status = member_status;
 
                 if CONFIG.mail_enabled() {
-                    let org_name = match Organization::find_by_uuid(&org_id, &mut conn).await {
-                        Some(org) => org.name,
-                        None => err!("Error looking up organization"),
+                    let Some(org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+                        err!("Error looking up organization")
                     };
 
                     mail::send_invite(
                         &user,
                         org_id.clone(),
                         new_member.uuid.clone(),
-                        &org_name,
+                        &org.name,
                         Some(headers.user.email.clone()),
+                        Some(&org),
                     )
                     .await?;
                 }
@@ -2429,7 +2961,7 @@ async fn import(org_id: OrganizationId, data: Json<OrgImportData>, headers: Head
 async fn deactivate_member(
     org_id: OrganizationId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     _revoke_member(&org_id, &member_id, &headers, &mut conn).await
@@ -2446,7 +2978,7 @@ struct BulkRevokeMembershipIds {
 async fn bulk_deactivate_members(
     org_id: OrganizationId,
     data: Json<BulkRevokeMembershipIds>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     conn: DbConn,
 ) -> JsonResult {
     bulk_revoke_members(org_id, data, headers, conn).await
@@ -2456,7 +2988,7 @@ async fn bulk_deactivate_members(
 async fn revoke_member(
     org_id: OrganizationId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     _revoke_member(&org_id, &member_id, &headers, &mut conn).await
@@ -2466,7 +2998,7 @@ async fn revoke_member(
 async fn bulk_revoke_members(
     org_id: OrganizationId,
     data: Json<BulkRevokeMembershipIds>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -2519,6 +3051,9 @@ async fn _revoke_member(
             if member.atype == MembershipType::Owner && headers.membership_type != MembershipType::Owner {
                 err!("Only owners can revoke other owners")
             }
+            if member.atype != MembershipType::User && headers.membership_type < MembershipType::Admin {
+                err!("Only owners and admins can revoke Custom or Admin users")
+            }
             if member.atype == MembershipType::Owner
                 && Membership::count_confirmed_by_org_and_type(org_id, MembershipType::Owner, conn).await <= 1
             {
@@ -2545,12 +3080,188 @@ async fn _revoke_member(
     Ok(())
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OffboardMemberData {
+    // Gets the manage rights of the collections the member could manage
+    new_manager_id: MembershipId,
+    #[serde(default)]
+    notify_owners: bool,
+}
+
+/// Offboards a member in one call: revokes the membership, hands the collections the member could manage over
+/// to another member, removes the member from all groups, and optionally mails a summary to the owners.
+/// Members below Admin can only hand over collections they can manage themselves.
+#[post("/organizations/<org_id>/users/<member_id>/offboard", data = "<data>")]
+async fn offboard_member(
+    org_id: OrganizationId,
+    member_id: MembershipId,
+    data: Json<OffboardMemberData>,
+    headers: ManageUsersHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let data = data.into_inner();
+
+    let Some(member) = Membership::find_by_uuid_and_org(&member_id, &org_id, &mut conn).await else {
+        err!("User not found in organization")
+    };
+    // The same checks as for revoking, they also apply when the member already is revoked
+    if member.user_uuid == headers.user.uuid {
+        err!("You cannot offboard yourself")
+    }
+    if member.atype == MembershipType::Owner && headers.membership_type != MembershipType::Owner {
+        err!("Only owners can offboard other owners")
+    }
+    if member.atype != MembershipType::User && headers.membership_type < MembershipType::Admin {
+        err!("Only owners and admins can offboard Custom or Admin users")
+    }
+
+    let Some(new_manager) = Membership::find_by_uuid_and_org(&data.new_manager_id, &org_id, &mut conn)
+        .await
+        .filter(|m| m.uuid != member.uuid && m.status == MembershipStatus::Confirmed as i32)
+    else {
+        err!("The new manager has to be another confirmed member of the organization")
+    };
+
+    // The collections the member could manage, directly or through one of their groups
+    let direct: Vec<CollectionUser> =
+        CollectionUser::find_by_organization_and_user_uuid(&org_id, &member.user_uuid, &mut conn)
+            .await
+            .into_iter()
+            .filter(|col_user| col_user.manage)
+            .collect();
+    let groups: Vec<GroupId> =
+        GroupUser::find_by_member(&member.uuid, &mut conn).await.into_iter().map(|gu| gu.groups_uuid).collect();
+    let mut collections: Vec<CollectionId> = direct.iter().map(|col_user| col_user.collection_uuid.clone()).collect();
+    if CONFIG.org_groups_enabled() {
+        for group_id in &groups {
+            for col_group in CollectionGroup::find_by_group(group_id, &mut conn).await {
+                if col_group.manage && !collections.contains(&col_group.collections_uuid) {
+                    collections.push(col_group.collections_uuid);
+                }
+            }
+        }
+    }
+
+    // Otherwise a member with the manage users permission could hand themselves collections they can't manage
+    if headers.membership_type < MembershipType::Admin {
+        let Some(caller) = Membership::find_by_user_and_org(&headers.user.uuid, &org_id, &mut conn).await else {
+            err!("User not found in organization")
+        };
+        for col_id in &collections {
+            if !Collection::can_manage_collection(&caller, col_id, &mut conn).await {
+                err!("You can only hand over the collections you can manage yourself")
+            }
+        }
+    }
+
+    let revoked = member.status > MembershipStatus::Revoked as i32;
+    begin_transaction(&mut conn).await?;
+    let result: EmptyResult = async {
+        // 1. Revoke the membership, unless it already is
+        if revoked {
+            _revoke_member(&org_id, &member_id, &headers, &mut conn).await?;
+        }
+
+        // 2. Move the manage rights of the member's collections to the new manager, keeping the other assignments
+        for col_id in &collections {
+            CollectionUser::save(&new_manager.user_uuid, col_id, false, false, true, &mut conn).await?;
+        }
+        for col_user in direct {
+            col_user.delete(&mut conn).await?;
+        }
+
+        // 3. Remove the member from all groups
+        if !groups.is_empty() {
+            GroupUser::delete_all_by_member(&member.uuid, &mut conn).await?;
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        if let Err(rollback_err) = rollback_transaction(&mut conn).await {
+            error!("Error rolling back the offboarding of member {member_id}: {rollback_err:#?}");
+        }
+        return Err(e);
+    }
+    commit_transaction(&mut conn).await?;
+
+    for col_id in &collections {
+        log_event(
+            EventType::CollectionUpdated as i32,
+            col_id,
+            &org_id,
+            &headers.user.uuid,
+            headers.device.atype,
+            &headers.ip.ip,
+            &mut conn,
+        )
+        .await;
+    }
+    if !groups.is_empty() {
+        log_event(
+            EventType::OrganizationUserUpdatedGroups as i32,
+            &member.uuid,
+            &org_id,
+            &headers.user.uuid,
+            headers.device.atype,
+            &headers.ip.ip,
+            &mut conn,
+        )
+        .await;
+    }
+
+    // 4. Mail the summary to the owners
+    let mut owners_notified = 0;
+    if data.notify_owners && CONFIG.mail_enabled() {
+        let org_name = Organization::find_by_uuid(&org_id, &mut conn).await.map(|org| org.name).unwrap_or_default();
+        let member_email = User::find_by_uuid(&member.user_uuid, &mut conn).await.map(|u| u.email).unwrap_or_default();
+        let new_manager_email =
+            User::find_by_uuid(&new_manager.user_uuid, &mut conn).await.map(|u| u.email).unwrap_or_default();
+
+        for owner in Membership::find_by_org_and_type(&org_id, MembershipType::Owner, &mut conn).await {
+            if !owner.has_status(MembershipStatus::Confirmed) {
+                continue;
+            }
+            let Some(user) = User::find_by_uuid(&owner.user_uuid, &mut conn).await else {
+                continue;
+            };
+            match mail::send_member_offboarded(
+                &user.email,
+                &org_name,
+                &member_email,
+                &headers.user.email,
+                &new_manager_email,
+                (revoked, collections.len(), groups.len()),
+            )
+            .await
+            {
+                Ok(()) => owners_notified += 1,
+                Err(e) => error!("Error sending offboarding summary to {}: {e:#?}", user.email),
+            }
+        }
+    }
+
+    Ok(Json(json!({
+        "id": member.uuid,
+        "revoked": revoked,
+        "newManagerId": new_manager.uuid,
+        "collectionIds": collections,
+        "groupIds": groups,
+        "ownersNotified": owners_notified,
+        "object": "organizationUserOffboard",
+    })))
+}
+
 // Pre web-vault v2022.9.x endpoint
 #[put("/organizations/<org_id>/users/<member_id>/activate")]
 async fn activate_member(
     org_id: OrganizationId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     _restore_member(&org_id, &member_id, &headers, &mut conn).await
@@ -2561,7 +3272,7 @@ async fn activate_member(
 async fn bulk_activate_members(
     org_id: OrganizationId,
     data: Json<BulkMembershipIds>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     conn: DbConn,
 ) -> JsonResult {
     bulk_restore_members(org_id, data, headers, conn).await
@@ -2571,7 +3282,7 @@ async fn bulk_activate_members(
 async fn restore_member(
     org_id: OrganizationId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     _restore_member(&org_id, &member_id, &headers, &mut conn).await
@@ -2581,7 +3292,7 @@ async fn restore_member(
 async fn bulk_restore_members(
     org_id: OrganizationId,
     data: Json<BulkMembershipIds>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -2629,22 +3340,33 @@ async fn _restore_member(
             if member.atype == MembershipType::Owner && headers.membership_type != MembershipType::Owner {
                 err!("Only owners can restore other owners")
             }
+            if member.atype != MembershipType::User && headers.membership_type < MembershipType::Admin {
+                err!("Only owners and admins can restore Custom or Admin users")
+            }
+            if let Some(org) = Organization::find_by_uuid(org_id, conn).await {
+                org.check_available_seats(1, conn).await?;
+            }
 
             // This check is also done at accept_invite, _confirm_invite, _activate_member, edit_member, admin::update_membership_type
             // It returns different error messages per function.
             if member.atype < MembershipType::Admin {
-                match OrgPolicy::is_user_allowed(&member.user_uuid, org_id, false, conn).await {
-                    Ok(_) => {}
+                match check_user_allowed(
+                    &member.user_uuid,
+                    org_id,
+                    false,
+                    "Restoring the member",
+                    &headers.user.uuid,
+                    conn,
+                )
+                .await?
+                {
+                    Ok(()) => {}
                     Err(OrgPolicyErr::TwoFactorMissing) => {
-                        if CONFIG.email_2fa_auto_fallback() {
-                            two_factor::email::find_and_activate_email_2fa(&member.user_uuid, conn).await?;
-                        } else {
-                            err!("You cannot restore this user because they have not setup 2FA");
-                        }
-                    }
-                    Err(OrgPolicyErr::SingleOrgEnforced) => {
-                        err!("You cannot restore this user because they are a member of an organization which forbids it");
+                        err!("You cannot restore this user because they have not setup 2FA")
                     }
+                    Err(OrgPolicyErr::SingleOrgEnforced) => err!(
+                        "You cannot restore this user because they are a member of an organization which forbids it"
+                    ),
                 }
             }
 
@@ -2760,7 +3482,7 @@ async fn post_group(
     org_id: OrganizationId,
     group_id: GroupId,
     data: Json<GroupRequest>,
-    headers: AdminHeaders,
+    headers: ManageGroupsHeaders,
     conn: DbConn,
 ) -> JsonResult {
     put_group(org_id, group_id, data, headers, conn).await
@@ -2769,7 +3491,7 @@ async fn post_group(
 #[post("/organizations/<org_id>/groups", data = "<data>")]
 async fn post_groups(
     org_id: OrganizationId,
-    headers: AdminHeaders,
+    headers: ManageGroupsHeaders,
     data: Json<GroupRequest>,
     mut conn: DbConn,
 ) -> JsonResult {
@@ -2802,7 +3524,7 @@ async fn put_group(
     org_id: OrganizationId,
     group_id: GroupId,
     data: Json<GroupRequest>,
-    headers: AdminHeaders,
+    headers: ManageGroupsHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -2881,7 +3603,7 @@ async fn add_update_group(
 async fn get_group_details(
     org_id: OrganizationId,
     group_id: GroupId,
-    headers: AdminHeaders,
+    headers: ManageGroupsHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -2902,7 +3624,7 @@ async fn get_group_details(
 async fn post_delete_group(
     org_id: OrganizationId,
     group_id: GroupId,
-    headers: AdminHeaders,
+    headers: ManageGroupsHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     _delete_group(&org_id, &group_id, &headers, &mut conn).await
@@ -2912,7 +3634,7 @@ async fn post_delete_group(
 async fn delete_group(
     org_id: OrganizationId,
     group_id: GroupId,
-    headers: AdminHeaders,
+    headers: ManageGroupsHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     _delete_group(&org_id, &group_id, &headers, &mut conn).await
@@ -2953,7 +3675,7 @@ async fn _delete_group(
 async fn bulk_delete_groups(
     org_id: OrganizationId,
     data: Json<BulkGroupIds>,
-    headers: AdminHeaders,
+    headers: ManageGroupsHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     if org_id != headers.org_id {
@@ -2972,7 +3694,12 @@ async fn bulk_delete_groups(
 }
 
 #[get("/organizations/<org_id>/groups/<group_id>", rank = 2)]
-async fn get_group(org_id: OrganizationId, group_id: GroupId, headers: AdminHeaders, mut conn: DbConn) -> JsonResult {
+async fn get_group(
+    org_id: OrganizationId,
+    group_id: GroupId,
+    headers: ManageGroupsHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
     if org_id != headers.org_id {
         err!("Organization not found", "Organization id's do not match");
     }
@@ -2991,7 +3718,7 @@ async fn get_group(org_id: OrganizationId, group_id: GroupId, headers: AdminHead
 async fn get_group_members(
     org_id: OrganizationId,
     group_id: GroupId,
-    headers: AdminHeaders,
+    headers: ManageGroupsHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -3018,7 +3745,7 @@ async fn get_group_members(
 async fn put_group_members(
     org_id: OrganizationId,
     group_id: GroupId,
-    headers: AdminHeaders,
+    headers: ManageGroupsHeaders,
     data: Json<Vec<MembershipId>>,
     mut conn: DbConn,
 ) -> EmptyResult {
@@ -3059,7 +3786,7 @@ async fn put_group_members(
 async fn get_user_groups(
     org_id: OrganizationId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -3090,7 +3817,7 @@ async fn post_user_groups(
     org_id: OrganizationId,
     member_id: MembershipId,
     data: Json<OrganizationUserUpdateGroupsRequest>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     conn: DbConn,
 ) -> EmptyResult {
     put_user_groups(org_id, member_id, data, headers, conn).await
@@ -3101,7 +3828,7 @@ async fn put_user_groups(
     org_id: OrganizationId,
     member_id: MembershipId,
     data: Json<OrganizationUserUpdateGroupsRequest>,
-    headers: AdminHeaders,
+    headers: ManageUsersHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     if org_id != headers.org_id {
@@ -3142,7 +3869,7 @@ async fn post_delete_group_member(
     org_id: OrganizationId,
     group_id: GroupId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageGroupsHeaders,
     conn: DbConn,
 ) -> EmptyResult {
     delete_group_member(org_id, group_id, member_id, headers, conn).await
@@ -3153,7 +3880,7 @@ async fn delete_group_member(
     org_id: OrganizationId,
     group_id: GroupId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageGroupsHeaders,
     mut conn: DbConn,
 ) -> EmptyResult {
     if org_id != headers.org_id {
@@ -3233,7 +3960,7 @@ async fn get_organization_keys(org_id: OrganizationId, headers: OrgMemberHeaders
 async fn put_reset_password(
     org_id: OrganizationId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageResetPasswordHeaders,
     data: Json<OrganizationUserResetPasswordRequest>,
     mut conn: DbConn,
     nt: Notify<'_>,
@@ -3272,6 +3999,8 @@ async fn put_reset_password(
 
     let mut user = user;
     user.set_password(reset_request.new_master_password_hash.as_str(), Some(reset_request.key), true, None);
+    // The devices could otherwise keep refreshing their sessions, which is what the reset is meant to end
+    Device::delete_all_by_user(&user.uuid, &mut conn).await?;
     user.save(&mut conn).await?;
 
     nt.send_logout(&user, None, &mut conn).await;
@@ -3294,7 +4023,7 @@ async fn put_reset_password(
 async fn get_reset_password_details(
     org_id: OrganizationId,
     member_id: MembershipId,
-    headers: AdminHeaders,
+    headers: ManageResetPasswordHeaders,
     mut conn: DbConn,
 ) -> JsonResult {
     if org_id != headers.org_id {
@@ -3343,6 +4072,7 @@ async fn check_reset_password_applicable_and_permissions(
     match headers.membership_type {
         MembershipType::Owner => Ok(()),
         MembershipType::Admin if target_user.atype <= MembershipType::Admin => Ok(()),
+        MembershipType::Manager if target_user.atype == MembershipType::User => Ok(()),
         _ => err!("No permission to reset this user's password"),
     }
 }
@@ -3419,15 +4149,28 @@ async fn put_reset_password_enrollment(
 // Vaultwarden does not yet support exporting only managed collections!
 // https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Api/Tools/Controllers/OrganizationExportController.cs#L52
 #[get("/organizations/<org_id>/export")]
-async fn get_org_export(org_id: OrganizationId, headers: AdminHeaders, mut conn: DbConn) -> JsonResult {
+async fn get_org_export(org_id: OrganizationId, headers: AccessImportExportHeaders, mut conn: DbConn) -> JsonResult {
     if org_id != headers.org_id {
         err!("Organization not found", "Organization id's do not match");
     }
 
-    Ok(Json(json!({
+    let export = json!({
         "collections": convert_json_key_lcase_first(_get_org_collections(&org_id, &mut conn).await),
         "ciphers": convert_json_key_lcase_first(_get_org_details(&org_id, &headers.host, &headers.user.uuid, &mut conn).await?),
-    })))
+    });
+
+    // The OrganizationClientExportedVault event is logged by the clients themselves
+    notify_sensitive_action(
+        &org_id,
+        SensitiveAction::VaultExport,
+        &format!("The export was requested from {}", headers.ip.ip),
+        &headers.user.email,
+        Some(&headers.user.uuid),
+        &mut conn,
+    )
+    .await;
+
+    Ok(Json(export))
 }
 
 async fn _api_key(
@@ -0,0 +1,185 @@
+use rocket::{serde::json::Json, Route};
+
+use super::ciphers::{update_cipher_from_data, CipherData};
+use crate::{
+    api::{core::log_event, EmptyResult, JsonResult, Notify, UpdateType},
+    auth::{AdminHeaders, Headers},
+    db::{begin_transaction, commit_transaction, models::*, rollback_transaction, DbConn},
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![get_personal_ownership_migration, post_personal_ownership_migration, get_personal_ownership_pending]
+}
+
+/// Returns the data of the enabled personal ownership policy of the organization, `None` when it is disabled.
+async fn find_enabled_policy_data(org_id: &OrganizationId, conn: &mut DbConn) -> Option<PersonalOwnershipPolicyData> {
+    match OrgPolicy::find_by_org_and_type(org_id, OrgPolicyType::PersonalOwnership, conn).await {
+        Some(policy) if policy.enabled => Some(serde_json::from_str(&policy.data).unwrap_or_default()),
+        _ => None,
+    }
+}
+
+/// The members the policy applies to, like `OrgPolicy::is_applicable_to_user` these are the confirmed
+/// members below Admin.
+async fn check_policy_applies(org_id: &OrganizationId, user_id: &UserId, conn: &mut DbConn) -> EmptyResult {
+    match Membership::find_confirmed_by_user_and_org(user_id, org_id, conn).await {
+        Some(member) if member.atype < MembershipType::Admin => Ok(()),
+        Some(_) => err!("The personal ownership policy doesn't apply to the owners and admins"),
+        None => err!("Organization not found", "User is not a confirmed member of the organization"),
+    }
+}
+
+/// The personal items the member still has to move, and the collection they go to. Without a collection
+/// the member can only share or delete them, which the clients already offer.
+#[get("/organizations/<org_id>/policies/personal-ownership/migration")]
+async fn get_personal_ownership_migration(org_id: OrganizationId, headers: Headers, mut conn: DbConn) -> JsonResult {
+    check_policy_applies(&org_id, &headers.user.uuid, &mut conn).await?;
+    let Some(policy_data) = find_enabled_policy_data(&org_id, &mut conn).await else {
+        err!("The personal ownership policy is not enabled")
+    };
+
+    let cipher_ids: Vec<CipherId> =
+        Cipher::find_owned_by_user(&headers.user.uuid, &mut conn).await.into_iter().map(|c| c.uuid).collect();
+
+    Ok(Json(json!({
+        "collectionId": policy_data.migration_collection_id,
+        "pendingCount": cipher_ids.len(),
+        "cipherIds": cipher_ids,
+        "object": "personalOwnershipMigration",
+    })))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MigrationData {
+    // The personal items, encrypted with the key of the organization like a share
+    ciphers: Vec<CipherData>,
+}
+
+/// Moves personal items of the member into the collection of the policy. The server can't re-encrypt them,
+/// so the client sends them encrypted with the key of the organization, like the bulk share does. The member
+/// doesn't need write access to the collection, the policy decides where the items go.
+#[post("/organizations/<org_id>/policies/personal-ownership/migration", data = "<data>")]
+async fn post_personal_ownership_migration(
+    org_id: OrganizationId,
+    data: Json<MigrationData>,
+    headers: Headers,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> JsonResult {
+    check_policy_applies(&org_id, &headers.user.uuid, &mut conn).await?;
+    let Some(collection_id) =
+        find_enabled_policy_data(&org_id, &mut conn).await.and_then(|policy_data| policy_data.migration_collection_id)
+    else {
+        err!("The personal ownership policy has no collection to move the personal items to")
+    };
+    let Some(collection) = Collection::find_by_uuid_and_org(&collection_id, &org_id, &mut conn).await else {
+        err!("The collection of the personal ownership policy doesn't exist anymore")
+    };
+
+    let data = data.into_inner();
+    // Everything is checked first, so a bad item doesn't leave the vault half migrated
+    let mut ciphers = Vec::with_capacity(data.ciphers.len());
+    for cipher_data in data.ciphers {
+        let Some(cipher_id) = &cipher_data.id else {
+            err!("Request missing ids field")
+        };
+        let Some(cipher) = Cipher::find_by_uuid(cipher_id, &mut conn).await else {
+            err!("Cipher doesn't exist")
+        };
+        if cipher.organization_uuid.is_some() || cipher.user_uuid.as_ref() != Some(&headers.user.uuid) {
+            err!("Only your personal items can be moved", format!("Cipher {cipher_id} is not a personal item"))
+        }
+        if cipher_data.organization_id.as_ref() != Some(&org_id) {
+            err!("The items have to be encrypted for the organization of the policy")
+        }
+        ciphers.push((cipher, cipher_data));
+    }
+
+    let migrated = ciphers.len();
+    let mut migrated_ids = Vec::with_capacity(migrated);
+    // All the items move or none, a failure halfway would leave some of them in the organization
+    begin_transaction(&mut conn).await?;
+    let result: EmptyResult = async {
+        for (mut cipher, cipher_data) in ciphers {
+            let shared_to_collections = Some(vec![collection.uuid.clone()]);
+            update_cipher_from_data(
+                &mut cipher,
+                cipher_data,
+                &headers,
+                shared_to_collections,
+                &mut conn,
+                &nt,
+                UpdateType::None,
+            )
+            .await?;
+            // Only once the item belongs to the organization
+            CollectionCipher::save(&cipher.uuid, &collection.uuid, &mut conn).await?;
+            migrated_ids.push(cipher.uuid);
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        if let Err(rollback_err) = rollback_transaction(&mut conn).await {
+            error!("Error rolling back the personal ownership migration: {rollback_err:#?}");
+        }
+        return Err(e);
+    }
+    commit_transaction(&mut conn).await?;
+
+    for cipher_id in &migrated_ids {
+        log_event(
+            EventType::CipherShared as i32,
+            cipher_id,
+            &org_id,
+            &headers.user.uuid,
+            headers.device.atype,
+            &headers.ip.ip,
+            &mut conn,
+        )
+        .await;
+    }
+
+    // One sync for all the items, like the bulk share
+    if migrated > 0 {
+        nt.send_user_update(UpdateType::SyncCiphers, &headers.user, &headers.device.push_uuid, &mut conn).await;
+    }
+
+    Ok(Json(json!({
+        "migrated": migrated,
+        "object": "personalOwnershipMigration",
+    })))
+}
+
+/// The members the policy applies to who still have personal items, so the admins know who has to migrate.
+#[get("/organizations/<org_id>/policies/personal-ownership/pending")]
+async fn get_personal_ownership_pending(org_id: OrganizationId, headers: AdminHeaders, mut conn: DbConn) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+
+    let mut members_json = Vec::new();
+    for member in Membership::find_confirmed_by_org(&org_id, &mut conn).await {
+        if member.atype >= MembershipType::Admin {
+            continue;
+        }
+        let pending_count = Cipher::count_owned_by_user(&member.user_uuid, &mut conn).await;
+        if pending_count == 0 {
+            continue;
+        }
+        let email = User::find_by_uuid(&member.user_uuid, &mut conn).await.map(|user| user.email);
+        members_json.push(json!({
+            "id": member.uuid,
+            "userId": member.user_uuid,
+            "email": email,
+            "pendingCount": pending_count,
+        }));
+    }
+
+    Ok(Json(json!({
+        "data": members_json,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
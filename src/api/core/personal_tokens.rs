@@ -0,0 +1,133 @@
+//
+// Personal API tokens, which users create for their own scripts. A token is used as bearer token directly,
+// without the login flow, and only works on the `/personal` endpoints of its scopes.
+//
+use chrono::{TimeDelta, Utc};
+use rocket::{serde::json::Json, Route};
+use serde_json::Value;
+
+use super::{
+    check_org_ip_restrictions,
+    ciphers::sync_vault_json,
+    sends::{create_personal_text_send, SendData},
+};
+use crate::{
+    api::{EmptyResult, JsonResult, Notify, PasswordOrOtpData},
+    auth::{Headers, PersonalSendHeaders, PersonalSyncHeaders},
+    db::{models::*, DbConn},
+    CONFIG,
+};
+
+// Keeps the list manageable, scripts are expected to share a few tokens
+const MAX_TOKENS_PER_USER: i64 = 20;
+const MAX_EXPIRATION_DAYS: i64 = 365;
+
+pub fn routes() -> Vec<Route> {
+    routes![get_personal_tokens, post_personal_token, delete_personal_token, get_personal_sync, post_personal_send]
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PersonalApiTokenData {
+    name: String,
+    scopes: Vec<String>,
+    // Without it the token doesn't expire
+    expiration_days: Option<i64>,
+    master_password_hash: Option<String>,
+    otp: Option<String>,
+}
+
+#[get("/accounts/personal-tokens")]
+async fn get_personal_tokens(headers: Headers, mut conn: DbConn) -> JsonResult {
+    if !CONFIG.personal_api_tokens_allowed() {
+        err!("Personal API tokens are not enabled on this server")
+    }
+
+    PersonalApiToken::delete_revoked_by_user(&headers.user, &mut conn).await?;
+    let tokens_json: Vec<Value> = PersonalApiToken::find_by_user(&headers.user.uuid, &mut conn)
+        .await
+        .iter()
+        .map(PersonalApiToken::to_json)
+        .collect();
+
+    Ok(Json(json!({
+        "data": tokens_json,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+/// Creates a token, the token string is only part of this response.
+#[post("/accounts/personal-tokens", data = "<data>")]
+async fn post_personal_token(data: Json<PersonalApiTokenData>, headers: Headers, mut conn: DbConn) -> JsonResult {
+    if !CONFIG.personal_api_tokens_allowed() {
+        err!("Personal API tokens are not enabled on this server")
+    }
+
+    let data: PersonalApiTokenData = data.into_inner();
+    PasswordOrOtpData {
+        master_password_hash: data.master_password_hash,
+        otp: data.otp,
+    }
+    .validate(&headers.user, true, &mut conn)
+    .await?;
+
+    let name = data.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        err!("The name of the token has to be between 1 and 100 characters")
+    }
+
+    let mut scopes = Vec::with_capacity(data.scopes.len());
+    for scope in &data.scopes {
+        let Some(scope) = PersonalApiTokenScope::from_name(scope) else {
+            err!(format!("Unknown scope `{scope}`, the supported scopes are `sync` and `send`"))
+        };
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    if scopes.is_empty() {
+        err!("The token needs at least one scope")
+    }
+
+    let expire_at = match data.expiration_days {
+        None => None,
+        Some(days @ 1..=MAX_EXPIRATION_DAYS) => Some(Utc::now().naive_utc() + TimeDelta::try_days(days).unwrap()),
+        Some(_) => err!(format!("The expiration has to be between 1 and {MAX_EXPIRATION_DAYS} days")),
+    };
+
+    PersonalApiToken::delete_revoked_by_user(&headers.user, &mut conn).await?;
+    if PersonalApiToken::count_by_user(&headers.user.uuid, &mut conn).await >= MAX_TOKENS_PER_USER {
+        err!(format!("You can't have more than {MAX_TOKENS_PER_USER} personal API tokens"))
+    }
+
+    let (token, token_string) = PersonalApiToken::new(&headers.user, name.to_string(), &scopes, expire_at);
+    token.save(&mut conn).await?;
+
+    let mut token_json = token.to_json();
+    token_json["token"] = Value::String(token_string);
+    Ok(Json(token_json))
+}
+
+#[delete("/accounts/personal-tokens/<token_id>")]
+async fn delete_personal_token(token_id: PersonalApiTokenId, headers: Headers, mut conn: DbConn) -> EmptyResult {
+    PersonalApiToken::delete_by_uuid_and_user(&token_id, &headers.user.uuid, &mut conn).await
+}
+
+/// The vault of the token user, like a sync of the clients. The items are encrypted as usual.
+#[get("/personal/sync")]
+async fn get_personal_sync(headers: PersonalSyncHeaders, mut conn: DbConn) -> JsonResult {
+    check_org_ip_restrictions(&headers.user.uuid, DeviceType::Sdk as i32, &headers.ip.ip, &mut conn).await?;
+    Ok(Json(sync_vault_json(&headers.user, &headers.host, true, &mut conn).await?))
+}
+
+#[post("/personal/sends", data = "<data>")]
+async fn post_personal_send(
+    data: Json<SendData>,
+    headers: PersonalSendHeaders,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> JsonResult {
+    check_org_ip_restrictions(&headers.user.uuid, DeviceType::Sdk as i32, &headers.ip.ip, &mut conn).await?;
+    create_personal_text_send(data.into_inner(), &headers.user.uuid, &mut conn, nt).await
+}
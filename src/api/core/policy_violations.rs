@@ -0,0 +1,147 @@
+use reqwest::Method;
+use rocket::{serde::json::Json, Route};
+use serde_json::Value;
+
+use crate::{
+    api::{core::two_factor, ApiResult, JsonResult},
+    auth::AdminHeaders,
+    db::{models::*, DbConn},
+    http_client::make_http_request,
+    util::{format_date, spawn_background},
+    CONFIG,
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![get_policy_violations]
+}
+
+#[get("/organizations/<org_id>/policy-violations")]
+async fn get_policy_violations(org_id: OrganizationId, headers: AdminHeaders, mut conn: DbConn) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+
+    let violations_json: Vec<Value> =
+        PolicyViolation::find_by_org(&org_id, &mut conn).await.iter().map(PolicyViolation::to_json).collect();
+
+    Ok(Json(json!({
+        "data": violations_json,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+/// Records that a policy revoked, removed or blocked a member, and posts it to `POLICY_VIOLATION_WEBHOOK_URL`.
+/// Like the events, a failure is only logged so it doesn't change the outcome of the enforcement.
+pub async fn record_policy_violation(
+    org_id: &OrganizationId,
+    user_id: &UserId,
+    policy_type: OrgPolicyType,
+    action: PolicyViolationAction,
+    reason: &str,
+    actor_id: &UserId,
+    conn: &mut DbConn,
+) {
+    let violation = PolicyViolation::new(
+        org_id.clone(),
+        user_id.clone(),
+        policy_type,
+        action,
+        reason.to_string(),
+        actor_id.clone(),
+    );
+    if let Err(e) = violation.save(conn).await {
+        error!("Error saving the policy violation of user {user_id}: {e:#?}");
+    }
+
+    let Some(url) = CONFIG.policy_violation_webhook_url() else {
+        return;
+    };
+
+    // The ticketing system shouldn't have to look up who and what this is about
+    let org_name = Organization::find_by_uuid(org_id, conn).await.map(|org| org.name);
+    let user_email = User::find_by_uuid(user_id, conn).await.map(|user| user.email);
+    // `None` when the policy was enforced by the admin panel or a scheduled job
+    let actor_email = User::find_by_uuid(actor_id, conn).await.map(|user| user.email);
+
+    let payload = json!({
+        "event": "policy.violation",
+        "violationId": violation.uuid,
+        "organizationId": org_id,
+        "organizationName": org_name,
+        "userId": user_id,
+        "userEmail": user_email,
+        "policyType": policy_type as i32,
+        "policyName": format!("{policy_type:?}"),
+        "action": action.as_str(),
+        "reason": reason,
+        "actorId": actor_id,
+        "actorEmail": actor_email,
+        "date": format_date(&violation.created_at),
+    });
+
+    spawn_background(async move {
+        let req = match make_http_request(Method::POST, &url) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("An error occurred while sending the policy violation webhook: {e}");
+                return;
+            }
+        };
+        match req.json(&payload).send().await.and_then(|r| r.error_for_status()) {
+            Ok(_) => debug!("Sent the policy violation webhook"),
+            Err(e) => error!("An error occurred while sending the policy violation webhook: {e}"),
+        }
+    });
+}
+
+/// Checks whether the policies allow a membership change, like `OrgPolicy::is_user_allowed`, and records a refusal as a
+/// policy violation. With `EMAIL_2FA_AUTO_FALLBACK` a missing two-step login is enabled by email instead of refused.
+/// `change` names the refused change in the recorded reason, like "Confirming the member".
+pub async fn check_user_allowed(
+    user_id: &UserId,
+    org_id: &OrganizationId,
+    exclude_current_org: bool,
+    change: &str,
+    actor_id: &UserId,
+    conn: &mut DbConn,
+) -> ApiResult<OrgPolicyResult> {
+    let mut result = OrgPolicy::is_user_allowed(user_id, org_id, exclude_current_org, conn).await;
+    if matches!(result, Err(OrgPolicyErr::TwoFactorMissing)) && CONFIG.email_2fa_auto_fallback() {
+        two_factor::email::find_and_activate_email_2fa(user_id, conn).await?;
+        // The single organization policy is only checked once two-step login is enabled
+        result = OrgPolicy::is_user_allowed(user_id, org_id, exclude_current_org, conn).await;
+    }
+
+    let (policy_type, cause) = match result {
+        Ok(()) => return Ok(result),
+        Err(OrgPolicyErr::TwoFactorMissing) => {
+            (OrgPolicyType::TwoFactorAuthentication, "two-step login is not enabled")
+        }
+        Err(OrgPolicyErr::SingleOrgEnforced) => {
+            (OrgPolicyType::SingleOrg, "the user is a member of an organization which forbids it")
+        }
+    };
+    let reason = format!("{change} was refused, {cause}");
+    let action = PolicyViolationAction::Blocked;
+    record_policy_violation(org_id, user_id, policy_type, action, &reason, actor_id, conn).await;
+    Ok(result)
+}
+
+/// Records a login which is held until the clients checked the master password against the master password policy,
+/// because it was set before the policy applied to the user. The clients make the user change a password which fails.
+pub async fn record_master_password_check(user: &User, conn: &mut DbConn) {
+    let policy_type = OrgPolicyType::MasterPassword;
+    for policy in OrgPolicy::find_confirmed_by_user_and_active_policy(&user.uuid, policy_type, conn).await {
+        record_policy_violation(
+            &policy.org_uuid,
+            &user.uuid,
+            policy_type,
+            PolicyViolationAction::Blocked,
+            "Logging in was held to check the master password, it was set before the master password policy applied",
+            &user.uuid,
+            conn,
+        )
+        .await;
+    }
+}
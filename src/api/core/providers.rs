@@ -0,0 +1,665 @@
+use num_traits::FromPrimitive;
+use rocket::{serde::json::Json, Route};
+
+use crate::{
+    api::{ApiResult, EmptyResult, JsonResult},
+    auth::{decode_provider_invite, Headers},
+    db::{models::*, DbConn},
+    mail,
+    util::NumberOrString,
+    CONFIG,
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        get_provider,
+        put_provider,
+        post_provider,
+        setup_provider,
+        get_provider_users,
+        invite_provider_users,
+        reinvite_provider_user,
+        accept_provider_invite,
+        provider_users_public_keys,
+        confirm_provider_user,
+        put_provider_user,
+        delete_provider_user,
+        post_delete_provider_user,
+        get_provider_organizations,
+        create_provider_organization,
+        add_provider_organization,
+        delete_provider_organization,
+        post_delete_provider_organization,
+        delete_organization_provider,
+        post_delete_organization_provider,
+    ]
+}
+
+/// Returns the provider and the provider user of the current user, who needs to be confirmed.
+/// With `admin_only` set the user also needs to be a ProviderAdmin, service users can only manage the client organizations.
+async fn get_provider_access(
+    provider_id: &ProviderId,
+    user_id: &UserId,
+    admin_only: bool,
+    conn: &mut DbConn,
+) -> ApiResult<(Provider, ProviderUser)> {
+    let (Some(provider), Some(provider_user)) = (
+        Provider::find_by_uuid(provider_id, conn).await,
+        ProviderUser::find_by_user_and_provider(user_id, provider_id, conn).await,
+    ) else {
+        err!("Provider not found")
+    };
+    if !provider.enabled {
+        err!("This provider is disabled")
+    }
+    if provider_user.status != ProviderUserStatus::Confirmed as i32 {
+        err!("You need to be a confirmed user of the provider")
+    }
+    if admin_only && provider_user.atype != ProviderUserType::ProviderAdmin as i32 {
+        err!("You need to be a provider admin to call this endpoint")
+    }
+    Ok((provider, provider_user))
+}
+
+/// Adds the user with the email to the provider, the account is created when it doesn't exist yet.
+/// With `setup` set this is the first admin of a new provider, who receives the link to set it up.
+pub async fn invite_provider_user(
+    provider: &Provider,
+    email: &str,
+    atype: ProviderUserType,
+    setup: bool,
+    conn: &mut DbConn,
+) -> ApiResult<ProviderUser> {
+    let mut user_created = false;
+    let user = match User::find_by_mail(email, conn).await {
+        Some(user) => {
+            if ProviderUser::find_by_user_and_provider(&user.uuid, &provider.uuid, conn).await.is_some() {
+                err!(format!("User already in provider: {email}"))
+            }
+            user
+        }
+        None => {
+            if !CONFIG.invitations_allowed() {
+                err!(format!("User does not exist: {email}"))
+            }
+            if !CONFIG.is_email_domain_allowed(email) {
+                err!("Email domain not eligible for invitations")
+            }
+
+            // There is no registration with a provider invite token, the invitation allows the new account to register
+            Invitation::new(email).save(conn).await?;
+            let mut new_user = User::new(email.to_string(), None);
+            new_user.save(conn).await?;
+            user_created = true;
+            new_user
+        }
+    };
+
+    let mut provider_user = ProviderUser::new(provider.uuid.clone(), user.uuid.clone(), atype);
+    // Like the invitations to organizations, existing accounts join right away when mail is disabled
+    if !setup && !CONFIG.mail_enabled() && !user.password_hash.is_empty() {
+        provider_user.status = ProviderUserStatus::Accepted as i32;
+    }
+    provider_user.save(conn).await?;
+
+    if CONFIG.mail_enabled() {
+        if let Err(e) =
+            mail::send_provider_invite(&user, provider.uuid.clone(), provider_user.uuid.clone(), &provider.name, setup)
+                .await
+        {
+            // Upon error delete the user or the provider user again
+            if user_created {
+                user.delete(conn).await?;
+            } else {
+                provider_user.delete(conn).await?;
+            }
+            err!(format!("Error sending invite: {e:?} "));
+        }
+    }
+
+    Ok(provider_user)
+}
+
+#[get("/providers/<provider_id>")]
+async fn get_provider(provider_id: ProviderId, headers: Headers, mut conn: DbConn) -> JsonResult {
+    let (provider, _) = get_provider_access(&provider_id, &headers.user.uuid, false, &mut conn).await?;
+    Ok(Json(provider.to_json()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderUpdateData {
+    name: String,
+    business_name: Option<String>,
+    billing_email: String,
+}
+
+#[put("/providers/<provider_id>", data = "<data>")]
+async fn put_provider(
+    provider_id: ProviderId,
+    data: Json<ProviderUpdateData>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> JsonResult {
+    let (mut provider, _) = get_provider_access(&provider_id, &headers.user.uuid, true, &mut conn).await?;
+    let data: ProviderUpdateData = data.into_inner();
+
+    provider.name = data.name;
+    provider.business_name = data.business_name;
+    provider.billing_email = data.billing_email.to_lowercase();
+    provider.save(&mut conn).await?;
+
+    Ok(Json(provider.to_json()))
+}
+
+#[post("/providers/<provider_id>", data = "<data>")]
+async fn post_provider(
+    provider_id: ProviderId,
+    data: Json<ProviderUpdateData>,
+    headers: Headers,
+    conn: DbConn,
+) -> JsonResult {
+    put_provider(provider_id, data, headers, conn).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderSetupData {
+    name: String,
+    business_name: Option<String>,
+    billing_email: String,
+    token: String,
+    // The provider key, encrypted with the user key of the first admin
+    key: String,
+}
+
+// The first admin sets up the provider which was created by the server administrator
+#[post("/providers/<provider_id>/setup", data = "<data>")]
+async fn setup_provider(
+    provider_id: ProviderId,
+    data: Json<ProviderSetupData>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: ProviderSetupData = data.into_inner();
+    let claims = decode_provider_invite(&data.token)?;
+    if claims.sub != headers.user.uuid || claims.provider_id != provider_id {
+        err!("Invalid provider setup token")
+    }
+
+    let (Some(mut provider), Some(mut provider_user)) = (
+        Provider::find_by_uuid(&provider_id, &mut conn).await,
+        ProviderUser::find_by_uuid_and_provider(&claims.provider_user_id, &provider_id, &mut conn).await,
+    ) else {
+        err!("Provider not found")
+    };
+    // Only the first admin sets up the provider, the others are confirmed by an admin
+    if provider_user.status != ProviderUserStatus::Invited as i32
+        || provider_user.atype != ProviderUserType::ProviderAdmin as i32
+        || ProviderUser::count_confirmed_admins(&provider_id, &mut conn).await > 0
+    {
+        err!("The provider has already been set up")
+    }
+
+    provider.name = data.name;
+    provider.business_name = data.business_name;
+    provider.billing_email = data.billing_email.to_lowercase();
+    provider.save(&mut conn).await?;
+
+    provider_user.akey = Some(data.key);
+    provider_user.status = ProviderUserStatus::Confirmed as i32;
+    provider_user.save(&mut conn).await?;
+
+    Ok(Json(provider.to_json()))
+}
+
+#[get("/providers/<provider_id>/users")]
+async fn get_provider_users(provider_id: ProviderId, headers: Headers, mut conn: DbConn) -> JsonResult {
+    get_provider_access(&provider_id, &headers.user.uuid, false, &mut conn).await?;
+
+    let mut users_json = Vec::new();
+    for provider_user in ProviderUser::find_by_provider(&provider_id, &mut conn).await {
+        users_json.push(provider_user.to_json_user_details(&mut conn).await);
+    }
+
+    Ok(Json(json!({
+        "data": users_json,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderInviteData {
+    emails: Vec<String>,
+    r#type: NumberOrString,
+}
+
+fn provider_user_type(atype: NumberOrString) -> ApiResult<ProviderUserType> {
+    match atype.into_i32().ok().and_then(ProviderUserType::from_i32) {
+        Some(atype) => Ok(atype),
+        None => err!("Invalid type"),
+    }
+}
+
+#[post("/providers/<provider_id>/users/invite", data = "<data>")]
+async fn invite_provider_users(
+    provider_id: ProviderId,
+    data: Json<ProviderInviteData>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let (provider, _) = get_provider_access(&provider_id, &headers.user.uuid, true, &mut conn).await?;
+    let data: ProviderInviteData = data.into_inner();
+    let atype = provider_user_type(data.r#type)?;
+
+    for email in data.emails.iter() {
+        invite_provider_user(&provider, email, atype, false, &mut conn).await?;
+    }
+    Ok(())
+}
+
+#[post("/providers/<provider_id>/users/<provider_user_id>/reinvite")]
+async fn reinvite_provider_user(
+    provider_id: ProviderId,
+    provider_user_id: ProviderUserId,
+    headers: Headers,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let (provider, _) = get_provider_access(&provider_id, &headers.user.uuid, true, &mut conn).await?;
+    if !CONFIG.mail_enabled() {
+        err!("SMTP is not configured")
+    }
+    let Some(provider_user) = ProviderUser::find_by_uuid_and_provider(&provider_user_id, &provider_id, &mut conn).await
+    else {
+        err!("The user hasn't been invited to the provider")
+    };
+    if provider_user.status != ProviderUserStatus::Invited as i32 {
+        err!("The user already accepted or was already confirmed")
+    }
+    let Some(user) = User::find_by_uuid(&provider_user.user_uuid, &mut conn).await else {
+        err!("User not found")
+    };
+
+    mail::send_provider_invite(&user, provider_id, provider_user.uuid, &provider.name, false).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderAcceptData {
+    token: String,
+}
+
+#[post("/providers/<provider_id>/users/<provider_user_id>/accept", data = "<data>")]
+async fn accept_provider_invite(
+    provider_id: ProviderId,
+    provider_user_id: ProviderUserId,
+    data: Json<ProviderAcceptData>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let data: ProviderAcceptData = data.into_inner();
+    let claims = decode_provider_invite(&data.token)?;
+
+    // Don't allow other users from accepting an invitation.
+    if !claims.email.eq(&headers.user.email) {
+        err!("Invitation was issued to a different account", "Claim does not match user_id")
+    }
+    if claims.provider_id != provider_id || claims.provider_user_id != provider_user_id {
+        err!("Error accepting the invitation", "Claim does not match the provider user")
+    }
+
+    let Some(mut provider_user) =
+        ProviderUser::find_by_uuid_and_provider(&provider_user_id, &provider_id, &mut conn).await
+    else {
+        err!("Error accepting the invitation")
+    };
+    if provider_user.status != ProviderUserStatus::Invited as i32 {
+        err!("User already accepted the invitation")
+    }
+
+    provider_user.status = ProviderUserStatus::Accepted as i32;
+    provider_user.save(&mut conn).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BulkProviderUserIds {
+    ids: Vec<ProviderUserId>,
+}
+
+#[post("/providers/<provider_id>/users/public-keys", data = "<data>")]
+async fn provider_users_public_keys(
+    provider_id: ProviderId,
+    data: Json<BulkProviderUserIds>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> JsonResult {
+    get_provider_access(&provider_id, &headers.user.uuid, true, &mut conn).await?;
+    let data: BulkProviderUserIds = data.into_inner();
+
+    let mut bulk_response = Vec::new();
+    for provider_user_id in data.ids {
+        let Some(provider_user) =
+            ProviderUser::find_by_uuid_and_provider(&provider_user_id, &provider_id, &mut conn).await
+        else {
+            continue;
+        };
+        if let Some(user) = User::find_by_uuid(&provider_user.user_uuid, &mut conn).await {
+            bulk_response.push(json!({
+                "object": "providerUserPublicKeyResponseModel",
+                "id": provider_user.uuid,
+                "userId": user.uuid,
+                "key": user.public_key,
+            }));
+        }
+    }
+
+    Ok(Json(json!({
+        "data": bulk_response,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderConfirmData {
+    // The provider key, encrypted with the public key of the user
+    key: String,
+}
+
+#[post("/providers/<provider_id>/users/<provider_user_id>/confirm", data = "<data>")]
+async fn confirm_provider_user(
+    provider_id: ProviderId,
+    provider_user_id: ProviderUserId,
+    data: Json<ProviderConfirmData>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> EmptyResult {
+    get_provider_access(&provider_id, &headers.user.uuid, true, &mut conn).await?;
+    let data: ProviderConfirmData = data.into_inner();
+
+    let Some(mut provider_user) =
+        ProviderUser::find_by_uuid_and_provider(&provider_user_id, &provider_id, &mut conn).await
+    else {
+        err!("The specified user isn't a user of the provider")
+    };
+    if provider_user.status != ProviderUserStatus::Accepted as i32 {
+        err!("User in invalid state")
+    }
+
+    provider_user.akey = Some(data.key);
+    provider_user.status = ProviderUserStatus::Confirmed as i32;
+    provider_user.save(&mut conn).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderUserUpdateData {
+    r#type: NumberOrString,
+}
+
+#[put("/providers/<provider_id>/users/<provider_user_id>", data = "<data>")]
+async fn put_provider_user(
+    provider_id: ProviderId,
+    provider_user_id: ProviderUserId,
+    data: Json<ProviderUserUpdateData>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> EmptyResult {
+    get_provider_access(&provider_id, &headers.user.uuid, true, &mut conn).await?;
+    let atype = provider_user_type(data.into_inner().r#type)?;
+
+    let Some(mut provider_user) =
+        ProviderUser::find_by_uuid_and_provider(&provider_user_id, &provider_id, &mut conn).await
+    else {
+        err!("The specified user isn't a user of the provider")
+    };
+    if provider_user.is_confirmed_admin()
+        && atype != ProviderUserType::ProviderAdmin
+        && ProviderUser::count_confirmed_admins(&provider_id, &mut conn).await <= 1
+    {
+        err!("Can't change the type of the last provider admin")
+    }
+
+    provider_user.atype = atype as i32;
+    provider_user.save(&mut conn).await
+}
+
+#[delete("/providers/<provider_id>/users/<provider_user_id>")]
+async fn delete_provider_user(
+    provider_id: ProviderId,
+    provider_user_id: ProviderUserId,
+    headers: Headers,
+    mut conn: DbConn,
+) -> EmptyResult {
+    get_provider_access(&provider_id, &headers.user.uuid, true, &mut conn).await?;
+
+    let Some(provider_user) = ProviderUser::find_by_uuid_and_provider(&provider_user_id, &provider_id, &mut conn).await
+    else {
+        err!("The specified user isn't a user of the provider")
+    };
+    if provider_user.is_confirmed_admin() && ProviderUser::count_confirmed_admins(&provider_id, &mut conn).await <= 1 {
+        err!("Can't delete the last provider admin")
+    }
+
+    provider_user.delete(&mut conn).await
+}
+
+#[post("/providers/<provider_id>/users/<provider_user_id>/delete")]
+async fn post_delete_provider_user(
+    provider_id: ProviderId,
+    provider_user_id: ProviderUserId,
+    headers: Headers,
+    conn: DbConn,
+) -> EmptyResult {
+    delete_provider_user(provider_id, provider_user_id, headers, conn).await
+}
+
+#[get("/providers/<provider_id>/organizations")]
+async fn get_provider_organizations(provider_id: ProviderId, headers: Headers, mut conn: DbConn) -> JsonResult {
+    get_provider_access(&provider_id, &headers.user.uuid, false, &mut conn).await?;
+
+    let mut orgs_json = Vec::new();
+    for provider_org in ProviderOrganization::find_by_provider(&provider_id, &mut conn).await {
+        orgs_json.push(provider_org.to_json(&mut conn).await);
+    }
+
+    Ok(Json(json!({
+        "data": orgs_json,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderOrgKeyData {
+    encrypted_private_key: String,
+    public_key: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderOrgData {
+    name: String,
+    billing_email: String,
+    collection_name: String,
+    // The organization key, encrypted with the provider key
+    key: String,
+    keys: Option<ProviderOrgKeyData>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderOrgCreateData {
+    client_owner_email: String,
+    organization_create_request: ProviderOrgData,
+}
+
+// Creates an organization for a client, the client owner is invited as its Owner
+#[post("/providers/<provider_id>/organizations", data = "<data>")]
+async fn create_provider_organization(
+    provider_id: ProviderId,
+    data: Json<ProviderOrgCreateData>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> JsonResult {
+    let (provider, _) = get_provider_access(&provider_id, &headers.user.uuid, true, &mut conn).await?;
+    let data: ProviderOrgCreateData = data.into_inner();
+    let org_data = data.organization_create_request;
+    let owner_email = data.client_owner_email.to_lowercase();
+
+    let (private_key, public_key) = match org_data.keys {
+        Some(keys) => (Some(keys.encrypted_private_key), Some(keys.public_key)),
+        None => (None, None),
+    };
+    let org = Organization::new(org_data.name, org_data.billing_email, private_key, public_key);
+    let collection = Collection::new(org.uuid.clone(), org_data.collection_name, None);
+    let provider_org = ProviderOrganization::new(provider.uuid.clone(), org.uuid.clone(), Some(org_data.key));
+
+    org.save(&mut conn).await?;
+    collection.save(&mut conn).await?;
+    provider_org.save(&mut conn).await?;
+
+    if let Err(e) = invite_client_owner(&org, &owner_email, &headers.user.email, &mut conn).await {
+        org.delete(&mut conn).await?;
+        return Err(e);
+    }
+
+    Ok(Json(provider_org.to_json(&mut conn).await))
+}
+
+/// Invites the owner of a client organization, a provider user confirms the owner from the members of the organization.
+async fn invite_client_owner(
+    org: &Organization,
+    email: &str,
+    invited_by_email: &str,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    let user = match User::find_by_mail(email, conn).await {
+        Some(user) => user,
+        None => {
+            if !CONFIG.invitations_allowed() {
+                err!(format!("User does not exist: {email}"))
+            }
+            if !CONFIG.is_email_domain_allowed(email) {
+                err!("Email domain not eligible for invitations")
+            }
+            if !CONFIG.mail_enabled() {
+                Invitation::new(email).save(conn).await?;
+            }
+
+            let mut new_user = User::new(email.to_string(), None);
+            new_user.save(conn).await?;
+            new_user
+        }
+    };
+
+    let mut member = Membership::new(user.uuid.clone(), org.uuid.clone(), Some(invited_by_email.to_string()));
+    member.access_all = true;
+    member.atype = MembershipType::Owner as i32;
+    member.status = if !CONFIG.mail_enabled() && !user.password_hash.is_empty() {
+        MembershipStatus::Accepted as i32
+    } else {
+        MembershipStatus::Invited as i32
+    };
+    member.save(conn).await?;
+
+    if CONFIG.mail_enabled() {
+        mail::send_invite(
+            &user,
+            org.uuid.clone(),
+            member.uuid.clone(),
+            &org.name,
+            Some(invited_by_email.to_string()),
+            Some(org),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderOrgAddData {
+    organization_id: OrganizationId,
+    // The organization key, encrypted with the provider key
+    key: String,
+}
+
+// Adds an existing organization to the provider, which needs to be done by an admin of the provider who owns it
+#[post("/providers/<provider_id>/organizations/add", data = "<data>")]
+async fn add_provider_organization(
+    provider_id: ProviderId,
+    data: Json<ProviderOrgAddData>,
+    headers: Headers,
+    mut conn: DbConn,
+) -> EmptyResult {
+    get_provider_access(&provider_id, &headers.user.uuid, true, &mut conn).await?;
+    let data: ProviderOrgAddData = data.into_inner();
+
+    match Membership::find_confirmed_by_user_and_org(&headers.user.uuid, &data.organization_id, &mut conn).await {
+        Some(member) if member.atype == MembershipType::Owner => (),
+        _ => err!("You need to be an Owner of the organization to add it to the provider"),
+    }
+    if ProviderOrganization::find_by_org(&data.organization_id, &mut conn).await.is_some() {
+        err!("The organization is already managed by a provider")
+    }
+
+    ProviderOrganization::new(provider_id, data.organization_id, Some(data.key)).save(&mut conn).await
+}
+
+// Removes the organization from the provider, the organization keeps existing on its own
+#[delete("/providers/<provider_id>/organizations/<provider_org_id>")]
+async fn delete_provider_organization(
+    provider_id: ProviderId,
+    provider_org_id: ProviderOrganizationId,
+    headers: Headers,
+    mut conn: DbConn,
+) -> EmptyResult {
+    get_provider_access(&provider_id, &headers.user.uuid, true, &mut conn).await?;
+
+    let Some(provider_org) =
+        ProviderOrganization::find_by_uuid_and_provider(&provider_org_id, &provider_id, &mut conn).await
+    else {
+        err!("Organization not found")
+    };
+    if Membership::count_confirmed_by_org_and_type(&provider_org.org_uuid, MembershipType::Owner, &mut conn).await < 1 {
+        err!("The organization needs a confirmed Owner before it can be removed from the provider")
+    }
+
+    provider_org.delete(&mut conn).await
+}
+
+#[post("/providers/<provider_id>/organizations/<provider_org_id>/delete")]
+async fn post_delete_provider_organization(
+    provider_id: ProviderId,
+    provider_org_id: ProviderOrganizationId,
+    headers: Headers,
+    conn: DbConn,
+) -> EmptyResult {
+    delete_provider_organization(provider_id, provider_org_id, headers, conn).await
+}
+
+// Removes the organization from its provider from the side of the organization. The provider users get Owner access
+// to its client organizations, so only an actual Owner of the organization can do this.
+#[delete("/organizations/<org_id>/provider")]
+async fn delete_organization_provider(org_id: OrganizationId, headers: Headers, mut conn: DbConn) -> EmptyResult {
+    match Membership::find_confirmed_by_user_and_org(&headers.user.uuid, &org_id, &mut conn).await {
+        Some(member) if member.atype == MembershipType::Owner => (),
+        _ => err!("You need to be an Owner of the organization to remove it from its provider"),
+    }
+    let Some(provider_org) = ProviderOrganization::find_by_org(&org_id, &mut conn).await else {
+        err!("The organization isn't managed by a provider")
+    };
+
+    provider_org.delete(&mut conn).await
+}
+
+#[post("/organizations/<org_id>/provider/delete")]
+async fn post_delete_organization_provider(org_id: OrganizationId, headers: Headers, conn: DbConn) -> EmptyResult {
+    delete_organization_provider(org_id, headers, conn).await
+}
@@ -8,12 +8,19 @@ use rocket::{
 use std::collections::HashSet;
 
 use crate::{
-    api::EmptyResult,
-    auth,
+    api::{
+        check_report_invite_gate,
+        core::{log_event, notify_sensitive_action},
+        EmptyResult,
+    },
+    auth::{self, ClientIp},
     db::{models::*, DbConn},
     mail, CONFIG,
 };
 
+// Used as the acting user for events caused by the organization API key
+const PUBLIC_API_ACTING_USER: &str = "vaultwarden-pubapi-0000-000000000000";
+
 pub fn routes() -> Vec<Route> {
     routes![ldap_import]
 }
@@ -44,13 +51,16 @@ struct OrgImportData {
 }
 
 #[post("/public/organization/import", data = "<data>")]
-async fn ldap_import(data: Json<OrgImportData>, token: PublicToken, mut conn: DbConn) -> EmptyResult {
+async fn ldap_import(data: Json<OrgImportData>, token: PublicToken, ip: ClientIp, mut conn: DbConn) -> EmptyResult {
     // Most of the logic for this function can be found here
     // https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Core/AdminConsole/Services/Implementations/OrganizationService.cs#L1203
 
     let org_id = token.0;
     let data = data.into_inner();
 
+    // Keep track of the membership changes, so the owners can be notified about them
+    let mut member_events: Vec<(EventType, MembershipId)> = Vec::new();
+
     for user_data in &data.members {
         let mut user_created: bool = false;
         if user_data.deleted {
@@ -75,16 +85,27 @@ async fn ldap_import(data: Json<OrgImportData>, token: PublicToken, mut conn: Db
                 if revoked || ext_modified {
                     member.save(&mut conn).await?;
                 }
+                if revoked {
+                    member_events.push((EventType::OrganizationUserRevoked, member.uuid));
+                }
             }
         // If user is part of the organization, restore it
         } else if let Some(mut member) = Membership::find_by_email_and_org(&user_data.email, &org_id, &mut conn).await {
+            if member.status == MembershipStatus::Revoked as i32 {
+                check_org_seats(&org_id, &mut conn).await?;
+            }
             let restored = member.restore();
             let ext_modified = member.set_external_id(Some(user_data.external_id.clone()));
             if restored || ext_modified {
                 member.save(&mut conn).await?;
             }
+            if restored {
+                member_events.push((EventType::OrganizationUserRestored, member.uuid));
+            }
         } else {
             // If user is not part of the organization
+            check_report_invite_gate(&org_id, false, &mut conn).await?;
+            check_org_seats(&org_id, &mut conn).await?;
             let user = match User::find_by_mail(&user_data.email, &mut conn).await {
                 Some(user) => user, // exists in vaultwarden
                 None => {
@@ -105,10 +126,10 @@ async fn ldap_import(data: Json<OrgImportData>, token: PublicToken, mut conn: Db
                 MembershipStatus::Accepted as i32 // Automatically mark user as accepted if no email invites
             };
 
-            let (org_name, org_email) = match Organization::find_by_uuid(&org_id, &mut conn).await {
-                Some(org) => (org.name, org.billing_email),
-                None => err!("Error looking up organization"),
+            let Some(org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+                err!("Error looking up organization")
             };
+            let org_email = org.billing_email.clone();
 
             let mut new_member = Membership::new(user.uuid.clone(), org_id.clone(), Some(org_email.clone()));
             new_member.set_external_id(Some(user_data.external_id.clone()));
@@ -119,8 +140,9 @@ async fn ldap_import(data: Json<OrgImportData>, token: PublicToken, mut conn: Db
             new_member.save(&mut conn).await?;
 
             if CONFIG.mail_enabled() {
+                let member_id = new_member.uuid.clone();
                 if let Err(e) =
-                    mail::send_invite(&user, org_id.clone(), new_member.uuid.clone(), &org_name, Some(org_email)).await
+                    mail::send_invite(&user, org_id.clone(), member_id, &org.name, Some(org_email), Some(&org)).await
                 {
                     // Upon error delete the user, invite and org member records when needed
                     if user_created {
@@ -132,6 +154,7 @@ async fn ldap_import(data: Json<OrgImportData>, token: PublicToken, mut conn: Db
                     err!(format!("Error sending invite: {e:?} "));
                 }
             }
+            member_events.push((EventType::OrganizationUserInvited, new_member.uuid));
         }
     }
 
@@ -181,15 +204,58 @@ async fn ldap_import(data: Json<OrgImportData>, token: PublicToken, mut conn: Db
                             continue;
                         }
                     }
+                    member_events.push((EventType::OrganizationUserRemoved, member.uuid.clone()));
                     member.delete(&mut conn).await?;
                 }
             }
         }
     }
 
+    if !member_events.is_empty() {
+        let count_of = |event_type: EventType| member_events.iter().filter(|(e, _)| *e == event_type).count();
+        let details = format!(
+            "Invited: {}, restored: {}, revoked: {}, removed: {}",
+            count_of(EventType::OrganizationUserInvited),
+            count_of(EventType::OrganizationUserRestored),
+            count_of(EventType::OrganizationUserRevoked),
+            count_of(EventType::OrganizationUserRemoved),
+        );
+
+        for (event_type, member_id) in &member_events {
+            log_event(
+                *event_type as i32,
+                member_id,
+                &org_id,
+                &PUBLIC_API_ACTING_USER.into(),
+                14, // Use UnknownBrowser type
+                &ip.ip,
+                &mut conn,
+            )
+            .await;
+        }
+
+        notify_sensitive_action(
+            &org_id,
+            SensitiveAction::ApiMembershipChange,
+            &details,
+            "Organization API key",
+            None,
+            &mut conn,
+        )
+        .await;
+    }
+
     Ok(())
 }
 
+/// Each imported member takes a seat, so the import is stopped as soon as the seat limit is reached.
+async fn check_org_seats(org_id: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+    match Organization::find_by_uuid(org_id, conn).await {
+        Some(org) => org.check_available_seats(1, conn).await,
+        None => err!("Error looking up organization"),
+    }
+}
+
 pub struct PublicToken(OrganizationId);
 
 #[rocket::async_trait]
@@ -226,7 +292,7 @@ impl<'r> FromRequest<'r> for PublicToken {
 
         // Check if claims.sub is org_api_key.uuid
         // Check if claims.client_sub is org_api_key.org_uuid
-        let conn = match DbConn::from_request(request).await {
+        let mut conn = match DbConn::from_request(request).await {
             Outcome::Success(conn) => conn,
             _ => err_handler!("Error getting DB"),
         };
@@ -243,6 +309,9 @@ impl<'r> FromRequest<'r> for PublicToken {
         if org_api_key.uuid != claims.sub {
             err_handler!("Token not issued for this client");
         }
+        if Organization::find_by_uuid(&org_api_key.org_uuid, &mut conn).await.is_some_and(|org| org.is_archived()) {
+            err_handler!("The organization is archived");
+        }
 
         Outcome::Success(PublicToken(claims.client_sub))
     }
@@ -0,0 +1,768 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use rocket::{
+    outcome::try_outcome,
+    request::{FromRequest, Outcome, Request},
+    serde::json::Json,
+    Route,
+};
+use serde_json::Value;
+
+use crate::{
+    api::{ApiResult, EmptyResult, JsonResult},
+    auth::{self, Headers},
+    db::{
+        models::{
+            Membership, MembershipType, OrganizationId, Project, ProjectId, Secret, SecretId, SecretProject,
+            SecretsRevision, ServiceAccount, ServiceAccountId, ServiceAccountProject, ServiceAccountToken,
+            ServiceAccountTokenId,
+        },
+        DbConn,
+    },
+    CONFIG,
+};
+
+// The Secrets Manager API, as used by the `bws` CLI, the Secrets Manager SDK and the Secrets Manager web app.
+// Secrets and project names are encrypted with the organization key, so the server only stores them.
+pub fn routes() -> Vec<Route> {
+    routes![
+        get_org_secrets,
+        post_org_secret,
+        get_secret,
+        put_secret,
+        post_secrets_by_ids,
+        post_delete_secrets,
+        get_org_secrets_sync,
+        get_project_secrets,
+        get_org_projects,
+        post_org_project,
+        get_project,
+        put_project,
+        post_delete_projects,
+        get_project_service_account_policies,
+        put_project_service_account_policies,
+        get_org_service_accounts,
+        post_org_service_account,
+        get_service_account,
+        put_service_account,
+        post_delete_service_accounts,
+        get_access_tokens,
+        post_access_token,
+        post_revoke_access_tokens,
+    ]
+}
+
+/// The caller of a Secrets Manager endpoint, either a user or a machine account using one of its access tokens.
+pub enum SecretsManagerCaller {
+    User(Headers),
+    ServiceAccount(ServiceAccount),
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SecretsManagerCaller {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        if !CONFIG.secrets_manager_enabled() {
+            err_handler!("Secrets Manager is not enabled on this server")
+        }
+
+        let access_token = request.headers().get_one("Authorization").and_then(|a| a.strip_prefix("Bearer "));
+        if let Some(Ok(claims)) = access_token.map(auth::decode_service_account) {
+            let mut conn = match DbConn::from_request(request).await {
+                Outcome::Success(conn) => conn,
+                _ => err_handler!("Error getting DB"),
+            };
+
+            // Check the access token itself too, so revoking it takes effect right away
+            let Some(token) = ServiceAccountToken::find_by_uuid(&claims.client_id, &mut conn).await else {
+                err_handler!("The access token has been revoked")
+            };
+            if token.service_account_uuid != claims.sub || token.is_expired() {
+                err_handler!("Invalid access token")
+            }
+            let Some(service_account) = ServiceAccount::find_by_uuid(&claims.sub, &mut conn).await else {
+                err_handler!("The machine account has been deleted")
+            };
+            return Outcome::Success(Self::ServiceAccount(service_account));
+        }
+
+        let headers = try_outcome!(Headers::from_request(request).await);
+        Outcome::Success(Self::User(headers))
+    }
+}
+
+/// What the caller can do in an organization.
+enum OrgAccess {
+    /// Owners and Admins can read and write everything
+    Full,
+    /// Machine accounts can read or write the projects they have been granted, and the secrets in them
+    Projects(HashMap<ProjectId, (bool, bool)>),
+}
+
+impl OrgAccess {
+    fn is_full(&self) -> bool {
+        matches!(self, OrgAccess::Full)
+    }
+
+    /// Returns if the project can be (read, written).
+    fn project(&self, project_uuid: &ProjectId) -> (bool, bool) {
+        match self {
+            OrgAccess::Full => (true, true),
+            OrgAccess::Projects(grants) => grants.get(project_uuid).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Returns if a secret in the given projects can be (read, written).
+    /// Secrets without a project can only be accessed by Owners and Admins.
+    fn secret(&self, projects: &[Project]) -> (bool, bool) {
+        projects.iter().fold((self.is_full(), self.is_full()), |(read, write), project| {
+            let (project_read, project_write) = self.project(&project.uuid);
+            (read || project_read, write || project_write)
+        })
+    }
+}
+
+impl SecretsManagerCaller {
+    async fn org_access(&self, org_id: &OrganizationId, conn: &mut DbConn) -> ApiResult<OrgAccess> {
+        match self {
+            Self::User(headers) => {
+                match Membership::find_confirmed_by_user_and_org(&headers.user.uuid, org_id, conn).await {
+                    Some(member) if member.atype >= MembershipType::Admin => Ok(OrgAccess::Full),
+                    _ => err!("You need to be Admin or Owner to use Secrets Manager"),
+                }
+            }
+            Self::ServiceAccount(service_account) if &service_account.org_uuid == org_id => {
+                let grants = ServiceAccountProject::find_by_service_account(&service_account.uuid, conn)
+                    .await
+                    .into_iter()
+                    .map(|grant| (grant.project_uuid, (grant.can_read, grant.can_write)))
+                    .collect();
+                Ok(OrgAccess::Projects(grants))
+            }
+            Self::ServiceAccount(_) => {
+                err!("Organization not found", "The machine account belongs to another organization")
+            }
+        }
+    }
+
+    /// Managing projects and machine accounts is restricted to Owners and Admins.
+    async fn check_admin(&self, org_id: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        if self.org_access(org_id, conn).await?.is_full() {
+            Ok(())
+        } else {
+            err!("Machine accounts can't manage projects or machine accounts")
+        }
+    }
+}
+
+fn bulk_response(results: Vec<(String, String)>) -> Value {
+    let data: Vec<Value> = results
+        .into_iter()
+        .map(|(id, error)| {
+            json!({
+                "id": id,
+                "error": error,
+                "object": "bulkDeleteResponseModel",
+            })
+        })
+        .collect();
+
+    json!({
+        "data": data,
+        "object": "list",
+        "continuationToken": null,
+    })
+}
+
+//
+// Secrets
+//
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SecretData {
+    key: String,
+    value: String,
+    note: String,
+    project_ids: Option<Vec<ProjectId>>,
+}
+
+/// Checks the projects of a new or updated secret, which need to be writable by the caller.
+async fn check_secret_projects(
+    org_id: &OrganizationId,
+    project_ids: &[ProjectId],
+    access: &OrgAccess,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    if project_ids.is_empty() && !access.is_full() {
+        err!("Machine accounts can only create secrets in a project")
+    }
+
+    for project_id in project_ids {
+        match Project::find_by_uuid(project_id, conn).await {
+            Some(project) if &project.org_uuid == org_id => {
+                if !access.project(&project.uuid).1 {
+                    err!("You don't have write access to this project")
+                }
+            }
+            _ => err!("Project not found"),
+        }
+    }
+    Ok(())
+}
+
+async fn secrets_list_json(secrets: Vec<Secret>, access: &OrgAccess, conn: &mut DbConn) -> Value {
+    let mut secrets_json = Vec::with_capacity(secrets.len());
+    let mut projects_json = HashMap::new();
+    for secret in secrets {
+        let projects = Project::find_by_secret(&secret.uuid, conn).await;
+        let (read, write) = access.secret(&projects);
+        if !read {
+            continue;
+        }
+        for project in &projects {
+            projects_json.entry(project.uuid.clone()).or_insert_with(|| project.to_json_mini());
+        }
+        secrets_json.push(secret.to_json_list_item(&projects, read, write));
+    }
+
+    json!({
+        "secrets": secrets_json,
+        "projects": projects_json.into_values().collect::<Vec<Value>>(),
+        "object": "SecretsWithProjectsList",
+    })
+}
+
+#[get("/organizations/<org_id>/secrets")]
+async fn get_org_secrets(org_id: OrganizationId, caller: SecretsManagerCaller, mut conn: DbConn) -> JsonResult {
+    let access = caller.org_access(&org_id, &mut conn).await?;
+    let secrets = Secret::find_by_org(&org_id, &mut conn).await;
+    Ok(Json(secrets_list_json(secrets, &access, &mut conn).await))
+}
+
+#[get("/projects/<project_id>/secrets")]
+async fn get_project_secrets(project_id: ProjectId, caller: SecretsManagerCaller, mut conn: DbConn) -> JsonResult {
+    let Some(project) = Project::find_by_uuid(&project_id, &mut conn).await else {
+        err!("Project not found")
+    };
+    let access = caller.org_access(&project.org_uuid, &mut conn).await?;
+    if !access.project(&project.uuid).0 {
+        err!("Project not found", "No read access to the project")
+    }
+
+    let secrets = Secret::find_by_project(&project.uuid, &mut conn).await;
+    Ok(Json(secrets_list_json(secrets, &access, &mut conn).await))
+}
+
+#[post("/organizations/<org_id>/secrets", data = "<data>")]
+async fn post_org_secret(
+    org_id: OrganizationId,
+    data: Json<SecretData>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: SecretData = data.into_inner();
+    let access = caller.org_access(&org_id, &mut conn).await?;
+    let project_ids = data.project_ids.unwrap_or_default();
+    check_secret_projects(&org_id, &project_ids, &access, &mut conn).await?;
+
+    let mut secret = Secret::new(org_id, data.key, data.value, data.note);
+    secret.save(&mut conn).await?;
+    SecretProject::replace_projects(&secret.uuid, &project_ids, &mut conn).await?;
+
+    let projects = Project::find_by_secret(&secret.uuid, &mut conn).await;
+    Ok(Json(secret.to_json(&projects, true, true)))
+}
+
+#[get("/secrets/<secret_id>")]
+async fn get_secret(secret_id: SecretId, caller: SecretsManagerCaller, mut conn: DbConn) -> JsonResult {
+    let Some(secret) = Secret::find_by_uuid(&secret_id, &mut conn).await else {
+        err!("Secret not found")
+    };
+    let access = caller.org_access(&secret.org_uuid, &mut conn).await?;
+    let projects = Project::find_by_secret(&secret.uuid, &mut conn).await;
+    let (read, write) = access.secret(&projects);
+    if !read {
+        err!("Secret not found", "No read access to the secret")
+    }
+
+    Ok(Json(secret.to_json(&projects, read, write)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SecretIdsData {
+    ids: Vec<SecretId>,
+}
+
+#[post("/secrets/get-by-ids", data = "<data>")]
+async fn post_secrets_by_ids(data: Json<SecretIdsData>, caller: SecretsManagerCaller, mut conn: DbConn) -> JsonResult {
+    let mut secrets_json = Vec::new();
+    for secret_id in data.into_inner().ids {
+        let Some(secret) = Secret::find_by_uuid(&secret_id, &mut conn).await else {
+            err!("Secret not found")
+        };
+        let access = caller.org_access(&secret.org_uuid, &mut conn).await?;
+        let projects = Project::find_by_secret(&secret.uuid, &mut conn).await;
+        let (read, write) = access.secret(&projects);
+        if !read {
+            err!("Secret not found", "No read access to the secret")
+        }
+        secrets_json.push(secret.to_json(&projects, read, write));
+    }
+
+    Ok(Json(json!({
+        "data": secrets_json,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+#[put("/secrets/<secret_id>", data = "<data>")]
+async fn put_secret(
+    secret_id: SecretId,
+    data: Json<SecretData>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: SecretData = data.into_inner();
+    let Some(mut secret) = Secret::find_by_uuid(&secret_id, &mut conn).await else {
+        err!("Secret not found")
+    };
+    let access = caller.org_access(&secret.org_uuid, &mut conn).await?;
+    if !access.secret(&Project::find_by_secret(&secret.uuid, &mut conn).await).1 {
+        err!("You don't have write access to this secret")
+    }
+    let project_ids = data.project_ids.unwrap_or_default();
+    check_secret_projects(&secret.org_uuid, &project_ids, &access, &mut conn).await?;
+
+    secret.akey = data.key;
+    secret.value = data.value;
+    secret.note = data.note;
+    secret.save(&mut conn).await?;
+    SecretProject::replace_projects(&secret.uuid, &project_ids, &mut conn).await?;
+
+    let projects = Project::find_by_secret(&secret.uuid, &mut conn).await;
+    Ok(Json(secret.to_json(&projects, true, true)))
+}
+
+#[post("/secrets/delete", data = "<data>")]
+async fn post_delete_secrets(data: Json<Vec<SecretId>>, caller: SecretsManagerCaller, mut conn: DbConn) -> JsonResult {
+    let mut results = Vec::new();
+    for secret_id in data.into_inner() {
+        let error = match Secret::find_by_uuid(&secret_id, &mut conn).await {
+            Some(secret) => match caller.org_access(&secret.org_uuid, &mut conn).await {
+                Ok(access) if access.secret(&Project::find_by_secret(&secret.uuid, &mut conn).await).1 => {
+                    match secret.delete(&mut conn).await {
+                        Ok(()) => String::new(),
+                        Err(e) => format!("{e:?}"),
+                    }
+                }
+                _ => String::from("access denied"),
+            },
+            None => String::from("not found"),
+        };
+        results.push((secret_id.to_string(), error));
+    }
+
+    Ok(Json(bulk_response(results)))
+}
+
+#[get("/organizations/<org_id>/secrets/sync?<lastSyncedDate>")]
+#[allow(non_snake_case)]
+async fn get_org_secrets_sync(
+    org_id: OrganizationId,
+    lastSyncedDate: Option<String>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let access = caller.org_access(&org_id, &mut conn).await?;
+
+    // Organizations without a revision yet get one, from then on the deletes and access changes are tracked too
+    let last_synced = lastSyncedDate.and_then(|d| DateTime::parse_from_rfc3339(&d).ok()).map(|d| d.naive_utc());
+    let latest_revision = SecretsRevision::find_by_org(&org_id, &mut conn).await;
+    if latest_revision.is_none() {
+        SecretsRevision::bump(&org_id, &mut conn).await;
+    }
+    if let (Some(last_synced), Some(latest_revision)) = (last_synced, latest_revision) {
+        if latest_revision <= last_synced {
+            return Ok(Json(json!({
+                "hasChanges": false,
+                "secrets": null,
+                "object": "secretsSync",
+            })));
+        }
+    }
+
+    let mut secrets_json = Vec::new();
+    for secret in Secret::find_by_org(&org_id, &mut conn).await {
+        let projects = Project::find_by_secret(&secret.uuid, &mut conn).await;
+        let (read, write) = access.secret(&projects);
+        if read {
+            secrets_json.push(secret.to_json(&projects, read, write));
+        }
+    }
+
+    Ok(Json(json!({
+        "hasChanges": true,
+        "secrets": {
+            "data": secrets_json,
+            "object": "list",
+            "continuationToken": null,
+        },
+        "object": "secretsSync",
+    })))
+}
+
+//
+// Projects
+//
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProjectData {
+    name: String,
+}
+
+#[get("/organizations/<org_id>/projects")]
+async fn get_org_projects(org_id: OrganizationId, caller: SecretsManagerCaller, mut conn: DbConn) -> JsonResult {
+    let access = caller.org_access(&org_id, &mut conn).await?;
+    let projects_json: Vec<Value> = Project::find_by_org(&org_id, &mut conn)
+        .await
+        .iter()
+        .filter_map(|project| {
+            let (read, write) = access.project(&project.uuid);
+            read.then(|| project.to_json(read, write))
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "data": projects_json,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+#[post("/organizations/<org_id>/projects", data = "<data>")]
+async fn post_org_project(
+    org_id: OrganizationId,
+    data: Json<ProjectData>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    caller.check_admin(&org_id, &mut conn).await?;
+
+    let mut project = Project::new(org_id, data.into_inner().name);
+    project.save(&mut conn).await?;
+    Ok(Json(project.to_json(true, true)))
+}
+
+#[get("/projects/<project_id>")]
+async fn get_project(project_id: ProjectId, caller: SecretsManagerCaller, mut conn: DbConn) -> JsonResult {
+    let Some(project) = Project::find_by_uuid(&project_id, &mut conn).await else {
+        err!("Project not found")
+    };
+    let (read, write) = caller.org_access(&project.org_uuid, &mut conn).await?.project(&project.uuid);
+    if !read {
+        err!("Project not found", "No read access to the project")
+    }
+
+    Ok(Json(project.to_json(read, write)))
+}
+
+#[put("/projects/<project_id>", data = "<data>")]
+async fn put_project(
+    project_id: ProjectId,
+    data: Json<ProjectData>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let Some(mut project) = Project::find_by_uuid(&project_id, &mut conn).await else {
+        err!("Project not found")
+    };
+    if !caller.org_access(&project.org_uuid, &mut conn).await?.project(&project.uuid).1 {
+        err!("You don't have write access to this project")
+    }
+
+    project.name = data.into_inner().name;
+    project.save(&mut conn).await?;
+    Ok(Json(project.to_json(true, true)))
+}
+
+#[post("/projects/delete", data = "<data>")]
+async fn post_delete_projects(
+    data: Json<Vec<ProjectId>>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let mut results = Vec::new();
+    for project_id in data.into_inner() {
+        let error = match Project::find_by_uuid(&project_id, &mut conn).await {
+            Some(project) => match caller.org_access(&project.org_uuid, &mut conn).await {
+                // The secrets of the project are kept, they are only unassigned
+                Ok(access) if access.project(&project.uuid).1 => match project.delete(&mut conn).await {
+                    Ok(()) => String::new(),
+                    Err(e) => format!("{e:?}"),
+                },
+                _ => String::from("access denied"),
+            },
+            None => String::from("not found"),
+        };
+        results.push((project_id.to_string(), error));
+    }
+
+    Ok(Json(bulk_response(results)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessPolicyData {
+    grantee_id: ServiceAccountId,
+    read: bool,
+    write: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServiceAccountPoliciesData {
+    service_account_access_policy_requests: Vec<AccessPolicyData>,
+}
+
+async fn project_service_account_policies_json(project: &Project, conn: &mut DbConn) -> Value {
+    let mut policies_json = Vec::new();
+    for grant in ServiceAccountProject::find_by_project(&project.uuid, conn).await {
+        if let Some(service_account) = ServiceAccount::find_by_uuid(&grant.service_account_uuid, conn).await {
+            policies_json.push(grant.to_json(&service_account));
+        }
+    }
+
+    json!({
+        "serviceAccountAccessPolicies": policies_json,
+        "object": "projectServiceAccountsAccessPolicies",
+    })
+}
+
+#[get("/projects/<project_id>/access-policies/service-accounts")]
+async fn get_project_service_account_policies(
+    project_id: ProjectId,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let Some(project) = Project::find_by_uuid(&project_id, &mut conn).await else {
+        err!("Project not found")
+    };
+    caller.check_admin(&project.org_uuid, &mut conn).await?;
+
+    Ok(Json(project_service_account_policies_json(&project, &mut conn).await))
+}
+
+#[put("/projects/<project_id>/access-policies/service-accounts", data = "<data>")]
+async fn put_project_service_account_policies(
+    project_id: ProjectId,
+    data: Json<ServiceAccountPoliciesData>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let Some(project) = Project::find_by_uuid(&project_id, &mut conn).await else {
+        err!("Project not found")
+    };
+    caller.check_admin(&project.org_uuid, &mut conn).await?;
+
+    SecretsRevision::bump(&project.org_uuid, &mut conn).await;
+    ServiceAccountProject::delete_all_by_project(&project.uuid, &mut conn).await?;
+    for policy in data.into_inner().service_account_access_policy_requests {
+        match ServiceAccount::find_by_uuid(&policy.grantee_id, &mut conn).await {
+            Some(service_account) if service_account.org_uuid == project.org_uuid => {}
+            _ => err!("Machine account not found"),
+        }
+        if !policy.read && !policy.write {
+            continue;
+        }
+
+        ServiceAccountProject {
+            project_uuid: project.uuid.clone(),
+            service_account_uuid: policy.grantee_id,
+            // Write access without read access isn't useful, Bitwarden treats it the same way
+            can_read: true,
+            can_write: policy.write,
+        }
+        .save(&mut conn)
+        .await?;
+    }
+
+    Ok(Json(project_service_account_policies_json(&project, &mut conn).await))
+}
+
+//
+// Machine accounts
+//
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServiceAccountData {
+    name: String,
+}
+
+async fn get_admin_service_account(
+    service_account_id: &ServiceAccountId,
+    caller: &SecretsManagerCaller,
+    conn: &mut DbConn,
+) -> ApiResult<ServiceAccount> {
+    let Some(service_account) = ServiceAccount::find_by_uuid(service_account_id, conn).await else {
+        err!("Machine account not found")
+    };
+    caller.check_admin(&service_account.org_uuid, conn).await?;
+    Ok(service_account)
+}
+
+#[get("/organizations/<org_id>/service-accounts")]
+async fn get_org_service_accounts(
+    org_id: OrganizationId,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    caller.check_admin(&org_id, &mut conn).await?;
+    let service_accounts_json: Vec<Value> =
+        ServiceAccount::find_by_org(&org_id, &mut conn).await.iter().map(ServiceAccount::to_json).collect();
+
+    Ok(Json(json!({
+        "data": service_accounts_json,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+#[post("/organizations/<org_id>/service-accounts", data = "<data>")]
+async fn post_org_service_account(
+    org_id: OrganizationId,
+    data: Json<ServiceAccountData>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    caller.check_admin(&org_id, &mut conn).await?;
+
+    let mut service_account = ServiceAccount::new(org_id, data.into_inner().name);
+    service_account.save(&mut conn).await?;
+    Ok(Json(service_account.to_json()))
+}
+
+#[get("/service-accounts/<service_account_id>")]
+async fn get_service_account(
+    service_account_id: ServiceAccountId,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let service_account = get_admin_service_account(&service_account_id, &caller, &mut conn).await?;
+    Ok(Json(service_account.to_json()))
+}
+
+#[put("/service-accounts/<service_account_id>", data = "<data>")]
+async fn put_service_account(
+    service_account_id: ServiceAccountId,
+    data: Json<ServiceAccountData>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let mut service_account = get_admin_service_account(&service_account_id, &caller, &mut conn).await?;
+    service_account.name = data.into_inner().name;
+    service_account.save(&mut conn).await?;
+    Ok(Json(service_account.to_json()))
+}
+
+#[post("/service-accounts/delete", data = "<data>")]
+async fn post_delete_service_accounts(
+    data: Json<Vec<ServiceAccountId>>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let mut results = Vec::new();
+    for service_account_id in data.into_inner() {
+        let error = match get_admin_service_account(&service_account_id, &caller, &mut conn).await {
+            Ok(service_account) => match service_account.delete(&mut conn).await {
+                Ok(()) => String::new(),
+                Err(e) => format!("{e:?}"),
+            },
+            Err(_) => String::from("access denied"),
+        };
+        results.push((service_account_id.to_string(), error));
+    }
+
+    Ok(Json(bulk_response(results)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessTokenData {
+    name: String,
+    encrypted_payload: String,
+    key: String,
+    expire_at: Option<DateTime<Utc>>,
+}
+
+#[get("/service-accounts/<service_account_id>/access-tokens")]
+async fn get_access_tokens(
+    service_account_id: ServiceAccountId,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let service_account = get_admin_service_account(&service_account_id, &caller, &mut conn).await?;
+    let tokens_json: Vec<Value> = ServiceAccountToken::find_by_service_account(&service_account.uuid, &mut conn)
+        .await
+        .iter()
+        .map(ServiceAccountToken::to_json)
+        .collect();
+
+    Ok(Json(json!({
+        "data": tokens_json,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+#[post("/service-accounts/<service_account_id>/access-tokens", data = "<data>")]
+async fn post_access_token(
+    service_account_id: ServiceAccountId,
+    data: Json<AccessTokenData>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: AccessTokenData = data.into_inner();
+    let service_account = get_admin_service_account(&service_account_id, &caller, &mut conn).await?;
+
+    let (token, client_secret) = ServiceAccountToken::new(
+        service_account.uuid,
+        data.name,
+        data.encrypted_payload,
+        data.key,
+        data.expire_at.map(|d| d.naive_utc()),
+    );
+    token.save(&mut conn).await?;
+
+    // The client secret is only returned once, the client combines it with its own key into the access token
+    let mut token_json = token.to_json();
+    token_json["clientSecret"] = Value::String(client_secret);
+    token_json["object"] = json!("accessTokenCreation");
+    Ok(Json(token_json))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RevokeAccessTokensData {
+    ids: Vec<ServiceAccountTokenId>,
+}
+
+#[post("/service-accounts/<service_account_id>/access-tokens/revoke", data = "<data>")]
+async fn post_revoke_access_tokens(
+    service_account_id: ServiceAccountId,
+    data: Json<RevokeAccessTokensData>,
+    caller: SecretsManagerCaller,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let service_account = get_admin_service_account(&service_account_id, &caller, &mut conn).await?;
+    for token_id in data.into_inner().ids {
+        ServiceAccountToken::delete_by_uuid_and_service_account(&token_id, &service_account.uuid, &mut conn).await?;
+    }
+    Ok(())
+}
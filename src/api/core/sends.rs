@@ -4,6 +4,7 @@ use std::time::Duration;
 use chrono::{DateTime, TimeDelta, Utc};
 use num_traits::ToPrimitive;
 use once_cell::sync::Lazy;
+use reqwest::Method;
 use rocket::form::Form;
 use rocket::fs::NamedFile;
 use rocket::fs::TempFile;
@@ -12,10 +13,14 @@ use serde_json::Value;
 
 use crate::{
     api::{ApiResult, EmptyResult, JsonResult, Notify, UpdateType},
-    auth::{ClientIp, Headers, Host},
+    auth::{
+        file_url_expiration, sign_file_url, verify_file_url, AccessReportsHeaders, AdminHeaders, ClientIp, Headers,
+        Host,
+    },
     config::PathType,
     db::{models::*, DbConn, DbPool},
-    util::{save_temp_file, NumberOrString},
+    http_client::make_http_request,
+    util::{format_date, save_temp_file, spawn_background, Cached, NumberOrString},
     CONFIG,
 };
 
@@ -43,6 +48,11 @@ pub fn routes() -> Vec<rocket::Route> {
     routes![
         get_sends,
         get_send,
+        get_send_accesses,
+        get_org_send_access_report,
+        get_org_sends,
+        delete_org_send,
+        put_org_send_disable,
         post_send,
         post_send_file,
         post_access,
@@ -59,6 +69,7 @@ pub fn routes() -> Vec<rocket::Route> {
 pub async fn purge_sends(pool: DbPool) {
     debug!("Purging sends");
     if let Ok(mut conn) = pool.get().await {
+        notify_expired_sends(&mut conn).await;
         Send::purge(&mut conn).await;
     } else {
         error!("Failed to get DB connection while purging sends")
@@ -84,6 +95,9 @@ pub struct SendData {
     file: Option<Value>,
     file_length: Option<NumberOrString>,
 
+    // Vaultwarden specific and opt-in, creates the Send for an organization, see `enforce_org_send_policy`
+    organization_id: Option<OrganizationId>,
+
     // Used for key rotations
     pub id: Option<SendId>,
 }
@@ -96,8 +110,7 @@ pub struct SendData {
 ///
 /// There is also a Vaultwarden-specific `sends_allowed` config setting that
 /// controls this policy globally.
-async fn enforce_disable_send_policy(headers: &Headers, conn: &mut DbConn) -> EmptyResult {
-    let user_id = &headers.user.uuid;
+async fn enforce_disable_send_policy(user_id: &UserId, conn: &mut DbConn) -> EmptyResult {
     if !CONFIG.sends_allowed()
         || OrgPolicy::is_applicable_to_user(user_id, OrgPolicyType::DisableSend, None, conn).await
     {
@@ -112,8 +125,7 @@ async fn enforce_disable_send_policy(headers: &Headers, conn: &mut DbConn) -> Em
 /// but is allowed to remove this option from an existing Send.
 ///
 /// Ref: https://bitwarden.com/help/article/policies/#send-options
-async fn enforce_disable_hide_email_policy(data: &SendData, headers: &Headers, conn: &mut DbConn) -> EmptyResult {
-    let user_id = &headers.user.uuid;
+async fn enforce_disable_hide_email_policy(data: &SendData, user_id: &UserId, conn: &mut DbConn) -> EmptyResult {
     let hide_email = data.hide_email.unwrap_or(false);
     if hide_email && OrgPolicy::is_hide_email_disabled(user_id, conn).await {
         err!(
@@ -124,6 +136,50 @@ async fn enforce_disable_hide_email_policy(data: &SendData, headers: &Headers, c
     Ok(())
 }
 
+/// Checks a Send of an organization: the user has to be a confirmed member, and the Send has to follow the
+/// OrgSends policy of the organization. Personal Sends aren't checked.
+/// Creating a Send for an organization is opt-in: only API clients which send `organizationId` do, the
+/// official clients never send it and their Sends stay personal, so the policy doesn't restrict them.
+/// Use the Disable Send policy to keep the members from creating personal Sends.
+async fn enforce_org_send_policy(send: &Send, user_id: &UserId, conn: &mut DbConn) -> EmptyResult {
+    let Some(org_id) = &send.organization_uuid else {
+        return Ok(());
+    };
+    if Membership::find_confirmed_by_user_and_org(user_id, org_id, conn).await.is_none() {
+        err!("You can only create Sends for an organization you are a confirmed member of")
+    }
+
+    let Some(policy) = OrgPolicy::find_by_org_and_type(org_id, OrgPolicyType::OrgSends, conn).await else {
+        return Ok(());
+    };
+    if !policy.enabled {
+        return Ok(());
+    }
+    let policy_data = match serde_json::from_str::<OrgSendsPolicyData>(&policy.data) {
+        Ok(policy_data) => policy_data,
+        Err(_) => {
+            error!("Failed to deserialize OrgSendsPolicyData: {}", policy.data);
+            return Ok(());
+        }
+    };
+
+    if policy_data.allowed_types.as_ref().is_some_and(|types| !types.contains(&send.atype)) {
+        err!("Due to an Enterprise Policy, this type of Send can't be created for the organization.")
+    }
+    if policy_data.require_password && send.password_hash.is_none() {
+        err!("Due to an Enterprise Policy, the Sends of the organization need a password.")
+    }
+    if let Some(days) = policy_data.max_lifetime_days {
+        let latest = send.creation_date + TimeDelta::try_days(days).unwrap_or_default();
+        if send.deletion_date > latest || send.expiration_date.is_some_and(|date| date > latest) {
+            err!(format!(
+                "Due to an Enterprise Policy, the Sends of the organization have to be deleted within {days} days."
+            ))
+        }
+    }
+    Ok(())
+}
+
 fn create_send(data: SendData, user_id: UserId) -> ApiResult<Send> {
     let data_val = if data.r#type == SendType::Text as i32 {
         data.text
@@ -148,6 +204,7 @@ fn create_send(data: SendData, user_id: UserId) -> ApiResult<Send> {
 
     let mut send = Send::new(data.r#type, data.name, data_str, data.key, data.deletion_date.naive_utc());
     send.user_uuid = Some(user_id);
+    send.organization_uuid = data.organization_id;
     send.notes = data.notes;
     send.max_access_count = match data.max_access_count {
         Some(m) => Some(m.into_i32()?),
@@ -183,18 +240,150 @@ async fn get_send(send_id: SendId, headers: Headers, mut conn: DbConn) -> JsonRe
     }
 }
 
+#[get("/sends/<send_id>/accesses")]
+async fn get_send_accesses(send_id: SendId, headers: Headers, mut conn: DbConn) -> JsonResult {
+    let Some(send) = Send::find_by_uuid_and_user(&send_id, &headers.user.uuid, &mut conn).await else {
+        err!("Send not found", "Invalid send uuid or does not belong to user")
+    };
+
+    let accesses_json: Vec<Value> =
+        SendAccess::find_by_send(&send.uuid, &mut conn).await.iter().map(SendAccess::to_json).collect();
+    let (success_count, failed_count, first_access) = SendAccess::summary_by_send(&send.uuid, &mut conn).await;
+
+    Ok(Json(json!({
+        "sendId": send.uuid,
+        "successCount": success_count,
+        "failedCount": failed_count,
+        "firstAccessDate": first_access.as_ref().map(format_date),
+        "data": accesses_json,
+        "object": "sendAccessSummary",
+    })))
+}
+
+/// Per member counts of the Sends and their accesses, the Sends themselves stay private.
+#[get("/organizations/<org_id>/sends/access-report")]
+async fn get_org_send_access_report(
+    org_id: OrganizationId,
+    headers: AccessReportsHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+
+    let mut members_json = Vec::new();
+    for member in Membership::find_confirmed_by_org(&org_id, &mut conn).await {
+        let sends = Send::find_by_user(&member.user_uuid, &mut conn).await;
+        let (retrieved, success_count, failed_count) = SendAccess::counts_by_user(&member.user_uuid, &mut conn).await;
+
+        members_json.push(json!({
+            "memberId": member.uuid,
+            "userId": member.user_uuid,
+            "sendCount": sends.len(),
+            "retrievedCount": retrieved,
+            "successCount": success_count,
+            "failedCount": failed_count,
+            "object": "sendAccessReportMember",
+        }));
+    }
+
+    Ok(Json(json!({
+        "data": members_json,
+        "object": "list",
+        "continuationToken": null
+    })))
+}
+
+/// The Sends members created for the organization, for compliance review. Their content stays private.
+#[get("/organizations/<org_id>/sends")]
+async fn get_org_sends(org_id: OrganizationId, headers: AdminHeaders, mut conn: DbConn) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+
+    let mut sends_json = Vec::new();
+    for send in Send::find_by_org(&org_id, &mut conn).await {
+        sends_json.push(send.to_json_organization(&mut conn).await);
+    }
+
+    Ok(Json(json!({
+        "data": sends_json,
+        "object": "list",
+        "continuationToken": null
+    })))
+}
+
+#[delete("/organizations/<org_id>/sends/<send_id>")]
+async fn delete_org_send(
+    org_id: OrganizationId,
+    send_id: SendId,
+    headers: AdminHeaders,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> EmptyResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let Some(send) = Send::find_by_uuid_and_org(&send_id, &org_id, &mut conn).await else {
+        err!("Send not found", "Invalid send uuid, or does not belong to the organization")
+    };
+
+    send.delete(&mut conn).await?;
+    nt.send_send_update(
+        UpdateType::SyncSendDelete,
+        &send,
+        &send.update_users_revision(&mut conn).await,
+        &headers.device,
+        &mut conn,
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Stops a Send of the organization from being accessed, its creator can enable it again within the policy.
+#[put("/organizations/<org_id>/sends/<send_id>/disable")]
+async fn put_org_send_disable(
+    org_id: OrganizationId,
+    send_id: SendId,
+    headers: AdminHeaders,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let Some(mut send) = Send::find_by_uuid_and_org(&send_id, &org_id, &mut conn).await else {
+        err!("Send not found", "Invalid send uuid, or does not belong to the organization")
+    };
+
+    send.disabled = true;
+    send.save(&mut conn).await?;
+    nt.send_send_update(
+        UpdateType::SyncSendUpdate,
+        &send,
+        &send.update_users_revision(&mut conn).await,
+        &headers.device,
+        &mut conn,
+    )
+    .await;
+
+    Ok(Json(send.to_json_organization(&mut conn).await))
+}
+
 #[post("/sends", data = "<data>")]
 async fn post_send(data: Json<SendData>, headers: Headers, mut conn: DbConn, nt: Notify<'_>) -> JsonResult {
-    enforce_disable_send_policy(&headers, &mut conn).await?;
+    enforce_disable_send_policy(&headers.user.uuid, &mut conn).await?;
 
     let data: SendData = data.into_inner();
-    enforce_disable_hide_email_policy(&data, &headers, &mut conn).await?;
+    enforce_disable_hide_email_policy(&data, &headers.user.uuid, &mut conn).await?;
 
     if data.r#type == SendType::File as i32 {
         err!("File sends should use /api/sends/file")
     }
 
-    let mut send = create_send(data, headers.user.uuid)?;
+    let mut send = create_send(data, headers.user.uuid.clone())?;
+    enforce_org_send_policy(&send, &headers.user.uuid, &mut conn).await?;
     send.save(&mut conn).await?;
     nt.send_send_update(
         UpdateType::SyncSendCreate,
@@ -208,6 +397,36 @@ async fn post_send(data: Json<SendData>, headers: Headers, mut conn: DbConn, nt:
     Ok(Json(send.to_json()))
 }
 
+/// Creates a text Send for a script using a personal API token. There is no device of the user which made the change,
+/// so the update is pushed as if it came from an anonymous access.
+pub async fn create_personal_text_send(
+    data: SendData,
+    user_id: &UserId,
+    conn: &mut DbConn,
+    nt: Notify<'_>,
+) -> JsonResult {
+    enforce_disable_send_policy(user_id, conn).await?;
+    enforce_disable_hide_email_policy(&data, user_id, conn).await?;
+
+    if data.r#type != SendType::Text as i32 {
+        err!("Only text Sends can be created with a personal API token")
+    }
+
+    let mut send = create_send(data, user_id.clone())?;
+    enforce_org_send_policy(&send, user_id, conn).await?;
+    send.save(conn).await?;
+    nt.send_send_update(
+        UpdateType::SyncSendCreate,
+        &send,
+        &send.update_users_revision(conn).await,
+        &ANON_PUSH_DEVICE,
+        conn,
+    )
+    .await;
+
+    Ok(Json(send.to_json()))
+}
+
 #[derive(FromForm)]
 struct UploadData<'f> {
     model: Json<SendData>,
@@ -226,7 +445,7 @@ struct UploadDataV2<'f> {
 // See: https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Api/Tools/Controllers/SendsController.cs
 #[post("/sends/file", format = "multipart/form-data", data = "<data>")]
 async fn post_send_file(data: Form<UploadData<'_>>, headers: Headers, mut conn: DbConn, nt: Notify<'_>) -> JsonResult {
-    enforce_disable_send_policy(&headers, &mut conn).await?;
+    enforce_disable_send_policy(&headers.user.uuid, &mut conn).await?;
 
     let UploadData {
         model,
@@ -241,9 +460,9 @@ async fn post_send_file(data: Form<UploadData<'_>>, headers: Headers, mut conn:
         err!("Send size can't be negative")
     }
 
-    enforce_disable_hide_email_policy(&model, &headers, &mut conn).await?;
+    enforce_disable_hide_email_policy(&model, &headers.user.uuid, &mut conn).await?;
 
-    let size_limit = match CONFIG.user_send_limit() {
+    let size_limit = match headers.user.effective_send_limit() {
         Some(0) => err!("File uploads are disabled"),
         Some(limit_kb) => {
             let Some(already_used) = Send::size_by_user(&headers.user.uuid, &mut conn).await else {
@@ -264,10 +483,16 @@ async fn post_send_file(data: Form<UploadData<'_>>, headers: Headers, mut conn:
         err!("Send storage limit exceeded with this file");
     }
 
-    let mut send = create_send(model, headers.user.uuid)?;
+    let mut send = create_send(model, headers.user.uuid.clone())?;
     if send.atype != SendType::File as i32 {
         err!("Send content is not a file");
     }
+    enforce_org_send_policy(&send, &headers.user.uuid, &mut conn).await?;
+
+    if let Some(signature) = crate::virus_scan::scan_temp_file(&data).await? {
+        quarantine_send_file(&send, size, signature, &mut conn).await;
+        err!("The file was rejected by the virus scanner")
+    }
 
     let file_id = crate::crypto::generate_send_file_id();
 
@@ -298,7 +523,7 @@ async fn post_send_file(data: Form<UploadData<'_>>, headers: Headers, mut conn:
 // Upstream: https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Api/Tools/Controllers/SendsController.cs#L165
 #[post("/sends/file/v2", data = "<data>")]
 async fn post_send_file_v2(data: Json<SendData>, headers: Headers, mut conn: DbConn) -> JsonResult {
-    enforce_disable_send_policy(&headers, &mut conn).await?;
+    enforce_disable_send_policy(&headers.user.uuid, &mut conn).await?;
 
     let data = data.into_inner();
 
@@ -306,7 +531,7 @@ async fn post_send_file_v2(data: Json<SendData>, headers: Headers, mut conn: DbC
         err!("Send content is not a file");
     }
 
-    enforce_disable_hide_email_policy(&data, &headers, &mut conn).await?;
+    enforce_disable_hide_email_policy(&data, &headers.user.uuid, &mut conn).await?;
 
     let file_length = match &data.file_length {
         Some(m) => m.into_i64()?,
@@ -316,7 +541,7 @@ async fn post_send_file_v2(data: Json<SendData>, headers: Headers, mut conn: DbC
         err!("Send size can't be negative")
     }
 
-    let size_limit = match CONFIG.user_send_limit() {
+    let size_limit = match headers.user.effective_send_limit() {
         Some(0) => err!("File uploads are disabled"),
         Some(limit_kb) => {
             let Some(already_used) = Send::size_by_user(&headers.user.uuid, &mut conn).await else {
@@ -337,7 +562,8 @@ async fn post_send_file_v2(data: Json<SendData>, headers: Headers, mut conn: DbC
         err!("Send storage limit exceeded with this file");
     }
 
-    let mut send = create_send(data, headers.user.uuid)?;
+    let mut send = create_send(data, headers.user.uuid.clone())?;
+    enforce_org_send_policy(&send, &headers.user.uuid, &mut conn).await?;
 
     let file_id = crate::crypto::generate_send_file_id();
 
@@ -367,6 +593,18 @@ pub struct SendFileData {
 }
 
 // https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Api/Tools/Controllers/SendsController.cs#L195
+async fn quarantine_send_file(send: &Send, size: i64, signature: String, conn: &mut DbConn) {
+    warn!("Send file upload for send {} rejected by the virus scanner: {signature}", send.uuid);
+    let Some(user_id) = send.user_uuid.clone() else {
+        return;
+    };
+    let mut quarantined = QuarantinedFile::new(user_id, send.organization_uuid.clone(), size, signature);
+    quarantined.send_uuid = Some(send.uuid.clone());
+    if let Err(e) = quarantined.save(conn).await {
+        error!("Failed to save the quarantined file of send {}: {e:?}", send.uuid);
+    }
+}
+
 #[post("/sends/<send_id>/file/<file_id>", format = "multipart/form-data", data = "<data>")]
 async fn post_send_file_v2_data(
     send_id: SendId,
@@ -376,7 +614,7 @@ async fn post_send_file_v2_data(
     mut conn: DbConn,
     nt: Notify<'_>,
 ) -> EmptyResult {
-    enforce_disable_send_policy(&headers, &mut conn).await?;
+    enforce_disable_send_policy(&headers.user.uuid, &mut conn).await?;
 
     let data = data.into_inner();
 
@@ -421,6 +659,13 @@ async fn post_send_file_v2_data(
         err!("Send file size does not match.", format!("Expected a file size of {} got {size}", send_data.size));
     }
 
+    if let Some(signature) = crate::virus_scan::scan_temp_file(&data.data).await? {
+        // The Send was already created for this upload, without its file it is useless
+        quarantine_send_file(&send, size.to_i64().unwrap_or_default(), signature, &mut conn).await;
+        send.delete(&mut conn).await?;
+        err!("The file was rejected by the virus scanner")
+    }
+
     let file_path = format!("{send_id}/{file_id}");
 
     save_temp_file(PathType::Sends, &file_path, data.data, false).await?;
@@ -478,7 +723,10 @@ async fn post_access(
     if send.password_hash.is_some() {
         match data.into_inner().password {
             Some(ref p) if send.check_password(p) => { /* Nothing to do here */ }
-            Some(_) => err!("Invalid password", format!("IP: {}.", ip.ip)),
+            Some(_) => {
+                record_send_access(&send, &ip, false, &mut conn).await;
+                err!("Invalid password", format!("IP: {}.", ip.ip))
+            }
             None => err_code!("Password not provided", format!("IP: {}.", ip.ip), 401),
         }
     }
@@ -490,6 +738,10 @@ async fn post_access(
 
     send.save(&mut conn).await?;
 
+    if send.atype == SendType::Text as i32 {
+        record_send_access(&send, &ip, true, &mut conn).await;
+    }
+
     nt.send_send_update(
         UpdateType::SyncSendUpdate,
         &send,
@@ -509,6 +761,7 @@ async fn post_access_file(
     data: Json<SendAccessData>,
     host: Host,
     mut conn: DbConn,
+    ip: ClientIp,
     nt: Notify<'_>,
 ) -> JsonResult {
     let Some(mut send) = Send::find_by_uuid(&send_id, &mut conn).await else {
@@ -538,7 +791,10 @@ async fn post_access_file(
     if send.password_hash.is_some() {
         match data.into_inner().password {
             Some(ref p) if send.check_password(p) => { /* Nothing to do here */ }
-            Some(_) => err!("Invalid password."),
+            Some(_) => {
+                record_send_access(&send, &ip, false, &mut conn).await;
+                err!("Invalid password.")
+            }
             None => err_code!("Password not provided", 401),
         }
     }
@@ -546,6 +802,7 @@ async fn post_access_file(
     send.access_count += 1;
 
     send.save(&mut conn).await?;
+    record_send_access(&send, &ip, true, &mut conn).await;
 
     nt.send_send_update(
         UpdateType::SyncSendUpdate,
@@ -563,27 +820,102 @@ async fn post_access_file(
     })))
 }
 
+/// Records an access attempt, a successful one is a retrieval of the Send content.
+/// Fires the `SEND_WEBHOOK_URL` webhook on the first retrieval and when the max access count is reached.
+async fn record_send_access(send: &Send, ip: &ClientIp, success: bool, conn: &mut DbConn) {
+    let first_access = success && SendAccess::count_by_send(&send.uuid, true, conn).await == 0;
+    if let Err(e) = SendAccess::new(send.uuid.clone(), &ip.ip, success).save(conn).await {
+        error!("Error saving send access: {e:#?}");
+    }
+
+    if first_access {
+        send_webhook("send.first_access", send, None);
+    }
+    if success && send.max_access_count.is_some_and(|max| send.access_count >= max) {
+        send_webhook("send.expired", send, Some("maxAccessCount"));
+    }
+}
+
+async fn notify_expired_sends(conn: &mut DbConn) {
+    if CONFIG.send_webhook_url().is_none() {
+        return;
+    }
+
+    for mut send in Send::find_expired_unnotified(conn).await {
+        send_webhook("send.expired", &send, Some("expirationDate"));
+        send.expiration_notified = true;
+        if let Err(e) = send.save(conn).await {
+            error!("Error saving send: {e:#?}");
+        }
+    }
+}
+
+/// Posts the event to `SEND_WEBHOOK_URL` in the background, failures are only logged.
+fn send_webhook(event: &'static str, send: &Send, reason: Option<&'static str>) {
+    let Some(url) = CONFIG.send_webhook_url() else {
+        return;
+    };
+
+    let payload = json!({
+        "event": event,
+        "sendId": send.uuid,
+        "userId": send.user_uuid,
+        "reason": reason,
+        "date": format_date(&Utc::now().naive_utc()),
+    });
+
+    spawn_background(async move {
+        let req = match make_http_request(Method::POST, &url) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("An error occurred while sending the {event} send webhook: {e}");
+                return;
+            }
+        };
+        match req.json(&payload).send().await.and_then(|r| r.error_for_status()) {
+            Ok(_) => debug!("Sent the {event} send webhook"),
+            Err(e) => error!("An error occurred while sending the {event} send webhook: {e}"),
+        }
+    });
+}
+
 async fn download_url(host: &Host, send_id: &SendId, file_id: &SendFileId) -> Result<String, crate::Error> {
     let operator = CONFIG.opendal_operator_for_path_type(PathType::Sends)?;
 
     if operator.info().scheme() == opendal::Scheme::Fs {
-        let token_claims = crate::auth::generate_send_claims(send_id, file_id);
-        let token = crate::auth::encode_jwt(&token_claims);
+        let expires = file_url_expiration();
+        let signature = sign_file_url(&format!("sends/{send_id}/{file_id}"), expires);
 
-        Ok(format!("{}/api/sends/{send_id}/{file_id}?t={token}", &host.host))
+        Ok(format!("{}/api/sends/{send_id}/{file_id}?expires={expires}&signature={signature}", &host.host))
     } else {
-        Ok(operator.presign_read(&format!("{send_id}/{file_id}"), Duration::from_secs(5 * 60)).await?.uri().to_string())
+        let ttl = Duration::from_secs(CONFIG.file_download_url_ttl());
+        Ok(operator.presign_read(&format!("{send_id}/{file_id}"), ttl).await?.uri().to_string())
     }
 }
 
-#[get("/sends/<send_id>/<file_id>?<t>")]
-async fn download_send(send_id: SendId, file_id: SendFileId, t: &str) -> Option<NamedFile> {
-    if let Ok(claims) = crate::auth::decode_send(t) {
-        if claims.sub == format!("{send_id}/{file_id}") {
-            return NamedFile::open(Path::new(&CONFIG.sends_folder()).join(send_id).join(file_id)).await.ok();
-        }
+/// Downloads a Send file with the signed url given when the Send is accessed.
+/// Deleted Sends are removed from the database, which revokes their urls, and the urls of Sends which expired
+/// or were disabled since don't work anymore either. The access count was already checked by the access.
+#[get("/sends/<send_id>/<file_id>?<expires>&<signature>")]
+async fn download_send(
+    send_id: SendId,
+    file_id: SendFileId,
+    expires: i64,
+    signature: &str,
+    mut conn: DbConn,
+) -> Option<Cached<NamedFile>> {
+    let send = Send::find_by_uuid(&send_id, &mut conn).await?;
+    let now = Utc::now().naive_utc();
+    if send.disabled || now >= send.deletion_date || send.expiration_date.is_some_and(|expiration| now >= expiration) {
+        return None;
     }
-    None
+    if !verify_file_url(&format!("sends/{send_id}/{file_id}"), expires, signature) {
+        return None;
+    }
+
+    let file = NamedFile::open(Path::new(&CONFIG.sends_folder()).join(send_id).join(file_id)).await.ok()?;
+    let ttl = (expires - Utc::now().timestamp()).max(0) as u64;
+    Some(Cached::ttl(file, ttl, true))
 }
 
 #[put("/sends/<send_id>", data = "<data>")]
@@ -594,10 +926,10 @@ async fn put_send(
     mut conn: DbConn,
     nt: Notify<'_>,
 ) -> JsonResult {
-    enforce_disable_send_policy(&headers, &mut conn).await?;
+    enforce_disable_send_policy(&headers.user.uuid, &mut conn).await?;
 
     let data: SendData = data.into_inner();
-    enforce_disable_hide_email_policy(&data, &headers, &mut conn).await?;
+    enforce_disable_hide_email_policy(&data, &headers.user.uuid, &mut conn).await?;
 
     let Some(mut send) = Send::find_by_uuid_and_user(&send_id, &headers.user.uuid, &mut conn).await else {
         err!("Send not found", "Send send_id is invalid or does not belong to user")
@@ -624,6 +956,11 @@ pub async fn update_send_from_data(
         err!("Sends can't change type")
     }
 
+    // The clients don't know the organization of a Send, so it is only compared when it is sent
+    if data.organization_id.as_ref().is_some_and(|org_id| send.organization_uuid.as_ref() != Some(org_id)) {
+        err!("Sends can't change organization")
+    }
+
     if data.deletion_date > Utc::now() + TimeDelta::try_days(31).unwrap() {
         err!(
             "You cannot have a Send with a deletion date that far into the future. Adjust the Deletion Date to a value less than 31 days from now and try again."
@@ -650,7 +987,11 @@ pub async fn update_send_from_data(
         Some(m) => Some(m.into_i32()?),
         _ => None,
     };
-    send.expiration_date = data.expiration_date.map(|d| d.naive_utc());
+    let expiration_date = data.expiration_date.map(|d| d.naive_utc());
+    if send.expiration_date != expiration_date {
+        send.expiration_notified = false;
+    }
+    send.expiration_date = expiration_date;
     send.hide_email = data.hide_email;
     send.disabled = data.disabled;
 
@@ -659,6 +1000,7 @@ pub async fn update_send_from_data(
         send.set_password(Some(&password));
     }
 
+    enforce_org_send_policy(send, &headers.user.uuid, conn).await?;
     send.save(conn).await?;
     if ut != UpdateType::None {
         nt.send_send_update(ut, send, &send.update_users_revision(conn).await, &headers.device, conn).await;
@@ -687,13 +1029,14 @@ async fn delete_send(send_id: SendId, headers: Headers, mut conn: DbConn, nt: No
 
 #[put("/sends/<send_id>/remove-password")]
 async fn put_remove_password(send_id: SendId, headers: Headers, mut conn: DbConn, nt: Notify<'_>) -> JsonResult {
-    enforce_disable_send_policy(&headers, &mut conn).await?;
+    enforce_disable_send_policy(&headers.user.uuid, &mut conn).await?;
 
     let Some(mut send) = Send::find_by_uuid_and_user(&send_id, &headers.user.uuid, &mut conn).await else {
         err!("Send not found", "Invalid send uuid, or does not belong to user")
     };
 
     send.set_password(None);
+    enforce_org_send_policy(&send, &headers.user.uuid, &mut conn).await?;
     send.save(&mut conn).await?;
     nt.send_send_update(
         UpdateType::SyncSendUpdate,
@@ -0,0 +1,140 @@
+use num_traits::FromPrimitive;
+use rocket::{serde::json::Json, Route};
+use serde_json::Value;
+
+use crate::{
+    api::JsonResult,
+    auth::OwnerHeaders,
+    db::{models::*, DbConn},
+    mail, CONFIG,
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![get_sensitive_notifications, put_sensitive_notifications]
+}
+
+const SENSITIVE_ACTIONS: [(SensitiveAction, &str); 4] = [
+    (SensitiveAction::RoleEscalation, "roleEscalation"),
+    (SensitiveAction::PolicyDisabled, "policyDisabled"),
+    (SensitiveAction::VaultExport, "vaultExport"),
+    (SensitiveAction::ApiMembershipChange, "apiMembershipChange"),
+];
+
+fn sensitive_notifications_json(member: &Membership) -> Value {
+    let mut json = json!({
+        "object": "sensitiveActionNotifications",
+    });
+    for (action, key) in SENSITIVE_ACTIONS {
+        json[key] = json!(member.wants_sensitive_notification(action));
+    }
+    json
+}
+
+#[get("/organizations/<org_id>/sensitive-notifications")]
+async fn get_sensitive_notifications(org_id: OrganizationId, headers: OwnerHeaders, mut conn: DbConn) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let Some(member) = Membership::find_by_user_and_org(&headers.user.uuid, &org_id, &mut conn).await else {
+        err!("The current user isn't member of the organization")
+    };
+
+    Ok(Json(sensitive_notifications_json(&member)))
+}
+
+// Every key is optional, missing keys keep their current value
+#[put("/organizations/<org_id>/sensitive-notifications", data = "<data>")]
+async fn put_sensitive_notifications(
+    org_id: OrganizationId,
+    data: Json<Value>,
+    headers: OwnerHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let Some(mut member) = Membership::find_by_user_and_org(&headers.user.uuid, &org_id, &mut conn).await else {
+        err!("The current user isn't member of the organization")
+    };
+
+    let data = data.into_inner();
+    for (action, key) in SENSITIVE_ACTIONS {
+        match data.get(key) {
+            None | Some(Value::Null) => {}
+            Some(Value::Bool(true)) => member.sensitive_notifications |= action as i32,
+            Some(Value::Bool(false)) => member.sensitive_notifications &= !(action as i32),
+            Some(_) => err!(format!("Invalid value for {key}, expected a boolean")),
+        }
+    }
+    member.save(&mut conn).await?;
+
+    Ok(Json(sensitive_notifications_json(&member)))
+}
+
+/// Mails the confirmed owners of an organization about a sensitive action, honoring their preferences.
+/// The owner who performed the action is not notified about it.
+pub async fn notify_sensitive_action(
+    org_id: &OrganizationId,
+    action: SensitiveAction,
+    details: &str,
+    actor: &str,
+    actor_id: Option<&UserId>,
+    conn: &mut DbConn,
+) {
+    if !CONFIG.mail_enabled() {
+        return;
+    }
+    let Some(org) = Organization::find_by_uuid(org_id, conn).await else {
+        return;
+    };
+
+    for owner in Membership::find_by_org_and_type(org_id, MembershipType::Owner, conn).await {
+        if owner.status != MembershipStatus::Confirmed as i32
+            || !owner.wants_sensitive_notification(action)
+            || actor_id == Some(&owner.user_uuid)
+        {
+            continue;
+        }
+        let Some(user) = User::find_by_uuid(&owner.user_uuid, conn).await else {
+            continue;
+        };
+        if let Err(e) = mail::send_sensitive_action(&user.email, &org.name, action.description(), details, actor).await
+        {
+            error!("Error sending sensitive action notification to {}: {e:#?}", user.email);
+        }
+    }
+}
+
+/// Notifies the owners when a member was granted a higher role than `old_type`.
+pub async fn notify_role_escalation(
+    member: &Membership,
+    old_type: i32,
+    actor: &str,
+    actor_id: Option<&UserId>,
+    conn: &mut DbConn,
+) {
+    let (Some(old_type), Some(new_type)) = (MembershipType::from_i32(old_type), MembershipType::from_i32(member.atype))
+    else {
+        return;
+    };
+    if new_type <= old_type {
+        return;
+    }
+
+    let email = match User::find_by_uuid(&member.user_uuid, conn).await {
+        Some(user) => user.email,
+        None => member.user_uuid.to_string(),
+    };
+    let details = format!("{email} was changed from {} to {}", role_name(old_type), role_name(new_type));
+    notify_sensitive_action(&member.org_uuid, SensitiveAction::RoleEscalation, &details, actor, actor_id, conn).await;
+}
+
+fn role_name(atype: MembershipType) -> &'static str {
+    match atype {
+        MembershipType::Owner => "Owner",
+        MembershipType::Admin => "Admin",
+        MembershipType::User => "User",
+        // The manager role is presented as the custom role to the clients
+        MembershipType::Manager => "Custom",
+    }
+}
@@ -7,7 +7,7 @@ use crate::{
     auth::{ClientIp, Headers},
     crypto,
     db::{
-        models::{EventType, TwoFactor, TwoFactorType, UserId},
+        models::{AdminTwoFactor, EventType, TwoFactor, TwoFactorType, UserId},
         DbConn,
     },
     util::NumberOrString,
@@ -180,6 +180,42 @@ pub async fn validate_totp_code(
     );
 }
 
+/// Validates a TOTP code of the admin panel login, see `ADMIN_TWO_FACTOR`.
+/// While setting up the authenticator `twofactor` is the new one, it is only saved when the code is correct.
+pub async fn validate_admin_totp_code(
+    mut twofactor: AdminTwoFactor,
+    totp_code: &str,
+    ip: &ClientIp,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    use totp_lite::{totp_custom, Sha1};
+
+    if !totp_code.chars().all(char::is_numeric) {
+        err!("TOTP code is not a number");
+    }
+
+    let Ok(decoded_secret) = BASE32.decode(twofactor.data.as_bytes()) else {
+        err!("Invalid TOTP secret")
+    };
+
+    let steps = i64::from(!CONFIG.authenticator_disable_time_drift());
+    let current_timestamp = chrono::Utc::now().timestamp();
+
+    for step in -steps..=steps {
+        let time_step = current_timestamp / 30i64 + step;
+        let time = (current_timestamp + step * 30i64) as u64;
+        let generated = totp_custom::<Sha1>(30, 6, &decoded_secret, time);
+
+        // Like for the users, a code of an already used time step is not accepted again
+        if generated == totp_code && time_step > twofactor.last_used {
+            twofactor.last_used = time_step;
+            return twofactor.save(conn).await;
+        }
+    }
+
+    err!("Invalid TOTP code", format!("Invalid admin TOTP code. IP: {}", ip.ip))
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct DisableAuthenticatorData {
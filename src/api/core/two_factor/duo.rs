@@ -5,13 +5,16 @@ use rocket::Route;
 
 use crate::{
     api::{
-        core::log_user_event, core::two_factor::_generate_recover_code, ApiResult, EmptyResult, JsonResult,
-        PasswordOrOtpData,
+        core::{
+            log_event, log_user_event,
+            two_factor::{_generate_recover_code, duo_oidc},
+        },
+        ApiResult, EmptyResult, JsonResult, PasswordOrOtpData,
     },
-    auth::Headers,
+    auth::{Headers, OwnerHeaders},
     crypto,
     db::{
-        models::{EventType, TwoFactor, TwoFactorType, User, UserId},
+        models::{EventType, OrgTwoFactor, OrganizationId, TwoFactor, TwoFactorType, User, UserId},
         DbConn,
     },
     error::MapResult,
@@ -20,7 +23,7 @@ use crate::{
 };
 
 pub fn routes() -> Vec<Route> {
-    routes![get_duo, activate_duo, activate_duo_put,]
+    routes![get_duo, activate_duo, activate_duo_put, get_org_duo, activate_org_duo, activate_org_duo_put]
 }
 
 #[derive(Serialize, Deserialize)]
@@ -172,7 +175,7 @@ async fn activate_duo(data: Json<EnableDuoData>, headers: Headers, mut conn: DbC
     let (data, data_str) = if check_duo_fields_custom(&data) {
         let data_req: DuoData = data.into();
         let data_str = serde_json::to_string(&data_req)?;
-        duo_api_request("GET", "/auth/v2/check", "", &data_req).await.map_res("Failed to validate Duo credentials")?;
+        validate_duo_credentials(&data_req).await?;
         (data_req.obscure(), data_str)
     } else {
         (DuoData::secret(), String::new())
@@ -200,6 +203,122 @@ async fn activate_duo_put(data: Json<EnableDuoData>, headers: Headers, conn: DbC
     activate_duo(data, headers, conn).await
 }
 
+#[post("/organizations/<org_id>/two-factor/get-duo", data = "<data>")]
+async fn get_org_duo(
+    org_id: OrganizationId,
+    data: Json<PasswordOrOtpData>,
+    headers: OwnerHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let data: PasswordOrOtpData = data.into_inner();
+
+    data.validate(&headers.user, false, &mut conn).await?;
+
+    let type_ = TwoFactorType::OrganizationDuo as i32;
+    let data: Option<DuoData> = OrgTwoFactor::find_by_org_and_type(&org_id, type_, &mut conn)
+        .await
+        .filter(|tf| tf.enabled)
+        .and_then(|tf| serde_json::from_str(&tf.data).ok());
+    let data = data.map(DuoData::obscure);
+
+    Ok(Json(json!({
+        "enabled": data.is_some(),
+        "host": data.as_ref().map(|d| &d.host),
+        "clientSecret": data.as_ref().map(|d| &d.sk),
+        "clientId": data.as_ref().map(|d| &d.ik),
+        "object": "twoFactorDuo"
+    })))
+}
+
+/// Configures Duo for the whole organization, the confirmed members are able to use it as a provider during login.
+/// Only the Universal Prompt supports organization keys, the legacy iframe flow can only use the keys of the user.
+#[post("/organizations/<org_id>/two-factor/duo", data = "<data>")]
+async fn activate_org_duo(
+    org_id: OrganizationId,
+    data: Json<EnableDuoData>,
+    headers: OwnerHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    if CONFIG.duo_use_iframe() {
+        err!("Organization Duo is only available with the Duo Universal Prompt, `DUO_USE_IFRAME` has to be disabled")
+    }
+    let data: EnableDuoData = data.into_inner();
+
+    PasswordOrOtpData {
+        master_password_hash: data.master_password_hash.clone(),
+        otp: data.otp.clone(),
+    }
+    .validate(&headers.user, true, &mut conn)
+    .await?;
+
+    // The global keys are only meant for users, an organization has to provide its own
+    if !check_duo_fields_custom(&data) {
+        err!("The host, client id and client secret of the Duo application are required")
+    }
+
+    let data_req: DuoData = data.into();
+    let data_str = serde_json::to_string(&data_req)?;
+    validate_duo_credentials(&data_req).await?;
+
+    let type_ = TwoFactorType::OrganizationDuo as i32;
+    let mut twofactor = match OrgTwoFactor::find_by_org_and_type(&org_id, type_, &mut conn).await {
+        Some(mut twofactor) => {
+            twofactor.enabled = true;
+            twofactor.data = data_str;
+            twofactor
+        }
+        None => OrgTwoFactor::new(org_id.clone(), TwoFactorType::OrganizationDuo, data_str),
+    };
+    twofactor.save(&mut conn).await?;
+
+    log_event(
+        EventType::OrganizationUpdated as i32,
+        org_id.as_ref(),
+        &org_id,
+        &headers.user.uuid,
+        headers.device.atype,
+        &headers.ip.ip,
+        &mut conn,
+    )
+    .await;
+
+    let data = data_req.obscure();
+    Ok(Json(json!({
+        "enabled": true,
+        "host": data.host,
+        "clientSecret": data.sk,
+        "clientId": data.ik,
+        "object": "twoFactorDuo"
+    })))
+}
+
+#[put("/organizations/<org_id>/two-factor/duo", data = "<data>")]
+async fn activate_org_duo_put(
+    org_id: OrganizationId,
+    data: Json<EnableDuoData>,
+    headers: OwnerHeaders,
+    conn: DbConn,
+) -> JsonResult {
+    activate_org_duo(org_id, data, headers, conn).await
+}
+
+// Makes sure the credentials work before saving them, with the API matching the configured prompt
+async fn validate_duo_credentials(data: &DuoData) -> EmptyResult {
+    if CONFIG.duo_use_iframe() {
+        duo_api_request("GET", "/auth/v2/check", "", data).await.map_res("Failed to validate Duo credentials")
+    } else {
+        duo_oidc::check_duo_credentials(&data.ik, &data.sk, &data.host)
+            .await
+            .map_res("Failed to validate Duo credentials")
+    }
+}
+
 async fn duo_api_request(method: &str, path: &str, params: &str, data: &DuoData) -> EmptyResult {
     use reqwest::{header, Method};
     use std::str::FromStr;
@@ -264,6 +383,34 @@ pub(crate) async fn get_duo_keys_email(email: &str, conn: &mut DbConn) -> ApiRes
     Ok((data.ik, data.sk, CONFIG.get_duo_akey().await, data.host))
 }
 
+// let (ik, sk, ak, host) = get_org_duo_keys_email();
+// The keys of the first organization with Duo enabled the user is a confirmed member of.
+async fn get_org_duo_keys_email(email: &str, conn: &mut DbConn) -> ApiResult<(String, String, String, String)> {
+    let Some(user) = User::find_by_mail(email, conn).await else {
+        err!("Can't fetch Duo Keys")
+    };
+
+    let type_ = TwoFactorType::OrganizationDuo as i32;
+    let data: DuoData = OrgTwoFactor::find_enabled_by_confirmed_user_and_type(&user.uuid, type_, conn)
+        .await
+        .into_iter()
+        .find_map(|tf| serde_json::from_str(&tf.data).ok())
+        .map_res("Can't fetch Duo Keys")?;
+
+    Ok((data.ik, data.sk, CONFIG.get_duo_akey().await, data.host))
+}
+
+pub(crate) async fn get_duo_keys_provider(
+    provider: TwoFactorType,
+    email: &str,
+    conn: &mut DbConn,
+) -> ApiResult<(String, String, String, String)> {
+    match provider {
+        TwoFactorType::OrganizationDuo => get_org_duo_keys_email(email, conn).await,
+        _ => get_duo_keys_email(email, conn).await,
+    }
+}
+
 pub async fn generate_duo_signature(email: &str, conn: &mut DbConn) -> ApiResult<(String, String)> {
     let now = Utc::now().timestamp();
 
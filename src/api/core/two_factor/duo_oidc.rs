@@ -7,10 +7,10 @@ use serde::Serialize;
 use std::collections::HashMap;
 
 use crate::{
-    api::{core::two_factor::duo::get_duo_keys_email, EmptyResult},
+    api::{core::two_factor::duo::get_duo_keys_provider, EmptyResult},
     crypto,
     db::{
-        models::{DeviceId, EventType, TwoFactorDuoContext},
+        models::{DeviceId, EventType, TwoFactorDuoContext, TwoFactorType},
         DbConn, DbPool,
     },
     error::Error,
@@ -374,15 +374,26 @@ fn make_callback_url(client_name: &str) -> Result<String, Error> {
     Ok(callback.to_string())
 }
 
+// Checks Duo credentials against the health check endpoint before they are saved.
+pub(crate) async fn check_duo_credentials(client_id: &str, client_secret: &str, api_host: &str) -> EmptyResult {
+    // The redirect URI isn't part of the health check, the web vault one is used to validate the domain configuration
+    let callback_url = make_callback_url("web")?;
+    let client = DuoClient::new(client_id.to_string(), client_secret.to_string(), api_host.to_string(), callback_url);
+
+    client.health_check().await
+}
+
 // Pre-redirect first stage of the Duo OIDC authentication flow.
 // Returns the "AuthUrl" that should be returned to clients for MFA.
+// `provider` selects the keys to use, those of the user (Duo) or of their organization (OrganizationDuo).
 pub async fn get_duo_auth_url(
     email: &str,
     client_id: &str,
     device_identifier: &DeviceId,
+    provider: TwoFactorType,
     conn: &mut DbConn,
 ) -> Result<String, Error> {
-    let (ik, sk, _, host) = get_duo_keys_email(email, conn).await?;
+    let (ik, sk, _, host) = get_duo_keys_provider(provider, email, conn).await?;
 
     let callback_url = match make_callback_url(client_id) {
         Ok(url) => url,
@@ -418,6 +429,7 @@ pub async fn validate_duo_login(
     two_factor_token: &str,
     client_id: &str,
     device_identifier: &DeviceId,
+    provider: TwoFactorType,
     conn: &mut DbConn,
 ) -> EmptyResult {
     // Result supplied to us by clients in the form "<authz code>|<state>"
@@ -434,7 +446,7 @@ pub async fn validate_duo_login(
     let code = split[0];
     let state = split[1];
 
-    let (ik, sk, _, host) = get_duo_keys_email(email, conn).await?;
+    let (ik, sk, _, host) = get_duo_keys_provider(provider, email, conn).await?;
 
     // Get the context by the state reported by the client. If we don't have one,
     // it means the context is either missing or expired.
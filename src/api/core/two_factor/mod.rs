@@ -1,16 +1,15 @@
-use chrono::{TimeDelta, Utc};
-use data_encoding::BASE32;
+use chrono::{NaiveDate, TimeDelta, Utc};
 use rocket::serde::json::Json;
 use rocket::Route;
 use serde_json::Value;
 
 use crate::{
     api::{
-        core::{log_event, log_user_event},
+        admin::ACTING_ADMIN_USER,
+        core::{log_event, log_user_event, record_policy_violation},
         EmptyResult, JsonResult, PasswordOrOtpData,
     },
-    auth::{ClientHeaders, Headers},
-    crypto,
+    auth::{ClientHeaders, Headers, OwnerHeaders},
     db::{models::*, DbConn, DbPool},
     mail,
     util::NumberOrString,
@@ -32,6 +31,9 @@ pub fn routes() -> Vec<Route> {
         recover,
         disable_twofactor,
         disable_twofactor_put,
+        get_org_twofactor,
+        disable_org_twofactor,
+        disable_org_twofactor_put,
         get_device_verification_settings,
     ];
 
@@ -60,10 +62,16 @@ async fn get_twofactor(headers: Headers, mut conn: DbConn) -> Json<Value> {
 #[post("/two-factor/get-recover", data = "<data>")]
 async fn get_recover(data: Json<PasswordOrOtpData>, headers: Headers, mut conn: DbConn) -> JsonResult {
     let data: PasswordOrOtpData = data.into_inner();
-    let user = headers.user;
+    let mut user = headers.user;
 
     data.validate(&user, true, &mut conn).await?;
 
+    // Tracked for the audits of the custom API, the rotated codes have to be viewed again
+    if user.totp_recover.is_some() {
+        user.totp_recover_viewed_at = Some(Utc::now().naive_utc());
+        user.save(&mut conn).await?;
+    }
+
     Ok(Json(json!({
         "code": user.totp_recover,
         "object": "twoFactorRecover"
@@ -114,14 +122,14 @@ async fn recover(data: Json<RecoverTwoFactor>, client_headers: ClientHeaders, mu
 
     // Remove the recovery code, not needed without twofactors
     user.totp_recover = None;
+    user.totp_recover_used_at = Some(Utc::now().naive_utc());
     user.save(&mut conn).await?;
     Ok(Json(Value::Object(serde_json::Map::new())))
 }
 
 async fn _generate_recover_code(user: &mut User, conn: &mut DbConn) {
     if user.totp_recover.is_none() {
-        let totp_recover = crypto::encode_random_bytes::<20>(BASE32);
-        user.totp_recover = Some(totp_recover);
+        user.new_recovery_code();
         user.save(conn).await.ok();
     }
 }
@@ -171,6 +179,73 @@ async fn disable_twofactor_put(data: Json<DisableTwoFactorData>, headers: Header
     disable_twofactor(data, headers, conn).await
 }
 
+#[get("/organizations/<org_id>/two-factor")]
+async fn get_org_twofactor(org_id: OrganizationId, headers: OwnerHeaders, mut conn: DbConn) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let twofactors = OrgTwoFactor::find_by_org(&org_id, &mut conn).await;
+    let twofactors_json: Vec<Value> = twofactors.iter().map(OrgTwoFactor::to_json_provider).collect();
+
+    Ok(Json(json!({
+        "data": twofactors_json,
+        "object": "list",
+        "continuationToken": null,
+    })))
+}
+
+#[post("/organizations/<org_id>/two-factor/disable", data = "<data>")]
+async fn disable_org_twofactor(
+    org_id: OrganizationId,
+    data: Json<DisableTwoFactorData>,
+    headers: OwnerHeaders,
+    mut conn: DbConn,
+) -> JsonResult {
+    if org_id != headers.org_id {
+        err!("Organization not found", "Organization id's do not match");
+    }
+    let data: DisableTwoFactorData = data.into_inner();
+
+    PasswordOrOtpData {
+        master_password_hash: data.master_password_hash,
+        otp: data.otp,
+    }
+    .validate(&headers.user, true, &mut conn)
+    .await?;
+
+    let type_ = data.r#type.into_i32()?;
+
+    if let Some(twofactor) = OrgTwoFactor::find_by_org_and_type(&org_id, type_, &mut conn).await {
+        twofactor.delete(&mut conn).await?;
+        log_event(
+            EventType::OrganizationUpdated as i32,
+            org_id.as_ref(),
+            &org_id,
+            &headers.user.uuid,
+            headers.device.atype,
+            &headers.ip.ip,
+            &mut conn,
+        )
+        .await;
+    }
+
+    Ok(Json(json!({
+        "enabled": false,
+        "type": type_,
+        "object": "twoFactorProvider"
+    })))
+}
+
+#[put("/organizations/<org_id>/two-factor/disable", data = "<data>")]
+async fn disable_org_twofactor_put(
+    org_id: OrganizationId,
+    data: Json<DisableTwoFactorData>,
+    headers: OwnerHeaders,
+    conn: DbConn,
+) -> JsonResult {
+    disable_org_twofactor(org_id, data, headers, conn).await
+}
+
 pub async fn enforce_2fa_policy(
     user: &User,
     act_user_id: &UserId,
@@ -183,24 +258,9 @@ pub async fn enforce_2fa_policy(
     {
         // Policy only applies to non-Owner/non-Admin members who have accepted joining the org
         if member.atype < MembershipType::Admin {
-            if CONFIG.mail_enabled() {
-                let org = Organization::find_by_uuid(&member.org_uuid, conn).await.unwrap();
-                mail::send_2fa_removed_from_org(&user.email, &org.name).await?;
-            }
-            let mut member = member;
-            member.revoke();
-            member.save(conn).await?;
-
-            log_event(
-                EventType::OrganizationUserRevoked as i32,
-                &member.uuid,
-                &member.org_uuid,
-                act_user_id,
-                device_type,
-                ip,
-                conn,
-            )
-            .await;
+            let org = Organization::find_by_uuid(&member.org_uuid, conn).await.unwrap();
+            let reason = "Two-step login was disabled";
+            revoke_member_without_2fa(member, &org, reason, act_user_id, device_type, ip, conn).await?;
         }
     }
 
@@ -218,30 +278,125 @@ pub async fn enforce_2fa_policy_for_org(
     for member in Membership::find_confirmed_by_org(org_id, conn).await.into_iter() {
         // Don't enforce the policy for Admins and Owners.
         if member.atype < MembershipType::Admin && TwoFactor::find_by_user(&member.user_uuid, conn).await.is_empty() {
+            let reason = "The two-step login policy was enabled, two-step login is not enabled";
+            revoke_member_without_2fa(member, &org, reason, act_user_id, device_type, ip, conn).await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn two_factor_deadline_job(pool: DbPool) {
+    debug!("Start two-step login deadline job");
+    let Ok(mut conn) = pool.get().await else {
+        error!("Failed to get DB connection while running the two-step login deadline job");
+        return;
+    };
+
+    let today = Utc::now().date_naive();
+    for policy in OrgPolicy::find_enabled_by_type(OrgPolicyType::TwoFactorDeadline, &mut conn).await {
+        let deadline = serde_json::from_str::<TwoFactorDeadlinePolicyData>(&policy.data)
+            .ok()
+            .and_then(|data| Some((NaiveDate::parse_from_str(&data.deadline, "%Y-%m-%d").ok()?, data.warning_days)));
+        let Some((deadline, warning_days)) = deadline else {
+            warn!("The two-step login deadline policy of {} has no valid deadline", policy.org_uuid);
+            continue;
+        };
+        if let Err(e) =
+            enforce_2fa_deadline(&policy.org_uuid, deadline, warning_days.unwrap_or(7), today, &mut conn).await
+        {
+            error!("Failed to enforce the two-step login deadline of {}: {e:?}", policy.org_uuid);
+        }
+    }
+}
+
+/// Warns the members without two-step login before the deadline, and revokes them once it has passed.
+/// Warnings are sent `warning_days` days and one day before the deadline. As with the 2FA policy, Admins and Owners are exempt.
+async fn enforce_2fa_deadline(
+    org_id: &OrganizationId,
+    deadline: NaiveDate,
+    warning_days: i64,
+    today: NaiveDate,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    let Some(org) = Organization::find_by_uuid(org_id, conn).await else {
+        return Ok(());
+    };
+    let days_left = (deadline - today).num_days();
+    if days_left > 0 && days_left != warning_days && days_left != 1 {
+        return Ok(());
+    }
+
+    for member in Membership::find_confirmed_by_org(org_id, conn).await {
+        if member.atype >= MembershipType::Admin || !TwoFactor::find_by_user(&member.user_uuid, conn).await.is_empty() {
+            continue;
+        }
+        let Some(user) = User::find_by_uuid(&member.user_uuid, conn).await else {
+            continue;
+        };
+
+        if days_left > 0 {
             if CONFIG.mail_enabled() {
-                let user = User::find_by_uuid(&member.user_uuid, conn).await.unwrap();
-                mail::send_2fa_removed_from_org(&user.email, &org.name).await?;
+                let deadline = deadline.format("%Y-%m-%d").to_string();
+                if let Err(e) = mail::send_2fa_deadline_warning(&user.email, &org.name, &deadline, days_left).await {
+                    error!("Error sending the two-step login deadline warning to {}: {e:?}", user.email);
+                }
             }
-            let mut member = member;
-            member.revoke();
-            member.save(conn).await?;
-
-            log_event(
-                EventType::OrganizationUserRevoked as i32,
-                &member.uuid,
-                org_id,
-                act_user_id,
-                device_type,
-                ip,
-                conn,
-            )
-            .await;
+            continue;
         }
+
+        revoke_member_without_2fa(
+            member,
+            &org,
+            "The deadline of the two-step login policy passed, two-step login is not enabled",
+            &ACTING_ADMIN_USER.into(),
+            14, // Use UnknownBrowser type
+            &std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+            conn,
+        )
+        .await?;
     }
 
     Ok(())
 }
 
+/// Revokes a member who has no two-step login from an organization which requires it, and records the policy violation
+/// with `reason`.
+async fn revoke_member_without_2fa(
+    mut member: Membership,
+    org: &Organization,
+    reason: &str,
+    act_user_id: &UserId,
+    device_type: i32,
+    ip: &std::net::IpAddr,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    member.revoke();
+    member.save(conn).await?;
+    // The member is revoked either way, a failing mail doesn't stop the enforcement
+    if CONFIG.mail_enabled() {
+        if let Some(user) = User::find_by_uuid(&member.user_uuid, conn).await {
+            if let Err(e) = mail::send_2fa_removed_from_org(&user.email, &org.name).await {
+                error!("Error sending the two-step login removal email to {}: {e:?}", user.email);
+            }
+        }
+    }
+
+    log_event(EventType::OrganizationUserRevoked as i32, &member.uuid, &org.uuid, act_user_id, device_type, ip, conn)
+        .await;
+    record_policy_violation(
+        &org.uuid,
+        &member.user_uuid,
+        OrgPolicyType::TwoFactorAuthentication,
+        PolicyViolationAction::Revoked,
+        reason,
+        act_user_id,
+        conn,
+    )
+    .await;
+    Ok(())
+}
+
 pub async fn send_incomplete_2fa_notifications(pool: DbPool) {
     debug!("Sending notifications for incomplete 2FA logins");
 
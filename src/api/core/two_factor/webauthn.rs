@@ -6,7 +6,7 @@ use crate::{
     auth::Headers,
     crypto::ct_eq,
     db::{
-        models::{EventType, TwoFactor, TwoFactorType, UserId},
+        models::{AdminTwoFactor, EventType, TwoFactor, TwoFactorType, UserId},
         DbConn,
     },
     error::Error,
@@ -464,6 +464,102 @@ pub async fn validate_webauthn_login(user_id: &UserId, response: &str, conn: &mu
     )
 }
 
+// The admin panel login, see `ADMIN_TWO_FACTOR`, uses the same relying party with one fixed user.
+// There is no older credential format to migrate, so the backup eligible checks of the users are not needed.
+
+pub async fn generate_admin_webauthn_challenge(conn: &mut DbConn) -> JsonResult {
+    let (mut challenge, state) =
+        WEBAUTHN.start_passkey_registration(Uuid::nil(), "admin", "Vaultwarden admin", None)?;
+
+    let mut state = serde_json::to_value(&state)?;
+    state["rs"]["policy"] = Value::String("discouraged".to_string());
+    state["rs"]["extensions"].as_object_mut().unwrap().clear();
+
+    let type_ = TwoFactorType::WebauthnRegisterChallenge;
+    AdminTwoFactor::new(type_, serde_json::to_string(&state)?).save(conn).await?;
+
+    challenge.public_key.extensions = None;
+    if let Some(asc) = challenge.public_key.authenticator_selection.as_mut() {
+        asc.user_verification = UserVerificationPolicy::Discouraged_DO_NOT_USE;
+    }
+
+    Ok(Json(serde_json::to_value(challenge.public_key)?))
+}
+
+pub async fn activate_admin_webauthn(name: String, response: &str, conn: &mut DbConn) -> EmptyResult {
+    let state = match AdminTwoFactor::find_by_type(TwoFactorType::WebauthnRegisterChallenge, conn).await {
+        Some(tf) => {
+            let state: PasskeyRegistration = serde_json::from_str(&tf.data)?;
+            tf.delete(conn).await?;
+            state
+        }
+        None => err!("Can't recover challenge"),
+    };
+
+    let rsp: RegisterPublicKeyCredentialCopy = serde_json::from_str(response)?;
+    let credential = WEBAUTHN.finish_passkey_registration(&rsp.into(), &state)?;
+
+    let registrations = vec![WebauthnRegistration {
+        id: 1,
+        name,
+        migrated: false,
+
+        credential,
+    }];
+    AdminTwoFactor::new(TwoFactorType::Webauthn, serde_json::to_string(&registrations)?).save(conn).await
+}
+
+pub async fn generate_admin_webauthn_login(conn: &mut DbConn) -> JsonResult {
+    let creds: Vec<Passkey> = match AdminTwoFactor::find_by_type(TwoFactorType::Webauthn, conn).await {
+        Some(tf) => {
+            serde_json::from_str::<Vec<WebauthnRegistration>>(&tf.data)?.into_iter().map(|r| r.credential).collect()
+        }
+        None => err!("No Webauthn devices registered"),
+    };
+
+    let (mut response, state) = WEBAUTHN.start_passkey_authentication(&creds)?;
+
+    let mut state = serde_json::to_value(&state)?;
+    state["ast"]["policy"] = Value::String("discouraged".to_string());
+    response.public_key.user_verification = UserVerificationPolicy::Discouraged_DO_NOT_USE;
+
+    let type_ = TwoFactorType::WebauthnLoginChallenge;
+    AdminTwoFactor::new(type_, serde_json::to_string(&state)?).save(conn).await?;
+
+    Ok(Json(serde_json::to_value(response.public_key)?))
+}
+
+pub async fn validate_admin_webauthn_login(response: &str, conn: &mut DbConn) -> EmptyResult {
+    let state = match AdminTwoFactor::find_by_type(TwoFactorType::WebauthnLoginChallenge, conn).await {
+        Some(tf) => {
+            let state: PasskeyAuthentication = serde_json::from_str(&tf.data)?;
+            tf.delete(conn).await?;
+            state
+        }
+        None => err!("Can't recover login challenge"),
+    };
+
+    let Some(mut twofactor) = AdminTwoFactor::find_by_type(TwoFactorType::Webauthn, conn).await else {
+        err!("No Webauthn devices registered")
+    };
+    let mut registrations: Vec<WebauthnRegistration> = serde_json::from_str(&twofactor.data)?;
+
+    let rsp: PublicKeyCredentialCopy = serde_json::from_str(response)?;
+    let authentication_result = WEBAUTHN.finish_passkey_authentication(&rsp.into(), &state)?;
+
+    for reg in &mut registrations {
+        if ct_eq(reg.credential.cred_id(), authentication_result.cred_id()) {
+            if reg.credential.update_credential(&authentication_result) == Some(true) {
+                twofactor.data = serde_json::to_string(&registrations)?;
+                twofactor.save(conn).await?;
+            }
+            return Ok(());
+        }
+    }
+
+    err!("Credential not present")
+}
+
 async fn check_and_update_backup_eligible(
     user_id: &UserId,
     rsp: &PublicKeyCredential,
@@ -0,0 +1,122 @@
+use std::{net::IpAddr, num::NonZeroU32};
+
+use chrono::Utc;
+use data_encoding::BASE64;
+use serde_json::Value;
+
+use crate::{
+    api::{
+        core::log_admin_user_event,
+        core::{CipherSyncData, CipherSyncType},
+        ApiResult,
+    },
+    config::PathType,
+    crypto,
+    db::{models::*, DbConn},
+    util::{format_date, get_uuid},
+    CONFIG,
+};
+
+// Same as the current default of the clients for password protected exports
+const EXPORT_KDF_ITERATIONS: u32 = 600_000;
+
+// The fields of the export format of the clients, the other fields of the cipher details are left out
+const EXPORT_CIPHER_FIELDS: [&str; 19] = [
+    "id",
+    "organizationId",
+    "folderId",
+    "type",
+    "reprompt",
+    "name",
+    "notes",
+    "favorite",
+    "login",
+    "card",
+    "identity",
+    "secureNote",
+    "sshKey",
+    "fields",
+    "passwordHistory",
+    "revisionDate",
+    "creationDate",
+    "deletedDate",
+    "key",
+];
+
+/// Writes the personal vault of the user to `VAULT_EXPORT_FOLDER`, in the password protected export format of the
+/// clients. The items stay encrypted with the key of the account, like an "account restricted" export, so reading them
+/// also needs the master password of the user. The protected account key and its KDF settings are included for that.
+/// Returns the path of the export, and the number of items and folders in it.
+pub async fn export_user_vault(
+    user: &User,
+    password: &str,
+    ip: &IpAddr,
+    conn: &mut DbConn,
+) -> ApiResult<(String, usize, usize)> {
+    if password.chars().count() < 12 {
+        err!("The export password must be at least 12 characters long")
+    }
+
+    let sync_data = CipherSyncData::new(&user.uuid, CipherSyncType::User, conn).await;
+    let mut items = Vec::new();
+    for cipher in Cipher::find_owned_by_user(&user.uuid, conn).await {
+        let cipher_json =
+            cipher.to_json(&CONFIG.domain(), &user.uuid, Some(&sync_data), CipherSyncType::User, conn).await?;
+        let item: serde_json::Map<String, Value> =
+            EXPORT_CIPHER_FIELDS.iter().map(|field| ((*field).to_string(), cipher_json[*field].clone())).collect();
+        items.push(Value::Object(item));
+    }
+    let folders: Vec<Value> = Folder::find_by_user(&user.uuid, conn)
+        .await
+        .iter()
+        .map(|f| {
+            json!({
+                "id": f.uuid,
+                "name": f.name,
+            })
+        })
+        .collect();
+    let (item_count, folder_count) = (items.len(), folders.len());
+
+    let now = Utc::now().naive_utc();
+    let account_export = json!({
+        "encrypted": true,
+        "folders": folders,
+        "items": items,
+        // Vaultwarden specific, the master password of the user decrypts `key`, which decrypts the items
+        "account": {
+            "id": user.uuid,
+            "email": user.email,
+            "key": user.akey,
+            "kdfType": user.client_kdf_type,
+            "kdfIterations": user.client_kdf_iter,
+            "kdfMemory": user.client_kdf_memory,
+            "kdfParallelism": user.client_kdf_parallelism,
+        },
+        "exportDate": format_date(&now),
+    });
+
+    let salt = crypto::encode_random_bytes::<16>(BASE64);
+    let iterations = NonZeroU32::new(EXPORT_KDF_ITERATIONS).unwrap();
+    let key = crypto::derive_password_key(password, &salt, iterations);
+    let export = json!({
+        "encrypted": true,
+        "passwordProtected": true,
+        "salt": salt,
+        "kdfType": 0, // PBKDF2-SHA256
+        "kdfIterations": EXPORT_KDF_ITERATIONS,
+        "kdfMemory": null,
+        "kdfParallelism": null,
+        "encKeyValidation_DO_NOT_EDIT": crypto::encrypt_to_enc_string(&key, get_uuid().as_bytes())?,
+        "data": crypto::encrypt_to_enc_string(&key, serde_json::to_string(&account_export)?.as_bytes())?,
+    });
+
+    let path = format!("{}-{}.json", user.uuid, now.format("%Y%m%dT%H%M%S%.3fZ"));
+    let operator = CONFIG.opendal_operator_for_path_type(PathType::VaultExport)?;
+    operator.write(&path, serde_json::to_vec_pretty(&export)?).await?;
+    info!("Exported the vault of user {} to {path} ({item_count} items, {folder_count} folders)", user.uuid);
+
+    log_admin_user_event(EventType::UserAdminExportedVault as i32, &user.uuid, ip, conn).await;
+
+    Ok((path, item_count, folder_count))
+}
@@ -1,11 +1,23 @@
+use num_traits::FromPrimitive;
 use rocket::serde::json::Json;
-use rocket::{http::Status, Route, request::{FromRequest, Outcome, Request}};
+use rocket::{http::{ContentType, Header, Status}, Route, request::{FromRequest, Outcome, Request}};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    api::{EmptyResult, JsonResult},
-    db::{models::*, DbConn},
-    mail, CONFIG,
+    api::{
+        admin::{kdf_description, kdf_minimum_description, ACTING_ADMIN_USER},
+        core::{
+            accounts::revoke_device, export_user_vault as _export_user_vault, invite_provider_user, log_event,
+            set_org_archived, two_factor,
+        },
+        ApiResult, EmptyResult, JsonResult, MasterPasswordPolicy, Notify, UpdateType,
+    },
+    auth::{decode_org_purge, encode_jwt, generate_org_purge_claims, ClientIp},
+    crypto,
+    db::{begin_transaction, commit_transaction, models::*, rollback_transaction, DbConn, DbPool},
+    mail,
+    report_export::{ExportMember, ReportExport},
+    CONFIG,
 };
 
 pub const FAKE_ADMIN_UUID: &str = "00000000-0000-0000-0000-000000000000";
@@ -37,19 +49,81 @@ impl<'r> FromRequest<'r> for VWApi {
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![invite_user, get_user_details, exposed]
+    routes![
+        invite_user,
+        get_user_details,
+        get_user_fingerprint,
+        get_user_logins,
+        get_user_devices,
+        delete_user_device,
+        export_user_vault,
+        restore_user,
+        offboard_user,
+        resend_user_verify_email,
+        get_user_2fa_status,
+        get_user_kdf,
+        require_user_kdf_upgrade,
+        clear_user_kdf_upgrade,
+        require_user_password_reset,
+        clear_user_password_reset,
+        get_user_lockout,
+        clear_user_lockout,
+        put_user_new_device_email,
+        require_org_password_reset,
+        get_user_generator_policy,
+        exposed,
+        exposed_batch,
+        post_org_collection,
+        put_org_collection,
+        delete_org_collection,
+        sync_org_collections,
+        sync_org_groups,
+        get_org_groups,
+        get_org_group,
+        get_org_group_by_external_id,
+        post_org_group,
+        put_org_group,
+        delete_org_group,
+        put_org_seats,
+        put_org_archive,
+        get_org_templates,
+        post_org_template,
+        delete_org_template,
+        bootstrap_org,
+        transfer_org_ownership,
+        get_org_breaches,
+        get_org_password_age,
+        get_quarantine,
+        get_providers,
+        post_provider,
+        put_provider,
+        delete_provider,
+        get_org_2fa_report,
+        get_org_usage,
+        get_org_collections_report,
+        get_org_report_export,
+        get_reports_summary,
+        get_org_risk,
+        purge_org,
+        get_maintenance,
+        put_maintenance
+    ]
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct InviteData {
     email: String,
+    // Organization the user is invited for, its exposure report can block the invite
+    org_id: Option<OrganizationId>,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct InviteResponse {
     user_id: String,
+    // Only known for existing users with keys, see `/fingerprint`
+    fingerprint: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -58,38 +132,75 @@ struct ExposedData {
     user_id: String,
     org: std::collections::HashMap<String, i32>,
     me: i32,
+    // Optional breakdown of the org counts per collection
+    #[serde(default)]
+    collections: std::collections::HashMap<String, i32>,
+    // Optional findings of the user in the vault of each organization, the per-member breakdown of the org reports
+    #[serde(default)]
+    findings: std::collections::HashMap<String, MemberFindingsData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MemberFindingsData {
+    exposed: i32,
+    #[serde(default)]
+    weak: i32,
+    #[serde(default)]
+    reused: i32,
 }
 
 #[post("/invite", format = "application/json", data = "<data>")]
 async fn invite_user(_auth: VWApi, data: Json<InviteData>, mut conn: DbConn) -> JsonResult {
     let data: InviteData = data.into_inner();
+    // The organization customizes the text of the invitation, if it has any
+    let org = match &data.org_id {
+        Some(org_id) => {
+            let Some(org) = Organization::find_by_uuid(org_id, &mut conn).await else {
+                err_code!("Organization not found", Status::NotFound.code)
+            };
+            Some(org)
+        }
+        None => None,
+    };
+    // A verified alias resolves to its account, which is returned instead of creating a duplicate user
     if let Some(existing_user) = User::find_by_mail(&data.email, &mut conn).await {
         return Ok(Json(serde_json::to_value(InviteResponse {
             user_id: existing_user.uuid.to_string(),
+            fingerprint: existing_user.fingerprint_phrase(),
         }).unwrap()))
     }
 
-    let mut user = User::new(data.email, None);
-
-    async fn _generate_invite(user: &User, conn: &mut DbConn) -> EmptyResult {
-        if CONFIG.mail_enabled() {
-            let org_id: OrganizationId = FAKE_ADMIN_UUID.to_string().into();
-            let member_id: MembershipId = FAKE_ADMIN_UUID.to_string().into();
-            mail::send_admin_invite(user, org_id, member_id, &CONFIG.invitation_org_name(), None).await
-        } else {
-            let invitation = Invitation::new(&user.email);
-            invitation.save(conn).await
-        }
-    }
-
-    _generate_invite(&user, &mut conn).await.map_err(|e| e.with_code(Status::InternalServerError.code))?;
-    user.save(&mut conn).await.map_err(|e| e.with_code(Status::InternalServerError.code))?;
+    let user = invite_new_user(data.email, org.as_ref(), &mut conn).await?;
 
     Ok(Json(serde_json::to_value(InviteResponse {
         user_id: user.uuid.to_string(),
+        fingerprint: None,
     }).unwrap()))
 }
 
+/// Creates a new user and sends the server invitation, or stores it when mail is disabled.
+/// This is the invite path of `/invite`, also used by the LDAP directory sync and the CLI.
+/// An invite for an organization is refused while its report blocks invites.
+pub async fn invite_new_user(email: String, org: Option<&Organization>, conn: &mut DbConn) -> ApiResult<User> {
+    if let Some(org) = org {
+        check_report_invite_gate(&org.uuid, false, conn).await?;
+    }
+    let mut user = User::new(email, None);
+
+    let invited = if CONFIG.mail_enabled() {
+        let org_id: OrganizationId = FAKE_ADMIN_UUID.to_string().into();
+        let member_id: MembershipId = FAKE_ADMIN_UUID.to_string().into();
+        mail::send_admin_invite(&user, org_id, member_id, &CONFIG.invitation_org_name(), None, org).await
+    } else {
+        Invitation::new(&user.email).save(conn).await
+    };
+    invited.map_err(|e| e.with_code(Status::InternalServerError.code))?;
+
+    user.save(conn).await.map_err(|e| e.with_code(Status::InternalServerError.code))?;
+    Ok(user)
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct UserDetailsResponse {
@@ -98,6 +209,11 @@ struct UserDetailsResponse {
     members_count: i64,
     exposed_count: i64,
     last_updated_at: Option<String>,
+    risk_score: Option<i32>,
+    // Set when the mail provider reported the address, mails to it are suppressed
+    email_undeliverable: bool,
+    email_undeliverable_at: Option<String>,
+    email_undeliverable_reason: Option<String>,
 }
 
 #[get("/user/<user_id>/details")]
@@ -105,7 +221,7 @@ async fn get_user_details(_auth: VWApi, user_id: String, mut conn: DbConn) -> Js
     let user_uuid = UserId::from(user_id);
 
     match User::find_by_uuid(&user_uuid, &mut conn).await {
-        Some(_user) => {
+        Some(user) => {
             // Get user memberships to determine status
             let memberships = Membership::find_by_user(&user_uuid, &mut conn).await;
             
@@ -134,6 +250,13 @@ async fn get_user_details(_auth: VWApi, user_id: String, mut conn: DbConn) -> Js
                 (0, None)
             };
 
+            // Risk score: the latest daily score of the organization (None until it is calculated)
+            let risk_score = if let Some(membership) = memberships.first() {
+                OrgRiskScore::find_latest_by_org(&membership.org_uuid, &mut conn).await.map(|s| s.score)
+            } else {
+                None
+            };
+
             let org_id = memberships.first().map(|m| m.org_uuid.to_string());
 
             Ok(Json(serde_json::to_value(UserDetailsResponse {
@@ -142,62 +265,2631 @@ async fn get_user_details(_auth: VWApi, user_id: String, mut conn: DbConn) -> Js
                 members_count,
                 exposed_count: exposed_count.into(),
                 last_updated_at,
+                risk_score,
+                email_undeliverable: user.email_undeliverable_at.is_some(),
+                email_undeliverable_at: user.email_undeliverable_at.map(|dt| dt.and_utc().to_rfc3339()),
+                email_undeliverable_reason: user.email_undeliverable_reason,
             }).unwrap()))
         }
         None => err_code!("User not found", Status::NotFound.code),
     }
 }
 
-#[post("/exposed", format = "application/json", data = "<data>")]
-async fn exposed(data: Json<ExposedData>, mut conn: DbConn) -> EmptyResult {
-    let data: ExposedData = data.into_inner();
-    let user_uuid = UserId::from(data.user_id);
-    
+/// The fingerprint phrase of the user with this email, so it can be verified with them out-of-band before
+/// confirming them, like the clients do. Only users who logged in once have keys and a fingerprint.
+#[get("/fingerprint?<email>")]
+async fn get_user_fingerprint(_auth: VWApi, email: String, ip: ClientIp, mut conn: DbConn) -> JsonResult {
+    crate::ratelimit::check_limit_fingerprint(&ip.ip)?;
+
+    let Some(user) = User::find_by_mail(&email, &mut conn).await else {
+        err_code!("User not found", Status::NotFound.code)
+    };
+    let Some(fingerprint) = user.fingerprint_phrase() else {
+        err!("The user has no keys yet, the fingerprint is known once they logged in")
+    };
+
+    Ok(Json(json!({
+        "userId": user.uuid,
+        "email": user.email,
+        "fingerprint": fingerprint,
+    })))
+}
+
+#[get("/user/<user_id>/logins")]
+async fn get_user_logins(_auth: VWApi, user_id: String, mut conn: DbConn) -> JsonResult {
+    let user_uuid = UserId::from(user_id);
+
     match User::find_by_uuid(&user_uuid, &mut conn).await {
-        Some(_) => {
-            // Get user's memberships once for efficiency
-            let user_memberships = Membership::find_by_user(&user_uuid, &mut conn).await;
-            
-            // 1. Store personal exposed passwords (me field) - with userId, no org
-            match Report::find_by_user_personal(&user_uuid, &mut conn).await {
-                Some(mut existing_report) => {
-                    existing_report.update_exposed_count(data.me);
-                    existing_report.save(&mut conn).await?;
-                }
-                None => {
-                    let mut report = Report::new_personal(user_uuid.clone(), data.me);
-                    report.save(&mut conn).await?;
+        Some(user) => {
+            let logins: Vec<serde_json::Value> =
+                LoginEvent::find_by_user(&user.uuid, &mut conn).await.iter().map(LoginEvent::to_json).collect();
+            Ok(Json(serde_json::Value::Array(logins)))
+        }
+        None => err_code!("User not found", Status::NotFound.code),
+    }
+}
+
+/// Lists the devices of the user with their last activity and IP, the most recently used first.
+#[get("/user/<user_id>/devices")]
+async fn get_user_devices(_auth: VWApi, user_id: String, mut conn: DbConn) -> JsonResult {
+    let user_uuid = UserId::from(user_id);
+    if User::find_by_uuid(&user_uuid, &mut conn).await.is_none() {
+        err_code!("User not found", Status::NotFound.code)
+    }
+
+    let mut devices = Device::find_by_user(&user_uuid, &mut conn).await;
+    devices.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+    Ok(Json(serde_json::Value::Array(devices.iter().map(Device::to_json).collect())))
+}
+
+/// Logs out a single device of the user, the other devices stay logged in.
+#[delete("/user/<user_id>/devices/<device_id>")]
+async fn delete_user_device(_auth: VWApi, user_id: String, device_id: DeviceId, mut conn: DbConn) -> EmptyResult {
+    let user_uuid = UserId::from(user_id);
+    match Device::find_by_uuid_and_user(&device_id, &user_uuid, &mut conn).await {
+        Some(device) => revoke_device(device, &mut conn).await,
+        None => err_code!("Device not found", Status::NotFound.code),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VaultExportData {
+    password: String,
+}
+
+/// Writes a password protected export of the personal vault of the user to the data folder, e.g. for a legal hold.
+#[post("/user/<user_id>/export", format = "application/json", data = "<data>")]
+async fn export_user_vault(
+    _auth: VWApi,
+    user_id: String,
+    data: Json<VaultExportData>,
+    ip: ClientIp,
+    mut conn: DbConn,
+) -> JsonResult {
+    let Some(user) = User::find_by_uuid(&UserId::from(user_id), &mut conn).await else {
+        err_code!("User not found", Status::NotFound.code)
+    };
+
+    let (path, ciphers, folders) = _export_user_vault(&user, &data.password, &ip.ip, &mut conn).await?;
+    Ok(Json(json!({
+        "path": path,
+        "ciphers": ciphers,
+        "folders": folders,
+    })))
+}
+
+#[post("/user/<user_id>/restore")]
+async fn restore_user(_auth: VWApi, user_id: String, mut conn: DbConn) -> EmptyResult {
+    let user_uuid = UserId::from(user_id);
+
+    match User::find_by_uuid(&user_uuid, &mut conn).await {
+        Some(mut user) => user.restore(&mut conn).await,
+        None => err_code!("User not found", Status::NotFound.code),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OffboardData {
+    // Approves the recovery of the emergency access grants of the user, so their grantees can take over right away
+    #[serde(default)]
+    approve_emergency_access: bool,
+    // Schedules the purge of the account after `USER_DELETION_GRACE_DAYS`, enabled unless set to false
+    purge: Option<bool>,
+}
+
+/// Offboards a user in one call: revokes all their memberships, logs out and removes all their devices, disables the
+/// login, optionally approves their emergency access grants, and schedules the purge of the account.
+/// The steps which can't be done, like revoking the last owner of an organization, are listed in the summary.
+#[post("/user/<user_id>/offboard", format = "application/json", data = "<data>")]
+async fn offboard_user(
+    _auth: VWApi,
+    user_id: String,
+    data: Json<OffboardData>,
+    ip: ClientIp,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> JsonResult {
+    let data: OffboardData = data.into_inner();
+    let Some(mut user) = User::find_by_uuid(&UserId::from(user_id), &mut conn).await else {
+        err_code!("User not found", Status::NotFound.code)
+    };
+    let mut errors = Vec::new();
+
+    let mut revoked = Vec::new();
+    for mut member in Membership::find_any_state_by_user(&user.uuid, &mut conn).await {
+        if member.status <= MembershipStatus::Revoked as i32 {
+            continue;
+        }
+        let org_id = member.org_uuid.clone();
+        if member.atype == MembershipType::Owner
+            && member.status == MembershipStatus::Confirmed as i32
+            && Membership::count_confirmed_by_org_and_type(&org_id, MembershipType::Owner, &mut conn).await <= 1
+        {
+            errors.push(format!("The user is the last owner of organization {org_id}, the membership is kept"));
+            continue;
+        }
+
+        member.revoke();
+        member.save(&mut conn).await?;
+        log_event(
+            EventType::OrganizationUserRevoked as i32,
+            &member.uuid,
+            &member.org_uuid,
+            &ACTING_ADMIN_USER.into(),
+            14, // Use UnknownBrowser type
+            &ip.ip,
+            &mut conn,
+        )
+        .await;
+        revoked.push(json!({
+            "organizationId": member.org_uuid,
+            "memberId": member.uuid,
+        }));
+    }
+
+    let mut emergency_access_approved = Vec::new();
+    if data.approve_emergency_access {
+        for mut emergency_access in EmergencyAccess::find_all_by_grantor_uuid(&user.uuid, &mut conn).await {
+            let approvable = emergency_access.status == EmergencyAccessStatus::Confirmed as i32
+                || emergency_access.status == EmergencyAccessStatus::RecoveryInitiated as i32;
+            let Some(grantee_uuid) = emergency_access.grantee_uuid.clone().filter(|_| approvable) else {
+                continue;
+            };
+            let Some(grantee) = User::find_by_uuid(&grantee_uuid, &mut conn).await else {
+                continue;
+            };
+
+            emergency_access.status = EmergencyAccessStatus::RecoveryApproved as i32;
+            if emergency_access.recovery_initiated_at.is_none() {
+                emergency_access.recovery_initiated_at = Some(chrono::Utc::now().naive_utc());
+            }
+            emergency_access.save(&mut conn).await?;
+            if CONFIG.mail_enabled() {
+                if let Err(e) = mail::send_emergency_access_recovery_approved(&grantee.email, &user.name).await {
+                    errors.push(format!("Error notifying the grantee {}: {}", grantee.email, e.message()));
                 }
             }
-            
-            // 2. Store organization-specific exposed passwords (no userId, only orgId)
-            for (org_id_str, exposed_count) in data.org {
-                let org_uuid = OrganizationId::from(org_id_str);
-                
-                // Verify user is member of this organization
-                let is_member = user_memberships
-                    .iter()
-                    .any(|membership| membership.org_uuid == org_uuid);
-                
-                if !is_member {
-                    continue; // Skip if user is not a member of this org
+            emergency_access_approved.push(json!({
+                "id": emergency_access.uuid,
+                "granteeId": grantee.uuid,
+                "granteeEmail": grantee.email,
+            }));
+        }
+    }
+
+    // The clients are logged out before their devices are removed, the notifications are sent to these devices
+    nt.send_logout(&user, None, &mut conn).await;
+    let devices = Device::find_by_user(&user.uuid, &mut conn).await;
+    let devices_removed = devices.len();
+    for device in devices {
+        revoke_device(device, &mut conn).await?;
+    }
+
+    user.enabled = false;
+    user.reset_security_stamp();
+    user.save(&mut conn).await?;
+
+    let mut purge_date = None;
+    if data.purge.unwrap_or(true) {
+        match user.soft_delete(false, &mut conn).await {
+            Ok(()) => purge_date = user.purge_date(),
+            Err(e) => errors.push(format!("The purge of the account couldn't be scheduled: {}", e.message())),
+        }
+    }
+    info!("Offboarded user {} ({})", user.uuid, user.email);
+
+    Ok(Json(json!({
+        "userId": user.uuid,
+        "revokedMemberships": revoked,
+        "devicesRemoved": devices_removed,
+        "loginDisabled": true,
+        "emergencyAccessApproved": emergency_access_approved,
+        "purgeScheduled": purge_date.is_some(),
+        "purgeDate": purge_date.as_ref().map(crate::util::format_date),
+        "errors": errors,
+    })))
+}
+
+/// Sends the verification email again, e.g. for the accounts which are blocked by `EMAIL_VERIFY_ENFORCE` and can't
+/// log in to ask for it themselves.
+#[post("/user/<user_id>/verify-email")]
+async fn resend_user_verify_email(_auth: VWApi, user_id: String, mut conn: DbConn) -> JsonResult {
+    let user_uuid = UserId::from(user_id);
+    let Some(mut user) = User::find_by_uuid(&user_uuid, &mut conn).await else {
+        err_code!("User not found", Status::NotFound.code)
+    };
+    if !CONFIG.mail_enabled() {
+        err!("Cannot verify email address")
+    }
+    if user.verified_at.is_some() {
+        err!("The email address is already verified")
+    }
+
+    mail::send_verify_email(&user.email, &user.uuid).await?;
+    user.last_verifying_at = Some(chrono::Utc::now().naive_utc());
+    user.save(&mut conn).await?;
+
+    Ok(Json(json!({
+        "userId": user.uuid,
+        "email": user.email,
+        "blockedDate": user.email_verify_deadline(&mut conn).await.as_ref().map(crate::util::format_date),
+    })))
+}
+
+/// The two-step login of the user for the audits of the security team: the enabled providers, and whether the
+/// current recovery code was ever shown to the user and when a recovery code was last used.
+#[get("/user/<user_id>/2fa-status")]
+async fn get_user_2fa_status(_auth: VWApi, user_id: String, mut conn: DbConn) -> JsonResult {
+    let user_uuid = UserId::from(user_id);
+    let Some(user) = User::find_by_uuid(&user_uuid, &mut conn).await else {
+        err_code!("User not found", Status::NotFound.code)
+    };
+
+    let providers: Vec<serde_json::Value> = TwoFactor::find_by_user(&user.uuid, &mut conn)
+        .await
+        .iter()
+        .filter(|tf| tf.enabled)
+        .map(TwoFactor::to_json_provider)
+        .collect();
+
+    Ok(Json(json!({
+        "userId": user.uuid,
+        "enabled": !providers.is_empty(),
+        "providers": providers,
+        "recoveryCode": {
+            "set": user.totp_recover.is_some(),
+            "viewed": user.totp_recover_viewed_at.is_some(),
+            "viewedDate": user.totp_recover_viewed_at.as_ref().map(crate::util::format_date),
+            "used": user.totp_recover_used_at.is_some(),
+            "lastUsedDate": user.totp_recover_used_at.as_ref().map(crate::util::format_date),
+            "rotatedAfterUse": CONFIG.rotate_2fa_recovery_code(),
+        },
+    })))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UserKdfResponse {
+    kdf: String,
+    kdf_iterations: i32,
+    kdf_memory: Option<i32>,
+    kdf_parallelism: Option<i32>,
+    below_minimum: bool,
+    upgrade_required: bool,
+}
+
+#[get("/user/<user_id>/kdf")]
+async fn get_user_kdf(_auth: VWApi, user_id: String, mut conn: DbConn) -> JsonResult {
+    let user_uuid = UserId::from(user_id);
+
+    match User::find_by_uuid(&user_uuid, &mut conn).await {
+        Some(user) => {
+            let kdf = if user.client_kdf_type == UserKdfType::Argon2id as i32 {
+                "Argon2id"
+            } else {
+                "PBKDF2"
+            };
+
+            let response = UserKdfResponse {
+                kdf: kdf.to_string(),
+                kdf_iterations: user.client_kdf_iter,
+                kdf_memory: user.client_kdf_memory,
+                kdf_parallelism: user.client_kdf_parallelism,
+                below_minimum: user.is_kdf_below_minimum(),
+                upgrade_required: user.kdf_upgrade_required,
+            };
+            Ok(Json(serde_json::to_value(response).unwrap()))
+        }
+        None => err_code!("User not found", Status::NotFound.code),
+    }
+}
+
+/// Asks the user to change their KDF settings. The clients have no flow to force this, so the user is mailed
+/// and the flag is shown on the profile until they changed them.
+#[post("/user/<user_id>/kdf/upgrade")]
+async fn require_user_kdf_upgrade(_auth: VWApi, user_id: String, mut conn: DbConn) -> EmptyResult {
+    set_user_kdf_upgrade(UserId::from(user_id), true, &mut conn).await
+}
+
+#[delete("/user/<user_id>/kdf/upgrade")]
+async fn clear_user_kdf_upgrade(_auth: VWApi, user_id: String, mut conn: DbConn) -> EmptyResult {
+    set_user_kdf_upgrade(UserId::from(user_id), false, &mut conn).await
+}
+
+async fn set_user_kdf_upgrade(user_uuid: UserId, required: bool, conn: &mut DbConn) -> EmptyResult {
+    match User::find_by_uuid(&user_uuid, conn).await {
+        Some(mut user) => {
+            let notify = required && !user.kdf_upgrade_required;
+            user.kdf_upgrade_required = required;
+            user.save(conn).await?;
+
+            if notify && CONFIG.mail_enabled() {
+                let current = kdf_description(user.client_kdf_type, user.client_kdf_iter);
+                if let Err(e) = mail::send_kdf_migration(&user.email, &current, &kdf_minimum_description()).await {
+                    error!("Error sending KDF migration email to {}: {e:#?}", user.email);
                 }
-                
-                // Find and update or create new report for this specific org (no userId stored)
-                match Report::find_by_org(&org_uuid, &mut conn).await {
-                    Some(mut existing_report) => {
-                        existing_report.update_exposed_count(exposed_count);
-                        existing_report.save(&mut conn).await?;
-                    }
-                    None => {
-                        let mut report = Report::new_org(org_uuid, exposed_count);
-                        report.save(&mut conn).await?;
-                    }
+            }
+            Ok(())
+        }
+        None => err_code!("User not found", Status::NotFound.code),
+    }
+}
+
+/// Forces the user to choose a new master password, refreshing tokens and API key logins fail until they did.
+#[post("/user/<user_id>/force-password-reset")]
+async fn require_user_password_reset(_auth: VWApi, user_id: String, mut conn: DbConn) -> EmptyResult {
+    set_user_force_password_reset(UserId::from(user_id), true, &mut conn).await
+}
+
+#[delete("/user/<user_id>/force-password-reset")]
+async fn clear_user_password_reset(_auth: VWApi, user_id: String, mut conn: DbConn) -> EmptyResult {
+    set_user_force_password_reset(UserId::from(user_id), false, &mut conn).await
+}
+
+/// The failed login lockout of the user, `lockoutSeconds` is 0 when the user can log in.
+#[get("/user/<user_id>/lockout")]
+async fn get_user_lockout(_auth: VWApi, user_id: String, mut conn: DbConn) -> JsonResult {
+    let user_uuid = UserId::from(user_id);
+    let Some(user) = User::find_by_uuid(&user_uuid, &mut conn).await else {
+        err_code!("User not found", Status::NotFound.code)
+    };
+    Ok(Json(json!({
+        "userId": user.uuid,
+        "lockoutSeconds": crate::ratelimit::login_lockout_seconds(&user.uuid),
+    })))
+}
+
+/// Clears the failed logins of the user, which also lifts a lockout.
+#[delete("/user/<user_id>/lockout")]
+async fn clear_user_lockout(_auth: VWApi, user_id: String, mut conn: DbConn) -> EmptyResult {
+    let user_uuid = UserId::from(user_id);
+    let Some(user) = User::find_by_uuid(&user_uuid, &mut conn).await else {
+        err_code!("User not found", Status::NotFound.code)
+    };
+    crate::ratelimit::clear_failed_logins(&user.uuid);
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NewDeviceEmailData {
+    enabled: bool,
+}
+
+/// Whether the user gets an email when logging in from a new device, `NEW_DEVICE_EMAIL` has to be enabled as well.
+#[put("/user/<user_id>/new-device-email", format = "application/json", data = "<data>")]
+async fn put_user_new_device_email(
+    _auth: VWApi,
+    user_id: String,
+    data: Json<NewDeviceEmailData>,
+    mut conn: DbConn,
+) -> JsonResult {
+    let user_uuid = UserId::from(user_id);
+    let Some(mut user) = User::find_by_uuid(&user_uuid, &mut conn).await else {
+        err_code!("User not found", Status::NotFound.code)
+    };
+
+    user.new_device_email = data.into_inner().enabled;
+    user.save(&mut conn).await?;
+
+    Ok(Json(json!({
+        "userId": user.uuid,
+        "enabled": user.new_device_email,
+    })))
+}
+
+/// Forces all the confirmed members of the organization to choose a new master password.
+#[post("/org/<org_id>/force-password-reset")]
+async fn require_org_password_reset(_auth: VWApi, org_id: OrganizationId, mut conn: DbConn) -> JsonResult {
+    if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
+        err_code!("Organization not found", Status::NotFound.code)
+    }
+
+    let members = Membership::find_confirmed_by_org(&org_id, &mut conn).await;
+    for member in &members {
+        set_user_force_password_reset(member.user_uuid.clone(), true, &mut conn).await?;
+    }
+
+    Ok(Json(json!({
+        "users": members.len(),
+    })))
+}
+
+async fn set_user_force_password_reset(user_uuid: UserId, required: bool, conn: &mut DbConn) -> EmptyResult {
+    match User::find_by_uuid(&user_uuid, conn).await {
+        Some(mut user) => {
+            user.force_password_reset = required;
+            user.save(conn).await
+        }
+        None => err_code!("User not found", Status::NotFound.code),
+    }
+}
+
+/// Returns the password generator options enforced on the user, all the enabled policies of their organizations merged.
+/// `policy` is null when none of the organizations of the user enabled the password generator policy.
+#[get("/user/<user_id>/generator-policy")]
+async fn get_user_generator_policy(_auth: VWApi, user_id: String, mut conn: DbConn) -> JsonResult {
+    let user_uuid = UserId::from(user_id);
+    if User::find_by_uuid(&user_uuid, &mut conn).await.is_none() {
+        err_code!("User not found", Status::NotFound.code)
+    }
+
+    let policy = OrgPolicy::find_password_generator_policy(&user_uuid, &mut conn).await;
+    Ok(Json(json!({
+        "userId": user_uuid,
+        "policy": policy,
+    })))
+}
+
+#[post("/exposed", format = "application/json", data = "<data>")]
+async fn exposed(_auth: VWApi, data: Json<ExposedData>, nt: Notify<'_>, mut conn: DbConn) -> EmptyResult {
+    let data: ExposedData = data.into_inner();
+    let user_uuid = UserId::from(data.user_id.clone());
+    if User::find_by_uuid(&user_uuid, &mut conn).await.is_none() {
+        return Ok(());
+    }
+
+    let changes = store_exposed(&user_uuid, data, &mut conn).await?;
+    for (org_id, previous_count, report) in changes.deltas {
+        notify_report_delta(&org_id, previous_count, &report, &mut conn).await;
+    }
+    notify_report_updated(&changes.orgs, &nt, &mut conn).await;
+    Ok(())
+}
+
+// Above this the scanner has to split its submissions, so the transaction of a batch stays short
+const EXPOSED_BATCH_MAX_USERS: usize = 1000;
+
+/// Stores the reports of many users in a single transaction, either all of them are stored or none.
+/// Unknown users are skipped and listed in the response, like with `/exposed` they don't fail the batch.
+#[post("/exposed/batch", format = "application/json", data = "<data>")]
+async fn exposed_batch(_auth: VWApi, data: Json<Vec<ExposedData>>, nt: Notify<'_>, mut conn: DbConn) -> JsonResult {
+    let data: Vec<ExposedData> = data.into_inner();
+    if data.len() > EXPOSED_BATCH_MAX_USERS {
+        err!(format!("A batch can contain at most {EXPOSED_BATCH_MAX_USERS} users"))
+    }
+    let received = data.len();
+
+    let mut stored = 0;
+    let mut errors = Vec::new();
+    let mut changes = ExposedChanges::default();
+    begin_transaction(&mut conn).await?;
+    let result: EmptyResult = async {
+        for entry in data {
+            let user_uuid = UserId::from(entry.user_id.clone());
+            if User::find_by_uuid(&user_uuid, &mut conn).await.is_none() {
+                errors.push(json!({
+                    "userId": user_uuid,
+                    "error": "User not found",
+                }));
+                continue;
+            }
+            match store_exposed(&user_uuid, entry, &mut conn).await {
+                Ok(user_changes) => changes.extend(user_changes),
+                Err(e) => err!(format!("Error storing the report of user {user_uuid}: {}", e.message())),
+            }
+            stored += 1;
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        if let Err(rollback_err) = rollback_transaction(&mut conn).await {
+            error!("Error rolling back the exposed reports batch: {rollback_err:#?}");
+        }
+        return Err(e);
+    }
+    commit_transaction(&mut conn).await?;
+
+    // The owners are only notified once the reports are committed
+    for (org_id, previous_count, report) in changes.deltas {
+        notify_report_delta(&org_id, previous_count, &report, &mut conn).await;
+    }
+    notify_report_updated(&changes.orgs, &nt, &mut conn).await;
+
+    Ok(Json(json!({
+        "received": received,
+        "stored": stored,
+        "failed": errors.len(),
+        "errors": errors,
+    })))
+}
+
+/// What changed with the stored reports, the notifications are sent once they are committed.
+#[derive(Default)]
+struct ExposedChanges {
+    // The org reports whose exposed count increased enough to notify their owners, with the previous count
+    deltas: Vec<(OrganizationId, i32, Report)>,
+    // The organizations whose report or member findings were updated
+    orgs: std::collections::HashSet<OrganizationId>,
+}
+
+impl ExposedChanges {
+    fn extend(&mut self, other: Self) {
+        self.deltas.extend(other.deltas);
+        self.orgs.extend(other.orgs);
+    }
+}
+
+/// Stores the personal, organization and collection reports of the user.
+async fn store_exposed(user_uuid: &UserId, data: ExposedData, conn: &mut DbConn) -> ApiResult<ExposedChanges> {
+    let mut deltas = Vec::new();
+
+    // Get user's memberships once for efficiency
+    let user_memberships = Membership::find_by_user(user_uuid, conn).await;
+
+    // 1. Store personal exposed passwords (me field) - with userId, no org
+    match Report::find_by_user_personal(user_uuid, conn).await {
+        Some(mut existing_report) => {
+            existing_report.update_exposed_count(data.me);
+            existing_report.save(conn).await?;
+        }
+        None => {
+            let mut report = Report::new_personal(user_uuid.clone(), data.me);
+            report.save(conn).await?;
+        }
+    }
+
+    // 2. Store organization-specific exposed passwords (no userId, only orgId)
+    let mut reported_orgs = std::collections::HashSet::new();
+    for (org_id_str, exposed_count) in data.org {
+        let org_uuid = OrganizationId::from(org_id_str);
+
+        // Verify user is member of this organization
+        let is_member = user_memberships.iter().any(|membership| membership.org_uuid == org_uuid);
+
+        if !is_member {
+            continue; // Skip if user is not a member of this org
+        }
+        reported_orgs.insert(org_uuid.clone());
+
+        // Find and update or create new report for this specific org (no userId stored)
+        match Report::find_by_org(&org_uuid, conn).await {
+            Some(mut existing_report) => {
+                let previous_count = existing_report.exposed_count;
+                existing_report.update_exposed_count(exposed_count);
+                let notify = existing_report.evaluate_delta(
+                    previous_count,
+                    CONFIG.report_delta_threshold(),
+                    CONFIG.report_delta_percentage(),
+                );
+                existing_report.save(conn).await?;
+                if notify {
+                    deltas.push((org_uuid, previous_count, existing_report));
                 }
             }
+            None => {
+                let mut report = Report::new_org(org_uuid, exposed_count);
+                report.save(conn).await?;
+            }
+        }
+    }
+
+    // 3. Store the per-collection breakdown, keyed by collection and its organization
+    for (collection_id_str, exposed_count) in data.collections {
+        let collection_uuid = CollectionId::from(collection_id_str);
+        let Some(collection) = Collection::find_by_uuid(&collection_uuid, conn).await else {
+            continue;
+        };
+        if !user_memberships.iter().any(|membership| membership.org_uuid == collection.org_uuid) {
+            continue;
+        }
+
+        match Report::find_by_collection(&collection_uuid, conn).await {
+            Some(mut existing_report) => {
+                existing_report.update_exposed_count(exposed_count);
+                existing_report.save(conn).await?;
+            }
+            None => {
+                let mut report = Report::new_collection(collection.org_uuid, collection_uuid, exposed_count);
+                report.save(conn).await?;
+            }
+        }
+    }
+
+    // 4. Store the findings of the user per organization
+    for (org_id_str, findings) in data.findings {
+        let org_uuid = OrganizationId::from(org_id_str);
+        if !user_memberships.iter().any(|membership| membership.org_uuid == org_uuid) {
+            continue;
+        }
+        ReportMember::new(org_uuid.clone(), user_uuid.clone(), findings.exposed, findings.weak, findings.reused)
+            .save(conn)
+            .await?;
+        reported_orgs.insert(org_uuid);
+    }
+
+    // 5. Keep the snapshot of today up to date, so the exports can show the evolution of the org reports
+    for org_uuid in &reported_orgs {
+        ReportHistory::collect(org_uuid, conn).await.save(conn).await?;
+    }
+
+    Ok(ExposedChanges {
+        deltas,
+        orgs: reported_orgs,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CollectionData {
+    // Collection names are encrypted with the organization key, like with the clients
+    name: String,
+    external_id: Option<String>,
+    #[serde(default)]
+    groups: Vec<CollectionAccessData<GroupId>>,
+    #[serde(default)]
+    users: Vec<CollectionAccessData<MembershipId>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CollectionAccessData<T> {
+    id: T,
+    #[serde(default)]
+    read_only: bool,
+    #[serde(default)]
+    hide_passwords: bool,
+    #[serde(default)]
+    manage: bool,
+}
+
+// Replaces the group and member access of the collection, like the collection endpoints used by the clients.
+// Checks that all the groups and members of the access are part of the organization. There are no transactions,
+// so this is done before anything is written, a wrong id would otherwise leave the collection half updated.
+async fn check_collection_access(
+    org_id: &OrganizationId,
+    groups: &[CollectionAccessData<GroupId>],
+    users: &[CollectionAccessData<MembershipId>],
+    conn: &mut DbConn,
+) -> EmptyResult {
+    for group in groups {
+        if Group::find_by_uuid_and_org(&group.id, org_id, conn).await.is_none() {
+            err!(format!("Group {} is not part of organization", group.id))
+        }
+    }
+    for user in users {
+        if Membership::find_by_uuid_and_org(&user.id, org_id, conn).await.is_none() {
+            err!(format!("User {} is not part of organization", user.id))
+        }
+    }
+    Ok(())
+}
+
+// The access has to be checked with `check_collection_access` first
+async fn set_collection_access(
+    collection: &Collection,
+    groups: Vec<CollectionAccessData<GroupId>>,
+    users: Vec<CollectionAccessData<MembershipId>>,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    CollectionGroup::delete_all_by_collection(&collection.uuid, conn).await?;
+    for group in groups {
+        CollectionGroup::new(collection.uuid.clone(), group.id, group.read_only, group.hide_passwords, group.manage)
+            .save(conn)
+            .await?;
+    }
+
+    CollectionUser::delete_all_by_collection(&collection.uuid, conn).await?;
+    for user in users {
+        let Some(member) = Membership::find_by_uuid_and_org(&user.id, &collection.org_uuid, conn).await else {
+            continue;
+        };
+        if member.access_all {
+            continue;
         }
-        None => (),
+        CollectionUser::save(
+            &member.user_uuid,
+            &collection.uuid,
+            user.read_only,
+            user.hide_passwords,
+            user.manage,
+            conn,
+        )
+        .await?;
     }
+    Ok(())
+}
 
+async fn check_collection_external_id(
+    external_id: Option<&str>,
+    org_id: &OrganizationId,
+    col_id: Option<&CollectionId>,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    if let Some(external_id) = external_id {
+        if let Some(other) = Collection::find_by_external_id_and_org(external_id, org_id, conn).await {
+            if Some(&other.uuid) != col_id {
+                err!(format!("External id {external_id} is already used by another collection"))
+            }
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+async fn log_collection_event(event_type: EventType, collection: &Collection, ip: &ClientIp, conn: &mut DbConn) {
+    log_event(
+        event_type as i32,
+        &collection.uuid,
+        &collection.org_uuid,
+        &ACTING_ADMIN_USER.into(),
+        14, // Use UnknownBrowser type
+        &ip.ip,
+        conn,
+    )
+    .await;
+}
+
+#[post("/org/<org_id>/collections", format = "application/json", data = "<data>")]
+async fn post_org_collection(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    data: Json<CollectionData>,
+    ip: ClientIp,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: CollectionData = data.into_inner();
+    let Some(org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err_code!("Organization not found", Status::NotFound.code)
+    };
+
+    let external_id = data.external_id.filter(|id| !id.trim().is_empty());
+    check_collection_external_id(external_id.as_deref(), &org.uuid, None, &mut conn).await?;
+    check_collection_access(&org.uuid, &data.groups, &data.users, &mut conn).await?;
+    let collection = Collection::new(org.uuid, data.name, external_id);
+    collection.save(&mut conn).await?;
+    set_collection_access(&collection, data.groups, data.users, &mut conn).await?;
+
+    log_collection_event(EventType::CollectionCreated, &collection, &ip, &mut conn).await;
+
+    Ok(Json(collection.to_json()))
+}
+
+#[put("/org/<org_id>/collections/<col_id>", format = "application/json", data = "<data>", rank = 2)]
+async fn put_org_collection(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    col_id: CollectionId,
+    data: Json<CollectionData>,
+    ip: ClientIp,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: CollectionData = data.into_inner();
+    let Some(mut collection) = Collection::find_by_uuid_and_org(&col_id, &org_id, &mut conn).await else {
+        err_code!("Collection not found", Status::NotFound.code)
+    };
+
+    let external_id = data.external_id.filter(|id| !id.trim().is_empty());
+    check_collection_external_id(external_id.as_deref(), &org_id, Some(&col_id), &mut conn).await?;
+    check_collection_access(&org_id, &data.groups, &data.users, &mut conn).await?;
+    collection.name = data.name;
+    collection.external_id = external_id;
+    collection.save(&mut conn).await?;
+    set_collection_access(&collection, data.groups, data.users, &mut conn).await?;
+
+    log_collection_event(EventType::CollectionUpdated, &collection, &ip, &mut conn).await;
+
+    Ok(Json(collection.to_json()))
+}
+
+#[delete("/org/<org_id>/collections/<col_id>")]
+async fn delete_org_collection(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    col_id: CollectionId,
+    ip: ClientIp,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let Some(collection) = Collection::find_by_uuid_and_org(&col_id, &org_id, &mut conn).await else {
+        err_code!("Collection not found", Status::NotFound.code)
+    };
+
+    log_collection_event(EventType::CollectionDeleted, &collection, &ip, &mut conn).await;
+    collection.delete(&mut conn).await
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CollectionSyncData {
+    collections: Vec<CollectionSyncEntry>,
+    // An empty list deletes all the synced collections, which is refused unless it is confirmed
+    #[serde(default)]
+    confirm_empty: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CollectionSyncEntry {
+    external_id: String,
+    // Encrypted with the organization key, like with the clients
+    name: String,
+    // The access is only replaced when it is given, otherwise the access of existing collections is kept
+    groups: Option<Vec<CollectionAccessData<GroupId>>>,
+    users: Option<Vec<CollectionAccessData<MembershipId>>>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CollectionSyncSummary {
+    // The external ids of the collections
+    created: Vec<String>,
+    updated: Vec<String>,
+    deleted: Vec<String>,
+}
+
+// Makes the collections with an external id match the given list, the ones which are not listed are deleted.
+// Collections without an external id are not managed by the sync and are kept. The changes are made in one transaction.
+#[put("/org/<org_id>/collections/sync", format = "application/json", data = "<data>", rank = 1)]
+async fn sync_org_collections(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    data: Json<CollectionSyncData>,
+    ip: ClientIp,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: CollectionSyncData = data.into_inner();
+    if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
+        err_code!("Organization not found", Status::NotFound.code)
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    for entry in &data.collections {
+        if entry.external_id.trim().is_empty() {
+            err!("Collections need an external id to be synced")
+        }
+        if !seen.insert(entry.external_id.as_str()) {
+            err!(format!("Collection {} is listed more than once", entry.external_id))
+        }
+        let groups = entry.groups.as_deref().unwrap_or_default();
+        let users = entry.users.as_deref().unwrap_or_default();
+        check_collection_access(&org_id, groups, users, &mut conn).await?;
+    }
+
+    let mut summary = CollectionSyncSummary::default();
+    let mut existing: Vec<Collection> = Collection::find_by_organization(&org_id, &mut conn)
+        .await
+        .into_iter()
+        .filter(|c| c.external_id.is_some())
+        .collect();
+    if data.collections.is_empty() && !existing.is_empty() && !data.confirm_empty {
+        err!("An empty list deletes all the synced collections, set `confirmEmpty` to do so")
+    }
+
+    // The events are only logged once the changes are committed
+    let mut events = Vec::new();
+    begin_transaction(&mut conn).await?;
+    let result: EmptyResult = async {
+        for entry in data.collections {
+            let position = existing.iter().position(|c| c.external_id.as_ref() == Some(&entry.external_id));
+            let collection = match position {
+                Some(position) => {
+                    let mut collection = existing.swap_remove(position);
+                    let access_changed = entry.groups.is_some() || entry.users.is_some();
+                    if collection.name == entry.name && !access_changed {
+                        continue;
+                    }
+                    collection.name = entry.name;
+                    collection.save(&mut conn).await?;
+                    events.push((EventType::CollectionUpdated, collection.uuid.clone()));
+                    summary.updated.push(entry.external_id);
+                    collection
+                }
+                None => {
+                    let collection = Collection::new(org_id.clone(), entry.name, Some(entry.external_id.clone()));
+                    collection.save(&mut conn).await?;
+                    events.push((EventType::CollectionCreated, collection.uuid.clone()));
+                    summary.created.push(entry.external_id);
+                    collection
+                }
+            };
+
+            if entry.groups.is_some() || entry.users.is_some() {
+                let groups = match entry.groups {
+                    Some(groups) => groups,
+                    None => CollectionGroup::find_by_collection(&collection.uuid, &mut conn)
+                        .await
+                        .into_iter()
+                        .map(|cg| CollectionAccessData {
+                            id: cg.groups_uuid,
+                            read_only: cg.read_only,
+                            hide_passwords: cg.hide_passwords,
+                            manage: cg.manage,
+                        })
+                        .collect(),
+                };
+                let users = match entry.users {
+                    Some(users) => users,
+                    None => CollectionUser::find_by_org_and_coll_swap_user_uuid_with_member_uuid(
+                        &org_id,
+                        &collection.uuid,
+                        &mut conn,
+                    )
+                    .await
+                    .into_iter()
+                    .map(|cu| CollectionAccessData {
+                        id: cu.membership_uuid,
+                        read_only: cu.read_only,
+                        hide_passwords: cu.hide_passwords,
+                        manage: cu.manage,
+                    })
+                    .collect(),
+                };
+                set_collection_access(&collection, groups, users, &mut conn).await?;
+            }
+        }
+
+        // All synced collections which were not listed are no longer part of the desired state
+        for collection in existing {
+            events.push((EventType::CollectionDeleted, collection.uuid.clone()));
+            if let Some(external_id) = collection.external_id.clone() {
+                summary.deleted.push(external_id);
+            }
+            collection.delete(&mut conn).await?;
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        if let Err(rollback_err) = rollback_transaction(&mut conn).await {
+            error!("Error rolling back the collection sync: {rollback_err:#?}");
+        }
+        return Err(e);
+    }
+    commit_transaction(&mut conn).await?;
+
+    for (event_type, col_id) in events {
+        log_event(
+            event_type as i32,
+            &col_id,
+            &org_id,
+            &ACTING_ADMIN_USER.into(),
+            14, // Use UnknownBrowser type
+            &ip.ip,
+            &mut conn,
+        )
+        .await;
+    }
+
+    Ok(Json(serde_json::to_value(summary)?))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GroupSyncData {
+    groups: Vec<GroupSyncEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GroupSyncEntry {
+    name: String,
+    // Used to match the existing group when set, otherwise groups are matched by name
+    external_id: Option<String>,
+    #[serde(default)]
+    members: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GroupSyncSummary {
+    groups_created: Vec<String>,
+    groups_deleted: Vec<String>,
+    members_added: usize,
+    members_removed: usize,
+    // Emails which are not a member of the organization, these are skipped
+    unknown_members: Vec<String>,
+}
+
+async fn log_group_event(event_type: EventType, group: &Group, ip: &ClientIp, conn: &mut DbConn) {
+    log_event(
+        event_type as i32,
+        &group.uuid,
+        &group.organizations_uuid,
+        &ACTING_ADMIN_USER.into(),
+        14, // Use UnknownBrowser type
+        &ip.ip,
+        conn,
+    )
+    .await;
+}
+
+// Makes the groups of the organization match the given list, groups which are not listed are deleted.
+// Only the group membership is synced, the collection access of existing groups is kept.
+#[put("/org/<org_id>/groups/sync", format = "application/json", data = "<data>", rank = 1)]
+async fn sync_org_groups(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    data: Json<GroupSyncData>,
+    ip: ClientIp,
+    mut conn: DbConn,
+) -> JsonResult {
+    if !CONFIG.org_groups_enabled() {
+        err!("Group support is disabled");
+    }
+    let data: GroupSyncData = data.into_inner();
+    if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
+        err_code!("Organization not found", Status::NotFound.code)
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    for entry in &data.groups {
+        if entry.name.trim().is_empty() {
+            err!("Group names can't be empty")
+        }
+        let key = entry.external_id.clone().filter(|id| !id.trim().is_empty()).unwrap_or_else(|| entry.name.clone());
+        if !seen.insert(key) {
+            err!(format!("Group {} is listed more than once", entry.name))
+        }
+    }
+
+    let mut summary = GroupSyncSummary::default();
+    let mut existing = Group::find_by_organization(&org_id, &mut conn).await;
+
+    for entry in data.groups {
+        let external_id = entry.external_id.filter(|id| !id.trim().is_empty());
+        let position = existing.iter().position(|g| match &external_id {
+            Some(external_id) => g.external_id.as_ref() == Some(external_id),
+            None => g.name == entry.name,
+        });
+        let mut group = match position {
+            Some(position) => existing.swap_remove(position),
+            None => {
+                let mut group = Group::new(org_id.clone(), entry.name.clone(), false, external_id.clone());
+                group.save(&mut conn).await?;
+                log_group_event(EventType::GroupCreated, &group, &ip, &mut conn).await;
+                summary.groups_created.push(entry.name.clone());
+                group
+            }
+        };
+        if group.name != entry.name {
+            group.name = entry.name;
+            group.save(&mut conn).await?;
+            log_group_event(EventType::GroupUpdated, &group, &ip, &mut conn).await;
+        }
+
+        // A member listed more than once, also with a different case of the email, is only added once
+        let mut wanted = std::collections::HashSet::new();
+        for email in entry.members {
+            match Membership::find_by_email_and_org(&email, &org_id, &mut conn).await {
+                Some(member) => {
+                    wanted.insert(member.uuid);
+                }
+                None => summary.unknown_members.push(email),
+            }
+        }
+
+        let current = GroupUser::find_by_group(&group.uuid, &mut conn).await;
+        for group_user in &current {
+            if !wanted.contains(&group_user.users_organizations_uuid) {
+                GroupUser::delete_by_group_and_member(&group.uuid, &group_user.users_organizations_uuid, &mut conn)
+                    .await?;
+                summary.members_removed += 1;
+            }
+        }
+        for member_id in wanted {
+            if !current.iter().any(|gu| gu.users_organizations_uuid == member_id) {
+                GroupUser::new(group.uuid.clone(), member_id).save(&mut conn).await?;
+                summary.members_added += 1;
+            }
+        }
+    }
+
+    // All groups that were not matched are no longer part of the desired state
+    for group in existing {
+        log_group_event(EventType::GroupDeleted, &group, &ip, &mut conn).await;
+        group.delete(&mut conn).await?;
+        summary.groups_deleted.push(group.name);
+    }
+
+    summary.unknown_members.sort();
+    summary.unknown_members.dedup();
+    Ok(Json(serde_json::to_value(summary)?))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GroupData {
+    name: String,
+    #[serde(default)]
+    access_all: bool,
+    external_id: Option<String>,
+    // Replaces the collection access of the group, the members are managed by the sync endpoint
+    #[serde(default)]
+    collections: Vec<CollectionAccessData<CollectionId>>,
+}
+
+async fn check_group_data(
+    data: &GroupData,
+    org_id: &OrganizationId,
+    group_id: Option<&GroupId>,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    if !CONFIG.org_groups_enabled() {
+        err!("Group support is disabled");
+    }
+    if data.name.trim().is_empty() {
+        err!("Group names can't be empty")
+    }
+    if let Some(external_id) = data.external_id.as_deref().filter(|id| !id.trim().is_empty()) {
+        if let Some(other) = Group::find_by_external_id_and_org(external_id, org_id, conn).await {
+            if Some(&other.uuid) != group_id {
+                err!(format!("External id {external_id} is already used by another group"))
+            }
+        }
+    }
+    for access in &data.collections {
+        if Collection::find_by_uuid_and_org(&access.id, org_id, conn).await.is_none() {
+            err!("Collection is not part of organization")
+        }
+    }
+    Ok(())
+}
+
+// Replaces the collection access of the group, and lets its members know their access changed.
+async fn set_group_collections(
+    group: &Group,
+    collections: Vec<CollectionAccessData<CollectionId>>,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    CollectionGroup::delete_all_by_group(&group.uuid, conn).await?;
+    for access in collections {
+        CollectionGroup::new(access.id, group.uuid.clone(), access.read_only, access.hide_passwords, access.manage)
+            .save(conn)
+            .await?;
+    }
+    for group_user in GroupUser::find_by_group(&group.uuid, conn).await {
+        group_user.update_user_revision(conn).await;
+    }
+    Ok(())
+}
+
+#[get("/org/<org_id>/groups")]
+async fn get_org_groups(_auth: VWApi, org_id: OrganizationId, mut conn: DbConn) -> JsonResult {
+    if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
+        err_code!("Organization not found", Status::NotFound.code)
+    }
+
+    let mut groups = Vec::new();
+    for group in Group::find_by_organization(&org_id, &mut conn).await {
+        groups.push(group.to_json_details(&mut conn).await);
+    }
+    Ok(Json(serde_json::Value::Array(groups)))
+}
+
+#[get("/org/<org_id>/groups/<group_id>")]
+async fn get_org_group(_auth: VWApi, org_id: OrganizationId, group_id: GroupId, mut conn: DbConn) -> JsonResult {
+    let Some(group) = Group::find_by_uuid_and_org(&group_id, &org_id, &mut conn).await else {
+        err_code!("Group not found", Status::NotFound.code)
+    };
+    Ok(Json(group.to_json_details(&mut conn).await))
+}
+
+/// Looks up a group by the id it has in the directory, so tools don't have to keep track of the group ids.
+#[get("/org/<org_id>/groups/external/<external_id>")]
+async fn get_org_group_by_external_id(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    external_id: &str,
+    mut conn: DbConn,
+) -> JsonResult {
+    let Some(group) = Group::find_by_external_id_and_org(external_id, &org_id, &mut conn).await else {
+        err_code!("Group not found", Status::NotFound.code)
+    };
+    Ok(Json(group.to_json_details(&mut conn).await))
+}
+
+#[post("/org/<org_id>/groups", format = "application/json", data = "<data>")]
+async fn post_org_group(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    data: Json<GroupData>,
+    ip: ClientIp,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: GroupData = data.into_inner();
+    if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
+        err_code!("Organization not found", Status::NotFound.code)
+    }
+    check_group_data(&data, &org_id, None, &mut conn).await?;
+
+    let mut group = Group::new(org_id, data.name, data.access_all, data.external_id);
+    group.save(&mut conn).await?;
+    set_group_collections(&group, data.collections, &mut conn).await?;
+
+    log_group_event(EventType::GroupCreated, &group, &ip, &mut conn).await;
+
+    Ok(Json(group.to_json_details(&mut conn).await))
+}
+
+#[put("/org/<org_id>/groups/<group_id>", format = "application/json", data = "<data>", rank = 2)]
+async fn put_org_group(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    group_id: GroupId,
+    data: Json<GroupData>,
+    ip: ClientIp,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: GroupData = data.into_inner();
+    let Some(mut group) = Group::find_by_uuid_and_org(&group_id, &org_id, &mut conn).await else {
+        err_code!("Group not found", Status::NotFound.code)
+    };
+    check_group_data(&data, &org_id, Some(&group_id), &mut conn).await?;
+
+    group.name = data.name;
+    group.access_all = data.access_all;
+    group.set_external_id(data.external_id);
+    group.save(&mut conn).await?;
+    set_group_collections(&group, data.collections, &mut conn).await?;
+
+    log_group_event(EventType::GroupUpdated, &group, &ip, &mut conn).await;
+
+    Ok(Json(group.to_json_details(&mut conn).await))
+}
+
+#[delete("/org/<org_id>/groups/<group_id>")]
+async fn delete_org_group(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    group_id: GroupId,
+    ip: ClientIp,
+    mut conn: DbConn,
+) -> EmptyResult {
+    let Some(group) = Group::find_by_uuid_and_org(&group_id, &org_id, &mut conn).await else {
+        err_code!("Group not found", Status::NotFound.code)
+    };
+
+    log_group_event(EventType::GroupDeleted, &group, &ip, &mut conn).await;
+    group.delete(&mut conn).await
+}
+
+/// Lets the confirmed owners of the organization know that its exposed count increased beyond
+/// `REPORT_DELTA_THRESHOLD` or `REPORT_DELTA_PERCENTAGE`.
+async fn notify_report_delta(org_id: &OrganizationId, previous_count: i32, report: &Report, conn: &mut DbConn) {
+    if !CONFIG.mail_enabled() {
+        return;
+    }
+    let Some(org) = Organization::find_by_uuid(org_id, conn).await else {
+        return;
+    };
+
+    for owner in Membership::find_by_org_and_type(org_id, MembershipType::Owner, conn).await {
+        if !owner.has_status(MembershipStatus::Confirmed) {
+            continue;
+        }
+        if let Some(user) = User::find_by_uuid(&owner.user_uuid, conn).await {
+            if let Err(e) = mail::send_report_delta(
+                &user.email,
+                &org.name,
+                previous_count,
+                report.exposed_count,
+                &report.last_updated_at,
+            )
+            .await
+            {
+                error!("Error sending report delta email to {}: {e:#?}", user.email);
+            }
+        }
+    }
+}
+
+/// Pushes a `ReportUpdated` message with the new counts to the connected admins and owners of the organizations.
+async fn notify_report_updated(
+    org_ids: &std::collections::HashSet<OrganizationId>,
+    nt: &Notify<'_>,
+    conn: &mut DbConn,
+) {
+    if !CONFIG.enable_websocket() {
+        return;
+    }
+
+    for org_id in org_ids {
+        let Some(report) = Report::find_by_org(org_id, conn).await else {
+            continue;
+        };
+        let (weak_count, reused_count) = ReportMember::totals_by_org(org_id, conn).await;
+        let admin_ids: Vec<UserId> = Membership::find_confirmed_by_org(org_id, conn)
+            .await
+            .into_iter()
+            .filter(|member| member.atype >= MembershipType::Admin)
+            .map(|member| member.user_uuid)
+            .collect();
+
+        let counts = (report.exposed_count, weak_count, reused_count);
+        nt.send_report_update(org_id, counts, report.last_updated_at, &admin_ids).await;
+    }
+}
+
+/// Blocks new invites to an organization while its exposed count is above `REPORT_INVITE_THRESHOLD`.
+/// Owners can bypass this when `REPORT_INVITE_OWNER_OVERRIDE` is enabled. Every path which invites or adds
+/// members to an organization has to check this, the refusal has the `report_invite_blocked` error code.
+pub async fn check_report_invite_gate(org_id: &OrganizationId, is_owner: bool, conn: &mut DbConn) -> EmptyResult {
+    let Some(threshold) = CONFIG.report_invite_threshold() else {
+        return Ok(());
+    };
+    if is_owner && CONFIG.report_invite_owner_override() {
+        return Ok(());
+    }
+
+    if let Some(report) = Report::find_by_org(org_id, conn).await {
+        if report.exposed_count > threshold {
+            let msg = format!(
+                "Invites are blocked while the organization has {} exposed passwords, remediate them to go below {threshold} first",
+                report.exposed_count
+            );
+            // The `error` code lets integrations tell this apart from the other refused invites
+            let body = json!({
+                "message": msg,
+                "error": "report_invite_blocked",
+                "error_description": msg,
+                "exposedCount": report.exposed_count,
+                "threshold": threshold,
+                "errorModel": {
+                    "message": msg,
+                    "object": "error"
+                },
+                "object": "error"
+            });
+            return Err(crate::error::Error::from((msg, body)).with_code(Status::Forbidden.code));
+        }
+    }
+    Ok(())
+}
+
+pub async fn report_evaluation_job(pool: DbPool) {
+    debug!("Start report evaluation job");
+    let Some(threshold) = CONFIG.report_policy_threshold() else {
+        debug!("report_policy_threshold is not configured, abort");
+        return;
+    };
+
+    let Ok(mut conn) = pool.get().await else {
+        error!("Failed to get DB connection while evaluating the reports");
+        return;
+    };
+
+    for mut report in Report::find_all_orgs(&mut conn).await {
+        let Some(org_id) = report.org_uuid.clone() else {
+            continue;
+        };
+
+        let exceeded_days = report.evaluate_threshold(threshold);
+        if let Err(e) = report.save(&mut conn).await {
+            error!("Error saving report of organization {org_id}: {e:#?}");
+            continue;
+        }
+
+        match exceeded_days {
+            Some(days) if days >= CONFIG.report_policy_threshold_days() => {
+                if let Err(e) = enable_report_policies(&org_id, report.exposed_count, days, &mut conn).await {
+                    error!("Error enabling report policies for organization {org_id}: {e:#?}");
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Enables the configured policies which are not enabled yet for the organization,
+/// and lets the owners know about it when at least one of them changed.
+async fn enable_report_policies(
+    org_id: &OrganizationId,
+    exposed_count: i32,
+    days: i64,
+    conn: &mut DbConn,
+) -> EmptyResult {
+    let Some(org) = Organization::find_by_uuid(org_id, conn).await else {
+        err!("Organization not found")
+    };
+
+    let act_user_id: UserId = ACTING_ADMIN_USER.into();
+    let ip = std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED);
+
+    let mut enabled_policies = Vec::new();
+    for policy_type in CONFIG.report_policy_auto_enable_vec() {
+        let mut policy = match OrgPolicy::find_by_org_and_type(org_id, policy_type, conn).await {
+            Some(p) if p.enabled => continue,
+            Some(p) => p,
+            None => OrgPolicy::new(org_id.clone(), policy_type, false, "{}".to_string()),
+        };
+
+        // Same as when an admin enables the policy, revoke all members that do not have 2FA
+        if policy_type == OrgPolicyType::TwoFactorAuthentication {
+            two_factor::enforce_2fa_policy_for_org(org_id, &act_user_id, 14, &ip, conn).await?;
+        }
+
+        policy.enabled = true;
+        policy.save(conn).await?;
+
+        log_event(
+            EventType::PolicyUpdated as i32,
+            policy.uuid.as_ref(),
+            org_id,
+            &act_user_id,
+            14, // Use UnknownBrowser type
+            &ip,
+            conn,
+        )
+        .await;
+
+        enabled_policies.push(format!("{policy_type:?}"));
+    }
+
+    if enabled_policies.is_empty() {
+        return Ok(());
+    }
+
+    info!(
+        "Organization {org_id} exceeded the exposed passwords threshold for {days} days, enabled policies: {}",
+        enabled_policies.join(", ")
+    );
+
+    if CONFIG.mail_enabled() {
+        for owner in Membership::find_by_org_and_type(org_id, MembershipType::Owner, conn).await {
+            if !owner.has_status(MembershipStatus::Confirmed) {
+                continue;
+            }
+            if let Some(user) = User::find_by_uuid(&owner.user_uuid, conn).await {
+                if let Err(e) =
+                    mail::send_report_policies_enabled(&user.email, &org.name, exposed_count, days, &enabled_policies)
+                        .await
+                {
+                    error!("Error sending report policies email to {}: {e:#?}", user.email);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Calculates and stores the risk score of today for every organization, a later run of the same day replaces it.
+pub async fn risk_score_job(pool: DbPool) {
+    debug!("Start risk score job");
+    let Ok(mut conn) = pool.get().await else {
+        error!("Failed to get DB connection while calculating the risk scores");
+        return;
+    };
+
+    for org in Organization::get_all(&mut conn).await {
+        if let Err(e) = OrgRiskScore::calculate(&org.uuid, &mut conn).await.save(&mut conn).await {
+            error!("Error saving the risk score of organization {}: {e:#?}", org.uuid);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgSeatsData {
+    // None falls back to `ORG_MAX_SEATS`
+    max_seats: Option<i32>,
+}
+
+#[put("/org/<org_id>/seats", format = "application/json", data = "<data>")]
+async fn put_org_seats(_auth: VWApi, org_id: OrganizationId, data: Json<OrgSeatsData>, mut conn: DbConn) -> JsonResult {
+    let data: OrgSeatsData = data.into_inner();
+    if data.max_seats.is_some_and(|s| s < 1) {
+        err!("The seat limit must be at least 1")
+    }
+    let Some(mut org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err_code!("Organization not found", Status::NotFound.code)
+    };
+
+    org.max_seats = data.max_seats;
+    org.save(&mut conn).await?;
+
+    Ok(Json(json!({
+        "maxSeats": org.effective_max_seats(),
+        "occupiedSeats": Membership::count_seats_by_org(&org.uuid, &mut conn).await,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgArchiveData {
+    archived: bool,
+}
+
+/// Archives the organization when the customer suspends the service, or unarchives it again.
+/// The data is kept until `ORG_ARCHIVE_RETENTION_DAYS` passed, meanwhile the members can't use the items.
+#[put("/org/<org_id>/archive", format = "application/json", data = "<data>")]
+async fn put_org_archive(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    data: Json<OrgArchiveData>,
+    nt: Notify<'_>,
+    mut conn: DbConn,
+) -> JsonResult {
+    let Some(mut org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err_code!("Organization not found", Status::NotFound.code)
+    };
+
+    set_org_archived(&mut org, data.archived, &nt, &mut conn).await?;
+
+    Ok(Json(json!({
+        "archived": org.is_archived(),
+        "archivedDate": org.archived_at.as_ref().map(crate::util::format_date),
+        "archiveDeletionDate": org.archive_deletion_date().as_ref().map(crate::util::format_date),
+    })))
+}
+
+#[get("/org/templates")]
+async fn get_org_templates(_auth: VWApi, mut conn: DbConn) -> JsonResult {
+    let templates: Vec<serde_json::Value> =
+        OrgTemplate::get_all(&mut conn).await.iter().map(OrgTemplate::to_json).collect();
+    Ok(Json(json!(templates)))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgTemplateRequest {
+    name: String,
+    #[serde(flatten)]
+    template: OrgTemplateData,
+}
+
+/// Checks the template like the matching endpoints used by the clients would, so it can be applied without errors.
+fn validate_org_template(template: &OrgTemplateData) -> EmptyResult {
+    if template.max_seats.is_some_and(|s| s < 1) {
+        err!("The seat limit must be at least 1")
+    }
+
+    let mut group_names = std::collections::HashSet::new();
+    for group in &template.groups {
+        if group.name.trim().is_empty() || !group_names.insert(group.name.as_str()) {
+            err!(format!("The group name `{}` is empty or used twice", group.name))
+        }
+    }
+
+    let mut external_ids = std::collections::HashSet::new();
+    for collection in &template.collections {
+        if collection.external_id.trim().is_empty() || !external_ids.insert(collection.external_id.as_str()) {
+            err!(format!("The collection external id `{}` is empty or used twice", collection.external_id))
+        }
+        if let Some(access) = collection.groups.iter().find(|access| !group_names.contains(access.name.as_str())) {
+            err!(format!("The collection `{}` refers to the unknown group `{}`", collection.external_id, access.name))
+        }
+    }
+
+    let mut policy_types = std::collections::HashSet::new();
+    for policy in &template.policies {
+        let Some(policy_type) = OrgPolicyType::from_i32(policy.r#type) else {
+            err!(format!("Unknown policy type {}", policy.r#type))
+        };
+        if !policy_types.insert(policy.r#type) {
+            err!(format!("The policy type {} is used twice", policy.r#type))
+        }
+        if !policy.enabled {
+            continue;
+        }
+        let data = policy.data.clone().unwrap_or_else(|| json!({}));
+        match policy_type {
+            OrgPolicyType::PasswordGenerator => match serde_json::from_value::<PasswordGeneratorPolicyData>(data) {
+                Ok(policy_data) => policy_data.validate()?,
+                Err(_) => err!("Invalid password generator policy options"),
+            },
+            OrgPolicyType::MasterPassword => match serde_json::from_value::<MasterPasswordPolicy>(data) {
+                Ok(policy_data) => policy_data.validate()?,
+                Err(_) => err!("Invalid master password policy options"),
+            },
+            OrgPolicyType::CipherHistory => match serde_json::from_value::<CipherHistoryPolicyData>(data) {
+                Ok(policy_data) => policy_data.validate()?,
+                Err(_) => err!("Invalid item history policy options"),
+            },
+            OrgPolicyType::VerifiedEmail => match serde_json::from_value::<VerifiedEmailPolicyData>(data) {
+                Ok(policy_data) => policy_data.validate()?,
+                Err(_) => err!("Invalid verified email policy options"),
+            },
+            OrgPolicyType::SessionLifetime => match serde_json::from_value::<SessionLifetimePolicyData>(data) {
+                Ok(policy_data) => policy_data.validate()?,
+                Err(_) => err!("Invalid session lifetime policy options"),
+            },
+            OrgPolicyType::OrgSends => match serde_json::from_value::<OrgSendsPolicyData>(data) {
+                Ok(policy_data) => policy_data.validate()?,
+                Err(_) => err!("Invalid organization Sends policy options"),
+            },
+            // A collection only exists in one organization
+            OrgPolicyType::PersonalOwnership => match serde_json::from_value::<PersonalOwnershipPolicyData>(data) {
+                Ok(policy_data) if policy_data.migration_collection_id.is_some() => {
+                    err!("The migration collection of the personal ownership policy can't be part of a template")
+                }
+                Ok(_) => (),
+                Err(_) => err!("Invalid personal ownership policy options"),
+            },
+            _ => (),
+        }
+    }
+    let single_org = OrgPolicyType::SingleOrg as i32;
+    let require_sso = OrgPolicyType::RequireSso as i32;
+    if template.policies.iter().any(|p| p.r#type == require_sso && p.enabled)
+        && !template.policies.iter().any(|p| p.r#type == single_org && p.enabled)
+    {
+        err!("The Require single sign-on authentication policy needs the Single Organization policy")
+    }
+
+    Ok(())
+}
+
+/// Creates or replaces the template with the given name.
+#[post("/org/templates", format = "application/json", data = "<data>")]
+async fn post_org_template(_auth: VWApi, data: Json<OrgTemplateRequest>, mut conn: DbConn) -> JsonResult {
+    let data: OrgTemplateRequest = data.into_inner();
+    let name = data.name.trim().to_string();
+    if name.is_empty() {
+        err!("The template name can't be empty")
+    }
+    validate_org_template(&data.template)?;
+
+    let mut template = match OrgTemplate::find_by_name(&name, &mut conn).await {
+        Some(mut template) => {
+            template.set_data(&data.template);
+            template
+        }
+        None => OrgTemplate::new(name, &data.template),
+    };
+    template.save(&mut conn).await?;
+
+    Ok(Json(template.to_json()))
+}
+
+#[delete("/org/templates/<name>")]
+async fn delete_org_template(_auth: VWApi, name: &str, mut conn: DbConn) -> EmptyResult {
+    let Some(template) = OrgTemplate::find_by_name(name, &mut conn).await else {
+        err_code!("Template not found", Status::NotFound.code)
+    };
+    template.delete(&mut conn).await
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgBootstrapData {
+    template: String,
+    // The names of the collections of the template encrypted with the organization key, by their external id
+    #[serde(default)]
+    collection_names: std::collections::HashMap<String, String>,
+}
+
+/// Provisions a new organization with a template: its seat limit, groups, collections with their group access and
+/// policies. The organization and its keys are created by the clients, it can be bootstrapped while it only has its
+/// creator as member. So the policies don't have to be enforced on existing members like when enabled by the clients.
+/// Running it again doesn't duplicate anything: the groups are matched by their external id, or their name without
+/// one, and the collections by their external id. These are updated to the template instead of created again.
+#[post("/org/<org_id>/bootstrap", format = "application/json", data = "<data>")]
+async fn bootstrap_org(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    data: Json<OrgBootstrapData>,
+    ip: ClientIp,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: OrgBootstrapData = data.into_inner();
+    let Some(mut org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err_code!("Organization not found", Status::NotFound.code)
+    };
+    let Some(template) = OrgTemplate::find_by_name(&data.template, &mut conn).await else {
+        err_code!("Template not found", Status::NotFound.code)
+    };
+    let Some(template_data) = template.get_data() else {
+        err!("The template is invalid")
+    };
+    if Membership::count_by_org(&org.uuid, &mut conn).await > 1 {
+        err!("Only a new organization with a single member can be bootstrapped")
+    }
+    let missing_name = template_data.collections.iter().find(|c| !data.collection_names.contains_key(&c.external_id));
+    if let Some(collection) = missing_name {
+        err!(format!("The encrypted name of the collection `{}` is missing", collection.external_id))
+    }
+
+    let mut groups = Vec::new();
+    let mut collections = Vec::new();
+    begin_transaction(&mut conn).await?;
+    let result: EmptyResult = async {
+        if template_data.max_seats.is_some() {
+            org.max_seats = template_data.max_seats;
+            org.save(&mut conn).await?;
+        }
+
+        let mut group_ids = std::collections::HashMap::new();
+        let mut existing_groups = Group::find_by_organization(&org.uuid, &mut conn).await;
+        for template_group in &template_data.groups {
+            let external_id = template_group.external_id.as_deref().filter(|id| !id.is_empty());
+            let existing = existing_groups.iter().position(|group| match external_id {
+                Some(external_id) => group.external_id.as_deref() == Some(external_id),
+                None => group.name == template_group.name,
+            });
+            let (mut group, created) = match existing.map(|index| existing_groups.swap_remove(index)) {
+                Some(mut group) => {
+                    group.name.clone_from(&template_group.name);
+                    group.access_all = template_group.access_all;
+                    (group, false)
+                }
+                None => (
+                    Group::new(
+                        org.uuid.clone(),
+                        template_group.name.clone(),
+                        template_group.access_all,
+                        template_group.external_id.clone(),
+                    ),
+                    true,
+                ),
+            };
+            group.save(&mut conn).await?;
+            group_ids.insert(template_group.name.as_str(), group.uuid.clone());
+            groups.push((group, created));
+        }
+
+        for template_collection in &template_data.collections {
+            let name = data.collection_names[&template_collection.external_id].clone();
+            let existing =
+                Collection::find_by_external_id_and_org(&template_collection.external_id, &org.uuid, &mut conn).await;
+            let created = existing.is_none();
+            let collection = match existing {
+                Some(mut collection) => {
+                    collection.name = name;
+                    collection
+                }
+                None => Collection::new(org.uuid.clone(), name, Some(template_collection.external_id.clone())),
+            };
+            collection.save(&mut conn).await?;
+            for access in &template_collection.groups {
+                let Some(group_id) = group_ids.get(access.name.as_str()) else {
+                    continue;
+                };
+                CollectionGroup::new(
+                    collection.uuid.clone(),
+                    group_id.clone(),
+                    access.read_only,
+                    access.hide_passwords,
+                    access.manage,
+                )
+                .save(&mut conn)
+                .await?;
+            }
+            collections.push((collection, created));
+        }
+
+        for template_policy in &template_data.policies {
+            let Some(policy_type) = OrgPolicyType::from_i32(template_policy.r#type) else {
+                continue;
+            };
+            let policy_data = template_policy.data.clone().unwrap_or_else(|| json!({}));
+            OrgPolicy::new(org.uuid.clone(), policy_type, template_policy.enabled, policy_data.to_string())
+                .save(&mut conn)
+                .await?;
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        if let Err(rollback_err) = rollback_transaction(&mut conn).await {
+            error!("Error rolling back the bootstrap of organization {org_id}: {rollback_err:#?}");
+        }
+        return Err(e);
+    }
+    commit_transaction(&mut conn).await?;
+
+    for (group, created) in &groups {
+        let event_type = if *created {
+            EventType::GroupCreated
+        } else {
+            EventType::GroupUpdated
+        };
+        log_group_event(event_type, group, &ip, &mut conn).await;
+    }
+    for (collection, created) in &collections {
+        let event_type = if *created {
+            EventType::CollectionCreated
+        } else {
+            EventType::CollectionUpdated
+        };
+        log_collection_event(event_type, collection, &ip, &mut conn).await;
+    }
+    info!("Bootstrapped organization {org_id} with the template {}", template.name);
+
+    Ok(Json(json!({
+        "template": template.name,
+        "maxSeats": org.effective_max_seats(),
+        "groups": groups.iter().map(|(group, _)| group.to_json()).collect::<Vec<_>>(),
+        "collections": collections.iter().map(|(collection, _)| collection.to_json()).collect::<Vec<_>>(),
+        "policies": template_data.policies.len(),
+    })))
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum PreviousOwnerAction {
+    Keep,
+    #[default]
+    Demote,
+    Remove,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransferOwnershipData {
+    // Has to be a confirmed member of the organization
+    new_owner_id: String,
+    // Usually the temporary account which created the organization, it becomes an admin by default
+    previous_owner_id: Option<String>,
+    #[serde(default)]
+    previous_owner_action: PreviousOwnerAction,
+}
+
+/// Promotes a confirmed member to owner, and demotes or removes the previous owner, in a single transaction.
+#[post("/org/<org_id>/transfer-ownership", format = "application/json", data = "<data>")]
+async fn transfer_org_ownership(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    data: Json<TransferOwnershipData>,
+    ip: ClientIp,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data: TransferOwnershipData = data.into_inner();
+    let Some(org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err_code!("Organization not found", Status::NotFound.code)
+    };
+
+    let new_owner_uuid = UserId::from(data.new_owner_id);
+    let Some(mut new_owner) = Membership::find_by_user_and_org(&new_owner_uuid, &org_id, &mut conn).await else {
+        err_code!("The new owner isn't a member of the organization", Status::NotFound.code)
+    };
+    if !new_owner.has_status(MembershipStatus::Confirmed) {
+        err!("The new owner has to be a confirmed member of the organization")
+    }
+
+    let previous_owner = match data.previous_owner_id {
+        Some(previous_owner_id) => {
+            let previous_owner_uuid = UserId::from(previous_owner_id);
+            if previous_owner_uuid == new_owner_uuid {
+                err!("The previous and the new owner can't be the same user")
+            }
+            match Membership::find_by_user_and_org(&previous_owner_uuid, &org_id, &mut conn).await {
+                Some(member) if member.atype == MembershipType::Owner => Some(member),
+                _ => err!("The previous owner isn't an owner of the organization"),
+            }
+        }
+        None => None,
+    };
+    let previous_owner_ids = previous_owner.as_ref().map(|m| (m.uuid.clone(), m.user_uuid.clone()));
+    let action = data.previous_owner_action;
+
+    begin_transaction(&mut conn).await?;
+    let result: EmptyResult = async {
+        new_owner.atype = MembershipType::Owner as i32;
+        new_owner.access_all = true;
+        new_owner.save(&mut conn).await?;
+
+        if let Some(mut previous_owner) = previous_owner {
+            match action {
+                PreviousOwnerAction::Keep => (),
+                PreviousOwnerAction::Demote => {
+                    previous_owner.atype = MembershipType::Admin as i32;
+                    previous_owner.save(&mut conn).await?;
+                }
+                PreviousOwnerAction::Remove => previous_owner.delete(&mut conn).await?,
+            }
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        if let Err(rollback_err) = rollback_transaction(&mut conn).await {
+            error!("Error rolling back the ownership transfer of organization {org_id}: {rollback_err:#?}");
+        }
+        return Err(e);
+    }
+    commit_transaction(&mut conn).await?;
+
+    log_event(
+        EventType::OrganizationUserUpdated as i32,
+        &new_owner.uuid,
+        &org_id,
+        &ACTING_ADMIN_USER.into(),
+        14, // Use UnknownBrowser type
+        &ip.ip,
+        &mut conn,
+    )
+    .await;
+
+    let new_owner_email =
+        User::find_by_uuid(&new_owner.user_uuid, &mut conn).await.map(|u| u.email).unwrap_or_default();
+    let mut notified = vec![(new_owner.user_uuid.clone(), false, false)];
+
+    if let Some((previous_owner_id, previous_owner_user_uuid)) = previous_owner_ids {
+        let event_type = match action {
+            PreviousOwnerAction::Keep => None,
+            PreviousOwnerAction::Demote => Some(EventType::OrganizationUserUpdated),
+            PreviousOwnerAction::Remove => Some(EventType::OrganizationUserRemoved),
+        };
+        if let Some(event_type) = event_type {
+            log_event(
+                event_type as i32,
+                &previous_owner_id,
+                &org_id,
+                &ACTING_ADMIN_USER.into(),
+                14, // Use UnknownBrowser type
+                &ip.ip,
+                &mut conn,
+            )
+            .await;
+        }
+        notified.push((
+            previous_owner_user_uuid,
+            action == PreviousOwnerAction::Demote,
+            action == PreviousOwnerAction::Remove,
+        ));
+    }
+
+    if CONFIG.mail_enabled() {
+        for (user_uuid, demoted, removed) in notified {
+            if let Some(user) = User::find_by_uuid(&user_uuid, &mut conn).await {
+                if let Err(e) =
+                    mail::send_org_ownership_transferred(&user.email, &org.name, &new_owner_email, demoted, removed)
+                        .await
+                {
+                    error!("Error sending ownership transfer email to {}: {e:#?}", user.email);
+                }
+            }
+        }
+    }
+
+    Ok(Json(json!({
+        "organizationId": org_id,
+        "ownerCount": Membership::count_confirmed_by_org_and_type(&org_id, MembershipType::Owner, &mut conn).await,
+    })))
+}
+
+/// Returns the findings of the last breach check, grouped per member. Only organizations with the
+/// breach monitoring policy enabled are checked, see `BREACH_CHECK_SCHEDULE`.
+#[get("/org/<org_id>/breaches")]
+async fn get_org_breaches(_auth: VWApi, org_id: OrganizationId, mut conn: DbConn) -> JsonResult {
+    if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
+        err_code!("Organization not found", Status::NotFound.code)
+    }
+    let enabled = OrgPolicy::find_by_org_and_type(&org_id, OrgPolicyType::BreachMonitoring, &mut conn)
+        .await
+        .is_some_and(|p| p.enabled);
+
+    let mut members: Vec<serde_json::Value> = Vec::new();
+    let mut current: Option<UserId> = None;
+    for finding in BreachFinding::find_by_org(&org_id, &mut conn).await {
+        if current.as_ref() != Some(&finding.user_uuid) {
+            let email = User::find_by_uuid(&finding.user_uuid, &mut conn).await.map(|u| u.email);
+            members.push(json!({
+                "userId": finding.user_uuid,
+                "email": email,
+                "breaches": [],
+            }));
+            current = Some(finding.user_uuid.clone());
+        }
+        if let Some(serde_json::Value::Array(breaches)) = members.last_mut().map(|m| &mut m["breaches"]) {
+            breaches.push(finding.to_json());
+        }
+    }
+
+    Ok(Json(json!({
+        "monitoringEnabled": enabled,
+        "members": members,
+    })))
+}
+
+/// Lists the login ciphers of the organization whose password is older than `days`, or the threshold of the
+/// enabled password age policy. Ciphers from before the password changes were tracked use their creation date.
+#[get("/org/<org_id>/password-age?<days>")]
+async fn get_org_password_age(_auth: VWApi, org_id: OrganizationId, days: Option<i64>, mut conn: DbConn) -> JsonResult {
+    if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
+        err_code!("Organization not found", Status::NotFound.code)
+    }
+    let policy_days = match OrgPolicy::find_by_org_and_type(&org_id, OrgPolicyType::PasswordAge, &mut conn).await {
+        Some(policy) if policy.enabled => match serde_json::from_str::<PasswordAgePolicyData>(&policy.data) {
+            Ok(data) => Some(data.max_age_days),
+            Err(_) => err!("The password age policy has no valid `maxAgeDays`"),
+        },
+        _ => None,
+    };
+    let Some(max_age_days) = days.or(policy_days) else {
+        err!("Enable the password age policy or pass the `days` parameter")
+    };
+    if max_age_days < 0 {
+        err!("The password age can't be negative")
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let mut items = Vec::new();
+    for cipher in Cipher::find_by_org(&org_id, &mut conn).await {
+        if cipher.deleted_at.is_some() || cipher.atype != CipherType::Login as i32 {
+            continue;
+        }
+        let has_password = serde_json::from_str::<serde_json::Value>(&cipher.data)
+            .is_ok_and(|data| data["password"].as_str().is_some_and(|p| !p.is_empty()));
+        if !has_password {
+            continue;
+        }
+        let changed_at = cipher.password_last_changed.unwrap_or(cipher.created_at);
+        let age_days = (now - changed_at).num_days();
+        if age_days >= max_age_days {
+            items.push(json!({
+                "cipherId": cipher.uuid,
+                "passwordLastChanged": cipher.password_last_changed.as_ref().map(crate::util::format_date),
+                "ageDays": age_days,
+            }));
+        }
+    }
+
+    Ok(Json(json!({
+        "maxAgeDays": max_age_days,
+        "items": items,
+    })))
+}
+
+/// Lists the members of the organization without any two-step login provider, invited members are left out.
+#[get("/org/<org_id>/report/2fa")]
+async fn get_org_2fa_report(_auth: VWApi, org_id: OrganizationId, mut conn: DbConn) -> JsonResult {
+    if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
+        err_code!("Organization not found", Status::NotFound.code)
+    }
+    let deadline = match OrgPolicy::find_by_org_and_type(&org_id, OrgPolicyType::TwoFactorDeadline, &mut conn).await {
+        Some(policy) if policy.enabled => {
+            serde_json::from_str::<TwoFactorDeadlinePolicyData>(&policy.data).ok().map(|data| data.deadline)
+        }
+        _ => None,
+    };
+
+    let mut members = Vec::new();
+    for member in Membership::find_by_org(&org_id, &mut conn).await {
+        if member.status == MembershipStatus::Invited as i32
+            || !TwoFactor::find_by_user(&member.user_uuid, &mut conn).await.is_empty()
+        {
+            continue;
+        }
+        let Some(user) = User::find_by_uuid(&member.user_uuid, &mut conn).await else {
+            continue;
+        };
+        members.push(json!({
+            "id": member.uuid,
+            "userId": user.uuid,
+            "email": user.email,
+            "name": user.name,
+            "type": member.atype,
+            "status": member.status,
+        }));
+    }
+
+    Ok(Json(json!({
+        "deadline": deadline,
+        "members": members,
+    })))
+}
+
+/// The usage of the organization for the chargeback, polled by the billing system. Events are counted over the
+/// last `days`, 30 by default, the storage is the size of the attachments and the file sends in bytes.
+#[get("/org/<org_id>/usage?<days>")]
+async fn get_org_usage(_auth: VWApi, org_id: OrganizationId, days: Option<i64>, mut conn: DbConn) -> JsonResult {
+    let Some(org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err_code!("Organization not found", Status::NotFound.code)
+    };
+    let days = days.unwrap_or(30);
+    if !(1..=366).contains(&days) {
+        err!("The period has to be between 1 and 366 days")
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let period_start = now - chrono::TimeDelta::days(days);
+
+    let attachments_size = Attachment::size_by_org(&org_id, &mut conn).await;
+    let Some(sends_size) = Send::size_by_org(&org_id, &mut conn).await else {
+        err!("The size of the sends of the organization can't be computed")
+    };
+    let report = Report::find_by_org(&org_id, &mut conn).await;
+
+    Ok(Json(json!({
+        "organizationId": org.uuid,
+        "name": org.name,
+        "billingEmail": org.billing_email,
+        "generatedAt": crate::util::format_date(&now),
+        "seats": {
+            "max": org.effective_max_seats(),
+            "used": Membership::count_seats_by_org(&org_id, &mut conn).await,
+            "members": Membership::count_by_org(&org_id, &mut conn).await,
+        },
+        "storage": {
+            "limitKb": org.effective_attachment_limit(),
+            "attachmentsBytes": attachments_size,
+            "sendsBytes": sends_size,
+            "totalBytes": attachments_size.saturating_add(sends_size),
+            "attachmentCount": Attachment::count_by_org(&org_id, &mut conn).await,
+        },
+        "collections": Collection::count_by_org(&org_id, &mut conn).await,
+        "groups": Group::count_by_org(&org_id, &mut conn).await,
+        "ciphers": Cipher::count_by_org(&org_id, &mut conn).await,
+        "events": {
+            "periodDays": days,
+            "periodStart": crate::util::format_date(&period_start),
+            "inPeriod": Event::count_by_org_since(&org_id, &period_start, &mut conn).await,
+            "total": Event::count_by_org(&org_id, &mut conn).await,
+        },
+        "report": report.map(|r| json!({
+            "exposedCount": r.exposed_count,
+            "lastUpdatedAt": crate::util::format_date(&r.last_updated_at),
+            "thresholdExceededSince": r.threshold_exceeded_since.as_ref().map(crate::util::format_date),
+        })),
+    })))
+}
+
+/// The exposed counts per collection, the highest first, to find which team's collection holds the exposed passwords.
+/// Only the collections reported with `collections` in `/exposed` are listed.
+#[get("/org/<org_id>/report/collections")]
+async fn get_org_collections_report(_auth: VWApi, org_id: OrganizationId, mut conn: DbConn) -> JsonResult {
+    if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
+        err_code!("Organization not found", Status::NotFound.code)
+    }
+
+    let mut collections = Vec::new();
+    for report in Report::find_collections_by_org(&org_id, &mut conn).await {
+        let Some(collection_uuid) = report.collection_uuid else {
+            continue;
+        };
+        let Some(collection) = Collection::find_by_uuid(&collection_uuid, &mut conn).await else {
+            continue;
+        };
+        collections.push(json!({
+            "collectionId": collection.uuid,
+            // Encrypted with the organization key
+            "name": collection.name,
+            "externalId": collection.external_id,
+            "exposedCount": report.exposed_count,
+            "lastUpdatedAt": crate::util::format_date(&report.last_updated_at),
+        }));
+    }
+
+    Ok(Json(json!({
+        "organizationId": org_id,
+        "exposedCount": Report::find_by_org(&org_id, &mut conn).await.map(|r| r.exposed_count),
+        "collections": collections,
+    })))
+}
+
+#[derive(rocket::Responder)]
+struct ReportExportResponse {
+    file: (ContentType, Vec<u8>),
+    disposition: Header<'static>,
+}
+
+/// Exports the vault health report of the organization as a signed CSV or PDF file, for auditors which need a
+/// tamper-evident copy: the totals, the findings per member and the daily history of the last `days`, 90 by default.
+/// The findings per member and the history are only available when the scanner sends `findings` to `/exposed`.
+#[get("/org/<org_id>/report/export?<format>&<days>")]
+async fn get_org_report_export(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    format: &str,
+    days: Option<i64>,
+    mut conn: DbConn,
+) -> ApiResult<ReportExportResponse> {
+    let Some(org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+        err_code!("Organization not found", Status::NotFound.code)
+    };
+    let days = days.unwrap_or(90);
+    if !(1..=366).contains(&days) {
+        err!("The period has to be between 1 and 366 days")
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let mut members = Vec::new();
+    for finding in ReportMember::find_by_org(&org_id, &mut conn).await {
+        let Some(user) = User::find_by_uuid(&finding.user_uuid, &mut conn).await else {
+            continue;
+        };
+        members.push(ExportMember {
+            email: user.email,
+            name: user.name,
+            exposed_count: finding.exposed_count,
+            weak_count: finding.weak_count,
+            reused_count: finding.reused_count,
+            updated_at: finding.updated_at,
+        });
+    }
+    let (weak_count, reused_count) = ReportMember::totals_by_org(&org_id, &mut conn).await;
+    let since = (now - chrono::TimeDelta::days(days)).date();
+    let export = ReportExport {
+        org_name: org.name,
+        org_id: org.uuid.to_string(),
+        generated_at: now,
+        // Only counts stored by a logged in client are signed
+        exposed_count: Report::find_by_org(&org_id, &mut conn)
+            .await
+            .filter(|r| r.authenticated)
+            .map_or(0, |r| r.exposed_count),
+        weak_count,
+        reused_count,
+        members,
+        history: ReportHistory::find_by_org_since(&org_id, since, &mut conn).await,
+    };
+
+    let (content_type, extension, file) = match format {
+        "csv" => (ContentType::CSV, "csv", export.to_csv()?),
+        "pdf" => (ContentType::PDF, "pdf", export.to_pdf()?),
+        _ => err!("The format has to be `csv` or `pdf`"),
+    };
+    let filename = format!("vaultwarden_report_{}_{}.{extension}", org.uuid, now.format("%Y%m%d"));
+    Ok(ReportExportResponse {
+        file: (content_type, file),
+        disposition: Header::new("Content-Disposition", format!("attachment; filename=\"{filename}\"")),
+    })
+}
+
+/// The exposure reports of all organizations in one call, for providers managing many tenant organizations:
+/// the totals, the ten organizations with the highest exposed count, and how long ago each report was updated.
+/// Reports older than `stale_days`, 7 by default, and organizations without a report are flagged as stale.
+#[get("/reports/summary?<stale_days>")]
+async fn get_reports_summary(_auth: VWApi, stale_days: Option<i64>, mut conn: DbConn) -> JsonResult {
+    let stale_days = stale_days.unwrap_or(7);
+    if !(1..=366).contains(&stale_days) {
+        err!("The staleness has to be between 1 and 366 days")
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let (personal_exposed, org_exposed) = Report::total_exposed_counts(&mut conn).await;
+    let mut reports: std::collections::HashMap<OrganizationId, Report> = Report::find_all_orgs(&mut conn)
+        .await
+        .into_iter()
+        .filter_map(|report| Some((report.org_uuid.clone()?, report)))
+        .collect();
+
+    let mut organizations = Vec::new();
+    for org in Organization::get_all(&mut conn).await {
+        let report = reports.remove(&org.uuid);
+        let age_days = report.as_ref().map(|r| (now - r.last_updated_at).num_days());
+        organizations.push((org, report, age_days));
+    }
+
+    let mut top = organizations
+        .iter()
+        .filter_map(|(org, report, _)| Some((org, report.as_ref()?)))
+        .filter(|(_, report)| report.exposed_count > 0)
+        .collect::<Vec<_>>();
+    top.sort_by(|a, b| b.1.exposed_count.cmp(&a.1.exposed_count));
+    let top: Vec<serde_json::Value> = top
+        .into_iter()
+        .take(10)
+        .map(|(org, report)| {
+            json!({
+                "organizationId": org.uuid,
+                "name": org.name,
+                "exposedCount": report.exposed_count,
+                "thresholdExceededSince": report.threshold_exceeded_since.as_ref().map(crate::util::format_date),
+            })
+        })
+        .collect();
+
+    // The oldest reports first, organizations which never reported lead the list
+    organizations.sort_by_key(|(_, _, age_days)| std::cmp::Reverse(age_days.unwrap_or(i64::MAX)));
+    let stale_count = organizations.iter().filter(|(_, _, age)| age.is_none_or(|age| age >= stale_days)).count();
+    let staleness: Vec<serde_json::Value> = organizations
+        .iter()
+        .map(|(org, report, age_days)| {
+            json!({
+                "organizationId": org.uuid,
+                "name": org.name,
+                "exposedCount": report.as_ref().map(|r| r.exposed_count),
+                "lastUpdatedAt": report.as_ref().map(|r| crate::util::format_date(&r.last_updated_at)),
+                "ageDays": age_days,
+                "stale": age_days.is_none_or(|age| age >= stale_days),
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "organizationCount": organizations.len(),
+        "reportedOrganizations": organizations.iter().filter(|(_, report, _)| report.is_some()).count(),
+        "staleOrganizations": stale_count,
+        "totalExposed": org_exposed,
+        "personalExposed": personal_exposed,
+        "topOrganizations": top,
+        "organizations": staleness,
+        "generatedAt": crate::util::format_date(&now),
+    })))
+}
+
+/// The risk score of the organization, 0 to 100 where higher is worse, with the daily scores of the last `days`,
+/// 30 by default. It is made of the exposed passwords per member (40 points), the trend of the exposed count over
+/// the last week (20), the members without two-step login (25) and the age of the report (15).
+/// Without a score yet, for example before the first run of `RISK_SCORE_SCHEDULE`, it is calculated right away.
+#[get("/org/<org_id>/risk?<days>")]
+async fn get_org_risk(_auth: VWApi, org_id: OrganizationId, days: Option<i64>, mut conn: DbConn) -> JsonResult {
+    if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
+        err_code!("Organization not found", Status::NotFound.code)
+    }
+    let days = days.unwrap_or(30);
+    if !(1..=366).contains(&days) {
+        err!("The period has to be between 1 and 366 days")
+    }
+
+    let latest = match OrgRiskScore::find_latest_by_org(&org_id, &mut conn).await {
+        Some(score) => score,
+        None => {
+            let score = OrgRiskScore::calculate(&org_id, &mut conn).await;
+            score.save(&mut conn).await?;
+            score
+        }
+    };
+    let since = chrono::Utc::now().date_naive() - chrono::TimeDelta::days(days);
+    let history: Vec<serde_json::Value> = OrgRiskScore::find_by_org_since(&org_id, since, &mut conn)
+        .await
+        .iter()
+        .map(|score| {
+            json!({
+                "day": score.day.format("%Y-%m-%d").to_string(),
+                "score": score.score,
+            })
+        })
+        .collect();
+
+    let mut risk_json = latest.to_json();
+    risk_json["history"] = json!(history);
+    Ok(Json(risk_json))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgPurgeData {
+    // Only removes the items in the trash
+    #[serde(default)]
+    trashed_only: bool,
+    // Returned by the first request, nothing is removed until it is sent back
+    confirmation_token: Option<String>,
+}
+
+/// The items a purge removes, all the items of the organization or only the trashed ones.
+async fn find_purged_ciphers(org_id: &OrganizationId, trashed_only: bool, conn: &mut DbConn) -> Vec<Cipher> {
+    Cipher::find_by_org(org_id, conn)
+        .await
+        .into_iter()
+        .filter(|cipher| !trashed_only || cipher.deleted_at.is_some())
+        .collect()
+}
+
+/// Identifies the exact set of items, so a confirmation token can't remove items which were added or
+/// replaced after it was issued, even if their number stayed the same.
+fn purged_ciphers_hash(ciphers: &[Cipher]) -> String {
+    let mut cipher_ids: Vec<String> = ciphers.iter().map(|cipher| cipher.uuid.to_string()).collect();
+    cipher_ids.sort_unstable();
+    crypto::sha256_hex(&cipher_ids.join(","))
+}
+
+/// Removes the items of the organization and their attachments, or only the trashed ones.
+/// The first request only returns what would be removed with a confirmation token, which is valid for 10 minutes.
+/// Sending the token back does the purge, as long as the items didn't change in the meantime.
+/// Either all the items are removed or none.
+#[post("/org/<org_id>/purge", format = "application/json", data = "<data>")]
+async fn purge_org(
+    _auth: VWApi,
+    org_id: OrganizationId,
+    data: Json<OrgPurgeData>,
+    ip: ClientIp,
+    nt: Notify<'_>,
+    mut conn: DbConn,
+) -> JsonResult {
+    let data = data.into_inner();
+    if Organization::find_by_uuid(&org_id, &mut conn).await.is_none() {
+        err_code!("Organization not found", Status::NotFound.code)
+    }
+
+    let Some(token) = data.confirmation_token else {
+        let ciphers = find_purged_ciphers(&org_id, data.trashed_only, &mut conn).await;
+        let mut attachment_count = 0;
+        for cipher in &ciphers {
+            attachment_count += Attachment::find_by_cipher(&cipher.uuid, &mut conn).await.len();
+        }
+        let claims = generate_org_purge_claims(org_id, data.trashed_only, purged_ciphers_hash(&ciphers));
+        return Ok(Json(json!({
+            "confirmationToken": encode_jwt(&claims),
+            "trashedOnly": data.trashed_only,
+            "cipherCount": ciphers.len(),
+            "attachmentCount": attachment_count,
+            "purged": false,
+        })));
+    };
+
+    let claims = decode_org_purge(&token)?;
+    if claims.sub != org_id || claims.trashed_only != data.trashed_only {
+        err!("The confirmation token isn't valid for this purge")
+    }
+
+    begin_transaction(&mut conn).await?;
+    let result: ApiResult<(Vec<Cipher>, usize)> = async {
+        let ciphers = find_purged_ciphers(&org_id, data.trashed_only, &mut conn).await;
+        if purged_ciphers_hash(&ciphers) != claims.ciphers_hash {
+            err!(
+                "The items of the organization changed since the purge was confirmed, request a new confirmation token"
+            )
+        }
+        let mut attachment_count = 0;
+        for cipher in &ciphers {
+            attachment_count += Attachment::find_by_cipher(&cipher.uuid, &mut conn).await.len();
+            cipher.delete(&mut conn).await?;
+        }
+        Ok((ciphers, attachment_count))
+    }
+    .await;
+    let (ciphers, attachment_count) = match result {
+        Ok(purged) => purged,
+        Err(e) => {
+            if let Err(rollback_err) = rollback_transaction(&mut conn).await {
+                error!("Error rolling back the purge of the organization: {rollback_err:#?}");
+            }
+            return Err(e);
+        }
+    };
+    commit_transaction(&mut conn).await?;
+
+    for cipher in &ciphers {
+        log_event(
+            EventType::CipherDeleted as i32,
+            &cipher.uuid,
+            &org_id,
+            &ACTING_ADMIN_USER.into(),
+            14, // Use UnknownBrowser type
+            &ip.ip,
+            &mut conn,
+        )
+        .await;
+    }
+    if !data.trashed_only {
+        log_event(
+            EventType::OrganizationPurgedVault as i32,
+            &org_id,
+            &org_id,
+            &ACTING_ADMIN_USER.into(),
+            14, // Use UnknownBrowser type
+            &ip.ip,
+            &mut conn,
+        )
+        .await;
+    }
+
+    // The members sync their whole vault, like after a purge from the web vault
+    for member in Membership::find_confirmed_by_org(&org_id, &mut conn).await {
+        if let Some(user) = User::find_by_uuid(&member.user_uuid, &mut conn).await {
+            nt.send_user_update(UpdateType::SyncVault, &user, &None, &mut conn).await;
+        }
+    }
+
+    Ok(Json(json!({
+        "trashedOnly": data.trashed_only,
+        "cipherCount": ciphers.len(),
+        "attachmentCount": attachment_count,
+        "purged": true,
+    })))
+}
+
+/// Lists the latest uploads rejected by the virus scanner, optionally only the ones of an organization.
+#[get("/quarantine?<org_id>")]
+async fn get_quarantine(_auth: VWApi, org_id: Option<OrganizationId>, mut conn: DbConn) -> JsonResult {
+    let files = match org_id {
+        Some(org_id) => QuarantinedFile::find_by_org(&org_id, &mut conn).await,
+        None => QuarantinedFile::find_latest(&mut conn).await,
+    };
+    let files: Vec<serde_json::Value> = files.iter().map(QuarantinedFile::to_json).collect();
+    Ok(Json(serde_json::Value::Array(files)))
+}
+
+#[get("/providers")]
+async fn get_providers(_auth: VWApi, mut conn: DbConn) -> JsonResult {
+    let mut providers_json = Vec::new();
+    for provider in Provider::get_all(&mut conn).await {
+        let mut provider_json = provider.to_json();
+        provider_json["userCount"] = json!(ProviderUser::find_by_provider(&provider.uuid, &mut conn).await.len());
+        provider_json["organizationCount"] =
+            json!(ProviderOrganization::find_by_provider(&provider.uuid, &mut conn).await.len());
+        providers_json.push(provider_json);
+    }
+    Ok(Json(serde_json::Value::Array(providers_json)))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderCreateData {
+    name: String,
+    billing_email: String,
+    // The first admin, who sets up the provider
+    owner_email: String,
+}
+
+/// Creates a provider, its owner receives the link to set it up. Without mail the link is returned instead.
+#[post("/providers", format = "application/json", data = "<data>")]
+async fn post_provider(_auth: VWApi, data: Json<ProviderCreateData>, mut conn: DbConn) -> JsonResult {
+    let data: ProviderCreateData = data.into_inner();
+    let name = data.name.trim().to_string();
+    if name.is_empty() {
+        err!("The provider name can't be empty")
+    }
+
+    let mut provider = Provider::new(name, data.billing_email);
+    provider.save(&mut conn).await?;
+
+    let owner_email = data.owner_email.to_lowercase();
+    let provider_user =
+        match invite_provider_user(&provider, &owner_email, ProviderUserType::ProviderAdmin, true, &mut conn).await {
+            Ok(provider_user) => provider_user,
+            Err(e) => {
+                provider.delete(&mut conn).await?;
+                return Err(e);
+            }
+        };
+
+    let setup_url = match User::find_by_uuid(&provider_user.user_uuid, &mut conn).await {
+        Some(owner) if !CONFIG.mail_enabled() => {
+            Some(mail::provider_invite_url(&owner, provider.uuid.clone(), provider_user.uuid, &provider.name, true)?)
+        }
+        _ => None,
+    };
+
+    let mut provider_json = provider.to_json();
+    provider_json["setupUrl"] = json!(setup_url);
+    Ok(Json(provider_json))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderStatusData {
+    enabled: bool,
+}
+
+/// Enables or disables a provider, the users of a disabled provider can't administer its client organizations.
+#[put("/providers/<provider_id>", format = "application/json", data = "<data>")]
+async fn put_provider(
+    _auth: VWApi,
+    provider_id: ProviderId,
+    data: Json<ProviderStatusData>,
+    mut conn: DbConn,
+) -> JsonResult {
+    let Some(mut provider) = Provider::find_by_uuid(&provider_id, &mut conn).await else {
+        err_code!("Provider not found", Status::NotFound.code)
+    };
+    provider.enabled = data.into_inner().enabled;
+    provider.save(&mut conn).await?;
+
+    for provider_user in ProviderUser::find_by_provider(&provider_id, &mut conn).await {
+        User::update_uuid_revision(&provider_user.user_uuid, &mut conn).await;
+    }
+    Ok(Json(provider.to_json()))
+}
+
+/// Deletes a provider, its client organizations keep existing on their own.
+#[delete("/providers/<provider_id>")]
+async fn delete_provider(_auth: VWApi, provider_id: ProviderId, mut conn: DbConn) -> EmptyResult {
+    let Some(provider) = Provider::find_by_uuid(&provider_id, &mut conn).await else {
+        err_code!("Provider not found", Status::NotFound.code)
+    };
+    provider.delete(&mut conn).await
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MaintenanceData {
+    mode: String,
+    message: Option<String>,
+}
+
+#[get("/maintenance")]
+async fn get_maintenance(_auth: VWApi) -> JsonResult {
+    let data = MaintenanceData {
+        mode: CONFIG.maintenance_mode(),
+        message: CONFIG.maintenance_message().filter(|m| !m.is_empty()),
+    };
+    Ok(Json(serde_json::to_value(data).unwrap()))
+}
+
+/// Switches the maintenance mode, the same setting as `MAINTENANCE_MODE` in the admin panel.
+#[put("/maintenance", format = "application/json", data = "<data>")]
+async fn put_maintenance(_auth: VWApi, data: Json<MaintenanceData>) -> JsonResult {
+    let data: MaintenanceData = data.into_inner();
+    if let Err(e) = CONFIG.set_maintenance_mode(data.mode, data.message).await {
+        err_code!(format!("Unable to update the maintenance mode: {}", e.message()), Status::BadRequest.code)
+    }
+    info!("Maintenance mode set to {} via the custom API", CONFIG.maintenance_mode());
+    get_maintenance(VWApi).await
+}
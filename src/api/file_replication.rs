@@ -0,0 +1,251 @@
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicI64, Ordering},
+};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use crate::{
+    config::PathType,
+    db::{
+        models::{Attachment, AttachmentId, Send, SendId},
+        DbConn, DbPool,
+    },
+    error::Error,
+    util::format_date,
+    CONFIG,
+};
+
+// Mirrors the attachments and Sends to `REPLICA_FOLDER`. The files are never changed once stored, a new file always
+// gets a new id, so a file which is on both sides is in sync and only the missing files have to be copied.
+// Only this job writes to the replica, so its files are listed once and then kept up to date with what the job
+// copies and removes. A run from the admin API lists the replica again, in case it was changed by hand.
+
+// The files of each store on the replica, the lock also prevents concurrent runs
+static REPLICA_FILES: Lazy<Mutex<[Option<HashSet<String>>; 2]>> = Lazy::new(|| Mutex::new([None, None]));
+// Holds the report of the last run, with the comparison of the stores returned by the consistency check
+static LAST_FILE_REPLICATION: Lazy<Mutex<Option<Value>>> = Lazy::new(|| Mutex::new(None));
+// The start of the last run without errors as a timestamp, every file stored before it is on the replica
+static LAST_COMPLETE_REPLICATION: AtomicI64 = AtomicI64::new(0);
+
+// The number of paths listed by the consistency check, the counts are always complete
+const LISTED_FILES: usize = 50;
+
+#[derive(Default, Serialize)]
+struct FileReplicationReport {
+    date: String,
+    copied: usize,
+    removed: usize,
+    errors: Vec<String>,
+    stores: Vec<Value>,
+}
+
+#[derive(Clone, Copy)]
+enum Store {
+    Attachments,
+    Sends,
+}
+
+impl Store {
+    const ALL: [Self; 2] = [Self::Attachments, Self::Sends];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Attachments => "attachments",
+            Self::Sends => "sends",
+        }
+    }
+
+    fn primary(self) -> Result<opendal::Operator, Error> {
+        CONFIG.opendal_operator_for_path_type(match self {
+            Self::Attachments => PathType::Attachments,
+            Self::Sends => PathType::Sends,
+        })
+    }
+
+    fn replica(self) -> Result<opendal::Operator, Error> {
+        CONFIG.opendal_operator_for_path_type(match self {
+            Self::Attachments => PathType::ReplicaAttachments,
+            Self::Sends => PathType::ReplicaSends,
+        })
+    }
+
+    /// Whether the file still belongs to an attachment or a Send, the paths are `<cipher>/<attachment>`
+    /// and `<send>/<file>`.
+    async fn is_referenced(self, path: &str, conn: &mut DbConn) -> bool {
+        let Some((parent, id)) = path.split_once('/') else {
+            return false;
+        };
+        match self {
+            Self::Attachments => Attachment::find_by_id(&AttachmentId(id.to_string()), conn).await.is_some(),
+            Self::Sends => Send::find_by_uuid(&SendId::from(parent.to_string()), conn).await.is_some(),
+        }
+    }
+}
+
+pub async fn file_replication_job(pool: DbPool) {
+    debug!("Start file replication job");
+    if CONFIG.replica_folder().is_none() {
+        debug!("REPLICA_FOLDER is not configured, abort");
+        return;
+    }
+
+    if let Ok(mut conn) = pool.get().await {
+        run_file_replication(false, &mut conn).await;
+    } else {
+        error!("Failed to get DB connection while replicating the files")
+    }
+}
+
+/// Copies the files missing from the replica and removes the replicated files of deleted attachments and Sends.
+/// With `relist` the files of the replica are listed again instead of using the ones known from the last run.
+/// The report is stored for the consistency check, and also returned.
+pub async fn run_file_replication(relist: bool, conn: &mut DbConn) -> Value {
+    let mut replica_files = REPLICA_FILES.lock().await;
+
+    let started = Utc::now();
+    let mut report = FileReplicationReport {
+        date: format_date(&started.naive_utc()),
+        ..Default::default()
+    };
+    for store in Store::ALL {
+        let known_files = &mut replica_files[store as usize];
+        if relist {
+            *known_files = None;
+        }
+        match replicate_store(store, known_files, &mut report, conn).await {
+            Ok(comparison) => report.stores.push(comparison),
+            Err(e) => {
+                report.errors.push(format!("Replicating the {} failed: {e:?}", store.name()));
+                report.stores.push(json!({
+                    "name": store.name(),
+                    "error": format!("{e:?}"),
+                }));
+            }
+        }
+    }
+
+    if report.errors.is_empty() {
+        LAST_COMPLETE_REPLICATION.store(started.timestamp(), Ordering::Relaxed);
+    } else {
+        warn!("File replication finished with errors: {}", report.errors.join(", "));
+    }
+    let report = serde_json::to_value(report).unwrap_or_default();
+    *LAST_FILE_REPLICATION.lock().await = Some(report.clone());
+    report
+}
+
+/// Replicates one store and returns how it compares with the primary storage afterwards.
+async fn replicate_store(
+    store: Store,
+    known_files: &mut Option<HashSet<String>>,
+    report: &mut FileReplicationReport,
+    conn: &mut DbConn,
+) -> Result<Value, Error> {
+    let primary = store.primary()?;
+    let replica = store.replica()?;
+    let primary_files = list_files(&primary).await?;
+    let mut replica_files = match known_files.take() {
+        Some(files) => files,
+        None => list_files(&replica).await?,
+    };
+
+    let missing: Vec<String> = primary_files.difference(&replica_files).cloned().collect();
+    for path in missing {
+        match copy_file(&primary, &replica, &path).await {
+            Ok(()) => {
+                report.copied += 1;
+                replica_files.insert(path);
+            }
+            Err(e) => {
+                // A partial copy would look replicated once the replica is listed again
+                replica.delete(&path).await.ok();
+                report.errors.push(format!("Copying {}/{path} failed: {e:?}", store.name()));
+            }
+        }
+    }
+
+    // Files are only removed once their attachment or Send is deleted, so losing the primary storage
+    // doesn't take the replica with it. The ones which still belong to one were lost by the primary storage.
+    let mut lost = Vec::new();
+    let replica_only: Vec<String> = replica_files.difference(&primary_files).cloned().collect();
+    for path in replica_only {
+        if store.is_referenced(&path, conn).await {
+            lost.push(path);
+            continue;
+        }
+        match replica.delete(&path).await {
+            Ok(()) => {
+                report.removed += 1;
+                replica_files.remove(&path);
+            }
+            Err(e) => report.errors.push(format!("Removing {}/{path} failed: {e:?}", store.name())),
+        }
+    }
+    lost.sort();
+
+    // The files which failed to copy
+    let mut missing: Vec<&String> = primary_files.difference(&replica_files).collect();
+    missing.sort();
+
+    let comparison = json!({
+        "name": store.name(),
+        "primaryFiles": primary_files.len(),
+        "replicaFiles": replica_files.len(),
+        "missingCount": missing.len(),
+        "missing": missing.iter().take(LISTED_FILES).collect::<Vec<_>>(),
+        "lostCount": lost.len(),
+        "lost": lost.iter().take(LISTED_FILES).collect::<Vec<_>>(),
+    });
+    *known_files = Some(replica_files);
+    Ok(comparison)
+}
+
+async fn list_files(operator: &opendal::Operator) -> Result<HashSet<String>, Error> {
+    let entries = match operator.list_with("/").recursive(true).await {
+        Ok(entries) => entries,
+        // Nothing was stored yet
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(entries.into_iter().filter(|entry| entry.metadata().is_file()).map(|entry| entry.path().to_string()).collect())
+}
+
+async fn copy_file(primary: &opendal::Operator, replica: &opendal::Operator, path: &str) -> Result<(), Error> {
+    use futures::AsyncWriteExt as _;
+
+    let size = primary.stat(path).await?.content_length();
+    let mut reader = primary.reader(path).await?.into_futures_async_read(0..size).await?;
+    let mut writer = replica.writer(path).await?.into_futures_async_write();
+    let copied = futures::io::copy(&mut reader, &mut writer).await?;
+    writer.close().await?;
+
+    // The file was still being written, it is copied again once it is complete
+    if copied != size || primary.stat(path).await?.content_length() != size {
+        err!(format!("The file changed while it was copied ({copied} of {size} bytes)"))
+    }
+    Ok(())
+}
+
+/// Returns the state of the last replication, with how the files of the primary and the replica storage compared
+/// at the end of it, the storages aren't listed again. The lag is the time since the start of the last run without
+/// errors, files stored after it may be missing.
+pub async fn check_file_replication() -> Value {
+    let running = REPLICA_FILES.try_lock().is_err();
+    let last_report = LAST_FILE_REPLICATION.lock().await.clone();
+    let last_complete = match LAST_COMPLETE_REPLICATION.load(Ordering::Relaxed) {
+        0 => None,
+        timestamp => DateTime::from_timestamp(timestamp, 0),
+    };
+
+    json!({
+        "running": running,
+        "lastComplete": last_complete.map(|date| format_date(&date.naive_utc())),
+        "lagSeconds": last_complete.map(|date| (Utc::now() - date).num_seconds()),
+        "lastReport": last_report,
+    })
+}
@@ -1,28 +1,40 @@
 use std::{
     collections::HashMap,
+    io::Cursor,
     net::IpAddr,
-    sync::Arc,
-    time::{Duration, SystemTime},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
 use bytes::{Bytes, BytesMut};
+use chrono::Utc;
+use data_encoding::HEXLOWER;
 use futures::{stream::StreamExt, TryFutureExt};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::{
     header::{self, HeaderMap, HeaderValue},
-    Client, Response,
+    Client, RequestBuilder, Response, StatusCode,
 };
+use ring::digest;
 use rocket::{http::ContentType, response::Redirect, Route};
+use serde_json::Value;
 use svg_hush::{data_url_filter, Filter};
 
 use html5gum::{Emitter, HtmlString, Readable, StringReader, Tokenizer};
+use image::{DynamicImage, ImageFormat, ImageReader};
 
 use crate::{
     config::PathType,
+    db::{models::IconCache, DbConn, DbPool},
     error::Error,
-    http_client::{get_reqwest_client_builder, should_block_address, CustomHttpClientError},
-    util::Cached,
+    http_client::{
+        check_icon_host, get_icon_client_builder, record_icon_request, should_block_address, CustomHttpClientError,
+    },
+    util::{get_display_size, Cached},
     CONFIG,
 };
 
@@ -66,7 +78,7 @@ static CLIENT: Lazy<Client> = Lazy::new(|| {
     let icon_download_timeout = Duration::from_secs(CONFIG.icon_download_timeout());
     let pool_idle_timeout = Duration::from_secs(10);
     // Reuse the client between requests
-    get_reqwest_client_builder()
+    get_icon_client_builder()
         .cookie_provider(Arc::clone(&cookie_store))
         .timeout(icon_download_timeout)
         .pool_max_idle_per_host(5) // Configure the Hyper Pool to only have max 5 idle connections
@@ -77,6 +89,13 @@ static CLIENT: Lazy<Client> = Lazy::new(|| {
         .expect("Failed to build client")
 });
 
+// Requests for icons answered from the cache, including the negatively cached ones, and those which weren't
+static ICON_CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static ICON_CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+// Icons removed to keep the cache below `ICON_CACHE_MAX_SIZE`, and the cache size measured by the last eviction run
+static ICON_CACHE_EVICTIONS: AtomicU64 = AtomicU64::new(0);
+static ICON_CACHE_SIZE: AtomicU64 = AtomicU64::new(0);
+
 // Build Regex only once since this takes a lot of time.
 static ICON_SIZE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?x)(\d+)\D*(\d+)").unwrap());
 
@@ -109,7 +128,7 @@ fn icon_external(domain: &str) -> Option<Redirect> {
 }
 
 #[get("/<domain>/icon.png")]
-async fn icon_internal(domain: &str) -> Cached<(ContentType, Vec<u8>)> {
+async fn icon_internal(domain: &str, mut conn: DbConn) -> Cached<(ContentType, Vec<u8>)> {
     const FALLBACK_ICON: &[u8] = include_bytes!("../static/images/fallback-icon.png");
 
     if !is_valid_domain(domain) {
@@ -130,9 +149,10 @@ async fn icon_internal(domain: &str) -> Cached<(ContentType, Vec<u8>)> {
         );
     }
 
-    match get_icon(domain).await {
-        Some((icon, icon_type)) => {
-            Cached::ttl((ContentType::new("image", icon_type), icon), CONFIG.icon_cache_ttl(), true)
+    match get_icon(domain, &mut conn).await {
+        Some(icon) => {
+            Cached::ttl((ContentType::new("image", icon.icon_type), icon.data), CONFIG.icon_cache_ttl(), true)
+                .etag(icon.etag)
         }
         _ => Cached::ttl((ContentType::new("image", "png"), FALLBACK_ICON.to_vec()), CONFIG.icon_cache_negttl(), true),
     }
@@ -174,98 +194,102 @@ fn is_valid_domain(domain: &str) -> bool {
     true
 }
 
-async fn get_icon(domain: &str) -> Option<(Vec<u8>, String)> {
-    let path = format!("{domain}.png");
+/// An icon as served to the clients, `etag` is the hash of its content.
+struct CachedIcon {
+    data: Vec<u8>,
+    icon_type: String,
+    etag: String,
+}
 
-    // Check for expiration of negatively cached copy
-    if icon_is_negcached(&path).await {
-        return None;
-    }
+async fn get_icon(domain: &str, conn: &mut DbConn) -> Option<CachedIcon> {
+    let path = format!("{domain}.png");
+    let mut entry = IconCache::find_by_domain(domain, conn).await;
 
-    if let Some(icon) = get_cached_icon(&path).await {
-        let icon_type = get_icon_type(&icon).unwrap_or("x-icon");
-        return Some((icon, icon_type.to_string()));
+    if let Some(entry) = &mut entry {
+        if !entry.is_expired() {
+            // Still negatively cached
+            if entry.miss {
+                ICON_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            if let Some(icon) = get_cached_icon(&path, entry).await {
+                ICON_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+                if let Err(e) = entry.touch(conn).await {
+                    warn!("Unable to update the icon cache of {domain}: {e:?}");
+                }
+                return Some(icon);
+            }
+        }
     }
+    ICON_CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
 
     if CONFIG.disable_icon_download() {
         return None;
     }
 
+    // An expired icon is first revalidated with the site, so an unchanged icon doesn't need to be downloaded again
+    if let Some(mut entry) = entry.filter(IconCache::can_revalidate) {
+        match revalidate_icon(&entry).await {
+            Ok(Some(icon)) => return Some(save_icon(domain, &path, icon, conn).await),
+            Ok(None) => {
+                if let Some(icon) = get_cached_icon(&path, &entry).await {
+                    debug!("Icon for {domain} has not been modified");
+                    entry.fetched_at = Utc::now().naive_utc();
+                    entry.accessed_at = entry.fetched_at;
+                    entry.file_size = icon.data.len() as i64;
+                    if let Err(e) = entry.save(conn).await {
+                        warn!("Unable to update the icon cache of {domain}: {e:?}");
+                    }
+                    return Some(icon);
+                }
+            }
+            Err(e) => debug!("Unable to revalidate icon for {domain}: {e:?}"),
+        }
+    }
+
     // Get the icon, or None in case of error
     match download_icon(domain).await {
-        Ok((icon, icon_type)) => {
-            save_icon(&path, icon.to_vec()).await;
-            Some((icon.to_vec(), icon_type.unwrap_or("x-icon").to_string()))
-        }
+        Ok(icon) => Some(save_icon(domain, &path, icon, conn).await),
         Err(e) => {
             // If this error comes from the custom resolver, this means this is a blocked domain
-            // or non global IP, don't save the miss file in this case to avoid leaking it
+            // or non global IP, don't save the miss in this case to avoid leaking it
             if let Some(error) = CustomHttpClientError::downcast_ref(&e) {
                 warn!("{error}");
                 return None;
             }
 
             warn!("Unable to download icon: {e:?}");
-            let miss_indicator = path + ".miss";
-            save_icon(&miss_indicator, vec![]).await;
+            if let Err(e) = IconCache::new_miss(domain.to_string()).save(conn).await {
+                warn!("Unable to update the icon cache of {domain}: {e:?}");
+            }
             None
         }
     }
 }
 
-async fn get_cached_icon(path: &str) -> Option<Vec<u8>> {
-    // Check for expiration of successfully cached copy
-    if icon_is_expired(path).await {
-        return None;
-    }
-
+async fn get_cached_icon(path: &str, entry: &IconCache) -> Option<CachedIcon> {
     // Try to read the cached icon, and return it if it exists
-    if let Ok(operator) = CONFIG.opendal_operator_for_path_type(PathType::IconCache) {
-        if let Ok(buf) = operator.read(path).await {
-            return Some(buf.to_vec());
-        }
-    }
-
-    None
-}
-
-async fn file_is_expired(path: &str, ttl: u64) -> Result<bool, Error> {
-    let operator = CONFIG.opendal_operator_for_path_type(PathType::IconCache)?;
-    let meta = operator.stat(path).await?;
-    let modified =
-        meta.last_modified().ok_or_else(|| std::io::Error::other(format!("No last modified time for `{path}`")))?;
-    let age = SystemTime::now().duration_since(modified.into())?;
-
-    Ok(ttl > 0 && ttl <= age.as_secs())
-}
+    let operator = CONFIG.opendal_operator_for_path_type(PathType::IconCache).ok()?;
+    let data = operator.read(path).await.ok()?.to_vec();
 
-async fn icon_is_negcached(path: &str) -> bool {
-    let miss_indicator = path.to_owned() + ".miss";
-    let expired = file_is_expired(&miss_indicator, CONFIG.icon_cache_negttl()).await;
+    let icon_type = match &entry.icon_type {
+        Some(icon_type) => icon_type.clone(),
+        None => get_icon_type(&data).unwrap_or("x-icon").to_string(),
+    };
+    let etag = match &entry.etag {
+        Some(etag) => etag.clone(),
+        None => icon_etag(&data),
+    };
 
-    match expired {
-        // No longer negatively cached, drop the marker
-        Ok(true) => {
-            match CONFIG.opendal_operator_for_path_type(PathType::IconCache) {
-                Ok(operator) => {
-                    if let Err(e) = operator.delete(&miss_indicator).await {
-                        error!("Could not remove negative cache indicator for icon {path:?}: {e:?}");
-                    }
-                }
-                Err(e) => error!("Could not remove negative cache indicator for icon {path:?}: {e:?}"),
-            }
-            false
-        }
-        // The marker hasn't expired yet.
-        Ok(false) => true,
-        // The marker is missing or inaccessible in some way.
-        Err(_) => false,
-    }
+    Some(CachedIcon {
+        data,
+        icon_type,
+        etag,
+    })
 }
 
-async fn icon_is_expired(path: &str) -> bool {
-    let expired = file_is_expired(path, CONFIG.icon_cache_ttl()).await;
-    expired.unwrap_or(true)
+fn icon_etag(data: &[u8]) -> String {
+    HEXLOWER.encode(digest::digest(&digest::SHA256, data).as_ref())
 }
 
 struct Icon {
@@ -446,7 +470,30 @@ async fn get_page_with_referer(url: &str, referer: &str) -> Result<Response, Err
         client = client.header("Referer", referer)
     }
 
-    Ok(client.send().await?.error_for_status()?)
+    Ok(send_request(client, url).await?.error_for_status()?)
+}
+
+/// Sends a request, and keeps track of the failed connections for the circuit breaker of the host.
+async fn send_request(request: RequestBuilder, url: &str) -> Result<Response, Error> {
+    let Some(host) = url::Url::parse(url).ok().and_then(|url| url.host().map(|host| host.to_owned())) else {
+        err_silent!("Invalid host")
+    };
+    check_icon_host(&host.as_ref())?;
+    let host = host.to_string();
+
+    match request.send().await {
+        Ok(res) => {
+            record_icon_request(&host, true);
+            Ok(res)
+        }
+        Err(e) => {
+            // Blocked requests never reached the host, so they don't count as a failure
+            if (e.is_connect() || e.is_timeout()) && CustomHttpClientError::downcast_ref(&e).is_none() {
+                record_icon_request(&host, false);
+            }
+            Err(e.into())
+        }
+    }
 }
 
 /// Returns a Integer with the priority of the type of the icon which to prefer.
@@ -527,11 +574,38 @@ fn parse_sizes(sizes: &str) -> (u16, u16) {
     (width, height)
 }
 
-async fn download_icon(domain: &str) -> Result<(Bytes, Option<&str>), Error> {
+/// The URL an icon was downloaded from, with the validators the site sent along with it.
+struct IconUpstream {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl IconUpstream {
+    fn from_response(res: &Response) -> Self {
+        let header_value = |name: header::HeaderName| res.headers().get(name)?.to_str().ok().map(str::to_string);
+
+        Self {
+            url: res.url().to_string(),
+            etag: header_value(header::ETAG),
+            last_modified: header_value(header::LAST_MODIFIED),
+        }
+    }
+}
+
+struct DownloadedIcon {
+    data: Vec<u8>,
+    icon_type: &'static str,
+    // Not available for icons extracted from a data:image uri
+    upstream: Option<IconUpstream>,
+}
+
+async fn download_icon(domain: &str) -> Result<DownloadedIcon, Error> {
     let icon_result = get_icon_url(domain).await?;
 
     let mut buffer = Bytes::new();
-    let mut icon_type: Option<&str> = None;
+    let mut icon_type: Option<&'static str> = None;
+    let mut upstream: Option<IconUpstream> = None;
 
     use data_url::DataUrl;
 
@@ -564,6 +638,7 @@ async fn download_icon(domain: &str) -> Result<(Bytes, Option<&str>), Error> {
             };
         } else {
             let res = get_page_with_referer(&icon.href, &icon_result.referer).await?;
+            let icon_upstream = IconUpstream::from_response(&res);
 
             buffer = stream_to_bytes_limit(res, 5120 * 1024).await?; // 5120KB/5MB for each icon max (Same as icons.bitwarden.net)
 
@@ -574,40 +649,221 @@ async fn download_icon(domain: &str) -> Result<(Bytes, Option<&str>), Error> {
                 debug!("Icon from {}, is not a valid image type", icon.href);
                 continue;
             }
+            upstream = Some(icon_upstream);
             info!("Downloaded icon from {}", icon.href);
             break;
         }
     }
 
-    if buffer.is_empty() {
+    let Some(icon_type) = icon_type.filter(|_| !buffer.is_empty()) else {
         err_silent!("Empty response or unable find a valid icon", domain);
-    } else if icon_type == Some("svg+xml") {
+    };
+    let (data, icon_type) = prepare_icon(buffer, icon_type).await?;
+
+    Ok(DownloadedIcon {
+        data,
+        icon_type,
+        upstream,
+    })
+}
+
+/// Requests an expired icon again from its URL, using the `ETag` and `Last-Modified` it was sent with.
+/// Returns `None` when the site answers that the icon has not been modified.
+async fn revalidate_icon(entry: &IconCache) -> Result<Option<DownloadedIcon>, Error> {
+    let Some(url) = &entry.upstream_url else {
+        err_silent!("No URL to revalidate the icon with", &entry.domain);
+    };
+
+    let mut request = CLIENT.get(url);
+    if let Some(etag) = &entry.upstream_etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &entry.upstream_last_modified {
+        request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let res = send_request(request, url).await?.error_for_status()?;
+    if res.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+
+    let upstream = IconUpstream::from_response(&res);
+    let buffer = stream_to_bytes_limit(res, 5120 * 1024).await?;
+    let Some(icon_type) = get_icon_type(&buffer) else {
+        err_silent!("Revalidated icon is not a valid image type", url);
+    };
+    let (data, icon_type) = prepare_icon(buffer, icon_type).await?;
+
+    Ok(Some(DownloadedIcon {
+        data,
+        icon_type,
+        upstream: Some(upstream),
+    }))
+}
+
+/// Sanitizes SVG icons, and scales down other icons which are larger than `ICON_THUMBNAIL_SIZE`.
+async fn prepare_icon(buffer: Bytes, icon_type: &'static str) -> Result<(Vec<u8>, &'static str), Error> {
+    if icon_type == "svg+xml" {
         let mut svg_filter = Filter::new();
         svg_filter.set_data_url_filter(data_url_filter::allow_standard_images);
         let mut sanitized_svg = Vec::new();
         if svg_filter.filter(&*buffer, &mut sanitized_svg).is_err() {
-            icon_type = None;
-            buffer.clear();
-        } else {
-            buffer = sanitized_svg.into();
+            err_silent!("Unable to sanitize the svg icon");
         }
+        return Ok((sanitized_svg, icon_type));
+    }
+
+    let max_size = CONFIG.icon_thumbnail_size();
+    if max_size == 0 {
+        return Ok((buffer.to_vec(), icon_type));
+    }
+
+    let original = buffer.clone();
+    match tokio::task::spawn_blocking(move || make_thumbnail(&buffer, max_size)).await {
+        Ok(Ok(Some(thumbnail))) => Ok(thumbnail),
+        Ok(Err(e)) => {
+            debug!("Unable to scale down the icon: {e}");
+            Ok((original.to_vec(), icon_type))
+        }
+        _ => Ok((original.to_vec(), icon_type)),
+    }
+}
+
+/// Returns the scaled down icon in the `ICON_THUMBNAIL_FORMAT`, or `None` if it isn't larger than `max_size`.
+fn make_thumbnail(buffer: &[u8], max_size: u32) -> image::ImageResult<Option<(Vec<u8>, &'static str)>> {
+    // Only read the dimensions first, most icons are small enough and don't need to be decoded
+    let (width, height) = ImageReader::new(Cursor::new(buffer)).with_guessed_format()?.into_dimensions()?;
+    if width <= max_size && height <= max_size {
+        return Ok(None);
     }
 
-    Ok((buffer, icon_type))
+    let (format, icon_type) = match CONFIG.icon_thumbnail_format().as_str() {
+        "png" => (ImageFormat::Png, "png"),
+        _ => (ImageFormat::WebP, "webp"),
+    };
+
+    // The WebP encoder only supports 8-bit images
+    let thumbnail = DynamicImage::from(image::load_from_memory(buffer)?.thumbnail(max_size, max_size).into_rgba8());
+    let mut output = Cursor::new(Vec::new());
+    thumbnail.write_to(&mut output, format)?;
+
+    Ok(Some((output.into_inner(), icon_type)))
 }
 
-async fn save_icon(path: &str, icon: Vec<u8>) {
+async fn save_icon(domain: &str, path: &str, icon: DownloadedIcon, conn: &mut DbConn) -> CachedIcon {
+    let etag = icon_etag(&icon.data);
+    let mut entry = IconCache::new(domain.to_string());
+    entry.icon_type = Some(icon.icon_type.to_string());
+    entry.etag = Some(etag.clone());
+    entry.file_size = icon.data.len() as i64;
+    if let Some(upstream) = icon.upstream {
+        entry.upstream_url = Some(upstream.url);
+        entry.upstream_etag = upstream.etag;
+        entry.upstream_last_modified = upstream.last_modified;
+    }
+
+    match CONFIG.opendal_operator_for_path_type(PathType::IconCache) {
+        Ok(operator) => {
+            if let Err(e) = operator.write(path, icon.data.clone()).await {
+                warn!("Unable to save icon: {e:?}");
+            } else if let Err(e) = entry.save(conn).await {
+                warn!("Unable to update the icon cache of {domain}: {e:?}");
+            }
+        }
+        Err(e) => warn!("Failed to get OpenDAL operator while saving icon: {e}"),
+    }
+
+    CachedIcon {
+        data: icon.data,
+        icon_type: icon.icon_type.to_string(),
+        etag,
+    }
+}
+
+// Entries loaded per query by the eviction job
+const EVICTION_BATCH_SIZE: i64 = 100;
+
+/// Removes the least recently served icons while the cache is larger than `ICON_CACHE_MAX_SIZE`.
+/// It evicts down to 90% of the maximum, so the cache doesn't go over it again with the next few icons.
+pub async fn icon_cache_eviction_job(pool: DbPool) {
+    let Some(max_size_mb) = CONFIG.icon_cache_max_size() else {
+        return;
+    };
+    debug!("Checking the size of the icon cache");
+    let Ok(mut conn) = pool.get().await else {
+        error!("Failed to get DB connection while checking the icon cache size");
+        return;
+    };
     let operator = match CONFIG.opendal_operator_for_path_type(PathType::IconCache) {
         Ok(operator) => operator,
         Err(e) => {
-            warn!("Failed to get OpenDAL operator while saving icon: {e}");
+            error!("Failed to get OpenDAL operator while checking the icon cache size: {e}");
             return;
         }
     };
 
-    if let Err(e) = operator.write(path, icon).await {
-        warn!("Unable to save icon: {e:?}");
+    // The icons stored before their size was recorded, all of them so the size of the cache is right.
+    // It stops at the first failure, otherwise the same entries would be loaded again and again.
+    'backfill: loop {
+        let entries = IconCache::find_without_size(EVICTION_BATCH_SIZE, &mut conn).await;
+        if entries.is_empty() {
+            break;
+        }
+        for mut entry in entries {
+            let res = match operator.stat(&format!("{}.png", entry.domain)).await {
+                Ok(metadata) => {
+                    entry.file_size = (metadata.content_length() as i64).max(1);
+                    entry.save(&mut conn).await
+                }
+                Err(e) if e.kind() == opendal::ErrorKind::NotFound => entry.delete(&mut conn).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = res {
+                warn!("Unable to record the size of the cached icon of {}: {e:?}", entry.domain);
+                break 'backfill;
+            }
+        }
+    }
+
+    let max_size = (max_size_mb as i64).saturating_mul(1024 * 1024);
+    let target_size = max_size / 10 * 9;
+    let mut size = IconCache::total_size(&mut conn).await;
+    if size > max_size {
+        'evict: while size > target_size {
+            let entries = IconCache::find_least_recently_used(EVICTION_BATCH_SIZE, &mut conn).await;
+            if entries.is_empty() {
+                break;
+            }
+            for entry in entries {
+                if let Err(e) = operator.delete(&format!("{}.png", entry.domain)).await {
+                    warn!("Unable to remove the cached icon of {}: {e:?}", entry.domain);
+                    break 'evict;
+                }
+                if let Err(e) = entry.delete(&mut conn).await {
+                    warn!("Unable to remove the icon cache entry of {}: {e:?}", entry.domain);
+                    break 'evict;
+                }
+                size -= entry.file_size;
+                ICON_CACHE_EVICTIONS.fetch_add(1, Ordering::Relaxed);
+                if size <= target_size {
+                    break;
+                }
+            }
+        }
+        info!("Evicted icons from the icon cache, its size is now {}", get_display_size(size));
     }
+    ICON_CACHE_SIZE.store(size.max(0) as u64, Ordering::Relaxed);
+}
+
+/// The hit and miss counters of the icon cache since the start, for the metrics of the admin API.
+pub fn icon_cache_metrics() -> Value {
+    json!({
+        "hits": ICON_CACHE_HITS.load(Ordering::Relaxed),
+        "misses": ICON_CACHE_MISSES.load(Ordering::Relaxed),
+        "evictions": ICON_CACHE_EVICTIONS.load(Ordering::Relaxed),
+        "sizeBytes": CONFIG.icon_cache_max_size().map(|_| ICON_CACHE_SIZE.load(Ordering::Relaxed)),
+        "maxSizeBytes": CONFIG.icon_cache_max_size().map(|mb| mb.saturating_mul(1024 * 1024)),
+    })
 }
 
 fn get_icon_type(bytes: &[u8]) -> Option<&'static str> {
@@ -1,5 +1,8 @@
+use std::{collections::HashSet, sync::Mutex};
+
 use chrono::{NaiveDateTime, Utc};
 use num_traits::FromPrimitive;
+use once_cell::sync::Lazy;
 use rocket::{
     form::{Form, FromForm},
     http::Status,
@@ -13,7 +16,8 @@ use crate::{
     api::{
         core::{
             accounts::{PreloginData, RegisterData, _prelogin, _register, kdf_upgrade},
-            log_user_event,
+            capture_domain_user, check_org_ip_restrictions, is_sso_required, log_user_event,
+            record_master_password_check,
             two_factor::{authenticator, duo, duo_oidc, email, enforce_2fa_policy, webauthn, yubikey},
         },
         master_password_policy,
@@ -21,8 +25,11 @@ use crate::{
         ApiResult, EmptyResult, JsonResult,
     },
     auth,
-    auth::{generate_organization_api_key_login_claims, AuthMethod, ClientHeaders, ClientIp, ClientVersion},
-    db::{models::*, DbConn},
+    auth::{
+        generate_organization_api_key_login_claims, generate_service_account_login_claims, AuthMethod, ClientHeaders,
+        ClientIp, ClientVersion,
+    },
+    db::{models::*, DbConn, DbPool},
     error::MapResult,
     mail, sso,
     sso::{OIDCCode, OIDCState},
@@ -53,6 +60,18 @@ async fn login(
     let data: ConnectData = data.into_inner();
 
     let mut user_id: Option<UserId> = None;
+    let password_login = data.grant_type == "password";
+
+    // Refreshing a token isn't a login attempt, recording those would only flood the audit table
+    let mut login_event = (data.grant_type != "refresh_token").then(|| {
+        let mut event = LoginEvent::new(data.grant_type.clone(), client_header.ip.ip.to_string(), false);
+        event.username = data.username.clone().or_else(|| data.client_id.clone());
+        event.device_type = data.device_type.as_ref().and_then(|d| d.parse().ok());
+        event.device_name = data.device_name.clone();
+        event.user_agent = client_header.user_agent.clone();
+        event.country = client_header.country.clone();
+        event
+    });
 
     let login_result = match data.grant_type.as_ref() {
         "refresh_token" => {
@@ -72,6 +91,13 @@ async fn login(
 
             _password_login(data, &mut user_id, &mut conn, &client_header.ip, &client_version).await
         }
+        // Secrets Manager access tokens are not bound to a device
+        "client_credentials" if data.scope.as_ref() == Some(&AuthMethod::ServiceAccount.scope()) => {
+            _check_is_some(&data.client_id, "client_id cannot be blank")?;
+            _check_is_some(&data.client_secret, "client_secret cannot be blank")?;
+
+            _service_account_login(data, &mut conn, &client_header.ip).await
+        }
         "client_credentials" => {
             _check_is_some(&data.client_id, "client_id cannot be blank")?;
             _check_is_some(&data.client_secret, "client_secret cannot be blank")?;
@@ -97,12 +123,12 @@ async fn login(
         t => err!("Invalid type", t),
     };
 
-    if let Some(user_id) = user_id {
+    if let Some(user_id) = &user_id {
         match &login_result {
             Ok(_) => {
                 log_user_event(
                     EventType::UserLoggedIn as i32,
-                    &user_id,
+                    user_id,
                     client_header.device_type,
                     &client_header.ip.ip,
                     &mut conn,
@@ -111,22 +137,70 @@ async fn login(
             }
             Err(e) => {
                 if let Some(ev) = e.get_event() {
-                    log_user_event(
-                        ev.event as i32,
-                        &user_id,
-                        client_header.device_type,
-                        &client_header.ip.ip,
-                        &mut conn,
-                    )
-                    .await
+                    log_user_event(ev.event as i32, user_id, client_header.device_type, &client_header.ip.ip, &mut conn)
+                        .await
                 }
             }
         }
     }
 
+    if password_login {
+        track_failed_logins(&login_result, user_id.as_ref(), &client_header.ip, &mut conn).await;
+    }
+
+    if let Some(ref mut event) = login_event {
+        event.user_uuid = user_id;
+        event.success = login_result.is_ok();
+        if let Err(e) = event.save(&mut conn).await {
+            error!("Error saving login event: {e:#?}");
+        }
+    }
+
     login_result
 }
 
+// Counts the wrong master passwords and two-step login codes, the lockout is checked by `_password_login`.
+async fn track_failed_logins(login_result: &JsonResult, user_id: Option<&UserId>, ip: &ClientIp, conn: &mut DbConn) {
+    let failed = match login_result {
+        Ok(_) => {
+            if let Some(user_id) = user_id {
+                crate::ratelimit::clear_failed_logins(user_id);
+            }
+            return;
+        }
+        Err(e) => e.get_event().as_ref().map(|ev| ev.event),
+    };
+    if !matches!(failed, Some(EventType::UserFailedLogIn | EventType::UserFailedLogIn2fa)) {
+        return;
+    }
+
+    let count = crate::ratelimit::register_failed_login(user_id, &ip.ip);
+    let notify_after = CONFIG.login_failure_notify_after();
+    let Some(user_id) = user_id.filter(|_| notify_after > 0 && count == notify_after && CONFIG.mail_enabled()) else {
+        return;
+    };
+    if let Some(user) = User::find_by_uuid(user_id, conn).await {
+        let now = Utc::now().naive_utc();
+        if let Err(e) = mail::send_failed_logins(&user.email, count, &ip.ip.to_string(), &now).await {
+            error!("Error sending the failed logins email: {e:#?}");
+        }
+    }
+}
+
+pub async fn login_event_cleanup_job(pool: DbPool) {
+    debug!("Start login events cleanup job");
+    if CONFIG.login_events_days_retain().is_none() {
+        debug!("login_events_days_retain is not configured, abort");
+        return;
+    }
+
+    if let Ok(mut conn) = pool.get().await {
+        LoginEvent::clean_old(&mut conn).await.ok();
+    } else {
+        error!("Failed to get DB connection while trying to cleanup the login events table")
+    }
+}
+
 // Return Status::Unauthorized to trigger logout
 async fn _refresh_login(data: ConnectData, conn: &mut DbConn, ip: &ClientIp) -> JsonResult {
     // Extract token
@@ -146,7 +220,18 @@ async fn _refresh_login(data: ConnectData, conn: &mut DbConn, ip: &ClientIp) ->
             err_code!(format!("Unable to refresh login credentials: {}", err.message()), Status::Unauthorized.code)
         }
         Ok((mut device, auth_tokens)) => {
+            // Sessions can't be extended until the user changed their master password, or the KDF settings if enforced
+            if let Some(user) = User::find_by_uuid(&device.user_uuid, conn).await {
+                check_kdf_upgrade(&user, ip)?;
+                if user.force_password_reset {
+                    err_json!(_json_err_force_password_reset(), format!("IP: {}. Username: {}.", ip.ip, user.email))
+                }
+                check_email_verify_deadline(&user, ip, conn).await?;
+                check_org_ip_restrictions(&user.uuid, device.atype, &ip.ip, conn).await?;
+            }
+
             // Save to update `device.updated_at` to track usage and toggle new status
+            device.last_ip = Some(ip.ip.to_string());
             device.save(conn).await?;
 
             let result = json!({
@@ -252,6 +337,10 @@ async fn _sso_login(
             user.verified_at = Some(now);
             user.save(conn).await?;
 
+            if let Err(e) = capture_domain_user(&user, conn).await {
+                error!("Error adding user to the organization of a claimed domain: {e:#?}");
+            }
+
             let device = get_device(&data, conn, &user).await?;
 
             (user, device, None, None)
@@ -265,9 +354,19 @@ async fn _sso_login(
                 }
             )
         }
+        Some((user, _)) if user.is_soft_deleted() && !user.self_deleted => {
+            err!(
+                "This user has been deleted",
+                format!("IP: {}. Username: {}.", ip.ip, user.name),
+                ErrorEvent {
+                    event: EventType::UserFailedLogIn
+                }
+            )
+        }
         Some((mut user, sso_user)) => {
             let mut device = get_device(&data, conn, &user).await?;
             let twofactor_token = twofactor_auth(&mut user, &data, &mut device, ip, client_version, conn).await?;
+            restore_self_deleted(&mut user, conn).await?;
 
             if user.private_key.is_none() {
                 // User was invited a stub was created
@@ -328,16 +427,34 @@ async fn _password_login(
 
     // Ratelimit the login
     crate::ratelimit::check_limit_login(&ip.ip)?;
+    crate::ratelimit::check_login_failures_ip(&ip.ip)?;
 
     // Get the user
     let username = data.username.as_ref().unwrap().trim();
     let Some(mut user) = User::find_by_mail(username, conn).await else {
-        err!("Username or password is incorrect. Try again", format!("IP: {}. Username: {username}.", ip.ip))
+        err!(
+            "Username or password is incorrect. Try again",
+            format!("IP: {}. Username: {username}.", ip.ip),
+            ErrorEvent {
+                event: EventType::UserFailedLogIn
+            }
+        )
     };
 
+    // The clients derive the master key from the email address, so the password of an alias can't match
+    if !user.email.eq_ignore_ascii_case(username) {
+        err!(
+            "This email address is an alias, log in with the main email address of the account",
+            format!("IP: {}. Username: {username}.", ip.ip)
+        )
+    }
+
     // Set the user_id here to be passed back used for event logging.
     *user_id = Some(user.uuid.clone());
 
+    // Delay the attempts after failed logins, and refuse them while the account is locked
+    crate::ratelimit::check_login_failures_user(&user.uuid)?;
+
     // Check if the user is disabled
     if !user.enabled {
         err!(
@@ -349,6 +466,17 @@ async fn _password_login(
         )
     }
 
+    // Check if the user is awaiting deletion, users who deleted their own account can still login to restore it
+    if user.is_soft_deleted() && !user.self_deleted {
+        err!(
+            "This user has been deleted",
+            format!("IP: {}. Username: {username}.", ip.ip),
+            ErrorEvent {
+                event: EventType::UserFailedLogIn
+            }
+        )
+    }
+
     let password = data.password.as_ref().unwrap();
 
     // If we get an auth request, we don't check the user's password, but the access code of the auth request
@@ -363,12 +491,11 @@ async fn _password_login(
             )
         };
 
-        let expiration_time = auth_request.creation_date + chrono::Duration::minutes(5);
-        let request_expired = Utc::now().naive_utc() >= expiration_time;
-
+        // An approved request can only be used for a single login
         if auth_request.user_uuid != user.uuid
             || !auth_request.approved.unwrap_or(false)
-            || request_expired
+            || auth_request.authentication_date.is_some()
+            || auth_request.is_expired()
             || ip.ip.to_string() != auth_request.request_ip
             || !auth_request.check_access_code(password)
         {
@@ -390,6 +517,17 @@ async fn _password_login(
         )
     }
 
+    // Accounts claimed by an organization which requires SSO can't use their master password anymore
+    if data.auth_request.is_none() && CONFIG.sso_enabled() && is_sso_required(&user, conn).await {
+        err!(
+            "SSO sign-in is required for your organization",
+            format!("IP: {}. Username: {username}.", ip.ip),
+            ErrorEvent {
+                event: EventType::UserFailedLogIn,
+            }
+        )
+    }
+
     // Change the KDF Iterations (only when not logging in with an auth request)
     if data.auth_request.is_none() {
         kdf_upgrade(&mut user, password, conn).await?;
@@ -397,7 +535,9 @@ async fn _password_login(
 
     let now = Utc::now().naive_utc();
 
-    if user.verified_at.is_none() && CONFIG.mail_enabled() && CONFIG.signups_verify() {
+    // Accounts past their verification deadline are refused like with `SIGNUPS_VERIFY`, and get the reminders as well
+    let verify_overdue = user.email_verify_deadline(conn).await.is_some_and(|deadline| deadline <= now);
+    if user.verified_at.is_none() && CONFIG.mail_enabled() && (CONFIG.signups_verify() || verify_overdue) {
         if user.last_verifying_at.is_none()
             || now.signed_duration_since(user.last_verifying_at.unwrap()).num_seconds()
                 > CONFIG.signups_verify_resend_time() as i64
@@ -432,23 +572,63 @@ async fn _password_login(
     let mut device = get_device(&data, conn, &user).await?;
 
     let twofactor_token = twofactor_auth(&mut user, &data, &mut device, ip, client_version, conn).await?;
+    restore_self_deleted(&mut user, conn).await?;
+
+    // The request is used up once the login fully went through, the 2FA step can still retry it before that
+    if let Some(ref auth_request_id) = data.auth_request {
+        if let Some(mut auth_request) = AuthRequest::find_by_uuid_and_user(auth_request_id, &user.uuid, conn).await {
+            auth_request.authentication_date = Some(now);
+            auth_request.save(conn).await?;
+        }
+    }
 
     let auth_tokens = auth::AuthTokens::new(&device, &user, AuthMethod::Password, data.client_id);
 
     authenticated_response(&user, &mut device, auth_tokens, twofactor_token, &now, conn, ip).await
 }
 
+// Accounts which didn't verify their email address in time don't get tokens anymore, see `User::email_verify_deadline`
+async fn check_email_verify_deadline(user: &User, ip: &ClientIp, conn: &mut DbConn) -> EmptyResult {
+    if user.email_verify_deadline(conn).await.is_some_and(|deadline| deadline <= Utc::now().naive_utc()) {
+        err!(
+            "Please verify your email before trying again.",
+            format!("IP: {}. Username: {}.", ip.ip, user.email),
+            ErrorEvent {
+                event: EventType::UserFailedLogIn
+            }
+        )
+    }
+    Ok(())
+}
+
+/// Restores a self-deleted account once the user fully authenticated during the grace period.
+async fn restore_self_deleted(user: &mut User, conn: &mut DbConn) -> EmptyResult {
+    if user.is_soft_deleted() && user.self_deleted {
+        user.restore(conn).await?;
+        info!("User {} restored their deleted account by logging in", user.email);
+    }
+    Ok(())
+}
+
+/// Whether a login from a new device is reported by email, it always is when the login depends on the email.
+fn new_device_email_enabled(user: &User) -> bool {
+    CONFIG.mail_enabled() && (CONFIG.require_device_email() || (CONFIG.new_device_email() && user.new_device_email))
+}
+
 async fn authenticated_response(
     user: &User,
     device: &mut Device,
-    auth_tokens: auth::AuthTokens,
+    mut auth_tokens: auth::AuthTokens,
     twofactor_token: Option<String>,
     now: &NaiveDateTime,
     conn: &mut DbConn,
     ip: &ClientIp,
 ) -> JsonResult {
-    if CONFIG.mail_enabled() && device.is_new() {
-        if let Err(e) = mail::send_new_device_logged_in(&user.email, &ip.ip.to_string(), now, device).await {
+    check_org_ip_restrictions(&user.uuid, device.atype, &ip.ip, conn).await?;
+    auth_tokens.apply_session_lifetime(&user.uuid, conn).await;
+
+    if device.is_new() && new_device_email_enabled(user) {
+        if let Err(e) = mail::send_new_device_logged_in(user, &ip.ip.to_string(), now, device).await {
             error!("Error sending new device email: {e:#?}");
 
             if CONFIG.require_device_email() {
@@ -468,9 +648,32 @@ async fn authenticated_response(
     }
 
     // Save to update `device.updated_at` to track usage and toggle new status
+    device.last_ip = Some(ip.ip.to_string());
     device.save(conn).await?;
 
+    // Every login starts a new refresh token family, the tokens of the previous login on this device stop working
+    if CONFIG.refresh_token_rotation() {
+        let family = RefreshTokenFamily::new(user.uuid.clone(), device.uuid.clone(), ip.ip.to_string());
+        family.save(conn).await?;
+        auth_tokens.set_family(&family);
+    }
+
     let master_password_policy = master_password_policy(user, conn).await;
+    // The clients check the master password against the policy during this login, once is enough
+    if user.password_policy_pending && master_password_policy["enforceOnLogin"] == Value::Bool(true) {
+        User::clear_password_policy_pending(&user.uuid, conn).await?;
+        record_master_password_check(user, conn).await;
+    }
+
+    // New SSO users and members with a master password set up Key Connector after an SSO login
+    let key_connector_url = if user.uses_key_connector
+        || (matches!(auth_tokens.refresh_claims.sub, AuthMethod::Sso)
+            && OrgPolicy::find_key_connector_org(&user.uuid, conn).await.is_some())
+    {
+        Some(CONFIG.sso_key_connector_url())
+    } else {
+        None
+    };
 
     let mut result = json!({
         "access_token": auth_tokens.access_token(),
@@ -483,7 +686,8 @@ async fn authenticated_response(
         "KdfMemory": user.client_kdf_memory,
         "KdfParallelism": user.client_kdf_parallelism,
         "ResetMasterPassword": false, // TODO: Same as above
-        "ForcePasswordReset": false,
+        "ForcePasswordReset": user.force_password_reset,
+        "KdfUpgradeRequired": user.kdf_upgrade_required,
         "MasterPasswordPolicy": master_password_policy,
         "scope": auth_tokens.scope(),
         "UserDecryptionOptions": {
@@ -496,6 +700,13 @@ async fn authenticated_response(
         result["Key"] = Value::String(user.akey.clone());
     }
 
+    if let Some(url) = key_connector_url {
+        result["UserDecryptionOptions"]["KeyConnectorOption"] = json!({
+            "KeyConnectorUrl": url,
+        });
+        result["KeyConnectorUrl"] = Value::String(url);
+    }
+
     if let Some(token) = twofactor_token {
         result["TwoFactorToken"] = Value::String(token);
     }
@@ -551,6 +762,17 @@ async fn _user_api_key_login(
         )
     }
 
+    // Check if the user is awaiting deletion
+    if user.is_soft_deleted() {
+        err!(
+            "This user has been deleted (API key login)",
+            format!("IP: {}. Username: {}.", ip.ip, user.email),
+            ErrorEvent {
+                event: EventType::UserFailedLogIn
+            }
+        )
+    }
+
     // Check API key. Note that API key logins bypass 2FA.
     let client_secret = data.client_secret.as_ref().unwrap();
     if !user.check_valid_api_key(client_secret) {
@@ -563,11 +785,19 @@ async fn _user_api_key_login(
         )
     }
 
+    // Changing the KDF settings or the master password needs a password login, so API key logins are refused until then
+    check_kdf_upgrade(&user, ip)?;
+    if user.force_password_reset {
+        err_json!(_json_err_force_password_reset(), format!("IP: {}. Username: {}.", ip.ip, user.email))
+    }
+    check_email_verify_deadline(&user, ip, conn).await?;
+
     let mut device = get_device(&data, conn, &user).await?;
+    check_org_ip_restrictions(&user.uuid, device.atype, &ip.ip, conn).await?;
 
-    if CONFIG.mail_enabled() && device.is_new() {
+    if device.is_new() && new_device_email_enabled(&user) {
         let now = Utc::now().naive_utc();
-        if let Err(e) = mail::send_new_device_logged_in(&user.email, &ip.ip.to_string(), &now, &device).await {
+        if let Err(e) = mail::send_new_device_logged_in(&user, &ip.ip.to_string(), &now, &device).await {
             error!("Error sending new device email: {e:#?}");
 
             if CONFIG.require_device_email() {
@@ -587,9 +817,15 @@ async fn _user_api_key_login(
     // See: https://github.com/dani-garcia/vaultwarden/issues/4156
     // ---
     // let orgs = Membership::find_confirmed_by_user(&user.uuid, conn).await;
-    let access_claims = auth::LoginJwtClaims::default(&device, &user, &AuthMethod::UserApiKey, data.client_id);
+    let mut access_claims = auth::LoginJwtClaims::default(&device, &user, &AuthMethod::UserApiKey, data.client_id);
+    // Every API key login is a new session, so the maximum session lifetime counts from now
+    if let Some(session_policy) = OrgPolicy::find_session_lifetime_policy(&user.uuid, conn).await {
+        let now = Utc::now().timestamp();
+        access_claims.exp = session_policy.limit_expiration(access_claims.exp, now, now);
+    }
 
     // Save to update `device.updated_at` to track usage and toggle new status
+    device.last_ip = Some(ip.ip.to_string());
     device.save(conn).await?;
 
     info!("User {} logged in successfully via API key. IP: {}", user.email, ip.ip);
@@ -630,6 +866,9 @@ async fn _organization_api_key_login(data: ConnectData, conn: &mut DbConn, ip: &
     if !org_api_key.check_valid_api_key(client_secret) {
         err!("Incorrect client_secret", format!("IP: {}. Organization: {}.", ip.ip, org_api_key.org_uuid))
     }
+    if Organization::find_by_uuid(&org_api_key.org_uuid, conn).await.is_some_and(|org| org.is_archived()) {
+        err!("The organization is archived", format!("IP: {}. Organization: {}.", ip.ip, org_api_key.org_uuid))
+    }
 
     let claim = generate_organization_api_key_login_claims(org_api_key.uuid, org_api_key.org_uuid);
     let access_token = auth::encode_jwt(&claim);
@@ -642,6 +881,46 @@ async fn _organization_api_key_login(data: ConnectData, conn: &mut DbConn, ip: &
     })))
 }
 
+async fn _service_account_login(data: ConnectData, conn: &mut DbConn, ip: &ClientIp) -> JsonResult {
+    // Ratelimit the login
+    crate::ratelimit::check_limit_login(&ip.ip)?;
+
+    if !CONFIG.secrets_manager_enabled() {
+        err!("Secrets Manager is not enabled on this server")
+    }
+
+    // The client_id is the id of the access token, the client_secret is part of the token string given to the user
+    let token_id: ServiceAccountTokenId = data.client_id.clone().unwrap().into();
+    let Some(token) = ServiceAccountToken::find_by_uuid(&token_id, conn).await else {
+        err!("Invalid client_id", format!("IP: {}.", ip.ip))
+    };
+
+    let client_secret = data.client_secret.as_ref().unwrap();
+    if !token.check_valid_client_secret(client_secret) {
+        err!("Incorrect client_secret", format!("IP: {}. Access token: {}.", ip.ip, token.uuid))
+    }
+
+    if token.is_expired() {
+        err!("Access token has expired", format!("IP: {}. Access token: {}.", ip.ip, token.uuid))
+    }
+
+    let Some(service_account) = ServiceAccount::find_by_uuid(&token.service_account_uuid, conn).await else {
+        err!("Invalid client_id", format!("IP: {}. Access token: {}.", ip.ip, token.uuid))
+    };
+
+    let claims = generate_service_account_login_claims(token.uuid, service_account.uuid, service_account.org_uuid);
+    let access_token = auth::encode_jwt(&claims);
+
+    Ok(Json(json!({
+        "access_token": access_token,
+        "expires_in": 3600,
+        "token_type": "Bearer",
+        "scope": AuthMethod::ServiceAccount.scope(),
+        // The organization key, which the client decrypts with the key of its access token
+        "encrypted_payload": token.encrypted_payload,
+    })))
+}
+
 /// Retrieves an existing device or creates a new device from ConnectData and the User
 async fn get_device(data: &ConnectData, conn: &mut DbConn, user: &User) -> ApiResult<Device> {
     // On iOS, device_type sends "iOS", on others it sends a number
@@ -667,15 +946,28 @@ async fn twofactor_auth(
 ) -> ApiResult<Option<String>> {
     let twofactors = TwoFactor::find_by_user(&user.uuid, conn).await;
 
+    // The Duo configured by an organization is only supported by the Universal Prompt
+    let org_duo = !CONFIG.duo_use_iframe()
+        && !OrgTwoFactor::find_enabled_by_confirmed_user_and_type(
+            &user.uuid,
+            TwoFactorType::OrganizationDuo as i32,
+            conn,
+        )
+        .await
+        .is_empty();
+
     // No twofactor token if twofactor is disabled
-    if twofactors.is_empty() {
+    if twofactors.is_empty() && !org_duo {
         enforce_2fa_policy(user, &user.uuid, device.atype, &ip.ip, conn).await?;
         return Ok(None);
     }
 
     TwoFactorIncomplete::mark_incomplete(&user.uuid, &device.uuid, &device.name, device.atype, ip, conn).await?;
 
-    let twofactor_ids: Vec<_> = twofactors.iter().map(|tf| tf.atype).collect();
+    let mut twofactor_ids: Vec<_> = twofactors.iter().map(|tf| tf.atype).collect();
+    if org_duo {
+        twofactor_ids.push(TwoFactorType::OrganizationDuo as i32);
+    }
     let selected_id = data.two_factor_provider.unwrap_or(twofactor_ids[0]); // If we aren't given a two factor provider, assume the first one
 
     let twofactor_code = match data.two_factor_token {
@@ -714,12 +1006,24 @@ async fn twofactor_auth(
                         twofactor_code,
                         data.client_id.as_ref().unwrap(),
                         data.device_identifier.as_ref().unwrap(),
+                        TwoFactorType::Duo,
                         conn,
                     )
                     .await?
                 }
             }
         }
+        Some(TwoFactorType::OrganizationDuo) if org_duo => {
+            duo_oidc::validate_duo_login(
+                &user.email,
+                twofactor_code,
+                data.client_id.as_ref().unwrap(),
+                data.device_identifier.as_ref().unwrap(),
+                TwoFactorType::OrganizationDuo,
+                conn,
+            )
+            .await?
+        }
         Some(TwoFactorType::Email) => {
             email::validate_email_code_str(&user.uuid, twofactor_code, &selected_data?, &ip.ip, conn).await?
         }
@@ -742,14 +1046,21 @@ async fn twofactor_auth(
                 err!("Recovery code is incorrect. Try again.")
             }
 
-            // Remove all twofactors from the user
-            TwoFactor::delete_all_by_user(&user.uuid, conn).await?;
-            enforce_2fa_policy(user, &user.uuid, device.atype, &ip.ip, conn).await?;
+            if CONFIG.rotate_2fa_recovery_code() {
+                // The code is only valid once, the twofactors stay enabled
+                user.new_recovery_code();
+            } else {
+                // Remove all twofactors from the user
+                TwoFactor::delete_all_by_user(&user.uuid, conn).await?;
+                enforce_2fa_policy(user, &user.uuid, device.atype, &ip.ip, conn).await?;
+
+                // Remove the recovery code, not needed without twofactors
+                user.totp_recover = None;
+            }
 
             log_user_event(EventType::UserRecovered2fa as i32, &user.uuid, device.atype, &ip.ip, conn).await;
 
-            // Remove the recovery code, not needed without twofactors
-            user.totp_recover = None;
+            user.totp_recover_used_at = Some(Utc::now().naive_utc());
             user.save(conn).await?;
         }
         _ => err!(
@@ -775,6 +1086,52 @@ fn _selected_data(tf: Option<TwoFactor>) -> ApiResult<String> {
     tf.map(|t| t.data).map_res("Two factor doesn't exist")
 }
 
+// The users flagged for a KDF upgrade which were logged since the start, so refreshing a session doesn't log every time
+static KDF_UPGRADE_LOGGED: Lazy<Mutex<HashSet<UserId>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Refuses the token when the user has to change their KDF settings first and `KDF_UPGRADE_ENFORCED` is set,
+/// see `User::kdf_upgrade_required`. Otherwise the user was only mailed about it.
+fn check_kdf_upgrade(user: &User, ip: &ClientIp) -> EmptyResult {
+    if !user.kdf_upgrade_required {
+        return Ok(());
+    }
+    if KDF_UPGRADE_LOGGED.lock().unwrap().insert(user.uuid.clone()) {
+        info!("User {} still has to update the KDF settings. IP: {}", user.uuid, ip.ip);
+    }
+    if CONFIG.kdf_upgrade_enforced() {
+        err_json!(_json_err_kdf_upgrade(), format!("KDF upgrade required. IP: {}. User: {}.", ip.ip, user.uuid))
+    }
+    Ok(())
+}
+
+/// Returned when the user has to change their KDF settings first and the upgrade is enforced.
+/// The password login still succeeds, because changing the KDF settings needs an authenticated session.
+fn _json_err_kdf_upgrade() -> Value {
+    json!({
+        "error": "invalid_grant",
+        "error_description": "kdf_upgrade_required",
+        "KdfUpgradeRequired": true,
+        "ErrorModel": {
+            "Message": "Your encryption settings need to be updated. Log in with your master password and change the KDF settings.",
+            "Object": "error"
+        }
+    })
+}
+
+/// Returned when the user has to choose a new master password first, see `User::force_password_reset`.
+/// The password login still succeeds and tells the client to prompt for the new master password.
+fn _json_err_force_password_reset() -> Value {
+    json!({
+        "error": "invalid_grant",
+        "error_description": "force_password_reset",
+        "ForcePasswordReset": true,
+        "ErrorModel": {
+            "Message": "Your master password has to be changed. Log in with your master password to choose a new one.",
+            "Object": "error"
+        }
+    })
+}
+
 async fn _json_err_twofactor(
     providers: &[i32],
     user_id: &UserId,
@@ -824,6 +1181,7 @@ async fn _json_err_twofactor(
                             &email,
                             data.client_id.as_ref().unwrap(),
                             data.device_identifier.as_ref().unwrap(),
+                            TwoFactorType::Duo,
                             conn,
                         )
                         .await?;
@@ -835,6 +1193,26 @@ async fn _json_err_twofactor(
                 }
             }
 
+            Some(TwoFactorType::OrganizationDuo) => {
+                let email = match User::find_by_uuid(user_id, conn).await {
+                    Some(u) => u.email,
+                    None => err!("User does not exist"),
+                };
+
+                let auth_url = duo_oidc::get_duo_auth_url(
+                    &email,
+                    data.client_id.as_ref().unwrap(),
+                    data.device_identifier.as_ref().unwrap(),
+                    TwoFactorType::OrganizationDuo,
+                    conn,
+                )
+                .await?;
+
+                result["TwoFactorProviders2"][provider.to_string()] = json!({
+                    "AuthUrl": auth_url,
+                })
+            }
+
             Some(tf_type @ TwoFactorType::YubiKey) => {
                 let Some(twofactor) = TwoFactor::find_by_user_and_type(user_id, tf_type as i32, conn).await else {
                     err!("No YubiKey devices registered")
@@ -884,8 +1262,8 @@ async fn prelogin(data: Json<PreloginData>, conn: DbConn) -> Json<Value> {
 }
 
 #[post("/accounts/register", data = "<data>")]
-async fn identity_register(data: Json<RegisterData>, conn: DbConn) -> JsonResult {
-    _register(data, false, conn).await
+async fn identity_register(data: Json<RegisterData>, ip: ClientIp, conn: DbConn) -> JsonResult {
+    _register(data, false, &ip, conn).await
 }
 
 #[derive(Debug, Deserialize)]
@@ -905,12 +1283,14 @@ enum RegisterVerificationResponse {
 #[post("/accounts/register/send-verification-email", data = "<data>")]
 async fn register_verification_email(
     data: Json<RegisterVerificationData>,
+    ip: ClientIp,
     mut conn: DbConn,
 ) -> ApiResult<RegisterVerificationResponse> {
+    crate::ratelimit::check_limit_register(&ip.ip)?;
     let data = data.into_inner();
 
     // the registration can only continue if signup is allowed or there exists an invitation
-    if !(CONFIG.is_signup_allowed(&data.email)
+    if !(SignupRule::is_signup_allowed(&data.email, &mut conn).await
         || (!CONFIG.mail_enabled() && Invitation::find_by_mail(&data.email, &mut conn).await.is_some()))
     {
         err!("Registration not allowed or user already exists")
@@ -945,8 +1325,8 @@ async fn register_verification_email(
 }
 
 #[post("/accounts/register/finish", data = "<data>")]
-async fn register_finish(data: Json<RegisterData>, conn: DbConn) -> JsonResult {
-    _register(data, true, conn).await
+async fn register_finish(data: Json<RegisterData>, ip: ClientIp, conn: DbConn) -> JsonResult {
+    _register(data, true, &ip, conn).await
 }
 
 // https://github.com/bitwarden/jslib/blob/master/common/src/models/request/tokenRequest.ts
@@ -0,0 +1,248 @@
+use std::collections::HashSet;
+
+use ldap3::{LdapConnAsync, Scope, SearchEntry};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use crate::{
+    api::{admin::ACTING_ADMIN_USER, core::log_event, custom::invite_new_user, ApiResult},
+    db::{models::*, DbConn, DbPool},
+    util::{format_date, is_valid_email},
+    CONFIG,
+};
+
+// Holds the report of the last sync, the lock also prevents concurrent syncs
+static LAST_LDAP_SYNC: Lazy<Mutex<Option<Value>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Default, Serialize)]
+struct LdapSyncReport {
+    date: String,
+    dry_run: bool,
+    directory_users: usize,
+    invited: Vec<String>,
+    linked: Vec<String>,
+    restored: Vec<String>,
+    revoked: Vec<String>,
+    errors: Vec<String>,
+}
+
+/// Returns the report of the last sync, and whether a sync is running right now.
+pub fn last_ldap_sync_report() -> (Option<Value>, bool) {
+    match LAST_LDAP_SYNC.try_lock() {
+        Ok(report) => (report.clone(), false),
+        Err(_) => (None, true),
+    }
+}
+
+pub async fn ldap_sync_job(pool: DbPool) {
+    debug!("Start LDAP sync job");
+    if !CONFIG.ldap_sync_enabled() {
+        debug!("LDAP sync is not enabled, abort");
+        return;
+    }
+
+    if let Ok(mut conn) = pool.get().await {
+        run_ldap_sync(CONFIG.ldap_sync_dry_run(), &mut conn).await;
+    } else {
+        error!("Failed to get DB connection while running the LDAP sync")
+    }
+}
+
+/// Syncs the directory users and stores the report, which is also returned.
+/// New directory users are invited to the server. Members of `LDAP_SYNC_ORG` are linked to their directory entry,
+/// and the linked members which are no longer part of the directory are revoked.
+pub async fn run_ldap_sync(dry_run: bool, conn: &mut DbConn) -> Value {
+    let mut last_sync = LAST_LDAP_SYNC.lock().await;
+
+    let mut report = LdapSyncReport {
+        date: format_date(&chrono::Utc::now().naive_utc()),
+        dry_run,
+        ..Default::default()
+    };
+    match search_directory().await {
+        Ok(entries) => sync_entries(entries, dry_run, &mut report, conn).await,
+        Err(e) => report.errors.push(format!("Searching the directory failed: {e:?}")),
+    }
+
+    if !report.errors.is_empty() {
+        warn!("LDAP sync finished with errors: {}", report.errors.join(", "));
+    }
+    let report = serde_json::to_value(report).unwrap_or_default();
+    *last_sync = Some(report.clone());
+    report
+}
+
+/// Returns the DN and email address of every user matching the filter.
+async fn search_directory() -> ApiResult<Vec<(String, String)>> {
+    let Some(url) = CONFIG.ldap_url() else {
+        err!("`LDAP_URL` is not configured")
+    };
+    let (ldap_conn, mut ldap) = LdapConnAsync::new(&url).await?;
+    ldap3::drive!(ldap_conn);
+
+    if let (Some(bind_dn), Some(bind_password)) = (CONFIG.ldap_bind_dn(), CONFIG.ldap_bind_password()) {
+        ldap.simple_bind(&bind_dn, &bind_password).await?.success()?;
+    }
+
+    let email_attribute = CONFIG.ldap_email_attribute();
+    let (entries, _) = ldap
+        .search(&CONFIG.ldap_search_base(), Scope::Subtree, &CONFIG.ldap_user_filter(), vec![email_attribute.as_str()])
+        .await?
+        .success()?;
+    ldap.unbind().await?;
+
+    Ok(entries
+        .into_iter()
+        .map(SearchEntry::construct)
+        .filter_map(|entry| {
+            // Servers don't always return the attribute name with the requested casing
+            let (_, values) = entry.attrs.iter().find(|(name, _)| name.eq_ignore_ascii_case(&email_attribute))?;
+            let email = values.first()?.trim().to_lowercase();
+            Some((entry.dn, email))
+        })
+        .collect())
+}
+
+async fn sync_entries(entries: Vec<(String, String)>, dry_run: bool, report: &mut LdapSyncReport, conn: &mut DbConn) {
+    report.directory_users = entries.len();
+
+    let org = match CONFIG.ldap_sync_org() {
+        Some(org_id) => {
+            let org_id = OrganizationId::from(org_id);
+            let Some(org) = Organization::find_by_uuid(&org_id, conn).await else {
+                report.errors.push(format!("Organization {org_id} of `LDAP_SYNC_ORG` does not exist"));
+                return;
+            };
+            Some(org)
+        }
+        None => None,
+    };
+    let org_id = org.as_ref().map(|org| org.uuid.clone());
+
+    let mut directory_dns = HashSet::new();
+    for (dn, email) in entries {
+        if !is_valid_email(&email) {
+            report.errors.push(format!("{dn} has an invalid email address {email}"));
+            continue;
+        }
+        directory_dns.insert(dn.clone());
+
+        let Some(user) = User::find_by_mail(&email, conn).await else {
+            if !dry_run {
+                // Invites for the organization are refused while its report blocks them
+                if let Err(e) = invite_new_user(email.clone(), org.as_ref(), conn).await {
+                    report.errors.push(format!("Inviting {email} failed: {e:?}"));
+                    continue;
+                }
+            }
+            report.invited.push(email);
+            continue;
+        };
+
+        let Some(org_id) = &org_id else {
+            continue;
+        };
+        let Some(mut member) = Membership::find_by_user_and_org(&user.uuid, org_id, conn).await else {
+            continue;
+        };
+        // Only members revoked by an earlier sync are restored, not the ones revoked manually
+        let restore = member.ldap_revoked && member.status == MembershipStatus::Revoked as i32;
+        if restore {
+            if let Some(org) = &org {
+                if let Err(e) = org.check_available_seats(1, conn).await {
+                    report.errors.push(format!("Can't restore {email}: {}", e.message()));
+                    continue;
+                }
+            }
+        }
+        let restored = restore && member.restore();
+        let linked = member.ldap_dn.as_ref() != Some(&dn);
+        if linked {
+            member.ldap_dn = Some(dn);
+            report.linked.push(email.clone());
+        }
+        if restored {
+            report.restored.push(email);
+        }
+        if dry_run || !(linked || restored) {
+            continue;
+        }
+        if let Err(e) = member.save(conn).await {
+            report.errors.push(format!("Updating the membership of {} failed: {e:?}", user.email));
+        } else if restored {
+            log_member_event(EventType::OrganizationUserRestored, &member, conn).await;
+        }
+    }
+
+    let Some(org_id) = org_id else {
+        return;
+    };
+    // Only members linked to a directory entry by an earlier sync are revoked, manually added members
+    // and the ones of the Directory Connector are left alone
+    let linked_members: Vec<Membership> =
+        Membership::find_by_org(&org_id, conn).await.into_iter().filter(|member| member.ldap_dn.is_some()).collect();
+    let linked_count = linked_members.len();
+    let to_revoke: Vec<Membership> = linked_members
+        .into_iter()
+        .filter(|member| {
+            member.ldap_dn.as_ref().is_some_and(|dn| !directory_dns.contains(dn))
+                && member.status != MembershipStatus::Revoked as i32
+        })
+        .collect();
+
+    // A broken directory or a wrong filter looks like everyone left, the revocations are only reported then
+    let max_percent = usize::from(CONFIG.ldap_sync_max_revoke_percent());
+    let held_back = if report.directory_users == 0 && !to_revoke.is_empty() {
+        report.errors.push("The directory search returned no users, no members were revoked".to_string());
+        true
+    } else if to_revoke.len() > 1 && to_revoke.len() * 100 > linked_count * max_percent {
+        report.errors.push(format!(
+            "{} of {linked_count} synced members would be revoked, over `LDAP_SYNC_MAX_REVOKE_PERCENT`, none were revoked",
+            to_revoke.len()
+        ));
+        true
+    } else {
+        false
+    };
+
+    for mut member in to_revoke {
+        if member.atype == MembershipType::Owner
+            && member.status == MembershipStatus::Confirmed as i32
+            && Membership::count_confirmed_by_org_and_type(&org_id, MembershipType::Owner, conn).await <= 1
+        {
+            report.errors.push(format!("Can't revoke the last owner {}", member.user_uuid));
+            continue;
+        }
+        if !member.revoke() {
+            continue;
+        }
+        member.ldap_revoked = true;
+
+        let email = match User::find_by_uuid(&member.user_uuid, conn).await {
+            Some(user) => user.email,
+            None => member.user_uuid.to_string(),
+        };
+        if !(dry_run || held_back) {
+            if let Err(e) = member.save(conn).await {
+                report.errors.push(format!("Revoking {email} failed: {e:?}"));
+                continue;
+            }
+            log_member_event(EventType::OrganizationUserRevoked, &member, conn).await;
+        }
+        report.revoked.push(email);
+    }
+}
+
+async fn log_member_event(event_type: EventType, member: &Membership, conn: &mut DbConn) {
+    log_event(
+        event_type as i32,
+        &member.uuid,
+        &member.org_uuid,
+        &ACTING_ADMIN_USER.into(),
+        14, // Use UnknownBrowser type
+        &std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+        conn,
+    )
+    .await;
+}
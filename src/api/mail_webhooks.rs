@@ -0,0 +1,171 @@
+use reqwest::Method;
+use rocket::{
+    data::{Data, ToByteUnit},
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    serde::json::Json,
+    Route,
+};
+use serde_json::Value;
+use url::Url;
+
+use crate::{
+    api::EmptyResult,
+    db::{models::User, DbConn},
+    http_client::make_http_request,
+    CONFIG,
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![post_bounces, post_ses_bounces, post_sendgrid_bounces]
+}
+
+pub struct MailWebhookToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MailWebhookToken {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(expected) = CONFIG.mail_webhook_token() else {
+            return Outcome::Error((Status::NotFound, "The mail webhooks are not enabled"));
+        };
+        // The providers can't send a header, so the token is part of the configured URL
+        match request.query_value::<&str>("token") {
+            Some(Ok(token)) if crate::crypto::ct_eq(token, &expected) => Outcome::Success(MailWebhookToken),
+            Some(_) => Outcome::Error((Status::Unauthorized, "Invalid token")),
+            None => Outcome::Error((Status::Unauthorized, "Missing token")),
+        }
+    }
+}
+
+/// Marks the user of the address as undeliverable. Only the primary address of a user is marked,
+/// a bounce of an alias doesn't say anything about it.
+async fn mark_undeliverable(address: &str, reason: &str, conn: &mut DbConn) -> EmptyResult {
+    let Some(mut user) = User::find_by_mail(address, conn).await else {
+        debug!("Received a bounce for {address}, which isn't the address of a user");
+        return Ok(());
+    };
+    if user.email != address.to_lowercase() || user.email_undeliverable_at.is_some() {
+        return Ok(());
+    }
+    info!("Mails to {} can't be delivered: {reason}", user.email);
+    user.mark_email_undeliverable(reason);
+    user.save(conn).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BounceData {
+    email: String,
+    // "bounce" or "complaint"
+    r#type: String,
+    reason: Option<String>,
+}
+
+/// Receives the bounces and complaints of other providers, or of the scripts processing the bounces of an SMTP server.
+/// The caller decides what is a permanent bounce, every report marks the address.
+#[post("/bounces", format = "application/json", data = "<data>")]
+async fn post_bounces(data: Json<BounceData>, _token: MailWebhookToken, mut conn: DbConn) -> EmptyResult {
+    let data = data.into_inner();
+    let kind = match data.r#type.as_str() {
+        "bounce" => "Bounce",
+        "complaint" => "Complaint",
+        _ => err!("The type needs to be bounce or complaint"),
+    };
+    let reason = match data.reason {
+        Some(reason) => format!("{kind}: {reason}"),
+        None => kind.to_string(),
+    };
+    mark_undeliverable(&data.email, &reason, &mut conn).await
+}
+
+/// Receives the SES bounce and complaint notifications, which SNS posts as `text/plain` JSON.
+/// The subscription of the topic is confirmed when SNS asks for it, after which the notifications of the
+/// configuration set or identity arrive. Only permanent bounces and complaints mark the address.
+#[post("/bounces/ses", data = "<data>")]
+async fn post_ses_bounces(data: Data<'_>, _token: MailWebhookToken, mut conn: DbConn) -> EmptyResult {
+    let body = data.open(256.kibibytes()).into_string().await?;
+    if !body.is_complete() {
+        err!("The SNS message is too large")
+    }
+    let message: Value = serde_json::from_str(&body)?;
+
+    match message["Type"].as_str() {
+        Some("SubscriptionConfirmation") => {
+            let Some(subscribe_url) = message["SubscribeURL"].as_str() else {
+                err!("The SubscribeURL is missing")
+            };
+            // Only SNS is asked to confirm, so the endpoint can't be used to call anything else
+            let is_sns = Url::parse(subscribe_url).is_ok_and(|url| {
+                url.scheme() == "https" && url.host_str().is_some_and(|host| host.ends_with(".amazonaws.com"))
+            });
+            if !is_sns {
+                err!("The SubscribeURL is not an SNS URL")
+            }
+            make_http_request(Method::GET, subscribe_url)?.send().await?.error_for_status()?;
+            info!("Confirmed the SNS subscription of topic {}", message["TopicArn"].as_str().unwrap_or_default());
+            Ok(())
+        }
+        Some("Notification") => {
+            let notification: Value = match message["Message"].as_str() {
+                Some(notification) => serde_json::from_str(notification)?,
+                None => err!("The SNS message is missing"),
+            };
+            // Identities use `notificationType` and configuration sets `eventType`
+            let kind = notification["notificationType"].as_str().or(notification["eventType"].as_str());
+            let (recipients, reason) = match kind {
+                Some("Bounce") => {
+                    let bounce = &notification["bounce"];
+                    if bounce["bounceType"].as_str() != Some("Permanent") {
+                        return Ok(());
+                    }
+                    let sub_type = bounce["bounceSubType"].as_str().unwrap_or("General");
+                    (&bounce["bouncedRecipients"], format!("SES permanent bounce ({sub_type})"))
+                }
+                Some("Complaint") => {
+                    let complaint = &notification["complaint"];
+                    let feedback_type = complaint["complaintFeedbackType"].as_str().unwrap_or("abuse");
+                    (&complaint["complainedRecipients"], format!("SES complaint ({feedback_type})"))
+                }
+                _ => return Ok(()),
+            };
+
+            for recipient in recipients.as_array().into_iter().flatten() {
+                let Some(address) = recipient["emailAddress"].as_str() else {
+                    continue;
+                };
+                let reason = match recipient["diagnosticCode"].as_str() {
+                    Some(diagnostic) => format!("{reason}: {diagnostic}"),
+                    None => reason.clone(),
+                };
+                mark_undeliverable(address, &reason, &mut conn).await?;
+            }
+            Ok(())
+        }
+        // Like `UnsubscribeConfirmation`, nothing to do
+        _ => Ok(()),
+    }
+}
+
+/// Receives the events of the SendGrid event webhook. SendGrid reports the rejections which may
+/// succeed later as `blocked`, only the other bounces and the spam reports mark the address.
+#[post("/bounces/sendgrid", data = "<data>")]
+async fn post_sendgrid_bounces(data: Json<Vec<Value>>, _token: MailWebhookToken, mut conn: DbConn) -> EmptyResult {
+    for event in data.into_inner() {
+        let kind = match event["event"].as_str() {
+            Some("bounce") if event["type"].as_str() != Some("blocked") => "SendGrid bounce",
+            Some("spamreport") => "SendGrid spam report",
+            _ => continue,
+        };
+        let Some(address) = event["email"].as_str() else {
+            continue;
+        };
+        let reason = match event["reason"].as_str() {
+            Some(reason) => format!("{kind}: {reason}"),
+            None => kind.to_string(),
+        };
+        mark_undeliverable(address, &reason, &mut conn).await?;
+    }
+    Ok(())
+}
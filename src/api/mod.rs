@@ -1,10 +1,15 @@
 mod admin;
+mod breaches;
 pub mod core;
 mod custom;
+mod file_replication;
 mod icons;
 mod identity;
+mod ldap;
+mod mail_webhooks;
 mod notifications;
 mod push;
+mod replication;
 mod web;
 
 use rocket::serde::json::Json;
@@ -12,24 +17,37 @@ use serde_json::Value;
 
 pub use crate::api::{
     admin::catchers as admin_catchers,
+    admin::daily_stats_job,
     admin::routes as admin_routes,
+    breaches::breach_check_job,
     core::catchers as core_catchers,
+    core::org_domain_verification_job,
+    core::purge_archived_organizations,
+    core::purge_attachment_uploads,
     core::purge_auth_requests,
+    core::purge_cipher_history,
+    core::purge_deleted_users,
     core::purge_sends,
     core::purge_trashed_ciphers,
     core::routes as core_routes,
-    core::two_factor::send_incomplete_2fa_notifications,
+    core::two_factor::{send_incomplete_2fa_notifications, two_factor_deadline_job},
     core::{emergency_notification_reminder_job, emergency_request_timeout_job},
     core::{event_cleanup_job, events_routes as core_events_routes},
-    custom::routes as custom_routes,
-    icons::routes as icons_routes,
-    identity::routes as identity_routes,
+    custom::{
+        check_report_invite_gate, invite_new_user, report_evaluation_job, risk_score_job, routes as custom_routes,
+    },
+    file_replication::{check_file_replication, file_replication_job, run_file_replication},
+    icons::{icon_cache_eviction_job, icon_cache_metrics, routes as icons_routes},
+    identity::{login_event_cleanup_job, routes as identity_routes},
+    ldap::ldap_sync_job,
+    mail_webhooks::routes as mail_webhooks_routes,
     notifications::routes as notifications_routes,
-    notifications::{AnonymousNotify, Notify, UpdateType, WS_ANONYMOUS_SUBSCRIPTIONS, WS_USERS},
+    notifications::{AnonymousNotify, Notify, UpdateType, WebSocketUsers, WS_ANONYMOUS_SUBSCRIPTIONS, WS_USERS},
     push::{
         push_cipher_update, push_folder_update, push_logout, push_send_update, push_user_update, register_push_device,
         unregister_push_device,
     },
+    replication::{replication_cleanup_job, routes as replication_routes},
     web::catchers as web_catchers,
     web::routes as web_routes,
     web::static_files,
@@ -76,7 +94,7 @@ impl PasswordOrOtpData {
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(default, rename_all = "camelCase")]
 pub struct MasterPasswordPolicy {
     min_complexity: Option<u8>,
     min_length: Option<u32>,
@@ -87,31 +105,47 @@ pub struct MasterPasswordPolicy {
     enforce_on_login: bool,
 }
 
+impl MasterPasswordPolicy {
+    /// Checks the options against the limits of the policy editor of the clients.
+    /// The password itself never reaches the server, the clients check it against the policy.
+    pub fn validate(&self) -> EmptyResult {
+        if self.min_complexity.is_some_and(|c| c > 4) {
+            err!("The minimum complexity score has to be between 0 and 4")
+        }
+        if self.min_length.is_some_and(|l| !(12..=128).contains(&l)) {
+            err!("The minimum master password length has to be between 12 and 128")
+        }
+        Ok(())
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            min_complexity: self.min_complexity.max(other.min_complexity),
+            min_length: self.min_length.max(other.min_length),
+            require_lower: self.require_lower || other.require_lower,
+            require_upper: self.require_upper || other.require_upper,
+            require_numbers: self.require_numbers || other.require_numbers,
+            require_special: self.require_special || other.require_special,
+            enforce_on_login: self.enforce_on_login || other.enforce_on_login,
+        }
+    }
+}
+
 // Fetch all valid Master Password Policies and merge them into one with all trues and largest numbers as one policy
 async fn master_password_policy(user: &User, conn: &DbConn) -> Value {
-    let master_password_policies: Vec<MasterPasswordPolicy> =
-        OrgPolicy::find_accepted_and_confirmed_by_user_and_active_policy(
-            &user.uuid,
-            OrgPolicyType::MasterPassword,
-            conn,
-        )
+    let policy = OrgPolicy::find_confirmed_by_user_and_active_policy(&user.uuid, OrgPolicyType::MasterPassword, conn)
         .await
         .into_iter()
-        .filter_map(|p| serde_json::from_str(&p.data).ok())
-        .collect();
+        .filter_map(|p| serde_json::from_str::<MasterPasswordPolicy>(&p.data).ok())
+        .reduce(MasterPasswordPolicy::merge)
+        // The master password was chosen while the user was only invited, the clients have to check it once
+        .map(|policy| MasterPasswordPolicy {
+            enforce_on_login: policy.enforce_on_login || user.password_policy_pending,
+            ..policy
+        });
 
-    let mut mpp_json = if !master_password_policies.is_empty() {
-        json!(master_password_policies.into_iter().reduce(|acc, policy| {
-            MasterPasswordPolicy {
-                min_complexity: acc.min_complexity.max(policy.min_complexity),
-                min_length: acc.min_length.max(policy.min_length),
-                require_lower: acc.require_lower || policy.require_lower,
-                require_upper: acc.require_upper || policy.require_upper,
-                require_numbers: acc.require_numbers || policy.require_numbers,
-                require_special: acc.require_special || policy.require_special,
-                enforce_on_login: acc.enforce_on_login || policy.enforce_on_login,
-            }
-        }))
+    let mut mpp_json = if let Some(policy) = policy {
+        json!(policy)
     } else if CONFIG.sso_enabled() {
         CONFIG.sso_master_password_policy_value().unwrap_or(json!({}))
     } else {
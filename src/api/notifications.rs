@@ -5,12 +5,18 @@ use rmpv::Value;
 use rocket::{futures::StreamExt, Route};
 use tokio::sync::mpsc::Sender;
 
-use rocket_ws::{Message, WebSocket};
+use rocket_ws::{
+    frame::{CloseCode, CloseFrame},
+    Message, WebSocket,
+};
 
 use crate::{
     auth::{ClientIp, WsAccessTokenHeader},
     db::{
-        models::{AuthRequestId, Cipher, CollectionId, Device, DeviceId, Folder, PushId, Send as DbSend, User, UserId},
+        models::{
+            AuthRequestId, Cipher, CollectionId, Device, DeviceId, Folder, OrganizationId, PushId, Send as DbSend,
+            User, UserId,
+        },
         DbConn,
     },
     Error, CONFIG,
@@ -174,6 +180,11 @@ fn websockets_hub<'r>(
 
                     res = rx.recv() => {
                         match res {
+                            // The server is shutting down, the client reconnects once it is back
+                            Some(res @ Message::Close(_)) => {
+                                yield res;
+                                break;
+                            }
                             Some(res) => yield res,
                             None => break,
                         }
@@ -241,6 +252,11 @@ fn anonymous_websockets_hub<'r>(ws: WebSocket, token: String, ip: ClientIp) -> R
 
                     res = rx.recv() => {
                         match res {
+                            // The server is shutting down, the client reconnects once it is back
+                            Some(res @ Message::Close(_)) => {
+                                yield res;
+                                break;
+                            }
                             Some(res) => yield res,
                             None => break,
                         }
@@ -306,6 +322,14 @@ fn convert_option<T: Into<Value>>(option: Option<T>) -> Value {
     }
 }
 
+/// The close message sent when the server shuts down, the Service Restart code tells the clients to reconnect.
+fn restart_message() -> Message {
+    Message::Close(Some(CloseFrame {
+        code: CloseCode::Restart,
+        reason: "The server is restarting".into(),
+    }))
+}
+
 const RECORD_SEPARATOR: u8 = 0x1e;
 const INITIAL_RESPONSE: [u8; 3] = [0x7b, 0x7d, RECORD_SEPARATOR]; // {, }, <RS>
 
@@ -328,6 +352,15 @@ pub struct WebSocketUsers {
 }
 
 impl WebSocketUsers {
+    /// Closes the connections of all users, which are then reconnected by the clients.
+    pub fn close_all(&self) {
+        for user in self.map.iter() {
+            for (_, sender) in user.value() {
+                let _ = sender.try_send(restart_message());
+            }
+        }
+    }
+
     async fn send_update(&self, user_id: &UserId, data: &[u8]) {
         if let Some(user) = self.map.get(user_id.as_ref()).map(|v| v.clone()) {
             for (_, sender) in user.iter() {
@@ -486,6 +519,36 @@ impl WebSocketUsers {
         }
     }
 
+    /// Tells the connected org admins the new counts of the report of the organization, so dashboards can refresh.
+    /// This is only sent over WebSockets, the push relay doesn't know this update type.
+    pub async fn send_report_update(
+        &self,
+        org_id: &OrganizationId,
+        counts: (i32, i64, i64),
+        revision_date: NaiveDateTime,
+        user_ids: &[UserId],
+    ) {
+        if !CONFIG.enable_websocket() {
+            return;
+        }
+        let (exposed_count, weak_count, reused_count) = counts;
+        let data = create_update(
+            vec![
+                ("OrganizationId".into(), org_id.to_string().into()),
+                ("ExposedCount".into(), exposed_count.into()),
+                ("WeakCount".into(), weak_count.into()),
+                ("ReusedCount".into(), reused_count.into()),
+                ("RevisionDate".into(), serialize_date(revision_date)),
+            ],
+            UpdateType::ReportUpdated,
+            None,
+        );
+
+        for uuid in user_ids {
+            self.send_update(uuid, &data).await;
+        }
+    }
+
     pub async fn send_auth_request(
         &self,
         user_id: &UserId,
@@ -543,6 +606,12 @@ pub struct AnonymousWebSocketSubscriptions {
 }
 
 impl AnonymousWebSocketSubscriptions {
+    pub fn close_all(&self) {
+        for subscription in self.map.iter() {
+            let _ = subscription.value().try_send(restart_message());
+        }
+    }
+
     async fn send_update(&self, token: &str, data: &[u8]) {
         if let Some(sender) = self.map.get(token).map(|v| v.clone()) {
             if let Err(e) = sender.send(Message::binary(data)).await {
@@ -653,6 +722,9 @@ pub enum UpdateType {
 
     // RefreshSecurityTasks = 22, // Not supported
     None = 100,
+
+    // Vaultwarden specific, not handled by the Bitwarden clients
+    ReportUpdated = 1000,
 }
 
 pub type Notify<'a> = &'a rocket::State<Arc<WebSocketUsers>>;
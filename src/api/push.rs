@@ -1,3 +1,5 @@
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
 use reqwest::{
     header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
     Method,
@@ -7,7 +9,7 @@ use tokio::sync::RwLock;
 
 use crate::{
     api::{ApiResult, EmptyResult, UpdateType},
-    db::models::{AuthRequestId, Cipher, Device, DeviceId, Folder, PushId, Send, User, UserId},
+    db::models::{AuthRequestId, Cipher, Device, DeviceId, DeviceType, Folder, PushId, Send, User, UserId},
     http_client::make_http_request,
     util::{format_date, get_uuid},
     CONFIG,
@@ -97,6 +99,14 @@ pub async fn register_push_device(device: &mut Device, conn: &mut crate::db::DbC
         device.push_uuid = Some(PushId(get_uuid()));
     }
 
+    // In the direct mode the push token is used as is, there is nothing to register with a relay
+    if uses_direct_push() {
+        if let Err(e) = device.save(conn).await {
+            err!(format!("An error occurred while trying to save the (registered) device push uuid: {e}"));
+        }
+        return Ok(());
+    }
+
     //Needed to register a device for push to bitwarden :
     let data = json!({
         "deviceId": device.push_uuid, // Unique UUID per user/device
@@ -131,7 +141,7 @@ pub async fn register_push_device(device: &mut Device, conn: &mut crate::db::DbC
 }
 
 pub async fn unregister_push_device(push_id: &Option<PushId>) -> EmptyResult {
-    if !CONFIG.push_enabled() || push_id.is_none() {
+    if !CONFIG.push_enabled() || uses_direct_push() || push_id.is_none() {
         return Ok(());
     }
     let auth_api_token = get_auth_api_token().await?;
@@ -162,23 +172,26 @@ pub async fn push_cipher_update(ut: UpdateType, cipher: &Cipher, device: &Device
         return;
     };
 
-    if Device::check_user_has_push_device(user_id, conn).await {
-        send_to_push_relay(json!({
-            "userId": user_id,
-            "organizationId": null,
-            "deviceId": device.push_uuid, // Should be the records unique uuid of the acting device (unique uuid per user/device)
-            "identifier": device.uuid, // Should be the acting device id (aka uuid per device/app)
-            "type": ut as i32,
-            "payload": {
-                "id": cipher.uuid,
-                "userId": cipher.user_uuid,
+    if let Some(targets) = PushTargets::find(user_id, conn).await {
+        send_push_notification(
+            targets,
+            json!({
+                "userId": user_id,
                 "organizationId": null,
-                "collectionIds": null,
-                "revisionDate": format_date(&cipher.updated_at)
-            },
-            "clientType": null,
-            "installationId": null
-        }))
+                "deviceId": device.push_uuid, // Should be the records unique uuid of the acting device (unique uuid per user/device)
+                "identifier": device.uuid, // Should be the acting device id (aka uuid per device/app)
+                "type": ut as i32,
+                "payload": {
+                    "id": cipher.uuid,
+                    "userId": cipher.user_uuid,
+                    "organizationId": null,
+                    "collectionIds": null,
+                    "revisionDate": format_date(&cipher.updated_at)
+                },
+                "clientType": null,
+                "installationId": null
+            }),
+        )
         .await;
     }
 }
@@ -186,81 +199,122 @@ pub async fn push_cipher_update(ut: UpdateType, cipher: &Cipher, device: &Device
 pub async fn push_logout(user: &User, acting_device_id: Option<DeviceId>, conn: &mut crate::db::DbConn) {
     let acting_device_id: Value = acting_device_id.map(|v| v.to_string().into()).unwrap_or_else(|| Value::Null);
 
-    if Device::check_user_has_push_device(&user.uuid, conn).await {
-        tokio::task::spawn(send_to_push_relay(json!({
-            "userId": user.uuid,
-            "organizationId": (),
-            "deviceId": acting_device_id,
-            "identifier": acting_device_id,
-            "type": UpdateType::LogOut as i32,
-            "payload": {
+    if let Some(targets) = PushTargets::find(&user.uuid, conn).await {
+        tokio::task::spawn(send_push_notification(
+            targets,
+            json!({
                 "userId": user.uuid,
-                "date": format_date(&user.updated_at)
-            },
-            "clientType": null,
-            "installationId": null
-        })));
+                "organizationId": (),
+                "deviceId": acting_device_id,
+                "identifier": acting_device_id,
+                "type": UpdateType::LogOut as i32,
+                "payload": {
+                    "userId": user.uuid,
+                    "date": format_date(&user.updated_at)
+                },
+                "clientType": null,
+                "installationId": null
+            }),
+        ));
     }
 }
 
 pub async fn push_user_update(ut: UpdateType, user: &User, push_uuid: &Option<PushId>, conn: &mut crate::db::DbConn) {
-    if Device::check_user_has_push_device(&user.uuid, conn).await {
-        tokio::task::spawn(send_to_push_relay(json!({
-            "userId": user.uuid,
-            "organizationId": null,
-            "deviceId": push_uuid,
-            "identifier": null,
-            "type": ut as i32,
-            "payload": {
+    if let Some(targets) = PushTargets::find(&user.uuid, conn).await {
+        tokio::task::spawn(send_push_notification(
+            targets,
+            json!({
                 "userId": user.uuid,
-                "date": format_date(&user.updated_at)
-            },
-            "clientType": null,
-            "installationId": null
-        })));
+                "organizationId": null,
+                "deviceId": push_uuid,
+                "identifier": null,
+                "type": ut as i32,
+                "payload": {
+                    "userId": user.uuid,
+                    "date": format_date(&user.updated_at)
+                },
+                "clientType": null,
+                "installationId": null
+            }),
+        ));
     }
 }
 
 pub async fn push_folder_update(ut: UpdateType, folder: &Folder, device: &Device, conn: &mut crate::db::DbConn) {
-    if Device::check_user_has_push_device(&folder.user_uuid, conn).await {
-        tokio::task::spawn(send_to_push_relay(json!({
-            "userId": folder.user_uuid,
-            "organizationId": null,
-            "deviceId": device.push_uuid, // Should be the records unique uuid of the acting device (unique uuid per user/device)
-            "identifier": device.uuid, // Should be the acting device id (aka uuid per device/app)
-            "type": ut as i32,
-            "payload": {
-                "id": folder.uuid,
+    if let Some(targets) = PushTargets::find(&folder.user_uuid, conn).await {
+        tokio::task::spawn(send_push_notification(
+            targets,
+            json!({
                 "userId": folder.user_uuid,
-                "revisionDate": format_date(&folder.updated_at)
-            },
-            "clientType": null,
-            "installationId": null
-        })));
-    }
-}
-
-pub async fn push_send_update(ut: UpdateType, send: &Send, device: &Device, conn: &mut crate::db::DbConn) {
-    if let Some(s) = &send.user_uuid {
-        if Device::check_user_has_push_device(s, conn).await {
-            tokio::task::spawn(send_to_push_relay(json!({
-                "userId": send.user_uuid,
                 "organizationId": null,
                 "deviceId": device.push_uuid, // Should be the records unique uuid of the acting device (unique uuid per user/device)
                 "identifier": device.uuid, // Should be the acting device id (aka uuid per device/app)
                 "type": ut as i32,
                 "payload": {
-                    "id": send.uuid,
-                    "userId": send.user_uuid,
-                    "revisionDate": format_date(&send.revision_date)
+                    "id": folder.uuid,
+                    "userId": folder.user_uuid,
+                    "revisionDate": format_date(&folder.updated_at)
                 },
                 "clientType": null,
                 "installationId": null
-            })));
+            }),
+        ));
+    }
+}
+
+pub async fn push_send_update(ut: UpdateType, send: &Send, device: &Device, conn: &mut crate::db::DbConn) {
+    if let Some(s) = &send.user_uuid {
+        if let Some(targets) = PushTargets::find(s, conn).await {
+            tokio::task::spawn(send_push_notification(
+                targets,
+                json!({
+                    "userId": send.user_uuid,
+                    "organizationId": null,
+                    "deviceId": device.push_uuid, // Should be the records unique uuid of the acting device (unique uuid per user/device)
+                    "identifier": device.uuid, // Should be the acting device id (aka uuid per device/app)
+                    "type": ut as i32,
+                    "payload": {
+                        "id": send.uuid,
+                        "userId": send.user_uuid,
+                        "revisionDate": format_date(&send.revision_date)
+                    },
+                    "clientType": null,
+                    "installationId": null
+                }),
+            ));
         }
     }
 }
 
+fn uses_direct_push() -> bool {
+    CONFIG.push_mode() == "direct"
+}
+
+/// Where a notification for a user is pushed to, the relay only needs to know the user
+/// while the direct mode sends to each of the push devices of the user itself.
+enum PushTargets {
+    Relay,
+    Direct(Vec<Device>),
+}
+
+impl PushTargets {
+    async fn find(user_id: &UserId, conn: &mut crate::db::DbConn) -> Option<Self> {
+        if uses_direct_push() {
+            let devices = Device::find_push_devices_by_user(user_id, conn).await;
+            (!devices.is_empty()).then_some(Self::Direct(devices))
+        } else {
+            Device::check_user_has_push_device(user_id, conn).await.then_some(Self::Relay)
+        }
+    }
+}
+
+async fn send_push_notification(targets: PushTargets, notification_data: Value) {
+    match targets {
+        PushTargets::Relay => send_to_push_relay(notification_data).await,
+        PushTargets::Direct(devices) => send_direct(devices, notification_data).await,
+    }
+}
+
 async fn send_to_push_relay(notification_data: Value) {
     if !CONFIG.push_enabled() {
         return;
@@ -297,20 +351,23 @@ async fn send_to_push_relay(notification_data: Value) {
 }
 
 pub async fn push_auth_request(user_id: &UserId, auth_request_id: &str, device: &Device, conn: &mut crate::db::DbConn) {
-    if Device::check_user_has_push_device(user_id, conn).await {
-        tokio::task::spawn(send_to_push_relay(json!({
-            "userId": user_id,
-            "organizationId": null,
-            "deviceId": device.push_uuid, // Should be the records unique uuid of the acting device (unique uuid per user/device)
-            "identifier": device.uuid, // Should be the acting device id (aka uuid per device/app)
-            "type": UpdateType::AuthRequest as i32,
-            "payload": {
+    if let Some(targets) = PushTargets::find(user_id, conn).await {
+        tokio::task::spawn(send_push_notification(
+            targets,
+            json!({
                 "userId": user_id,
-                "id": auth_request_id,
-            },
-            "clientType": null,
-            "installationId": null
-        })));
+                "organizationId": null,
+                "deviceId": device.push_uuid, // Should be the records unique uuid of the acting device (unique uuid per user/device)
+                "identifier": device.uuid, // Should be the acting device id (aka uuid per device/app)
+                "type": UpdateType::AuthRequest as i32,
+                "payload": {
+                    "userId": user_id,
+                    "id": auth_request_id,
+                },
+                "clientType": null,
+                "installationId": null
+            }),
+        ));
     }
 }
 
@@ -320,19 +377,257 @@ pub async fn push_auth_response(
     device: &Device,
     conn: &mut crate::db::DbConn,
 ) {
-    if Device::check_user_has_push_device(user_id, conn).await {
-        tokio::task::spawn(send_to_push_relay(json!({
-            "userId": user_id,
-            "organizationId": null,
-            "deviceId": device.push_uuid, // Should be the records unique uuid of the acting device (unique uuid per user/device)
-            "identifier": device.uuid, // Should be the acting device id (aka uuid per device/app)
-            "type": UpdateType::AuthRequestResponse as i32,
-            "payload": {
+    if let Some(targets) = PushTargets::find(user_id, conn).await {
+        tokio::task::spawn(send_push_notification(
+            targets,
+            json!({
                 "userId": user_id,
-                "id": auth_request_id,
+                "organizationId": null,
+                "deviceId": device.push_uuid, // Should be the records unique uuid of the acting device (unique uuid per user/device)
+                "identifier": device.uuid, // Should be the acting device id (aka uuid per device/app)
+                "type": UpdateType::AuthRequestResponse as i32,
+                "payload": {
+                    "userId": user_id,
+                    "id": auth_request_id,
+                },
+                "clientType": null,
+                "installationId": null
+            }),
+        ));
+    }
+}
+
+//
+// Direct push mode, sending to FCM and APNs without the Bitwarden push relay
+//
+async fn send_direct(devices: Vec<Device>, notification_data: Value) {
+    if !CONFIG.push_enabled() {
+        return;
+    }
+
+    // Like the relay, don't notify the device which caused the notification
+    let acting_device = notification_data["identifier"].as_str();
+    let push_type = notification_data["type"].as_i64().unwrap_or_default();
+    let payload = notification_data["payload"].to_string();
+
+    for device in devices.iter().filter(|d| acting_device != Some(d.uuid.to_string().as_str())) {
+        let Some(push_token) = &device.push_token else {
+            continue;
+        };
+
+        let result = match DeviceType::from_i32(device.atype) {
+            DeviceType::Android => send_to_fcm(push_token, push_type, &payload).await,
+            DeviceType::Ios => send_to_apns(push_token, push_type, &payload).await,
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            error!("An error occurred while sending a push notification to device {}: {e}", device.uuid);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FcmServiceAccount {
+    project_id: String,
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Serialize)]
+struct FcmTokenClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+async fn get_fcm_service_account() -> ApiResult<FcmServiceAccount> {
+    let Some(path) = CONFIG.push_fcm_service_account() else {
+        err!("`PUSH_FCM_SERVICE_ACCOUNT` is not configured")
+    };
+    let service_account = tokio::fs::read_to_string(&path).await?;
+    Ok(serde_json::from_str(&service_account)?)
+}
+
+async fn get_fcm_access_token(service_account: &FcmServiceAccount) -> ApiResult<String> {
+    static FCM_TOKEN: Lazy<RwLock<LocalAuthPushToken>> = Lazy::new(|| {
+        RwLock::new(LocalAuthPushToken {
+            access_token: String::new(),
+            valid_until: Instant::now(),
+        })
+    });
+    let fcm_token = FCM_TOKEN.read().await;
+
+    if fcm_token.valid_until.saturating_duration_since(Instant::now()).as_secs() > 0 {
+        return Ok(fcm_token.access_token.clone());
+    }
+    drop(fcm_token); // Drop the read lock now
+
+    // Exchange a JWT signed with the key of the service account for an OAuth 2.0 access token
+    let now = Utc::now().timestamp();
+    let claims = FcmTokenClaims {
+        iss: &service_account.client_email,
+        scope: "https://www.googleapis.com/auth/firebase.messaging",
+        aud: &service_account.token_uri,
+        iat: now,
+        exp: now + 3600,
+    };
+    let key = EncodingKey::from_rsa_pem(service_account.private_key.as_bytes())?;
+    let assertion = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &key)?;
+
+    let params = [("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"), ("assertion", &assertion)];
+    let res = match make_http_request(Method::POST, &service_account.token_uri)?.form(&params).send().await {
+        Ok(r) => r,
+        Err(e) => err!(format!("Error getting the FCM access token: {e}")),
+    };
+
+    let json_token = match res.error_for_status()?.json::<AuthPushToken>().await {
+        Ok(r) => r,
+        Err(e) => err!(format!("Unexpected FCM access token received: {e}")),
+    };
+
+    let mut fcm_token = FCM_TOKEN.write().await;
+    fcm_token.valid_until = Instant::now()
+        .checked_add(Duration::new((json_token.expires_in / 2) as u64, 0)) // Token valid for half the specified time
+        .unwrap();
+    fcm_token.access_token = json_token.access_token;
+
+    Ok(fcm_token.access_token.clone())
+}
+
+async fn send_to_fcm(push_token: &str, push_type: i64, payload: &str) -> EmptyResult {
+    let service_account = get_fcm_service_account().await?;
+    let access_token = get_fcm_access_token(&service_account).await?;
+
+    // The data of FCM messages can only contain strings
+    let message = json!({
+        "message": {
+            "token": push_token,
+            "data": {
+                "type": push_type.to_string(),
+                "payload": payload,
             },
-            "clientType": null,
-            "installationId": null
-        })));
+            "android": {
+                "priority": "high",
+            },
+        }
+    });
+
+    let url = format!("https://fcm.googleapis.com/v1/projects/{}/messages:send", service_account.project_id);
+    if let Err(e) = make_http_request(Method::POST, &url)?
+        .header(AUTHORIZATION, format!("Bearer {access_token}"))
+        .json(&message)
+        .send()
+        .await?
+        .error_for_status()
+    {
+        err!(format!("FCM rejected the push notification: {e}"));
     }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ApnsTokenClaims {
+    iss: String,
+    iat: i64,
+}
+
+async fn get_apns_token() -> ApiResult<String> {
+    static APNS_TOKEN: Lazy<RwLock<LocalAuthPushToken>> = Lazy::new(|| {
+        RwLock::new(LocalAuthPushToken {
+            access_token: String::new(),
+            valid_until: Instant::now(),
+        })
+    });
+    let apns_token = APNS_TOKEN.read().await;
+
+    if apns_token.valid_until.saturating_duration_since(Instant::now()).as_secs() > 0 {
+        return Ok(apns_token.access_token.clone());
+    }
+    drop(apns_token); // Drop the read lock now
+
+    let Some(path) = CONFIG.push_apns_key() else {
+        err!("`PUSH_APNS_KEY` is not configured")
+    };
+    let key = EncodingKey::from_ec_pem(&tokio::fs::read(&path).await?)?;
+
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(CONFIG.push_apns_key_id());
+    let claims = ApnsTokenClaims {
+        iss: CONFIG.push_apns_team_id(),
+        iat: Utc::now().timestamp(),
+    };
+
+    let mut apns_token = APNS_TOKEN.write().await;
+    apns_token.access_token = jsonwebtoken::encode(&header, &claims, &key)?;
+    // APNs accepts a token for an hour, and rejects tokens which are refreshed more often than every 20 minutes
+    apns_token.valid_until = Instant::now().checked_add(Duration::from_secs(50 * 60)).unwrap();
+
+    Ok(apns_token.access_token.clone())
+}
+
+async fn send_to_apns(push_token: &str, push_type: i64, payload: &str) -> EmptyResult {
+    let apns_token = get_apns_token().await?;
+
+    // Login requests are shown to the user, all other notifications only make the app sync in the background
+    let is_alert = push_type == UpdateType::AuthRequest as i64;
+    let message = if is_alert {
+        json!({
+            "aps": {
+                "alert": "Log in request",
+                "content-available": 1,
+            },
+            "data": {
+                "type": push_type,
+                "payload": payload,
+            },
+        })
+    } else {
+        json!({
+            "aps": {
+                "content-available": 1,
+            },
+            "data": {
+                "type": push_type,
+                "payload": payload,
+            },
+        })
+    };
+
+    let host = if CONFIG.push_apns_sandbox() {
+        "api.sandbox.push.apple.com"
+    } else {
+        "api.push.apple.com"
+    };
+    if let Err(e) = make_http_request(Method::POST, &format!("https://{host}/3/device/{push_token}"))?
+        .header(AUTHORIZATION, format!("bearer {apns_token}"))
+        .header("apns-topic", CONFIG.push_apns_topic())
+        .header(
+            "apns-push-type",
+            if is_alert {
+                "alert"
+            } else {
+                "background"
+            },
+        )
+        .header(
+            "apns-priority",
+            if is_alert {
+                "10"
+            } else {
+                "5"
+            },
+        )
+        .json(&message)
+        .send()
+        .await?
+        .error_for_status()
+    {
+        err!(format!("APNs rejected the push notification: {e}"));
+    }
+
+    Ok(())
 }
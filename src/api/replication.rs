@@ -0,0 +1,75 @@
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    serde::json::Json,
+    Route,
+};
+use serde_json::Value;
+
+use crate::{
+    api::JsonResult,
+    db::{models::Change, DbConn, DbPool},
+    CONFIG,
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![get_changes]
+}
+
+pub struct ReplicationToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ReplicationToken {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(expected) = CONFIG.replication_token() else {
+            return Outcome::Error((Status::NotFound, "Replication is not enabled"));
+        };
+        match request.headers().get_one("x-vaultwarden-replication") {
+            Some(token) if crate::crypto::ct_eq(token, &expected) => Outcome::Success(ReplicationToken),
+            Some(_) => Outcome::Error((Status::Unauthorized, "Invalid x-vaultwarden-replication")),
+            None => Outcome::Error((Status::Unauthorized, "Missing x-vaultwarden-replication header")),
+        }
+    }
+}
+
+/// Returns the changes after sequence number `since`, in order.
+/// A standby resumes by passing the `lastSeq` of the previous response, until `hasMore` is false.
+/// Changes after a gap which may still be filled are held back until the gap is filled or settled.
+#[get("/changes?<since>&<limit>")]
+async fn get_changes(since: Option<i64>, limit: Option<i64>, _token: ReplicationToken, mut conn: DbConn) -> JsonResult {
+    let since = since.unwrap_or(0);
+    let limit = limit.unwrap_or(Change::PAGE_SIZE).clamp(1, Change::PAGE_SIZE);
+
+    let changes = Change::until_open_gap(since, Change::find_since(since, limit, &mut conn).await);
+    let last_seq = changes.last().map_or(since, |c| c.seq);
+    let latest_seq = Change::latest_seq(&mut conn).await;
+
+    let mut data: Vec<Value> = Vec::with_capacity(changes.len());
+    for change in &changes {
+        data.push(change.to_json(&mut conn).await);
+    }
+
+    Ok(Json(json!({
+        "data": data,
+        "lastSeq": last_seq,
+        "latestSeq": latest_seq,
+        "hasMore": last_seq < latest_seq,
+        "object": "list",
+    })))
+}
+
+pub async fn replication_cleanup_job(pool: DbPool) {
+    debug!("Start replication changes cleanup job");
+    if CONFIG.replication_token().is_none() {
+        debug!("replication_token is not configured, abort");
+        return;
+    }
+
+    if let Ok(mut conn) = pool.get().await {
+        Change::clean_old(&mut conn).await.ok();
+    } else {
+        error!("Failed to get DB connection while trying to cleanup the replication changes")
+    }
+}
@@ -1,6 +1,7 @@
 use std::path::{Path, PathBuf};
 
 use rocket::{
+    form::Form,
     fs::NamedFile,
     http::ContentType,
     response::{content::RawCss as Css, content::RawHtml as Html, Redirect},
@@ -10,9 +11,9 @@ use rocket::{
 use serde_json::Value;
 
 use crate::{
-    api::{core::now, ApiResult, EmptyResult},
-    auth::decode_file_download,
-    db::models::{AttachmentId, CipherId},
+    api::{core::now, unregister_push_device, ApiResult, EmptyResult, Notify},
+    auth::{decode_device_deny, verify_file_url, ClientIp},
+    db::models::{Attachment, AttachmentId, Cipher, CipherId, Device, User},
     error::Error,
     util::Cached,
     CONFIG,
@@ -21,7 +22,7 @@ use crate::{
 pub fn routes() -> Vec<Route> {
     // If adding more routes here, consider also adding them to
     // crate::utils::LOGGED_ROUTES to make sure they appear in the log
-    let mut routes = routes![attachments, alive, alive_head, static_files];
+    let mut routes = routes![attachments, alive, alive_head, static_files, maintenance, device_deny_page, device_deny];
     if CONFIG.web_vault_enabled() {
         routes.append(&mut routes![web_index, web_index_direct, web_index_head, app_id, web_files, vaultwarden_css]);
     }
@@ -163,16 +164,26 @@ async fn web_files(p: PathBuf) -> Cached<Option<NamedFile>> {
     Cached::long(NamedFile::open(Path::new(&CONFIG.web_vault_folder()).join(p)).await.ok(), true)
 }
 
-#[get("/attachments/<cipher_id>/<file_id>?<token>")]
-async fn attachments(cipher_id: CipherId, file_id: AttachmentId, token: String) -> Option<NamedFile> {
-    let Ok(claims) = decode_file_download(&token) else {
-        return None;
-    };
-    if claims.sub != cipher_id || claims.file_id != file_id {
+/// Downloads an attachment with the signed url given by the API, see `Attachment::get_url`.
+/// The attachment files never change, so the response can be cached until the url expires.
+#[get("/attachments/<cipher_id>/<file_id>?<expires>&<signature>")]
+async fn attachments(
+    cipher_id: CipherId,
+    file_id: AttachmentId,
+    expires: i64,
+    signature: &str,
+    mut conn: DbConn,
+) -> Option<Cached<NamedFile>> {
+    let attachment = Attachment::find_by_id(&file_id, &mut conn).await.filter(|a| a.cipher_uuid == cipher_id)?;
+    let cipher = Cipher::find_by_uuid(&cipher_id, &mut conn).await?;
+    if !verify_file_url(&attachment.get_signed_path(cipher.deleted_at.as_ref()), expires, signature) {
         return None;
     }
 
-    NamedFile::open(Path::new(&CONFIG.attachments_folder()).join(cipher_id.as_ref()).join(file_id.as_ref())).await.ok()
+    let path = Path::new(&CONFIG.attachments_folder()).join(cipher_id.as_ref()).join(file_id.as_ref());
+    let file = NamedFile::open(path).await.ok()?;
+    let ttl = (expires - chrono::Utc::now().timestamp()).max(0) as u64;
+    Some(Cached::ttl(file, ttl, true))
 }
 
 // We use DbConn here to let the alive healthcheck also verify the database connection.
@@ -189,6 +200,91 @@ fn alive_head(_conn: DbConn) -> EmptyResult {
     Ok(())
 }
 
+// Requests refused by the maintenance mode are rerouted here, see `util::MaintenanceMode`.
+#[get("/vw_maintenance")]
+fn maintenance() -> EmptyResult {
+    let msg = match CONFIG.maintenance_message().filter(|m| !m.is_empty()) {
+        Some(msg) => msg,
+        None => match CONFIG.maintenance_mode().as_str() {
+            "read_only" => "The server is in read-only maintenance mode, changes can't be saved right now.",
+            "auth_only" => "The server is in maintenance mode, logging in is not possible right now.",
+            _ => "The server is down for maintenance, please try again later.",
+        }
+        .to_string(),
+    };
+    // Not logged, this would flood the logs during a lockout
+    Err(Error::new(msg.clone(), msg).with_code(503))
+}
+
+fn render_device_deny(token: Option<&str>, done: bool, error: Option<&str>) -> ApiResult<Html<String>> {
+    let json = json!({
+        "urlpath": CONFIG.domain_path(),
+        "token": token,
+        "done": done,
+        "error": error,
+    });
+    let text = CONFIG.render_template("device_deny", &json)?;
+    Ok(Html(text))
+}
+
+/// The "this wasn't me" link of the new device email, the page only asks for a confirmation.
+/// Mail scanners open the links in an email, so nothing happens until the user submits the form.
+#[get("/device-deny?<token>")]
+fn device_deny_page(token: &str) -> ApiResult<Html<String>> {
+    match decode_device_deny(token) {
+        Ok(_) => render_device_deny(Some(token), false, None),
+        Err(_) => render_device_deny(None, false, Some("This link is invalid or has expired.")),
+    }
+}
+
+#[derive(FromForm)]
+struct DeviceDenyForm {
+    token: String,
+}
+
+/// Signs out all the devices of the user, and requires a new master password before the user can log in again.
+#[post("/device-deny", format = "application/x-www-form-urlencoded", data = "<data>")]
+async fn device_deny(
+    data: Form<DeviceDenyForm>,
+    ip: ClientIp,
+    mut conn: DbConn,
+    nt: Notify<'_>,
+) -> ApiResult<Html<String>> {
+    let Ok(claims) = decode_device_deny(&data.token) else {
+        return render_device_deny(None, false, Some("This link is invalid or has expired."));
+    };
+    let Some(mut user) = User::find_by_uuid(&claims.sub, &mut conn).await else {
+        return render_device_deny(None, false, Some("This link is invalid or has expired."));
+    };
+    // The security stamp changes when the devices are signed out, so the link only works once
+    if user.security_stamp != claims.stamp {
+        return render_device_deny(
+            None,
+            false,
+            Some("This link was already used, or your sessions were signed out since."),
+        );
+    }
+
+    warn!("User {} denied the login of device {}. IP: {}", user.email, claims.device_id, ip.ip);
+
+    nt.send_logout(&user, None, &mut conn).await;
+
+    if CONFIG.push_enabled() {
+        for device in Device::find_push_devices_by_user(&user.uuid, &mut conn).await {
+            if let Err(e) = unregister_push_device(&device.push_uuid).await {
+                error!("Unable to unregister devices from Bitwarden server: {e}");
+            }
+        }
+    }
+
+    Device::delete_all_by_user(&user.uuid, &mut conn).await?;
+    user.reset_security_stamp();
+    user.force_password_reset = true;
+    user.save(&mut conn).await?;
+
+    render_device_deny(None, true, None)
+}
+
 // This endpoint/function is used during development and development only.
 // It allows to easily develop the admin interface by always loading the files from disk instead from a slice of bytes
 // This will only be active during a debug build and only when `RELOAD_TEMPLATES` is set to `true`
@@ -233,6 +329,7 @@ pub fn static_files(filename: &str) -> Result<(ContentType, &'static [u8]), Erro
         "admin_diagnostics.js" => {
             Ok((ContentType::JavaScript, include_bytes!("../static/scripts/admin_diagnostics.js")))
         }
+        "admin_two_factor.js" => Ok((ContentType::JavaScript, include_bytes!("../static/scripts/admin_two_factor.js"))),
         "bootstrap.css" => Ok((ContentType::CSS, include_bytes!("../static/scripts/bootstrap.css"))),
         "bootstrap.bundle.js" => Ok((ContentType::JavaScript, include_bytes!("../static/scripts/bootstrap.bundle.js"))),
         "jdenticon-3.3.0.js" => Ok((ContentType::JavaScript, include_bytes!("../static/scripts/jdenticon-3.3.0.js"))),
@@ -1,9 +1,21 @@
 // JWT Handling
 use chrono::{DateTime, TimeDelta, Utc};
+use data_encoding::{BASE64, BASE64URL_NOPAD, HEXLOWER};
 use jsonwebtoken::{errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header};
 use num_traits::FromPrimitive;
 use once_cell::sync::{Lazy, OnceCell};
-use openssl::rsa::Rsa;
+use openssl::{
+    asn1::Asn1Time,
+    bn::{BigNum, MsbOption},
+    hash::MessageDigest,
+    nid::Nid,
+    pkcs7::{Pkcs7, Pkcs7Flags},
+    pkey::{PKey, Private},
+    rsa::Rsa,
+    stack::Stack,
+    x509::{extension::SubjectAlternativeName, X509NameBuilder, X509},
+};
+use ring::{aead, digest, hmac};
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 use std::{env, net::IpAddr};
@@ -11,9 +23,10 @@ use std::{env, net::IpAddr};
 use crate::{
     api::ApiResult,
     config::PathType,
+    crypto,
     db::models::{
-        AttachmentId, CipherId, CollectionId, DeviceId, DeviceType, EmergencyAccessId, MembershipId, OrgApiKeyId,
-        OrganizationId, SendFileId, SendId, UserId,
+        CollectionId, DeviceId, DeviceType, EmergencyAccessId, MembershipId, OrgApiKeyId, OrganizationId, ProviderId,
+        ProviderUserId, SendId, ServiceAccountId, ServiceAccountTokenId, UserId,
     },
     error::Error,
     sso, CONFIG,
@@ -33,16 +46,23 @@ pub static JWT_LOGIN_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|login", CON
 static JWT_INVITE_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|invite", CONFIG.domain_origin()));
 static JWT_EMERGENCY_ACCESS_INVITE_ISSUER: Lazy<String> =
     Lazy::new(|| format!("{}|emergencyaccessinvite", CONFIG.domain_origin()));
+static JWT_PROVIDER_INVITE_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|providerinvite", CONFIG.domain_origin()));
 static JWT_DELETE_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|delete", CONFIG.domain_origin()));
+static JWT_DEVICE_DENY_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|devicedeny", CONFIG.domain_origin()));
 static JWT_VERIFYEMAIL_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|verifyemail", CONFIG.domain_origin()));
 static JWT_ADMIN_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|admin", CONFIG.domain_origin()));
-static JWT_SEND_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|send", CONFIG.domain_origin()));
+static JWT_ADMIN_2FA_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|admin2fa", CONFIG.domain_origin()));
 static JWT_ORG_API_KEY_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|api.organization", CONFIG.domain_origin()));
-static JWT_FILE_DOWNLOAD_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|file_download", CONFIG.domain_origin()));
+static JWT_SERVICE_ACCOUNT_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|api.secrets", CONFIG.domain_origin()));
 static JWT_REGISTER_VERIFY_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|register_verify", CONFIG.domain_origin()));
+static JWT_ORG_PURGE_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|orgpurge", CONFIG.domain_origin()));
 
 static PRIVATE_RSA_KEY: OnceCell<EncodingKey> = OnceCell::new();
 static PUBLIC_RSA_KEY: OnceCell<DecodingKey> = OnceCell::new();
+static FILE_URL_KEY: OnceCell<hmac::Key> = OnceCell::new();
+static SEND_ACCESS_KEY: OnceCell<hmac::Key> = OnceCell::new();
+static REPORT_SIGNER: OnceCell<(X509, PKey<Private>)> = OnceCell::new();
+static DATA_KEY: OnceCell<aead::LessSafeKey> = OnceCell::new();
 
 pub async fn initialize_keys() -> Result<(), Error> {
     use std::io::Error;
@@ -81,9 +101,138 @@ pub async fn initialize_keys() -> Result<(), Error> {
     if PUBLIC_RSA_KEY.set(dec).is_err() {
         err!("PUBLIC_RSA_KEY must only be initialized once")
     }
+    // Derived from the private key, so all the instances sharing the key accept the same download urls
+    let file_url_key = hmac::Key::new(hmac::HMAC_SHA256, digest::digest(&digest::SHA256, &priv_key_buffer).as_ref());
+    // Derived from the same secret, but the hashes of the Send visitors don't reveal anything about the download urls
+    let send_access_key = hmac::Key::new(hmac::HMAC_SHA256, hmac::sign(&file_url_key, b"send access").as_ref());
+    if FILE_URL_KEY.set(file_url_key).is_err() {
+        err!("FILE_URL_KEY must only be initialized once")
+    }
+    if SEND_ACCESS_KEY.set(send_access_key).is_err() {
+        err!("SEND_ACCESS_KEY must only be initialized once")
+    }
+    if REPORT_SIGNER.set(load_report_signer().await?).is_err() {
+        err!("REPORT_SIGNER must only be initialized once")
+    }
+    if DATA_KEY.set(load_data_key().await?).is_err() {
+        err!("DATA_KEY must only be initialized once")
+    }
     Ok(())
 }
 
+/// Loads the key sensitive data is encrypted with in the database, it is created on the first start.
+/// It is kept out of the database, so a copy of the database alone doesn't reveal the data.
+async fn load_data_key() -> Result<aead::LessSafeKey, Error> {
+    use std::io::Error;
+
+    let filename = std::path::PathBuf::from(CONFIG.data_key_filename())
+        .file_name()
+        .ok_or_else(|| Error::other("Data key path missing filename"))?
+        .to_str()
+        .ok_or_else(|| Error::other("Data key path filename is not valid UTF-8"))?
+        .to_string();
+
+    let operator = CONFIG.opendal_operator_for_path_type(PathType::DataKey).map_err(Error::other)?;
+
+    let key = match operator.read(&filename).await {
+        Ok(buffer) => BASE64
+            .decode(String::from_utf8_lossy(&buffer.to_vec()).trim().as_bytes())
+            .map_err(|_| Error::other("The data key is not valid base64"))?,
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => {
+            let key = crypto::get_random_bytes::<32>().to_vec();
+            operator.write(&filename, BASE64.encode(&key)).await?;
+            info!("Data key '{}' created correctly", CONFIG.data_key_filename());
+            key
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let key = aead::UnboundKey::new(&aead::AES_256_GCM, &key).map_err(|_| Error::other("The data key is invalid"))?;
+
+    Ok(aead::LessSafeKey::new(key))
+}
+
+/// Loads the key and certificate the report exports are signed with, both are created on the first start.
+/// They are kept apart from the JWT key, so the certificate the exports are checked against stays the same
+/// and rotating the session key doesn't invalidate it.
+async fn load_report_signer() -> Result<(X509, PKey<Private>), Error> {
+    use std::io::Error;
+
+    let base_name = std::path::PathBuf::from(CONFIG.report_signing_key_filename())
+        .file_name()
+        .ok_or_else(|| Error::other("Report signing key path missing filename"))?
+        .to_str()
+        .ok_or_else(|| Error::other("Report signing key path filename is not valid UTF-8"))?
+        .to_string();
+    let key_filename = format!("{base_name}.pem");
+    let cert_filename = format!("{base_name}.crt");
+
+    let operator = CONFIG.opendal_operator_for_path_type(PathType::ReportSigningKey).map_err(Error::other)?;
+
+    let key = match operator.read(&key_filename).await {
+        Ok(buffer) => PKey::private_key_from_pem(buffer.to_vec().as_slice())?,
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => {
+            let key = PKey::from_rsa(Rsa::generate(3072)?)?;
+            operator.write(&key_filename, key.private_key_to_pem_pkcs8()?).await?;
+            // A certificate left over from a previous key doesn't match the new one
+            if let Err(e) = operator.delete(&cert_filename).await {
+                warn!("Unable to remove the previous report signing certificate: {e}");
+            }
+            info!("Report signing key '{}.pem' created correctly", CONFIG.report_signing_key_filename());
+            key
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let certificate = match operator.read(&cert_filename).await {
+        Ok(buffer) => X509::from_pem(buffer.to_vec().as_slice())?,
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => {
+            let certificate = report_signing_certificate(&key)?;
+            operator.write(&cert_filename, certificate.to_pem()?).await?;
+            info!("Report signing certificate '{}.crt' created correctly", CONFIG.report_signing_key_filename());
+            certificate
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if !certificate.public_key()?.public_eq(&key) {
+        err!("The report signing certificate doesn't match the report signing key")
+    }
+
+    Ok((certificate, key))
+}
+
+/// A self-signed certificate of the report signing key, which identifies the signer of the report exports.
+fn report_signing_certificate(key: &PKey<Private>) -> Result<X509, Error> {
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::ORGANIZATIONNAME, "Vaultwarden")?;
+    name.append_entry_by_nid(Nid::COMMONNAME, "Report signing")?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial.to_asn1_integer()?)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(key)?;
+    builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+    builder.set_not_after(&Asn1Time::days_from_now(3650)?)?;
+    let san = SubjectAlternativeName::new().uri(&CONFIG.domain_origin()).build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+    builder.sign(key, MessageDigest::sha256())?;
+
+    Ok(builder.build())
+}
+
+/// Signs a report export with the report signing key, returns the DER encoded detached PKCS#7 signature.
+/// The signature includes the signing certificate, so it can be checked with `openssl smime -verify -noverify`.
+pub fn sign_report(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let (certificate, key) = REPORT_SIGNER.wait();
+    let signature = Pkcs7::sign(certificate, key, &Stack::new()?, data, Pkcs7Flags::DETACHED | Pkcs7Flags::BINARY)?;
+    Ok(signature.to_der()?)
+}
+
 pub fn encode_jwt<T: Serialize>(claims: &T) -> String {
     match jsonwebtoken::encode(&JWT_HEADER, claims, PRIVATE_RSA_KEY.wait()) {
         Ok(token) => token,
@@ -126,10 +275,18 @@ pub fn decode_emergency_access_invite(token: &str) -> Result<EmergencyAccessInvi
     decode_jwt(token, JWT_EMERGENCY_ACCESS_INVITE_ISSUER.to_string())
 }
 
+pub fn decode_provider_invite(token: &str) -> Result<ProviderInviteJwtClaims, Error> {
+    decode_jwt(token, JWT_PROVIDER_INVITE_ISSUER.to_string())
+}
+
 pub fn decode_delete(token: &str) -> Result<BasicJwtClaims, Error> {
     decode_jwt(token, JWT_DELETE_ISSUER.to_string())
 }
 
+pub fn decode_device_deny(token: &str) -> Result<DeviceDenyJwtClaims, Error> {
+    decode_jwt(token, JWT_DEVICE_DENY_ISSUER.to_string())
+}
+
 pub fn decode_verify_email(token: &str) -> Result<BasicJwtClaims, Error> {
     decode_jwt(token, JWT_VERIFYEMAIL_ISSUER.to_string())
 }
@@ -138,22 +295,26 @@ pub fn decode_admin(token: &str) -> Result<BasicJwtClaims, Error> {
     decode_jwt(token, JWT_ADMIN_ISSUER.to_string())
 }
 
-pub fn decode_send(token: &str) -> Result<BasicJwtClaims, Error> {
-    decode_jwt(token, JWT_SEND_ISSUER.to_string())
+pub fn decode_admin_two_factor(token: &str) -> Result<BasicJwtClaims, Error> {
+    decode_jwt(token, JWT_ADMIN_2FA_ISSUER.to_string())
 }
 
 pub fn decode_api_org(token: &str) -> Result<OrgApiKeyLoginJwtClaims, Error> {
     decode_jwt(token, JWT_ORG_API_KEY_ISSUER.to_string())
 }
 
-pub fn decode_file_download(token: &str) -> Result<FileDownloadClaims, Error> {
-    decode_jwt(token, JWT_FILE_DOWNLOAD_ISSUER.to_string())
+pub fn decode_service_account(token: &str) -> Result<ServiceAccountLoginJwtClaims, Error> {
+    decode_jwt(token, JWT_SERVICE_ACCOUNT_ISSUER.to_string())
 }
 
 pub fn decode_register_verify(token: &str) -> Result<RegisterVerifyClaims, Error> {
     decode_jwt(token, JWT_REGISTER_VERIFY_ISSUER.to_string())
 }
 
+pub fn decode_org_purge(token: &str) -> Result<OrgPurgeJwtClaims, Error> {
+    decode_jwt(token, JWT_ORG_PURGE_ISSUER.to_string())
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginJwtClaims {
     // Not before
@@ -202,7 +363,7 @@ impl LoginJwtClaims {
         user: &User,
         nbf: i64,
         exp: i64,
-        scope: Vec<String>,
+        auth_method: &AuthMethod,
         client_id: Option<String>,
         now: DateTime<Utc>,
     ) -> Self {
@@ -247,8 +408,8 @@ impl LoginJwtClaims {
             device: device.uuid.clone(),
             devicetype: DeviceType::from_i32(device.atype).to_string(),
             client_id: client_id.unwrap_or("undefined".to_string()),
-            scope,
-            amr: vec!["Application".into()],
+            scope: auth_method.scope_vec(),
+            amr: auth_method.amr(),
         }
     }
 
@@ -259,7 +420,7 @@ impl LoginJwtClaims {
             user,
             time_now.timestamp(),
             (time_now + *DEFAULT_ACCESS_VALIDITY).timestamp(),
-            auth_method.scope_vec(),
+            auth_method,
             client_id,
             time_now,
         )
@@ -350,6 +511,41 @@ pub fn generate_emergency_access_invite_claims(
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProviderInviteJwtClaims {
+    // Not before
+    pub nbf: i64,
+    // Expiration time
+    pub exp: i64,
+    // Issuer
+    pub iss: String,
+    // Subject
+    pub sub: UserId,
+
+    pub email: String,
+    pub provider_id: ProviderId,
+    pub provider_user_id: ProviderUserId,
+}
+
+pub fn generate_provider_invite_claims(
+    user_id: UserId,
+    email: String,
+    provider_id: ProviderId,
+    provider_user_id: ProviderUserId,
+) -> ProviderInviteJwtClaims {
+    let time_now = Utc::now();
+    let expire_hours = i64::from(CONFIG.invitation_expiration_hours());
+    ProviderInviteJwtClaims {
+        nbf: time_now.timestamp(),
+        exp: (time_now + TimeDelta::try_hours(expire_hours).unwrap()).timestamp(),
+        iss: JWT_PROVIDER_INVITE_ISSUER.to_string(),
+        sub: user_id,
+        email,
+        provider_id,
+        provider_user_id,
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OrgApiKeyLoginJwtClaims {
     // Not before
@@ -383,7 +579,7 @@ pub fn generate_organization_api_key_login_claims(
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct FileDownloadClaims {
+pub struct ServiceAccountLoginJwtClaims {
     // Not before
     pub nbf: i64,
     // Expiration time
@@ -391,19 +587,29 @@ pub struct FileDownloadClaims {
     // Issuer
     pub iss: String,
     // Subject
-    pub sub: CipherId,
+    pub sub: ServiceAccountId,
 
-    pub file_id: AttachmentId,
+    // The access token used to log in, checked on every request so revoked tokens stop working right away
+    pub client_id: ServiceAccountTokenId,
+    // The SDK reads the organization of the service account from this claim
+    pub organization: OrganizationId,
+    pub scope: Vec<String>,
 }
 
-pub fn generate_file_download_claims(cipher_id: CipherId, file_id: AttachmentId) -> FileDownloadClaims {
+pub fn generate_service_account_login_claims(
+    token_uuid: ServiceAccountTokenId,
+    service_account_uuid: ServiceAccountId,
+    org_id: OrganizationId,
+) -> ServiceAccountLoginJwtClaims {
     let time_now = Utc::now();
-    FileDownloadClaims {
+    ServiceAccountLoginJwtClaims {
         nbf: time_now.timestamp(),
-        exp: (time_now + TimeDelta::try_minutes(5).unwrap()).timestamp(),
-        iss: JWT_FILE_DOWNLOAD_ISSUER.to_string(),
-        sub: cipher_id,
-        file_id,
+        exp: (time_now + TimeDelta::try_hours(1).unwrap()).timestamp(),
+        iss: JWT_SERVICE_ACCOUNT_ISSUER.to_string(),
+        sub: service_account_uuid,
+        client_id: token_uuid,
+        organization: org_id,
+        scope: AuthMethod::ServiceAccount.scope_vec(),
     }
 }
 
@@ -434,6 +640,38 @@ pub fn generate_register_verify_claims(email: String, name: Option<String>, veri
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrgPurgeJwtClaims {
+    // Not before
+    pub nbf: i64,
+    // Expiration time
+    pub exp: i64,
+    // Issuer
+    pub iss: String,
+    // Subject
+    pub sub: OrganizationId,
+
+    pub trashed_only: bool,
+    // The hash of the ids of the items the purge was confirmed for, the token can't be used anymore once they changed
+    pub ciphers_hash: String,
+}
+
+pub fn generate_org_purge_claims(
+    org_id: OrganizationId,
+    trashed_only: bool,
+    ciphers_hash: String,
+) -> OrgPurgeJwtClaims {
+    let time_now = Utc::now();
+    OrgPurgeJwtClaims {
+        nbf: time_now.timestamp(),
+        exp: (time_now + TimeDelta::try_minutes(10).unwrap()).timestamp(),
+        iss: JWT_ORG_PURGE_ISSUER.to_string(),
+        sub: org_id,
+        trashed_only,
+        ciphers_hash,
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BasicJwtClaims {
     // Not before
@@ -457,6 +695,35 @@ pub fn generate_delete_claims(uuid: String) -> BasicJwtClaims {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceDenyJwtClaims {
+    // Not before
+    pub nbf: i64,
+    // Expiration time
+    pub exp: i64,
+    // Issuer
+    pub iss: String,
+    // Subject
+    pub sub: UserId,
+
+    pub device_id: DeviceId,
+    // The security stamp of the user when the mail was sent, so the link can't be used again once it did its job
+    pub stamp: String,
+}
+
+pub fn generate_device_deny_claims(user_id: UserId, device_id: DeviceId, stamp: String) -> DeviceDenyJwtClaims {
+    let time_now = Utc::now();
+    let expire_hours = i64::from(CONFIG.invitation_expiration_hours());
+    DeviceDenyJwtClaims {
+        nbf: time_now.timestamp(),
+        exp: (time_now + TimeDelta::try_hours(expire_hours).unwrap()).timestamp(),
+        iss: JWT_DEVICE_DENY_ISSUER.to_string(),
+        sub: user_id,
+        device_id,
+        stamp,
+    }
+}
+
 pub fn generate_verify_email_claims(user_id: UserId) -> BasicJwtClaims {
     let time_now = Utc::now();
     let expire_hours = i64::from(CONFIG.invitation_expiration_hours());
@@ -478,26 +745,102 @@ pub fn generate_admin_claims() -> BasicJwtClaims {
     }
 }
 
-pub fn generate_send_claims(send_id: &SendId, file_id: &SendFileId) -> BasicJwtClaims {
+/// Issued after a valid admin token when `ADMIN_TWO_FACTOR` is enabled, only allows to complete the two-step login.
+pub fn generate_admin_two_factor_claims() -> BasicJwtClaims {
     let time_now = Utc::now();
     BasicJwtClaims {
         nbf: time_now.timestamp(),
-        exp: (time_now + TimeDelta::try_minutes(2).unwrap()).timestamp(),
-        iss: JWT_SEND_ISSUER.to_string(),
-        sub: format!("{send_id}/{file_id}"),
+        exp: (time_now + TimeDelta::try_minutes(10).unwrap()).timestamp(),
+        iss: JWT_ADMIN_2FA_ISSUER.to_string(),
+        sub: "admin_panel".to_string(),
     }
 }
 
+//
+// Signed file download urls
+//
+/// The expiration of a new download url, rounded up to a multiple of `FILE_DOWNLOAD_URL_TTL`.
+/// So the url of a file stays the same for a while, and a caching proxy in front of the downloads can reuse its copy.
+pub fn file_url_expiration() -> i64 {
+    let ttl = CONFIG.file_download_url_ttl() as i64;
+    (Utc::now().timestamp() / ttl + 2) * ttl
+}
+
+/// Signs the download url of a file, `path` identifies the file and anything else the url depends on.
+/// The urls don't need the access token, so clients and proxies can fetch the files directly.
+pub fn sign_file_url(path: &str, expires: i64) -> String {
+    let signature = hmac::sign(FILE_URL_KEY.wait(), format!("{path}|{expires}").as_bytes());
+    BASE64URL_NOPAD.encode(signature.as_ref())
+}
+
+pub fn verify_file_url(path: &str, expires: i64, signature: &str) -> bool {
+    expires >= Utc::now().timestamp() && crypto::ct_eq(sign_file_url(path, expires), signature)
+}
+
+/// Pseudonymizes the IP of a Send visitor, the same visitor gets the same hash for a Send but not across Sends.
+/// The key is a server secret, so the hashes can't be reversed by trying all the IPs with the public Send id.
+pub fn send_visitor_hash(send_id: &SendId, ip: &IpAddr) -> String {
+    let tag = hmac::sign(SEND_ACCESS_KEY.wait(), format!("{send_id}|{ip}").as_bytes());
+    HEXLOWER.encode(tag.as_ref())
+}
+
+//
+// Encryption at rest
+//
+const ENCRYPTED_PREFIX: &str = "enc1.";
+
+/// Encrypts data before it is stored in the database, with AES-256-GCM and the data key.
+/// `context` is authenticated as well, like the id of the row, so an encrypted value can't be moved to another row.
+pub fn encrypt_at_rest(data: &str, context: &str) -> Result<String, Error> {
+    let nonce = crypto::get_random_bytes::<{ aead::NONCE_LEN }>();
+    let mut in_out = data.as_bytes().to_vec();
+    if DATA_KEY
+        .wait()
+        .seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce), aead::Aad::from(context), &mut in_out)
+        .is_err()
+    {
+        err!("Error encrypting data")
+    }
+    Ok(format!("{ENCRYPTED_PREFIX}{}.{}", BASE64.encode(&nonce), BASE64.encode(&in_out)))
+}
+
+/// Decrypts data stored by `encrypt_at_rest`.
+pub fn decrypt_at_rest(value: &str, context: &str) -> Result<String, Error> {
+    let Some((nonce, data)) = value.strip_prefix(ENCRYPTED_PREFIX).and_then(|value| value.split_once('.')) else {
+        err!("The data is not encrypted")
+    };
+    let (Ok(nonce), Ok(mut in_out)) = (BASE64.decode(nonce.as_bytes()), BASE64.decode(data.as_bytes())) else {
+        err!("The encrypted data is not valid base64")
+    };
+    let Ok(nonce) = aead::Nonce::try_assume_unique_for_key(&nonce) else {
+        err!("The encrypted data has an invalid nonce")
+    };
+    let Ok(data) = DATA_KEY.wait().open_in_place(nonce, aead::Aad::from(context), &mut in_out) else {
+        err!("Error decrypting data, the data key doesn't match")
+    };
+    String::from_utf8(data.to_vec()).map_err(|_| Error::new("Error decrypting data", "The data is not valid UTF-8"))
+}
+
+/// Whether a value was stored by `encrypt_at_rest`, values from before the encryption are plain text.
+pub fn is_encrypted_at_rest(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
 //
 // Bearer token authentication
 //
 use rocket::{
+    http::Method,
     outcome::try_outcome,
     request::{FromRequest, Outcome, Request},
 };
 
 use crate::db::{
-    models::{Collection, Device, Membership, MembershipStatus, MembershipType, User, UserStampException},
+    models::{
+        Collection, Device, Membership, MembershipStatus, MembershipType, OrgPermission, OrgPolicy, OrgPolicyType,
+        Organization, PersonalApiToken, PersonalApiTokenScope, ProviderOrganization, ProviderUser, RefreshTokenFamily,
+        TwoFactor, User, UserStampException,
+    },
     DbConn,
 };
 
@@ -545,6 +888,8 @@ impl<'r> FromRequest<'r> for Host {
 pub struct ClientHeaders {
     pub device_type: i32,
     pub ip: ClientIp,
+    pub user_agent: Option<String>,
+    pub country: Option<String>,
 }
 
 #[rocket::async_trait]
@@ -559,10 +904,17 @@ impl<'r> FromRequest<'r> for ClientHeaders {
         // When unknown or unable to parse, return 14, which is 'Unknown Browser'
         let device_type: i32 =
             request.headers().get_one("device-type").map(|d| d.parse().unwrap_or(14)).unwrap_or_else(|| 14);
+        let user_agent = request.headers().get_one("User-Agent").map(String::from);
+        let country = CONFIG
+            .ip_country_header()
+            .and_then(|header| request.headers().get_one(&header).map(|c| c.trim().to_uppercase()))
+            .filter(|c| !c.is_empty());
 
         Outcome::Success(ClientHeaders {
             device_type,
             ip,
+            user_agent,
+            country,
         })
     }
 }
@@ -603,6 +955,7 @@ impl<'r> FromRequest<'r> for Headers {
 
         let device_id = claims.device;
         let user_id = claims.sub;
+        request.local_cache(|| LoginAmr(claims.amr));
 
         let mut conn = match DbConn::from_request(request).await {
             Outcome::Success(conn) => conn,
@@ -617,6 +970,10 @@ impl<'r> FromRequest<'r> for Headers {
             err_handler!("Device has no user associated")
         };
 
+        if user.is_soft_deleted() {
+            err_handler!("User has been deleted")
+        }
+
         if user.security_stamp != claims.sstamp {
             if let Some(stamp_exception) =
                 user.stamp_exception.as_deref().and_then(|s| serde_json::from_str::<UserStampException>(s).ok())
@@ -656,6 +1013,106 @@ impl<'r> FromRequest<'r> for Headers {
     }
 }
 
+/// The PersonalTokenHeaders are used for the endpoints scripts can call with a personal API token,
+/// which needs to have the scope `S` (see PersonalApiTokenScope). There is no device, the token is used directly.
+pub struct PersonalTokenHeaders<const S: i32> {
+    pub host: String,
+    pub user: User,
+    pub ip: ClientIp,
+}
+
+pub type PersonalSyncHeaders = PersonalTokenHeaders<{ PersonalApiTokenScope::Sync as i32 }>;
+pub type PersonalSendHeaders = PersonalTokenHeaders<{ PersonalApiTokenScope::Send as i32 }>;
+
+#[rocket::async_trait]
+impl<'r, const S: i32> FromRequest<'r> for PersonalTokenHeaders<S> {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        if !CONFIG.personal_api_tokens_allowed() {
+            err_handler!("Personal API tokens are not enabled on this server")
+        }
+
+        let host = try_outcome!(Host::from_request(request).await).host;
+        let ip = match ClientIp::from_request(request).await {
+            Outcome::Success(ip) => ip,
+            _ => err_handler!("Error getting Client IP"),
+        };
+
+        let token_string = request.headers().get_one("Authorization").and_then(|a| a.strip_prefix("Bearer "));
+        let Some((token_id, secret)) = token_string.and_then(PersonalApiToken::parse) else {
+            err_handler!("No personal API token provided")
+        };
+
+        let mut conn = match DbConn::from_request(request).await {
+            Outcome::Success(conn) => conn,
+            _ => err_handler!("Error getting DB"),
+        };
+
+        let Some(mut token) = PersonalApiToken::find_by_uuid(&token_id, &mut conn).await else {
+            err_handler!("Invalid personal API token")
+        };
+        if !token.check_valid_secret(secret) || token.is_expired() {
+            err_handler!("Invalid personal API token")
+        }
+        if !PersonalApiTokenScope::from_i32(S).is_some_and(|scope| token.has_scope(scope)) {
+            err_handler!("The personal API token doesn't have the scope of this endpoint")
+        }
+
+        let Some(user) = User::find_by_uuid(&token.user_uuid, &mut conn).await else {
+            err_handler!("Token has no user associated")
+        };
+        if !user.enabled || user.is_soft_deleted() {
+            err_handler!("User is disabled or has been deleted")
+        }
+        if token.is_revoked(&user) {
+            if let Err(e) = PersonalApiToken::delete_revoked_by_user(&user, &mut conn).await {
+                error!("Error deleting revoked personal API tokens: {e:#?}");
+            }
+            err_handler!("Invalid personal API token")
+        }
+
+        if let Err(e) = token.touch(&mut conn).await {
+            error!("Error updating personal API token: {e:#?}");
+        }
+
+        Outcome::Success(Self {
+            host,
+            user,
+            ip,
+        })
+    }
+}
+
+const SSO_AMR: &str = "external";
+
+// The `amr` claim of the access token of the request, cached by `Headers` for the guards built on top of it
+struct LoginAmr(Vec<String>);
+
+/// Provider users aren't members of the client organizations, so the checks which are done for the members on login
+/// are done on every request instead: the IP restrictions, and the TwoFactorAuthentication and RequireSso policies.
+async fn check_provider_org_access(
+    org: &Organization,
+    user: &User,
+    ip: &IpAddr,
+    sso_login: bool,
+    conn: &mut DbConn,
+) -> Result<(), &'static str> {
+    if org.has_ip_restrictions() && !org.is_ip_allowed(*ip) {
+        return Err("Access from this IP address isn't allowed by the organization");
+    }
+    let policy_enabled = |policy: Option<OrgPolicy>| policy.is_some_and(|p| p.enabled);
+    if policy_enabled(OrgPolicy::find_by_org_and_type(&org.uuid, OrgPolicyType::TwoFactorAuthentication, conn).await)
+        && TwoFactor::find_by_user(&user.uuid, conn).await.is_empty()
+    {
+        return Err("The organization requires two-step login");
+    }
+    if policy_enabled(OrgPolicy::find_by_org_and_type(&org.uuid, OrgPolicyType::RequireSso, conn).await) && !sso_login {
+        return Err("The organization requires an SSO login");
+    }
+    Ok(())
+}
+
 pub struct OrgHeaders {
     pub host: String,
     pub device: Device,
@@ -712,10 +1169,36 @@ impl<'r> FromRequest<'r> for OrgHeaders {
                 };
 
                 let user = headers.user;
-                let Some(membership) = Membership::find_by_user_and_org(&user.uuid, &org_id, &mut conn).await else {
-                    err_handler!("The current user isn't member of the organization");
+                let membership = match Membership::find_by_user_and_org(&user.uuid, &org_id, &mut conn).await {
+                    Some(membership) => membership,
+                    // The users of the provider managing the organization administer it without being members
+                    None => {
+                        let (Some(provider_user), Some(provider_org)) = (
+                            ProviderUser::find_confirmed_by_user_and_org(&user.uuid, &org_id, &mut conn).await,
+                            ProviderOrganization::find_by_org(&org_id, &mut conn).await,
+                        ) else {
+                            err_handler!("The current user isn't member of the organization");
+                        };
+                        let Some(org) = Organization::find_by_uuid(&org_id, &mut conn).await else {
+                            err_handler!("The current user isn't member of the organization");
+                        };
+                        let sso_login = request.local_cache(|| LoginAmr(Vec::new())).0.iter().any(|amr| amr == SSO_AMR);
+                        if let Err(e) =
+                            check_provider_org_access(&org, &user, &headers.ip.ip, sso_login, &mut conn).await
+                        {
+                            err_handler!(e, format!("Provider user: {}. Organization: {org_id}", user.uuid))
+                        }
+                        provider_org.membership_for(&provider_user)
+                    }
                 };
 
+                // Archived organizations keep their data, but only until they are unarchived it can be changed
+                if request.method() != Method::Get
+                    && Organization::find_by_uuid(&org_id, &mut conn).await.is_some_and(|org| org.is_archived())
+                {
+                    err_handler!("The organization is archived, it can't be changed")
+                }
+
                 Outcome::Success(Self {
                     host: headers.host,
                     device: headers.device,
@@ -776,6 +1259,50 @@ impl<'r> FromRequest<'r> for AdminHeaders {
     }
 }
 
+/// The PermissionHeaders are used for endpoints which Admins and Owners can always call,
+/// and Custom members only when they have been granted the permission `P` (see OrgPermission).
+/// They dereference to the AdminHeaders, so they can be used in the same way.
+pub struct PermissionHeaders<const P: i32>(AdminHeaders);
+
+pub type AccessEventLogsHeaders = PermissionHeaders<{ OrgPermission::AccessEventLogs as i32 }>;
+pub type AccessImportExportHeaders = PermissionHeaders<{ OrgPermission::AccessImportExport as i32 }>;
+pub type AccessReportsHeaders = PermissionHeaders<{ OrgPermission::AccessReports as i32 }>;
+pub type ManageGroupsHeaders = PermissionHeaders<{ OrgPermission::ManageGroups as i32 }>;
+pub type ManagePoliciesHeaders = PermissionHeaders<{ OrgPermission::ManagePolicies as i32 }>;
+pub type ManageUsersHeaders = PermissionHeaders<{ OrgPermission::ManageUsers as i32 }>;
+pub type ManageResetPasswordHeaders = PermissionHeaders<{ OrgPermission::ManageResetPassword as i32 }>;
+
+#[rocket::async_trait]
+impl<'r, const P: i32> FromRequest<'r> for PermissionHeaders<P> {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = try_outcome!(OrgHeaders::from_request(request).await);
+        let has_permission = headers.is_confirmed_and_admin()
+            || (headers.is_confirmed_and_manager() && headers.membership.permissions & P != 0);
+        if has_permission {
+            Outcome::Success(Self(AdminHeaders {
+                host: headers.host,
+                device: headers.device,
+                user: headers.user,
+                membership_type: headers.membership_type,
+                ip: headers.ip,
+                org_id: headers.membership.org_uuid,
+            }))
+        } else {
+            err_handler!("You don't have the permission to call this endpoint")
+        }
+    }
+}
+
+impl<const P: i32> std::ops::Deref for PermissionHeaders<P> {
+    type Target = AdminHeaders;
+
+    fn deref(&self) -> &AdminHeaders {
+        &self.0
+    }
+}
+
 // col_id is usually the fourth path param ("/organizations/<org_id>/collections/<col_id>"),
 // but there could be cases where it is a query value.
 // First check the path, if this is not a valid uuid, try the query values.
@@ -795,8 +1322,9 @@ fn get_col_id(request: &Request<'_>) -> Option<CollectionId> {
     None
 }
 
-/// The ManagerHeaders are used to check if you are at least a Manager
-/// and have access to the specific collection provided via the <col_id>/collections/collectionId.
+/// The ManagerHeaders are used to check if you can manage the specific collection provided via the
+/// <col_id>/collections/collectionId. Besides Owners, Admins and Managers with access to all collections,
+/// any member with the manage permission of the collection can.
 /// This does strict checking on the collection_id, ManagerHeadersLoose does not.
 pub struct ManagerHeaders {
     pub host: String,
@@ -812,31 +1340,27 @@ impl<'r> FromRequest<'r> for ManagerHeaders {
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         let headers = try_outcome!(OrgHeaders::from_request(request).await);
-        if headers.is_confirmed_and_manager() {
-            match get_col_id(request) {
-                Some(col_id) => {
-                    let mut conn = match DbConn::from_request(request).await {
-                        Outcome::Success(conn) => conn,
-                        _ => err_handler!("Error getting DB"),
-                    };
-
-                    if !Collection::can_access_collection(&headers.membership, &col_id, &mut conn).await {
-                        err_handler!("The current user isn't a manager for this collection")
-                    }
+        match get_col_id(request) {
+            Some(col_id) => {
+                let mut conn = match DbConn::from_request(request).await {
+                    Outcome::Success(conn) => conn,
+                    _ => err_handler!("Error getting DB"),
+                };
+
+                if !Collection::can_manage_collection(&headers.membership, &col_id, &mut conn).await {
+                    err_handler!("The current user can't manage this collection")
                 }
-                _ => err_handler!("Error getting the collection id"),
             }
-
-            Outcome::Success(Self {
-                host: headers.host,
-                device: headers.device,
-                user: headers.user,
-                ip: headers.ip,
-                org_id: headers.membership.org_uuid,
-            })
-        } else {
-            err_handler!("You need to be a Manager, Admin or Owner to call this endpoint")
+            _ => err_handler!("Error getting the collection id"),
         }
+
+        Outcome::Success(Self {
+            host: headers.host,
+            device: headers.device,
+            user: headers.user,
+            ip: headers.ip,
+            org_id: headers.membership.org_uuid,
+        })
     }
 }
 
@@ -851,7 +1375,7 @@ impl From<ManagerHeaders> for Headers {
     }
 }
 
-/// The ManagerHeadersLoose is used when you at least need to be a Manager,
+/// The ManagerHeadersLoose is used when you at least need to be a Manager, or be able to manage one of the collections,
 /// but there is no collection_id sent with the request (either in the path or as form data).
 pub struct ManagerHeadersLoose {
     pub host: String,
@@ -867,7 +1391,13 @@ impl<'r> FromRequest<'r> for ManagerHeadersLoose {
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         let headers = try_outcome!(OrgHeaders::from_request(request).await);
-        if headers.is_confirmed_and_manager() {
+        let mut conn = match DbConn::from_request(request).await {
+            Outcome::Success(conn) => conn,
+            _ => err_handler!("Error getting DB"),
+        };
+        if headers.is_confirmed_and_manager()
+            || Collection::can_manage_any_collection(&headers.membership, &mut conn).await
+        {
             Outcome::Success(Self {
                 host: headers.host,
                 device: headers.device,
@@ -876,7 +1406,7 @@ impl<'r> FromRequest<'r> for ManagerHeadersLoose {
                 ip: headers.ip,
             })
         } else {
-            err_handler!("You need to be a Manager, Admin or Owner to call this endpoint")
+            err_handler!("You need to be a Manager, Admin or Owner, or manage a collection to call this endpoint")
         }
     }
 }
@@ -902,8 +1432,8 @@ impl ManagerHeaders {
             if uuid::Uuid::parse_str(col_id.as_ref()).is_err() {
                 err!("Collection Id is malformed!");
             }
-            if !Collection::can_access_collection(&h.membership, col_id, conn).await {
-                err!("You don't have access to all collections!");
+            if !Collection::can_manage_collection(&h.membership, col_id, conn).await {
+                err!("You can't manage all collections!");
             }
         }
 
@@ -918,6 +1448,7 @@ impl ManagerHeaders {
 }
 
 pub struct OwnerHeaders {
+    pub host: String,
     pub device: Device,
     pub user: User,
     pub ip: ClientIp,
@@ -932,6 +1463,7 @@ impl<'r> FromRequest<'r> for OwnerHeaders {
         let headers = try_outcome!(OrgHeaders::from_request(request).await);
         if headers.is_confirmed_and_owner() {
             Outcome::Success(Self {
+                host: headers.host,
                 device: headers.device,
                 user: headers.user,
                 ip: headers.ip,
@@ -943,6 +1475,17 @@ impl<'r> FromRequest<'r> for OwnerHeaders {
     }
 }
 
+impl From<OwnerHeaders> for Headers {
+    fn from(h: OwnerHeaders) -> Headers {
+        Headers {
+            host: h.host,
+            device: h.device,
+            user: h.user,
+            ip: h.ip,
+        }
+    }
+}
+
 pub struct OrgMemberHeaders {
     pub host: String,
     pub device: Device,
@@ -1095,6 +1638,7 @@ impl<'r> FromRequest<'r> for ClientVersion {
 pub enum AuthMethod {
     OrgApiKey,
     Password,
+    ServiceAccount,
     Sso,
     UserApiKey,
 }
@@ -1104,6 +1648,7 @@ impl AuthMethod {
         match self {
             AuthMethod::OrgApiKey => "api.organization".to_string(),
             AuthMethod::Password => "api offline_access".to_string(),
+            AuthMethod::ServiceAccount => "api.secrets".to_string(),
             AuthMethod::Sso => "api offline_access".to_string(),
             AuthMethod::UserApiKey => "api".to_string(),
         }
@@ -1113,6 +1658,14 @@ impl AuthMethod {
         self.scope().split_whitespace().map(str::to_string).collect()
     }
 
+    /// Like the Bitwarden server, the access tokens of an SSO login are marked as `external`.
+    pub fn amr(&self) -> Vec<String> {
+        match self {
+            AuthMethod::Sso => vec![SSO_AMR.into()],
+            _ => vec!["Application".into()],
+        }
+    }
+
     pub fn check_scope(&self, scope: Option<&String>) -> ApiResult<String> {
         let method_scope = self.scope();
         match scope {
@@ -1143,6 +1696,18 @@ pub struct RefreshJwtClaims {
     pub device_token: String,
 
     pub token: Option<TokenWrapper>,
+
+    // The refresh token family of the device and the generation of this token in it, see `RefreshTokenFamily`.
+    // Tokens issued before rotation was enabled don't have them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation: Option<i32>,
+
+    // The time of the login the token belongs to, for the maximum session lifetime of the organizations.
+    // Tokens issued before it was added don't have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -1168,6 +1733,24 @@ impl AuthTokens {
         self.refresh_claims.sub.scope()
     }
 
+    /// Shortens the tokens to the strictest session lifetime policy of the organizations of the user.
+    /// The maximum lifetime counts from the login, the idle timeout from now since the tokens are renewed on every use.
+    pub async fn apply_session_lifetime(&mut self, user_uuid: &UserId, conn: &mut DbConn) {
+        let Some(policy) = OrgPolicy::find_session_lifetime_policy(user_uuid, conn).await else {
+            return;
+        };
+        let now = Utc::now().timestamp();
+        let auth_time = self.refresh_claims.auth_time.unwrap_or(self.refresh_claims.nbf);
+        self.refresh_claims.exp = policy.limit_expiration(self.refresh_claims.exp, auth_time, now);
+        self.access_claims.exp = self.access_claims.exp.min(self.refresh_claims.exp);
+    }
+
+    /// Binds the refresh token to the current generation of the family.
+    pub fn set_family(&mut self, family: &RefreshTokenFamily) {
+        self.refresh_claims.family = Some(family.family_uuid.clone());
+        self.refresh_claims.generation = Some(family.generation);
+    }
+
     // Create refresh_token and access_token with default validity
     pub fn new(device: &Device, user: &User, sub: AuthMethod, client_id: Option<String>) -> Self {
         let time_now = Utc::now();
@@ -1187,6 +1770,9 @@ impl AuthTokens {
             sub,
             device_token: device.refresh_token.clone(),
             token: None,
+            family: None,
+            generation: None,
+            auth_time: Some(time_now.timestamp()),
         };
 
         Self {
@@ -1216,15 +1802,24 @@ pub async fn refresh_tokens(
         Some(device) => device,
     };
 
+    let family = if CONFIG.refresh_token_rotation() {
+        Some(rotate_refresh_family(ip, &refresh_claims, &mut device, conn).await?)
+    } else {
+        None
+    };
+    // The session keeps the time of its login, older tokens count from when they were issued
+    let auth_time = refresh_claims.auth_time.unwrap_or(refresh_claims.nbf);
+
     // Save to update `updated_at`.
     device.save(conn).await?;
 
     let user = match User::find_by_uuid(&device.user_uuid, conn).await {
         None => err!("Impossible to find user"),
+        Some(user) if user.is_soft_deleted() => err!("User has been deleted"),
         Some(user) => user,
     };
 
-    let auth_tokens = match refresh_claims.sub {
+    let mut auth_tokens = match refresh_claims.sub {
         AuthMethod::Sso if CONFIG.sso_enabled() && CONFIG.sso_auth_only_not_session() => {
             AuthTokens::new(&device, &user, refresh_claims.sub, client_id)
         }
@@ -1236,6 +1831,133 @@ pub async fn refresh_tokens(
         AuthMethod::Password => AuthTokens::new(&device, &user, refresh_claims.sub, client_id),
         _ => err!("Invalid auth method, cannot refresh token"),
     };
+    if let Some(family) = family {
+        auth_tokens.set_family(&family);
+    }
+
+    auth_tokens.refresh_claims.auth_time = Some(auth_time);
+    auth_tokens.apply_session_lifetime(&user.uuid, conn).await;
+    if auth_tokens.refresh_claims.exp <= Utc::now().timestamp() {
+        err!("The session lifetime of your organization has been reached, login again")
+    }
 
     Ok((device, auth_tokens))
 }
+
+// Checks the refresh token against the family of the device, and moves the family to the next generation.
+// A token of an older generation was used before, so either the client or someone holding a copy of it has
+// a newer token. There is no way to tell which one is legitimate, so the family and the device sessions end.
+async fn rotate_refresh_family(
+    ip: &ClientIp,
+    refresh_claims: &RefreshJwtClaims,
+    device: &mut Device,
+    conn: &mut DbConn,
+) -> ApiResult<RefreshTokenFamily> {
+    let ip_str = ip.ip.to_string();
+    let Some(mut family) = RefreshTokenFamily::find_by_user_and_device(&device.user_uuid, &device.uuid, conn).await
+    else {
+        // The family was revoked, unless the token predates rotation
+        if refresh_claims.family.is_some() {
+            err!("Invalid refresh token")
+        }
+        let family = RefreshTokenFamily::new(device.user_uuid.clone(), device.uuid.clone(), ip_str);
+        family.save(conn).await?;
+        return Ok(family);
+    };
+
+    // Tokens without a family were issued before it was started, they count as the generation before the first.
+    // Unless they were issued after it, while rotation was disabled, then the family is started again.
+    let generation = match (&refresh_claims.family, refresh_claims.generation) {
+        (Some(family_uuid), Some(generation)) if family_uuid == &family.family_uuid => generation,
+        (None, _) if refresh_claims.nbf > family.rotated_at.and_utc().timestamp() => {
+            let family = RefreshTokenFamily::new(device.user_uuid.clone(), device.uuid.clone(), ip_str);
+            family.save(conn).await?;
+            return Ok(family);
+        }
+        (None, _) => -1,
+        // A token of a previous login on this device
+        _ => err!("Invalid refresh token"),
+    };
+
+    if generation == family.generation && family.advance(&ip_str, conn).await? {
+        return Ok(family);
+    }
+
+    // A concurrent refresh with the same token may have rotated the family already
+    if let Some(current) = RefreshTokenFamily::find_by_user_and_device(&device.user_uuid, &device.uuid, conn).await {
+        family = current;
+    }
+    let grace = TimeDelta::try_seconds(CONFIG.refresh_token_reuse_grace_seconds() as i64).unwrap_or_default();
+    if generation == family.generation - 1 && Utc::now().naive_utc() - family.rotated_at <= grace {
+        return Ok(family);
+    }
+
+    warn!(
+        "Reuse of a refresh token of generation {generation} for device {} of user {} from IP {}, the current \
+         generation is {}. Revoking the sessions of the device.",
+        device.uuid, device.user_uuid, ip.ip, family.generation
+    );
+    RefreshTokenFamily::delete_by_user_and_device(&device.user_uuid, &device.uuid, conn).await?;
+    device.reset_refresh_token();
+    device.save(conn).await?;
+    err!("Invalid refresh token")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_file_url_key() {
+        FILE_URL_KEY.get_or_init(|| hmac::Key::new(hmac::HMAC_SHA256, b"file url test key"));
+    }
+
+    #[test]
+    fn verify_file_url_accepts_signed_url() {
+        init_file_url_key();
+        let expires = Utc::now().timestamp() + 300;
+        let signature = sign_file_url("attachments/cipher/file", expires);
+        assert!(verify_file_url("attachments/cipher/file", expires, &signature));
+    }
+
+    #[test]
+    fn verify_file_url_rejects_expired_url() {
+        init_file_url_key();
+        let expires = Utc::now().timestamp() - 1;
+        let signature = sign_file_url("attachments/cipher/file", expires);
+        assert!(!verify_file_url("attachments/cipher/file", expires, &signature));
+    }
+
+    #[test]
+    fn verify_file_url_rejects_tampered_url() {
+        init_file_url_key();
+        let expires = Utc::now().timestamp() + 300;
+        let signature = sign_file_url("attachments/cipher/file", expires);
+        assert!(!verify_file_url("attachments/cipher/other", expires, &signature));
+        assert!(!verify_file_url("attachments/cipher/file", expires + 3600, &signature));
+        assert!(!verify_file_url("attachments/cipher/file", expires, "invalid"));
+    }
+
+    #[test]
+    fn send_visitor_hash_differs_per_send() {
+        SEND_ACCESS_KEY.get_or_init(|| hmac::Key::new(hmac::HMAC_SHA256, b"send access test key"));
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let first = SendId::from(String::from("a5a6a9c4-4d8f-4a0e-8a6d-6f4f5b8e1c01"));
+        let second = SendId::from(String::from("0f6c2a3e-9b1d-4c57-b0c2-1d9e8f7a6b02"));
+        assert_eq!(send_visitor_hash(&first, &ip), send_visitor_hash(&first, &ip));
+        assert_ne!(send_visitor_hash(&first, &ip), send_visitor_hash(&second, &ip));
+        // Without the server key the hash can't be computed from the public Send id
+        assert_ne!(send_visitor_hash(&first, &ip), crypto::hmac_sign(&first, &ip.to_string()));
+    }
+
+    #[test]
+    fn encrypt_at_rest_round_trip() {
+        DATA_KEY.get_or_init(|| aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_256_GCM, &[7; 32]).unwrap()));
+        let encrypted = encrypt_at_rest("master key", "user").unwrap();
+        assert!(is_encrypted_at_rest(&encrypted));
+        assert!(!encrypted.contains("master key"));
+        assert_eq!(decrypt_at_rest(&encrypted, "user").unwrap(), "master key");
+        // Bound to its row
+        assert!(decrypt_at_rest(&encrypted, "other user").is_err());
+        assert!(decrypt_at_rest("master key", "user").is_err());
+    }
+}
@@ -0,0 +1,182 @@
+//
+// The `vaultwarden admin ...` commands, which do what the admin panel and the custom API do without a running server.
+// The changes of the config are saved to the config file, a running server only uses them after a restart.
+//
+use argon2::{password_hash::SaltString, Algorithm::Argon2id, Argon2, ParamsBuilder, PasswordHasher, Version::V0x13};
+use chrono::Utc;
+
+use crate::{
+    api::invite_new_user,
+    crypto,
+    db::{
+        models::{DailyStat, OrgRiskScore, Organization, OrganizationId, Report, ReportHistory, User},
+        DbConn, DbPool,
+    },
+    error::Error,
+    CONFIG,
+};
+
+pub const ADMIN_HELP: &str = "\
+USAGE:
+    vaultwarden admin COMMAND
+
+COMMAND:
+    create-admin [--generate] [--preset {bitwarden|owasp}]
+                                       Set the ADMIN_TOKEN of the admin panel when none is set yet,
+                                       from a prompted password or a generated one with --generate
+    invite <EMAIL> [--org <ORG_ID>]    Invite a new user to the server, the organization customizes
+                                       the invitation text and its exposed passwords can block it
+    rotate-api-key                     Replace the x-vaultwarden-api key of the custom API
+    backup                             Create a backup of the SQLite database
+    recompute-reports                  Recalculate the report history of today, the risk scores
+                                       and the daily stats from the current reports
+
+";
+
+/// Returns the Argon2id hasher of the preset, `bitwarden` unless `owasp` is selected, and the name of the preset.
+pub fn argon2_hasher(preset: Option<&str>) -> (Argon2<'static>, &'static str) {
+    let mut argon2_params = ParamsBuilder::new();
+    let selected_preset = match preset {
+        Some("owasp") => {
+            argon2_params.m_cost(19456);
+            argon2_params.t_cost(2);
+            argon2_params.p_cost(1);
+            "owasp"
+        }
+        _ => {
+            // Bitwarden preset is the default
+            argon2_params.m_cost(65540);
+            argon2_params.t_cost(3);
+            argon2_params.p_cost(4);
+            "bitwarden"
+        }
+    };
+    (Argon2::new(Argon2id, V0x13, argon2_params.build().unwrap()), selected_preset)
+}
+
+pub async fn run_admin(mut pargs: pico_args::Arguments) -> Result<(), Error> {
+    let Some(command) = pargs.subcommand().unwrap_or_default() else {
+        print!("{ADMIN_HELP}");
+        return Ok(());
+    };
+
+    match command.as_str() {
+        "create-admin" => {
+            let generate = pargs.contains("--generate");
+            let preset: Option<String> = pargs.opt_value_from_str(["-p", "--preset"]).unwrap_or_default();
+            create_admin(generate, preset.as_deref()).await
+        }
+        "invite" => {
+            let org_id: Option<String> = pargs.opt_value_from_str("--org").unwrap_or_default();
+            let Ok(email) = pargs.free_from_str::<String>() else {
+                err_silent!("The email address of the user is missing")
+            };
+            invite(email, org_id.map(OrganizationId::from), &mut get_conn().await?).await
+        }
+        "rotate-api-key" => rotate_api_key().await,
+        "backup" => {
+            let backup_file = crate::backup_sqlite().await?;
+            println!("Backup to '{backup_file}' was successful");
+            Ok(())
+        }
+        "recompute-reports" => recompute_reports(&mut get_conn().await?).await,
+        _ => {
+            print!("{ADMIN_HELP}");
+            err_silent!(format!("Unknown admin command '{command}'"))
+        }
+    }
+}
+
+async fn get_conn() -> Result<DbConn, Error> {
+    Ok(DbPool::from_config()?.get().await?)
+}
+
+/// Sets the first admin token, an existing one is only changed from the admin panel or the environment.
+async fn create_admin(generate: bool, preset: Option<&str>) -> Result<(), Error> {
+    if CONFIG.is_admin_token_set() {
+        err_silent!("An ADMIN_TOKEN is already set, it can be changed in the admin panel")
+    }
+
+    let password = if generate {
+        crypto::get_random_string_alphanum(32)
+    } else {
+        let password = rpassword::prompt_password("Password: ")?;
+        if password.len() < 8 {
+            err_silent!("Password must contain at least 8 characters")
+        }
+        if password != rpassword::prompt_password("Confirm Password: ")? {
+            err_silent!("Passwords do not match")
+        }
+        password
+    };
+
+    let (argon2, selected_preset) = argon2_hasher(preset);
+    let salt = SaltString::encode_b64(&crypto::get_random_bytes::<32>()).unwrap();
+    let Ok(password_hash) = argon2.hash_password(password.as_bytes(), &salt) else {
+        err_silent!("Unable to generate Argon2id PHC hash")
+    };
+    CONFIG.set_admin_token(password_hash.to_string()).await?;
+
+    println!("The ADMIN_TOKEN was saved as an Argon2id PHC string using the '{selected_preset}' preset");
+    if generate {
+        println!("Log in to the admin panel with this password, it isn't shown again:\n\n{password}\n");
+    }
+    if CONFIG.disable_admin_token() {
+        println!("DISABLE_ADMIN_TOKEN is enabled, the admin panel doesn't ask for it");
+    }
+    Ok(())
+}
+
+/// The invite of `/custom/invite`, only for addresses without an account.
+async fn invite(email: String, org_id: Option<OrganizationId>, conn: &mut DbConn) -> Result<(), Error> {
+    if User::find_by_mail(&email, conn).await.is_some() {
+        err_silent!("User already exists")
+    }
+
+    let org = match org_id {
+        Some(org_id) => {
+            let Some(org) = Organization::find_by_uuid(&org_id, conn).await else {
+                err_silent!("Organization not found")
+            };
+            Some(org)
+        }
+        None => None,
+    };
+
+    let user = invite_new_user(email, org.as_ref(), conn).await?;
+    if CONFIG.mail_enabled() {
+        println!("Invited {} as user {}", user.email, user.uuid);
+    } else {
+        println!("Invited {} as user {}, mail is disabled so the user has to register", user.email, user.uuid);
+    }
+    Ok(())
+}
+
+/// Replaces the key of the custom API, the clients using the old key need the new one.
+async fn rotate_api_key() -> Result<(), Error> {
+    let key = crypto::generate_api_key();
+    CONFIG.set_x_vaultwarden_api(key.clone()).await?;
+    println!("The new x-vaultwarden-api key was saved, restart Vaultwarden to use it:\n\n{key}\n");
+    Ok(())
+}
+
+/// Recalculates what the scheduled jobs and the report uploads store, after the reports were changed in the database.
+async fn recompute_reports(conn: &mut DbConn) -> Result<(), Error> {
+    let reports = Report::find_all_orgs(conn).await;
+    for report in &reports {
+        if let Some(org_id) = &report.org_uuid {
+            ReportHistory::collect(org_id, conn).await.save(conn).await?;
+        }
+    }
+    println!("Recalculated the report history of today of {} organizations", reports.len());
+
+    let orgs = Organization::get_all(conn).await;
+    for org in &orgs {
+        OrgRiskScore::calculate(&org.uuid, conn).await.save(conn).await?;
+    }
+    println!("Recalculated the risk scores of {} organizations", orgs.len());
+
+    DailyStat::collect(Utc::now().date_naive(), conn).await.save(conn).await?;
+    println!("Recalculated the daily stats of today");
+    Ok(())
+}
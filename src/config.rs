@@ -12,7 +12,7 @@ use once_cell::sync::Lazy;
 use reqwest::Url;
 
 use crate::{
-    db::DbConnType,
+    db::{models::OrgPolicyType, DbConnType},
     error::Error,
     util::{get_env, get_env_bool, get_web_vault_version, is_valid_email, parse_experimental_client_feature_flags},
 };
@@ -277,6 +277,7 @@ macro_rules! make_config {
                     "domain_path",
                     "domain",
                     "helo_name",
+                    "ses_access_key_id",
                     "org_creation_users",
                     "signups_domains_whitelist",
                     "smtp_from",
@@ -408,12 +409,25 @@ make_config! {
         attachments_folder:     String, false,  auto,   |c| format!("{}/attachments", c.data_folder);
         /// Sends folder
         sends_folder:           String, false,  auto,   |c| format!("{}/sends", c.data_folder);
+        /// Events export folder |> Where expiring events are exported to, when `EVENTS_EXPORT_FORMAT` is set
+        events_export_folder:   String, false,  auto,   |c| format!("{}/events_export", c.data_folder);
+        /// Vault export folder |> Where the vault exports of single users made through the admin API are written to
+        vault_export_folder:    String, false,  auto,   |c| format!("{}/vault_exports", c.data_folder);
+        /// Replica folder |> A second storage, like a bucket in another region, the attachments and Sends are mirrored to by the file replication job.
+        /// They are kept in its `attachments` and `sends` subfolders. If unset, the files are not replicated
+        replica_folder:         String, false,  option;
         /// Temp folder |> Used for storing temporary file uploads
         tmp_folder:             String, false,  auto,   |c| format!("{}/tmp", c.data_folder);
         /// Templates folder
         templates_folder:       String, false,  auto,   |c| format!("{}/templates", c.data_folder);
+        /// ACME folder |> Where the ACME account and the certificate are stored, see `ACME_ENABLED`
+        acme_folder:            String, false,  auto,   |c| format!("{}/acme", c.data_folder);
         /// Session JWT key
         rsa_key_filename:       String, false,  auto,   |c| format!("{}/rsa_key", c.data_folder);
+        /// Report signing key |> The key and self-signed certificate (`.pem` and `.crt`) the report exports are signed with, created on the first start
+        report_signing_key_filename: String, false,  auto,   |c| format!("{}/report_signing_key", c.data_folder);
+        /// Data encryption key |> The key the master keys of the built-in Key Connector and the bodies of the queued mails are encrypted with in the database, created on the first start. Without it the stored keys can't be read, back it up apart from the database
+        data_key_filename:      String, false,  auto,   |c| format!("{}/data_key", c.data_folder);
         /// Web vault folder
         web_vault_folder:       String, false,  def,    "web-vault/".to_string();
     },
@@ -432,6 +446,21 @@ make_config! {
         push_installation_id:   Pass,   false,  def,    String::new();
         /// Installation key |> The installation key from https://bitwarden.com/host
         push_installation_key:  Pass,   false,  def,    String::new();
+        /// Push mode |> `relay` delivers the push notifications through the Bitwarden push relay, `direct` sends them straight
+        /// to FCM and APNs with the credentials below, so no metadata leaves the server. The direct mode only works with mobile apps built for these credentials
+        push_mode:              String, false,  def,    "relay".to_string();
+        /// FCM service account |> Path to the JSON key of the Google service account used to push to Android devices in the direct mode
+        push_fcm_service_account: String, false, option;
+        /// APNs key |> Path to the `.p8` APNs authentication key used to push to iOS devices in the direct mode
+        push_apns_key:          String, false,  option;
+        /// APNs key id |> The key id of the APNs authentication key
+        push_apns_key_id:       String, false,  def,    String::new();
+        /// APNs team id |> The id of the Apple developer team the APNs authentication key belongs to
+        push_apns_team_id:      String, false,  def,    String::new();
+        /// APNs topic |> The bundle id of the iOS app
+        push_apns_topic:        String, false,  def,    "com.8bit.bitwarden".to_string();
+        /// APNs sandbox |> Send to the APNs development environment, for development builds of the iOS app
+        push_apns_sandbox:      bool,   false,  def,    false;
     },
     jobs {
         /// Job scheduler poll interval |> How often the job scheduler thread checks for jobs to run.
@@ -464,6 +493,57 @@ make_config! {
         /// Purge incomplete SSO nonce. |> Cron schedule of the job that cleans leftover nonce in db due to incomplete SSO login.
         /// Defaults to daily. Set blank to disable this job.
         purge_incomplete_sso_nonce: String, false,  def,   "0 20 0 * * *".to_string();
+        /// Report evaluation schedule |> Cron schedule of the job that evaluates the organization exposure reports against the configured threshold.
+        /// Defaults to daily. Set blank to disable this job. Also without REPORT_POLICY_THRESHOLD set, this job will not start.
+        report_evaluation_schedule: String, false,  def,   "0 15 0 * * *".to_string();
+        /// Deleted user purge schedule |> Cron schedule of the job that permanently deletes users whose deletion grace period has passed.
+        /// Defaults to daily. Set blank to disable this job.
+        user_purge_schedule:    String, false,  def,    "0 25 0 * * *".to_string();
+        /// Archived organization purge schedule |> Cron schedule of the job that deletes the organizations archived longer than `ORG_ARCHIVE_RETENTION_DAYS`.
+        /// Defaults to daily. Set blank to disable this job.
+        org_archive_purge_schedule: String, false, def, "0 27 0 * * *".to_string();
+        /// Login event cleanup schedule |> Cron schedule of the job that cleans old entries from the login audit table.
+        /// Defaults to daily. Set blank to disable this job. Also without LOGIN_EVENTS_DAYS_RETAIN set, this job will not start.
+        login_event_cleanup_schedule: String, false, def, "0 30 0 * * *".to_string();
+        /// Replication cleanup schedule |> Cron schedule of the job that cleans old entries from the replication change stream.
+        /// Defaults to daily. Set blank to disable this job.
+        replication_cleanup_schedule: String, false, def, "0 35 0 * * *".to_string();
+        /// LDAP sync schedule |> Cron schedule of the job that syncs the users of the LDAP directory.
+        /// Defaults to hourly. Set blank to disable this job. Also without the LDAP sync enabled, this job will not start.
+        ldap_sync_schedule:     String, false,  def,    "0 5 * * * *".to_string();
+        /// ACME renewal schedule |> Cron schedule of the job that renews the certificate obtained with ACME once it is due.
+        /// Defaults to daily. Set blank to disable this job. Also without ACME enabled, this job will not start.
+        acme_renew_schedule:    String, false,  def,    "0 25 4 * * *".to_string();
+        /// Breach check schedule |> Cron schedule of the job that checks the members of the organizations with the breach monitoring policy against HIBP.
+        /// Defaults to daily. Set blank to disable this job. Also without HIBP_API_KEY set, this job will not start.
+        breach_check_schedule:  String, false,  def,    "0 20 3 * * *".to_string();
+        /// Two-step login deadline schedule |> Cron schedule of the job that warns and revokes the members without two-step login of organizations with a deadline policy.
+        /// Defaults to daily. Set blank to disable this job.
+        two_factor_deadline_schedule: String, false, def, "0 40 0 * * *".to_string();
+        /// Organization domain verification schedule |> Cron schedule of the job that checks the DNS TXT record of the domains added by organizations during their first three days.
+        /// Defaults to hourly. Set blank to disable this job.
+        org_domain_verification_schedule: String, false, def, "0 50 * * * *".to_string();
+        /// Daily stats schedule |> Cron schedule of the job that stores a snapshot of the instance totals, charted on the diagnostics page of the admin panel.
+        /// Defaults to daily, just before midnight. Set blank to disable this job.
+        daily_stats_schedule:   String, false,  def,    "0 55 23 * * *".to_string();
+        /// Mail queue schedule |> Cron schedule of the job that sends the queued mails, see `MAIL_QUEUE_ENABLED`.
+        /// Defaults to every minute. Set blank to disable this job.
+        mail_queue_schedule:    String, false,  def,    "15 * * * * *".to_string();
+        /// Item history purge schedule |> Cron schedule of the job that removes the item revisions older than `CIPHER_HISTORY_DAYS`, or the retention of the organization item history policy.
+        /// Defaults to daily. Set blank to disable this job.
+        cipher_history_purge_schedule: String, false, def, "0 45 0 * * *".to_string();
+        /// Resumable upload purge schedule |> Cron schedule of the job that removes the resumable attachment uploads past `RESUMABLE_UPLOAD_EXPIRATION_HOURS` and their received chunks.
+        /// Defaults to hourly. Set blank to disable this job.
+        resumable_upload_purge_schedule: String, false, def, "0 20 * * * *".to_string();
+        /// Icon cache eviction schedule |> Cron schedule of the job that removes the least recently served icons while the icon cache is larger than `ICON_CACHE_MAX_SIZE`.
+        /// Defaults to every 15 minutes. Set blank to disable this job. Also without ICON_CACHE_MAX_SIZE set, this job will not start.
+        icon_cache_eviction_schedule: String, false, def, "0 */15 * * * *".to_string();
+        /// File replication schedule |> Cron schedule of the job that copies the attachments and Sends missing from `REPLICA_FOLDER`.
+        /// Defaults to every 10 minutes. Set blank to disable this job. Also without REPLICA_FOLDER set, this job will not start.
+        file_replication_schedule: String, false, def, "0 */10 * * * *".to_string();
+        /// Risk score schedule |> Cron schedule of the job that calculates the daily risk score of every organization, from its exposure report, the trend of the report, the two-step login of its members and the age of the report.
+        /// Defaults to daily. Set blank to disable this job, the score is then only calculated when it is requested the first time.
+        risk_score_schedule: String, false, def, "0 5 1 * * *".to_string();
     },
 
     /// General settings
@@ -484,21 +564,61 @@ make_config! {
         /// This setting applies globally to all users. To control this on a per-org basis instead, use the "Disable Send" org policy.
         sends_allowed:          bool,   true,   def,    true;
 
+        /// Maintenance mode |> One of `off`, `read_only` (syncing works, changes are refused), `auth_only` (existing sessions keep working, no new logins)
+        /// or `lockout` (only the admin panel and the custom API are available). Can also be switched via `/custom/maintenance`.
+        maintenance_mode:       String, true,   def,    "off".to_string();
+        /// Maintenance message |> Shown to the clients instead of the default message of the current maintenance mode.
+        maintenance_message:    String, true,   option;
+
         /// HIBP Api Key |> HaveIBeenPwned API Key, request it here: https://haveibeenpwned.com/API/Key
         hibp_api_key:           Pass,   true,   option;
+        /// HIBP request delay (ms) |> Delay between the requests of the breach check job, the HIBP rate limit depends on the subscription of the API key.
+        hibp_request_delay:     u64,    true,   def,    6_500;
 
         /// Per-user attachment storage limit (KB) |> Max kilobytes of attachment storage allowed per user. When this limit is reached, the user will not be allowed to upload further attachments.
         user_attachment_limit:  i64,    true,   option;
         /// Per-organization attachment storage limit (KB) |> Max kilobytes of attachment storage allowed per org. When this limit is reached, org members will not be allowed to upload further attachments for ciphers owned by that org.
         org_attachment_limit:   i64,    true,   option;
+        /// File download URL lifetime (seconds) |> Attachments and Send files are downloaded with signed URLs which don't need the access token, so they can be served through a caching proxy.
+        /// The expiration is rounded up so the URL of a file stays the same for a while, they are valid between one and two times this lifetime.
+        file_download_url_ttl:  u64,    true,   def,    300;
+        /// Resumable upload chunk size (MB) |> Max size of one chunk of a resumable attachment upload, see `/ciphers/<id>/attachment/<id>/upload`.
+        resumable_upload_chunk_size_mb: u64, true, def, 50;
+        /// Resumable upload expiration (hours) |> Resumable attachment uploads which didn't receive a chunk for this long are removed, including their attachment.
+        resumable_upload_expiration_hours: i64, true, def, 24;
+        /// Virus scanner URL |> Uploaded attachments and Send files are scanned before they are stored, either by clamd (`clamd://host:3310`)
+        /// or by an ICAP server (`icap://host:1344/service`). Rejected uploads are recorded in the quarantine. Note that clients encrypt the files before uploading them.
+        virus_scan_url:         String, true,   option;
+        /// Virus scan fail-open |> Accept uploads without a scan when the virus scanner can't be reached or times out, instead of rejecting them.
+        virus_scan_fail_open:   bool,   true,   def,    false;
+        /// Virus scan timeout (seconds) |> Max time a scan of a single upload may take.
+        virus_scan_timeout:     u64,    true,   def,    30;
+        /// Per-organization seat limit |> Max number of members per organization, revoked members don't take a seat. Invitations beyond this limit are refused.
+        org_max_seats:          i32,    true,   option;
         /// Per-user send storage limit (KB) |> Max kilobytes of sends storage allowed per user. When this limit is reached, the user will not be allowed to upload further sends.
         user_send_limit:   i64,    true,   option;
+        /// Send webhook URL |> A JSON payload is posted to this URL the first time a Send is retrieved and when a Send expires.
+        send_webhook_url:       String, true,   option;
+        /// Policy violation webhook URL |> A JSON payload is posted to this URL when an organization policy revokes, removes or blocks a member.
+        policy_violation_webhook_url: String, true, option;
+
+        /// Background import threshold |> Imports with more items than this are processed in the background.
+        /// The import request returns right away and its progress can be queried via /api/imports/<job_id>.
+        import_background_threshold: usize, true, def, 1_000;
 
         /// Trash auto-delete days |> Number of days to wait before auto-deleting a trashed item.
-        /// If unset, trashed items are not auto-deleted. This setting applies globally, so make
-        /// sure to inform all users of any changes to this setting.
+        /// If unset, trashed items are not auto-deleted. Organization owners and users can override it
+        /// for their items, so make sure to inform all users of any changes to this setting.
         trash_auto_delete_days: i64,    true,   option;
 
+        /// Item history revisions |> Number of previous revisions kept per item, so overwritten items can be restored.
+        /// 0 disables the history. Organizations can override it with their item history policy.
+        cipher_history_revisions: i64,  true,   def,    10;
+
+        /// Item history retention days |> Number of days previous revisions of items are kept.
+        /// If unset, revisions are only removed once there are more than `CIPHER_HISTORY_REVISIONS`.
+        cipher_history_days:    i64,    true,   option;
+
         /// Incomplete 2FA time limit |> Number of minutes to wait before a 2FA-enabled login is
         /// considered incomplete, resulting in an email notification. An incomplete 2FA login is one
         /// where the correct master password was provided but the required 2FA step was not completed,
@@ -506,6 +626,19 @@ make_config! {
         /// This setting applies globally to all users.
         incomplete_2fa_time_limit: i64, true,   def,    3;
 
+        /// New device emails |> Send an email when a user logs in from a device which wasn't used before.
+        /// The email has a link to sign out all devices and to require a new master password. Users can be
+        /// excluded through the custom API. When `REQUIRE_DEVICE_EMAIL` is enabled the email is always sent.
+        new_device_email:       bool,   true,   def,    true;
+
+        /// Rotate refresh tokens |> Every refresh hands out a new refresh token and the previous one stops working.
+        /// The tokens of a device since its last login form a family, when an older token is used again the whole
+        /// family is revoked and the device has to log in again.
+        refresh_token_rotation: bool,   true,   def,    true;
+        /// Refresh token reuse grace |> Number of seconds after a rotation during which the previous refresh token
+        /// is still accepted, for clients that refresh twice at once or didn't get the response.
+        refresh_token_reuse_grace_seconds: u64, true, def, 30;
+
         /// Disable icon downloads |> Set to true to disable icon downloading in the internal icon service.
         /// This still serves existing icons from $ICON_CACHE_FOLDER, without generating any external
         /// network requests. $ICON_CACHE_TTL must also be set to 0; otherwise, the existing icons
@@ -520,7 +653,13 @@ make_config! {
         signups_verify_resend_time: u64, true,  def,    3_600;
         /// If signups require email verification, limit how many emails are automatically sent when login is attempted (0 means no limit)
         signups_verify_resend_limit: u32, true, def,    6;
-        /// Email domain whitelist |> Allow signups only from this list of comma-separated domains, even when signups are otherwise disabled
+        /// Block unverified accounts |> Tokens aren't issued anymore to the accounts whose email address was never verified,
+        /// once the grace period after their creation passed. Organizations can also require this of their members with a policy.
+        email_verify_enforce:   bool,   true,   def,    false;
+        /// Grace period to verify the email address (days) |> Used by the setting above, and by the policies which don't set their own
+        email_verify_grace_days: u32,   true,   def,    7;
+        /// Email domain whitelist |> Allow signups only from this list of comma-separated domains, even when signups are otherwise disabled.
+        /// Deprecated, the signup rules of the admin API take precedence and this is only used for domains without a matching rule.
         signups_domains_whitelist: String, true, def,   String::new();
         /// Enable event logging |> Enables event logging for organizations.
         org_events_enabled:     bool,   false,  def,    false;
@@ -531,17 +670,36 @@ make_config! {
         invitations_allowed:    bool,   true,   def,    true;
         /// Disable organization deletion |> Controls whether organization owners can delete their organizations. This setting applies globally to all organizations.
         disable_org_deletion:   bool,   true,   def,    false;
+        /// User deletion grace period (days) |> Deleted users are kept for this many days and can be restored until they are purged.
+        /// Users who deleted their own account restore it by logging in. Set to 0 to delete users immediately.
+        user_deletion_grace_days: i64,  true,   def,    30;
+        /// Archived organization retention (days) |> Archived organizations are deleted with their items once they were archived this many days.
+        /// Until then they can be unarchived from the admin panel. Set to 0 to keep archived organizations indefinitely.
+        org_archive_retention_days: i64, true,  def,    90;
         /// Invitation token expiration time (in hours) |> The number of hours after which an organization invite token, emergency access invite token,
         /// email verification token and deletion request token will expire (must be at least 1)
         invitation_expiration_hours: u32, false, def, 120;
         /// Enable emergency access |> Controls whether users can enable emergency access to their accounts. This setting applies globally to all users.
         emergency_access_allowed:    bool,   true,   def,    true;
+        /// Allow direct sharing |> Controls whether users can share their personal items read-only with other users of this server, without an organization.
+        direct_sharing_allowed:  bool,   true,   def,    true;
+        /// Allow direct sharing with any user |> Lets users share with any user of this server. Otherwise they can only share with the users who are in an organization with them or already share items with them, so the registered emails can't be looked up with it.
+        direct_sharing_any_user: bool,   true,   def,    false;
+        /// Allow personal API tokens |> Controls whether users can create API tokens for their scripts, which can only read the vault or create Sends.
+        /// These tokens are used without logging in, so also without two-step login.
+        personal_api_tokens_allowed: bool, true, def,   false;
         /// Allow email change |> Controls whether users can change their email. This setting applies globally to all users.
         email_change_allowed:    bool,   true,   def,    true;
         /// Password iterations |> Number of server-side passwords hashing iterations for the password hash.
         /// The default for new users. If changed, it will be updated during login for existing users.
         password_iterations:    i32,    true,   def,    600_000;
-        /// Allow password hints |> Controls whether users can set or show password hints. This setting applies globally to all users.
+        /// Minimum client KDF iterations |> Users with PBKDF2 below this number of iterations are targeted by the KDF migration campaign
+        kdf_min_pbkdf2_iterations: i32, true,   def,    600_000;
+        /// Require Argon2id KDF |> Also target users of the KDF migration campaign who don't use Argon2id yet
+        kdf_require_argon2id:   bool,   true,   def,    false;
+        /// Enforce KDF upgrades |> Users flagged with `/custom/user/<id>/kdf/upgrade` can't refresh their session or log in with their API key until they changed the KDF settings, the clients show the error. The password login still succeeds, the change needs it
+        kdf_upgrade_enforced:   bool,   true,   def,    false;
+        /// Allow password hints |> Controls whether users can set or show password hints. This setting applies globally to all users. The hints stored before are kept until they are removed with `/admin/password-hints/clear`.
         password_hints_allowed: bool,   true,   def,    true;
         /// Show password hint (Know the risks!) |> Controls whether a password hint should be shown directly in the web page
         /// if SMTP service is not configured and password hints are allowed. Not recommended for publicly-accessible instances
@@ -553,12 +711,43 @@ make_config! {
         
         /// X-Vaultwarden-API |> Authenticate via x-vaultwarden-api header.
         x_vaultwarden_api:      Pass,   true,   option;
+        /// Replication token |> Token a standby instance uses to pull the change stream, via the x-vaultwarden-replication header.
+        /// Changes to users, organizations, ciphers and reports are only recorded while this is set.
+        replication_token:      Pass,   true,   option;
+
+        /// Report policy threshold |> Exposed password count above which an organization is considered at risk.
+        /// If unset, organization policies are never enabled automatically based on the reports.
+        report_policy_threshold: i32,   true,   option;
+        /// Report policy threshold days |> Number of consecutive days the exposed count of an organization needs to be above the threshold
+        /// before the configured policies are enabled automatically (must be at least 1)
+        report_policy_threshold_days: i64, true, def,   3;
+        /// Report auto-enabled policies |> Comma-separated list of organization policies to enable when the threshold is exceeded.
+        /// Supported values are: TwoFactorAuthentication, PersonalOwnership, DisableSend and RemoveUnlockWithPin
+        report_policy_auto_enable: String, true, def,   "TwoFactorAuthentication".to_string();
+        /// Report invite threshold |> Exposed password count above which new invites to an organization are blocked, until the exposed passwords are remediated.
+        /// If unset, invites are never blocked based on the reports.
+        report_invite_threshold: i32,   true,   option;
+        /// Report invite owner override |> Allow owners to still invite new members while invites are blocked by the reports
+        report_invite_owner_override: bool, true, def,  false;
+        /// Report delta threshold |> Increase of the exposed password count of an organization, between two reports, above which the owners are notified by email.
+        /// If unset, the owners are not notified based on the absolute increase.
+        report_delta_threshold: i32,    true,   option;
+        /// Report delta percentage |> Increase of the exposed password count of an organization, in percent of the previous count, above which the owners are notified by email.
+        /// If unset, the owners are not notified based on the relative increase. The owners are notified at most once a day per organization.
+        report_delta_percentage: i32,   true,   option;
 
         /// Invitation organization name |> Name shown in the invitation emails that don't come from a specific organization
         invitation_org_name:    String, true,   def,    "Vaultwarden".to_string();
 
         /// Events days retain |> Number of days to retain events stored in the database. If unset, events are kept indefinitely.
+        /// Organization owners can set their own retention, which can't be shorter than this one.
         events_days_retain:     i64,    false,   option;
+        /// Events export format |> Export the events to `EVENTS_EXPORT_FOLDER` before they are deleted, as `csv` or `jsonl`. If unset, expiring events are not exported.
+        events_export_format:   String, false,   option;
+        /// Login events days retain |> Number of days to retain the login attempts stored in the database. If unset, they are kept indefinitely.
+        login_events_days_retain: i64,  false,   option;
+        /// Replication changes days retain |> Number of days to retain the replication change stream. A standby that falls further behind needs a full resync.
+        replication_changes_days_retain: i64, false, def, 7;
     },
 
     /// Advanced settings
@@ -568,6 +757,9 @@ make_config! {
         ip_header:              String, true,   def,    "X-Real-IP".to_string();
         /// Internal IP header property, used to avoid recomputing each time
         _ip_header_enabled:     bool,   false,  generated,    |c| &c.ip_header.trim().to_lowercase() != "none";
+        /// Client country header |> Header set by a reverse proxy or CDN containing the country of the client, for example `CF-IPCountry`.
+        /// Stored with the login attempts of the users. If not set, no country is recorded
+        ip_country_header:      String, true,   option;
         /// Icon service |> The predefined icon services are: internal, bitwarden, duckduckgo, google.
         /// To specify a custom icon service, set a URL template with exactly one instance of `{}`,
         /// which is replaced with the domain. For example: `https://icon.example.com/domain/{}`.
@@ -589,8 +781,24 @@ make_config! {
         icon_cache_ttl:         u64,    true,   def,    2_592_000;
         /// Negative icon cache expiry |> Number of seconds before trying to download an icon that failed again.
         icon_cache_negttl:      u64,    true,   def,    259_200;
+        /// Icon cache max size (MB) |> Maximum disk size of the icon cache, the least recently served icons are removed by the eviction job once it is larger. Unlimited when unset.
+        icon_cache_max_size:    u64,    true,   option;
         /// Icon download timeout |> Number of seconds when to stop attempting to download an icon.
         icon_download_timeout:  u64,    true,   def,    10;
+        /// Icon thumbnail size |> Downloaded icons wider or higher than this number of pixels are scaled down. Set to 0 to keep the original size
+        icon_thumbnail_size:    u32,    true,   def,    256;
+        /// Icon thumbnail format |> The image format of the scaled down icons, either `webp` or `png`
+        icon_thumbnail_format:  String, true,   def,    "webp".to_string();
+        /// Icon DNS over HTTPS |> Resolve the domains of icons with DNS over HTTPS, using `cloudflare`, `google`, `quad9`
+        /// or a custom server in the `<ip>[,<ip>]@<tls name>` format. A change requires a restart
+        icon_dns_over_https:    String, false,  option;
+        /// Icon blocked IP ranges |> Comma separated IP ranges in CIDR notation which the icon service never connects to,
+        /// also not after a redirect. This applies even when `http_request_block_non_global_ips` is disabled
+        icon_blocked_ip_ranges: String, true,   def,    "0.0.0.0/8,10.0.0.0/8,100.64.0.0/10,127.0.0.0/8,169.254.0.0/16,172.16.0.0/12,192.168.0.0/16,::1/128,fc00::/7,fe80::/10".to_string();
+        /// Icon circuit breaker threshold |> Number of consecutive failed requests to a host after which the icon service stops contacting it for a while. Set to 0 to disable
+        icon_circuit_breaker_threshold: u32, true, def, 5;
+        /// Icon circuit breaker cooldown |> Number of seconds before a host is contacted again after its circuit breaker opened
+        icon_circuit_breaker_cooldown:  u64, true, def, 300;
 
         /// [Deprecated] Icon blacklist Regex |> Use `http_request_block_regex` instead
         icon_blacklist_regex:   String, false,   option;
@@ -604,10 +812,18 @@ make_config! {
         /// Useful to secure your internal environment: See https://en.wikipedia.org/wiki/Reserved_IP_addresses for a list of IPs which it will block
         http_request_block_non_global_ips:  bool,   true,   auto, |c| c.icon_blacklist_non_global_ips;
 
+        /// Shutdown grace period |> Number of seconds a shutdown waits for the open requests to finish, new connections are refused meanwhile.
+        /// The queued mails and webhooks are then finished within the same period
+        shutdown_grace_period:  u32,    false,  def,    30;
+
         /// Disable Two-Factor remember |> Enabling this would force the users to use a second factor to login every time.
         /// Note that the checkbox would still be present, but ignored.
         disable_2fa_remember:   bool,   true,   def,    false;
 
+        /// Rotate the 2FA recovery code after each use |> Logging in with the recovery code then only replaces it with a new one,
+        /// the two-step login providers of the user stay enabled. The new code has to be viewed again in the web vault.
+        rotate_2fa_recovery_code: bool, true,   def,    false;
+
         /// Disable authenticator time drifted codes to be valid |> Enabling this only allows the current TOTP code to be valid
         /// TOTP codes of the previous and next 30 seconds will be invalid.
         authenticator_disable_time_drift: bool, true, def, false;
@@ -626,6 +842,8 @@ make_config! {
         extended_logging:       bool,   false,  def,    true;
         /// Log timestamp format
         log_timestamp_format:   String, true,   def,    "%Y-%m-%d %H:%M:%S.%3f".to_string();
+        /// Log format |> Either `text` or `json`, which writes every log line as a JSON object with the timestamp, level, target and message
+        log_format:             String, false,  def,    "text".to_string();
         /// Enable the log to output to Syslog
         use_syslog:             bool,   false,  def,    false;
         /// Log file path
@@ -656,6 +874,13 @@ make_config! {
         /// Database connection init |> SQL statements to run when creating a new database connection, mainly useful for connection-scoped pragmas. If empty, a database-specific default is used.
         database_conn_init:     String, false,  def,    String::new();
 
+        /// Slow query threshold |> Database calls taking longer than this amount of milliseconds are logged as warnings, together with the route they were made for. Set to 0 to disable
+        database_slow_query_ms: u64,    true,   def,    0;
+
+        /// Check the database integrity on startup |> Logs the rows pointing to users, organizations or items which don't exist anymore. Nothing is changed,
+        /// they can be removed with the `check-db --repair` command or from `/admin/db-integrity`
+        db_integrity_check_on_startup: bool, false, def, false;
+
         /// Bypass admin page security (Know the risks!) |> Disables the Admin Token for the admin page so you may use your own auth in-front
         disable_admin_token:    bool,   false,  def,    false;
 
@@ -670,17 +895,47 @@ make_config! {
         /// Max burst size for login requests |> Allow a burst of requests of up to this size, while maintaining the average indicated by `login_ratelimit_seconds`. Note that this applies to both the login and the 2FA, so it's recommended to allow a burst size of at least 2
         login_ratelimit_max_burst:     u32, false, def, 10;
 
+        /// Failed logins before delays |> After this many consecutive failed logins of an account, every further attempt has to wait, starting at 1 second and doubling up to `login_delay_max_seconds`. Set to 0 to disable the delays
+        login_delay_after_failures:    u32, true, def, 3;
+        /// Max delay between failed logins |> The longest wait in seconds between two login attempts of an account with failed logins
+        login_delay_max_seconds:       u64, true, def, 60;
+        /// Failed logins before lockout |> After this many consecutive failed logins, including wrong two-step login codes, the account is locked for `login_lockout_minutes`. Set to 0 to disable
+        login_lockout_failures:        u32, true, def, 10;
+        /// Failed logins from an IP before lockout |> After this many consecutive failed logins from an IP address, for any account, the IP address is locked for `login_lockout_minutes`. Set to 0 to disable
+        login_lockout_ip_failures:     u32, true, def, 50;
+        /// Lockout minutes |> How long an account or IP address stays locked. The failed logins are also forgotten after this many minutes without another failure
+        login_lockout_minutes:         u64, true, def, 15;
+        /// Failed logins before notification |> Mail the user once the account has this many consecutive failed logins. Set to 0 to disable
+        login_failure_notify_after:    u32, true, def, 5;
+
         /// Seconds between admin login requests |> Number of seconds, on average, between admin requests from the same IP address before rate limiting kicks in
         admin_ratelimit_seconds:       u64, false, def, 300;
         /// Max burst size for admin login requests |> Allow a burst of requests of up to this size, while maintaining the average indicated by `admin_ratelimit_seconds`
         admin_ratelimit_max_burst:     u32, false, def, 3;
 
+        /// Seconds between registration requests |> Number of seconds, on average, between registration requests from the same IP address before rate limiting kicks in
+        register_ratelimit_seconds:    u64, false, def, 60;
+        /// Max burst size for registration requests |> Allow a burst of requests of up to this size, while maintaining the average indicated by `register_ratelimit_seconds`
+        register_ratelimit_max_burst:  u32, false, def, 5;
+
+        /// Seconds between fingerprint requests |> Number of seconds, on average, between fingerprint phrase requests from the same IP address before rate limiting kicks in
+        fingerprint_ratelimit_seconds:    u64, false, def, 60;
+        /// Max burst size for fingerprint requests |> Allow a burst of requests of up to this size, while maintaining the average indicated by `fingerprint_ratelimit_seconds`
+        fingerprint_ratelimit_max_burst:  u32, false, def, 10;
+
         /// Admin session lifetime |> Set the lifetime of admin sessions to this value (in minutes).
         admin_session_lifetime:        i64, true,  def, 20;
+        /// Admin two-step login |> Require a TOTP or WebAuthn second factor after the admin token, set up on the first login after enabling it.
+        /// To reset the second factors, remove the rows of the `admin_twofactor` table. The admin API then only accepts the `x-vaultwarden-api` key
+        admin_two_factor:              bool, false, def, false;
 
         /// Enable groups (BETA!) (Know the risks!) |> Enables groups support for organizations (Currently contains known issues!).
         org_groups_enabled:            bool, false, def, false;
 
+        /// Enable Secrets Manager |> Enables projects, secrets and machine accounts for organizations, used by the `bws` CLI and the Secrets Manager SDK.
+        /// Only Owners and Admins can manage them, machine accounts get access per project.
+        secrets_manager_enabled:       bool, true,  def, false;
+
         /// Increase note size limit (Know the risks!) |> Sets the secure note size limit to 100_000 instead of the default 10_000.
         /// WARNING: This could cause issues with clients. Also exports will not work on Bitwarden servers!
         increase_note_size_limit:      bool,  true,  def, false;
@@ -727,6 +982,10 @@ make_config! {
         sso_client_cache_expiration:    u64,    true,   def,    0;
         /// Log all tokens |> `LOG_LEVEL=debug` or `LOG_LEVEL=info,vaultwarden::sso=debug` is required
         sso_debug_tokens:               bool,   true,   def,    false;
+        /// Key Connector |> Allow organizations to enable the Key Connector policy, their members then unlock with SSO instead of a master password
+        sso_key_connector_enabled:      bool,   true,   def,    false;
+        /// Key Connector URL |> Generated from Domain, which uses the built-in key store. Set it to use an external Bitwarden Key Connector instead.
+        sso_key_connector_url:          String, true,  generated, |c| format!("{}/api/key-connector", c.domain);
     },
 
     /// Yubikey settings
@@ -761,6 +1020,8 @@ make_config! {
     smtp: _enable_smtp {
         /// Enabled
         _enable_smtp:                  bool,   true,   def,     true;
+        /// Mail provider |> ("smtp", "ses", "sendgrid") How the mails are delivered. "smtp" uses the SMTP settings below, or the sendmail command with USE_SENDMAIL. "ses" and "sendgrid" use the HTTP API of AWS SES or SendGrid, the SMTP_FROM settings are used for all of them.
+        mail_provider:                 String, true,   def,     "smtp".to_string();
         /// Use Sendmail |> Whether to send mail via the `sendmail` command
         use_sendmail:                  bool,   true,   def,     false;
         /// Sendmail Command |> Which sendmail command to use. The one found in the $PATH is used if not specified.
@@ -791,6 +1052,10 @@ make_config! {
         helo_name:                     String, true,   option;
         /// Embed images as email attachments.
         smtp_embed_images:             bool, true, def, true;
+        /// Queue mails |> Store the mails in the database and send them from a background job, which retries them when sending fails. The test email is always sent right away.
+        mail_queue_enabled:            bool,   true,   def,     true;
+        /// Mail queue max attempts |> Number of attempts after which a queued mail is marked as failed, it can then be retried from the diagnostics page of the admin panel
+        mail_queue_max_attempts:       i32,    true,   def,     8;
         /// _smtp_img_src
         _smtp_img_src:                 String, false, generated, |c| generate_smtp_img_src(c.smtp_embed_images, &c.domain);
         /// Enable SMTP debugging (Know the risks!) |> DANGEROUS: Enabling this will output very detailed SMTP messages. This could contain sensitive information like passwords and usernames! Only enable this during troubleshooting!
@@ -799,12 +1064,24 @@ make_config! {
         smtp_accept_invalid_certs:     bool,   true,   def,     false;
         /// Accept Invalid Hostnames (Know the risks!) |> DANGEROUS: Allow invalid hostnames. This option introduces significant vulnerabilities to man-in-the-middle attacks!
         smtp_accept_invalid_hostnames: bool,   true,   def,     false;
+        /// AWS SES region |> The region of the SES API used with MAIL_PROVIDER=ses, for example "eu-west-1"
+        ses_region:                    String, true,   option;
+        /// AWS SES access key id |> The access key of an IAM user allowed to call `ses:SendRawEmail`
+        ses_access_key_id:             String, true,   option;
+        /// AWS SES secret access key
+        ses_secret_access_key:         Pass,   true,   option;
+        /// AWS SES configuration set |> The configuration set sent with the mails, its event destination can publish the bounces to the bounce webhook through SNS
+        ses_configuration_set:         String, true,   option;
+        /// SendGrid API key |> The key used with MAIL_PROVIDER=sendgrid, it needs the "Mail Send" permission
+        sendgrid_api_key:              Pass,   true,   option;
+        /// Mail webhook token |> Enables the bounce webhooks `/mail/bounces`, `/mail/bounces/ses` and `/mail/bounces/sendgrid`, which need this token in the `token` query parameter. The addresses which permanently bounce or complain are marked as undeliverable, and no more mails are sent to them until the address is changed or the admin clears the mark.
+        mail_webhook_token:            Pass,   true,   option;
     },
 
     /// Email 2FA Settings
     email_2fa: _enable_email_2fa {
         /// Enabled |> Disabling will prevent users from setting up new email 2FA and using existing email 2FA configured
-        _enable_email_2fa:      bool,   true,   auto,    |c| c._enable_smtp && (c.smtp_host.is_some() || c.use_sendmail);
+        _enable_email_2fa:      bool,   true,   auto,    |c| c._enable_smtp && (c.smtp_host.is_some() || c.use_sendmail || c.mail_provider != "smtp");
         /// Email token size |> Number of digits in an email 2FA token (min: 6, max: 255). Note that the Bitwarden clients are hardcoded to mention 6 digit codes regardless of this setting.
         email_token_size:       u8,     true,   def,      6;
         /// Token expiration time |> Maximum time in seconds a token is valid. The time the user has to open email client and copy token.
@@ -816,6 +1093,49 @@ make_config! {
         /// Auto-enable 2FA (Know the risks!) |> Automatically setup email 2FA as fallback provider when needed
         email_2fa_auto_fallback: bool,  true,   def,      false;
     },
+
+    /// LDAP directory sync settings
+    ldap_sync {
+        /// Enabled |> Periodically sync the users of an LDAP directory
+        ldap_sync_enabled:      bool,   true,   def,     false;
+        /// Dry run |> Only report the changes the sync would make, without inviting or revoking any users
+        ldap_sync_dry_run:      bool,   true,   def,     false;
+        /// URL |> The ldap:// or ldaps:// URL of the directory server
+        ldap_url:               String, true,   option;
+        /// Bind DN |> The DN used to bind to the directory, leave empty for an anonymous bind
+        ldap_bind_dn:           String, true,   option;
+        /// Bind password
+        ldap_bind_password:     Pass,   true,   option;
+        /// Search base |> The DN below which the users are searched
+        ldap_search_base:       String, true,   def,     String::new();
+        /// User filter |> The LDAP filter selecting the users to sync
+        ldap_user_filter:       String, true,   def,     "(&(objectClass=person)(mail=*))".to_string();
+        /// Email attribute |> The attribute containing the email address of a user
+        ldap_email_attribute:   String, true,   def,     "mail".to_string();
+        /// Organization |> Id of the organization whose members are revoked when they are removed from the directory
+        ldap_sync_org:          String, true,   option;
+        /// Max revoked percentage |> A sync which would revoke more than this percentage of the synced members revokes none of them
+        /// and reports an error instead. A single member can always be revoked, an empty directory result never revokes any
+        ldap_sync_max_revoke_percent: u8, true, def,     10;
+    },
+
+    /// ACME settings
+    acme {
+        /// Enabled |> Obtain and renew the TLS certificate of the server with ACME, like from Let's Encrypt, instead of setting `ROCKET_TLS`
+        acme_enabled:           bool,   false,  def,    false;
+        /// Domain |> The domain name of the certificate, defaults to the host of `DOMAIN`
+        acme_domain:            String, false,  auto,   |c| Url::parse(&c.domain).ok().and_then(|u| u.host_str().map(str::to_string)).unwrap_or_default();
+        /// Contact email |> The certificate authority sends the expiry notices to this address
+        acme_email:             String, false,  option;
+        /// Directory URL |> The ACME directory of the certificate authority
+        acme_directory_url:     String, false,  def,    "https://acme-v02.api.letsencrypt.org/directory".to_string();
+        /// Challenge |> `http-01` is answered on `ACME_HTTP_PORT`, `tls-alpn-01` on the port of the server itself
+        acme_challenge:         String, false,  def,    "http-01".to_string();
+        /// HTTP-01 port |> The port the HTTP-01 challenges are answered on, it needs to be reachable as port 80 of the domain
+        acme_http_port:         u16,    false,  def,    80;
+        /// Renewal days |> Renew the certificate once it expires within this many days
+        acme_renew_days:        i64,    false,  def,    30;
+    },
 }
 
 fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
@@ -834,6 +1154,10 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
         err!("PASSWORD_ITERATIONS should be at least 100000 or higher. The default is 600000!");
     }
 
+    if cfg.kdf_min_pbkdf2_iterations < 100_000 {
+        err!("KDF_MIN_PBKDF2_ITERATIONS should be at least 100000, lower values are rejected by the clients anyway");
+    }
+
     let limit = 256;
     if cfg.database_max_conns < 1 || cfg.database_max_conns > limit {
         err!(format!("`DATABASE_MAX_CONNS` contains an invalid value. Ensure it is between 1 and {limit}.",));
@@ -886,7 +1210,15 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
         }
     }
 
-    if cfg.push_enabled && (cfg.push_installation_id == String::new() || cfg.push_installation_key == String::new()) {
+    match cfg.push_mode.as_str() {
+        "relay" | "direct" => (),
+        _ => err!("`PUSH_MODE` must be either `relay` or `direct`"),
+    }
+
+    if cfg.push_enabled
+        && cfg.push_mode == "relay"
+        && (cfg.push_installation_id == String::new() || cfg.push_installation_key == String::new())
+    {
         err!(
             "Misconfigured Push Notification service\n\
             ########################################################################################\n\
@@ -917,6 +1249,16 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
         }
     }
 
+    if cfg.push_enabled && cfg.push_mode == "direct" {
+        if cfg.push_fcm_service_account.is_none() && cfg.push_apns_key.is_none() {
+            err!("The direct `PUSH_MODE` requires `PUSH_FCM_SERVICE_ACCOUNT` and/or `PUSH_APNS_KEY` to be set")
+        }
+
+        if cfg.push_apns_key.is_some() && (cfg.push_apns_key_id.is_empty() || cfg.push_apns_team_id.is_empty()) {
+            err!("`PUSH_APNS_KEY` requires `PUSH_APNS_KEY_ID` and `PUSH_APNS_TEAM_ID` to be set")
+        }
+    }
+
     // Server (v2025.6.2): https://github.com/bitwarden/server/blob/d094be3267f2030bd0dc62106bc6871cf82682f5/src/Core/Constants.cs#L103
     // Client (web-v2025.6.1): https://github.com/bitwarden/clients/blob/747c2fd6a1c348a57a76e4a7de8128466ffd3c01/libs/common/src/enums/feature-flag.enum.ts#L12
     // Android (v2025.6.0): https://github.com/bitwarden/android/blob/b5b022caaad33390c31b3021b2c1205925b0e1a2/app/src/main/kotlin/com/x8bit/bitwarden/data/platform/manager/model/FlagKey.kt#L22
@@ -959,12 +1301,76 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
         }
     }
 
+    if !(30..=86_400).contains(&cfg.file_download_url_ttl) {
+        err!("`FILE_DOWNLOAD_URL_TTL` must be between 30 and 86400 seconds");
+    }
+
+    if !(1..=525).contains(&cfg.resumable_upload_chunk_size_mb) {
+        err!("`RESUMABLE_UPLOAD_CHUNK_SIZE_MB` must be between 1 and 525");
+    }
+
+    if cfg.resumable_upload_expiration_hours < 1 {
+        err!("`RESUMABLE_UPLOAD_EXPIRATION_HOURS` has a minimum of 1 hour");
+    }
+
+    if !cfg.icon_cache_eviction_schedule.is_empty() && cfg.icon_cache_eviction_schedule.parse::<Schedule>().is_err() {
+        err!("`ICON_CACHE_EVICTION_SCHEDULE` is not a valid cron expression")
+    }
+
+    if cfg.icon_cache_max_size == Some(0) {
+        err!("`ICON_CACHE_MAX_SIZE` has a minimum of 1 MB, leave it unset for an unlimited icon cache")
+    }
+
+    if !cfg.resumable_upload_purge_schedule.is_empty()
+        && cfg.resumable_upload_purge_schedule.parse::<Schedule>().is_err()
+    {
+        err!("`RESUMABLE_UPLOAD_PURGE_SCHEDULE` is not a valid cron expression")
+    }
+
+    if !cfg.file_replication_schedule.is_empty() && cfg.file_replication_schedule.parse::<Schedule>().is_err() {
+        err!("`FILE_REPLICATION_SCHEDULE` is not a valid cron expression")
+    }
+
+    if !cfg.risk_score_schedule.is_empty() && cfg.risk_score_schedule.parse::<Schedule>().is_err() {
+        err!("`RISK_SCORE_SCHEDULE` is not a valid cron expression")
+    }
+
+    if let Some(ref replica_folder) = cfg.replica_folder {
+        let replica_folder = replica_folder.trim_end_matches('/');
+        if [&cfg.data_folder, &cfg.attachments_folder, &cfg.sends_folder]
+            .iter()
+            .any(|folder| folder.trim_end_matches('/') == replica_folder)
+        {
+            err!("`REPLICA_FOLDER` has to be a different location than the data, attachments and sends folders")
+        }
+    }
+
+    if cfg.org_max_seats.is_some_and(|seats| seats < 1) {
+        err!("`ORG_MAX_SEATS` must be at least 1");
+    }
+
     if let Some(limit) = cfg.user_send_limit {
         if !(0i64..=MAX_FILESIZE_KB).contains(&limit) {
             err!("`USER_SEND_LIMIT` is out of bounds");
         }
     }
 
+    if !["off", "read_only", "auth_only", "lockout"].contains(&cfg.maintenance_mode.as_str()) {
+        err!("`MAINTENANCE_MODE` must be one of `off`, `read_only`, `auth_only` or `lockout`");
+    }
+
+    if let Some(url) = &cfg.send_webhook_url {
+        if !(url.starts_with("http://") || url.starts_with("https://")) || Url::parse(url).is_err() {
+            err!("`SEND_WEBHOOK_URL` must be a valid http(s) URL");
+        }
+    }
+
+    if let Some(url) = &cfg.policy_violation_webhook_url {
+        if !(url.starts_with("http://") || url.starts_with("https://")) || Url::parse(url).is_err() {
+            err!("`POLICY_VIOLATION_WEBHOOK_URL` must be a valid http(s) URL");
+        }
+    }
+
     if cfg._enable_duo
         && (cfg.duo_host.is_some() || cfg.duo_ikey.is_some() || cfg.duo_skey.is_some())
         && !(cfg.duo_host.is_some() && cfg.duo_ikey.is_some() && cfg.duo_skey.is_some())
@@ -982,6 +1388,15 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
         validate_sso_master_password_policy(&cfg.sso_master_password_policy)?;
     }
 
+    if cfg.sso_key_connector_enabled {
+        if !cfg.sso_enabled {
+            err!("`SSO_ENABLED` must be set for Key Connector support")
+        }
+        if let Err(e) = Url::parse(&cfg.sso_key_connector_url) {
+            err!(format!("Invalid SSO_KEY_CONNECTOR_URL ({}): {e}", cfg.sso_key_connector_url))
+        }
+    }
+
     if cfg._enable_yubico {
         if cfg.yubico_client_id.is_some() != cfg.yubico_secret_key.is_some() {
             err!("Both `YUBICO_CLIENT_ID` and `YUBICO_SECRET_KEY` must be set for Yubikey OTP support")
@@ -995,7 +1410,101 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
         }
     }
 
+    if cfg.ldap_sync_enabled {
+        match &cfg.ldap_url {
+            Some(url) if url.starts_with("ldap://") || url.starts_with("ldaps://") => (),
+            Some(_) => err!("`LDAP_URL` must start with ldap:// or ldaps://"),
+            None => err!("`LDAP_URL` must be set when the LDAP sync is enabled"),
+        }
+        if cfg.ldap_bind_dn.is_some() != cfg.ldap_bind_password.is_some() {
+            err!("Both `LDAP_BIND_DN` and `LDAP_BIND_PASSWORD` need to be set for a non-anonymous bind")
+        }
+        if cfg.ldap_email_attribute.trim().is_empty() {
+            err!("`LDAP_EMAIL_ATTRIBUTE` can't be empty")
+        }
+        if cfg.ldap_sync_max_revoke_percent > 100 {
+            err!("`LDAP_SYNC_MAX_REVOKE_PERCENT` can't be more than 100")
+        }
+    }
+
+    if cfg.acme_enabled {
+        if cfg.acme_domain.is_empty() || cfg.acme_domain.parse::<std::net::IpAddr>().is_ok() {
+            err!("`ACME_DOMAIN` needs to be a domain name, ACME can't issue certificates for IP addresses")
+        }
+        if !cfg.acme_directory_url.starts_with("https://") {
+            err!("`ACME_DIRECTORY_URL` must start with https://")
+        }
+        if !matches!(cfg.acme_challenge.as_str(), "http-01" | "tls-alpn-01") {
+            err!("`ACME_CHALLENGE` needs to be either http-01 or tls-alpn-01")
+        }
+        if !(1..=60).contains(&cfg.acme_renew_days) {
+            err!("`ACME_RENEW_DAYS` needs to be between 1 and 60")
+        }
+        if cfg.acme_folder.starts_with("s3://") {
+            err!("`ACME_FOLDER` must be a local path, the certificate is loaded from it by the server")
+        }
+    }
+
+    if !cfg.acme_renew_schedule.is_empty() && cfg.acme_renew_schedule.parse::<Schedule>().is_err() {
+        err!("`ACME_RENEW_SCHEDULE` is not a valid cron expression")
+    }
+
+    if !cfg.ldap_sync_schedule.is_empty() && cfg.ldap_sync_schedule.parse::<Schedule>().is_err() {
+        err!("`LDAP_SYNC_SCHEDULE` is not a valid cron expression")
+    }
+
+    if let Some(virus_scan_url) = &cfg.virus_scan_url {
+        match Url::parse(virus_scan_url) {
+            Ok(url) if matches!(url.scheme(), "clamd" | "icap") && url.host_str().is_some() => {}
+            _ => err!("`VIRUS_SCAN_URL` must be a `clamd://host:port` or `icap://host:port/service` URL"),
+        }
+    }
+
+    if !matches!(cfg.log_format.as_str(), "text" | "json") {
+        err!("`LOG_FORMAT` must be either `text` or `json`")
+    }
+
+    if !cfg.breach_check_schedule.is_empty() && cfg.breach_check_schedule.parse::<Schedule>().is_err() {
+        err!("`BREACH_CHECK_SCHEDULE` is not a valid cron expression")
+    }
+
+    if !cfg.two_factor_deadline_schedule.is_empty() && cfg.two_factor_deadline_schedule.parse::<Schedule>().is_err() {
+        err!("`TWO_FACTOR_DEADLINE_SCHEDULE` is not a valid cron expression")
+    }
+
+    if !cfg.org_domain_verification_schedule.is_empty()
+        && cfg.org_domain_verification_schedule.parse::<Schedule>().is_err()
+    {
+        err!("`ORG_DOMAIN_VERIFICATION_SCHEDULE` is not a valid cron expression")
+    }
+
+    if !cfg.daily_stats_schedule.is_empty() && cfg.daily_stats_schedule.parse::<Schedule>().is_err() {
+        err!("`DAILY_STATS_SCHEDULE` is not a valid cron expression")
+    }
+
     if cfg._enable_smtp {
+        match cfg.mail_provider.as_str() {
+            "smtp" => (),
+            "ses" => {
+                if cfg.ses_region.is_none() || cfg.ses_access_key_id.is_none() || cfg.ses_secret_access_key.is_none() {
+                    err!("`SES_REGION`, `SES_ACCESS_KEY_ID` and `SES_SECRET_ACCESS_KEY` need to be set with `MAIL_PROVIDER=ses`")
+                }
+                if cfg.ses_region.as_ref().is_some_and(|r| !r.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')) {
+                    err!("`SES_REGION` is not a valid AWS region")
+                }
+            }
+            "sendgrid" => {
+                if cfg.sendgrid_api_key.is_none() {
+                    err!("`SENDGRID_API_KEY` needs to be set with `MAIL_PROVIDER=sendgrid`")
+                }
+            }
+            _ => err!("`MAIL_PROVIDER` is invalid. It needs to be one of the following options: smtp, ses or sendgrid"),
+        }
+
+        if cfg.mail_provider != "smtp" && cfg.smtp_from.is_empty() {
+            err!(format!("`SMTP_FROM` needs to be set with `MAIL_PROVIDER={}`", cfg.mail_provider))
+        }
+
         match cfg.smtp_security.as_str() {
             "off" | "starttls" | "force_tls" => (),
             _ => err!(
@@ -1003,7 +1512,7 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
             ),
         }
 
-        if cfg.use_sendmail {
+        if cfg.use_sendmail && cfg.mail_provider == "smtp" {
             let command = cfg.sendmail_command.clone().unwrap_or_else(|| format!("sendmail{EXE_SUFFIX}"));
 
             let mut path = std::path::PathBuf::from(&command);
@@ -1036,7 +1545,7 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
                     }
                 }
             }
-        } else {
+        } else if cfg.mail_provider == "smtp" {
             if cfg.smtp_host.is_some() == cfg.smtp_from.is_empty() {
                 err!("Both `SMTP_HOST` and `SMTP_FROM` need to be set for email support without `USE_SENDMAIL`")
             }
@@ -1046,16 +1555,26 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
             }
         }
 
-        if (cfg.smtp_host.is_some() || cfg.use_sendmail) && !is_valid_email(&cfg.smtp_from) {
+        if (cfg.smtp_host.is_some() || cfg.use_sendmail || cfg.mail_provider != "smtp")
+            && !is_valid_email(&cfg.smtp_from)
+        {
             err!(format!("SMTP_FROM '{}' is not a valid email address", cfg.smtp_from))
         }
 
         if cfg._enable_email_2fa && cfg.email_token_size < 6 {
             err!("`EMAIL_TOKEN_SIZE` has a minimum size of 6")
         }
+
+        if cfg.mail_queue_max_attempts < 1 {
+            err!("`MAIL_QUEUE_MAX_ATTEMPTS` has to be at least 1")
+        }
+    }
+
+    if cfg.cipher_history_revisions < 0 || cfg.cipher_history_days.is_some_and(|d| d < 1) {
+        err!("`CIPHER_HISTORY_REVISIONS` can't be negative and `CIPHER_HISTORY_DAYS` has to be at least 1")
     }
 
-    if cfg._enable_email_2fa && !(cfg.smtp_host.is_some() || cfg.use_sendmail) {
+    if cfg._enable_email_2fa && !(cfg.smtp_host.is_some() || cfg.use_sendmail || cfg.mail_provider != "smtp") {
         err!("To enable email 2FA, a mail transport must be configured")
     }
 
@@ -1097,6 +1616,21 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
         _ => err!("Only HTTP 301/302 and 307/308 redirects are supported"),
     }
 
+    match cfg.icon_thumbnail_format.as_str() {
+        "webp" | "png" => (),
+        _ => err!("`ICON_THUMBNAIL_FORMAT` must be either `webp` or `png`"),
+    }
+
+    if let Some(ref dns_over_https) = cfg.icon_dns_over_https {
+        if let Err(e) = crate::http_client::parse_dns_over_https(dns_over_https) {
+            err!(format!("`ICON_DNS_OVER_HTTPS` is invalid: {e}"))
+        }
+    }
+
+    if let Err(e) = crate::http_client::parse_ip_ranges(&cfg.icon_blocked_ip_ranges) {
+        err!(format!("`ICON_BLOCKED_IP_RANGES` is invalid: {e}"))
+    }
+
     if cfg.invitation_expiration_hours < 1 {
         err!("`INVITATION_EXPIRATION_HOURS` has a minimum duration of 1 hour")
     }
@@ -1130,10 +1664,80 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
         err!("`EVENT_CLEANUP_SCHEDULE` is not a valid cron expression")
     }
 
+    if let Some(format) = &cfg.events_export_format {
+        if format != "csv" && format != "jsonl" {
+            err!("`EVENTS_EXPORT_FORMAT` has to be either `csv` or `jsonl`")
+        }
+    }
+
+    if !cfg.login_event_cleanup_schedule.is_empty() && cfg.login_event_cleanup_schedule.parse::<Schedule>().is_err() {
+        err!("`LOGIN_EVENT_CLEANUP_SCHEDULE` is not a valid cron expression")
+    }
+
+    if !cfg.replication_cleanup_schedule.is_empty() && cfg.replication_cleanup_schedule.parse::<Schedule>().is_err() {
+        err!("`REPLICATION_CLEANUP_SCHEDULE` is not a valid cron expression")
+    }
+
+    if cfg.replication_changes_days_retain < 1 {
+        err!("`REPLICATION_CHANGES_DAYS_RETAIN` has a minimum of 1 day")
+    }
+
     if !cfg.auth_request_purge_schedule.is_empty() && cfg.auth_request_purge_schedule.parse::<Schedule>().is_err() {
         err!("`AUTH_REQUEST_PURGE_SCHEDULE` is not a valid cron expression")
     }
 
+    if !cfg.report_evaluation_schedule.is_empty() && cfg.report_evaluation_schedule.parse::<Schedule>().is_err() {
+        err!("`REPORT_EVALUATION_SCHEDULE` is not a valid cron expression")
+    }
+
+    if !cfg.user_purge_schedule.is_empty() && cfg.user_purge_schedule.parse::<Schedule>().is_err() {
+        err!("`USER_PURGE_SCHEDULE` is not a valid cron expression")
+    }
+
+    if cfg.user_deletion_grace_days < 0 {
+        err!("`USER_DELETION_GRACE_DAYS` can't be negative")
+    }
+
+    if !cfg.org_archive_purge_schedule.is_empty() && cfg.org_archive_purge_schedule.parse::<Schedule>().is_err() {
+        err!("`ORG_ARCHIVE_PURGE_SCHEDULE` is not a valid cron expression")
+    }
+
+    if cfg.org_archive_retention_days < 0 {
+        err!("`ORG_ARCHIVE_RETENTION_DAYS` can't be negative")
+    }
+
+    if cfg.report_policy_threshold.is_some_and(|t| t < 0) {
+        err!("`REPORT_POLICY_THRESHOLD` can't be negative")
+    }
+
+    if cfg.report_invite_threshold.is_some_and(|t| t < 0) {
+        err!("`REPORT_INVITE_THRESHOLD` can't be negative")
+    }
+
+    if cfg.report_delta_threshold.is_some_and(|t| t < 0) {
+        err!("`REPORT_DELTA_THRESHOLD` can't be negative")
+    }
+
+    if cfg.report_delta_percentage.is_some_and(|p| p < 0) {
+        err!("`REPORT_DELTA_PERCENTAGE` can't be negative")
+    }
+
+    if cfg.report_policy_threshold_days < 1 {
+        err!("`REPORT_POLICY_THRESHOLD_DAYS` has a minimum duration of 1 day")
+    }
+
+    for policy in cfg.report_policy_auto_enable.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match OrgPolicyType::from_str(policy) {
+            Some(
+                OrgPolicyType::TwoFactorAuthentication
+                | OrgPolicyType::PersonalOwnership
+                | OrgPolicyType::DisableSend
+                | OrgPolicyType::RemoveUnlockWithPin,
+            ) => (),
+            _ => err!(format!("`REPORT_POLICY_AUTO_ENABLE` contains an unsupported policy `{policy}`")),
+        }
+    }
+
     if !cfg.disable_admin_token {
         match cfg.admin_token.as_ref() {
             Some(t) if t.starts_with("$argon2") => {
@@ -1354,7 +1958,22 @@ pub enum PathType {
     IconCache,
     Attachments,
     Sends,
+    EventsExport,
+    VaultExport,
     RsaKey,
+    ReportSigningKey,
+    DataKey,
+    ReplicaAttachments,
+    ReplicaSends,
+}
+
+/// The outcome of `Config::check_config`.
+pub struct ConfigCheck {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    /// The SMTP host and port of the candidate config, if SMTP is configured
+    pub smtp_server: Option<(String, u16)>,
+    pub smtp_timeout: u64,
 }
 
 impl Config {
@@ -1432,6 +2051,68 @@ impl Config {
         self.update_config(builder, false).await
     }
 
+    /// Validates a candidate config the same way `update_config` would, without applying or saving it.
+    /// Every key is parsed on its own, so all type errors are reported at once.
+    /// The semantic validation stops at the first error, like it does when saving the config.
+    pub fn check_config(&self, candidate: serde_json::Map<String, serde_json::Value>) -> ConfigCheck {
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+
+        let mut builder = ConfigBuilder::default();
+        for (key, value) in candidate {
+            if value.is_null() {
+                continue;
+            }
+            let name = key.to_uppercase();
+            let entry = serde_json::Value::Object(serde_json::Map::from_iter([(key, value)]));
+            let parsed = match serde_json::from_value::<ConfigBuilder>(entry) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    errors.push(format!("`{name}`: {e}"));
+                    continue;
+                }
+            };
+            let mut editable = parsed.clone();
+            editable.clear_non_editable();
+
+            let is_empty =
+                |b: &ConfigBuilder| serde_json::to_value(b).is_ok_and(|v| v.as_object().is_some_and(|o| o.is_empty()));
+            if is_empty(&parsed) {
+                warnings.push(format!("`{name}` is not a known setting and will be ignored"));
+            } else if is_empty(&editable) {
+                warnings.push(format!("`{name}` can't be changed from the admin panel and will be ignored"));
+            } else {
+                builder = builder.merge(&editable, false, &mut Vec::new());
+            }
+        }
+
+        let mut overrides = Vec::new();
+        let config = {
+            let env = &self.inner.read().unwrap()._env;
+            env.merge(&builder, false, &mut overrides).build()
+        };
+        if let Err(e) = validate_config(&config) {
+            errors.push(format!("{e:?}"));
+        }
+
+        if !overrides.is_empty() {
+            warnings.push(format!("These environment variables would be overridden: {}", overrides.join(", ")));
+        }
+        if !config.disable_admin_token && config.admin_token.as_ref().is_none_or(|t| t.trim().is_empty()) {
+            warnings.push(String::from("`ADMIN_TOKEN` is not set, the admin panel would be disabled"));
+        }
+        if config.domain.starts_with("http://") {
+            warnings.push(String::from("`DOMAIN` doesn't use https, most clients require a secure connection"));
+        }
+
+        ConfigCheck {
+            errors,
+            warnings,
+            smtp_server: config.smtp_host.clone().map(|host| (host, config.smtp_port)),
+            smtp_timeout: config.smtp_timeout,
+        }
+    }
+
     /// Tests whether an email's domain is allowed. A domain is allowed if it
     /// is in signups_domains_whitelist, or if no whitelist is set (so there
     /// are no domain restrictions in effect).
@@ -1459,11 +2140,13 @@ impl Config {
     }
 
     // The registration link should be hidden if
-    //  - Signup is not allowed and email whitelist is empty unless mail is disabled and invitations are allowed
+    //  - Signup is not allowed, email whitelist is empty and no signup rule allows a domain,
+    //    unless mail is disabled and invitations are allowed
     //  - The SSO is activated and password login is disabled.
     pub fn is_signup_disabled(&self) -> bool {
         (!self.signups_allowed()
             && self.signups_domains_whitelist().is_empty()
+            && !crate::db::models::SignupRule::any_allows_signups()
             && (self.mail_enabled() || !self.invitations_allowed()))
             || (self.sso_enabled() && self.sso_only())
     }
@@ -1510,7 +2193,7 @@ impl Config {
     }
     pub fn mail_enabled(&self) -> bool {
         let inner = &self.inner.read().unwrap().config;
-        inner._enable_smtp && (inner.smtp_host.is_some() || inner.use_sendmail)
+        inner._enable_smtp && (inner.smtp_host.is_some() || inner.use_sendmail || inner.mail_provider != "smtp")
     }
 
     pub async fn get_duo_akey(&self) -> String {
@@ -1530,6 +2213,34 @@ impl Config {
         }
     }
 
+    /// Switches the maintenance mode at runtime, this is saved to the config file like the changes from the admin panel.
+    pub async fn set_maintenance_mode(&self, mode: String, message: Option<String>) -> Result<(), Error> {
+        let builder = ConfigBuilder {
+            maintenance_mode: Some(mode),
+            maintenance_message: Some(message.unwrap_or_default()),
+            ..Default::default()
+        };
+        self.update_config_partial(builder).await
+    }
+
+    /// Sets the admin token from `vaultwarden admin create-admin`, this is saved like the changes from the admin panel.
+    pub async fn set_admin_token(&self, token: String) -> Result<(), Error> {
+        let builder = ConfigBuilder {
+            admin_token: Some(token),
+            ..Default::default()
+        };
+        self.update_config_partial(builder).await
+    }
+
+    /// Replaces the key of the custom API from `vaultwarden admin rotate-api-key`.
+    pub async fn set_x_vaultwarden_api(&self, key: String) -> Result<(), Error> {
+        let builder = ConfigBuilder {
+            x_vaultwarden_api: Some(key),
+            ..Default::default()
+        };
+        self.update_config_partial(builder).await
+    }
+
     pub fn is_webauthn_2fa_supported(&self) -> bool {
         Url::parse(&self.domain()).expect("DOMAIN not a valid URL").domain().is_some()
     }
@@ -1547,17 +2258,40 @@ impl Config {
             PathType::IconCache => self.icon_cache_folder(),
             PathType::Attachments => self.attachments_folder(),
             PathType::Sends => self.sends_folder(),
+            PathType::EventsExport => self.events_export_folder(),
+            PathType::VaultExport => self.vault_export_folder(),
             PathType::RsaKey => std::path::Path::new(&self.rsa_key_filename())
                 .parent()
                 .ok_or_else(|| std::io::Error::other("Failed to get directory of RSA key file"))?
                 .to_str()
                 .ok_or_else(|| std::io::Error::other("Failed to convert RSA key file directory to UTF-8 string"))?
                 .to_string(),
+            PathType::ReportSigningKey => std::path::Path::new(&self.report_signing_key_filename())
+                .parent()
+                .ok_or_else(|| std::io::Error::other("Failed to get directory of report signing key file"))?
+                .to_str()
+                .ok_or_else(|| std::io::Error::other("Failed to convert report signing key directory to UTF-8 string"))?
+                .to_string(),
+            PathType::DataKey => std::path::Path::new(&self.data_key_filename())
+                .parent()
+                .ok_or_else(|| std::io::Error::other("Failed to get directory of data key file"))?
+                .to_str()
+                .ok_or_else(|| std::io::Error::other("Failed to convert data key directory to UTF-8 string"))?
+                .to_string(),
+            PathType::ReplicaAttachments => self.replica_subfolder("attachments")?,
+            PathType::ReplicaSends => self.replica_subfolder("sends")?,
         };
 
         opendal_operator_for_path(&path)
     }
 
+    fn replica_subfolder(&self, subfolder: &str) -> Result<String, Error> {
+        let Some(replica_folder) = self.replica_folder() else {
+            err!("`REPLICA_FOLDER` is not configured")
+        };
+        Ok(format!("{}/{subfolder}", replica_folder.trim_end_matches('/')))
+    }
+
     pub fn render_template<T: serde::ser::Serialize>(&self, name: &str, data: &T) -> Result<String, Error> {
         if self.reload_templates() {
             warn!("RELOADING TEMPLATES");
@@ -1605,6 +2339,10 @@ impl Config {
     pub fn sso_authorize_extra_params_vec(&self) -> Vec<(String, String)> {
         url::form_urlencoded::parse(self.sso_authorize_extra_params().as_bytes()).into_owned().collect()
     }
+
+    pub fn report_policy_auto_enable_vec(&self) -> Vec<OrgPolicyType> {
+        self.report_policy_auto_enable().split(',').filter_map(|p| OrgPolicyType::from_str(p.trim())).collect()
+    }
 }
 
 use handlebars::{
@@ -1650,6 +2388,7 @@ where
     reg!("email/change_email_existing", ".html");
     reg!("email/change_email", ".html");
     reg!("email/delete_account", ".html");
+    reg!("email/delete_account_scheduled", ".html");
     reg!("email/emergency_access_invite_accepted", ".html");
     reg!("email/emergency_access_invite_confirmed", ".html");
     reg!("email/emergency_access_recovery_approved", ".html");
@@ -1658,21 +2397,32 @@ where
     reg!("email/emergency_access_recovery_reminder", ".html");
     reg!("email/emergency_access_recovery_timed_out", ".html");
     reg!("email/incomplete_2fa_login", ".html");
+    reg!("email/failed_logins", ".html");
     reg!("email/invite_accepted", ".html");
     reg!("email/invite_confirmed", ".html");
     reg!("email/new_device_logged_in", ".html");
     reg!("email/protected_action", ".html");
+    reg!("email/report_policies_enabled", ".html");
+    reg!("email/report_delta", ".html");
+    reg!("email/member_offboarded", ".html");
+    reg!("email/org_ownership_transferred", ".html");
     reg!("email/pw_hint_none", ".html");
     reg!("email/pw_hint_some", ".html");
     reg!("email/register_verify_email", ".html");
     reg!("email/send_2fa_removed_from_org", ".html");
+    reg!("email/send_2fa_deadline_warning", ".html");
+    reg!("email/sensitive_action", ".html");
+    reg!("email/data_report", ".html");
+    reg!("email/kdf_migration", ".html");
     reg!("email/send_emergency_access_invite", ".html");
     reg!("email/send_org_invite", ".html");
+    reg!("email/send_provider_invite", ".html");
     reg!("email/send_admin_invite", ".html");
     reg!("email/send_single_org_removed_from_org", ".html");
     reg!("email/smtp_test", ".html");
     reg!("email/sso_change_email", ".html");
     reg!("email/twofactor_email", ".html");
+    reg!("email/verify_alias", ".html");
     reg!("email/verify_email", ".html");
     reg!("email/welcome_must_verify", ".html");
     reg!("email/welcome", ".html");
@@ -1683,8 +2433,10 @@ where
     reg!("admin/users");
     reg!("admin/organizations");
     reg!("admin/diagnostics");
+    reg!("admin/two_factor");
 
     reg!("404");
+    reg!("device_deny");
 
     reg!(@withfallback "scss/vaultwarden.scss");
     reg!("scss/user.vaultwarden.scss");
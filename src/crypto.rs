@@ -4,7 +4,8 @@
 use std::num::NonZeroU32;
 
 use data_encoding::{Encoding, HEXLOWER};
-use ring::{digest, hmac, pbkdf2};
+use once_cell::sync::Lazy;
+use ring::{digest, hkdf, hmac, pbkdf2};
 
 const DIGEST_ALG: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
 const OUTPUT_LEN: usize = digest::SHA256_OUTPUT_LEN;
@@ -33,6 +34,102 @@ pub fn hmac_sign(key: &str, data: &str) -> String {
     HEXLOWER.encode(signature.as_ref())
 }
 
+//
+// Encrypted strings
+//
+/// The encryption and MAC keys of a Bitwarden symmetric key, see `derive_password_key`.
+pub struct SymmetricKey {
+    enc_key: [u8; 32],
+    mac_key: [u8; 32],
+}
+
+/// Derives the key of a password protected export like the Bitwarden clients do, PBKDF2-SHA256 of the password
+/// stretched into an encryption and a MAC key with HKDF-Expand.
+pub fn derive_password_key(password: &str, salt: &str, iterations: NonZeroU32) -> SymmetricKey {
+    use ring::hkdf;
+
+    let mut master_key = [0u8; OUTPUT_LEN];
+    pbkdf2::derive(DIGEST_ALG, iterations, salt.as_bytes(), password.as_bytes(), &mut master_key);
+
+    let prk = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, &master_key);
+    let expand = |info: &[u8]| {
+        let mut key = [0u8; 32];
+        prk.expand(&[info], hkdf::HKDF_SHA256).and_then(|okm| okm.fill(&mut key)).expect("HKDF output length is valid");
+        key
+    };
+
+    SymmetricKey {
+        enc_key: expand(b"enc"),
+        mac_key: expand(b"mac"),
+    }
+}
+
+/// Encrypts the data into an `AesCbc256_HmacSha256_B64` (type 2) encrypted string, which the clients can decrypt.
+pub fn encrypt_to_enc_string(key: &SymmetricKey, data: &[u8]) -> Result<String, crate::Error> {
+    use data_encoding::BASE64;
+    use openssl::symm::{encrypt, Cipher};
+
+    let iv = get_random_bytes::<16>();
+    let cipher_text = encrypt(Cipher::aes_256_cbc(), &key.enc_key, Some(&iv), data)?;
+
+    let mac_key = hmac::Key::new(hmac::HMAC_SHA256, &key.mac_key);
+    let mut ctx = hmac::Context::with_key(&mac_key);
+    ctx.update(&iv);
+    ctx.update(&cipher_text);
+    let mac = ctx.sign();
+
+    Ok(format!("2.{}|{}|{}", BASE64.encode(&iv), BASE64.encode(&cipher_text), BASE64.encode(mac.as_ref())))
+}
+
+//
+// Hashing
+//
+/// Returns the hex encoded SHA-256 digest, used to store secrets which only need to be compared.
+pub fn sha256_hex(data: &str) -> String {
+    HEXLOWER.encode(digest::digest(&digest::SHA256, data.as_bytes()).as_ref())
+}
+
+//
+// Fingerprint phrase
+//
+// The number of words of the EFF long word list
+const FINGERPRINT_WORDLIST_LEN: usize = 7776;
+// Like the clients, enough words for 64 bits of entropy, each word gives almost 13 bits
+const FINGERPRINT_WORDS: usize = 5;
+
+// The EFF long word list, https://www.eff.org/files/2016/07/18/eff_large_wordlist.txt, the dice numbers are skipped
+static FINGERPRINT_WORDLIST: Lazy<Vec<&str>> = Lazy::new(|| {
+    include_str!("static/eff_large_wordlist.txt")
+        .lines()
+        .filter_map(|line| line.split_whitespace().next_back())
+        .collect()
+});
+
+/// Returns the fingerprint phrase of a public key, the same one the clients show.
+/// The SHA-256 hash of the key is expanded with HKDF and the `fingerprint_material`, usually the user id,
+/// and the result is used as a big-endian number to pick the words.
+pub fn fingerprint_phrase(fingerprint_material: &str, public_key: &[u8]) -> String {
+    let key_fingerprint = digest::digest(&digest::SHA256, public_key);
+    let prk = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, key_fingerprint.as_ref());
+    let mut user_fingerprint = [0u8; digest::SHA256_OUTPUT_LEN];
+    prk.expand(&[fingerprint_material.as_bytes()], hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut user_fingerprint))
+        .expect("The fingerprint fits in one HKDF block");
+
+    let mut phrase = Vec::with_capacity(FINGERPRINT_WORDS);
+    for _ in 0..FINGERPRINT_WORDS {
+        // Divides the number in place and returns the remainder, which is always below the length of the list
+        let mut remainder = 0;
+        for byte in user_fingerprint.iter_mut() {
+            let current = (remainder << 8) | u32::from(*byte);
+            *byte = (current / FINGERPRINT_WORDLIST_LEN as u32) as u8;
+            remainder = current % FINGERPRINT_WORDLIST_LEN as u32;
+        }
+        phrase.push(FINGERPRINT_WORDLIST[remainder as usize]);
+    }
+    phrase.join("-")
+}
+
 //
 // Random values
 //
@@ -113,3 +210,25 @@ pub fn ct_eq<T: AsRef<[u8]>, U: AsRef<[u8]>>(a: T, b: U) -> bool {
     use subtle::ConstantTimeEq;
     a.as_ref().ct_eq(b.as_ref()).into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_wordlist_is_complete() {
+        assert_eq!(FINGERPRINT_WORDLIST.len(), FINGERPRINT_WORDLIST_LEN);
+        assert_eq!(FINGERPRINT_WORDLIST.first(), Some(&"abacus"));
+        assert_eq!(FINGERPRINT_WORDLIST.last(), Some(&"zoom"));
+    }
+
+    #[test]
+    fn fingerprint_phrase_uses_the_material() {
+        let public_key = b"public key";
+        let phrase = fingerprint_phrase("a09726a0-9590-49d1-a5f5-afe300b6a515", public_key);
+        assert_eq!(phrase.split('-').count(), FINGERPRINT_WORDS);
+        assert!(phrase.split('-').all(|word| FINGERPRINT_WORDLIST.contains(&word)));
+        assert_eq!(phrase, fingerprint_phrase("a09726a0-9590-49d1-a5f5-afe300b6a515", public_key));
+        assert_ne!(phrase, fingerprint_phrase("6e7a3b1c-0f4d-4c2e-9a8b-1d2c3e4f5a6b", public_key));
+    }
+}
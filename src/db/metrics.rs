@@ -0,0 +1,77 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+use crate::CONFIG;
+
+// Number of connection acquisitions kept for the wait time percentiles
+const WAIT_TIME_SAMPLES: usize = 1000;
+
+#[derive(Default)]
+struct PoolMetrics {
+    wait_times: VecDeque<Duration>,
+    acquired: u64,
+    timeouts: u64,
+    slow_queries: u64,
+}
+
+static POOL_METRICS: Lazy<Mutex<PoolMetrics>> = Lazy::new(|| Mutex::new(PoolMetrics::default()));
+
+/// Records how long a caller waited for a pooled connection, `None` when no connection became available in time.
+pub fn record_acquire(wait_time: Option<Duration>) {
+    let mut metrics = POOL_METRICS.lock().unwrap();
+    match wait_time {
+        Some(wait_time) => {
+            metrics.acquired += 1;
+            if metrics.wait_times.len() == WAIT_TIME_SAMPLES {
+                metrics.wait_times.pop_front();
+            }
+            metrics.wait_times.push_back(wait_time);
+        }
+        None => metrics.timeouts += 1,
+    }
+}
+
+/// Called by `db_run!` after every database call, logs the calls above `DATABASE_SLOW_QUERY_MS`.
+pub fn check_query_time(route: Option<&str>, file: &str, line: u32, elapsed: Duration) {
+    let threshold = CONFIG.database_slow_query_ms();
+    if threshold == 0 || elapsed < Duration::from_millis(threshold) {
+        return;
+    }
+
+    POOL_METRICS.lock().unwrap().slow_queries += 1;
+    warn!(
+        target: "db",
+        "Slow database call at {file}:{line} took {elapsed:?}, route: {}",
+        route.unwrap_or("(background job)")
+    );
+}
+
+/// The wait time percentiles in milliseconds, over the last `WAIT_TIME_SAMPLES` acquisitions.
+pub fn to_json() -> Value {
+    let metrics = POOL_METRICS.lock().unwrap();
+
+    let mut wait_times: Vec<Duration> = metrics.wait_times.iter().copied().collect();
+    wait_times.sort_unstable();
+    let percentile = |p: usize| -> Option<f64> {
+        if wait_times.is_empty() {
+            return None;
+        }
+        let index = (wait_times.len() * p).div_ceil(100).saturating_sub(1);
+        Some(wait_times[index].as_secs_f64() * 1000.0)
+    };
+
+    json!({
+        "acquired": metrics.acquired,
+        "timeouts": metrics.timeouts,
+        "slowQueries": metrics.slow_queries,
+        "waitTimeMs": {
+            "samples": wait_times.len(),
+            "p50": percentile(50),
+            "p90": percentile(90),
+            "p99": percentile(99),
+            "max": percentile(100),
+        },
+    })
+}
@@ -1,7 +1,7 @@
 use std::{sync::Arc, time::Duration};
 
 use diesel::{
-    connection::SimpleConnection,
+    connection::{SimpleConnection, TransactionManager},
     r2d2::{ConnectionManager, CustomizeConnection, Pool, PooledConnection},
 };
 
@@ -33,6 +33,8 @@ pub mod __mysql_schema;
 #[path = "schemas/postgresql/schema.rs"]
 pub mod __postgresql_schema;
 
+pub mod metrics;
+
 // These changes are based on Rocket 0.5-rc wrapper of Diesel: https://github.com/SergioBenitez/Rocket/blob/v0.5-rc/contrib/sync_db_pools
 
 // A wrapper around spawn_blocking that propagates panics to the calling code.
@@ -60,6 +62,8 @@ macro_rules! generate_connections {
         pub struct DbConn {
             conn: Arc<Mutex<Option<DbConnInner>>>,
             permit: Option<OwnedSemaphorePermit>,
+            // The route of the request the connection was acquired for, used to log slow queries
+            route: Option<String>,
         }
 
         #[allow(non_camel_case_types)]
@@ -121,17 +125,21 @@ macro_rules! generate_connections {
         }
 
         impl DbPool {
-            // For the given database URL, guess its type, run migrations, create pool, and return it
+            // For the configured database URL, guess its type, run migrations, create pool, and return it
             pub fn from_config() -> Result<Self, Error> {
-                let url = CONFIG.database_url();
-                let conn_type = DbConnType::from_url(&url)?;
+                Self::from_url(&CONFIG.database_url())
+            }
+
+            // Same as `from_config`, but for another database, like the SQLite database copied by `migrate-db`
+            pub fn from_url(url: &str) -> Result<Self, Error> {
+                let conn_type = DbConnType::from_url(url)?;
 
                 match conn_type { $(
                     DbConnType::$name => {
                         #[cfg($name)]
                         {
-                            pastey::paste!{ [< $name _migrations >]::run_migrations()?; }
-                            let manager = ConnectionManager::new(&url);
+                            pastey::paste!{ [< $name _migrations >]::run_migrations(url)?; }
+                            let manager = ConnectionManager::new(url);
                             let pool = Pool::builder()
                                 .max_size(CONFIG.database_max_conns())
                                 .min_idle(Some(CONFIG.database_min_conns()))
@@ -155,9 +163,11 @@ macro_rules! generate_connections {
             // Get a connection from the pool
             pub async fn get(&self) -> Result<DbConn, Error> {
                 let duration = Duration::from_secs(CONFIG.database_timeout());
+                let start = std::time::Instant::now();
                 let permit = match timeout(duration, Arc::clone(&self.semaphore).acquire_owned()).await {
                     Ok(p) => p.expect("Semaphore should be open"),
                     Err(_) => {
+                        metrics::record_acquire(None);
                         err!("Timeout waiting for database connection");
                     }
                 };
@@ -166,15 +176,42 @@ macro_rules! generate_connections {
                     #[cfg($name)]
                     DbPoolInner::$name(p) => {
                         let pool = p.clone();
-                        let c = run_blocking(move || pool.get_timeout(duration)).await.map_res("Error retrieving connection from pool")?;
+                        let c = match run_blocking(move || pool.get_timeout(duration)).await {
+                            Ok(c) => c,
+                            Err(e) => {
+                                metrics::record_acquire(None);
+                                return Err(e).map_res("Error retrieving connection from pool");
+                            }
+                        };
+                        metrics::record_acquire(Some(start.elapsed()));
 
                         Ok(DbConn {
                             conn: Arc::new(Mutex::new(Some(DbConnInner::$name(c)))),
-                            permit: Some(permit)
+                            permit: Some(permit),
+                            route: None,
                         })
                     },
                 )+ }
             }
+
+            // The state of the pool for the metrics, next to the wait times recorded by `get`
+            pub fn state_json(&self) -> serde_json::Value {
+                let pool = self.pool.as_ref().expect("DbPool.pool should always be Some()");
+                let (connections, idle_connections) = match pool {  $(
+                    #[cfg($name)]
+                    DbPoolInner::$name(p) => {
+                        let state = p.state();
+                        (state.connections, state.idle_connections)
+                    },
+                )+ };
+
+                json!({
+                    "maxConnections": CONFIG.database_max_conns(),
+                    "connections": connections,
+                    "idleConnections": idle_connections,
+                    "availablePermits": self.semaphore.available_permits(),
+                })
+            }
         }
     };
 }
@@ -257,7 +294,8 @@ macro_rules! db_run {
 
         let conn = $conn.conn.clone();
         let mut conn = conn.lock_owned().await;
-        match conn.as_mut().expect("internal invariant broken: self.connection is Some") {
+        let start = std::time::Instant::now();
+        let result = match conn.as_mut().expect("internal invariant broken: self.connection is Some") {
                 $($(
                 #[cfg($db)]
                 $crate::db::DbConnInner::$db($conn) => {
@@ -269,7 +307,11 @@ macro_rules! db_run {
                     tokio::task::block_in_place(move || { $body }) // Run blocking can't be used due to the 'static limitation, use block_in_place instead
                 },
             )+)+
-        }
+        };
+        let elapsed = start.elapsed();
+        trace!(target: "db", "Database call at {}:{} took {:?}", file!(), line!(), elapsed);
+        $crate::db::metrics::check_query_time($conn.route.as_deref(), file!(), line!(), elapsed);
+        result
     }};
 
     ( @raw $conn:ident: $( $($db:ident),+ $body:block )+ ) => {{
@@ -387,6 +429,57 @@ pub async fn backup_database(conn: &mut DbConn) -> Result<String, Error> {
     }
 }
 
+/// Moves the content of the SQLite write-ahead log into the database file, so it is complete once the server exits.
+/// MySQL/MariaDB and PostgreSQL don't need this.
+pub async fn checkpoint_database(conn: &mut DbConn) -> Result<(), Error> {
+    db_run! {@raw conn:
+        postgresql, mysql {
+            let _ = conn;
+            Ok(())
+        }
+        sqlite {
+            if CONFIG.enable_db_wal() {
+                conn.batch_execute("PRAGMA wal_checkpoint(TRUNCATE)")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+enum TransactionStep {
+    Begin,
+    Commit,
+    Rollback,
+}
+
+fn run_transaction_step<C: diesel::Connection>(conn: &mut C, step: TransactionStep) -> diesel::QueryResult<()> {
+    match step {
+        TransactionStep::Begin => C::TransactionManager::begin_transaction(conn),
+        TransactionStep::Commit => C::TransactionManager::commit_transaction(conn),
+        TransactionStep::Rollback => C::TransactionManager::rollback_transaction(conn),
+    }
+}
+
+/// Starts a transaction on the connection, the following queries are only applied once `commit_transaction` is called.
+/// A connection dropped while a transaction is still open is discarded by the pool instead of being reused.
+pub async fn begin_transaction(conn: &mut DbConn) -> Result<(), Error> {
+    db_run! {@raw conn: {
+        run_transaction_step(conn, TransactionStep::Begin).map_res("Error starting transaction")
+    }}
+}
+
+pub async fn commit_transaction(conn: &mut DbConn) -> Result<(), Error> {
+    db_run! {@raw conn: {
+        run_transaction_step(conn, TransactionStep::Commit).map_res("Error committing transaction")
+    }}
+}
+
+pub async fn rollback_transaction(conn: &mut DbConn) -> Result<(), Error> {
+    db_run! {@raw conn: {
+        run_transaction_step(conn, TransactionStep::Rollback).map_res("Error rolling back transaction")
+    }}
+}
+
 /// Get the SQL Server version
 pub async fn get_sql_server_version(conn: &mut DbConn) -> String {
     db_run! {@raw conn:
@@ -415,7 +508,10 @@ impl<'r> FromRequest<'r> for DbConn {
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         match request.rocket().state::<DbPool>() {
             Some(p) => match p.get().await {
-                Ok(dbconn) => Outcome::Success(dbconn),
+                Ok(mut dbconn) => {
+                    dbconn.route = request.route().map(|route| format!("{} {}", route.method, route.uri));
+                    Outcome::Success(dbconn)
+                }
                 _ => Outcome::Error((Status::ServiceUnavailable, ())),
             },
             None => Outcome::Error((Status::InternalServerError, ())),
@@ -431,13 +527,12 @@ mod sqlite_migrations {
     use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
     pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/sqlite");
 
-    pub fn run_migrations() -> Result<(), super::Error> {
+    pub fn run_migrations(url: &str) -> Result<(), super::Error> {
         use diesel::{Connection, RunQueryDsl};
-        let url = crate::CONFIG.database_url();
 
         // Establish a connection to the sqlite database (this will create a new one, if it does
         // not exist, and exit if there is an error).
-        let mut connection = diesel::sqlite::SqliteConnection::establish(&url)?;
+        let mut connection = diesel::sqlite::SqliteConnection::establish(url)?;
 
         // Run the migrations after successfully establishing a connection
         // Disable Foreign Key Checks during migration
@@ -461,10 +556,10 @@ mod mysql_migrations {
     use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
     pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/mysql");
 
-    pub fn run_migrations() -> Result<(), super::Error> {
+    pub fn run_migrations(url: &str) -> Result<(), super::Error> {
         use diesel::{Connection, RunQueryDsl};
         // Make sure the database is up to date (create if it doesn't exist, or run the migrations)
-        let mut connection = diesel::mysql::MysqlConnection::establish(&crate::CONFIG.database_url())?;
+        let mut connection = diesel::mysql::MysqlConnection::establish(url)?;
         // Disable Foreign Key Checks during migration
 
         // Scoped to a connection/session.
@@ -482,10 +577,10 @@ mod postgresql_migrations {
     use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
     pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/postgresql");
 
-    pub fn run_migrations() -> Result<(), super::Error> {
+    pub fn run_migrations(url: &str) -> Result<(), super::Error> {
         use diesel::Connection;
         // Make sure the database is up to date (create if it doesn't exist, or run the migrations)
-        let mut connection = diesel::pg::PgConnection::establish(&crate::CONFIG.database_url())?;
+        let mut connection = diesel::pg::PgConnection::establish(url)?;
         connection.run_pending_migrations(MIGRATIONS).expect("Error running migrations");
         Ok(())
     }
@@ -0,0 +1,198 @@
+use chrono::{NaiveDateTime, Utc};
+use data_encoding::{BASE32_NOPAD, HEXLOWER};
+
+use super::TwoFactorType;
+use crate::{api::EmptyResult, crypto, db::DbConn, error::MapResult, util::get_uuid};
+
+db_object! {
+    // A second factor of the admin panel login, there is at most one of every type.
+    // `data` holds the TOTP secret, or the WebAuthn registrations and challenges as json.
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = admin_twofactor)]
+    #[diesel(primary_key(atype))]
+    pub struct AdminTwoFactor {
+        pub atype: i32,
+        pub data: String,
+        // The last used TOTP time step, so a code can't be used twice
+        pub last_used: i64,
+        pub created_at: NaiveDateTime,
+    }
+
+    #[derive(Identifiable, Queryable, Insertable)]
+    #[diesel(table_name = admin_recovery_codes)]
+    #[diesel(primary_key(uuid))]
+    pub struct AdminRecoveryCode {
+        pub uuid: String,
+        // PBKDF2 of the code salted with the uuid, the codes themselves are only shown once
+        pub code_hash: String,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+/// Local methods
+impl AdminTwoFactor {
+    pub fn new(atype: TwoFactorType, data: String) -> Self {
+        Self {
+            atype: atype as i32,
+            data,
+            last_used: 0,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+}
+
+/// Database methods
+impl AdminTwoFactor {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(admin_twofactor::table)
+                    .values(AdminTwoFactorDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving admin twofactor")
+            }
+            postgresql {
+                let value = AdminTwoFactorDb::to_db(self);
+                diesel::insert_into(admin_twofactor::table)
+                    .values(&value)
+                    .on_conflict(admin_twofactor::atype)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving admin twofactor")
+            }
+        }
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(admin_twofactor::table.filter(admin_twofactor::atype.eq(self.atype)))
+                .execute(conn)
+                .map_res("Error deleting admin twofactor")
+        }}
+    }
+
+    pub async fn find_by_type(atype: TwoFactorType, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            admin_twofactor::table
+                .filter(admin_twofactor::atype.eq(atype as i32))
+                .first::<AdminTwoFactorDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    /// The configured second factors, without the pending challenges.
+    pub async fn find_all(conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            admin_twofactor::table
+                .filter(admin_twofactor::atype.lt(1000)) // Filter implementation types
+                .load::<AdminTwoFactorDb>(conn)
+                .expect("Error loading admin twofactor")
+                .from_db()
+        }}
+    }
+
+    pub async fn delete_all(conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(admin_twofactor::table)
+                .execute(conn)
+                .map_res("Error deleting admin twofactor")
+        }}
+    }
+}
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ITERATIONS: u32 = 100_000;
+
+/// Local methods
+impl AdminRecoveryCode {
+    fn new(code: &str) -> Self {
+        let uuid = get_uuid();
+        let hash = crypto::hash_password(Self::normalize(code).as_bytes(), uuid.as_bytes(), RECOVERY_CODE_ITERATIONS);
+        Self {
+            uuid,
+            code_hash: HEXLOWER.encode(&hash),
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    // The codes are shown in groups of four characters, which may or may not be typed
+    fn normalize(code: &str) -> String {
+        code.chars().filter(char::is_ascii_alphanumeric).collect::<String>().to_uppercase()
+    }
+
+    fn matches(&self, code: &str) -> bool {
+        let Ok(hash) = HEXLOWER.decode(self.code_hash.as_bytes()) else {
+            return false;
+        };
+        crypto::verify_password_hash(
+            Self::normalize(code).as_bytes(),
+            self.uuid.as_bytes(),
+            &hash,
+            RECOVERY_CODE_ITERATIONS,
+        )
+    }
+}
+
+/// Database methods
+impl AdminRecoveryCode {
+    /// Replaces the recovery codes with a new set, returns the codes to show to the admin.
+    pub async fn generate(conn: &mut DbConn) -> Result<Vec<String>, crate::Error> {
+        Self::delete_all(conn).await?;
+
+        let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+        for _ in 0..RECOVERY_CODE_COUNT {
+            let code = crypto::encode_random_bytes::<10>(BASE32_NOPAD);
+            let code = code.as_bytes().chunks(4).map(|c| String::from_utf8_lossy(c).into_owned()).collect::<Vec<_>>();
+            let code = code.join("-");
+            let row = Self::new(&code);
+            db_run! { conn: {
+                diesel::insert_into(admin_recovery_codes::table)
+                    .values(AdminRecoveryCodeDb::to_db(&row))
+                    .execute(conn)
+                    .map_res("Error saving admin recovery code")
+            }}?;
+            codes.push(code);
+        }
+        Ok(codes)
+    }
+
+    /// Checks the code against the stored hashes, a matching code is removed so it can only be used once.
+    pub async fn redeem(code: &str, conn: &mut DbConn) -> Result<bool, crate::Error> {
+        let rows: Vec<Self> = db_run! { conn: {
+            admin_recovery_codes::table
+                .load::<AdminRecoveryCodeDb>(conn)
+                .expect("Error loading admin recovery codes")
+                .from_db()
+        }};
+
+        let Some(row) = rows.into_iter().find(|row| row.matches(code)) else {
+            return Ok(false);
+        };
+        db_run! { conn: {
+            diesel::delete(admin_recovery_codes::table.filter(admin_recovery_codes::uuid.eq(row.uuid)))
+                .execute(conn)
+                .map_res("Error deleting admin recovery code")
+        }}?;
+        Ok(true)
+    }
+
+    pub async fn count(conn: &mut DbConn) -> i64 {
+        db_run! { conn: {
+            admin_recovery_codes::table
+                .count()
+                .first::<i64>(conn)
+                .ok()
+                .unwrap_or(0)
+        }}
+    }
+
+    pub async fn delete_all(conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(admin_recovery_codes::table)
+                .execute(conn)
+                .map_res("Error deleting admin recovery codes")
+        }}
+    }
+}
@@ -1,10 +1,11 @@
 use std::time::Duration;
 
 use bigdecimal::{BigDecimal, ToPrimitive};
+use chrono::NaiveDateTime;
 use derive_more::{AsRef, Deref, Display};
 use serde_json::Value;
 
-use super::{CipherId, OrganizationId, UserId};
+use super::{AttachmentUpload, CipherId, OrganizationId, UserId};
 use crate::{config::PathType, CONFIG};
 use macros::IdFromParam;
 
@@ -44,21 +45,39 @@ impl Attachment {
         format!("{}/{}", self.cipher_uuid, self.id)
     }
 
-    pub async fn get_url(&self, host: &str) -> Result<String, crate::Error> {
+    /// What the download url of the attachment is signed for, the deletion date of its item is part of it.
+    /// So the urls stop working once the item is deleted or restored.
+    pub fn get_signed_path(&self, cipher_deleted_at: Option<&NaiveDateTime>) -> String {
+        let deleted_at = cipher_deleted_at.map_or(0, |d| d.and_utc().timestamp_micros());
+        format!("attachments/{}/{}/{deleted_at}", self.cipher_uuid, self.id)
+    }
+
+    pub async fn get_url(&self, host: &str, cipher_deleted_at: Option<&NaiveDateTime>) -> Result<String, crate::Error> {
         let operator = CONFIG.opendal_operator_for_path_type(PathType::Attachments)?;
 
         if operator.info().scheme() == opendal::Scheme::Fs {
-            let token = encode_jwt(&generate_file_download_claims(self.cipher_uuid.clone(), self.id.clone()));
-            Ok(format!("{host}/attachments/{}/{}?token={token}", self.cipher_uuid, self.id))
+            let expires = file_url_expiration();
+            let signature = sign_file_url(&self.get_signed_path(cipher_deleted_at), expires);
+            Ok(format!("{host}/attachments/{}/{}?expires={expires}&signature={signature}", self.cipher_uuid, self.id))
         } else {
-            Ok(operator.presign_read(&self.get_file_path(), Duration::from_secs(5 * 60)).await?.uri().to_string())
+            let ttl = Duration::from_secs(CONFIG.file_download_url_ttl());
+            Ok(operator.presign_read(&self.get_file_path(), ttl).await?.uri().to_string())
+        }
+    }
+
+    /// Converts an attachment storage limit (KB) to the whole GB value clients expect in `maxStorageGb`.
+    /// No limit is reported as the maximum value, the clients do not show the storage in that case.
+    pub fn max_storage_gb(limit_kb: Option<i64>) -> i16 {
+        match limit_kb {
+            Some(kb) => (kb.max(0) as u64).div_ceil(1024 * 1024).try_into().unwrap_or(i16::MAX),
+            None => i16::MAX,
         }
     }
 
-    pub async fn to_json(&self, host: &str) -> Result<Value, crate::Error> {
+    pub async fn to_json(&self, host: &str, cipher_deleted_at: Option<&NaiveDateTime>) -> Result<Value, crate::Error> {
         Ok(json!({
             "id": self.id,
-            "url": self.get_url(host).await?,
+            "url": self.get_url(host, cipher_deleted_at).await?,
             "fileName": self.file_name,
             "size": self.file_size.to_string(),
             "sizeName": crate::util::get_display_size(self.file_size),
@@ -68,7 +87,7 @@ impl Attachment {
     }
 }
 
-use crate::auth::{encode_jwt, generate_file_download_claims};
+use crate::auth::{file_url_expiration, sign_file_url};
 use crate::db::DbConn;
 
 use crate::api::EmptyResult;
@@ -109,6 +128,11 @@ impl Attachment {
     }
 
     pub async fn delete(&self, conn: &mut DbConn) -> EmptyResult {
+        // An unfinished resumable upload of the attachment
+        if let Some(upload) = AttachmentUpload::find_by_attachment(&self.id, conn).await {
+            upload.delete(conn).await?;
+        }
+
         db_run! { conn: {
             crate::util::retry(
                 || diesel::delete(attachments::table.filter(attachments::id.eq(&self.id))).execute(conn),
@@ -159,6 +183,21 @@ impl Attachment {
         }}
     }
 
+    pub async fn size_all(conn: &mut DbConn) -> i64 {
+        db_run! { conn: {
+            let result: Option<BigDecimal> = attachments::table
+                .select(diesel::dsl::sum(attachments::file_size))
+                .first(conn)
+                .expect("Error loading attachment total size");
+
+            match result.map(|r| r.to_i64()) {
+                Some(Some(r)) => r,
+                Some(None) => i64::MAX,
+                None => 0
+            }
+        }}
+    }
+
     pub async fn size_by_user(user_uuid: &UserId, conn: &mut DbConn) -> i64 {
         db_run! { conn: {
             let result: Option<BigDecimal> = attachments::table
@@ -0,0 +1,139 @@
+use std::path::PathBuf;
+
+use chrono::{NaiveDateTime, Utc};
+use serde_json::Value;
+
+use super::{AttachmentId, CipherId, UserId};
+use crate::{api::EmptyResult, db::DbConn, error::MapResult, CONFIG};
+
+db_object! {
+    // A resumable upload of the data of an attachment, the chunks are appended to a file in the tmp folder.
+    // The attachment is only moved into the attachments folder once all of it was received and verified.
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = attachment_uploads)]
+    #[diesel(primary_key(attachment_id))]
+    pub struct AttachmentUpload {
+        pub attachment_id: AttachmentId,
+        pub cipher_uuid: CipherId,
+        pub user_uuid: UserId,
+        pub file_size: i64,
+        pub created_at: NaiveDateTime,
+        // The time of the last received chunk, stale uploads are removed after `RESUMABLE_UPLOAD_EXPIRATION_HOURS`
+        pub updated_at: NaiveDateTime,
+    }
+}
+
+/// Local methods
+impl AttachmentUpload {
+    pub fn new(attachment_id: AttachmentId, cipher_uuid: CipherId, user_uuid: UserId, file_size: i64) -> Self {
+        let now = Utc::now().naive_utc();
+        Self {
+            attachment_id,
+            cipher_uuid,
+            user_uuid,
+            file_size,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn temp_folder() -> PathBuf {
+        PathBuf::from(CONFIG.tmp_folder()).join("uploads")
+    }
+
+    pub fn temp_path(&self) -> PathBuf {
+        Self::temp_folder().join(self.attachment_id.as_str())
+    }
+
+    /// The number of bytes received so far, which is where the next chunk has to start.
+    pub async fn offset(&self) -> i64 {
+        match tokio::fs::metadata(self.temp_path()).await {
+            Ok(metadata) => metadata.len() as i64,
+            Err(_) => 0,
+        }
+    }
+
+    pub async fn to_json(&self) -> Value {
+        json!({
+            "attachmentId": self.attachment_id,
+            "offset": self.offset().await,
+            "size": self.file_size,
+            "maxChunkSize": CONFIG.resumable_upload_chunk_size_mb() * 1024 * 1024,
+            "object": "attachment-upload",
+        })
+    }
+}
+
+/// Database methods
+impl AttachmentUpload {
+    pub async fn save(&mut self, conn: &mut DbConn) -> EmptyResult {
+        self.updated_at = Utc::now().naive_utc();
+
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(attachment_uploads::table)
+                    .values(AttachmentUploadDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving attachment upload")
+            }
+            postgresql {
+                let value = AttachmentUploadDb::to_db(self);
+                diesel::insert_into(attachment_uploads::table)
+                    .values(&value)
+                    .on_conflict(attachment_uploads::attachment_id)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving attachment upload")
+            }
+        }
+    }
+
+    /// Removes the upload and the chunks received so far, the attachment itself is left alone.
+    pub async fn delete(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(attachment_uploads::table.filter(attachment_uploads::attachment_id.eq(&self.attachment_id)))
+                .execute(conn)
+                .map_res("Error deleting attachment upload")
+        }}?;
+
+        if let Err(e) = tokio::fs::remove_file(self.temp_path()).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn find_by_attachment(attachment_id: &AttachmentId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            attachment_uploads::table
+                .filter(attachment_uploads::attachment_id.eq(attachment_id))
+                .first::<AttachmentUploadDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_updated_before(dt: &NaiveDateTime, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            attachment_uploads::table
+                .filter(attachment_uploads::updated_at.lt(dt))
+                .load::<AttachmentUploadDb>(conn)
+                .expect("Error loading attachment uploads")
+                .from_db()
+        }}
+    }
+
+    pub async fn exists(attachment_id: &AttachmentId, conn: &mut DbConn) -> bool {
+        db_run! { conn: {
+            attachment_uploads::table
+                .filter(attachment_uploads::attachment_id.eq(attachment_id))
+                .count()
+                .first::<i64>(conn)
+                .ok()
+                .unwrap_or(0)
+                != 0
+        }}
+    }
+}
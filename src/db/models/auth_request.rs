@@ -5,6 +5,8 @@ use derive_more::{AsRef, Deref, Display, From};
 use macros::UuidFromParam;
 use serde_json::Value;
 
+const AUTH_REQUEST_LIFETIME_MINUTES: i64 = 5;
+
 db_object! {
     #[derive(Identifiable, Queryable, Insertable, AsChangeset, Deserialize, Serialize)]
     #[diesel(table_name = auth_requests)]
@@ -66,6 +68,12 @@ impl AuthRequest {
         }
     }
 
+    /// Clients stop waiting for an answer after 5 minutes, older requests can't be approved nor used to log in.
+    pub fn is_expired(&self) -> bool {
+        Utc::now().naive_utc()
+            >= self.creation_date + chrono::TimeDelta::try_minutes(AUTH_REQUEST_LIFETIME_MINUTES).unwrap()
+    }
+
     pub fn to_json_for_pending_device(&self) -> Value {
         json!({
             "id": self.uuid,
@@ -177,7 +185,8 @@ impl AuthRequest {
     }
 
     pub async fn purge_expired_auth_requests(conn: &mut DbConn) {
-        let expiry_time = Utc::now().naive_utc() - chrono::TimeDelta::try_minutes(5).unwrap(); //after 5 minutes, clients reject the request
+        let expiry_time =
+            Utc::now().naive_utc() - chrono::TimeDelta::try_minutes(AUTH_REQUEST_LIFETIME_MINUTES).unwrap();
         for auth_request in Self::find_created_before(&expiry_time, conn).await {
             auth_request.delete(conn).await.ok();
         }
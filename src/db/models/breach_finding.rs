@@ -0,0 +1,152 @@
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{OrganizationId, UserId};
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+};
+use macros::UuidFromParam;
+
+db_object! {
+    #[derive(Identifiable, Queryable, Insertable)]
+    #[diesel(table_name = breach_findings)]
+    #[diesel(primary_key(uuid))]
+    pub struct BreachFinding {
+        pub uuid: BreachFindingId,
+        pub org_uuid: OrganizationId,
+        pub user_uuid: UserId,
+        // The HIBP breach name, which is its unique identifier
+        pub breach_name: String,
+        pub title: String,
+        pub domain: String,
+        pub breach_date: Option<String>,
+        // JSON array of the compromised data, like "Email addresses" or "Passwords"
+        pub data_classes: String,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct BreachFindingId(String);
+
+/// Local methods
+impl BreachFinding {
+    /// Creates a finding from a breach model of the HIBP `breachedaccount` API.
+    pub fn from_hibp(org_uuid: OrganizationId, user_uuid: UserId, breach: &Value) -> Self {
+        let text = |key: &str| breach[key].as_str().unwrap_or_default().to_string();
+        Self {
+            uuid: BreachFindingId::from(get_uuid()),
+            org_uuid,
+            user_uuid,
+            breach_name: text("Name"),
+            title: text("Title"),
+            domain: text("Domain"),
+            breach_date: breach["BreachDate"].as_str().map(str::to_string),
+            data_classes: breach["DataClasses"].to_string(),
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.breach_name,
+            "title": self.title,
+            "domain": self.domain,
+            "breachDate": self.breach_date,
+            "dataClasses": serde_json::from_str::<Value>(&self.data_classes).unwrap_or(Value::Null),
+            "checkedAt": format_date(&self.created_at),
+        })
+    }
+}
+
+/// Database methods
+impl BreachFinding {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::insert_into(breach_findings::table)
+                .values(BreachFindingDb::to_db(self))
+                .execute(conn)
+                .map_res("Error saving breach finding")
+        }}
+    }
+
+    pub async fn find_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            breach_findings::table
+                .filter(breach_findings::org_uuid.eq(org_uuid))
+                .order_by((breach_findings::user_uuid, breach_findings::breach_date.desc()))
+                .load::<BreachFindingDb>(conn)
+                .expect("Error loading breach findings")
+                .from_db()
+        }}
+    }
+
+    pub async fn delete_all_by_org_and_user(
+        org_uuid: &OrganizationId,
+        user_uuid: &UserId,
+        conn: &mut DbConn,
+    ) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(
+                breach_findings::table
+                    .filter(breach_findings::org_uuid.eq(org_uuid))
+                    .filter(breach_findings::user_uuid.eq(user_uuid)),
+            )
+            .execute(conn)
+            .map_res("Error deleting breach findings")
+        }}
+    }
+
+    /// Removes the findings of users which are no longer members of the organization.
+    pub async fn delete_all_by_org_except_users(
+        org_uuid: &OrganizationId,
+        user_uuids: &[UserId],
+        conn: &mut DbConn,
+    ) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(
+                breach_findings::table
+                    .filter(breach_findings::org_uuid.eq(org_uuid))
+                    .filter(breach_findings::user_uuid.ne_all(user_uuids)),
+            )
+            .execute(conn)
+            .map_res("Error deleting breach findings")
+        }}
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(breach_findings::table.filter(breach_findings::org_uuid.eq(org_uuid)))
+                .execute(conn)
+                .map_res("Error deleting breach findings")
+        }}
+    }
+
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(breach_findings::table.filter(breach_findings::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting breach findings")
+        }}
+    }
+}
@@ -0,0 +1,162 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use serde_json::Value;
+
+use super::{Cipher, CipherId, Organization, OrganizationId, Report, ReportId, User, UserId};
+use crate::{api::EmptyResult, db::DbConn, error::MapResult, util::format_date, CONFIG};
+
+db_object! {
+    #[derive(Identifiable, Queryable)]
+    #[diesel(table_name = changes)]
+    #[diesel(primary_key(seq))]
+    pub struct Change {
+        pub seq: i64,
+        pub entity_type: String,
+        pub entity_uuid: String,
+        pub action: String,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum ChangeEntity {
+    User,
+    Organization,
+    Cipher,
+    Report,
+}
+
+impl ChangeEntity {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChangeEntity::User => "user",
+            ChangeEntity::Organization => "organization",
+            ChangeEntity::Cipher => "cipher",
+            ChangeEntity::Report => "report",
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum ChangeAction {
+    Upsert,
+    Delete,
+}
+
+impl ChangeAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChangeAction::Upsert => "upsert",
+            ChangeAction::Delete => "delete",
+        }
+    }
+}
+
+/// Local methods
+impl Change {
+    /// Maximum number of changes returned per request.
+    pub const PAGE_SIZE: i64 = 500;
+
+    /// The sequence number is assigned on insert, but the change only becomes visible once its transaction commits.
+    /// A gap is only passed once the change after it is this old, by then the missing change was rolled back.
+    const GAP_SETTLE_SECONDS: i64 = 60;
+
+    /// Cuts the changes off at the first gap in the sequence numbers which may still be filled.
+    /// A standby resuming after a later change would otherwise skip the missing one for good.
+    pub fn until_open_gap(since: i64, changes: Vec<Self>) -> Vec<Self> {
+        let settled = Utc::now().naive_utc() - TimeDelta::seconds(Self::GAP_SETTLE_SECONDS);
+        let mut next_seq = since.saturating_add(1);
+        let mut contiguous = Vec::with_capacity(changes.len());
+        for change in changes {
+            if change.seq != next_seq && change.created_at > settled {
+                break;
+            }
+            next_seq = change.seq.saturating_add(1);
+            contiguous.push(change);
+        }
+        contiguous
+    }
+
+    /// The current database row is included for upserts, a standby only needs the latest state of an entity.
+    /// Changes of entities which were deleted in the meantime don't have any data anymore.
+    pub async fn to_json(&self, conn: &mut DbConn) -> Value {
+        let data = if self.action == ChangeAction::Upsert.as_str() {
+            let uuid = self.entity_uuid.clone();
+            match self.entity_type.as_str() {
+                "user" => User::find_row_json(&UserId::from(uuid), conn).await,
+                "organization" => Organization::find_row_json(&OrganizationId::from(uuid), conn).await,
+                "cipher" => Cipher::find_row_json(&CipherId::from(uuid), conn).await,
+                "report" => Report::find_row_json(&ReportId::from(uuid), conn).await,
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        json!({
+            "seq": self.seq,
+            "entityType": self.entity_type,
+            "entityId": self.entity_uuid,
+            "action": self.action,
+            "date": format_date(&self.created_at),
+            "data": data,
+        })
+    }
+}
+
+/// Database methods
+impl Change {
+    /// Appends a change to the stream, this does nothing unless replication is enabled.
+    /// Failures are only logged, they should never block the actual change.
+    pub async fn record(entity: ChangeEntity, entity_uuid: &str, action: ChangeAction, conn: &mut DbConn) {
+        if CONFIG.replication_token().is_none() {
+            return;
+        }
+
+        let result: EmptyResult = db_run! { conn: {
+            diesel::insert_into(changes::table)
+                .values((
+                    changes::entity_type.eq(entity.as_str()),
+                    changes::entity_uuid.eq(entity_uuid),
+                    changes::action.eq(action.as_str()),
+                    changes::created_at.eq(Utc::now().naive_utc()),
+                ))
+                .execute(conn)
+                .map_res("Error saving change")
+        }};
+        if let Err(e) = result {
+            error!("Failed to record the {} change of {entity_uuid}: {e:#?}", entity.as_str());
+        }
+    }
+
+    pub async fn find_since(seq: i64, limit: i64, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            changes::table
+                .filter(changes::seq.gt(seq))
+                .order_by(changes::seq)
+                .limit(limit)
+                .load::<ChangeDb>(conn)
+                .expect("Error loading changes")
+                .from_db()
+        }}
+    }
+
+    pub async fn latest_seq(conn: &mut DbConn) -> i64 {
+        db_run! { conn: {
+            changes::table
+                .select(diesel::dsl::max(changes::seq))
+                .first::<Option<i64>>(conn)
+                .ok()
+                .flatten()
+                .unwrap_or(0)
+        }}
+    }
+
+    pub async fn clean_old(conn: &mut DbConn) -> EmptyResult {
+        let dt = Utc::now().naive_utc() - TimeDelta::try_days(CONFIG.replication_changes_days_retain()).unwrap();
+        db_run! { conn: {
+            diesel::delete(changes::table.filter(changes::created_at.lt(dt)))
+                .execute(conn)
+                .map_res("Error cleaning old changes")
+        }}
+    }
+}
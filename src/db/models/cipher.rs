@@ -5,13 +5,13 @@ use derive_more::{AsRef, Deref, Display, From};
 use serde_json::Value;
 
 use super::{
-    Attachment, CollectionCipher, CollectionId, Favorite, FolderCipher, FolderId, Group, Membership, MembershipStatus,
-    MembershipType, OrganizationId, User, UserId,
+    Attachment, Change, ChangeAction, ChangeEntity, CollectionCipher, CollectionId, Favorite, FolderCipher, FolderId,
+    Group, Membership, MembershipStatus, MembershipType, Organization, OrganizationId, User, UserId,
 };
 use crate::api::core::{CipherData, CipherSyncData, CipherSyncType};
 use macros::UuidFromParam;
 
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::HashMap};
 
 db_object! {
     #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
@@ -45,6 +45,8 @@ db_object! {
         pub password_history: Option<String>,
         pub deleted_at: Option<NaiveDateTime>,
         pub reprompt: Option<i32>,
+        // Set by the server when the encrypted login password changes, None for ciphers from before this was tracked
+        pub password_last_changed: Option<NaiveDateTime>,
     }
 }
 
@@ -53,6 +55,15 @@ pub enum RepromptType {
     Password = 1,
 }
 
+#[allow(dead_code)]
+pub enum CipherType {
+    Login = 1,
+    SecureNote = 2,
+    Card = 3,
+    Identity = 4,
+    SshKey = 5,
+}
+
 /// Local methods
 impl Cipher {
     pub fn new(atype: i32, name: String) -> Self {
@@ -78,7 +89,29 @@ impl Cipher {
             password_history: None,
             deleted_at: None,
             reprompt: None,
+            password_last_changed: None,
+        }
+    }
+
+    /// Updates `password_last_changed` before `new_data` replaces the type data of this cipher.
+    /// Clients re-encrypt every field on each save, so a different ciphertext alone doesn't mean a new password.
+    /// When the client keeps the `passwordRevisionDate` of the login, that decides whether the password changed.
+    pub fn track_password_change(&mut self, new_data: &Value) {
+        let new_password = new_data["password"].as_str().filter(|p| !p.is_empty());
+        if new_password.is_none() {
+            self.password_last_changed = None;
+            return;
         }
+
+        let old_data: Value = serde_json::from_str(&self.data).unwrap_or_default();
+        if old_data["password"].as_str() == new_password {
+            return;
+        }
+        let revision_date = new_data["passwordRevisionDate"].as_str();
+        if revision_date.is_some() && old_data["passwordRevisionDate"].as_str() == revision_date {
+            return;
+        }
+        self.password_last_changed = Some(Utc::now().naive_utc());
     }
 
     pub fn validate_cipher_data(cipher_data: &[CipherData]) -> EmptyResult {
@@ -150,7 +183,7 @@ impl Cipher {
                 if !attachments.is_empty() {
                     let mut attachments_json_vec = vec![];
                     for attachment in attachments {
-                        attachments_json_vec.push(attachment.to_json(host).await?);
+                        attachments_json_vec.push(attachment.to_json(host, self.deleted_at.as_ref()).await?);
                     }
                     attachments_json = Value::Array(attachments_json_vec);
                 }
@@ -160,7 +193,7 @@ impl Cipher {
             if !attachments.is_empty() {
                 let mut attachments_json_vec = vec![];
                 for attachment in attachments {
-                    attachments_json_vec.push(attachment.to_json(host).await?);
+                    attachments_json_vec.push(attachment.to_json(host, self.deleted_at.as_ref()).await?);
                 }
                 attachments_json = Value::Array(attachments_json_vec);
             }
@@ -252,7 +285,7 @@ impl Cipher {
 
         // NOTE: This was marked as *Backwards Compatibility Code*, but as of January 2021 this is still being used by upstream
         // Set the first element of the Uris array as Uri, this is needed several (mobile) clients.
-        if self.atype == 1 {
+        if self.atype == CipherType::Login as i32 {
             // Upstream always has an `uri` key/value
             type_data_json["uri"] = Value::Null;
             if let Some(uris) = type_data_json["uris"].as_array_mut() {
@@ -433,6 +466,7 @@ impl Cipher {
     pub async fn save(&mut self, conn: &mut DbConn) -> EmptyResult {
         self.update_users_revision(conn).await;
         self.updated_at = Utc::now().naive_utc();
+        Change::record(ChangeEntity::Cipher, &self.uuid, ChangeAction::Upsert, conn).await;
 
         db_run! { conn:
             sqlite, mysql {
@@ -472,6 +506,9 @@ impl Cipher {
         CollectionCipher::delete_all_by_cipher(&self.uuid, conn).await?;
         Attachment::delete_all_by_cipher(&self.uuid, conn).await?;
         Favorite::delete_all_by_cipher(&self.uuid, conn).await?;
+        super::CipherShare::delete_all_by_cipher(&self.uuid, conn).await?;
+        super::CipherHistory::delete_all_by_cipher(&self.uuid, conn).await?;
+        Change::record(ChangeEntity::Cipher, &self.uuid, ChangeAction::Delete, conn).await;
 
         db_run! { conn: {
             diesel::delete(ciphers::table.filter(ciphers::uuid.eq(&self.uuid)))
@@ -496,12 +533,43 @@ impl Cipher {
     }
 
     /// Purge all ciphers that are old enough to be auto-deleted.
+    /// Deletes the trashed ciphers which are older than the trash retention of their organization or user.
     pub async fn purge_trash(conn: &mut DbConn) {
-        if let Some(auto_delete_days) = CONFIG.trash_auto_delete_days() {
-            let now = Utc::now().naive_utc();
-            let dt = now - TimeDelta::try_days(auto_delete_days).unwrap();
-            for cipher in Self::find_deleted_before(&dt, conn).await {
-                cipher.delete(conn).await.ok();
+        let now = Utc::now().naive_utc();
+        let mut org_retention: HashMap<OrganizationId, Option<i64>> = HashMap::new();
+        let mut user_retention: HashMap<UserId, Option<i64>> = HashMap::new();
+
+        for cipher in Self::find_trashed(conn).await {
+            let retention_days = match (&cipher.organization_uuid, &cipher.user_uuid) {
+                (Some(org_uuid), _) => match org_retention.get(org_uuid) {
+                    Some(days) => *days,
+                    None => {
+                        let days = Organization::find_by_uuid(org_uuid, conn)
+                            .await
+                            .and_then(|org| org.effective_trash_retention_days());
+                        org_retention.insert(org_uuid.clone(), days);
+                        days
+                    }
+                },
+                (None, Some(user_uuid)) => match user_retention.get(user_uuid) {
+                    Some(days) => *days,
+                    None => {
+                        let days = User::find_by_uuid(user_uuid, conn)
+                            .await
+                            .and_then(|user| user.effective_trash_retention_days());
+                        user_retention.insert(user_uuid.clone(), days);
+                        days
+                    }
+                },
+                (None, None) => CONFIG.trash_auto_delete_days(),
+            };
+
+            // Values stored before the retention was limited can be out of range, those items are kept
+            let cutoff = retention_days.and_then(|days| now.checked_sub_signed(TimeDelta::try_days(days)?));
+            if let (Some(cutoff), Some(deleted_at)) = (cutoff, cipher.deleted_at) {
+                if deleted_at < cutoff {
+                    cipher.delete(conn).await.ok();
+                }
             }
         }
     }
@@ -614,7 +682,12 @@ impl Cipher {
                         rows.push((cu.read_only, cu.hide_passwords, cu.manage));
                     // Group permissions
                     } else if let Some(cg) = cipher_sync_data.user_collections_groups.get(collection) {
-                        rows.push((cg.read_only, cg.hide_passwords, cg.manage));
+                        let member_type = self
+                            .organization_uuid
+                            .as_ref()
+                            .and_then(|org_uuid| cipher_sync_data.members.get(org_uuid))
+                            .map_or(MembershipType::User as i32, |m| m.atype);
+                        rows.push((cg.read_only, cg.hide_passwords, cg.manage_for(member_type)));
                     }
                 }
             }
@@ -647,6 +720,7 @@ impl Cipher {
         // booleans and this behavior isn't portable anyway.
         //
         // The only exception is for the `manage` flag, that needs a boolean OR!
+        // Managing one of the collections also lifts the other restrictions.
         let mut read_only = true;
         let mut hide_passwords = true;
         let mut manage = false;
@@ -656,7 +730,7 @@ impl Cipher {
             manage |= mn;
         }
 
-        Some((read_only, hide_passwords, manage))
+        Some((read_only && !manage, hide_passwords && !manage, manage))
     }
 
     async fn get_user_collections_access_flags(
@@ -704,20 +778,53 @@ impl Cipher {
                     users_organizations::uuid.eq(groups_users::users_organizations_uuid)
                 ))
                 .filter(users_organizations::user_uuid.eq(user_uuid))
-                .select((collections_groups::read_only, collections_groups::hide_passwords, collections_groups::manage))
-                .load::<(bool, bool, bool)>(conn)
+                .select((
+                    collections_groups::read_only,
+                    collections_groups::hide_passwords,
+                    collections_groups::manage,
+                    users_organizations::atype,
+                ))
+                .load::<(bool, bool, bool, i32)>(conn)
                 .expect("Error getting group access restrictions")
+                .into_iter()
+                // Managers can manage what they can fully edit through a group, see `CollectionGroup::manage_for`
+                .map(|(ro, hp, mn, member_type)| {
+                    (ro, hp, mn || (member_type == MembershipType::Manager && !ro && !hp))
+                })
+                .collect()
         }}
     }
 
+    /// Whether the organization of this cipher is archived, its items can't be used then.
+    pub async fn is_in_archived_org(&self, conn: &mut DbConn) -> bool {
+        match &self.organization_uuid {
+            Some(org_uuid) => Organization::find_by_uuid(org_uuid, conn).await.is_some_and(|org| org.is_archived()),
+            None => false,
+        }
+    }
+
     pub async fn is_write_accessible_to_user(&self, user_uuid: &UserId, conn: &mut DbConn) -> bool {
+        if self.is_in_archived_org(conn).await {
+            return false;
+        }
         match self.get_access_restrictions(user_uuid, None, conn).await {
             Some((read_only, _hide_passwords, manage)) => !read_only || manage,
             None => false,
         }
     }
 
+    /// Deleting and restoring an item of an organization needs the `manage` permission of one of its collections.
+    pub async fn is_manageable_by_user(&self, user_uuid: &UserId, conn: &mut DbConn) -> bool {
+        if self.is_in_archived_org(conn).await {
+            return false;
+        }
+        self.get_access_restrictions(user_uuid, None, conn).await.is_some_and(|(_, _, manage)| manage)
+    }
+
     pub async fn is_accessible_to_user(&self, user_uuid: &UserId, conn: &mut DbConn) -> bool {
+        if self.is_in_archived_org(conn).await {
+            return false;
+        }
         self.get_access_restrictions(user_uuid, None, conn).await.is_some()
     }
 
@@ -756,6 +863,30 @@ impl Cipher {
         }}
     }
 
+    /// Returns the cipher as JSON for the replication stream, only the listed columns are part of it.
+    pub async fn find_row_json(uuid: &CipherId, conn: &mut DbConn) -> Option<Value> {
+        use crate::util::format_date;
+
+        let cipher = Self::find_by_uuid(uuid, conn).await?;
+        Some(json!({
+            "uuid": cipher.uuid,
+            "created_at": format_date(&cipher.created_at),
+            "updated_at": format_date(&cipher.updated_at),
+            "user_uuid": cipher.user_uuid,
+            "organization_uuid": cipher.organization_uuid,
+            "key": cipher.key,
+            "atype": cipher.atype,
+            "name": cipher.name,
+            "notes": cipher.notes,
+            "fields": cipher.fields,
+            "data": cipher.data,
+            "password_history": cipher.password_history,
+            "deleted_at": cipher.deleted_at.as_ref().map(format_date),
+            "reprompt": cipher.reprompt,
+            "password_last_changed": cipher.password_last_changed.as_ref().map(format_date),
+        }))
+    }
+
     pub async fn find_by_uuid_and_org(
         cipher_uuid: &CipherId,
         org_uuid: &OrganizationId,
@@ -789,7 +920,7 @@ impl Cipher {
         cipher_uuids: &Vec<CipherId>,
         conn: &mut DbConn,
     ) -> Vec<Self> {
-        if CONFIG.org_groups_enabled() {
+        let mut ciphers = if CONFIG.org_groups_enabled() {
             db_run! {conn: {
                 let mut query = ciphers::table
                     .left_join(ciphers_collections::table.on(
@@ -880,7 +1011,14 @@ impl Cipher {
                     .distinct()
                     .load::<CipherDb>(conn).expect("Error loading ciphers").from_db()
             }}
+        };
+
+        // The items of archived organizations are kept, but not available to the members
+        let archived_orgs = Organization::find_archived_uuids(conn).await;
+        if !archived_orgs.is_empty() {
+            ciphers.retain(|c| c.organization_uuid.as_ref().is_none_or(|org_uuid| !archived_orgs.contains(org_uuid)));
         }
+        ciphers
     }
 
     // Find all ciphers visible to the specified user.
@@ -916,6 +1054,16 @@ impl Cipher {
         }}
     }
 
+    pub async fn count_all(conn: &mut DbConn) -> i64 {
+        db_run! {conn: {
+            ciphers::table
+                .filter(ciphers::deleted_at.is_null())
+                .count()
+                .first::<i64>(conn)
+                .unwrap_or(0)
+        }}
+    }
+
     pub async fn count_owned_by_user(user_uuid: &UserId, conn: &mut DbConn) -> i64 {
         db_run! {conn: {
             ciphers::table
@@ -974,10 +1122,10 @@ impl Cipher {
     }
 
     /// Find all ciphers that were deleted before the specified datetime.
-    pub async fn find_deleted_before(dt: &NaiveDateTime, conn: &mut DbConn) -> Vec<Self> {
+    pub async fn find_trashed(conn: &mut DbConn) -> Vec<Self> {
         db_run! {conn: {
             ciphers::table
-                .filter(ciphers::deleted_at.lt(dt))
+                .filter(ciphers::deleted_at.is_not_null())
                 .load::<CipherDb>(conn).expect("Error loading ciphers").from_db()
         }}
     }
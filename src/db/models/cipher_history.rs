@@ -0,0 +1,282 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{Cipher, CipherHistoryPolicyData, CipherId, OrgPolicy, OrgPolicyType, OrganizationId, UserId};
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+    CONFIG,
+};
+use macros::UuidFromParam;
+
+db_object! {
+    // A previous revision of a cipher, stored as it was encrypted by the clients
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = cipher_history)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(uuid))]
+    pub struct CipherHistory {
+        pub uuid: CipherHistoryId,
+        pub cipher_uuid: CipherId,
+        // The user who replaced this revision
+        pub user_uuid: Option<UserId>,
+        pub atype: i32,
+        pub akey: Option<String>,
+        pub name: String,
+        pub notes: Option<String>,
+        pub fields: Option<String>,
+        pub data: String,
+        pub password_history: Option<String>,
+        pub reprompt: Option<i32>,
+        pub revision_date: NaiveDateTime,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct CipherHistoryId(String);
+
+/// Local methods
+impl CipherHistory {
+    pub fn new(cipher: &Cipher, user_uuid: Option<UserId>) -> Self {
+        Self {
+            uuid: CipherHistoryId::from(get_uuid()),
+            cipher_uuid: cipher.uuid.clone(),
+            user_uuid,
+            atype: cipher.atype,
+            akey: cipher.key.clone(),
+            name: cipher.name.clone(),
+            notes: cipher.notes.clone(),
+            fields: cipher.fields.clone(),
+            data: cipher.data.clone(),
+            password_history: cipher.password_history.clone(),
+            reprompt: cipher.reprompt,
+            revision_date: cipher.updated_at,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    /// Replaces the encrypted content of the cipher with this revision.
+    /// The folder, favorite and collections of the cipher are not part of the history and stay as they are.
+    pub fn restore_to(&self, cipher: &mut Cipher) {
+        let data: Value = serde_json::from_str(&self.data).unwrap_or_default();
+        cipher.track_password_change(&data);
+        cipher.atype = self.atype;
+        cipher.key.clone_from(&self.akey);
+        cipher.name.clone_from(&self.name);
+        cipher.notes.clone_from(&self.notes);
+        cipher.fields.clone_from(&self.fields);
+        cipher.data.clone_from(&self.data);
+        cipher.password_history.clone_from(&self.password_history);
+        cipher.reprompt = self.reprompt;
+    }
+
+    pub fn to_json(&self) -> Value {
+        let parse = |value: &Option<String>| -> Value {
+            value.as_deref().and_then(|v| serde_json::from_str(v).ok()).unwrap_or(Value::Null)
+        };
+
+        json!({
+            "id": self.uuid,
+            "cipherId": self.cipher_uuid,
+            "userId": self.user_uuid,
+            "type": self.atype,
+            "key": self.akey,
+            "name": self.name,
+            "notes": self.notes,
+            "fields": parse(&self.fields),
+            "data": serde_json::from_str::<Value>(&self.data).unwrap_or(Value::Null),
+            "passwordHistory": parse(&self.password_history),
+            "reprompt": self.reprompt.unwrap_or(0),
+            "revisionDate": format_date(&self.revision_date),
+            "creationDate": format_date(&self.created_at),
+            "object": "cipherHistory",
+        })
+    }
+
+    /// The item history policy of the organization overrides the global settings for its items.
+    async fn retention_policy(org_uuid: Option<&OrganizationId>, conn: &mut DbConn) -> Option<CipherHistoryPolicyData> {
+        let org_uuid = org_uuid?;
+        OrgPolicy::find_by_org_and_type(org_uuid, OrgPolicyType::CipherHistory, conn)
+            .await
+            .filter(|p| p.enabled)
+            .and_then(|p| serde_json::from_str(&p.data).ok())
+    }
+
+    /// Stores the current content of the cipher before it is overwritten, and drops the revisions above the limit.
+    pub async fn record(cipher: &Cipher, user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        let policy = Self::retention_policy(cipher.organization_uuid.as_ref(), conn).await;
+        let max_revisions = policy.and_then(|p| p.max_revisions).unwrap_or_else(|| CONFIG.cipher_history_revisions());
+        if max_revisions <= 0 {
+            return Self::delete_all_by_cipher(&cipher.uuid, conn).await;
+        }
+
+        Self::new(cipher, Some(user_uuid.clone())).save(conn).await?;
+        Self::trim_cipher(&cipher.uuid, max_revisions, conn).await
+    }
+}
+
+/// Database methods
+impl CipherHistory {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::insert_into(cipher_history::table)
+                .values(CipherHistoryDb::to_db(self))
+                .execute(conn)
+                .map_res("Error saving cipher history")
+        }}
+    }
+
+    pub async fn find_by_cipher(cipher_uuid: &CipherId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            cipher_history::table
+                .filter(cipher_history::cipher_uuid.eq(cipher_uuid))
+                .order_by(cipher_history::created_at.desc())
+                .load::<CipherHistoryDb>(conn)
+                .expect("Error loading cipher history")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_uuid_and_cipher(
+        uuid: &CipherHistoryId,
+        cipher_uuid: &CipherId,
+        conn: &mut DbConn,
+    ) -> Option<Self> {
+        db_run! { conn: {
+            cipher_history::table
+                .filter(cipher_history::uuid.eq(uuid))
+                .filter(cipher_history::cipher_uuid.eq(cipher_uuid))
+                .first::<CipherHistoryDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    /// Keeps only the `keep` most recent revisions of the cipher.
+    async fn trim_cipher(cipher_uuid: &CipherId, keep: i64, conn: &mut DbConn) -> EmptyResult {
+        let outdated: Vec<CipherHistoryId> = db_run! { conn: {
+            cipher_history::table
+                .filter(cipher_history::cipher_uuid.eq(cipher_uuid))
+                .order_by(cipher_history::created_at.desc())
+                .offset(keep)
+                .select(cipher_history::uuid)
+                .load::<CipherHistoryId>(conn)
+                .unwrap_or_default()
+        }};
+        if outdated.is_empty() {
+            return Ok(());
+        }
+
+        db_run! { conn: {
+            diesel::delete(cipher_history::table.filter(cipher_history::uuid.eq_any(outdated)))
+                .execute(conn)
+                .map_res("Error trimming cipher history")
+        }}
+    }
+
+    pub async fn delete_all_by_cipher(cipher_uuid: &CipherId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(cipher_history::table.filter(cipher_history::cipher_uuid.eq(cipher_uuid)))
+                .execute(conn)
+                .map_res("Error deleting cipher history")
+        }}
+    }
+
+    /// Used when the user key is rotated, the revisions of the personal items can't be decrypted anymore.
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(cipher_history::table.filter(cipher_history::cipher_uuid.eq_any(
+                ciphers::table.filter(ciphers::user_uuid.eq(user_uuid)).select(ciphers::uuid),
+            )))
+            .execute(conn)
+            .map_res("Error deleting cipher history")
+        }}
+    }
+
+    /// Used when the organization key is rotated, the revisions of its items can't be decrypted anymore.
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(cipher_history::table.filter(cipher_history::cipher_uuid.eq_any(
+                ciphers::table.filter(ciphers::organization_uuid.eq(org_uuid)).select(ciphers::uuid),
+            )))
+            .execute(conn)
+            .map_res("Error deleting cipher history")
+        }}
+    }
+
+    /// Removes the revisions stored before `cutoff` of the items of the organization.
+    pub async fn purge_organization(org_uuid: &OrganizationId, cutoff: &NaiveDateTime, conn: &mut DbConn) {
+        let result: EmptyResult = db_run! { conn: {
+            diesel::delete(
+                cipher_history::table
+                    .filter(cipher_history::created_at.lt(cutoff))
+                    .filter(cipher_history::cipher_uuid.eq_any(
+                        ciphers::table.filter(ciphers::organization_uuid.eq(org_uuid)).select(ciphers::uuid),
+                    )),
+            )
+            .execute(conn)
+            .map_res("Error purging cipher history")
+        }};
+        if let Err(e) = result {
+            error!("Error purging the item history of organization {org_uuid}: {e:?}");
+        }
+    }
+
+    /// Removes the revisions stored before `cutoff`, except those of the items of `excluded_orgs`.
+    pub async fn purge(cutoff: &NaiveDateTime, excluded_orgs: Vec<OrganizationId>, conn: &mut DbConn) {
+        let result: EmptyResult = db_run! { conn: {
+            diesel::delete(
+                cipher_history::table
+                    .filter(cipher_history::created_at.lt(cutoff))
+                    .filter(cipher_history::cipher_uuid.ne_all(
+                        ciphers::table.filter(ciphers::organization_uuid.eq_any(excluded_orgs)).select(ciphers::uuid),
+                    )),
+            )
+            .execute(conn)
+            .map_res("Error purging cipher history")
+        }};
+        if let Err(e) = result {
+            error!("Error purging the item history: {e:?}");
+        }
+    }
+
+    /// Removes the revisions older than the retention of their organization, or `CIPHER_HISTORY_DAYS`.
+    pub async fn purge_expired(conn: &mut DbConn) {
+        let now = Utc::now().naive_utc();
+        let cutoff = |days: i64| TimeDelta::try_days(days).and_then(|age| now.checked_sub_signed(age));
+
+        let mut excluded_orgs = Vec::new();
+        for policy in OrgPolicy::find_enabled_by_type(OrgPolicyType::CipherHistory, conn).await {
+            let policy_data = serde_json::from_str::<CipherHistoryPolicyData>(&policy.data).ok();
+            let Some(cutoff) = policy_data.and_then(|p| p.retention_days).and_then(cutoff) else {
+                continue;
+            };
+            Self::purge_organization(&policy.org_uuid, &cutoff, conn).await;
+            excluded_orgs.push(policy.org_uuid);
+        }
+
+        if let Some(cutoff) = CONFIG.cipher_history_days().and_then(cutoff) {
+            Self::purge(&cutoff, excluded_orgs, conn).await;
+        }
+    }
+}
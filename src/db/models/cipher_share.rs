@@ -0,0 +1,211 @@
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{CipherId, User, UserId};
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+};
+use macros::UuidFromParam;
+
+db_object! {
+    // A read-only copy of a personal cipher, shared directly with another user of the server.
+    // The copy is encrypted by the owner's client with a new key, `akey` is that key encrypted with the grantee's public key.
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = cipher_shares)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(uuid))]
+    pub struct CipherShare {
+        pub uuid: CipherShareId,
+        pub cipher_uuid: CipherId,
+        pub owner_uuid: UserId,
+        pub grantee_uuid: UserId,
+        pub akey: String,
+        pub data: String,
+        pub created_at: NaiveDateTime,
+        pub revision_date: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct CipherShareId(String);
+
+/// Local methods
+impl CipherShare {
+    pub fn new(cipher_uuid: CipherId, owner_uuid: UserId, grantee_uuid: UserId, akey: String, data: String) -> Self {
+        let now = Utc::now().naive_utc();
+
+        Self {
+            uuid: CipherShareId::from(get_uuid()),
+            cipher_uuid,
+            owner_uuid,
+            grantee_uuid,
+            akey,
+            data,
+            created_at: now,
+            revision_date: now,
+        }
+    }
+
+    pub async fn to_json_sent(&self, conn: &mut DbConn) -> Value {
+        let grantee = User::find_by_uuid(&self.grantee_uuid, conn).await;
+
+        json!({
+            "id": self.uuid,
+            "cipherId": self.cipher_uuid,
+            "granteeId": self.grantee_uuid,
+            "email": grantee.as_ref().map(|u| &u.email),
+            "name": grantee.as_ref().map(|u| &u.name),
+            "creationDate": format_date(&self.created_at),
+            "revisionDate": format_date(&self.revision_date),
+            "object": "directShareSent",
+        })
+    }
+
+    pub async fn to_json_received(&self, conn: &mut DbConn) -> Value {
+        let owner = User::find_by_uuid(&self.owner_uuid, conn).await;
+
+        json!({
+            "id": self.uuid,
+            "ownerId": self.owner_uuid,
+            "email": owner.as_ref().map(|u| &u.email),
+            "name": owner.as_ref().map(|u| &u.name),
+            "key": self.akey,
+            "data": self.data,
+            "creationDate": format_date(&self.created_at),
+            "revisionDate": format_date(&self.revision_date),
+            "object": "directShareReceived",
+        })
+    }
+}
+
+/// Database methods
+impl CipherShare {
+    pub async fn save(&mut self, conn: &mut DbConn) -> EmptyResult {
+        User::update_uuid_revision(&self.grantee_uuid, conn).await;
+        self.revision_date = Utc::now().naive_utc();
+
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(cipher_shares::table)
+                    .values(CipherShareDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving cipher share")
+            }
+            postgresql {
+                let value = CipherShareDb::to_db(self);
+                diesel::insert_into(cipher_shares::table)
+                    .values(&value)
+                    .on_conflict(cipher_shares::uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving cipher share")
+            }
+        }
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        User::update_uuid_revision(&self.grantee_uuid, conn).await;
+
+        db_run! { conn: {
+            diesel::delete(cipher_shares::table.filter(cipher_shares::uuid.eq(self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting cipher share")
+        }}
+    }
+
+    pub async fn delete_all_by_cipher(cipher_uuid: &CipherId, conn: &mut DbConn) -> EmptyResult {
+        for share in Self::find_by_cipher(cipher_uuid, conn).await {
+            share.delete(conn).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        for share in Self::find_by_owner(user_uuid, conn).await {
+            share.delete(conn).await?;
+        }
+        db_run! { conn: {
+            diesel::delete(cipher_shares::table.filter(cipher_shares::grantee_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting cipher shares")
+        }}
+    }
+
+    pub async fn find_by_uuid(uuid: &CipherShareId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            cipher_shares::table
+                .filter(cipher_shares::uuid.eq(uuid))
+                .first::<CipherShareDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_cipher_and_grantee(
+        cipher_uuid: &CipherId,
+        grantee_uuid: &UserId,
+        conn: &mut DbConn,
+    ) -> Option<Self> {
+        db_run! { conn: {
+            cipher_shares::table
+                .filter(cipher_shares::cipher_uuid.eq(cipher_uuid))
+                .filter(cipher_shares::grantee_uuid.eq(grantee_uuid))
+                .first::<CipherShareDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_cipher(cipher_uuid: &CipherId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            cipher_shares::table
+                .filter(cipher_shares::cipher_uuid.eq(cipher_uuid))
+                .load::<CipherShareDb>(conn)
+                .expect("Error loading cipher shares")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_owner(owner_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            cipher_shares::table
+                .filter(cipher_shares::owner_uuid.eq(owner_uuid))
+                .order_by(cipher_shares::created_at)
+                .load::<CipherShareDb>(conn)
+                .expect("Error loading cipher shares")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_grantee(grantee_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            cipher_shares::table
+                .filter(cipher_shares::grantee_uuid.eq(grantee_uuid))
+                .order_by(cipher_shares::created_at)
+                .load::<CipherShareDb>(conn)
+                .expect("Error loading cipher shares")
+                .from_db()
+        }}
+    }
+}
@@ -2,8 +2,8 @@ use derive_more::{AsRef, Deref, Display, From};
 use serde_json::Value;
 
 use super::{
-    CipherId, CollectionGroup, GroupUser, Membership, MembershipId, MembershipStatus, MembershipType, OrganizationId,
-    User, UserId,
+    CipherId, CollectionGroup, GroupUser, Membership, MembershipId, MembershipStatus, MembershipType, Organization,
+    OrganizationId, Report, User, UserId,
 };
 use crate::CONFIG;
 use macros::UuidFromParam;
@@ -87,24 +87,14 @@ impl Collection {
     ) -> Value {
         let (read_only, hide_passwords, manage) = if let Some(cipher_sync_data) = cipher_sync_data {
             match cipher_sync_data.members.get(&self.org_uuid) {
-                // Only for Manager types Bitwarden returns true for the manage option
-                // Owners and Admins always have true. Users are not able to have full access
+                // Owners, Admins and Managers with access to all collections can manage all of them
                 Some(m) if m.has_full_access() => (false, false, m.atype >= MembershipType::Manager),
+                // The `manage` flag is set per collection, for every type of member
                 Some(m) => {
-                    // Only let a manager manage collections when the have full read/write access
-                    let is_manager = m.atype == MembershipType::Manager;
                     if let Some(cu) = cipher_sync_data.user_collections.get(&self.uuid) {
-                        (
-                            cu.read_only,
-                            cu.hide_passwords,
-                            is_manager && (cu.manage || (!cu.read_only && !cu.hide_passwords)),
-                        )
+                        (cu.read_only, cu.hide_passwords, cu.manage)
                     } else if let Some(cg) = cipher_sync_data.user_collections_groups.get(&self.uuid) {
-                        (
-                            cg.read_only,
-                            cg.hide_passwords,
-                            is_manager && (cg.manage || (!cg.read_only && !cg.hide_passwords)),
-                        )
+                        (cg.read_only, cg.hide_passwords, cg.manage_for(m.atype))
                     } else {
                         (false, false, false)
                     }
@@ -114,14 +104,11 @@ impl Collection {
         } else {
             match Membership::find_confirmed_by_user_and_org(user_uuid, &self.org_uuid, conn).await {
                 Some(m) if m.has_full_access() => (false, false, m.atype >= MembershipType::Manager),
-                Some(m) if m.atype == MembershipType::Manager && self.is_manageable_by_user(user_uuid, conn).await => {
-                    (false, false, true)
-                }
-                Some(m) => {
-                    let is_manager = m.atype == MembershipType::Manager;
+                Some(_) if self.is_manageable_by_user(user_uuid, conn).await => (false, false, true),
+                Some(_) => {
                     let read_only = !self.is_writable_by_user(user_uuid, conn).await;
                     let hide_passwords = self.hide_passwords_for_user(user_uuid, conn).await;
-                    (read_only, hide_passwords, is_manager && !read_only && !hide_passwords)
+                    (read_only, hide_passwords, false)
                 }
                 _ => (true, true, false),
             }
@@ -143,6 +130,47 @@ impl Collection {
                     && (GroupUser::has_full_access_by_member(&member.org_uuid, &member.uuid, conn).await
                         || GroupUser::has_access_to_collection_by_member(col_id, &member.uuid, conn).await)))
     }
+
+    /// Like `can_access_collection`, for changing the collection, its access and deleting it. This needs the
+    /// `manage` permission of the collection, which any type of member can have.
+    pub async fn can_manage_collection(member: &Membership, col_id: &CollectionId, conn: &mut DbConn) -> bool {
+        if !member.has_status(MembershipStatus::Confirmed) {
+            return false;
+        }
+        if member.has_full_access() {
+            return member.atype >= MembershipType::Manager;
+        }
+        match Collection::find_by_uuid_and_org(col_id, &member.org_uuid, conn).await {
+            Some(collection) => collection.is_manageable_by_user(&member.user_uuid, conn).await,
+            None => false,
+        }
+    }
+
+    /// Whether the member can manage at least one collection, which gives access to the collection management of the
+    /// organization. Owners, Admins and Managers always have it.
+    pub async fn can_manage_any_collection(member: &Membership, conn: &mut DbConn) -> bool {
+        if !member.has_status(MembershipStatus::Confirmed) {
+            return false;
+        }
+        if member.atype >= MembershipType::Manager {
+            return true;
+        }
+        if CollectionUser::find_by_organization_and_user_uuid(&member.org_uuid, &member.user_uuid, conn)
+            .await
+            .iter()
+            .any(|cu| cu.manage)
+        {
+            return true;
+        }
+        if CONFIG.org_groups_enabled() {
+            for group_user in GroupUser::find_by_member(&member.uuid, conn).await {
+                if CollectionGroup::find_by_group(&group_user.groups_uuid, conn).await.iter().any(|cg| cg.manage) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
 }
 
 use crate::db::DbConn;
@@ -191,6 +219,7 @@ impl Collection {
         CollectionCipher::delete_all_by_collection(&self.uuid, conn).await?;
         CollectionUser::delete_all_by_collection(&self.uuid, conn).await?;
         CollectionGroup::delete_all_by_collection(&self.uuid, conn).await?;
+        Report::delete_all_by_collection(&self.uuid, conn).await?;
 
         db_run! { conn: {
             diesel::delete(collections::table.filter(collections::uuid.eq(self.uuid)))
@@ -223,7 +252,7 @@ impl Collection {
     }
 
     pub async fn find_by_user_uuid(user_uuid: UserId, conn: &mut DbConn) -> Vec<Self> {
-        if CONFIG.org_groups_enabled() {
+        let mut collections = if CONFIG.org_groups_enabled() {
             db_run! { conn: {
                 collections::table
                 .left_join(users_collections::table.on(
@@ -290,7 +319,14 @@ impl Collection {
                 .distinct()
                 .load::<CollectionDb>(conn).expect("Error loading collections").from_db()
             }}
+        };
+
+        // Like their items, see `Cipher::find_by_user`
+        let archived_orgs = Organization::find_archived_uuids(conn).await;
+        if !archived_orgs.is_empty() {
+            collections.retain(|c| !archived_orgs.contains(&c.org_uuid));
         }
+        collections
     }
 
     pub async fn find_by_organization_and_user_uuid(
@@ -342,6 +378,21 @@ impl Collection {
         }}
     }
 
+    pub async fn find_by_external_id_and_org(
+        external_id: &str,
+        org_uuid: &OrganizationId,
+        conn: &mut DbConn,
+    ) -> Option<Self> {
+        db_run! { conn: {
+            collections::table
+                .filter(collections::external_id.eq(external_id))
+                .filter(collections::org_uuid.eq(org_uuid))
+                .first::<CollectionDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
     pub async fn find_by_uuid_and_user(uuid: &CollectionId, user_uuid: UserId, conn: &mut DbConn) -> Option<Self> {
         if CONFIG.org_groups_enabled() {
             db_run! { conn: {
@@ -552,7 +603,12 @@ impl Collection {
                 ).or( // access via groups
                     groups_users::users_organizations_uuid.eq(users_organizations::uuid).and(
                         collections_groups::collections_uuid.is_not_null().and(
-                            collections_groups::manage.eq(true))
+                            collections_groups::manage.eq(true).or(
+                                // Managers who can fully edit it, see `CollectionGroup::manage_for`
+                                users_organizations::atype.eq(MembershipType::Manager as i32)
+                                    .and(collections_groups::read_only.eq(false))
+                                    .and(collections_groups::hide_passwords.eq(false))
+                            ))
                     )
                 )
             )
@@ -609,6 +665,8 @@ impl CollectionUser {
         manage: bool,
         conn: &mut DbConn,
     ) -> EmptyResult {
+        // Managing a collection includes editing its items and seeing their passwords
+        let (read_only, hide_passwords) = (read_only && !manage, hide_passwords && !manage);
         User::update_uuid_revision(user_uuid, conn).await;
 
         db_run! { conn:
@@ -859,11 +917,7 @@ impl CollectionMembership {
             "id": self.membership_uuid,
             "readOnly": self.read_only,
             "hidePasswords": self.hide_passwords,
-            "manage": membership_type >= MembershipType::Admin
-                || self.manage
-                || (membership_type == MembershipType::Manager
-                    && !self.read_only
-                    && !self.hide_passwords),
+            "manage": membership_type >= MembershipType::Admin || self.manage,
         })
     }
 }
@@ -0,0 +1,96 @@
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use serde_json::Value;
+
+use super::{Attachment, Cipher, Device, Report, User};
+use crate::api::EmptyResult;
+use crate::db::DbConn;
+use crate::error::MapResult;
+
+db_object! {
+    // A daily snapshot of the instance totals, used by the admin panel to chart their evolution
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = daily_stats)]
+    #[diesel(primary_key(day))]
+    pub struct DailyStat {
+        pub day: NaiveDate,
+        pub users: i64,
+        // Users with a device which was used during the last `ACTIVE_USER_DAYS` days
+        pub active_users: i64,
+        pub ciphers: i64,
+        pub attachments_size: i64,
+        // The total of the exposed passwords in the personal reports and in the organization reports
+        pub exposed_personal: i64,
+        pub exposed_org: i64,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+const ACTIVE_USER_DAYS: i64 = 30;
+
+/// Local methods
+impl DailyStat {
+    /// Collects the current totals as the snapshot of the given day.
+    pub async fn collect(day: NaiveDate, conn: &mut DbConn) -> Self {
+        let now = Utc::now().naive_utc();
+        let active_since = now - TimeDelta::days(ACTIVE_USER_DAYS);
+        let (exposed_personal, exposed_org) = Report::total_exposed_counts(conn).await;
+
+        Self {
+            day,
+            users: User::count_all(conn).await,
+            active_users: Device::count_active_users_since(&active_since, conn).await,
+            ciphers: Cipher::count_all(conn).await,
+            attachments_size: Attachment::size_all(conn).await,
+            exposed_personal,
+            exposed_org,
+            created_at: now,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "date": self.day.format("%Y-%m-%d").to_string(),
+            "users": self.users,
+            "activeUsers": self.active_users,
+            "ciphers": self.ciphers,
+            "attachmentsSize": self.attachments_size,
+            "exposedPersonal": self.exposed_personal,
+            "exposedOrg": self.exposed_org,
+        })
+    }
+}
+
+/// Database methods
+impl DailyStat {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(daily_stats::table)
+                    .values(DailyStatDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving daily stats")
+            }
+            postgresql {
+                let value = DailyStatDb::to_db(self);
+                diesel::insert_into(daily_stats::table)
+                    .values(&value)
+                    .on_conflict(daily_stats::day)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving daily stats")
+            }
+        }
+    }
+
+    pub async fn find_since(since: NaiveDate, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            daily_stats::table
+                .filter(daily_stats::day.ge(since))
+                .order(daily_stats::day.asc())
+                .load::<DailyStatDb>(conn)
+                .expect("Error loading daily stats")
+                .from_db()
+        }}
+    }
+}
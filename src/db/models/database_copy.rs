@@ -0,0 +1,214 @@
+// Copies the content of a SQLite database into a PostgreSQL or MySQL/MariaDB database, see the `migrate-db` command.
+// This lives next to the models, because the per database types of their rows are only visible from here.
+
+use crate::{
+    db::{begin_transaction, commit_transaction, rollback_transaction, DbConn},
+    error::{Error, MapResult},
+};
+
+// Rows inserted per query, keeps the number of bind parameters below the limits of PostgreSQL and MySQL
+const COPY_BATCH_SIZE: usize = 500;
+
+#[derive(QueryableByName)]
+struct ForeignKeyViolation {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    table: String,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::BigInt>)]
+    rowid: Option<i64>,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    parent: String,
+}
+
+/// The number of rows copied, or that would be copied in dry-run mode, per table.
+pub type CopySummary = Vec<(&'static str, usize)>;
+
+// For every table the target has to be empty, then the rows are loaded with the SQLite types of the model
+// and inserted with the types of the target database. Parent tables have to be listed before their children.
+macro_rules! copy_tables {
+    (
+        $source:ident, $target:ident, $dry_run:ident, $summary:ident;
+        $( $table:ident => $module:ident::$model:ident ),+ $(,)?
+    ) => {
+        $( pastey::paste! {
+            let existing: i64 = db_run! {@raw $target:
+                sqlite, mysql, postgresql {
+                    $table::table
+                        .count()
+                        .get_result::<i64>($target)
+                        .map_res(concat!("Error counting ", stringify!($table)))
+                }
+            }?;
+            if existing > 0 {
+                err!(concat!("The target database is not empty, table `", stringify!($table), "` already has rows"))
+            }
+
+            let rows: Vec<super::$module::$model> = db_run! {@raw $source:
+                sqlite {
+                    $table::table
+                        .load::<super::$module::__sqlite_model::[<$model Db>]>($source)
+                        .map_res(concat!("Error loading ", stringify!($table)))
+                        .map(FromDb::from_db)
+                }
+                mysql, postgresql {
+                    let _ = $source;
+                    err!("The source database has to be SQLite")
+                }
+            }?;
+
+            if !$dry_run {
+                for batch in rows.chunks(COPY_BATCH_SIZE) {
+                    let inserted: usize = db_run! {@raw $target:
+                        mysql {
+                            let values: Vec<_> =
+                                batch.iter().map(super::$module::__mysql_model::[<$model Db>]::to_db).collect();
+                            diesel::insert_into($table::table)
+                                .values(values)
+                                .execute($target)
+                                .map_res(concat!("Error copying ", stringify!($table)))
+                        }
+                        postgresql {
+                            let values: Vec<_> =
+                                batch.iter().map(super::$module::__postgresql_model::[<$model Db>]::to_db).collect();
+                            diesel::insert_into($table::table)
+                                .values(values)
+                                .execute($target)
+                                .map_res(concat!("Error copying ", stringify!($table)))
+                        }
+                        sqlite {
+                            let _ = $target;
+                            err!("The target database can't be SQLite")
+                        }
+                    }?;
+                    if inserted != batch.len() {
+                        let table = stringify!($table);
+                        err!(format!("Only {inserted} of {} rows were copied into `{table}`", batch.len()))
+                    }
+                }
+            }
+
+            $summary.push((stringify!($table), rows.len()));
+        } )+
+    };
+}
+
+/// Copies all the tables of the SQLite `source` into the empty `target`, in a single transaction.
+/// The `changes` log of the replication feed isn't copied, standbys have to do a full sync after the switch.
+/// With `dry_run` the checks are done and the rows are counted, but nothing is written.
+pub async fn copy_database(source: &mut DbConn, target: &mut DbConn, dry_run: bool) -> Result<CopySummary, Error> {
+    // SQLite doesn't always enforce the foreign keys, rows pointing to missing parents would fail the copy
+    let violations: Vec<ForeignKeyViolation> = db_run! {@raw source:
+        sqlite {
+            diesel::sql_query("PRAGMA foreign_key_check")
+                .load::<ForeignKeyViolation>(source)
+                .map_res("Error checking the foreign keys")
+        }
+        mysql, postgresql {
+            let _ = source;
+            err!("The source database has to be SQLite")
+        }
+    }?;
+    if !violations.is_empty() {
+        // Only the first ones are listed, the count is enough to see the extent of the problem
+        let listed: Vec<String> = violations
+            .iter()
+            .take(10)
+            .map(|v| {
+                let rowid = v.rowid.map_or_else(|| String::from("?"), |rowid| rowid.to_string());
+                format!("row {rowid} of `{}` references a missing row of `{}`", v.table, v.parent)
+            })
+            .collect();
+        err!(format!(
+            "The SQLite database has {} rows with broken references, remove or fix them before migrating:\n{}",
+            violations.len(),
+            listed.join("\n")
+        ))
+    }
+
+    if dry_run {
+        return copy_tables_into(source, target, true).await;
+    }
+
+    begin_transaction(target).await?;
+    match copy_tables_into(source, target, false).await {
+        Ok(summary) => {
+            commit_transaction(target).await?;
+            Ok(summary)
+        }
+        Err(e) => {
+            if let Err(rollback_err) = rollback_transaction(target).await {
+                error!("Error rolling back the database copy: {rollback_err:#?}");
+            }
+            Err(e)
+        }
+    }
+}
+
+async fn copy_tables_into(source: &mut DbConn, target: &mut DbConn, dry_run: bool) -> Result<CopySummary, Error> {
+    let mut summary = CopySummary::new();
+    copy_tables! { source, target, dry_run, summary;
+        users => user::User,
+        organizations => organization::Organization,
+        devices => device::Device,
+        twofactor => two_factor::TwoFactor,
+        twofactor_incomplete => two_factor_incomplete::TwoFactorIncomplete,
+        twofactor_duo_ctx => two_factor_duo_context::TwoFactorDuoContext,
+        invitations => user::Invitation,
+        sso_nonce => sso_nonce::SsoNonce,
+        sso_users => user::SsoUser,
+        user_aliases => user_alias::UserAlias,
+        emergency_access => emergency_access::EmergencyAccess,
+        auth_requests => auth_request::AuthRequest,
+        key_connector_keys => key_connector_key::KeyConnectorKey,
+        folders => folder::Folder,
+        organization_api_key => organization::OrganizationApiKey,
+        org_policies => org_policy::OrgPolicy,
+        org_domains => org_domain::OrgDomain,
+        org_twofactor => org_two_factor::OrgTwoFactor,
+        signup_rules => signup_rule::SignupRule,
+        users_organizations => organization::Membership,
+        providers => provider::Provider,
+        provider_users => provider::ProviderUser,
+        provider_organizations => provider::ProviderOrganization,
+        collections => collection::Collection,
+        groups => group::Group,
+        users_collections => collection::CollectionUser,
+        groups_users => group::GroupUser,
+        collections_groups => group::CollectionGroup,
+        ciphers => cipher::Cipher,
+        attachments => attachment::Attachment,
+        folders_ciphers => folder::FolderCipher,
+        ciphers_collections => collection::CollectionCipher,
+        favorites => favorite::Favorite,
+        cipher_shares => cipher_share::CipherShare,
+        cipher_history => cipher_history::CipherHistory,
+        sends => send::Send,
+        send_accesses => send_access::SendAccess,
+        reports => report::Report,
+        report_members => report_member::ReportMember,
+        report_history => report_member::ReportHistory,
+        event => event::Event,
+        login_events => login_event::LoginEvent,
+        breach_findings => breach_finding::BreachFinding,
+        quarantined_files => quarantined_file::QuarantinedFile,
+        projects => project::Project,
+        secrets => secret::Secret,
+        projects_secrets => secret::SecretProject,
+        secrets_revisions => secret::SecretsRevision,
+        service_accounts => service_account::ServiceAccount,
+        projects_service_accounts => service_account::ServiceAccountProject,
+        service_account_tokens => service_account::ServiceAccountToken,
+        icon_cache => icon_cache::IconCache,
+        daily_stats => daily_stat::DailyStat,
+        mail_queue => mail_queue::QueuedMail,
+        org_templates => org_template::OrgTemplate,
+        admin_twofactor => admin_two_factor::AdminTwoFactor,
+        admin_recovery_codes => admin_two_factor::AdminRecoveryCode,
+        refresh_token_families => refresh_token_family::RefreshTokenFamily,
+        personal_api_tokens => personal_api_token::PersonalApiToken,
+        attachment_uploads => attachment_upload::AttachmentUpload,
+        policy_violations => policy_violation::PolicyViolation,
+        org_risk_scores => org_risk_score::OrgRiskScore,
+    }
+
+    Ok(summary)
+}
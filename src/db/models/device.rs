@@ -4,7 +4,7 @@ use data_encoding::{BASE64, BASE64URL};
 use derive_more::{Display, From};
 use serde_json::Value;
 
-use super::{AuthRequest, UserId};
+use super::{AuthRequest, RefreshTokenFamily, UserId};
 use crate::{
     crypto,
     util::{format_date, get_uuid},
@@ -30,6 +30,8 @@ db_object! {
 
         pub refresh_token: String,
         pub twofactor_remember: Option<String>,
+        // The IP of the last login or token refresh, `updated_at` is the time of it
+        pub last_ip: Option<String>,
     }
 }
 
@@ -42,6 +44,8 @@ impl Device {
             "type": self.atype,
             "identifier": self.uuid,
             "creationDate": format_date(&self.created_at),
+            "lastActivityDate": format_date(&self.updated_at),
+            "lastIpAddress": self.last_ip, // Vaultwarden specific
             "isTrusted": false,
             "object":"device"
         })
@@ -58,6 +62,11 @@ impl Device {
         self.twofactor_remember = None;
     }
 
+    /// Invalidates every refresh token issued to the device, it has to log in again.
+    pub fn reset_refresh_token(&mut self) {
+        self.refresh_token = crypto::encode_random_bytes::<64>(BASE64URL);
+    }
+
     // This rely on the fact we only update the device after a successful login
     pub fn is_new(&self) -> bool {
         self.created_at == self.updated_at
@@ -93,6 +102,8 @@ impl DeviceWithAuthRequest {
             "type": self.device.atype,
             "identifier": self.device.uuid,
             "creationDate": format_date(&self.device.created_at),
+            "lastActivityDate": format_date(&self.device.updated_at),
+            "lastIpAddress": self.device.last_ip, // Vaultwarden specific
             "devicePendingAuthRequest": auth_request,
             "isTrusted": false,
             "encryptedPublicKey": null,
@@ -137,6 +148,7 @@ impl Device {
             push_token: None,
             refresh_token: crypto::encode_random_bytes::<64>(BASE64URL),
             twofactor_remember: None,
+            last_ip: None,
         };
 
         device.inner_save(conn).await.map(|()| device)
@@ -166,7 +178,56 @@ impl Device {
         self.inner_save(conn).await
     }
 
+    /// Renames the device, without touching `updated_at` which tracks the last activity.
+    pub async fn save_name(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::update(devices::table)
+                .filter(devices::uuid.eq(&self.uuid))
+                .filter(devices::user_uuid.eq(&self.user_uuid))
+                .set(devices::name.eq(&self.name))
+                .execute(conn)
+                .map_res("Error renaming device")
+        }}
+    }
+
+    /// Removing the device invalidates its refresh token and its access tokens, the other devices stay logged in.
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        RefreshTokenFamily::delete_by_user_and_device(&self.user_uuid, &self.uuid, conn).await?;
+
+        db_run! { conn: {
+            diesel::delete(
+                devices::table
+                    .filter(devices::uuid.eq(&self.uuid))
+                    .filter(devices::user_uuid.eq(&self.user_uuid)),
+            )
+            .execute(conn)
+            .map_res("Error removing device")
+        }}
+    }
+
+    /// Moves the device to another account. The sessions of the device are revoked, it has to log in again.
+    pub async fn transfer(self, user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        RefreshTokenFamily::delete_by_user_and_device(&self.user_uuid, &self.uuid, conn).await?;
+
+        db_run! { conn: {
+            diesel::update(
+                devices::table
+                    .filter(devices::uuid.eq(&self.uuid))
+                    .filter(devices::user_uuid.eq(&self.user_uuid)),
+            )
+            .set((
+                devices::user_uuid.eq(user_uuid),
+                devices::refresh_token.eq(crypto::encode_random_bytes::<64>(BASE64URL)),
+                devices::twofactor_remember.eq(None::<String>),
+            ))
+            .execute(conn)
+            .map_res("Error transferring device")
+        }}
+    }
+
     pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        RefreshTokenFamily::delete_all_by_user(user_uuid, conn).await?;
+
         db_run! { conn: {
             diesel::delete(devices::table.filter(devices::user_uuid.eq(user_uuid)))
                 .execute(conn)
@@ -245,6 +306,16 @@ impl Device {
         }}
     }
 
+    pub async fn count_active_users_since(since: &NaiveDateTime, conn: &mut DbConn) -> i64 {
+        db_run! { conn: {
+            devices::table
+                .filter(devices::updated_at.ge(since))
+                .select(diesel::dsl::count_distinct(devices::user_uuid))
+                .first::<i64>(conn)
+                .unwrap_or(0)
+        }}
+    }
+
     pub async fn find_push_devices_by_user(user_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
         db_run! { conn: {
             devices::table
@@ -1,9 +1,9 @@
-use chrono::{NaiveDateTime, TimeDelta, Utc};
+use chrono::{NaiveDateTime, Utc};
 //use derive_more::{AsRef, Deref, Display, From};
 use serde_json::Value;
 
 use super::{CipherId, CollectionId, GroupId, MembershipId, OrgPolicyId, OrganizationId, UserId};
-use crate::{api::EmptyResult, db::DbConn, error::MapResult, CONFIG};
+use crate::{api::EmptyResult, db::DbConn, error::MapResult};
 
 // https://bitwarden.com/help/event-logs/
 
@@ -37,7 +37,7 @@ db_object! {
 }
 
 // Upstream enum: https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Core/AdminConsole/Enums/EventType.cs
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum EventType {
     // User
     UserLoggedIn = 1000,
@@ -48,10 +48,11 @@ pub enum EventType {
     UserFailedLogIn = 1005,
     UserFailedLogIn2fa = 1006,
     UserClientExportedVault = 1007,
-    // UserUpdatedTempPassword = 1008, // Not supported
-    // UserMigratedKeyToKeyConnector = 1009, // Not supported
+    UserUpdatedTempPassword = 1008,
+    UserMigratedKeyToKeyConnector = 1009,
     UserRequestedDeviceApproval = 1010,
     // UserTdeOffboardingPasswordSet = 1011, // Not supported
+    UserAdminExportedVault = 1090, // Vaultwarden specific, the admin exported the vault of the user
 
     // Cipher
     CipherCreated = 1100,
@@ -72,6 +73,9 @@ pub enum EventType {
     CipherSoftDeleted = 1115,
     CipherRestored = 1116,
     CipherClientToggledCardNumberVisible = 1117,
+    CipherAttachmentQuarantined = 1190, // Vaultwarden specific, the upload was rejected by the virus scanner
+    // Vaultwarden specific, the download url of an attachment was requested
+    CipherAttachmentDownloaded = 1191,
 
     // Collection
     CollectionCreated = 1300,
@@ -125,12 +129,10 @@ pub enum EventType {
     // ProviderOrganizationAdded = 1901, // Not supported
     // ProviderOrganizationRemoved = 1902, // Not supported
     // ProviderOrganizationVaultAccessed = 1903, // Not supported
-
-    // OrganizationDomainAdded = 2000, // Not supported
-    // OrganizationDomainRemoved = 2001, // Not supported
-    // OrganizationDomainVerified = 2002, // Not supported
-    // OrganizationDomainNotVerified = 2003, // Not supported
-
+    OrganizationDomainAdded = 2000,
+    OrganizationDomainRemoved = 2001,
+    OrganizationDomainVerified = 2002,
+    OrganizationDomainNotVerified = 2003,
     // SecretRetrieved = 2100, // Not supported
 }
 
@@ -289,6 +291,18 @@ impl Event {
         }}
     }
 
+    pub async fn count_by_org_since(org_uuid: &OrganizationId, since: &NaiveDateTime, conn: &mut DbConn) -> i64 {
+        db_run! { conn: {
+            event::table
+                .filter(event::org_uuid.eq(org_uuid))
+                .filter(event::event_date.ge(since))
+                .count()
+                .first::<i64>(conn)
+                .ok()
+                .unwrap_or(0)
+        }}
+    }
+
     pub async fn find_by_org_and_member(
         org_uuid: &OrganizationId,
         member_uuid: &MembershipId,
@@ -311,6 +325,18 @@ impl Event {
         }}
     }
 
+    /// Returns all events where the user is either the subject or the one who acted, newest first.
+    pub async fn find_all_involving_user(user_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            event::table
+                .filter(event::user_uuid.eq(user_uuid).or(event::act_user_uuid.eq(user_uuid)))
+                .order_by(event::event_date.desc())
+                .load::<EventDb>(conn)
+                .expect("Error filtering events")
+                .from_db()
+        }}
+    }
+
     pub async fn find_by_cipher_uuid(
         cipher_uuid: &CipherId,
         start: &NaiveDateTime,
@@ -329,17 +355,70 @@ impl Event {
         }}
     }
 
-    pub async fn clean_events(conn: &mut DbConn) -> EmptyResult {
-        if let Some(days_to_retain) = CONFIG.events_days_retain() {
-            let dt = Utc::now().naive_utc() - TimeDelta::try_days(days_to_retain).unwrap();
-            db_run! { conn: {
-                diesel::delete(event::table.filter(event::event_date.lt(dt)))
+    /// Like `find_by_cipher_uuid`, but scoped to the organization so it also works for ciphers which have been deleted.
+    pub async fn find_by_org_and_cipher(
+        org_uuid: &OrganizationId,
+        cipher_uuid: &CipherId,
+        start: &NaiveDateTime,
+        end: &NaiveDateTime,
+        conn: &mut DbConn,
+    ) -> Vec<Self> {
+        db_run! { conn: {
+            event::table
+                .filter(event::org_uuid.eq(org_uuid))
+                .filter(event::cipher_uuid.eq(cipher_uuid))
+                .filter(event::event_date.between(start, end))
+                .order_by(event::event_date.desc())
+                .limit(Self::PAGE_SIZE)
+                .load::<EventDb>(conn)
+                .expect("Error filtering events")
+                .from_db()
+        }}
+    }
+
+    /// Returns the oldest `limit` events of the organization, or of the events without an organization, which happened
+    /// before `dt`. The cleanup job loads and deletes them in batches of this size.
+    pub async fn find_expired(
+        org_uuid: Option<&OrganizationId>,
+        dt: &NaiveDateTime,
+        limit: i64,
+        conn: &mut DbConn,
+    ) -> Vec<Self> {
+        db_run! { conn: {
+            let query = event::table.filter(event::event_date.lt(dt)).into_boxed();
+            let query = match org_uuid {
+                Some(org_uuid) => query.filter(event::org_uuid.eq(org_uuid)),
+                None => query.filter(event::org_uuid.is_null()),
+            };
+            query
+                .order_by(event::event_date)
+                .limit(limit)
+                .load::<EventDb>(conn)
+                .expect("Error loading expired events")
+                .from_db()
+        }}
+    }
+
+    /// Returns the organizations which have been deleted but still have events.
+    pub async fn find_deleted_org_uuids(conn: &mut DbConn) -> Vec<OrganizationId> {
+        db_run! { conn: {
+            event::table
+                .left_join(organizations::table.on(event::org_uuid.eq(organizations::uuid.nullable())))
+                .filter(event::org_uuid.is_not_null())
+                .filter(organizations::uuid.is_null())
+                .select(event::org_uuid.assume_not_null())
+                .distinct()
+                .load::<OrganizationId>(conn)
+                .unwrap_or_default()
+        }}
+    }
+
+    pub async fn delete_all_by_uuids(uuids: &[EventId], conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(event::table.filter(event::uuid.eq_any(uuids)))
                 .execute(conn)
                 .map_res("Error cleaning old events")
-            }}
-        } else {
-            Ok(())
-        }
+        }}
     }
 }
 
@@ -66,6 +66,38 @@ impl Favorite {
         }
     }
 
+    // Sets the favorite status of the ciphers at once, the caller updates the revision of the user.
+    pub async fn set_favorite_bulk(
+        favorite: bool,
+        cipher_uuids: &[CipherId],
+        user_uuid: &UserId,
+        conn: &mut DbConn,
+    ) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(
+                favorites::table
+                    .filter(favorites::user_uuid.eq(user_uuid))
+                    .filter(favorites::cipher_uuid.eq_any(cipher_uuids)),
+            )
+            .execute(conn)
+            .map_res("Error removing favorites")
+        }}?;
+
+        if !favorite {
+            return Ok(());
+        }
+        db_run! { conn: {
+            let values: Vec<_> = cipher_uuids
+                .iter()
+                .map(|cipher_uuid| (favorites::user_uuid.eq(user_uuid), favorites::cipher_uuid.eq(cipher_uuid)))
+                .collect();
+            diesel::insert_into(favorites::table)
+                .values(&values)
+                .execute(conn)
+                .map_res("Error adding favorites")
+        }}
+    }
+
     // Delete all favorite entries associated with the specified cipher.
     pub async fn delete_all_by_cipher(cipher_uuid: &CipherId, conn: &mut DbConn) -> EmptyResult {
         db_run! { conn: {
@@ -195,6 +195,43 @@ impl FolderCipher {
         }}
     }
 
+    /// Moves the ciphers to the folder of the user, or out of their folders when `folder_uuid` is `None`.
+    /// The revision of the user isn't updated here, the caller updates it once for all the ciphers.
+    pub async fn move_ciphers(
+        folder_uuid: Option<&FolderId>,
+        cipher_uuids: &[CipherId],
+        user_uuid: &UserId,
+        conn: &mut DbConn,
+    ) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(
+                folders_ciphers::table
+                    .filter(folders_ciphers::cipher_uuid.eq_any(cipher_uuids))
+                    .filter(folders_ciphers::folder_uuid.eq_any(
+                        folders::table.filter(folders::user_uuid.eq(user_uuid)).select(folders::uuid),
+                    )),
+            )
+            .execute(conn)
+            .map_res("Error removing ciphers from folders")
+        }}?;
+
+        let Some(folder_uuid) = folder_uuid else {
+            return Ok(());
+        };
+        db_run! { conn: {
+            let values: Vec<_> = cipher_uuids
+                .iter()
+                .map(|cipher_uuid| {
+                    (folders_ciphers::cipher_uuid.eq(cipher_uuid), folders_ciphers::folder_uuid.eq(folder_uuid))
+                })
+                .collect();
+            diesel::insert_into(folders_ciphers::table)
+                .values(&values)
+                .execute(conn)
+                .map_res("Error adding ciphers to folder")
+        }}
+    }
+
     pub async fn find_by_folder_and_cipher(
         folder_uuid: &FolderId,
         cipher_uuid: &CipherId,
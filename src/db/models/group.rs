@@ -1,4 +1,4 @@
-use super::{CollectionId, Membership, MembershipId, OrganizationId, User, UserId};
+use super::{CollectionId, Membership, MembershipId, MembershipType, OrganizationId, User, UserId};
 use crate::api::EmptyResult;
 use crate::db::DbConn;
 use crate::error::MapResult;
@@ -78,9 +78,6 @@ impl Group {
     }
 
     pub async fn to_json_details(&self, conn: &mut DbConn) -> Value {
-        // If both read_only and hide_passwords are false, then manage should be true
-        // You can't have an entry with read_only and manage, or hide_passwords and manage
-        // Or an entry with everything to false
         let collections_groups: Vec<Value> = CollectionGroup::find_by_group(&self.uuid, conn)
             .await
             .iter()
@@ -122,25 +119,28 @@ impl CollectionGroup {
         hide_passwords: bool,
         manage: bool,
     ) -> Self {
+        // Managing a collection includes editing its items and seeing their passwords
         Self {
             collections_uuid,
             groups_uuid,
-            read_only,
-            hide_passwords,
+            read_only: read_only && !manage,
+            hide_passwords: hide_passwords && !manage,
             manage,
         }
     }
 
+    /// Managers used to manage every collection they could edit. For the collections of their groups this isn't
+    /// stored per member, it is derived from the group, so it ends when the member leaves the group.
+    pub fn manage_for(&self, member_type: i32) -> bool {
+        self.manage || (member_type == MembershipType::Manager && !self.read_only && !self.hide_passwords)
+    }
+
     pub fn to_json_details_for_group(&self) -> Value {
-        // If both read_only and hide_passwords are false, then manage should be true
-        // You can't have an entry with read_only and manage, or hide_passwords and manage
-        // Or an entry with everything to false
-        // For backwards compatibility and migration proposes we keep checking read_only and hide_password
         json!({
             "id": self.groups_uuid,
             "readOnly": self.read_only,
             "hidePasswords": self.hide_passwords,
-            "manage": self.manage || (!self.read_only && !self.hide_passwords),
+            "manage": self.manage,
         })
     }
 }
@@ -0,0 +1,182 @@
+use bigdecimal::{BigDecimal, ToPrimitive};
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+
+use crate::api::EmptyResult;
+use crate::db::DbConn;
+use crate::error::MapResult;
+use crate::CONFIG;
+
+db_object! {
+    // Metadata of the icons in the icon cache, the icons themselves are stored in `ICON_CACHE_FOLDER`
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = icon_cache)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(domain))]
+    pub struct IconCache {
+        pub domain: String,
+        pub icon_type: Option<String>,
+        // Hash of the stored icon, sent to the clients as the `ETag`
+        pub etag: Option<String>,
+        // The URL the icon was downloaded from and its validators, used to revalidate an expired icon
+        pub upstream_url: Option<String>,
+        pub upstream_etag: Option<String>,
+        pub upstream_last_modified: Option<String>,
+        pub miss: bool,
+        pub fetched_at: NaiveDateTime,
+        // Size of the stored icon, 0 for a miss or when the icon was stored before the size was recorded
+        pub file_size: i64,
+        // When the icon was last served, the least recently used icons are evicted first, see `ICON_CACHE_MAX_SIZE`
+        pub accessed_at: NaiveDateTime,
+    }
+}
+
+// Serving an icon only updates `accessed_at` when it is older than this, so not every request writes to the database
+const ACCESS_RESOLUTION_MINUTES: i64 = 60;
+
+/// Local methods
+impl IconCache {
+    pub fn new(domain: String) -> Self {
+        let now = Utc::now().naive_utc();
+        Self {
+            domain,
+            icon_type: None,
+            etag: None,
+            upstream_url: None,
+            upstream_etag: None,
+            upstream_last_modified: None,
+            miss: false,
+            fetched_at: now,
+            file_size: 0,
+            accessed_at: now,
+        }
+    }
+
+    pub fn new_miss(domain: String) -> Self {
+        Self {
+            miss: true,
+            ..Self::new(domain)
+        }
+    }
+
+    /// Returns if the entry is older than `ICON_CACHE_TTL`, or `ICON_CACHE_NEGTTL` for a miss.
+    pub fn is_expired(&self) -> bool {
+        let ttl = if self.miss {
+            CONFIG.icon_cache_negttl()
+        } else {
+            CONFIG.icon_cache_ttl()
+        };
+        let Some(ttl) = i64::try_from(ttl).ok().and_then(TimeDelta::try_seconds) else {
+            return false;
+        };
+
+        ttl > TimeDelta::zero()
+            && self.fetched_at.checked_add_signed(ttl).is_some_and(|expiry| expiry <= Utc::now().naive_utc())
+    }
+
+    pub fn can_revalidate(&self) -> bool {
+        !self.miss
+            && self.upstream_url.is_some()
+            && (self.upstream_etag.is_some() || self.upstream_last_modified.is_some())
+    }
+}
+
+/// Database methods
+impl IconCache {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(icon_cache::table)
+                    .values(IconCacheDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving icon cache entry")
+            }
+            postgresql {
+                let value = IconCacheDb::to_db(self);
+                diesel::insert_into(icon_cache::table)
+                    .values(&value)
+                    .on_conflict(icon_cache::domain)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving icon cache entry")
+            }
+        }
+    }
+
+    /// Records that the icon was served, at most once every `ACCESS_RESOLUTION_MINUTES`.
+    pub async fn touch(&mut self, conn: &mut DbConn) -> EmptyResult {
+        let now = Utc::now().naive_utc();
+        if now - self.accessed_at < TimeDelta::minutes(ACCESS_RESOLUTION_MINUTES) {
+            return Ok(());
+        }
+        self.accessed_at = now;
+
+        db_run! { conn: {
+            diesel::update(icon_cache::table.filter(icon_cache::domain.eq(&self.domain)))
+                .set(icon_cache::accessed_at.eq(now))
+                .execute(conn)
+                .map_res("Error updating icon cache access")
+        }}
+    }
+
+    pub async fn delete(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(icon_cache::table.filter(icon_cache::domain.eq(&self.domain)))
+                .execute(conn)
+                .map_res("Error deleting icon cache entry")
+        }}
+    }
+
+    /// The total size of the stored icons.
+    pub async fn total_size(conn: &mut DbConn) -> i64 {
+        db_run! { conn: {
+            let result: Option<BigDecimal> = icon_cache::table
+                .filter(icon_cache::miss.eq(false))
+                .select(diesel::dsl::sum(icon_cache::file_size))
+                .first(conn)
+                .expect("Error loading icon cache total size");
+
+            match result.map(|r| r.to_i64()) {
+                Some(Some(r)) => r,
+                Some(None) => i64::MAX,
+                None => 0
+            }
+        }}
+    }
+
+    /// The stored icons which were served the longest time ago.
+    pub async fn find_least_recently_used(limit: i64, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            icon_cache::table
+                .filter(icon_cache::miss.eq(false))
+                .order(icon_cache::accessed_at.asc())
+                .limit(limit)
+                .load::<IconCacheDb>(conn)
+                .expect("Error loading icon cache entries")
+                .from_db()
+        }}
+    }
+
+    /// The stored icons without a recorded size, from before the size was recorded.
+    pub async fn find_without_size(limit: i64, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            icon_cache::table
+                .filter(icon_cache::miss.eq(false))
+                .filter(icon_cache::file_size.eq(0))
+                .limit(limit)
+                .load::<IconCacheDb>(conn)
+                .expect("Error loading icon cache entries")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_domain(domain: &str, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            icon_cache::table
+                .filter(icon_cache::domain.eq(domain))
+                .first::<IconCacheDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+}
@@ -0,0 +1,243 @@
+// Finds the rows pointing to a parent which doesn't exist anymore, see the `check-db` command and `/admin/db-integrity`.
+// SQLite doesn't always enforce the foreign keys and older versions didn't clean up everything, so these can exist.
+
+use std::collections::BTreeSet;
+
+use serde_json::Value;
+
+use super::{
+    Attachment, Cipher, CipherId, Collection, Device, Folder, Membership, OrganizationId, Report, ReportHistory,
+    ReportMember, Send, UserId,
+};
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::{Error, MapResult},
+};
+
+// The number of ids listed per check, the counts are always complete
+const LISTED_IDS: usize = 50;
+
+// Loads the `$column` of the rows of `$table` which point to a row of `$parent` which doesn't exist, once per row.
+// The `nullable` form is for optional references, rows without one are fine.
+macro_rules! find_orphans {
+    ($conn:ident, $table:ident::$column:ident => $parent:ident::$key:ident) => {
+        db_run! {@raw $conn: {
+            $table::table
+                .left_join($parent::table.on($parent::$key.eq($table::$column)))
+                .filter($parent::$key.is_null())
+                .select($table::$column)
+                .load::<String>($conn)
+                .map_res(concat!("Error checking ", stringify!($table)))
+        }}
+    };
+    (nullable $conn:ident, $table:ident::$column:ident => $parent:ident::$key:ident) => {
+        db_run! {@raw $conn: {
+            $table::table
+                .left_join($parent::table.on($table::$column.eq($parent::$key.nullable())))
+                .filter($table::$column.is_not_null())
+                .filter($parent::$key.is_null())
+                .select($table::$column.assume_not_null())
+                .load::<String>($conn)
+                .map_res(concat!("Error checking ", stringify!($table)))
+        }}
+    };
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum IntegrityCheck {
+    CiphersWithoutUser,
+    CiphersWithoutOrganization,
+    CiphersWithoutOwner,
+    AttachmentsWithoutCipher,
+    MembershipsWithoutUser,
+    MembershipsWithoutOrganization,
+    CollectionsWithoutOrganization,
+    FoldersWithoutUser,
+    DevicesWithoutUser,
+    SendsWithoutUser,
+    ReportsWithoutUser,
+    ReportsWithoutOrganization,
+    ReportMembersWithoutOrganization,
+    ReportHistoryWithoutOrganization,
+}
+
+/// The rows of one check, `ids` are the ones its repair works on: the missing parents, or the rows themselves.
+pub struct IntegrityIssue {
+    pub check: IntegrityCheck,
+    pub rows: usize,
+    pub ids: BTreeSet<String>,
+}
+
+impl IntegrityCheck {
+    // The items come first, their repair also removes their attachments
+    pub const ALL: [Self; 14] = [
+        Self::CiphersWithoutUser,
+        Self::CiphersWithoutOrganization,
+        Self::CiphersWithoutOwner,
+        Self::AttachmentsWithoutCipher,
+        Self::MembershipsWithoutUser,
+        Self::MembershipsWithoutOrganization,
+        Self::CollectionsWithoutOrganization,
+        Self::FoldersWithoutUser,
+        Self::DevicesWithoutUser,
+        Self::SendsWithoutUser,
+        Self::ReportsWithoutUser,
+        Self::ReportsWithoutOrganization,
+        Self::ReportMembersWithoutOrganization,
+        Self::ReportHistoryWithoutOrganization,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::CiphersWithoutUser => "ciphersWithoutUser",
+            Self::CiphersWithoutOrganization => "ciphersWithoutOrganization",
+            Self::CiphersWithoutOwner => "ciphersWithoutOwner",
+            Self::AttachmentsWithoutCipher => "attachmentsWithoutCipher",
+            Self::MembershipsWithoutUser => "membershipsWithoutUser",
+            Self::MembershipsWithoutOrganization => "membershipsWithoutOrganization",
+            Self::CollectionsWithoutOrganization => "collectionsWithoutOrganization",
+            Self::FoldersWithoutUser => "foldersWithoutUser",
+            Self::DevicesWithoutUser => "devicesWithoutUser",
+            Self::SendsWithoutUser => "sendsWithoutUser",
+            Self::ReportsWithoutUser => "reportsWithoutUser",
+            Self::ReportsWithoutOrganization => "reportsWithoutOrganization",
+            Self::ReportMembersWithoutOrganization => "reportMembersWithoutOrganization",
+            Self::ReportHistoryWithoutOrganization => "reportHistoryWithoutOrganization",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::CiphersWithoutUser => "Personal items of users which don't exist (ids of the users)",
+            Self::CiphersWithoutOrganization => "Items of organizations which don't exist (ids of the organizations)",
+            Self::CiphersWithoutOwner => "Items without a user or an organization (ids of the items)",
+            Self::AttachmentsWithoutCipher => "Attachments of items which don't exist (ids of the items)",
+            Self::MembershipsWithoutUser => "Memberships of users which don't exist (ids of the users)",
+            Self::MembershipsWithoutOrganization => {
+                "Memberships of organizations which don't exist (ids of the organizations)"
+            }
+            Self::CollectionsWithoutOrganization => {
+                "Collections of organizations which don't exist (ids of the organizations)"
+            }
+            Self::FoldersWithoutUser => "Folders of users which don't exist (ids of the users)",
+            Self::DevicesWithoutUser => "Devices of users which don't exist (ids of the users)",
+            Self::SendsWithoutUser => "Sends of users which don't exist (ids of the users)",
+            Self::ReportsWithoutUser => "Exposed password reports of users which don't exist (ids of the users)",
+            Self::ReportsWithoutOrganization => {
+                "Exposed password reports of organizations which don't exist (ids of the organizations)"
+            }
+            Self::ReportMembersWithoutOrganization => {
+                "Member reports of organizations which don't exist (ids of the organizations)"
+            }
+            Self::ReportHistoryWithoutOrganization => {
+                "Report history of organizations which don't exist (ids of the organizations)"
+            }
+        }
+    }
+
+    /// Returns the id of one affected row each, the same id is listed for every row of a missing parent.
+    async fn find(self, conn: &mut DbConn) -> Result<Vec<String>, Error> {
+        match self {
+            Self::CiphersWithoutUser => find_orphans!(nullable conn, ciphers::user_uuid => users::uuid),
+            Self::CiphersWithoutOrganization => {
+                find_orphans!(nullable conn, ciphers::organization_uuid => organizations::uuid)
+            }
+            Self::CiphersWithoutOwner => db_run! {@raw conn: {
+                ciphers::table
+                    .filter(ciphers::user_uuid.is_null())
+                    .filter(ciphers::organization_uuid.is_null())
+                    .select(ciphers::uuid)
+                    .load::<String>(conn)
+                    .map_res("Error checking ciphers")
+            }},
+            Self::AttachmentsWithoutCipher => find_orphans!(conn, attachments::cipher_uuid => ciphers::uuid),
+            Self::MembershipsWithoutUser => find_orphans!(conn, users_organizations::user_uuid => users::uuid),
+            Self::MembershipsWithoutOrganization => {
+                find_orphans!(conn, users_organizations::org_uuid => organizations::uuid)
+            }
+            Self::CollectionsWithoutOrganization => find_orphans!(conn, collections::org_uuid => organizations::uuid),
+            Self::FoldersWithoutUser => find_orphans!(conn, folders::user_uuid => users::uuid),
+            Self::DevicesWithoutUser => find_orphans!(conn, devices::user_uuid => users::uuid),
+            Self::SendsWithoutUser => find_orphans!(nullable conn, sends::user_uuid => users::uuid),
+            Self::ReportsWithoutUser => find_orphans!(nullable conn, reports::user_uuid => users::uuid),
+            Self::ReportsWithoutOrganization => find_orphans!(nullable conn, reports::org_uuid => organizations::uuid),
+            Self::ReportMembersWithoutOrganization => {
+                find_orphans!(conn, report_members::org_uuid => organizations::uuid)
+            }
+            Self::ReportHistoryWithoutOrganization => {
+                find_orphans!(conn, report_history::org_uuid => organizations::uuid)
+            }
+        }
+    }
+
+    /// Removes the rows of one id returned by `find`, with the methods of the models so their files
+    /// and dependent rows are removed as well.
+    async fn repair(self, id: &str, conn: &mut DbConn) -> EmptyResult {
+        let user_id = || UserId::from(id.to_string());
+        let org_id = || OrganizationId::from(id.to_string());
+        match self {
+            Self::CiphersWithoutUser => Cipher::delete_all_by_user(&user_id(), conn).await,
+            Self::CiphersWithoutOrganization => Cipher::delete_all_by_organization(&org_id(), conn).await,
+            Self::CiphersWithoutOwner => match Cipher::find_by_uuid(&CipherId::from(id.to_string()), conn).await {
+                Some(cipher) => cipher.delete(conn).await,
+                None => Ok(()),
+            },
+            Self::AttachmentsWithoutCipher => {
+                Attachment::delete_all_by_cipher(&CipherId::from(id.to_string()), conn).await
+            }
+            Self::MembershipsWithoutUser => Membership::delete_all_by_user(&user_id(), conn).await,
+            Self::MembershipsWithoutOrganization => Membership::delete_all_by_organization(&org_id(), conn).await,
+            Self::CollectionsWithoutOrganization => Collection::delete_all_by_organization(&org_id(), conn).await,
+            Self::FoldersWithoutUser => Folder::delete_all_by_user(&user_id(), conn).await,
+            Self::DevicesWithoutUser => Device::delete_all_by_user(&user_id(), conn).await,
+            Self::SendsWithoutUser => Send::delete_all_by_user(&user_id(), conn).await,
+            Self::ReportsWithoutUser => Report::delete_all_by_user(&user_id(), conn).await,
+            Self::ReportsWithoutOrganization => Report::delete_all_by_organization(&org_id(), conn).await,
+            Self::ReportMembersWithoutOrganization => ReportMember::delete_all_by_organization(&org_id(), conn).await,
+            Self::ReportHistoryWithoutOrganization => ReportHistory::delete_all_by_organization(&org_id(), conn).await,
+        }
+    }
+}
+
+impl IntegrityIssue {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.check.name(),
+            "description": self.check.description(),
+            "rows": self.rows,
+            "idCount": self.ids.len(),
+            "ids": self.ids.iter().take(LISTED_IDS).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Runs all the checks, only the checks which found rows are returned.
+pub async fn check_integrity(conn: &mut DbConn) -> Result<Vec<IntegrityIssue>, Error> {
+    let mut issues = Vec::new();
+    for check in IntegrityCheck::ALL {
+        let ids = check.find(conn).await?;
+        if !ids.is_empty() {
+            issues.push(IntegrityIssue {
+                check,
+                rows: ids.len(),
+                ids: ids.into_iter().collect(),
+            });
+        }
+    }
+    Ok(issues)
+}
+
+/// Removes the rows found by the checks, and returns how many were found. Each check runs again right
+/// before its repair, so the rows removed by the earlier repairs aren't counted twice.
+pub async fn repair_integrity(conn: &mut DbConn) -> Result<usize, Error> {
+    let mut repaired = 0;
+    for check in IntegrityCheck::ALL {
+        let ids = check.find(conn).await?;
+        repaired += ids.len();
+        for id in ids.into_iter().collect::<BTreeSet<_>>() {
+            check.repair(&id, conn).await?;
+        }
+    }
+    Ok(repaired)
+}
@@ -0,0 +1,105 @@
+use chrono::{NaiveDateTime, Utc};
+
+use super::UserId;
+use crate::api::EmptyResult;
+use crate::auth::{decrypt_at_rest, encrypt_at_rest, is_encrypted_at_rest};
+use crate::db::DbConn;
+use crate::error::{Error, MapResult};
+
+db_object! {
+    // Master keys stored by the built-in Key Connector, used when `SSO_KEY_CONNECTOR_URL` is not set
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = key_connector_keys)]
+    #[diesel(primary_key(user_uuid))]
+    pub struct KeyConnectorKey {
+        pub user_uuid: UserId,
+        // Encrypted with the data key, see `auth::encrypt_at_rest`
+        akey: String,
+        pub creation_date: NaiveDateTime,
+        pub revision_date: NaiveDateTime,
+    }
+}
+
+/// Local methods
+impl KeyConnectorKey {
+    pub fn new(user_uuid: UserId, key: &str) -> Result<Self, Error> {
+        let now = Utc::now().naive_utc();
+
+        Ok(Self {
+            akey: encrypt_at_rest(key, &user_uuid)?,
+            user_uuid,
+            creation_date: now,
+            revision_date: now,
+        })
+    }
+
+    pub fn key(&self) -> Result<String, Error> {
+        decrypt_at_rest(&self.akey, &self.user_uuid)
+    }
+
+    pub fn set_key(&mut self, key: &str) -> EmptyResult {
+        self.akey = encrypt_at_rest(key, &self.user_uuid)?;
+        Ok(())
+    }
+}
+
+/// Database methods
+impl KeyConnectorKey {
+    pub async fn save(&mut self, conn: &mut DbConn) -> EmptyResult {
+        self.revision_date = Utc::now().naive_utc();
+
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(key_connector_keys::table)
+                    .values(KeyConnectorKeyDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving key connector key")
+            }
+            postgresql {
+                let value = KeyConnectorKeyDb::to_db(self);
+                diesel::insert_into(key_connector_keys::table)
+                    .values(&value)
+                    .on_conflict(key_connector_keys::user_uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving key connector key")
+            }
+        }
+    }
+
+    pub async fn delete_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(key_connector_keys::table.filter(key_connector_keys::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting key connector key")
+        }}
+    }
+
+    /// Encrypts the keys stored before they were encrypted at rest, runs on every start.
+    pub async fn encrypt_plaintext_keys(conn: &mut DbConn) -> EmptyResult {
+        let keys: Vec<Self> = db_run! { conn: {
+            key_connector_keys::table
+                .load::<KeyConnectorKeyDb>(conn)
+                .expect("Error loading key connector keys")
+                .from_db()
+        }};
+
+        for mut key in keys.into_iter().filter(|key| !is_encrypted_at_rest(&key.akey)) {
+            let plaintext = std::mem::take(&mut key.akey);
+            key.set_key(&plaintext)?;
+            key.save(conn).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn find_by_user(user_uuid: &UserId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            key_connector_keys::table
+                .filter(key_connector_keys::user_uuid.eq(user_uuid))
+                .first::<KeyConnectorKeyDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+}
@@ -0,0 +1,148 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::UserId;
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+    CONFIG,
+};
+use macros::UuidFromParam;
+
+db_object! {
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = login_events)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(uuid))]
+    pub struct LoginEvent {
+        pub uuid: LoginEventId,
+        // Not set when the username didn't match any user
+        pub user_uuid: Option<UserId>,
+        pub username: Option<String>,
+        pub grant_type: String,
+        pub ip_address: String,
+        pub device_type: Option<i32>,
+        pub device_name: Option<String>,
+        pub user_agent: Option<String>,
+        pub country: Option<String>,
+        pub success: bool,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct LoginEventId(String);
+
+/// Local methods
+impl LoginEvent {
+    /// Number of login events returned per user.
+    pub const PAGE_SIZE: i64 = 50;
+
+    pub fn new(grant_type: String, ip_address: String, success: bool) -> Self {
+        Self {
+            uuid: LoginEventId::from(get_uuid()),
+            user_uuid: None,
+            username: None,
+            grant_type,
+            ip_address,
+            device_type: None,
+            device_name: None,
+            user_agent: None,
+            country: None,
+            success,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.uuid,
+            "userId": self.user_uuid,
+            "username": self.username,
+            "grantType": self.grant_type,
+            "ipAddress": self.ip_address,
+            "deviceType": self.device_type,
+            "deviceName": self.device_name,
+            "userAgent": self.user_agent,
+            "country": self.country,
+            "success": self.success,
+            "date": format_date(&self.created_at),
+            "object": "loginEvent",
+        })
+    }
+}
+
+/// Database methods
+impl LoginEvent {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::insert_into(login_events::table)
+                .values(LoginEventDb::to_db(self))
+                .execute(conn)
+                .map_res("Error saving login event")
+        }}
+    }
+
+    pub async fn find_by_user(user_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            login_events::table
+                .filter(login_events::user_uuid.eq(user_uuid))
+                .order_by(login_events::created_at.desc())
+                .limit(Self::PAGE_SIZE)
+                .load::<LoginEventDb>(conn)
+                .expect("Error loading login events")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            login_events::table
+                .filter(login_events::user_uuid.eq(user_uuid))
+                .order_by(login_events::created_at.desc())
+                .load::<LoginEventDb>(conn)
+                .expect("Error loading login events")
+                .from_db()
+        }}
+    }
+
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(login_events::table.filter(login_events::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting login events")
+        }}
+    }
+
+    pub async fn clean_old(conn: &mut DbConn) -> EmptyResult {
+        if let Some(days_to_retain) = CONFIG.login_events_days_retain() {
+            let dt = Utc::now().naive_utc() - TimeDelta::try_days(days_to_retain).unwrap();
+            db_run! { conn: {
+                diesel::delete(login_events::table.filter(login_events::created_at.lt(dt)))
+                    .execute(conn)
+                    .map_res("Error cleaning old login events")
+            }}
+        } else {
+            Ok(())
+        }
+    }
+}
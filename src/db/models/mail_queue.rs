@@ -0,0 +1,232 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use crate::{
+    api::EmptyResult,
+    auth::{decrypt_at_rest, encrypt_at_rest, is_encrypted_at_rest},
+    db::DbConn,
+    error::{Error, MapResult},
+    util::{format_date, get_uuid},
+    CONFIG,
+};
+use macros::UuidFromParam;
+
+db_object! {
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = mail_queue)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(uuid))]
+    pub struct QueuedMail {
+        pub uuid: QueuedMailId,
+        pub address: String,
+        pub subject: String,
+        // Encrypted with the data key, see `auth::encrypt_at_rest`, the bodies can contain links with tokens
+        body_html: String,
+        body_text: String,
+        pub attempts: i32,
+        pub last_error: Option<String>,
+        pub created_at: NaiveDateTime,
+        pub next_attempt_at: NaiveDateTime,
+        // Set when the mail isn't retried anymore, it then stays in the queue until an admin retries or deletes it
+        pub failed_at: Option<NaiveDateTime>,
+        pub reply_to: Option<String>,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct QueuedMailId(String);
+
+/// Local methods
+impl QueuedMail {
+    /// The longest wait between two attempts, the wait doubles after every failed attempt until then.
+    const MAX_RETRY_DELAY_MINUTES: i64 = 12 * 60;
+    /// How long a mail stays claimed by the instance sending it, longer than a send can take.
+    const CLAIM_MINUTES: i64 = 10;
+
+    pub fn new(
+        address: String,
+        subject: String,
+        body_html: &str,
+        body_text: &str,
+        reply_to: Option<String>,
+    ) -> Result<Self, Error> {
+        let now = Utc::now().naive_utc();
+        let uuid = QueuedMailId::from(get_uuid());
+
+        Ok(Self {
+            body_html: encrypt_at_rest(body_html, &uuid)?,
+            body_text: encrypt_at_rest(body_text, &uuid)?,
+            uuid,
+            address,
+            subject,
+            attempts: 0,
+            last_error: None,
+            created_at: now,
+            next_attempt_at: now,
+            failed_at: None,
+            reply_to,
+        })
+    }
+
+    /// The HTML and text bodies, mails queued before the bodies were encrypted are returned as they are.
+    pub fn bodies(&self) -> Result<(String, String), Error> {
+        let decrypt = |body: &str| {
+            if is_encrypted_at_rest(body) {
+                decrypt_at_rest(body, &self.uuid)
+            } else {
+                Ok(body.to_string())
+            }
+        };
+        Ok((decrypt(&self.body_html)?, decrypt(&self.body_text)?))
+    }
+
+    /// Schedules the next attempt with an exponential backoff, or gives up after `MAIL_QUEUE_MAX_ATTEMPTS`.
+    pub fn register_failure(&mut self, error: String) {
+        let now = Utc::now().naive_utc();
+        self.attempts += 1;
+        self.last_error = Some(error);
+
+        if self.attempts >= CONFIG.mail_queue_max_attempts() {
+            self.failed_at = Some(now);
+        } else {
+            let delay = (1i64 << self.attempts.min(10)).min(Self::MAX_RETRY_DELAY_MINUTES);
+            self.next_attempt_at = now + TimeDelta::try_minutes(delay).unwrap();
+        }
+    }
+
+    /// Puts a failed mail back in the queue, it is then sent by the next run of the mail queue job.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.failed_at = None;
+        self.next_attempt_at = Utc::now().naive_utc();
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.uuid,
+            "address": self.address,
+            "subject": self.subject,
+            "attempts": self.attempts,
+            "lastError": self.last_error,
+            "creationDate": format_date(&self.created_at),
+            "nextAttemptDate": format_date(&self.next_attempt_at),
+            "failedDate": self.failed_at.as_ref().map(format_date),
+            "object": "queuedMail",
+        })
+    }
+}
+
+/// Database methods
+impl QueuedMail {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(mail_queue::table)
+                    .values(QueuedMailDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving queued mail")
+            }
+            postgresql {
+                let value = QueuedMailDb::to_db(self);
+                diesel::insert_into(mail_queue::table)
+                    .values(&value)
+                    .on_conflict(mail_queue::uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving queued mail")
+            }
+        }
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(mail_queue::table.filter(mail_queue::uuid.eq(self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting queued mail")
+        }}
+    }
+
+    pub async fn find_by_uuid(uuid: &QueuedMailId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            mail_queue::table
+                .filter(mail_queue::uuid.eq(uuid))
+                .first::<QueuedMailDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    /// The mails which are still retried and due for their next attempt, the oldest first.
+    pub async fn find_due(now: &NaiveDateTime, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            mail_queue::table
+                .filter(mail_queue::failed_at.is_null())
+                .filter(mail_queue::next_attempt_at.le(now))
+                .order_by(mail_queue::created_at.asc())
+                .load::<QueuedMailDb>(conn)
+                .expect("Error loading queued mails")
+                .from_db()
+        }}
+    }
+
+    /// Claims a due mail by pushing its next attempt back, so the other instances sharing the database skip it
+    /// while this one sends it. Returns false when another instance claimed it first.
+    pub async fn claim(&mut self, now: &NaiveDateTime, conn: &mut DbConn) -> bool {
+        let claimed_until = *now + TimeDelta::try_minutes(Self::CLAIM_MINUTES).unwrap();
+        let updated: usize = db_run! { conn: {
+            diesel::update(
+                mail_queue::table
+                    .filter(mail_queue::uuid.eq(&self.uuid))
+                    .filter(mail_queue::failed_at.is_null())
+                    .filter(mail_queue::next_attempt_at.le(now)),
+            )
+            .set(mail_queue::next_attempt_at.eq(claimed_until))
+            .execute(conn)
+            .unwrap_or(0)
+        }};
+        if updated == 1 {
+            self.next_attempt_at = claimed_until;
+        }
+        updated == 1
+    }
+
+    pub async fn find_failed(conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            mail_queue::table
+                .filter(mail_queue::failed_at.is_not_null())
+                .order_by(mail_queue::failed_at.desc())
+                .load::<QueuedMailDb>(conn)
+                .expect("Error loading failed mails")
+                .from_db()
+        }}
+    }
+
+    pub async fn count_pending(conn: &mut DbConn) -> i64 {
+        db_run! { conn: {
+            mail_queue::table
+                .filter(mail_queue::failed_at.is_null())
+                .count()
+                .first::<i64>(conn)
+                .ok()
+                .unwrap_or(0)
+        }}
+    }
+}
@@ -1,45 +1,115 @@
+mod admin_two_factor;
 mod attachment;
+mod attachment_upload;
 mod auth_request;
+mod breach_finding;
+mod change;
 mod cipher;
+mod cipher_history;
+mod cipher_share;
 mod collection;
+mod daily_stat;
+mod database_copy;
 mod device;
 mod emergency_access;
 mod event;
 mod favorite;
 mod folder;
 mod group;
+mod icon_cache;
+mod integrity_check;
+mod key_connector_key;
+mod login_event;
+mod mail_queue;
+mod org_domain;
 mod org_policy;
+mod org_risk_score;
+mod org_template;
+mod org_two_factor;
 mod organization;
+mod personal_api_token;
+mod policy_violation;
+mod project;
+mod provider;
+mod quarantined_file;
+mod refresh_token_family;
 mod report;
+mod report_member;
+mod secret;
 mod send;
+mod send_access;
+mod service_account;
+mod signup_rule;
 mod sso_nonce;
 mod two_factor;
 mod two_factor_duo_context;
 mod two_factor_incomplete;
 mod user;
+mod user_alias;
 
+pub use self::admin_two_factor::{AdminRecoveryCode, AdminTwoFactor};
 pub use self::attachment::{Attachment, AttachmentId};
+pub use self::attachment_upload::AttachmentUpload;
 pub use self::auth_request::{AuthRequest, AuthRequestId};
-pub use self::cipher::{Cipher, CipherId, RepromptType};
+pub use self::breach_finding::{BreachFinding, BreachFindingId};
+pub use self::change::{Change, ChangeAction, ChangeEntity};
+pub use self::cipher::{Cipher, CipherId, CipherType, RepromptType};
+pub use self::cipher_history::{CipherHistory, CipherHistoryId};
+pub use self::cipher_share::{CipherShare, CipherShareId};
 pub use self::collection::{Collection, CollectionCipher, CollectionId, CollectionUser};
+pub use self::daily_stat::DailyStat;
+pub use self::database_copy::{copy_database, CopySummary};
 pub use self::device::{Device, DeviceId, DeviceType, PushId};
 pub use self::emergency_access::{EmergencyAccess, EmergencyAccessId, EmergencyAccessStatus, EmergencyAccessType};
 pub use self::event::{Event, EventType};
 pub use self::favorite::Favorite;
 pub use self::folder::{Folder, FolderCipher, FolderId};
 pub use self::group::{CollectionGroup, Group, GroupId, GroupUser};
-pub use self::org_policy::{OrgPolicy, OrgPolicyErr, OrgPolicyId, OrgPolicyType};
+pub use self::icon_cache::IconCache;
+pub use self::integrity_check::{check_integrity, repair_integrity, IntegrityCheck, IntegrityIssue};
+pub use self::key_connector_key::KeyConnectorKey;
+pub use self::login_event::{LoginEvent, LoginEventId};
+pub use self::mail_queue::{QueuedMail, QueuedMailId};
+pub use self::org_domain::{OrgDomain, OrgDomainId};
+pub use self::org_policy::{
+    CipherHistoryPolicyData, OrgPolicy, OrgPolicyErr, OrgPolicyId, OrgPolicyType, OrgSendsPolicyData,
+    PasswordAgePolicyData, PasswordGeneratorPolicyData, PersonalOwnershipPolicyData, SessionLifetimePolicyData,
+    TwoFactorDeadlinePolicyData, VerifiedEmailPolicyData,
+};
+pub use self::org_risk_score::OrgRiskScore;
+pub use self::org_template::{
+    OrgTemplate, OrgTemplateCollection, OrgTemplateCollectionGroup, OrgTemplateData, OrgTemplateGroup,
+    OrgTemplatePolicy,
+};
+pub use self::org_two_factor::{OrgTwoFactor, OrgTwoFactorId};
 pub use self::organization::{
-    Membership, MembershipId, MembershipStatus, MembershipType, OrgApiKeyId, Organization, OrganizationApiKey,
-    OrganizationId,
+    Membership, MembershipId, MembershipStatus, MembershipType, OrgApiKeyId, OrgPermission, Organization,
+    OrganizationApiKey, OrganizationId, SensitiveAction,
 };
-pub use self::report::Report;
+pub use self::personal_api_token::{PersonalApiToken, PersonalApiTokenId, PersonalApiTokenScope};
+pub use self::policy_violation::{PolicyViolation, PolicyViolationAction, PolicyViolationId};
+pub use self::project::{Project, ProjectId};
+pub use self::provider::{
+    Provider, ProviderId, ProviderOrganization, ProviderOrganizationId, ProviderUser, ProviderUserId,
+    ProviderUserStatus, ProviderUserType,
+};
+pub use self::quarantined_file::{QuarantinedFile, QuarantinedFileId};
+pub use self::refresh_token_family::RefreshTokenFamily;
+pub use self::report::{Report, ReportId};
+pub use self::report_member::{ReportHistory, ReportMember};
+pub use self::secret::{Secret, SecretId, SecretProject, SecretsRevision};
 pub use self::send::{
     id::{SendFileId, SendId},
     Send, SendType,
 };
+pub use self::send_access::{SendAccess, SendAccessId};
+pub use self::service_account::{
+    ServiceAccount, ServiceAccountId, ServiceAccountProject, ServiceAccountToken, ServiceAccountTokenId,
+};
+pub use self::signup_rule::{SignupRule, SignupRuleAction, SignupRuleId};
 pub use self::sso_nonce::SsoNonce;
 pub use self::two_factor::{TwoFactor, TwoFactorType};
 pub use self::two_factor_duo_context::TwoFactorDuoContext;
 pub use self::two_factor_incomplete::TwoFactorIncomplete;
 pub use self::user::{Invitation, SsoUser, User, UserId, UserKdfType, UserStampException};
+pub use self::user_alias::UserAlias;
@@ -0,0 +1,203 @@
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{CollectionId, MembershipType, OrganizationId, User};
+use crate::{
+    api::EmptyResult,
+    crypto,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+};
+use macros::UuidFromParam;
+
+db_object! {
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = org_domains)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(uuid))]
+    pub struct OrgDomain {
+        pub uuid: OrgDomainId,
+        pub org_uuid: OrganizationId,
+        pub domain_name: String,
+        pub verified_at: Option<NaiveDateTime>,
+        // Membership settings for users captured by this domain
+        pub member_type: i32,
+        pub collections: String, // JSON array of CollectionId
+        pub auto_confirm: bool,
+        pub created_at: NaiveDateTime,
+        // Value of the DNS TXT record which proves the organization controls the domain
+        pub txt: Option<String>,
+        pub last_checked_at: Option<NaiveDateTime>,
+        pub check_count: i32,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct OrgDomainId(String);
+
+/// Local methods
+impl OrgDomain {
+    pub fn new(org_uuid: OrganizationId, domain_name: &str) -> Self {
+        Self {
+            uuid: OrgDomainId::from(get_uuid()),
+            org_uuid,
+            domain_name: domain_name.trim().to_lowercase(),
+            verified_at: None,
+            member_type: MembershipType::User as i32,
+            collections: String::from("[]"),
+            auto_confirm: false,
+            created_at: Utc::now().naive_utc(),
+            txt: Some(format!("bw={}", crypto::generate_id::<32>())),
+            last_checked_at: None,
+            check_count: 0,
+        }
+    }
+
+    pub fn get_collections(&self) -> Vec<CollectionId> {
+        serde_json::from_str(&self.collections).unwrap_or_default()
+    }
+
+    pub fn set_collections(&mut self, collections: &[CollectionId]) {
+        self.collections = serde_json::to_string(collections).unwrap_or_else(|_| String::from("[]"));
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.uuid,
+            "organizationId": self.org_uuid,
+            "domainName": self.domain_name,
+            "verifiedDate": self.verified_at.as_ref().map(format_date),
+            "creationDate": format_date(&self.created_at),
+            "txt": self.txt,
+            "lastCheckedDate": self.last_checked_at.as_ref().map(format_date),
+            "jobRunCount": self.check_count,
+            "membershipDefaults": {
+                "type": self.member_type,
+                "collections": self.get_collections(),
+                "autoConfirm": self.auto_confirm,
+            },
+            "object": "organizationDomain",
+        })
+    }
+}
+
+/// Database methods
+impl OrgDomain {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(org_domains::table)
+                    .values(OrgDomainDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving organization domain")
+            }
+            postgresql {
+                let value = OrgDomainDb::to_db(self);
+                diesel::insert_into(org_domains::table)
+                    .values(&value)
+                    .on_conflict(org_domains::uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving organization domain")
+            }
+        }
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(org_domains::table.filter(org_domains::uuid.eq(self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting organization domain")
+        }}
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(org_domains::table.filter(org_domains::org_uuid.eq(org_uuid)))
+                .execute(conn)
+                .map_res("Error deleting organization domains")
+        }}
+    }
+
+    pub async fn find_by_uuid_and_org(
+        uuid: &OrgDomainId,
+        org_uuid: &OrganizationId,
+        conn: &mut DbConn,
+    ) -> Option<Self> {
+        db_run! { conn: {
+            org_domains::table
+                .filter(org_domains::uuid.eq(uuid))
+                .filter(org_domains::org_uuid.eq(org_uuid))
+                .first::<OrgDomainDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            org_domains::table
+                .filter(org_domains::org_uuid.eq(org_uuid))
+                .order_by(org_domains::domain_name)
+                .load::<OrgDomainDb>(conn)
+                .expect("Error loading organization domains")
+                .from_db()
+        }}
+    }
+
+    /// Returns the domains which still have to be verified and were registered after `since`.
+    pub async fn find_unverified_since(since: &NaiveDateTime, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            org_domains::table
+                .filter(org_domains::verified_at.is_null())
+                .filter(org_domains::txt.is_not_null())
+                .filter(org_domains::created_at.ge(since))
+                .load::<OrgDomainDb>(conn)
+                .expect("Error loading organization domains")
+                .from_db()
+        }}
+    }
+
+    /// Returns the verified domain of the email address, the account is then claimed by its organization.
+    pub async fn find_verified_by_email(email: &str, conn: &mut DbConn) -> Option<Self> {
+        let (_, domain_name) = email.rsplit_once('@')?;
+        Self::find_verified_by_domain(domain_name, conn).await
+    }
+
+    /// Returns the verified domain of the email address of the user.
+    /// Aliases are left out, an address the user added themselves must not make the account claimable.
+    pub async fn find_verified_by_user(user: &User, conn: &mut DbConn) -> Option<Self> {
+        Self::find_verified_by_email(&user.email, conn).await
+    }
+
+    /// A domain can only be verified for a single organization, so this returns at most one claim.
+    pub async fn find_verified_by_domain(domain_name: &str, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            org_domains::table
+                .filter(org_domains::domain_name.eq(domain_name.to_lowercase()))
+                .filter(org_domains::verified_at.is_not_null())
+                .first::<OrgDomainDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+}
@@ -1,12 +1,17 @@
 use derive_more::{AsRef, From};
-use serde::Deserialize;
+use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::api::EmptyResult;
 use crate::db::DbConn;
 use crate::error::MapResult;
+use crate::CONFIG;
 
-use super::{Membership, MembershipId, MembershipStatus, MembershipType, OrganizationId, TwoFactor, UserId};
+use super::{
+    CollectionId, Membership, MembershipId, MembershipStatus, MembershipType, OrganizationId, SendType, TwoFactor,
+    UserId,
+};
 
 db_object! {
     #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
@@ -22,13 +27,13 @@ db_object! {
 }
 
 // https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Core/AdminConsole/Enums/PolicyType.cs
-#[derive(Copy, Clone, Eq, PartialEq, num_derive::FromPrimitive)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, num_derive::FromPrimitive)]
 pub enum OrgPolicyType {
     TwoFactorAuthentication = 0,
     MasterPassword = 1,
     PasswordGenerator = 2,
     SingleOrg = 3,
-    // RequireSso = 4, // Not supported
+    RequireSso = 4,
     PersonalOwnership = 5,
     DisableSend = 6,
     SendOptions = 7,
@@ -39,6 +44,52 @@ pub enum OrgPolicyType {
     // AutomaticAppLogIn = 12,
     // FreeFamiliesSponsorshipPolicy = 13,
     RemoveUnlockWithPin = 14,
+    // Vaultwarden specific, opts the organization in to the breached account check of its members
+    BreachMonitoring = 1000,
+    // Vaultwarden specific, sets the password age threshold of the password age report
+    PasswordAge = 1001,
+    // Vaultwarden specific, revokes the members without two-step login after a deadline
+    TwoFactorDeadline = 1002,
+    // Vaultwarden specific, members except Owners and Admins unlock with SSO and the Key Connector
+    KeyConnector = 1003,
+    // Vaultwarden specific, overrides the retention of the revision history of the organization items
+    CipherHistory = 1004,
+    // Vaultwarden specific, members whose email address is still unverified after a grace period can't log in
+    VerifiedEmail = 1005,
+    // Vaultwarden specific, members can't store a master password hint, the existing hints are removed
+    DisablePasswordHints = 1006,
+    // Vaultwarden specific, limits the lifetime of the sessions and the time they can stay unused
+    SessionLifetime = 1007,
+    // Vaultwarden specific, sets the rules of the Sends members create for the organization. Only the Sends
+    // created with an `organizationId` belong to it, which the official clients never send
+    OrgSends = 1008,
+}
+
+impl OrgPolicyType {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "0" | "TwoFactorAuthentication" => Some(Self::TwoFactorAuthentication),
+            "1" | "MasterPassword" => Some(Self::MasterPassword),
+            "2" | "PasswordGenerator" => Some(Self::PasswordGenerator),
+            "3" | "SingleOrg" => Some(Self::SingleOrg),
+            "4" | "RequireSso" => Some(Self::RequireSso),
+            "5" | "PersonalOwnership" => Some(Self::PersonalOwnership),
+            "6" | "DisableSend" => Some(Self::DisableSend),
+            "7" | "SendOptions" => Some(Self::SendOptions),
+            "8" | "ResetPassword" => Some(Self::ResetPassword),
+            "14" | "RemoveUnlockWithPin" => Some(Self::RemoveUnlockWithPin),
+            "1000" | "BreachMonitoring" => Some(Self::BreachMonitoring),
+            "1001" | "PasswordAge" => Some(Self::PasswordAge),
+            "1002" | "TwoFactorDeadline" => Some(Self::TwoFactorDeadline),
+            "1003" | "KeyConnector" => Some(Self::KeyConnector),
+            "1004" | "CipherHistory" => Some(Self::CipherHistory),
+            "1005" | "VerifiedEmail" => Some(Self::VerifiedEmail),
+            "1006" | "DisablePasswordHints" => Some(Self::DisablePasswordHints),
+            "1007" | "SessionLifetime" => Some(Self::SessionLifetime),
+            "1008" | "OrgSends" => Some(Self::OrgSends),
+            _ => None,
+        }
+    }
 }
 
 // https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Core/AdminConsole/Models/Data/Organizations/Policies/SendOptionsPolicyData.cs#L5
@@ -57,6 +108,217 @@ pub struct ResetPasswordDataModel {
     pub auto_enroll_enabled: bool,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordAgePolicyData {
+    pub max_age_days: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TwoFactorDeadlinePolicyData {
+    // Formatted as `YYYY-MM-DD`, members without two-step login are revoked from this day on
+    pub deadline: String,
+    // Days before the deadline the warning emails start
+    pub warning_days: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CipherHistoryPolicyData {
+    // Falls back to `CIPHER_HISTORY_REVISIONS` when not set, 0 disables the history of the organization
+    pub max_revisions: Option<i64>,
+    // Falls back to `CIPHER_HISTORY_DAYS` when not set
+    pub retention_days: Option<i64>,
+}
+
+impl CipherHistoryPolicyData {
+    pub fn validate(&self) -> EmptyResult {
+        if self.max_revisions.is_some_and(|r| r < 0) {
+            err!("The number of kept revisions can't be negative")
+        }
+        if self.retention_days.is_some_and(|d| d < 1) {
+            err!("The revisions have to be kept at least one day")
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiedEmailPolicyData {
+    // Days after the creation of the account, falls back to `EMAIL_VERIFY_GRACE_DAYS` when not set
+    pub grace_days: Option<i64>,
+}
+
+impl VerifiedEmailPolicyData {
+    pub fn validate(&self) -> EmptyResult {
+        if self.grace_days.is_some_and(|d| !(0..=365).contains(&d)) {
+            err!("The grace period has to be between 0 and 365 days")
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionLifetimePolicyData {
+    // Minutes after the login the session ends, refreshing the tokens doesn't extend it
+    pub max_token_lifetime_minutes: Option<i64>,
+    // Minutes the session can stay unused, also the maximum vault timeout of the clients
+    pub idle_timeout_minutes: Option<i64>,
+}
+
+impl SessionLifetimePolicyData {
+    // Shorter sessions would need a new login for almost every request, at most a year
+    const MIN_MINUTES: i64 = 5;
+    const MAX_MINUTES: i64 = 525_600;
+
+    pub fn validate(&self) -> EmptyResult {
+        let in_range =
+            |minutes: Option<i64>| minutes.is_none_or(|m| (Self::MIN_MINUTES..=Self::MAX_MINUTES).contains(&m));
+        if !in_range(self.max_token_lifetime_minutes) || !in_range(self.idle_timeout_minutes) {
+            err!(format!(
+                "The session lifetime and idle timeout have to be between {} and {} minutes",
+                Self::MIN_MINUTES,
+                Self::MAX_MINUTES
+            ))
+        }
+        Ok(())
+    }
+
+    /// Combines two policies into the strictest one, the shortest of each limit is kept.
+    pub fn merge(self, other: Self) -> Self {
+        fn shortest(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+
+        Self {
+            max_token_lifetime_minutes: shortest(self.max_token_lifetime_minutes, other.max_token_lifetime_minutes),
+            idle_timeout_minutes: shortest(self.idle_timeout_minutes, other.idle_timeout_minutes),
+        }
+    }
+
+    /// Shortens the expiration timestamp of a token to the limits, for a session which started at `auth_time`.
+    pub fn limit_expiration(&self, exp: i64, auth_time: i64, now: i64) -> i64 {
+        let mut exp = exp;
+        if let Some(minutes) = self.max_token_lifetime_minutes {
+            exp = exp.min(auth_time + minutes * 60);
+        }
+        if let Some(minutes) = self.idle_timeout_minutes {
+            exp = exp.min(now + minutes * 60);
+        }
+        exp
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrgSendsPolicyData {
+    // Days after the creation the Sends have to be deleted, without it the 31 days of all Sends apply
+    pub max_lifetime_days: Option<i64>,
+    #[serde(default)]
+    pub require_password: bool,
+    // The allowed `SendType`s, all of them when not set
+    pub allowed_types: Option<Vec<i32>>,
+}
+
+impl OrgSendsPolicyData {
+    pub fn validate(&self) -> EmptyResult {
+        if self.max_lifetime_days.is_some_and(|d| !(1..=31).contains(&d)) {
+            err!("The maximum lifetime of the Sends has to be between 1 and 31 days")
+        }
+        if let Some(types) = &self.allowed_types {
+            if types.is_empty() {
+                err!("At least one type of Send has to be allowed, use the Disable Send policy instead")
+            }
+            if types.iter().any(|t| SendType::from_i32(*t).is_none()) {
+                err!("The allowed types of Send have to be 0 (text) or 1 (file)")
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PersonalOwnershipPolicyData {
+    // The collection the members move their personal items into, see `api::core::personal_ownership`
+    pub migration_collection_id: Option<CollectionId>,
+}
+
+// https://github.com/bitwarden/clients/blob/main/libs/tools/generator/core/src/types/password-generator-policy.ts
+// The web vault sends `null` for the options that were never touched, so every field is optional.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordGeneratorPolicyData {
+    #[serde(alias = "defaultType")]
+    pub override_password_type: Option<String>,
+    pub min_length: Option<i32>,
+    pub use_upper: Option<bool>,
+    pub use_lower: Option<bool>,
+    pub use_numbers: Option<bool>,
+    pub use_special: Option<bool>,
+    pub min_numbers: Option<i32>,
+    pub min_special: Option<i32>,
+    pub min_number_words: Option<i32>,
+    pub capitalize: Option<bool>,
+    pub include_number: Option<bool>,
+}
+
+impl PasswordGeneratorPolicyData {
+    /// Checks the options against the limits of the generator of the clients.
+    pub fn validate(&self) -> EmptyResult {
+        fn in_range(value: Option<i32>, min: i32, max: i32) -> bool {
+            value.is_none_or(|v| (min..=max).contains(&v))
+        }
+
+        match self.override_password_type.as_deref() {
+            None | Some("" | "password" | "passphrase") => {}
+            Some(_) => err!("The password generator type has to be `password` or `passphrase`"),
+        }
+        if !in_range(self.min_length, 5, 128) {
+            err!("The minimum password length has to be between 5 and 128")
+        }
+        if !in_range(self.min_numbers, 0, 9) || !in_range(self.min_special, 0, 9) {
+            err!("The minimum numbers and special characters have to be between 0 and 9")
+        }
+        if !in_range(self.min_number_words, 3, 20) {
+            err!("The minimum number of words has to be between 3 and 20")
+        }
+        Ok(())
+    }
+
+    /// Combines two policies into the strictest one, like the clients do for members of several organizations.
+    /// The highest minimums and every required option are kept, a forced `password` type wins over `passphrase`.
+    pub fn merge(self, other: Self) -> Self {
+        let non_empty = |t: Option<String>| t.filter(|t| !t.is_empty());
+        let override_password_type =
+            match (non_empty(self.override_password_type), non_empty(other.override_password_type)) {
+                (Some(t), _) | (_, Some(t)) if t == "password" => Some(t),
+                (t, o) => t.or(o),
+            };
+
+        // `None` is lower than any `Some` value, so `max` keeps the strictest option
+        Self {
+            override_password_type,
+            min_length: self.min_length.max(other.min_length),
+            use_upper: self.use_upper.max(other.use_upper),
+            use_lower: self.use_lower.max(other.use_lower),
+            use_numbers: self.use_numbers.max(other.use_numbers),
+            use_special: self.use_special.max(other.use_special),
+            min_numbers: self.min_numbers.max(other.min_numbers),
+            min_special: self.min_special.max(other.min_special),
+            min_number_words: self.min_number_words.max(other.min_number_words),
+            capitalize: self.capitalize.max(other.capitalize),
+            include_number: self.include_number.max(other.include_number),
+        }
+    }
+}
+
 pub type OrgPolicyResult = Result<(), OrgPolicyErr>;
 
 #[derive(Debug)]
@@ -102,6 +364,26 @@ impl OrgPolicy {
 
         policy
     }
+
+    /// The enabled session lifetime policy in the form of the `MaximumVaultTimeout` policy, which the clients
+    /// enforce as the longest vault timeout. `None` for other policies or without an idle timeout.
+    pub fn to_vault_timeout_json(&self) -> Option<Value> {
+        if self.atype != OrgPolicyType::SessionLifetime as i32 || !self.enabled {
+            return None;
+        }
+        let minutes = serde_json::from_str::<SessionLifetimePolicyData>(&self.data).ok()?.idle_timeout_minutes?;
+        Some(json!({
+            "id": self.uuid,
+            "organizationId": self.org_uuid,
+            "type": 9, // MaximumVaultTimeout
+            "data": {
+                "minutes": minutes,
+                "action": null,
+            },
+            "enabled": true,
+            "object": "policy",
+        }))
+    }
 }
 
 /// Database methods
@@ -200,6 +482,17 @@ impl OrgPolicy {
         }}
     }
 
+    pub async fn find_enabled_by_type(policy_type: OrgPolicyType, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            org_policies::table
+                .filter(org_policies::atype.eq(policy_type as i32))
+                .filter(org_policies::enabled.eq(true))
+                .load::<OrgPolicyDb>(conn)
+                .expect("Error loading org_policy")
+                .from_db()
+        }}
+    }
+
     pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
         db_run! { conn: {
             diesel::delete(org_policies::table.filter(org_policies::org_uuid.eq(org_uuid)))
@@ -235,10 +528,36 @@ impl OrgPolicy {
         }}
     }
 
+    /// Like `find_accepted_and_confirmed_by_user_and_active_policy`, for the memberships which aren't confirmed yet.
+    pub async fn find_unconfirmed_by_user_and_active_policy(
+        user_uuid: &UserId,
+        policy_type: OrgPolicyType,
+        conn: &DbConn,
+    ) -> Vec<Self> {
+        db_run! { conn: {
+            org_policies::table
+                .inner_join(
+                    users_organizations::table.on(
+                        users_organizations::org_uuid.eq(org_policies::org_uuid)
+                            .and(users_organizations::user_uuid.eq(user_uuid)))
+                )
+                .filter(users_organizations::status.eq_any([
+                    MembershipStatus::Invited as i32,
+                    MembershipStatus::Accepted as i32,
+                ]))
+                .filter(org_policies::atype.eq(policy_type as i32))
+                .filter(org_policies::enabled.eq(true))
+                .select(org_policies::all_columns)
+                .load::<OrgPolicyDb>(conn)
+                .expect("Error loading org_policy")
+                .from_db()
+        }}
+    }
+
     pub async fn find_confirmed_by_user_and_active_policy(
         user_uuid: &UserId,
         policy_type: OrgPolicyType,
-        conn: &mut DbConn,
+        conn: &DbConn,
     ) -> Vec<Self> {
         db_run! { conn: {
             org_policies::table
@@ -285,6 +604,13 @@ impl OrgPolicy {
         false
     }
 
+    /// Returns true if the user is a confirmed member of an org that has enabled the DisablePasswordHints policy.
+    /// Unlike most policies it applies to Owners and Admins.
+    pub async fn is_password_hint_disabled(user_uuid: &UserId, conn: &mut DbConn) -> bool {
+        let policy_type = OrgPolicyType::DisablePasswordHints;
+        !OrgPolicy::find_confirmed_by_user_and_active_policy(user_uuid, policy_type, conn).await.is_empty()
+    }
+
     pub async fn is_user_allowed(
         user_uuid: &UserId,
         org_uuid: &OrganizationId,
@@ -351,6 +677,64 @@ impl OrgPolicy {
         false
     }
 
+    /// Returns the generator options enforced on the user, the enabled password generator policies of all the
+    /// organizations the user is a confirmed member of merged together. Unlike most policies, this applies to
+    /// Owners and Admins too.
+    pub async fn find_password_generator_policy(
+        user_uuid: &UserId,
+        conn: &mut DbConn,
+    ) -> Option<PasswordGeneratorPolicyData> {
+        let mut merged: Option<PasswordGeneratorPolicyData> = None;
+        for policy in
+            OrgPolicy::find_confirmed_by_user_and_active_policy(user_uuid, OrgPolicyType::PasswordGenerator, conn).await
+        {
+            match serde_json::from_str::<PasswordGeneratorPolicyData>(&policy.data) {
+                Ok(data) => merged = Some(merged.unwrap_or_default().merge(data)),
+                _ => error!("Failed to deserialize PasswordGeneratorPolicyData: {}", policy.data),
+            }
+        }
+        merged
+    }
+
+    /// Returns the session limits enforced on the user, the enabled session lifetime policies of all the organizations
+    /// the user is a confirmed member of merged together. Like the password generator policy, this applies to
+    /// Owners and Admins too.
+    pub async fn find_session_lifetime_policy(
+        user_uuid: &UserId,
+        conn: &mut DbConn,
+    ) -> Option<SessionLifetimePolicyData> {
+        let mut merged: Option<SessionLifetimePolicyData> = None;
+        for policy in
+            OrgPolicy::find_confirmed_by_user_and_active_policy(user_uuid, OrgPolicyType::SessionLifetime, conn).await
+        {
+            match serde_json::from_str::<SessionLifetimePolicyData>(&policy.data) {
+                Ok(data) => merged = Some(merged.unwrap_or_default().merge(data)),
+                _ => error!("Failed to deserialize SessionLifetimePolicyData: {}", policy.data),
+            }
+        }
+        merged
+    }
+
+    /// Returns the organization whose Key Connector policy applies to the user, if any.
+    /// Unlike `is_applicable_to_user` this includes invited members, who set up the Key Connector on first login.
+    pub async fn find_key_connector_org(user_uuid: &UserId, conn: &mut DbConn) -> Option<OrganizationId> {
+        if !CONFIG.sso_key_connector_enabled() {
+            return None;
+        }
+
+        for member in Membership::find_any_state_by_user(user_uuid, conn).await {
+            if member.atype < MembershipType::Admin
+                && member.status != MembershipStatus::Revoked as i32
+                && Self::find_by_org_and_type(&member.org_uuid, OrgPolicyType::KeyConnector, conn)
+                    .await
+                    .is_some_and(|policy| policy.enabled)
+            {
+                return Some(member.org_uuid);
+            }
+        }
+        None
+    }
+
     pub async fn is_enabled_for_member(
         member_uuid: &MembershipId,
         policy_type: OrgPolicyType,
@@ -0,0 +1,189 @@
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use serde_json::Value;
+
+use super::{Membership, OrganizationId, Report, ReportHistory, TwoFactor};
+use crate::{api::EmptyResult, db::DbConn, error::MapResult, util::format_date};
+
+db_object! {
+    // The daily risk score of the organization, 0 to 100, with the parts it is made of
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = org_risk_scores)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(org_uuid, day))]
+    pub struct OrgRiskScore {
+        pub org_uuid: OrganizationId,
+        pub day: NaiveDate,
+        pub score: i32,
+        pub exposure_points: i32,
+        pub trend_points: i32,
+        pub two_factor_points: i32,
+        pub staleness_points: i32,
+        pub exposed_count: i32,
+        // The exposed count of the report history the trend is compared to, `None` without history
+        pub previous_exposed_count: Option<i32>,
+        // The confirmed members
+        pub member_count: i32,
+        pub two_factor_count: i32,
+        // `None` when the organization never reported
+        pub report_age_days: Option<i32>,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+/// Local methods
+impl OrgRiskScore {
+    // The maximum points of each part, they add up to 100
+    const EXPOSURE_POINTS: f64 = 40.0;
+    const TREND_POINTS: f64 = 20.0;
+    const TWO_FACTOR_POINTS: f64 = 25.0;
+    const STALENESS_POINTS: f64 = 15.0;
+
+    // Two exposed passwords per member give the maximum exposure points
+    const EXPOSED_PER_MEMBER_MAX: f64 = 2.0;
+    // The trend compares with the report history of this many days ago, or the oldest one since
+    const TREND_DAYS: i64 = 7;
+    // A report this old gives the maximum staleness points
+    const STALE_DAYS: i64 = 7;
+
+    /// Computes the score of today from the org report, its history and the two-step login of the members.
+    pub async fn calculate(org_uuid: &OrganizationId, conn: &mut DbConn) -> Self {
+        let now = Utc::now().naive_utc();
+        let today = now.date();
+
+        let report = Report::find_by_org(org_uuid, conn).await;
+        let exposed_count = report.as_ref().map_or(0, |r| r.exposed_count);
+        let report_age_days = report.as_ref().map(|r| (now - r.last_updated_at).num_days());
+
+        let since = today - TimeDelta::days(Self::TREND_DAYS);
+        let previous_exposed_count = ReportHistory::find_by_org_since(org_uuid, since, conn)
+            .await
+            .into_iter()
+            .find(|history| history.day < today)
+            .map(|history| history.exposed_count);
+
+        let members = Membership::find_confirmed_by_org(org_uuid, conn).await;
+        let mut two_factor_count = 0;
+        for member in &members {
+            if !TwoFactor::find_by_user(&member.user_uuid, conn).await.is_empty() {
+                two_factor_count += 1;
+            }
+        }
+        let member_count = members.len();
+
+        // The exposed passwords per member, so large organizations aren't ranked higher for their size alone
+        let exposure = f64::from(exposed_count) / member_count.max(1) as f64 / Self::EXPOSED_PER_MEMBER_MAX;
+        let exposure_points = exposure.min(1.0) * Self::EXPOSURE_POINTS;
+
+        // Half of the points for a stable count, more when it goes up and less when it goes down
+        let trend_points = match previous_exposed_count {
+            Some(previous) => {
+                let change = f64::from(exposed_count - previous) / f64::from(previous.max(1));
+                (1.0 + change.clamp(-1.0, 1.0)) / 2.0 * Self::TREND_POINTS
+            }
+            None => Self::TREND_POINTS / 2.0,
+        };
+
+        let two_factor_points = match member_count {
+            0 => 0.0,
+            count => (1.0 - f64::from(two_factor_count) / count as f64) * Self::TWO_FACTOR_POINTS,
+        };
+
+        // Without a report nothing is known about the vaults, which counts as stale
+        let staleness_points = match report_age_days {
+            Some(age) => age.clamp(0, Self::STALE_DAYS) as f64 / Self::STALE_DAYS as f64 * Self::STALENESS_POINTS,
+            None => Self::STALENESS_POINTS,
+        };
+
+        let points = [exposure_points, trend_points, two_factor_points, staleness_points].map(|p| p.round() as i32);
+        Self {
+            org_uuid: org_uuid.clone(),
+            day: today,
+            score: points.iter().sum::<i32>().clamp(0, 100),
+            exposure_points: points[0],
+            trend_points: points[1],
+            two_factor_points: points[2],
+            staleness_points: points[3],
+            exposed_count,
+            previous_exposed_count,
+            member_count: i32::try_from(member_count).unwrap_or(i32::MAX),
+            two_factor_count,
+            report_age_days: report_age_days.map(|age| i32::try_from(age).unwrap_or(i32::MAX)),
+            created_at: now,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "organizationId": self.org_uuid,
+            "day": self.day.format("%Y-%m-%d").to_string(),
+            "score": self.score,
+            "points": {
+                "exposure": self.exposure_points,
+                "trend": self.trend_points,
+                "twoFactor": self.two_factor_points,
+                "staleness": self.staleness_points,
+            },
+            "exposedCount": self.exposed_count,
+            "previousExposedCount": self.previous_exposed_count,
+            "memberCount": self.member_count,
+            "twoFactorCount": self.two_factor_count,
+            "reportAgeDays": self.report_age_days,
+            "calculatedAt": format_date(&self.created_at),
+        })
+    }
+}
+
+/// Database methods
+impl OrgRiskScore {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(org_risk_scores::table)
+                    .values(OrgRiskScoreDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving risk score")
+            }
+            postgresql {
+                let value = OrgRiskScoreDb::to_db(self);
+                diesel::insert_into(org_risk_scores::table)
+                    .values(&value)
+                    .on_conflict((org_risk_scores::org_uuid, org_risk_scores::day))
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving risk score")
+            }
+        }
+    }
+
+    pub async fn find_latest_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            org_risk_scores::table
+                .filter(org_risk_scores::org_uuid.eq(org_uuid))
+                .order(org_risk_scores::day.desc())
+                .first::<OrgRiskScoreDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_org_since(org_uuid: &OrganizationId, since: NaiveDate, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            org_risk_scores::table
+                .filter(org_risk_scores::org_uuid.eq(org_uuid))
+                .filter(org_risk_scores::day.ge(since))
+                .order(org_risk_scores::day.asc())
+                .load::<OrgRiskScoreDb>(conn)
+                .expect("Error loading risk scores")
+                .from_db()
+        }}
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(org_risk_scores::table.filter(org_risk_scores::org_uuid.eq(org_uuid)))
+                .execute(conn)
+                .map_res("Error deleting risk scores")
+        }}
+    }
+}
@@ -0,0 +1,158 @@
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{api::EmptyResult, db::DbConn, error::MapResult, util::format_date};
+
+db_object! {
+    // A reusable setup of a new organization, applied by the bootstrap endpoint of the custom API
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = org_templates)]
+    #[diesel(primary_key(name))]
+    pub struct OrgTemplate {
+        pub name: String,
+        // JSON of `OrgTemplateData`
+        pub data: String,
+        pub created_at: NaiveDateTime,
+        pub updated_at: NaiveDateTime,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrgTemplateData {
+    pub max_seats: Option<i32>,
+    #[serde(default)]
+    pub groups: Vec<OrgTemplateGroup>,
+    #[serde(default)]
+    pub collections: Vec<OrgTemplateCollection>,
+    #[serde(default)]
+    pub policies: Vec<OrgTemplatePolicy>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrgTemplateGroup {
+    pub name: String,
+    #[serde(default)]
+    pub access_all: bool,
+    pub external_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrgTemplateCollection {
+    // The names are encrypted with the key of each organization, so the bootstrap request gives them by external id
+    pub external_id: String,
+    #[serde(default)]
+    pub groups: Vec<OrgTemplateCollectionGroup>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrgTemplateCollectionGroup {
+    // The name of a group of the template
+    pub name: String,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub hide_passwords: bool,
+    #[serde(default)]
+    pub manage: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrgTemplatePolicy {
+    pub r#type: i32,
+    pub enabled: bool,
+    pub data: Option<Value>,
+}
+
+/// Local methods
+impl OrgTemplate {
+    pub fn new(name: String, data: &OrgTemplateData) -> Self {
+        let now = Utc::now().naive_utc();
+
+        let mut template = Self {
+            name,
+            data: String::new(),
+            created_at: now,
+            updated_at: now,
+        };
+        template.set_data(data);
+        template
+    }
+
+    pub fn set_data(&mut self, data: &OrgTemplateData) {
+        self.data = serde_json::to_string(data).unwrap_or_default();
+    }
+
+    pub fn get_data(&self) -> Option<OrgTemplateData> {
+        serde_json::from_str(&self.data).ok()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "template": serde_json::from_str::<Value>(&self.data).unwrap_or(Value::Null),
+            "creationDate": format_date(&self.created_at),
+            "revisionDate": format_date(&self.updated_at),
+            "object": "orgTemplate",
+        })
+    }
+}
+
+/// Database methods
+impl OrgTemplate {
+    pub async fn save(&mut self, conn: &mut DbConn) -> EmptyResult {
+        self.updated_at = Utc::now().naive_utc();
+
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(org_templates::table)
+                    .values(OrgTemplateDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving organization template")
+            }
+            postgresql {
+                let value = OrgTemplateDb::to_db(self);
+                diesel::insert_into(org_templates::table)
+                    .values(&value)
+                    .on_conflict(org_templates::name)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving organization template")
+            }
+        }
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(org_templates::table.filter(org_templates::name.eq(self.name)))
+                .execute(conn)
+                .map_res("Error deleting organization template")
+        }}
+    }
+
+    pub async fn find_by_name(name: &str, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            org_templates::table
+                .filter(org_templates::name.eq(name))
+                .first::<OrgTemplateDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn get_all(conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            org_templates::table
+                .order_by(org_templates::name.asc())
+                .load::<OrgTemplateDb>(conn)
+                .expect("Error loading organization templates")
+                .from_db()
+        }}
+    }
+}
@@ -0,0 +1,161 @@
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{MembershipStatus, OrganizationId, TwoFactorType, UserId};
+use crate::{api::EmptyResult, db::DbConn, error::MapResult, util::get_uuid};
+use macros::UuidFromParam;
+
+db_object! {
+    // A two-factor provider configured by an organization, enforced for all its confirmed members.
+    // Only Duo is supported for now, `data` holds the Duo credentials of the organization as json.
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = org_twofactor)]
+    #[diesel(primary_key(uuid))]
+    pub struct OrgTwoFactor {
+        pub uuid: OrgTwoFactorId,
+        pub org_uuid: OrganizationId,
+        pub atype: i32,
+        pub enabled: bool,
+        pub data: String,
+        pub revision_date: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct OrgTwoFactorId(String);
+
+/// Local methods
+impl OrgTwoFactor {
+    pub fn new(org_uuid: OrganizationId, atype: TwoFactorType, data: String) -> Self {
+        Self {
+            uuid: OrgTwoFactorId::from(get_uuid()),
+            org_uuid,
+            atype: atype as i32,
+            enabled: true,
+            data,
+            revision_date: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn to_json_provider(&self) -> Value {
+        json!({
+            "enabled": self.enabled,
+            "type": self.atype,
+            "object": "twoFactorProvider"
+        })
+    }
+}
+
+/// Database methods
+impl OrgTwoFactor {
+    pub async fn save(&mut self, conn: &mut DbConn) -> EmptyResult {
+        self.revision_date = Utc::now().naive_utc();
+
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(org_twofactor::table)
+                    .values(OrgTwoFactorDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving organization twofactor")
+            }
+            postgresql {
+                let value = OrgTwoFactorDb::to_db(self);
+                // Same as for the user twofactor, the unique constraint on org_uuid and atype can't be part of ON CONFLICT
+                let _: () = diesel::delete(
+                    org_twofactor::table
+                        .filter(org_twofactor::org_uuid.eq(&self.org_uuid))
+                        .filter(org_twofactor::atype.eq(&self.atype))
+                        .filter(org_twofactor::uuid.ne(&self.uuid)),
+                )
+                .execute(conn)
+                .map_res("Error deleting organization twofactor for insert")?;
+
+                diesel::insert_into(org_twofactor::table)
+                    .values(&value)
+                    .on_conflict(org_twofactor::uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving organization twofactor")
+            }
+        }
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(org_twofactor::table.filter(org_twofactor::uuid.eq(self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting organization twofactor")
+        }}
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(org_twofactor::table.filter(org_twofactor::org_uuid.eq(org_uuid)))
+                .execute(conn)
+                .map_res("Error deleting organization twofactors")
+        }}
+    }
+
+    pub async fn find_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            org_twofactor::table
+                .filter(org_twofactor::org_uuid.eq(org_uuid))
+                .load::<OrgTwoFactorDb>(conn)
+                .expect("Error loading organization twofactor")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_org_and_type(org_uuid: &OrganizationId, atype: i32, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            org_twofactor::table
+                .filter(org_twofactor::org_uuid.eq(org_uuid))
+                .filter(org_twofactor::atype.eq(atype))
+                .first::<OrgTwoFactorDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    /// The enabled providers of the organizations the user is a confirmed member of, oldest first.
+    pub async fn find_enabled_by_confirmed_user_and_type(
+        user_uuid: &UserId,
+        atype: i32,
+        conn: &mut DbConn,
+    ) -> Vec<Self> {
+        db_run! { conn: {
+            org_twofactor::table
+                .inner_join(
+                    users_organizations::table.on(
+                        users_organizations::org_uuid.eq(org_twofactor::org_uuid)
+                            .and(users_organizations::user_uuid.eq(user_uuid)))
+                )
+                .filter(users_organizations::status.eq(MembershipStatus::Confirmed as i32))
+                .filter(org_twofactor::atype.eq(atype))
+                .filter(org_twofactor::enabled.eq(true))
+                .order_by(org_twofactor::revision_date)
+                .select(org_twofactor::all_columns)
+                .load::<OrgTwoFactorDb>(conn)
+                .expect("Error loading organization twofactor")
+                .from_db()
+        }}
+    }
+}
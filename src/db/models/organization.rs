@@ -1,17 +1,19 @@
-use chrono::{NaiveDateTime, Utc};
+use chrono::{NaiveDateTime, TimeDelta, Utc};
 use derive_more::{AsRef, Deref, Display, From};
 use num_traits::FromPrimitive;
 use serde_json::Value;
 use std::{
     cmp::Ordering,
     collections::{HashMap, HashSet},
+    net::IpAddr,
 };
 
 use super::{
-    CipherId, Collection, CollectionGroup, CollectionId, CollectionUser, Group, GroupId, GroupUser, OrgPolicy,
-    OrgPolicyType, TwoFactor, User, UserId,
+    Attachment, BreachFinding, Change, ChangeAction, ChangeEntity, CipherId, Collection, CollectionGroup, CollectionId,
+    CollectionUser, Group, GroupId, GroupUser, OrgDomain, OrgPolicy, OrgPolicyType, SignupRule, TwoFactor, User,
+    UserId,
 };
-use crate::CONFIG;
+use crate::{http_client::parse_ip_ranges, util::format_date, CONFIG};
 use macros::UuidFromParam;
 
 db_object! {
@@ -25,6 +27,23 @@ db_object! {
         pub billing_email: String,
         pub private_key: Option<String>,
         pub public_key: Option<String>,
+        // Attachment storage limit (KB) set by the admin, this takes precedence over the global limit
+        pub attachment_limit: Option<i64>,
+        // Seat limit set by the admin, this takes precedence over the global limit
+        pub max_seats: Option<i32>,
+        // Trash retention set by the owners, this takes precedence over `TRASH_AUTO_DELETE_DAYS`
+        pub trash_retention_days: Option<i32>,
+        // Event log retention set by the owners, this takes precedence over `EVENTS_DAYS_RETAIN`
+        pub events_days_retain: Option<i32>,
+        // Comma separated IP ranges in CIDR notation, members can only log in and sync from the allowed ranges
+        pub ip_allowlist: Option<String>,
+        pub ip_denylist: Option<String>,
+        // Invitation text set by the owners or the admin, the body is markdown
+        pub invite_subject: Option<String>,
+        pub invite_body: Option<String>,
+        pub invite_reply_to: Option<String>,
+        // Set by the admin when the service is suspended, the items are kept but can't be used until it is unarchived
+        pub archived_at: Option<NaiveDateTime>,
     }
 
     #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
@@ -44,6 +63,12 @@ db_object! {
         pub atype: i32,
         pub reset_password_key: Option<String>,
         pub external_id: Option<String>,
+        pub sensitive_notifications: i32, // Bit mask of SensitiveAction
+        pub permissions: i32, // Bit mask of OrgPermission, only used for Custom members
+        // The directory entry of a member managed by the LDAP sync, and whether the sync revoked the member.
+        // Kept apart from `external_id`, which the Directory Connector sets as well.
+        pub ldap_dn: Option<String>,
+        pub ldap_revoked: bool,
     }
 
     #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
@@ -80,6 +105,84 @@ impl MembershipStatus {
     }
 }
 
+/// High-impact actions organization owners are notified about.
+/// Every owner can opt out per action, which is stored as a bit mask on their membership.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SensitiveAction {
+    RoleEscalation = 1,
+    PolicyDisabled = 2,
+    VaultExport = 4,
+    ApiMembershipChange = 8,
+}
+
+impl SensitiveAction {
+    pub const ALL: i32 = 15;
+
+    pub fn description(self) -> &'static str {
+        match self {
+            SensitiveAction::RoleEscalation => "A member was granted a higher role",
+            SensitiveAction::PolicyDisabled => "A policy was disabled",
+            SensitiveAction::VaultExport => "The organization vault was exported",
+            SensitiveAction::ApiMembershipChange => "Members were changed using the organization API key",
+        }
+    }
+}
+
+/// The granular permissions of the Custom role.
+/// Custom members are stored as Managers, these permissions grant them parts of the Admin role.
+// https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Core/Models/Data/Permissions.cs
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OrgPermission {
+    AccessEventLogs = 1,
+    AccessImportExport = 2,
+    AccessReports = 4,
+    CreateNewCollections = 8,
+    EditAnyCollection = 16,
+    DeleteAnyCollection = 32,
+    ManageGroups = 64,
+    ManagePolicies = 128,
+    ManageUsers = 256,
+    ManageResetPassword = 512,
+}
+
+impl OrgPermission {
+    const ALL: [(OrgPermission, &str); 10] = [
+        (OrgPermission::AccessEventLogs, "accessEventLogs"),
+        (OrgPermission::AccessImportExport, "accessImportExport"),
+        (OrgPermission::AccessReports, "accessReports"),
+        (OrgPermission::CreateNewCollections, "createNewCollections"),
+        (OrgPermission::EditAnyCollection, "editAnyCollection"),
+        (OrgPermission::DeleteAnyCollection, "deleteAnyCollection"),
+        (OrgPermission::ManageGroups, "manageGroups"),
+        (OrgPermission::ManagePolicies, "managePolicies"),
+        (OrgPermission::ManageUsers, "manageUsers"),
+        (OrgPermission::ManageResetPassword, "manageResetPassword"),
+    ];
+
+    /// Members with all three collection permissions have access to all collections.
+    pub const ALL_COLLECTIONS: i32 = OrgPermission::CreateNewCollections as i32
+        | OrgPermission::EditAnyCollection as i32
+        | OrgPermission::DeleteAnyCollection as i32;
+
+    /// Converts the `permissions` object sent by the clients into a bit mask.
+    pub fn from_json(permissions: &HashMap<String, Value>) -> i32 {
+        Self::ALL
+            .iter()
+            .filter(|(_, key)| permissions.get(*key) == Some(&Value::Bool(true)))
+            .fold(0, |mask, (permission, _)| mask | *permission as i32)
+    }
+
+    pub fn to_json(mask: i32) -> Value {
+        let mut permissions: serde_json::Map<String, Value> = Self::ALL
+            .iter()
+            .map(|(permission, key)| (key.to_string(), json!(mask & *permission as i32 != 0)))
+            .collect();
+        permissions.insert("manageSso".into(), json!(false)); // Not supported
+        permissions.insert("manageScim".into(), json!(false)); // Not supported (Not AGPLv3 Licensed)
+        Value::Object(permissions)
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive)]
 pub enum MembershipType {
     Owner = 0,
@@ -177,16 +280,108 @@ impl Organization {
             billing_email,
             private_key,
             public_key,
+            attachment_limit: None,
+            max_seats: None,
+            trash_retention_days: None,
+            events_days_retain: None,
+            ip_allowlist: None,
+            ip_denylist: None,
+            invite_subject: None,
+            invite_body: None,
+            invite_reply_to: None,
+            archived_at: None,
         }
     }
+
+    /// Returns the attachment storage limit (KB) for this organization, falling back to `ORG_ATTACHMENT_LIMIT`
+    pub fn effective_attachment_limit(&self) -> Option<i64> {
+        self.attachment_limit.or_else(|| CONFIG.org_attachment_limit())
+    }
+
+    /// Returns the seat limit for this organization, falling back to `ORG_MAX_SEATS`
+    pub fn effective_max_seats(&self) -> Option<i32> {
+        self.max_seats.or_else(|| CONFIG.org_max_seats())
+    }
+
+    /// Returns the number of days trashed items of this organization are kept, falling back to `TRASH_AUTO_DELETE_DAYS`
+    pub fn effective_trash_retention_days(&self) -> Option<i64> {
+        self.trash_retention_days.map(i64::from).or_else(|| CONFIG.trash_auto_delete_days())
+    }
+
+    /// Returns the number of days events of this organization are kept, falling back to `EVENTS_DAYS_RETAIN`.
+    /// `EVENTS_DAYS_RETAIN` is also the minimum, the organization can only keep its events longer.
+    pub fn effective_events_days_retain(&self) -> Option<i64> {
+        match (self.events_days_retain.map(i64::from), CONFIG.events_days_retain()) {
+            (Some(days), Some(minimum)) => Some(days.max(minimum)),
+            (days, global) => days.or(global),
+        }
+    }
+    /// Checks the IP against the ranges of the owners, without an allowlist all IPs outside the denylist are allowed.
+    /// The lists are validated when they are saved, a list which can't be parsed anymore denies every IP.
+    pub fn is_ip_allowed(&self, ip: IpAddr) -> bool {
+        // IPv4-mapped IPv6 addresses are checked as the IPv4 address they map to
+        let ip = ip.to_canonical();
+        let contains = |list: &str| parse_ip_ranges(list).map(|ranges| ranges.iter().any(|range| range.contains(&ip)));
+
+        let allowed = self.ip_allowlist.as_deref().map_or(Ok(true), contains);
+        let denied = self.ip_denylist.as_deref().map_or(Ok(false), contains);
+        matches!((allowed, denied), (Ok(true), Ok(false)))
+    }
+
+    pub fn has_ip_restrictions(&self) -> bool {
+        self.ip_allowlist.is_some() || self.ip_denylist.is_some()
+    }
+
+    pub fn is_archived(&self) -> bool {
+        self.archived_at.is_some()
+    }
+
+    /// Returns when this archived organization will be deleted, `None` when it isn't archived or
+    /// `ORG_ARCHIVE_RETENTION_DAYS` keeps archived organizations indefinitely.
+    pub fn archive_deletion_date(&self) -> Option<NaiveDateTime> {
+        let retention_days = CONFIG.org_archive_retention_days();
+        if retention_days == 0 {
+            return None;
+        }
+        self.archived_at.map(|archived_at| archived_at + TimeDelta::try_days(retention_days).unwrap_or_default())
+    }
+
+    /// Sets the customized invitation text, empty values fall back to the default text of the invitation mails.
+    pub fn set_invite_text(
+        &mut self,
+        subject: Option<String>,
+        body: Option<String>,
+        reply_to: Option<String>,
+    ) -> EmptyResult {
+        let normalize = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let subject = normalize(subject);
+        let body = normalize(body);
+        let reply_to = normalize(reply_to).map(|r| r.to_lowercase());
+
+        if subject.as_ref().is_some_and(|s| s.chars().count() > 200 || s.contains(['\r', '\n'])) {
+            err!("The invitation subject must be a single line of at most 200 characters")
+        }
+        if body.as_ref().is_some_and(|b| b.chars().count() > 10_000) {
+            err!("The invitation text can't be longer than 10000 characters")
+        }
+        if reply_to.as_ref().is_some_and(|r| !crate::util::is_valid_email(r)) {
+            err!("The reply-to address is not a valid email address")
+        }
+
+        self.invite_subject = subject;
+        self.invite_body = body;
+        self.invite_reply_to = reply_to;
+        Ok(())
+    }
+
     // https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Api/AdminConsole/Models/Response/Organizations/OrganizationResponseModel.cs
     pub fn to_json(&self) -> Value {
         json!({
             "id": self.uuid,
             "name": self.name,
-            "seats": null,
+            "seats": self.effective_max_seats(),
             "maxCollections": null,
-            "maxStorageGb": i16::MAX, // The value doesn't matter, we don't check server-side
+            "maxStorageGb": Attachment::max_storage_gb(self.effective_attachment_limit()),
             "use2fa": true,
             "useCustomPermissions": true,
             "useDirectory": false, // Is supported, but this value isn't checked anywhere (yet)
@@ -196,9 +391,9 @@ impl Organization {
             "usePolicies": true,
             "useScim": false, // Not supported (Not AGPLv3 Licensed)
             "useSso": false, // Not supported
-            "useKeyConnector": false, // Not supported
+            "useKeyConnector": CONFIG.sso_key_connector_enabled(),
             "usePasswordManager": true,
-            "useSecretsManager": false, // Not supported (Not AGPLv3 Licensed)
+            "useSecretsManager": CONFIG.secrets_manager_enabled(),
             "selfHost": true,
             "useApi": true,
             "hasPublicAndPrivateKeys": self.private_key.is_some() && self.public_key.is_some(),
@@ -223,6 +418,15 @@ impl Organization {
             "smServiceAccounts": null,
 
             "billingEmail": self.billing_email,
+            "trashRetentionDays": self.effective_trash_retention_days(), // Vaultwarden specific
+            "eventsDaysRetain": self.effective_events_days_retain(), // Vaultwarden specific
+            "ipAllowlist": self.ip_allowlist, // Vaultwarden specific
+            "ipDenylist": self.ip_denylist, // Vaultwarden specific
+            "inviteSubject": self.invite_subject, // Vaultwarden specific
+            "inviteBody": self.invite_body, // Vaultwarden specific
+            "inviteReplyTo": self.invite_reply_to, // Vaultwarden specific
+            "archivedDate": self.archived_at.as_ref().map(format_date), // Vaultwarden specific
+            "archiveDeletionDate": self.archive_deletion_date().as_ref().map(format_date), // Vaultwarden specific
             "planType": 6, // Custom plan
             "usersGetPremium": true,
             "object": "organization",
@@ -251,12 +455,22 @@ impl Membership {
             atype: MembershipType::User as i32,
             reset_password_key: None,
             external_id: None,
+            sensitive_notifications: SensitiveAction::ALL,
+            permissions: 0,
+            ldap_dn: None,
+            ldap_revoked: false,
         }
     }
 
+    pub fn wants_sensitive_notification(&self, action: SensitiveAction) -> bool {
+        self.sensitive_notifications & action as i32 != 0
+    }
+
     pub fn restore(&mut self) -> bool {
         if self.status < MembershipStatus::Invited as i32 {
             self.status += ACTIVATE_REVOKE_DIFF;
+            // A member revoked again later isn't restored by the LDAP sync, unless the sync revoked it itself
+            self.ldap_revoked = false;
             return true;
         }
         false
@@ -293,6 +507,12 @@ impl Membership {
 
     /// HACK: Convert the manager type to a custom type
     /// It will be converted back on other locations
+    /// Owners and Admins have every permission, Custom members only the ones granted to them.
+    pub fn has_permission(&self, permission: OrgPermission) -> bool {
+        self.atype >= MembershipType::Admin
+            || (self.atype == MembershipType::Manager && self.permissions & permission as i32 != 0)
+    }
+
     pub fn type_manager_as_custom(&self) -> i32 {
         match self.atype {
             3 => 4,
@@ -333,6 +553,7 @@ impl Organization {
         for member in Membership::find_by_org(&self.uuid, conn).await.iter() {
             User::update_uuid_revision(&member.user_uuid, conn).await;
         }
+        Change::record(ChangeEntity::Organization, &self.uuid, ChangeAction::Upsert, conn).await;
 
         db_run! { conn:
             sqlite, mysql {
@@ -367,14 +588,31 @@ impl Organization {
     }
 
     pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
-        use super::{Cipher, Collection};
+        use super::{Cipher, Collection, Project, Secret, ServiceAccount};
 
         Cipher::delete_all_by_organization(&self.uuid, conn).await?;
         Collection::delete_all_by_organization(&self.uuid, conn).await?;
         Membership::delete_all_by_organization(&self.uuid, conn).await?;
         OrgPolicy::delete_all_by_organization(&self.uuid, conn).await?;
+        super::OrgTwoFactor::delete_all_by_organization(&self.uuid, conn).await?;
         Group::delete_all_by_organization(&self.uuid, conn).await?;
         OrganizationApiKey::delete_all_by_organization(&self.uuid, conn).await?;
+        OrgDomain::delete_all_by_organization(&self.uuid, conn).await?;
+        SignupRule::delete_all_by_organization(&self.uuid, conn).await?;
+        BreachFinding::delete_all_by_organization(&self.uuid, conn).await?;
+        super::ReportMember::delete_all_by_organization(&self.uuid, conn).await?;
+        super::ReportHistory::delete_all_by_organization(&self.uuid, conn).await?;
+        super::Report::delete_all_by_organization(&self.uuid, conn).await?;
+        super::OrgRiskScore::delete_all_by_organization(&self.uuid, conn).await?;
+        super::QuarantinedFile::delete_all_by_organization(&self.uuid, conn).await?;
+        super::PolicyViolation::delete_all_by_organization(&self.uuid, conn).await?;
+        super::Send::delete_all_by_organization(&self.uuid, conn).await?;
+        Secret::delete_all_by_organization(&self.uuid, conn).await?;
+        Project::delete_all_by_organization(&self.uuid, conn).await?;
+        ServiceAccount::delete_all_by_organization(&self.uuid, conn).await?;
+        super::SecretsRevision::delete_by_org(&self.uuid, conn).await?;
+        super::ProviderOrganization::delete_by_org(&self.uuid, conn).await?;
+        Change::record(ChangeEntity::Organization, &self.uuid, ChangeAction::Delete, conn).await;
 
         db_run! { conn: {
             diesel::delete(organizations::table.filter(organizations::uuid.eq(self.uuid)))
@@ -383,6 +621,21 @@ impl Organization {
         }}
     }
 
+    /// Checks that `new_seats` more members fit in the seat limit, a revoked member doesn't take a seat.
+    /// With `new_seats` set to 0 this only checks that the organization isn't over its limit, which is enforced at confirmation.
+    pub async fn check_available_seats(&self, new_seats: i64, conn: &mut DbConn) -> EmptyResult {
+        let Some(max_seats) = self.effective_max_seats() else {
+            return Ok(());
+        };
+        let used_seats = Membership::count_seats_by_org(&self.uuid, conn).await;
+        if used_seats + new_seats > i64::from(max_seats) {
+            err!(format!(
+                "Seat limit reached, {used_seats} of the {max_seats} seats of this organization are in use. Contact the server administrator for more seats."
+            ))
+        }
+        Ok(())
+    }
+
     pub async fn find_by_uuid(uuid: &OrganizationId, conn: &mut DbConn) -> Option<Self> {
         db_run! { conn: {
             organizations::table
@@ -392,6 +645,28 @@ impl Organization {
         }}
     }
 
+    /// Returns the organization as JSON for the replication stream, only the listed columns are part of it.
+    pub async fn find_row_json(uuid: &OrganizationId, conn: &mut DbConn) -> Option<Value> {
+        let org = Self::find_by_uuid(uuid, conn).await?;
+        Some(json!({
+            "uuid": org.uuid,
+            "name": org.name,
+            "billing_email": org.billing_email,
+            "private_key": org.private_key,
+            "public_key": org.public_key,
+            "attachment_limit": org.attachment_limit,
+            "max_seats": org.max_seats,
+            "trash_retention_days": org.trash_retention_days,
+            "events_days_retain": org.events_days_retain,
+            "ip_allowlist": org.ip_allowlist,
+            "ip_denylist": org.ip_denylist,
+            "invite_subject": org.invite_subject,
+            "invite_body": org.invite_body,
+            "invite_reply_to": org.invite_reply_to,
+            "archived_at": org.archived_at.as_ref().map(format_date),
+        }))
+    }
+
     pub async fn find_by_name(name: &str, conn: &mut DbConn) -> Option<Self> {
         db_run! { conn: {
             organizations::table
@@ -407,6 +682,29 @@ impl Organization {
         }}
     }
 
+    pub async fn find_archived_before(dt: &NaiveDateTime, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            organizations::table
+                .filter(organizations::archived_at.lt(dt))
+                .load::<OrganizationDb>(conn)
+                .expect("Error loading archived organizations")
+                .from_db()
+        }}
+    }
+
+    /// The organizations whose items are excluded from the vaults of their members, see `Cipher::find_by_user`.
+    pub async fn find_archived_uuids(conn: &mut DbConn) -> HashSet<OrganizationId> {
+        db_run! { conn: {
+            organizations::table
+                .filter(organizations::archived_at.is_not_null())
+                .select(organizations::uuid)
+                .load::<OrganizationId>(conn)
+                .expect("Error loading archived organizations")
+                .into_iter()
+                .collect()
+        }}
+    }
+
     pub async fn find_main_org_user_email(user_email: &str, conn: &mut DbConn) -> Option<Organization> {
         let lower_mail = user_email.to_lowercase();
 
@@ -449,31 +747,24 @@ impl Membership {
         // It will be converted back on other locations
         let membership_type = self.type_manager_as_custom();
 
-        let permissions = json!({
-                // TODO: Add full support for Custom User Roles
-                // See: https://bitwarden.com/help/article/user-types-access-control/#custom-role
-                // Currently we use the custom role as a manager role and link the 3 Collection roles to mimic the access_all permission
-                "accessEventLogs": false,
-                "accessImportExport": false,
-                "accessReports": false,
-                // If the following 3 Collection roles are set to true a custom user has access all permission
-                "createNewCollections": membership_type == 4 && self.access_all,
-                "editAnyCollection": membership_type == 4 && self.access_all,
-                "deleteAnyCollection": membership_type == 4 && self.access_all,
-                "manageGroups": false,
-                "managePolicies": false,
-                "manageSso": false, // Not supported
-                "manageUsers": false,
-                "manageResetPassword": false,
-                "manageScim": false // Not supported (Not AGPLv3 Licensed)
+        let permissions = OrgPermission::to_json(if membership_type == 4 {
+            self.permissions
+        } else {
+            0
         });
 
+        // The clients only use the Key Connector for members which are not Owners or Admins
+        let key_connector_enabled = CONFIG.sso_key_connector_enabled()
+            && OrgPolicy::find_by_org_and_type(&self.org_uuid, OrgPolicyType::KeyConnector, conn)
+                .await
+                .is_some_and(|policy| policy.enabled);
+
         // https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Api/AdminConsole/Models/Response/ProfileOrganizationResponseModel.cs
         json!({
             "id": self.org_uuid,
             "identifier": null, // Not supported
             "name": org.name,
-            "seats": null,
+            "seats": org.effective_max_seats(),
             "maxCollections": null,
             "usersGetPremium": true,
             "use2fa": true,
@@ -490,8 +781,8 @@ impl Membership {
             "useResetPassword": CONFIG.mail_enabled(),
             "ssoBound": false, // Not supported
             "useSso": false, // Not supported
-            "useKeyConnector": false,
-            "useSecretsManager": false, // Not supported (Not AGPLv3 Licensed)
+            "useKeyConnector": CONFIG.sso_key_connector_enabled(),
+            "useSecretsManager": CONFIG.secrets_manager_enabled(),
             "usePasswordManager": true,
             "useCustomPermissions": true,
             "useActivateAutofillPolicy": false,
@@ -505,12 +796,12 @@ impl Membership {
             "familySponsorshipFriendlyName": null,
             "familySponsorshipAvailable": false,
             "productTierType": 3, // Enterprise tier
-            "keyConnectorEnabled": false,
-            "keyConnectorUrl": null,
+            "keyConnectorEnabled": key_connector_enabled,
+            "keyConnectorUrl": key_connector_enabled.then(|| CONFIG.sso_key_connector_url()),
             "familySponsorshipLastSyncDate": null,
             "familySponsorshipValidUntil": null,
             "familySponsorshipToDelete": null,
-            "accessSecretsManager": false,
+            "accessSecretsManager": CONFIG.secrets_manager_enabled() && self.atype >= MembershipType::Admin,
             "limitCollectionCreation": self.atype < MembershipType::Manager, // If less then a manager return true, to limit collection creations
             "limitCollectionDeletion": true,
             "limitItemDeletion": false,
@@ -520,14 +811,16 @@ impl Membership {
 
             "permissions": permissions,
 
-            "maxStorageGb": i16::MAX, // The value doesn't matter, we don't check server-side
+            "maxStorageGb": Attachment::max_storage_gb(org.effective_attachment_limit()),
 
             // These are per user
             "userId": self.user_uuid,
             "key": self.akey,
             "status": self.status,
             "type": membership_type,
-            "enabled": true,
+            // The clients show a disabled organization as suspended, and its items as unavailable
+            "enabled": !org.is_archived(),
+            "archivedDate": org.archived_at.as_ref().map(format_date), // Vaultwarden specific
 
             "object": "profileOrganization",
         })
@@ -588,11 +881,7 @@ impl Membership {
                     let (read_only, hide_passwords, manage) = if self.has_full_access() {
                         (false, false, self.atype >= MembershipType::Manager)
                     } else if let Some(cu) = cu.get(&c.uuid) {
-                        (
-                            cu.read_only,
-                            cu.hide_passwords,
-                            cu.manage || (self.atype == MembershipType::Manager && !cu.read_only && !cu.hide_passwords),
-                        )
+                        (cu.read_only, cu.hide_passwords, cu.manage)
                     // If previous checks failed it might be that this user has access via a group, but we should not return those elements here
                     // Those are returned via a special group endpoint
                     } else if cg.contains(&c.uuid) {
@@ -617,27 +906,9 @@ impl Membership {
         // It will be converted back on other locations
         let membership_type = self.type_manager_as_custom();
 
-        // HACK: Only return permissions if the user is of type custom and has access_all
-        // Else Bitwarden will assume the defaults of all false
-        let permissions = if membership_type == 4 && self.access_all {
-            json!({
-                // TODO: Add full support for Custom User Roles
-                // See: https://bitwarden.com/help/article/user-types-access-control/#custom-role
-                // Currently we use the custom role as a manager role and link the 3 Collection roles to mimic the access_all permission
-                "accessEventLogs": false,
-                "accessImportExport": false,
-                "accessReports": false,
-                // If the following 3 Collection roles are set to true a custom user has access all permission
-                "createNewCollections": true,
-                "editAnyCollection": true,
-                "deleteAnyCollection": true,
-                "manageGroups": false,
-                "managePolicies": false,
-                "manageSso": false, // Not supported
-                "manageUsers": false,
-                "manageResetPassword": false,
-                "manageScim": false // Not supported (Not AGPLv3 Licensed)
-            })
+        // Only return permissions for Custom members, else Bitwarden will assume the defaults of all false
+        let permissions = if membership_type == 4 {
+            OrgPermission::to_json(self.permissions)
         } else {
             json!(null)
         };
@@ -664,8 +935,8 @@ impl Membership {
             "ssoBound": false, // Not supported
             "managedByOrganization": false, // This key is obsolete replaced by claimedByOrganization
             "claimedByOrganization": false, // Means not managed via the Members UI, like SSO
-            "usesKeyConnector": false, // Not supported
-            "accessSecretsManager": false, // Not supported (Not AGPLv3 Licensed)
+            "usesKeyConnector": user.uses_key_connector,
+            "accessSecretsManager": CONFIG.secrets_manager_enabled() && self.atype >= MembershipType::Admin,
 
             "object": "organizationUserUserDetails",
         })
@@ -781,6 +1052,7 @@ impl Membership {
 
         CollectionUser::delete_all_by_user_and_org(&self.user_uuid, &self.org_uuid, conn).await?;
         GroupUser::delete_all_by_member(&self.uuid, conn).await?;
+        super::ReportMember::delete_by_org_and_user(&self.org_uuid, &self.user_uuid, conn).await?;
 
         db_run! { conn: {
             diesel::delete(users_organizations::table.filter(users_organizations::uuid.eq(self.uuid)))
@@ -950,6 +1222,19 @@ impl Membership {
         }}
     }
 
+    /// Counts the members taking a seat, which are all except the revoked ones.
+    pub async fn count_seats_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> i64 {
+        db_run! { conn: {
+            users_organizations::table
+                .filter(users_organizations::org_uuid.eq(org_uuid))
+                .filter(users_organizations::status.ne(MembershipStatus::Revoked as i32))
+                .count()
+                .first::<i64>(conn)
+                .ok()
+                .unwrap_or(0)
+        }}
+    }
+
     pub async fn find_by_org_and_type(
         org_uuid: &OrganizationId,
         atype: MembershipType,
@@ -1278,4 +1563,49 @@ mod tests {
         assert!(MembershipType::Manager > MembershipType::User);
         assert!(MembershipType::Manager == MembershipType::from_str("4").unwrap());
     }
+
+    fn org_with_ip_lists(allowlist: Option<&str>, denylist: Option<&str>) -> Organization {
+        let mut org = Organization::new(String::from("Test"), String::from("test@example.com"), None, None);
+        org.ip_allowlist = allowlist.map(String::from);
+        org.ip_denylist = denylist.map(String::from);
+        org
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn is_ip_allowed_without_lists() {
+        let org = org_with_ip_lists(None, None);
+        assert!(org.is_ip_allowed(ip("203.0.113.7")));
+        assert!(org.is_ip_allowed(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn is_ip_allowed_matches_cidr_ranges() {
+        let org = org_with_ip_lists(Some("10.0.0.0/8, 192.168.1.0/24,2001:db8::/32"), Some("10.1.0.0/16"));
+        assert!(org.is_ip_allowed(ip("10.2.3.4")));
+        assert!(org.is_ip_allowed(ip("192.168.1.255")));
+        assert!(org.is_ip_allowed(ip("2001:db8:1::1")));
+        assert!(!org.is_ip_allowed(ip("192.168.2.1")));
+        assert!(!org.is_ip_allowed(ip("2001:db9::1")));
+        // The denylist wins over the allowlist
+        assert!(!org.is_ip_allowed(ip("10.1.2.3")));
+    }
+
+    #[test]
+    fn is_ip_allowed_checks_ipv4_mapped_addresses_as_ipv4() {
+        let org = org_with_ip_lists(Some("192.168.1.0/24"), Some("192.168.1.128/25"));
+        assert!(org.is_ip_allowed(ip("::ffff:192.168.1.10")));
+        assert!(!org.is_ip_allowed(ip("::ffff:192.168.1.200")));
+        assert!(!org.is_ip_allowed(ip("::ffff:10.0.0.1")));
+    }
+
+    #[test]
+    fn is_ip_allowed_denies_with_unparsable_lists() {
+        assert!(!org_with_ip_lists(Some("not a range"), None).is_ip_allowed(ip("10.0.0.1")));
+        assert!(!org_with_ip_lists(None, Some("10.0.0.0/33")).is_ip_allowed(ip("10.0.0.1")));
+        assert!(!org_with_ip_lists(Some("10.0.0.0/8"), Some("10.1.0.0/16,nope")).is_ip_allowed(ip("10.0.0.1")));
+    }
 }
@@ -0,0 +1,251 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{User, UserId};
+use crate::{
+    api::EmptyResult,
+    crypto,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+};
+use macros::UuidFromParam;
+
+db_object! {
+    // Tokens users create for their own scripts, which can only use the endpoints of their scopes.
+    // They are used as bearer token directly, without a login and so without two-step login.
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = personal_api_tokens)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(uuid))]
+    pub struct PersonalApiToken {
+        pub uuid: PersonalApiTokenId,
+        pub user_uuid: UserId,
+        pub name: String,
+        // Comma separated list of `PersonalApiTokenScope`
+        pub scopes: String,
+        // Only a hash of the secret is stored, the token itself is shown once on creation
+        pub token_hash: String,
+        pub expire_at: Option<NaiveDateTime>,
+        pub last_used_at: Option<NaiveDateTime>,
+        pub creation_date: NaiveDateTime,
+        // The security stamp of the user at the creation, the token stops working once it changes, like after
+        // a password change, a key rotation or when the user deauthorizes the sessions
+        pub security_stamp: String,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct PersonalApiTokenId(String);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum PersonalApiTokenScope {
+    // Read the vault, like a sync of the clients
+    Sync = 0,
+    // Create text Sends
+    Send = 1,
+}
+
+impl PersonalApiTokenScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sync => "sync",
+            Self::Send => "send",
+        }
+    }
+
+    pub fn from_name(scope: &str) -> Option<Self> {
+        match scope {
+            "sync" => Some(Self::Sync),
+            "send" => Some(Self::Send),
+            _ => None,
+        }
+    }
+}
+
+/// Local methods
+impl PersonalApiToken {
+    // The tokens start with this, so they can be told apart from the access tokens of a login
+    pub const PREFIX: &'static str = "vwpat.";
+
+    // `last_used_at` is only updated once it is older than this, so every request doesn't cause a write
+    const LAST_USED_RESOLUTION_MINUTES: i64 = 5;
+
+    /// Returns the new token together with the token string, of which only a hash is stored.
+    pub fn new(
+        user: &User,
+        name: String,
+        scopes: &[PersonalApiTokenScope],
+        expire_at: Option<NaiveDateTime>,
+    ) -> (Self, String) {
+        let uuid = PersonalApiTokenId::from(get_uuid());
+        let secret = crypto::generate_api_key();
+        let token_string = format!("{}{uuid}.{secret}", Self::PREFIX);
+
+        let token = Self {
+            uuid,
+            user_uuid: user.uuid.clone(),
+            name,
+            scopes: scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>().join(","),
+            token_hash: crypto::sha256_hex(&secret),
+            expire_at,
+            last_used_at: None,
+            creation_date: Utc::now().naive_utc(),
+            security_stamp: user.security_stamp.clone(),
+        };
+        (token, token_string)
+    }
+
+    /// Splits a token string into the id of the token and its secret.
+    pub fn parse(token_string: &str) -> Option<(PersonalApiTokenId, &str)> {
+        let (uuid, secret) = token_string.strip_prefix(Self::PREFIX)?.split_once('.')?;
+        Some((PersonalApiTokenId::from(uuid.to_string()), secret))
+    }
+
+    pub fn check_valid_secret(&self, secret: &str) -> bool {
+        crypto::ct_eq(&self.token_hash, crypto::sha256_hex(secret))
+    }
+
+    pub fn is_revoked(&self, user: &User) -> bool {
+        self.security_stamp != user.security_stamp
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expire_at.is_some_and(|expire_at| expire_at < Utc::now().naive_utc())
+    }
+
+    pub fn scopes(&self) -> Vec<PersonalApiTokenScope> {
+        self.scopes.split(',').filter_map(PersonalApiTokenScope::from_name).collect()
+    }
+
+    pub fn has_scope(&self, scope: PersonalApiTokenScope) -> bool {
+        self.scopes().contains(&scope)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.uuid,
+            "name": self.name,
+            "scopes": self.scopes().iter().map(|scope| scope.as_str()).collect::<Vec<_>>(),
+            "expireAt": self.expire_at.as_ref().map(format_date),
+            "lastUsedDate": self.last_used_at.as_ref().map(format_date),
+            "creationDate": format_date(&self.creation_date),
+            "object": "personalApiToken",
+        })
+    }
+}
+
+/// Database methods
+impl PersonalApiToken {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::insert_into(personal_api_tokens::table)
+                .values(PersonalApiTokenDb::to_db(self))
+                .execute(conn)
+                .map_res("Error saving personal API token")
+        }}
+    }
+
+    /// Records the use of the token, at most every few minutes.
+    pub async fn touch(&mut self, conn: &mut DbConn) -> EmptyResult {
+        let now = Utc::now().naive_utc();
+        let resolution = TimeDelta::try_minutes(Self::LAST_USED_RESOLUTION_MINUTES).unwrap();
+        if self.last_used_at.is_some_and(|last_used_at| now - last_used_at < resolution) {
+            return Ok(());
+        }
+        self.last_used_at = Some(now);
+
+        db_run! { conn: {
+            diesel::update(personal_api_tokens::table.filter(personal_api_tokens::uuid.eq(&self.uuid)))
+                .set(personal_api_tokens::last_used_at.eq(now))
+                .execute(conn)
+                .map_res("Error updating personal API token")
+        }}
+    }
+
+    pub async fn find_by_uuid(uuid: &PersonalApiTokenId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            personal_api_tokens::table
+                .filter(personal_api_tokens::uuid.eq(uuid))
+                .first::<PersonalApiTokenDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_user(user_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            personal_api_tokens::table
+                .filter(personal_api_tokens::user_uuid.eq(user_uuid))
+                .order_by(personal_api_tokens::creation_date)
+                .load::<PersonalApiTokenDb>(conn)
+                .expect("Error loading personal API tokens")
+                .from_db()
+        }}
+    }
+
+    pub async fn count_by_user(user_uuid: &UserId, conn: &mut DbConn) -> i64 {
+        db_run! { conn: {
+            personal_api_tokens::table
+                .filter(personal_api_tokens::user_uuid.eq(user_uuid))
+                .count()
+                .first::<i64>(conn)
+                .ok()
+                .unwrap_or(0)
+        }}
+    }
+
+    pub async fn delete_by_uuid_and_user(
+        uuid: &PersonalApiTokenId,
+        user_uuid: &UserId,
+        conn: &mut DbConn,
+    ) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(
+                personal_api_tokens::table
+                    .filter(personal_api_tokens::uuid.eq(uuid))
+                    .filter(personal_api_tokens::user_uuid.eq(user_uuid)),
+            )
+            .execute(conn)
+            .map_res("Error deleting personal API token")
+        }}
+    }
+
+    /// Deletes the tokens created before the security stamp of the user changed.
+    pub async fn delete_revoked_by_user(user: &User, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(
+                personal_api_tokens::table
+                    .filter(personal_api_tokens::user_uuid.eq(&user.uuid))
+                    .filter(personal_api_tokens::security_stamp.ne(&user.security_stamp)),
+            )
+            .execute(conn)
+            .map_res("Error deleting personal API tokens")
+        }}
+    }
+
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(personal_api_tokens::table.filter(personal_api_tokens::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting personal API tokens")
+        }}
+    }
+}
@@ -0,0 +1,154 @@
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{OrgPolicyType, OrganizationId, UserId};
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+};
+use macros::UuidFromParam;
+
+db_object! {
+    // Records every time a policy revoked, removed or blocked a member, so it can be followed up on.
+    #[derive(Identifiable, Queryable, Insertable)]
+    #[diesel(table_name = policy_violations)]
+    #[diesel(primary_key(uuid))]
+    pub struct PolicyViolation {
+        pub uuid: PolicyViolationId,
+        pub org_uuid: OrganizationId,
+        pub user_uuid: UserId,
+        pub policy_type: i32,
+        pub action: i32,
+        pub reason: String,
+        // The user whose request enforced the policy, like `act_user_uuid` of the events
+        pub actor_uuid: UserId,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct PolicyViolationId(String);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PolicyViolationAction {
+    // The membership was revoked
+    Revoked = 0,
+    // The membership was deleted
+    Removed = 1,
+    // Joining, confirming, restoring or changing the type of the membership was refused
+    Blocked = 2,
+}
+
+impl PolicyViolationAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Revoked => "revoked",
+            Self::Removed => "removed",
+            Self::Blocked => "blocked",
+        }
+    }
+
+    pub fn from_i32(action: i32) -> Option<Self> {
+        match action {
+            0 => Some(Self::Revoked),
+            1 => Some(Self::Removed),
+            2 => Some(Self::Blocked),
+            _ => None,
+        }
+    }
+}
+
+/// Local methods
+impl PolicyViolation {
+    pub fn new(
+        org_uuid: OrganizationId,
+        user_uuid: UserId,
+        policy_type: OrgPolicyType,
+        action: PolicyViolationAction,
+        reason: String,
+        actor_uuid: UserId,
+    ) -> Self {
+        Self {
+            uuid: PolicyViolationId::from(get_uuid()),
+            org_uuid,
+            user_uuid,
+            policy_type: policy_type as i32,
+            action: action as i32,
+            reason,
+            actor_uuid,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.uuid,
+            "organizationId": self.org_uuid,
+            "userId": self.user_uuid,
+            "policyType": self.policy_type,
+            "action": PolicyViolationAction::from_i32(self.action).map(PolicyViolationAction::as_str),
+            "reason": self.reason,
+            "actorId": self.actor_uuid,
+            "date": format_date(&self.created_at),
+            "object": "policyViolation",
+        })
+    }
+}
+
+/// Database methods
+impl PolicyViolation {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::insert_into(policy_violations::table)
+                .values(PolicyViolationDb::to_db(self))
+                .execute(conn)
+                .map_res("Error saving policy violation")
+        }}
+    }
+
+    pub async fn find_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            policy_violations::table
+                .filter(policy_violations::org_uuid.eq(org_uuid))
+                .order_by(policy_violations::created_at.desc())
+                .load::<PolicyViolationDb>(conn)
+                .expect("Error loading policy violations")
+                .from_db()
+        }}
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(policy_violations::table.filter(policy_violations::org_uuid.eq(org_uuid)))
+                .execute(conn)
+                .map_res("Error deleting policy violations")
+        }}
+    }
+
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(policy_violations::table.filter(policy_violations::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting policy violations")
+        }}
+    }
+}
@@ -0,0 +1,172 @@
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{OrganizationId, SecretId, SecretProject, SecretsRevision, ServiceAccountProject};
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+};
+use macros::UuidFromParam;
+
+db_object! {
+    // Secrets Manager projects, used to group secrets and to grant service accounts access to them
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = projects)]
+    #[diesel(primary_key(uuid))]
+    pub struct Project {
+        pub uuid: ProjectId,
+        pub org_uuid: OrganizationId,
+        pub name: String, // Encrypted with the organization key
+        pub creation_date: NaiveDateTime,
+        pub revision_date: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct ProjectId(String);
+
+/// Local methods
+impl Project {
+    pub fn new(org_uuid: OrganizationId, name: String) -> Self {
+        let now = Utc::now().naive_utc();
+
+        Self {
+            uuid: ProjectId::from(get_uuid()),
+            org_uuid,
+            name,
+            creation_date: now,
+            revision_date: now,
+        }
+    }
+
+    pub fn to_json(&self, read: bool, write: bool) -> Value {
+        json!({
+            "id": self.uuid,
+            "organizationId": self.org_uuid,
+            "name": self.name,
+            "creationDate": format_date(&self.creation_date),
+            "revisionDate": format_date(&self.revision_date),
+            "read": read,
+            "write": write,
+            "object": "project",
+        })
+    }
+
+    pub fn to_json_mini(&self) -> Value {
+        json!({
+            "id": self.uuid,
+            "name": self.name,
+        })
+    }
+}
+
+/// Database methods
+impl Project {
+    pub async fn save(&mut self, conn: &mut DbConn) -> EmptyResult {
+        self.revision_date = Utc::now().naive_utc();
+
+        db_run! { conn:
+            sqlite, mysql {
+                match diesel::replace_into(projects::table)
+                    .values(ProjectDb::to_db(self))
+                    .execute(conn)
+                {
+                    Ok(_) => Ok(()),
+                    // Record already exists and causes a Foreign Key Violation because replace_into() wants to delete the record first.
+                    Err(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::ForeignKeyViolation, _)) => {
+                        diesel::update(projects::table)
+                            .filter(projects::uuid.eq(&self.uuid))
+                            .set(ProjectDb::to_db(self))
+                            .execute(conn)
+                            .map_res("Error saving project")
+                    }
+                    Err(e) => Err(e.into()),
+                }.map_res("Error saving project")
+            }
+            postgresql {
+                let value = ProjectDb::to_db(self);
+                diesel::insert_into(projects::table)
+                    .values(&value)
+                    .on_conflict(projects::uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving project")
+            }
+        }?;
+        // The project names are part of the secrets the clients sync
+        SecretsRevision::bump(&self.org_uuid, conn).await;
+        Ok(())
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        SecretsRevision::bump(&self.org_uuid, conn).await;
+        SecretProject::delete_all_by_project(&self.uuid, conn).await?;
+        ServiceAccountProject::delete_all_by_project(&self.uuid, conn).await?;
+
+        db_run! { conn: {
+            diesel::delete(projects::table.filter(projects::uuid.eq(self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting project")
+        }}
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        for project in Self::find_by_org(org_uuid, conn).await {
+            project.delete(conn).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn find_by_uuid(uuid: &ProjectId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            projects::table
+                .filter(projects::uuid.eq(uuid))
+                .first::<ProjectDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            projects::table
+                .filter(projects::org_uuid.eq(org_uuid))
+                .order_by(projects::creation_date)
+                .load::<ProjectDb>(conn)
+                .expect("Error loading projects")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_secret(secret_uuid: &SecretId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            projects::table
+                .inner_join(projects_secrets::table.on(projects_secrets::project_uuid.eq(projects::uuid)))
+                .filter(projects_secrets::secret_uuid.eq(secret_uuid))
+                .select(projects::all_columns)
+                .load::<ProjectDb>(conn)
+                .expect("Error loading projects")
+                .from_db()
+        }}
+    }
+}
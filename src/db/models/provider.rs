@@ -0,0 +1,574 @@
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{Membership, MembershipId, MembershipStatus, MembershipType, Organization, OrganizationId, User, UserId};
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+    CONFIG,
+};
+use macros::UuidFromParam;
+
+db_object! {
+    // A managed service provider, its users administer the client organizations without being members of them
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = providers)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(uuid))]
+    pub struct Provider {
+        pub uuid: ProviderId,
+        pub name: String,
+        pub business_name: Option<String>,
+        pub billing_email: String,
+        pub enabled: bool,
+        pub created_at: NaiveDateTime,
+        pub updated_at: NaiveDateTime,
+    }
+
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = provider_users)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(uuid))]
+    pub struct ProviderUser {
+        pub uuid: ProviderUserId,
+        pub provider_uuid: ProviderId,
+        pub user_uuid: UserId,
+        pub atype: i32,
+        pub status: i32,
+        // The provider key, encrypted with the public key of the user once confirmed
+        pub akey: Option<String>,
+        pub created_at: NaiveDateTime,
+    }
+
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = provider_organizations)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(uuid))]
+    pub struct ProviderOrganization {
+        pub uuid: ProviderOrganizationId,
+        pub provider_uuid: ProviderId,
+        pub org_uuid: OrganizationId,
+        // The organization key, encrypted with the provider key
+        pub akey: Option<String>,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+// https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Core/AdminConsole/Enums/Provider/ProviderUserType.cs
+#[derive(Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum ProviderUserType {
+    ProviderAdmin = 0,
+    ServiceUser = 1,
+}
+
+// https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Core/AdminConsole/Enums/Provider/ProviderUserStatusType.cs
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ProviderUserStatus {
+    Invited = 0,
+    Accepted = 1,
+    Confirmed = 2,
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct ProviderId(String);
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct ProviderUserId(String);
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct ProviderOrganizationId(String);
+
+/// Local methods
+impl Provider {
+    pub fn new(name: String, billing_email: String) -> Self {
+        let now = Utc::now().naive_utc();
+
+        Self {
+            uuid: ProviderId::from(get_uuid()),
+            name,
+            business_name: None,
+            billing_email: billing_email.to_lowercase(),
+            enabled: true,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    // https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Api/AdminConsole/Models/Response/Providers/ProviderResponseModel.cs
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.uuid,
+            "name": self.name,
+            "businessName": self.business_name,
+            "billingEmail": self.billing_email,
+            "creationDate": format_date(&self.created_at),
+            "type": 0, // Msp
+            "status": 2, // Billable
+            "enabled": self.enabled,
+            "useEvents": CONFIG.org_events_enabled(),
+            "object": "provider",
+        })
+    }
+}
+
+impl ProviderUser {
+    pub fn new(provider_uuid: ProviderId, user_uuid: UserId, atype: ProviderUserType) -> Self {
+        Self {
+            uuid: ProviderUserId::from(get_uuid()),
+            provider_uuid,
+            user_uuid,
+            atype: atype as i32,
+            status: ProviderUserStatus::Invited as i32,
+            akey: None,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn is_confirmed_admin(&self) -> bool {
+        self.status == ProviderUserStatus::Confirmed as i32 && self.atype == ProviderUserType::ProviderAdmin as i32
+    }
+
+    // https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Api/AdminConsole/Models/Response/Providers/ProviderUserResponseModel.cs
+    pub async fn to_json_user_details(&self, conn: &mut DbConn) -> Value {
+        let user = User::find_by_uuid(&self.user_uuid, conn).await;
+
+        json!({
+            "id": self.uuid,
+            "userId": self.user_uuid,
+            "type": self.atype,
+            "status": self.status,
+            "permissions": null,
+            "name": user.as_ref().map(|u| u.name.clone()),
+            "email": user.map(|u| u.email),
+            "object": "providerUserUserDetails",
+        })
+    }
+
+    // https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Api/AdminConsole/Models/Response/ProfileProviderResponseModel.cs
+    pub fn to_json_profile(&self, provider: &Provider) -> Value {
+        json!({
+            "id": provider.uuid,
+            "name": provider.name,
+            "key": self.akey,
+            "status": self.status,
+            "type": self.atype,
+            "enabled": provider.enabled,
+            "permissions": null,
+            "userId": self.user_uuid,
+            "useEvents": CONFIG.org_events_enabled(),
+            "providerStatus": 2, // Billable
+            "object": "profileProvider",
+        })
+    }
+}
+
+impl ProviderOrganization {
+    pub fn new(provider_uuid: ProviderId, org_uuid: OrganizationId, akey: Option<String>) -> Self {
+        Self {
+            uuid: ProviderOrganizationId::from(get_uuid()),
+            provider_uuid,
+            org_uuid,
+            akey,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    /// The access of a provider user to this client organization, as an Owner with access to all collections.
+    /// It only lives in memory, the provider user isn't a member of the organization.
+    pub fn membership_for(&self, provider_user: &ProviderUser) -> Membership {
+        let mut membership = Membership::new(provider_user.user_uuid.clone(), self.org_uuid.clone(), None);
+        membership.uuid = MembershipId::from(provider_user.uuid.to_string());
+        membership.access_all = true;
+        membership.akey = self.akey.clone().unwrap_or_default();
+        membership.status = MembershipStatus::Confirmed as i32;
+        membership.atype = MembershipType::Owner as i32;
+        membership
+    }
+
+    /// The organization in the profile of a provider user, next to the organizations they are a member of.
+    pub async fn to_json_profile(&self, provider: &Provider, provider_user: &ProviderUser, conn: &mut DbConn) -> Value {
+        let mut json = self.membership_for(provider_user).to_json(conn).await;
+        json["providerId"] = json!(provider.uuid);
+        json["providerName"] = json!(provider.name);
+        json["providerType"] = json!(0); // Msp
+        json["object"] = json!("profileProviderOrganization");
+        json
+    }
+
+    // https://github.com/bitwarden/server/blob/9ebe16587175b1c0e9208f84397bb75d0d595510/src/Api/AdminConsole/Models/Response/Providers/ProviderOrganizationResponseModel.cs
+    pub async fn to_json(&self, conn: &mut DbConn) -> Value {
+        let org = Organization::find_by_uuid(&self.org_uuid, conn).await;
+        let user_count = Membership::count_by_org(&self.org_uuid, conn).await;
+
+        json!({
+            "id": self.uuid,
+            "providerId": self.provider_uuid,
+            "organizationId": self.org_uuid,
+            "organizationName": org.as_ref().map(|o| o.name.clone()),
+            "key": self.akey,
+            "settings": null,
+            "creationDate": format_date(&self.created_at),
+            "revisionDate": format_date(&self.created_at),
+            "userCount": user_count,
+            "seats": org.and_then(|o| o.effective_max_seats()),
+            "plan": "Enterprise",
+            "status": 2, // Managed
+            "object": "providerOrganizationOrganizationDetails",
+        })
+    }
+}
+
+/// Database methods
+impl Provider {
+    pub async fn save(&mut self, conn: &mut DbConn) -> EmptyResult {
+        if !crate::util::is_valid_email(&self.billing_email) {
+            err!(format!("BillingEmail {} is not a valid email address", self.billing_email))
+        }
+        self.updated_at = Utc::now().naive_utc();
+
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(providers::table)
+                    .values(ProviderDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving provider")
+            }
+            postgresql {
+                let value = ProviderDb::to_db(self);
+                diesel::insert_into(providers::table)
+                    .values(&value)
+                    .on_conflict(providers::uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving provider")
+            }
+        }
+    }
+
+    /// Deletes the provider, the client organizations stay as they are without a provider.
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        for provider_user in ProviderUser::find_by_provider(&self.uuid, conn).await {
+            User::update_uuid_revision(&provider_user.user_uuid, conn).await;
+        }
+        ProviderOrganization::delete_all_by_provider(&self.uuid, conn).await?;
+        ProviderUser::delete_all_by_provider(&self.uuid, conn).await?;
+
+        db_run! { conn: {
+            diesel::delete(providers::table.filter(providers::uuid.eq(self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting provider")
+        }}
+    }
+
+    pub async fn find_by_uuid(uuid: &ProviderId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            providers::table
+                .filter(providers::uuid.eq(uuid))
+                .first::<ProviderDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn get_all(conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            providers::table
+                .order_by(providers::name.asc())
+                .load::<ProviderDb>(conn)
+                .expect("Error loading providers")
+                .from_db()
+        }}
+    }
+}
+
+impl ProviderUser {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        User::update_uuid_revision(&self.user_uuid, conn).await;
+
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(provider_users::table)
+                    .values(ProviderUserDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving provider user")
+            }
+            postgresql {
+                let value = ProviderUserDb::to_db(self);
+                diesel::insert_into(provider_users::table)
+                    .values(&value)
+                    .on_conflict(provider_users::uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving provider user")
+            }
+        }
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        User::update_uuid_revision(&self.user_uuid, conn).await;
+
+        db_run! { conn: {
+            diesel::delete(provider_users::table.filter(provider_users::uuid.eq(self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting provider user")
+        }}
+    }
+
+    pub async fn find_by_uuid_and_provider(
+        uuid: &ProviderUserId,
+        provider_uuid: &ProviderId,
+        conn: &mut DbConn,
+    ) -> Option<Self> {
+        db_run! { conn: {
+            provider_users::table
+                .filter(provider_users::uuid.eq(uuid))
+                .filter(provider_users::provider_uuid.eq(provider_uuid))
+                .first::<ProviderUserDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_user_and_provider(
+        user_uuid: &UserId,
+        provider_uuid: &ProviderId,
+        conn: &mut DbConn,
+    ) -> Option<Self> {
+        db_run! { conn: {
+            provider_users::table
+                .filter(provider_users::user_uuid.eq(user_uuid))
+                .filter(provider_users::provider_uuid.eq(provider_uuid))
+                .first::<ProviderUserDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_provider(provider_uuid: &ProviderId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            provider_users::table
+                .filter(provider_users::provider_uuid.eq(provider_uuid))
+                .order_by(provider_users::created_at.asc())
+                .load::<ProviderUserDb>(conn)
+                .expect("Error loading provider users")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_user(user_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            provider_users::table
+                .filter(provider_users::user_uuid.eq(user_uuid))
+                .load::<ProviderUserDb>(conn)
+                .expect("Error loading provider users")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_confirmed_by_user(user_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            provider_users::table
+                .filter(provider_users::user_uuid.eq(user_uuid))
+                .filter(provider_users::status.eq(ProviderUserStatus::Confirmed as i32))
+                .load::<ProviderUserDb>(conn)
+                .expect("Error loading provider users")
+                .from_db()
+        }}
+    }
+
+    /// The confirmed user of an enabled provider which manages the organization, if any.
+    pub async fn find_confirmed_by_user_and_org(
+        user_uuid: &UserId,
+        org_uuid: &OrganizationId,
+        conn: &mut DbConn,
+    ) -> Option<Self> {
+        db_run! { conn: {
+            provider_users::table
+                .inner_join(providers::table.on(providers::uuid.eq(provider_users::provider_uuid)))
+                .inner_join(
+                    provider_organizations::table
+                        .on(provider_organizations::provider_uuid.eq(provider_users::provider_uuid)),
+                )
+                .filter(provider_users::user_uuid.eq(user_uuid))
+                .filter(provider_users::status.eq(ProviderUserStatus::Confirmed as i32))
+                .filter(providers::enabled.eq(true))
+                .filter(provider_organizations::org_uuid.eq(org_uuid))
+                .select(provider_users::all_columns)
+                .first::<ProviderUserDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn count_confirmed_admins(provider_uuid: &ProviderId, conn: &mut DbConn) -> i64 {
+        db_run! { conn: {
+            provider_users::table
+                .filter(provider_users::provider_uuid.eq(provider_uuid))
+                .filter(provider_users::status.eq(ProviderUserStatus::Confirmed as i32))
+                .filter(provider_users::atype.eq(ProviderUserType::ProviderAdmin as i32))
+                .count()
+                .first::<i64>(conn)
+                .ok()
+                .unwrap_or(0)
+        }}
+    }
+
+    pub async fn delete_all_by_provider(provider_uuid: &ProviderId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(provider_users::table.filter(provider_users::provider_uuid.eq(provider_uuid)))
+                .execute(conn)
+                .map_res("Error deleting provider users")
+        }}
+    }
+
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(provider_users::table.filter(provider_users::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting provider users")
+        }}
+    }
+}
+
+impl ProviderOrganization {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        for provider_user in ProviderUser::find_by_provider(&self.provider_uuid, conn).await {
+            User::update_uuid_revision(&provider_user.user_uuid, conn).await;
+        }
+
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(provider_organizations::table)
+                    .values(ProviderOrganizationDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving provider organization")
+            }
+            postgresql {
+                let value = ProviderOrganizationDb::to_db(self);
+                diesel::insert_into(provider_organizations::table)
+                    .values(&value)
+                    .on_conflict(provider_organizations::uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving provider organization")
+            }
+        }
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        for provider_user in ProviderUser::find_by_provider(&self.provider_uuid, conn).await {
+            User::update_uuid_revision(&provider_user.user_uuid, conn).await;
+        }
+
+        db_run! { conn: {
+            diesel::delete(provider_organizations::table.filter(provider_organizations::uuid.eq(self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting provider organization")
+        }}
+    }
+
+    pub async fn find_by_uuid_and_provider(
+        uuid: &ProviderOrganizationId,
+        provider_uuid: &ProviderId,
+        conn: &mut DbConn,
+    ) -> Option<Self> {
+        db_run! { conn: {
+            provider_organizations::table
+                .filter(provider_organizations::uuid.eq(uuid))
+                .filter(provider_organizations::provider_uuid.eq(provider_uuid))
+                .first::<ProviderOrganizationDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            provider_organizations::table
+                .filter(provider_organizations::org_uuid.eq(org_uuid))
+                .first::<ProviderOrganizationDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_provider(provider_uuid: &ProviderId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            provider_organizations::table
+                .filter(provider_organizations::provider_uuid.eq(provider_uuid))
+                .order_by(provider_organizations::created_at.asc())
+                .load::<ProviderOrganizationDb>(conn)
+                .expect("Error loading provider organizations")
+                .from_db()
+        }}
+    }
+
+    pub async fn delete_all_by_provider(provider_uuid: &ProviderId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(provider_organizations::table.filter(provider_organizations::provider_uuid.eq(provider_uuid)))
+                .execute(conn)
+                .map_res("Error deleting provider organizations")
+        }}
+    }
+
+    pub async fn delete_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        if let Some(provider_org) = Self::find_by_org(org_uuid, conn).await {
+            provider_org.delete(conn).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{CipherId, OrganizationId, SendId, UserId};
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+};
+use macros::UuidFromParam;
+
+db_object! {
+    // Uploads rejected by the virus scanner, the file itself is not kept
+    #[derive(Identifiable, Queryable, Insertable)]
+    #[diesel(table_name = quarantined_files)]
+    #[diesel(primary_key(uuid))]
+    pub struct QuarantinedFile {
+        pub uuid: QuarantinedFileId,
+        pub user_uuid: UserId,
+        pub org_uuid: Option<OrganizationId>,
+        // Exactly one of these is set, depending on whether an attachment or a Send file was uploaded
+        pub cipher_uuid: Option<CipherId>,
+        pub send_uuid: Option<SendId>,
+        pub file_size: i64,
+        pub signature: String,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct QuarantinedFileId(String);
+
+/// Local methods
+impl QuarantinedFile {
+    pub fn new(user_uuid: UserId, org_uuid: Option<OrganizationId>, file_size: i64, signature: String) -> Self {
+        Self {
+            uuid: QuarantinedFileId::from(get_uuid()),
+            user_uuid,
+            org_uuid,
+            cipher_uuid: None,
+            send_uuid: None,
+            file_size,
+            signature,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.uuid,
+            "userId": self.user_uuid,
+            "organizationId": self.org_uuid,
+            "cipherId": self.cipher_uuid,
+            "sendId": self.send_uuid,
+            "fileSize": self.file_size,
+            "signature": self.signature,
+            "date": format_date(&self.created_at),
+        })
+    }
+}
+
+/// Database methods
+impl QuarantinedFile {
+    /// Number of entries returned per listing.
+    pub const PAGE_SIZE: i64 = 100;
+
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::insert_into(quarantined_files::table)
+                .values(QuarantinedFileDb::to_db(self))
+                .execute(conn)
+                .map_res("Error saving quarantined file")
+        }}
+    }
+
+    pub async fn find_latest(conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            quarantined_files::table
+                .order_by(quarantined_files::created_at.desc())
+                .limit(Self::PAGE_SIZE)
+                .load::<QuarantinedFileDb>(conn)
+                .expect("Error loading quarantined files")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            quarantined_files::table
+                .filter(quarantined_files::org_uuid.eq(org_uuid))
+                .order_by(quarantined_files::created_at.desc())
+                .limit(Self::PAGE_SIZE)
+                .load::<QuarantinedFileDb>(conn)
+                .expect("Error loading quarantined files")
+                .from_db()
+        }}
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(quarantined_files::table.filter(quarantined_files::org_uuid.eq(org_uuid)))
+                .execute(conn)
+                .map_res("Error deleting quarantined files")
+        }}
+    }
+
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(quarantined_files::table.filter(quarantined_files::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting quarantined files")
+        }}
+    }
+}
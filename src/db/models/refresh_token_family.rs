@@ -0,0 +1,159 @@
+use chrono::{NaiveDateTime, Utc};
+use serde_json::Value;
+
+use super::{DeviceId, UserId};
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+};
+
+db_object! {
+    // The refresh tokens handed out to a device since its last login, a token is only valid while its generation
+    // is the current one. Presenting an older generation means the token was copied, and ends the family.
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = refresh_token_families)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(user_uuid, device_uuid))]
+    pub struct RefreshTokenFamily {
+        pub user_uuid: UserId,
+        pub device_uuid: DeviceId,
+        pub family_uuid: String,
+        pub generation: i32,
+        pub created_at: NaiveDateTime,
+        // The time the current generation was issued
+        pub rotated_at: NaiveDateTime,
+        pub last_ip: Option<String>,
+    }
+}
+
+/// Local methods
+impl RefreshTokenFamily {
+    pub fn new(user_uuid: UserId, device_uuid: DeviceId, ip: String) -> Self {
+        let now = Utc::now().naive_utc();
+        Self {
+            user_uuid,
+            device_uuid,
+            family_uuid: get_uuid(),
+            generation: 0,
+            created_at: now,
+            rotated_at: now,
+            last_ip: Some(ip),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "deviceId": self.device_uuid,
+            "familyId": self.family_uuid,
+            "generation": self.generation,
+            "creationDate": format_date(&self.created_at),
+            "rotationDate": format_date(&self.rotated_at),
+            "lastIpAddress": self.last_ip,
+        })
+    }
+}
+
+/// Database methods
+impl RefreshTokenFamily {
+    /// Starts a new family for the device, the tokens of a previous one stop being valid.
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(refresh_token_families::table)
+                    .values(RefreshTokenFamilyDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving refresh token family")
+            }
+            postgresql {
+                let value = RefreshTokenFamilyDb::to_db(self);
+                diesel::insert_into(refresh_token_families::table)
+                    .values(&value)
+                    .on_conflict((refresh_token_families::user_uuid, refresh_token_families::device_uuid))
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving refresh token family")
+            }
+        }
+    }
+
+    /// Moves the family to the next generation, only if it is still at the generation that was read.
+    /// Returns false when a concurrent refresh got there first.
+    pub async fn advance(&mut self, ip: &str, conn: &mut DbConn) -> Result<bool, crate::Error> {
+        let now = Utc::now().naive_utc();
+        let updated: usize = db_run! { conn: {
+            diesel::update(refresh_token_families::table)
+                .filter(refresh_token_families::user_uuid.eq(&self.user_uuid))
+                .filter(refresh_token_families::device_uuid.eq(&self.device_uuid))
+                .filter(refresh_token_families::family_uuid.eq(&self.family_uuid))
+                .filter(refresh_token_families::generation.eq(self.generation))
+                .set((
+                    refresh_token_families::generation.eq(self.generation + 1),
+                    refresh_token_families::rotated_at.eq(now),
+                    refresh_token_families::last_ip.eq(ip),
+                ))
+                .execute(conn)
+                .map_res("Error rotating refresh token family")
+        }}?;
+
+        if updated != 1 {
+            return Ok(false);
+        }
+        self.generation += 1;
+        self.rotated_at = now;
+        self.last_ip = Some(ip.to_string());
+        Ok(true)
+    }
+
+    pub async fn find_by_user_and_device(
+        user_uuid: &UserId,
+        device_uuid: &DeviceId,
+        conn: &mut DbConn,
+    ) -> Option<Self> {
+        db_run! { conn: {
+            refresh_token_families::table
+                .filter(refresh_token_families::user_uuid.eq(user_uuid))
+                .filter(refresh_token_families::device_uuid.eq(device_uuid))
+                .first::<RefreshTokenFamilyDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_user(user_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            refresh_token_families::table
+                .filter(refresh_token_families::user_uuid.eq(user_uuid))
+                .order(refresh_token_families::rotated_at.desc())
+                .load::<RefreshTokenFamilyDb>(conn)
+                .expect("Error loading refresh token families")
+                .from_db()
+        }}
+    }
+
+    pub async fn delete_by_user_and_device(
+        user_uuid: &UserId,
+        device_uuid: &DeviceId,
+        conn: &mut DbConn,
+    ) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(
+                refresh_token_families::table
+                    .filter(refresh_token_families::user_uuid.eq(user_uuid))
+                    .filter(refresh_token_families::device_uuid.eq(device_uuid)),
+            )
+            .execute(conn)
+            .map_res("Error deleting refresh token family")
+        }}
+    }
+
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(refresh_token_families::table.filter(refresh_token_families::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting refresh token families")
+        }}
+    }
+}
@@ -1,13 +1,14 @@
-use chrono::{NaiveDateTime, Utc};
+use chrono::{NaiveDateTime, TimeDelta, Utc};
 use derive_more::{AsRef, Deref, Display, From};
 use diesel::prelude::*;
+use serde_json::Value;
 
-use super::{OrganizationId, UserId};
+use super::{Change, ChangeAction, ChangeEntity, CollectionId, OrganizationId, UserId};
 use crate::{
     api::EmptyResult,
     db::DbConn,
     error::MapResult,
-    util::get_uuid,
+    util::{format_date, get_uuid},
 };
 use macros::UuidFromParam;
 
@@ -23,6 +24,14 @@ db_object! {
         pub exposed_count: i32,
         pub created_at: NaiveDateTime,
         pub last_updated_at: NaiveDateTime,
+        pub threshold_exceeded_since: Option<NaiveDateTime>,
+        // When the owners were last notified about an increase of the exposed count
+        pub delta_notified_at: Option<NaiveDateTime>,
+        // Set for the per-collection breakdown of an org report, the org totals have no collection
+        pub collection_uuid: Option<CollectionId>,
+        // Set once the count was stored through the authenticated `/exposed` endpoints,
+        // counts stored before those required a login are left out of the signed exports
+        pub authenticated: bool,
     }
 }
 
@@ -56,6 +65,10 @@ impl Report {
             exposed_count,
             created_at: now,
             last_updated_at: now,
+            threshold_exceeded_since: None,
+            delta_notified_at: None,
+            collection_uuid: None,
+            authenticated: true,
         }
     }
     
@@ -69,9 +82,30 @@ impl Report {
             exposed_count,
             created_at: now,
             last_updated_at: now,
+            threshold_exceeded_since: None,
+            delta_notified_at: None,
+            collection_uuid: None,
+            authenticated: true,
         }
     }
     
+    pub fn new_collection(org_uuid: OrganizationId, collection_uuid: CollectionId, exposed_count: i32) -> Self {
+        let now = Utc::now().naive_utc();
+
+        Self {
+            uuid: ReportId::from(get_uuid()),
+            user_uuid: None,
+            org_uuid: Some(org_uuid),
+            exposed_count,
+            created_at: now,
+            last_updated_at: now,
+            threshold_exceeded_since: None,
+            delta_notified_at: None,
+            collection_uuid: Some(collection_uuid),
+            authenticated: true,
+        }
+    }
+
     pub async fn find_by_user_personal(user_uuid: &UserId, conn: &mut DbConn) -> Option<Self> {
         db_run! { conn: {
             reports::table
@@ -88,18 +122,111 @@ impl Report {
             reports::table
                 .filter(reports::user_uuid.is_null())
                 .filter(reports::org_uuid.eq(org_uuid))
+                .filter(reports::collection_uuid.is_null())
                 .first::<ReportDb>(conn)
                 .ok()
                 .from_db()
         }}
     }
     
+    pub async fn find_all_orgs(conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            reports::table
+                .filter(reports::user_uuid.is_null())
+                .filter(reports::org_uuid.is_not_null())
+                .filter(reports::collection_uuid.is_null())
+                .load::<ReportDb>(conn)
+                .expect("Error loading reports")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_collection(collection_uuid: &CollectionId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            reports::table
+                .filter(reports::collection_uuid.eq(collection_uuid))
+                .first::<ReportDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    /// The per-collection reports of the organization, the highest exposed count first.
+    pub async fn find_collections_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            reports::table
+                .filter(reports::org_uuid.eq(org_uuid))
+                .filter(reports::collection_uuid.is_not_null())
+                .order_by(reports::exposed_count.desc())
+                .load::<ReportDb>(conn)
+                .expect("Error loading reports")
+                .from_db()
+        }}
+    }
+
+    /// Returns the total exposed count of the personal reports and of the org reports.
+    pub async fn total_exposed_counts(conn: &mut DbConn) -> (i64, i64) {
+        db_run! { conn: {
+            reports::table
+                .filter(reports::collection_uuid.is_null())
+                .select((reports::org_uuid.is_null(), reports::exposed_count))
+                .load::<(bool, i32)>(conn)
+                .expect("Error loading reports")
+                .into_iter()
+                .fold((0, 0), |(personal, org), (is_personal, count)| {
+                    if is_personal {
+                        (personal + i64::from(count), org)
+                    } else {
+                        (personal, org + i64::from(count))
+                    }
+                })
+        }}
+    }
+
     pub fn update_exposed_count(&mut self, new_count: i32) {
         self.exposed_count = if new_count < 0 { 0 } else { new_count };
         self.last_updated_at = Utc::now().naive_utc();
+        self.authenticated = true;
+    }
+
+    /// Keeps track of since when the exposed count is above the given threshold.
+    /// Returns the number of consecutive days the threshold has been exceeded, or `None` if it currently isn't.
+    pub fn evaluate_threshold(&mut self, threshold: i32) -> Option<i64> {
+        if self.exposed_count <= threshold {
+            self.threshold_exceeded_since = None;
+            return None;
+        }
+
+        let now = Utc::now().naive_utc();
+        let since = *self.threshold_exceeded_since.get_or_insert(now);
+        Some((now.date() - since.date()).num_days())
+    }
+
+    /// Checks whether the increase of the exposed count since `previous_count` is beyond the given absolute threshold
+    /// or percentage, and whether the owners weren't notified during the last day. Marks the report as notified if so.
+    pub fn evaluate_delta(&mut self, previous_count: i32, threshold: Option<i32>, percentage: Option<i32>) -> bool {
+        let increase = i64::from(self.exposed_count) - i64::from(previous_count);
+        if increase <= 0 {
+            return false;
+        }
+
+        let exceeds_threshold = threshold.is_some_and(|t| increase > i64::from(t));
+        let exceeds_percentage = percentage
+            .is_some_and(|p| previous_count == 0 || increase * 100 > i64::from(previous_count) * i64::from(p));
+        if !exceeds_threshold && !exceeds_percentage {
+            return false;
+        }
+
+        let now = Utc::now().naive_utc();
+        if self.delta_notified_at.is_some_and(|last| now - last < TimeDelta::days(1)) {
+            return false;
+        }
+        self.delta_notified_at = Some(now);
+        true
     }
     
     pub async fn save(&mut self, conn: &mut DbConn) -> EmptyResult {
+        Change::record(ChangeEntity::Report, &self.uuid, ChangeAction::Upsert, conn).await;
         db_run! { conn:
             sqlite, mysql {
                 let value = ReportDb::to_db(self);
@@ -123,5 +250,65 @@ impl Report {
             }
         }
     }
+
+    /// Deletes the personal reports of the user, org reports don't reference a user.
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        if let Some(report) = Self::find_by_user_personal(user_uuid, conn).await {
+            Change::record(ChangeEntity::Report, &report.uuid, ChangeAction::Delete, conn).await;
+        }
+        db_run! { conn: {
+            diesel::delete(reports::table.filter(reports::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting reports")
+        }}
+    }
+
+    pub async fn delete_all_by_collection(collection_uuid: &CollectionId, conn: &mut DbConn) -> EmptyResult {
+        if let Some(report) = Self::find_by_collection(collection_uuid, conn).await {
+            Change::record(ChangeEntity::Report, &report.uuid, ChangeAction::Delete, conn).await;
+        }
+        db_run! { conn: {
+            diesel::delete(reports::table.filter(reports::collection_uuid.eq(collection_uuid)))
+                .execute(conn)
+                .map_res("Error deleting reports")
+        }}
+    }
+
+    /// Deletes the report of the organization and the ones of its collections.
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        let mut reports = Self::find_collections_by_org(org_uuid, conn).await;
+        reports.extend(Self::find_by_org(org_uuid, conn).await);
+        for report in reports {
+            Change::record(ChangeEntity::Report, &report.uuid, ChangeAction::Delete, conn).await;
+        }
+        db_run! { conn: {
+            diesel::delete(reports::table.filter(reports::org_uuid.eq(org_uuid)))
+                .execute(conn)
+                .map_res("Error deleting reports")
+        }}
+    }
+
+    /// Returns the report as JSON for the replication stream, only the listed columns are part of it.
+    pub async fn find_row_json(uuid: &ReportId, conn: &mut DbConn) -> Option<Value> {
+        let report: Option<Self> = db_run! { conn: {
+            reports::table
+                .filter(reports::uuid.eq(uuid))
+                .first::<ReportDb>(conn)
+                .ok()
+                .from_db()
+        }};
+        let report = report?;
+        Some(json!({
+            "uuid": report.uuid,
+            "user_uuid": report.user_uuid,
+            "org_uuid": report.org_uuid,
+            "collection_uuid": report.collection_uuid,
+            "exposed_count": report.exposed_count,
+            "created_at": format_date(&report.created_at),
+            "last_updated_at": format_date(&report.last_updated_at),
+            "threshold_exceeded_since": report.threshold_exceeded_since.as_ref().map(format_date),
+            "delta_notified_at": report.delta_notified_at.as_ref().map(format_date),
+        }))
+    }
 }
 
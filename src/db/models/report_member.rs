@@ -0,0 +1,195 @@
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+
+use super::{OrganizationId, Report, UserId};
+use crate::{api::EmptyResult, db::DbConn, error::MapResult};
+
+db_object! {
+    // The findings of a member in the vault of the organization, the per-member breakdown of the org report
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = report_members)]
+    #[diesel(primary_key(org_uuid, user_uuid))]
+    pub struct ReportMember {
+        pub org_uuid: OrganizationId,
+        pub user_uuid: UserId,
+        pub exposed_count: i32,
+        pub weak_count: i32,
+        pub reused_count: i32,
+        pub updated_at: NaiveDateTime,
+    }
+
+    // A daily snapshot of the org report, used to show its evolution in the report exports
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = report_history)]
+    #[diesel(primary_key(org_uuid, day))]
+    pub struct ReportHistory {
+        pub org_uuid: OrganizationId,
+        pub day: NaiveDate,
+        pub exposed_count: i32,
+        // The totals of the weak and reused passwords of the members
+        pub weak_count: i32,
+        pub reused_count: i32,
+    }
+}
+
+/// Local methods
+impl ReportMember {
+    pub fn new(
+        org_uuid: OrganizationId,
+        user_uuid: UserId,
+        exposed_count: i32,
+        weak_count: i32,
+        reused_count: i32,
+    ) -> Self {
+        Self {
+            org_uuid,
+            user_uuid,
+            exposed_count: exposed_count.max(0),
+            weak_count: weak_count.max(0),
+            reused_count: reused_count.max(0),
+            updated_at: Utc::now().naive_utc(),
+        }
+    }
+}
+
+/// Database methods
+impl ReportMember {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(report_members::table)
+                    .values(ReportMemberDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving member report")
+            }
+            postgresql {
+                let value = ReportMemberDb::to_db(self);
+                diesel::insert_into(report_members::table)
+                    .values(&value)
+                    .on_conflict((report_members::org_uuid, report_members::user_uuid))
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving member report")
+            }
+        }
+    }
+
+    /// The member reports of the organization, the highest exposed count first.
+    pub async fn find_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            report_members::table
+                .filter(report_members::org_uuid.eq(org_uuid))
+                .order_by((report_members::exposed_count.desc(), report_members::user_uuid))
+                .load::<ReportMemberDb>(conn)
+                .expect("Error loading member reports")
+                .from_db()
+        }}
+    }
+
+    /// Returns the total weak and reused counts of the members of the organization.
+    pub async fn totals_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> (i64, i64) {
+        db_run! { conn: {
+            report_members::table
+                .filter(report_members::org_uuid.eq(org_uuid))
+                .select((report_members::weak_count, report_members::reused_count))
+                .load::<(i32, i32)>(conn)
+                .expect("Error loading member reports")
+                .into_iter()
+                .fold((0, 0), |(weak, reused), (w, r)| (weak + i64::from(w), reused + i64::from(r)))
+        }}
+    }
+
+    pub async fn delete_by_org_and_user(
+        org_uuid: &OrganizationId,
+        user_uuid: &UserId,
+        conn: &mut DbConn,
+    ) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(
+                report_members::table
+                    .filter(report_members::org_uuid.eq(org_uuid))
+                    .filter(report_members::user_uuid.eq(user_uuid)),
+            )
+            .execute(conn)
+            .map_res("Error deleting member report")
+        }}
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(report_members::table.filter(report_members::org_uuid.eq(org_uuid)))
+                .execute(conn)
+                .map_res("Error deleting member reports")
+        }}
+    }
+
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(report_members::table.filter(report_members::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting member reports")
+        }}
+    }
+}
+
+/// Local methods
+impl ReportHistory {
+    /// Collects the current org report and member totals as the snapshot of today.
+    pub async fn collect(org_uuid: &OrganizationId, conn: &mut DbConn) -> Self {
+        let exposed_count =
+            Report::find_by_org(org_uuid, conn).await.filter(|r| r.authenticated).map_or(0, |r| r.exposed_count);
+        let (weak, reused) = ReportMember::totals_by_org(org_uuid, conn).await;
+
+        Self {
+            org_uuid: org_uuid.clone(),
+            day: Utc::now().date_naive(),
+            exposed_count,
+            weak_count: i32::try_from(weak).unwrap_or(i32::MAX),
+            reused_count: i32::try_from(reused).unwrap_or(i32::MAX),
+        }
+    }
+}
+
+/// Database methods
+impl ReportHistory {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(report_history::table)
+                    .values(ReportHistoryDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving report history")
+            }
+            postgresql {
+                let value = ReportHistoryDb::to_db(self);
+                diesel::insert_into(report_history::table)
+                    .values(&value)
+                    .on_conflict((report_history::org_uuid, report_history::day))
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving report history")
+            }
+        }
+    }
+
+    pub async fn find_by_org_since(org_uuid: &OrganizationId, since: NaiveDate, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            report_history::table
+                .filter(report_history::org_uuid.eq(org_uuid))
+                .filter(report_history::day.ge(since))
+                .order(report_history::day.asc())
+                .load::<ReportHistoryDb>(conn)
+                .expect("Error loading report history")
+                .from_db()
+        }}
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(report_history::table.filter(report_history::org_uuid.eq(org_uuid)))
+                .execute(conn)
+                .map_res("Error deleting report history")
+        }}
+    }
+}
@@ -0,0 +1,294 @@
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{OrganizationId, Project, ProjectId};
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+};
+use macros::UuidFromParam;
+
+db_object! {
+    // Secrets Manager secrets, the key, value and note are encrypted with the organization key
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = secrets)]
+    #[diesel(primary_key(uuid))]
+    pub struct Secret {
+        pub uuid: SecretId,
+        pub org_uuid: OrganizationId,
+        pub akey: String,
+        pub value: String,
+        pub note: String,
+        pub creation_date: NaiveDateTime,
+        pub revision_date: NaiveDateTime,
+    }
+
+    #[derive(Identifiable, Queryable, Insertable)]
+    #[diesel(table_name = projects_secrets)]
+    #[diesel(primary_key(project_uuid, secret_uuid))]
+    pub struct SecretProject {
+        pub project_uuid: ProjectId,
+        pub secret_uuid: SecretId,
+    }
+
+    // Bumped on every change of the secrets, projects or access policies of an organization, deletes included,
+    // the secrets don't keep a trace of those changes themselves
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = secrets_revisions)]
+    #[diesel(primary_key(org_uuid))]
+    pub struct SecretsRevision {
+        pub org_uuid: OrganizationId,
+        pub revision_date: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct SecretId(String);
+
+/// Local methods
+impl Secret {
+    pub fn new(org_uuid: OrganizationId, akey: String, value: String, note: String) -> Self {
+        let now = Utc::now().naive_utc();
+
+        Self {
+            uuid: SecretId::from(get_uuid()),
+            org_uuid,
+            akey,
+            value,
+            note,
+            creation_date: now,
+            revision_date: now,
+        }
+    }
+
+    /// The full secret, including the value and note.
+    pub fn to_json(&self, projects: &[Project], read: bool, write: bool) -> Value {
+        json!({
+            "id": self.uuid,
+            "organizationId": self.org_uuid,
+            "key": self.akey,
+            "value": self.value,
+            "note": self.note,
+            "creationDate": format_date(&self.creation_date),
+            "revisionDate": format_date(&self.revision_date),
+            "projects": projects.iter().map(Project::to_json_mini).collect::<Vec<Value>>(),
+            "read": read,
+            "write": write,
+            "object": "secret",
+        })
+    }
+
+    /// The secret as returned in listings, without the value and note.
+    pub fn to_json_list_item(&self, projects: &[Project], read: bool, write: bool) -> Value {
+        json!({
+            "id": self.uuid,
+            "organizationId": self.org_uuid,
+            "key": self.akey,
+            "creationDate": format_date(&self.creation_date),
+            "revisionDate": format_date(&self.revision_date),
+            "projects": projects.iter().map(Project::to_json_mini).collect::<Vec<Value>>(),
+            "read": read,
+            "write": write,
+        })
+    }
+}
+
+/// Database methods
+impl Secret {
+    pub async fn save(&mut self, conn: &mut DbConn) -> EmptyResult {
+        self.revision_date = Utc::now().naive_utc();
+
+        db_run! { conn:
+            sqlite, mysql {
+                match diesel::replace_into(secrets::table)
+                    .values(SecretDb::to_db(self))
+                    .execute(conn)
+                {
+                    Ok(_) => Ok(()),
+                    // Record already exists and causes a Foreign Key Violation because replace_into() wants to delete the record first.
+                    Err(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::ForeignKeyViolation, _)) => {
+                        diesel::update(secrets::table)
+                            .filter(secrets::uuid.eq(&self.uuid))
+                            .set(SecretDb::to_db(self))
+                            .execute(conn)
+                            .map_res("Error saving secret")
+                    }
+                    Err(e) => Err(e.into()),
+                }.map_res("Error saving secret")
+            }
+            postgresql {
+                let value = SecretDb::to_db(self);
+                diesel::insert_into(secrets::table)
+                    .values(&value)
+                    .on_conflict(secrets::uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving secret")
+            }
+        }?;
+        SecretsRevision::bump(&self.org_uuid, conn).await;
+        Ok(())
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        SecretsRevision::bump(&self.org_uuid, conn).await;
+        SecretProject::delete_all_by_secret(&self.uuid, conn).await?;
+
+        db_run! { conn: {
+            diesel::delete(secrets::table.filter(secrets::uuid.eq(self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting secret")
+        }}
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        for secret in Self::find_by_org(org_uuid, conn).await {
+            secret.delete(conn).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn find_by_uuid(uuid: &SecretId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            secrets::table
+                .filter(secrets::uuid.eq(uuid))
+                .first::<SecretDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            secrets::table
+                .filter(secrets::org_uuid.eq(org_uuid))
+                .order_by(secrets::creation_date)
+                .load::<SecretDb>(conn)
+                .expect("Error loading secrets")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_project(project_uuid: &ProjectId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            secrets::table
+                .inner_join(projects_secrets::table.on(projects_secrets::secret_uuid.eq(secrets::uuid)))
+                .filter(projects_secrets::project_uuid.eq(project_uuid))
+                .select(secrets::all_columns)
+                .order_by(secrets::creation_date)
+                .load::<SecretDb>(conn)
+                .expect("Error loading secrets")
+                .from_db()
+        }}
+    }
+}
+
+impl SecretsRevision {
+    /// Marks the secrets of the organization as changed, so the next sync of the clients returns them again.
+    pub async fn bump(org_uuid: &OrganizationId, conn: &mut DbConn) {
+        let value = Self {
+            org_uuid: org_uuid.clone(),
+            revision_date: Utc::now().naive_utc(),
+        };
+        let result: EmptyResult = db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(secrets_revisions::table)
+                    .values(SecretsRevisionDb::to_db(&value))
+                    .execute(conn)
+                    .map_res("Error saving secrets revision")
+            }
+            postgresql {
+                let value = SecretsRevisionDb::to_db(&value);
+                diesel::insert_into(secrets_revisions::table)
+                    .values(&value)
+                    .on_conflict(secrets_revisions::org_uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving secrets revision")
+            }
+        };
+        if let Err(e) = result {
+            warn!("Failed to update the secrets revision of {org_uuid}: {e:#?}");
+        }
+    }
+
+    /// The last change of the secrets of the organization, used to tell clients whether they need to sync.
+    pub async fn find_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Option<NaiveDateTime> {
+        db_run! { conn: {
+            secrets_revisions::table
+                .filter(secrets_revisions::org_uuid.eq(org_uuid))
+                .select(secrets_revisions::revision_date)
+                .first::<NaiveDateTime>(conn)
+                .ok()
+        }}
+    }
+
+    pub async fn delete_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(secrets_revisions::table.filter(secrets_revisions::org_uuid.eq(org_uuid)))
+                .execute(conn)
+                .map_res("Error deleting secrets revision")
+        }}
+    }
+}
+
+impl SecretProject {
+    /// Replaces the projects of a secret, Bitwarden allows one project per secret but the API accepts a list.
+    pub async fn replace_projects(
+        secret_uuid: &SecretId,
+        project_uuids: &[ProjectId],
+        conn: &mut DbConn,
+    ) -> EmptyResult {
+        Self::delete_all_by_secret(secret_uuid, conn).await?;
+
+        for project_uuid in project_uuids {
+            db_run! { conn: {
+                diesel::insert_into(projects_secrets::table)
+                    .values((
+                        projects_secrets::project_uuid.eq(project_uuid),
+                        projects_secrets::secret_uuid.eq(secret_uuid),
+                    ))
+                    .execute(conn)
+                    .map_res("Error adding secret to project")
+            }}?;
+        }
+        Ok(())
+    }
+
+    pub async fn delete_all_by_secret(secret_uuid: &SecretId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(projects_secrets::table.filter(projects_secrets::secret_uuid.eq(secret_uuid)))
+                .execute(conn)
+                .map_res("Error removing secret from projects")
+        }}
+    }
+
+    pub async fn delete_all_by_project(project_uuid: &ProjectId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(projects_secrets::table.filter(projects_secrets::project_uuid.eq(project_uuid)))
+                .execute(conn)
+                .map_res("Error removing secrets from project")
+        }}
+    }
+}
@@ -3,7 +3,7 @@ use serde_json::Value;
 
 use crate::{config::PathType, util::LowerCase, CONFIG};
 
-use super::{OrganizationId, User, UserId};
+use super::{OrganizationId, SendAccess, User, UserId};
 use id::SendId;
 
 db_object! {
@@ -37,6 +37,8 @@ db_object! {
 
         pub disabled: bool,
         pub hide_email: Option<bool>,
+        // Set once the expiration webhook was sent, see `SEND_WEBHOOK_URL`
+        pub expiration_notified: bool,
     }
 }
 
@@ -75,6 +77,7 @@ impl Send {
 
             disabled: false,
             hide_email: None,
+            expiration_notified: false,
         }
     }
 
@@ -155,6 +158,34 @@ impl Send {
         })
     }
 
+    /// The Send of an organization as its admins see it, without the key and the content.
+    pub async fn to_json_organization(&self, conn: &mut DbConn) -> Value {
+        use crate::util::format_date;
+
+        let creator = match &self.user_uuid {
+            Some(user_uuid) => User::find_by_uuid(user_uuid, conn).await,
+            None => None,
+        };
+
+        json!({
+            "id": self.uuid,
+            "organizationId": self.organization_uuid,
+            "creatorId": self.user_uuid,
+            "creatorEmail": creator.map(|user| user.email),
+            "type": self.atype,
+            "maxAccessCount": self.max_access_count,
+            "accessCount": self.access_count,
+            "hasPassword": self.password_hash.is_some(),
+            "disabled": self.disabled,
+            "hideEmail": self.hide_email,
+            "creationDate": format_date(&self.creation_date),
+            "revisionDate": format_date(&self.revision_date),
+            "expirationDate": self.expiration_date.as_ref().map(format_date),
+            "deletionDate": format_date(&self.deletion_date),
+            "object": "organizationSend",
+        })
+    }
+
     pub async fn to_json_access(&self, conn: &mut DbConn) -> Value {
         use crate::util::format_date;
 
@@ -230,6 +261,8 @@ impl Send {
             operator.remove_all(&self.uuid).await.ok();
         }
 
+        SendAccess::delete_all_by_send(&self.uuid, conn).await?;
+
         db_run! { conn: {
             diesel::delete(sends::table.filter(sends::uuid.eq(&self.uuid)))
                 .execute(conn)
@@ -265,6 +298,13 @@ impl Send {
         Ok(())
     }
 
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        for send in Self::find_by_org(org_uuid, conn).await {
+            send.delete(conn).await?;
+        }
+        Ok(())
+    }
+
     pub async fn find_by_access_id(access_id: &str, conn: &mut DbConn) -> Option<Self> {
         use data_encoding::BASE64URL_NOPAD;
         use uuid::Uuid;
@@ -302,6 +342,17 @@ impl Send {
         }}
     }
 
+    pub async fn find_by_uuid_and_org(uuid: &SendId, org_uuid: &OrganizationId, conn: &mut DbConn) -> Option<Self> {
+        db_run! {conn: {
+            sends::table
+                .filter(sends::uuid.eq(uuid))
+                .filter(sends::organization_uuid.eq(org_uuid))
+                .first::<SendDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
     pub async fn find_by_user(user_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
         db_run! {conn: {
             sends::table
@@ -311,8 +362,15 @@ impl Send {
     }
 
     pub async fn size_by_user(user_uuid: &UserId, conn: &mut DbConn) -> Option<i64> {
-        let sends = Self::find_by_user(user_uuid, conn).await;
+        Self::total_file_size(Self::find_by_user(user_uuid, conn).await)
+    }
+
+    pub async fn size_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Option<i64> {
+        Self::total_file_size(Self::find_by_org(org_uuid, conn).await)
+    }
 
+    // The sum of the sizes of the file sends, `None` on overflow
+    fn total_file_size(sends: Vec<Self>) -> Option<i64> {
         #[derive(serde::Deserialize)]
         struct FileData {
             #[serde(rename = "size", alias = "Size")]
@@ -341,6 +399,17 @@ impl Send {
         }}
     }
 
+    /// Sends past their expiration date for which no expiration webhook was sent yet.
+    pub async fn find_expired_unnotified(conn: &mut DbConn) -> Vec<Self> {
+        let now = Utc::now().naive_utc();
+        db_run! {conn: {
+            sends::table
+                .filter(sends::expiration_date.lt(now))
+                .filter(sends::expiration_notified.eq(false))
+                .load::<SendDb>(conn).expect("Error loading sends").from_db()
+        }}
+    }
+
     pub async fn find_by_past_deletion_date(conn: &mut DbConn) -> Vec<Self> {
         let now = Utc::now().naive_utc();
         db_run! {conn: {
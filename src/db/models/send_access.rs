@@ -0,0 +1,163 @@
+use std::net::IpAddr;
+
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{SendId, UserId};
+use crate::{
+    api::EmptyResult,
+    auth,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+};
+use macros::UuidFromParam;
+
+db_object! {
+    #[derive(Identifiable, Queryable, Insertable)]
+    #[diesel(table_name = send_accesses)]
+    #[diesel(primary_key(uuid))]
+    pub struct SendAccess {
+        pub uuid: SendAccessId,
+        pub send_uuid: SendId,
+        // Keyed with a server secret and the send id, the same visitor is recognized per send but not across sends
+        pub ip_hash: String,
+        // False when a wrong or no password was provided
+        pub success: bool,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct SendAccessId(String);
+
+/// Local methods
+impl SendAccess {
+    /// Number of access events returned per send.
+    pub const PAGE_SIZE: i64 = 100;
+
+    pub fn new(send_uuid: SendId, ip: &IpAddr, success: bool) -> Self {
+        Self {
+            uuid: SendAccessId::from(get_uuid()),
+            ip_hash: auth::send_visitor_hash(&send_uuid, ip),
+            send_uuid,
+            success,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "ipHash": self.ip_hash,
+            "success": self.success,
+            "date": format_date(&self.created_at),
+            "object": "sendAccess",
+        })
+    }
+}
+
+/// Database methods
+impl SendAccess {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::insert_into(send_accesses::table)
+                .values(SendAccessDb::to_db(self))
+                .execute(conn)
+                .map_res("Error saving send access")
+        }}
+    }
+
+    pub async fn find_by_send(send_uuid: &SendId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            send_accesses::table
+                .filter(send_accesses::send_uuid.eq(send_uuid))
+                .order_by(send_accesses::created_at.desc())
+                .limit(Self::PAGE_SIZE)
+                .load::<SendAccessDb>(conn)
+                .expect("Error loading send accesses")
+                .from_db()
+        }}
+    }
+
+    pub async fn count_by_send(send_uuid: &SendId, success: bool, conn: &mut DbConn) -> i64 {
+        db_run! { conn: {
+            send_accesses::table
+                .filter(send_accesses::send_uuid.eq(send_uuid))
+                .filter(send_accesses::success.eq(success))
+                .count()
+                .first::<i64>(conn)
+                .ok()
+                .unwrap_or(0)
+        }}
+    }
+
+    /// The successful and failed accesses of a send and the first successful access, in one query.
+    pub async fn summary_by_send(send_uuid: &SendId, conn: &mut DbConn) -> (i64, i64, Option<NaiveDateTime>) {
+        db_run! { conn: {
+            send_accesses::table
+                .filter(send_accesses::send_uuid.eq(send_uuid))
+                .group_by(send_accesses::success)
+                .select((
+                    send_accesses::success,
+                    diesel::dsl::count_star(),
+                    diesel::dsl::min(send_accesses::created_at),
+                ))
+                .load::<(bool, i64, Option<NaiveDateTime>)>(conn)
+                .unwrap_or_default()
+                .into_iter()
+                .fold((0, 0, None), |(successes, failures, first), (success, count, first_at)| {
+                    if success {
+                        (count, failures, first_at)
+                    } else {
+                        (successes, count, first)
+                    }
+                })
+        }}
+    }
+
+    /// The number of retrieved sends and the successful and failed accesses of all the sends of a user.
+    pub async fn counts_by_user(user_uuid: &UserId, conn: &mut DbConn) -> (usize, i64, i64) {
+        db_run! { conn: {
+            send_accesses::table
+                .inner_join(sends::table.on(sends::uuid.eq(send_accesses::send_uuid)))
+                .filter(sends::user_uuid.eq(user_uuid))
+                .group_by((send_accesses::send_uuid, send_accesses::success))
+                .select((send_accesses::success, diesel::dsl::count_star()))
+                .load::<(bool, i64)>(conn)
+                .unwrap_or_default()
+                .into_iter()
+                .fold((0, 0, 0), |(retrieved, successes, failures), (success, count)| {
+                    if success {
+                        (retrieved + 1, successes + count, failures)
+                    } else {
+                        (retrieved, successes, failures + count)
+                    }
+                })
+        }}
+    }
+
+    pub async fn delete_all_by_send(send_uuid: &SendId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(send_accesses::table.filter(send_accesses::send_uuid.eq(send_uuid)))
+                .execute(conn)
+                .map_res("Error deleting send accesses")
+        }}
+    }
+}
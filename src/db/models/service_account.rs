@@ -0,0 +1,370 @@
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+
+use super::{OrganizationId, ProjectId};
+use crate::{
+    api::EmptyResult,
+    crypto,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+};
+use macros::UuidFromParam;
+
+db_object! {
+    // Secrets Manager machine accounts, used by the `bws` CLI and the SDK to read secrets
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = service_accounts)]
+    #[diesel(primary_key(uuid))]
+    pub struct ServiceAccount {
+        pub uuid: ServiceAccountId,
+        pub org_uuid: OrganizationId,
+        pub name: String, // Encrypted with the organization key
+        pub creation_date: NaiveDateTime,
+        pub revision_date: NaiveDateTime,
+    }
+
+    #[derive(Identifiable, Queryable, Insertable)]
+    #[diesel(table_name = projects_service_accounts)]
+    #[diesel(primary_key(project_uuid, service_account_uuid))]
+    pub struct ServiceAccountProject {
+        pub project_uuid: ProjectId,
+        pub service_account_uuid: ServiceAccountId,
+        pub can_read: bool,
+        pub can_write: bool,
+    }
+
+    #[derive(Identifiable, Queryable, Insertable)]
+    #[diesel(table_name = service_account_tokens)]
+    #[diesel(primary_key(uuid))]
+    pub struct ServiceAccountToken {
+        pub uuid: ServiceAccountTokenId,
+        pub service_account_uuid: ServiceAccountId,
+        pub name: String,
+        // Only a hash of the client secret is stored, the secret itself is shown once on creation
+        pub client_secret_hash: String,
+        // The organization key, encrypted with a key which is only part of the access token given to the user
+        pub encrypted_payload: String,
+        pub akey: String,
+        pub expire_at: Option<NaiveDateTime>,
+        pub creation_date: NaiveDateTime,
+        pub revision_date: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct ServiceAccountId(String);
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct ServiceAccountTokenId(String);
+
+/// Local methods
+impl ServiceAccount {
+    pub fn new(org_uuid: OrganizationId, name: String) -> Self {
+        let now = Utc::now().naive_utc();
+
+        Self {
+            uuid: ServiceAccountId::from(get_uuid()),
+            org_uuid,
+            name,
+            creation_date: now,
+            revision_date: now,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.uuid,
+            "organizationId": self.org_uuid,
+            "name": self.name,
+            "creationDate": format_date(&self.creation_date),
+            "revisionDate": format_date(&self.revision_date),
+            "object": "serviceAccount",
+        })
+    }
+}
+
+impl ServiceAccountProject {
+    pub fn to_json(&self, service_account: &ServiceAccount) -> Value {
+        json!({
+            "id": format!("{}.{}", self.project_uuid, self.service_account_uuid),
+            "grantedProjectId": self.project_uuid,
+            "serviceAccountId": self.service_account_uuid,
+            "serviceAccountName": service_account.name,
+            "read": self.can_read,
+            "write": self.can_write,
+            "object": "serviceAccountProjectAccessPolicy",
+        })
+    }
+}
+
+impl ServiceAccountToken {
+    /// Returns the new token together with its client secret, which is not stored.
+    pub fn new(
+        service_account_uuid: ServiceAccountId,
+        name: String,
+        encrypted_payload: String,
+        akey: String,
+        expire_at: Option<NaiveDateTime>,
+    ) -> (Self, String) {
+        let now = Utc::now().naive_utc();
+        let client_secret = crypto::generate_api_key();
+
+        let token = Self {
+            uuid: ServiceAccountTokenId::from(get_uuid()),
+            service_account_uuid,
+            name,
+            client_secret_hash: crypto::sha256_hex(&client_secret),
+            encrypted_payload,
+            akey,
+            expire_at,
+            creation_date: now,
+            revision_date: now,
+        };
+        (token, client_secret)
+    }
+
+    pub fn check_valid_client_secret(&self, client_secret: &str) -> bool {
+        crypto::ct_eq(&self.client_secret_hash, crypto::sha256_hex(client_secret))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expire_at.is_some_and(|expire_at| expire_at < Utc::now().naive_utc())
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.uuid,
+            "name": self.name,
+            "scopes": ["api.secrets"],
+            "expireAt": self.expire_at.as_ref().map(format_date),
+            "creationDate": format_date(&self.creation_date),
+            "revisionDate": format_date(&self.revision_date),
+            "object": "accessToken",
+        })
+    }
+}
+
+/// Database methods
+impl ServiceAccount {
+    pub async fn save(&mut self, conn: &mut DbConn) -> EmptyResult {
+        self.revision_date = Utc::now().naive_utc();
+
+        db_run! { conn:
+            sqlite, mysql {
+                match diesel::replace_into(service_accounts::table)
+                    .values(ServiceAccountDb::to_db(self))
+                    .execute(conn)
+                {
+                    Ok(_) => Ok(()),
+                    // Record already exists and causes a Foreign Key Violation because replace_into() wants to delete the record first.
+                    Err(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::ForeignKeyViolation, _)) => {
+                        diesel::update(service_accounts::table)
+                            .filter(service_accounts::uuid.eq(&self.uuid))
+                            .set(ServiceAccountDb::to_db(self))
+                            .execute(conn)
+                            .map_res("Error saving service account")
+                    }
+                    Err(e) => Err(e.into()),
+                }.map_res("Error saving service account")
+            }
+            postgresql {
+                let value = ServiceAccountDb::to_db(self);
+                diesel::insert_into(service_accounts::table)
+                    .values(&value)
+                    .on_conflict(service_accounts::uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving service account")
+            }
+        }
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        ServiceAccountToken::delete_all_by_service_account(&self.uuid, conn).await?;
+        ServiceAccountProject::delete_all_by_service_account(&self.uuid, conn).await?;
+
+        db_run! { conn: {
+            diesel::delete(service_accounts::table.filter(service_accounts::uuid.eq(self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting service account")
+        }}
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        for service_account in Self::find_by_org(org_uuid, conn).await {
+            service_account.delete(conn).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn find_by_uuid(uuid: &ServiceAccountId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            service_accounts::table
+                .filter(service_accounts::uuid.eq(uuid))
+                .first::<ServiceAccountDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_org(org_uuid: &OrganizationId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            service_accounts::table
+                .filter(service_accounts::org_uuid.eq(org_uuid))
+                .order_by(service_accounts::creation_date)
+                .load::<ServiceAccountDb>(conn)
+                .expect("Error loading service accounts")
+                .from_db()
+        }}
+    }
+}
+
+impl ServiceAccountProject {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::insert_into(projects_service_accounts::table)
+                .values(ServiceAccountProjectDb::to_db(self))
+                .execute(conn)
+                .map_res("Error granting project access")
+        }}
+    }
+
+    pub async fn find_by_project(project_uuid: &ProjectId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            projects_service_accounts::table
+                .filter(projects_service_accounts::project_uuid.eq(project_uuid))
+                .load::<ServiceAccountProjectDb>(conn)
+                .expect("Error loading project access")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_service_account(service_account_uuid: &ServiceAccountId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            projects_service_accounts::table
+                .filter(projects_service_accounts::service_account_uuid.eq(service_account_uuid))
+                .load::<ServiceAccountProjectDb>(conn)
+                .expect("Error loading project access")
+                .from_db()
+        }}
+    }
+
+    pub async fn delete_all_by_project(project_uuid: &ProjectId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(projects_service_accounts::table.filter(projects_service_accounts::project_uuid.eq(project_uuid)))
+                .execute(conn)
+                .map_res("Error removing project access")
+        }}
+    }
+
+    pub async fn delete_all_by_service_account(
+        service_account_uuid: &ServiceAccountId,
+        conn: &mut DbConn,
+    ) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(
+                projects_service_accounts::table
+                    .filter(projects_service_accounts::service_account_uuid.eq(service_account_uuid)),
+            )
+            .execute(conn)
+            .map_res("Error removing project access")
+        }}
+    }
+}
+
+impl ServiceAccountToken {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::insert_into(service_account_tokens::table)
+                .values(ServiceAccountTokenDb::to_db(self))
+                .execute(conn)
+                .map_res("Error saving access token")
+        }}
+    }
+
+    pub async fn find_by_uuid(uuid: &ServiceAccountTokenId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            service_account_tokens::table
+                .filter(service_account_tokens::uuid.eq(uuid))
+                .first::<ServiceAccountTokenDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_service_account(service_account_uuid: &ServiceAccountId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            service_account_tokens::table
+                .filter(service_account_tokens::service_account_uuid.eq(service_account_uuid))
+                .order_by(service_account_tokens::creation_date)
+                .load::<ServiceAccountTokenDb>(conn)
+                .expect("Error loading access tokens")
+                .from_db()
+        }}
+    }
+
+    pub async fn delete_by_uuid_and_service_account(
+        uuid: &ServiceAccountTokenId,
+        service_account_uuid: &ServiceAccountId,
+        conn: &mut DbConn,
+    ) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(
+                service_account_tokens::table
+                    .filter(service_account_tokens::uuid.eq(uuid))
+                    .filter(service_account_tokens::service_account_uuid.eq(service_account_uuid)),
+            )
+            .execute(conn)
+            .map_res("Error revoking access token")
+        }}
+    }
+
+    pub async fn delete_all_by_service_account(
+        service_account_uuid: &ServiceAccountId,
+        conn: &mut DbConn,
+    ) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(
+                service_account_tokens::table
+                    .filter(service_account_tokens::service_account_uuid.eq(service_account_uuid)),
+            )
+            .execute(conn)
+            .map_res("Error revoking access tokens")
+        }}
+    }
+}
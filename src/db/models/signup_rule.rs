@@ -0,0 +1,221 @@
+use chrono::{NaiveDateTime, Utc};
+use derive_more::{AsRef, Deref, Display, From};
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{CollectionId, OrganizationId};
+use crate::{
+    api::EmptyResult,
+    db::DbConn,
+    error::MapResult,
+    util::{format_date, get_uuid},
+    CONFIG,
+};
+use macros::UuidFromParam;
+
+db_object! {
+    // Rules deciding which email domains can register, evaluated by `position` where the first matching rule wins.
+    // Allowing rules can add the new users to an organization, and optionally one of its collections.
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = signup_rules)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(uuid))]
+    pub struct SignupRule {
+        pub uuid: SignupRuleId,
+        // `*` matches all domains, `*.example.com` the subdomains of example.com, anything else has to match exactly
+        pub pattern: String,
+        pub action: i32,
+        pub position: i32,
+        pub org_uuid: Option<OrganizationId>,
+        pub collection_uuid: Option<CollectionId>,
+        // Users joining the organization are created as accepted, instead of having to accept an invitation
+        pub bypass_invitation: bool,
+        pub created_at: NaiveDateTime,
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    AsRef,
+    Deref,
+    DieselNewType,
+    Display,
+    From,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    UuidFromParam,
+)]
+#[deref(forward)]
+#[from(forward)]
+pub struct SignupRuleId(String);
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum SignupRuleAction {
+    Allow = 0,
+    Deny = 1,
+}
+
+impl SignupRuleAction {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "0" | "allow" => Some(SignupRuleAction::Allow),
+            "1" | "deny" => Some(SignupRuleAction::Deny),
+            _ => None,
+        }
+    }
+}
+
+// Whether an allowing rule exists, used to decide if the registration link is shown. Refreshed when the rules change.
+static ALLOWS_SIGNUPS: AtomicBool = AtomicBool::new(false);
+
+/// Local methods
+impl SignupRule {
+    pub fn new(pattern: String, action: SignupRuleAction, position: i32) -> Self {
+        Self {
+            uuid: SignupRuleId::from(get_uuid()),
+            pattern,
+            action: action as i32,
+            position,
+            org_uuid: None,
+            collection_uuid: None,
+            bypass_invitation: false,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    /// Checks if the given value is a pattern which can be used by a rule.
+    pub fn is_valid_pattern(pattern: &str) -> bool {
+        if pattern == "*" {
+            return true;
+        }
+        let domain = pattern.strip_prefix("*.").unwrap_or(pattern);
+        !domain.is_empty()
+            && domain.len() <= 255
+            && !domain.starts_with('.')
+            && !domain.ends_with('.')
+            && domain.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    }
+
+    pub fn matches(&self, domain: &str) -> bool {
+        if self.pattern == "*" {
+            return true;
+        }
+        match self.pattern.strip_prefix("*.") {
+            Some(parent) => domain.strip_suffix(parent).is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => domain == self.pattern,
+        }
+    }
+
+    pub fn is_allowing(&self) -> bool {
+        self.action == SignupRuleAction::Allow as i32
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.uuid,
+            "pattern": self.pattern,
+            "action": if self.is_allowing() { "allow" } else { "deny" },
+            "position": self.position,
+            "organizationId": self.org_uuid,
+            "collectionId": self.collection_uuid,
+            "bypassInvitation": self.bypass_invitation,
+            "creationDate": format_date(&self.created_at),
+            "object": "signupRule",
+        })
+    }
+
+    pub fn any_allows_signups() -> bool {
+        ALLOWS_SIGNUPS.load(Ordering::Relaxed)
+    }
+}
+
+/// Database methods
+impl SignupRule {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        let result: EmptyResult = db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(signup_rules::table)
+                    .values(SignupRuleDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving signup rule")
+            }
+            postgresql {
+                let value = SignupRuleDb::to_db(self);
+                diesel::insert_into(signup_rules::table)
+                    .values(&value)
+                    .on_conflict(signup_rules::uuid)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving signup rule")
+            }
+        };
+        Self::refresh_allows_signups(conn).await;
+        result
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        let result: EmptyResult = db_run! { conn: {
+            diesel::delete(signup_rules::table.filter(signup_rules::uuid.eq(self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting signup rule")
+        }};
+        Self::refresh_allows_signups(conn).await;
+        result
+    }
+
+    pub async fn delete_all_by_organization(org_uuid: &OrganizationId, conn: &mut DbConn) -> EmptyResult {
+        let result: EmptyResult = db_run! { conn: {
+            diesel::delete(signup_rules::table.filter(signup_rules::org_uuid.eq(org_uuid)))
+                .execute(conn)
+                .map_res("Error deleting signup rules")
+        }};
+        Self::refresh_allows_signups(conn).await;
+        result
+    }
+
+    pub async fn find_by_uuid(uuid: &SignupRuleId, conn: &mut DbConn) -> Option<Self> {
+        db_run! { conn: {
+            signup_rules::table
+                .filter(signup_rules::uuid.eq(uuid))
+                .first::<SignupRuleDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn get_all(conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            signup_rules::table
+                .order_by((signup_rules::position, signup_rules::created_at))
+                .load::<SignupRuleDb>(conn)
+                .expect("Error loading signup rules")
+                .from_db()
+        }}
+    }
+
+    /// Returns the first rule matching the domain of the email address.
+    pub async fn find_matching(email: &str, conn: &mut DbConn) -> Option<Self> {
+        let (_, domain) = email.rsplit_once('@')?;
+        let domain = domain.to_lowercase();
+        Self::get_all(conn).await.into_iter().find(|rule| rule.matches(&domain))
+    }
+
+    /// Tests whether signup is allowed for an email address. Without a matching rule,
+    /// this falls back to the `signups_allowed` and `signups_domains_whitelist` settings.
+    pub async fn is_signup_allowed(email: &str, conn: &mut DbConn) -> bool {
+        match Self::find_matching(email, conn).await {
+            Some(rule) => rule.is_allowing(),
+            None => CONFIG.is_signup_allowed(email),
+        }
+    }
+
+    pub async fn refresh_allows_signups(conn: &mut DbConn) {
+        let allows_signups = Self::get_all(conn).await.iter().any(Self::is_allowing);
+        ALLOWS_SIGNUPS.store(allows_signups, Ordering::Relaxed);
+    }
+}
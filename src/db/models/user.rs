@@ -3,7 +3,9 @@ use derive_more::{AsRef, Deref, Display, From};
 use serde_json::Value;
 
 use super::{
-    Cipher, Device, EmergencyAccess, Favorite, Folder, Membership, MembershipType, TwoFactor, TwoFactorIncomplete,
+    Attachment, Change, ChangeAction, ChangeEntity, Cipher, Device, EmergencyAccess, Favorite, Folder, LoginEvent,
+    Membership, MembershipType, OrgPolicy, OrgPolicyType, Provider, ProviderOrganization, ProviderUser, Send,
+    TwoFactor, TwoFactorIncomplete, VerifiedEmailPolicyData,
 };
 use crate::{
     api::EmptyResult,
@@ -48,6 +50,9 @@ db_object! {
         #[diesel(column_name = "totp_secret")] // Note, this is only added to the UserDb structs, not to User
         _totp_secret: Option<String>,
         pub totp_recover: Option<String>,
+        // When the current recovery code was last shown to the user, and when a recovery code was last used
+        pub totp_recover_viewed_at: Option<NaiveDateTime>,
+        pub totp_recover_used_at: Option<NaiveDateTime>,
 
         pub security_stamp: String,
         pub stamp_exception: Option<String>,
@@ -65,6 +70,32 @@ db_object! {
         pub avatar_color: Option<String>,
 
         pub external_id: Option<String>, // Todo: Needs to be removed in the future, this is not used anymore.
+
+        // Storage limits (KB) set by the admin, these take precedence over the global limits
+        pub attachment_limit: Option<i64>,
+        pub send_limit: Option<i64>,
+
+        pub deleted_at: Option<NaiveDateTime>,
+        // Set when the user was asked to update their KDF settings by the KDF migration campaign
+        pub kdf_campaign_at: Option<NaiveDateTime>,
+        // Set through the custom API, the user has to change their KDF settings before tokens can be refreshed again
+        pub kdf_upgrade_required: bool,
+        // The user deleted their own account, logging in during the grace period restores it
+        pub self_deleted: bool,
+        // The user has no master password, their master key is stored by the Key Connector
+        pub uses_key_connector: bool,
+        // Trash retention set by the user, this takes precedence over `TRASH_AUTO_DELETE_DAYS`
+        pub trash_retention_days: Option<i32>,
+        // Set through the custom API, the user has to choose a new master password before tokens can be refreshed again
+        pub force_password_reset: bool,
+        // Whether a login from a new device is reported by email, changeable through the custom API
+        pub new_device_email: bool,
+        // Set when the mail provider reported that mails to the address can't be delivered, see `api::mail_webhooks`
+        pub email_undeliverable_at: Option<NaiveDateTime>,
+        pub email_undeliverable_reason: Option<String>,
+        // The master password was set while invited to an organization with a master password policy,
+        // the clients check it against the policy on the first login once the membership is confirmed
+        pub password_policy_pending: bool,
     }
 
     #[derive(Identifiable, Queryable, Insertable)]
@@ -137,6 +168,8 @@ impl User {
 
             _totp_secret: None,
             totp_recover: None,
+            totp_recover_viewed_at: None,
+            totp_recover_used_at: None,
 
             equivalent_domains: "[]".to_string(),
             excluded_globals: "[]".to_string(),
@@ -151,7 +184,63 @@ impl User {
             avatar_color: None,
 
             external_id: None, // Todo: Needs to be removed in the future, this is not used anymore.
+
+            attachment_limit: None,
+            send_limit: None,
+
+            deleted_at: None,
+            kdf_campaign_at: None,
+            kdf_upgrade_required: false,
+            self_deleted: false,
+            uses_key_connector: false,
+            trash_retention_days: None,
+            force_password_reset: false,
+            new_device_email: true,
+            email_undeliverable_at: None,
+            email_undeliverable_reason: None,
+            password_policy_pending: false,
+        }
+    }
+
+    pub fn is_soft_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Records why mails to the address can't be delivered, only the first report is kept until it is cleared.
+    pub fn mark_email_undeliverable(&mut self, reason: &str) {
+        if self.email_undeliverable_at.is_none() {
+            self.email_undeliverable_at = Some(Utc::now().naive_utc());
+            self.email_undeliverable_reason = Some(reason.chars().take(500).collect());
+        }
+    }
+
+    /// Mails are sent to the address again, after it was corrected or the mailbox was fixed.
+    pub fn clear_email_undeliverable(&mut self) {
+        self.email_undeliverable_at = None;
+        self.email_undeliverable_reason = None;
+    }
+
+    /// Checks the client KDF settings against `KDF_MIN_PBKDF2_ITERATIONS` and `KDF_REQUIRE_ARGON2ID`.
+    pub fn is_kdf_below_minimum(&self) -> bool {
+        if self.client_kdf_type == UserKdfType::Argon2id as i32 {
+            return false;
         }
+        CONFIG.kdf_require_argon2id() || self.client_kdf_iter < CONFIG.kdf_min_pbkdf2_iterations()
+    }
+
+    /// Returns the attachment storage limit (KB) for this user, falling back to `USER_ATTACHMENT_LIMIT`
+    pub fn effective_attachment_limit(&self) -> Option<i64> {
+        self.attachment_limit.or_else(|| CONFIG.user_attachment_limit())
+    }
+
+    /// Returns the send storage limit (KB) for this user, falling back to `USER_SEND_LIMIT`
+    pub fn effective_send_limit(&self) -> Option<i64> {
+        self.send_limit.or_else(|| CONFIG.user_send_limit())
+    }
+
+    /// Returns the number of days trashed personal items are kept, falling back to `TRASH_AUTO_DELETE_DAYS`
+    pub fn effective_trash_retention_days(&self) -> Option<i64> {
+        self.trash_retention_days.map(i64::from).or_else(|| CONFIG.trash_auto_delete_days())
     }
 
     pub fn check_valid_password(&self, password: &str) -> bool {
@@ -171,10 +260,47 @@ impl User {
         }
     }
 
+    /// When the account is blocked if its email address is still unverified, with `EMAIL_VERIFY_ENFORCE` or the
+    /// Verified email policy of one of its organizations. The shortest grace period applies.
+    pub async fn email_verify_deadline(&self, conn: &mut DbConn) -> Option<NaiveDateTime> {
+        if self.verified_at.is_some() || !CONFIG.mail_enabled() {
+            return None;
+        }
+
+        let default_days = i64::from(CONFIG.email_verify_grace_days());
+        let mut grace_days = CONFIG.email_verify_enforce().then_some(default_days);
+        let policies = OrgPolicy::find_accepted_and_confirmed_by_user_and_active_policy(
+            &self.uuid,
+            OrgPolicyType::VerifiedEmail,
+            conn,
+        )
+        .await;
+        for policy in policies {
+            let policy_data = serde_json::from_str::<VerifiedEmailPolicyData>(&policy.data).ok();
+            let days = policy_data.and_then(|p| p.grace_days).unwrap_or(default_days);
+            grace_days = Some(grace_days.map_or(days, |d| d.min(days)));
+        }
+
+        grace_days.and_then(TimeDelta::try_days).map(|grace| self.created_at + grace)
+    }
+
+    /// Replaces the recovery code, the new one still has to be viewed by the user.
+    pub fn new_recovery_code(&mut self) {
+        self.totp_recover = Some(crypto::encode_random_bytes::<20>(data_encoding::BASE32));
+        self.totp_recover_viewed_at = None;
+    }
+
     pub fn check_valid_api_key(&self, key: &str) -> bool {
         matches!(self.api_key, Some(ref api_key) if crypto::ct_eq(api_key, key))
     }
 
+    /// The fingerprint phrase of the public key of the user, which they can compare with the one in their account
+    /// settings. `None` when the user didn't log in yet, so they have no keys.
+    pub fn fingerprint_phrase(&self) -> Option<String> {
+        let public_key = data_encoding::BASE64.decode(self.public_key.as_ref()?.as_bytes()).ok()?;
+        Some(crypto::fingerprint_phrase(&self.uuid, &public_key))
+    }
+
     /// Set the password hash generated
     /// And resets the security_stamp. Based upon the allow_next_route the security_stamp will be different.
     ///
@@ -242,10 +368,35 @@ impl User {
             orgs_json.push(c.to_json(conn).await);
         }
 
+        // The client organizations of the providers are administered without being a member of them
+        let mut providers_json = Vec::new();
+        let mut provider_orgs_json = Vec::new();
+        for provider_user in ProviderUser::find_confirmed_by_user(&self.uuid, conn).await {
+            let Some(provider) = Provider::find_by_uuid(&provider_user.provider_uuid, conn).await else {
+                continue;
+            };
+            providers_json.push(provider_user.to_json_profile(&provider));
+            if !provider.enabled {
+                continue;
+            }
+            for provider_org in ProviderOrganization::find_by_provider(&provider.uuid, conn).await {
+                provider_orgs_json.push(provider_org.to_json_profile(&provider, &provider_user, conn).await);
+            }
+        }
+
         let twofactor_enabled = !TwoFactor::find_by_user(&self.uuid, conn).await.is_empty();
 
+        // Storage limits and usage in bytes, so clients can show the remaining space
+        let attachment_limit = self.effective_attachment_limit();
+        let storage = json!({
+            "attachmentLimit": attachment_limit.and_then(|kb| kb.checked_mul(1024)),
+            "attachmentUsed": Attachment::size_by_user(&self.uuid, conn).await,
+            "sendLimit": self.effective_send_limit().and_then(|kb| kb.checked_mul(1024)),
+            "sendUsed": Send::size_by_user(&self.uuid, conn).await.unwrap_or_default(),
+        });
+
         // TODO: Might want to save the status field in the DB
-        let status = if self.password_hash.is_empty() {
+        let status = if self.password_hash.is_empty() && !self.uses_key_connector {
             UserStatus::Invited
         } else {
             UserStatus::Enabled
@@ -265,12 +416,16 @@ impl User {
             "privateKey": self.private_key,
             "securityStamp": self.security_stamp,
             "organizations": orgs_json,
-            "providers": [],
-            "providerOrganizations": [],
-            "forcePasswordReset": false,
+            "providers": providers_json,
+            "providerOrganizations": provider_orgs_json,
+            "forcePasswordReset": self.force_password_reset,
             "avatarColor": self.avatar_color,
-            "usesKeyConnector": false,
+            "usesKeyConnector": self.uses_key_connector,
+            "trashRetentionDays": self.effective_trash_retention_days(), // Vaultwarden specific
             "creationDate": format_date(&self.created_at),
+            "maxStorageGb": Attachment::max_storage_gb(attachment_limit),
+            "storage": storage,
+            "kdfMigrationRequired": self.kdf_upgrade_required || (self.kdf_campaign_at.is_some() && self.is_kdf_below_minimum()),
             "object": "profile",
         })
     }
@@ -281,6 +436,7 @@ impl User {
         }
 
         self.updated_at = Utc::now().naive_utc();
+        Change::record(ChangeEntity::User, &self.uuid, ChangeAction::Upsert, conn).await;
 
         db_run! {conn:
             sqlite, mysql {
@@ -313,7 +469,23 @@ impl User {
         }
     }
 
-    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+    /// Removes the master password hint, see `PASSWORD_HINTS_ALLOWED` and the DisablePasswordHints policy.
+    pub async fn clear_password_hint(&mut self, conn: &mut DbConn) -> EmptyResult {
+        if self.password_hint.is_none() {
+            return Ok(());
+        }
+        self.password_hint = None;
+        self.save(conn).await
+    }
+
+    /// Called when the master password is set, the clients only check it against the policies of confirmed memberships.
+    pub async fn update_password_policy_pending(&mut self, conn: &mut DbConn) {
+        let policy_type = OrgPolicyType::MasterPassword;
+        self.password_policy_pending =
+            !OrgPolicy::find_unconfirmed_by_user_and_active_policy(&self.uuid, policy_type, conn).await.is_empty();
+    }
+
+    pub async fn check_not_last_owner(&self, conn: &mut DbConn) -> EmptyResult {
         for member in Membership::find_confirmed_by_user(&self.uuid, conn).await {
             if member.atype == MembershipType::Owner
                 && Membership::count_confirmed_by_org_and_type(&member.org_uuid, MembershipType::Owner, conn).await <= 1
@@ -321,10 +493,44 @@ impl User {
                 err!("Can't delete last owner")
             }
         }
+        Ok(())
+    }
+
+    /// Marks the user as deleted, the user can't login anymore until restored.
+    /// The actual data is purged by the `user_purge_schedule` job once the grace period has passed.
+    /// When the user deleted the account themself, logging in during the grace period restores it.
+    pub async fn soft_delete(&mut self, self_deleted: bool, conn: &mut DbConn) -> EmptyResult {
+        self.check_not_last_owner(conn).await?;
+
+        Device::delete_all_by_user(&self.uuid, conn).await?;
+        self.reset_security_stamp();
+        self.deleted_at = Some(Utc::now().naive_utc());
+        self.self_deleted = self_deleted;
+        self.save(conn).await
+    }
+
+    /// The date the soft-deleted user will be purged.
+    pub fn purge_date(&self) -> Option<NaiveDateTime> {
+        let grace_period = TimeDelta::try_days(CONFIG.user_deletion_grace_days()).unwrap_or_default();
+        self.deleted_at.map(|deleted_at| deleted_at + grace_period)
+    }
+
+    pub async fn restore(&mut self, conn: &mut DbConn) -> EmptyResult {
+        if !self.is_soft_deleted() {
+            err!("User is not deleted")
+        }
+        self.deleted_at = None;
+        self.self_deleted = false;
+        self.save(conn).await
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        self.check_not_last_owner(conn).await?;
 
         super::Send::delete_all_by_user(&self.uuid, conn).await?;
         EmergencyAccess::delete_all_by_user(&self.uuid, conn).await?;
         EmergencyAccess::delete_all_by_grantee_email(&self.email, conn).await?;
+        super::CipherShare::delete_all_by_user(&self.uuid, conn).await?;
         Membership::delete_all_by_user(&self.uuid, conn).await?;
         Cipher::delete_all_by_user(&self.uuid, conn).await?;
         Favorite::delete_all_by_user(&self.uuid, conn).await?;
@@ -332,7 +538,18 @@ impl User {
         Device::delete_all_by_user(&self.uuid, conn).await?;
         TwoFactor::delete_all_by_user(&self.uuid, conn).await?;
         TwoFactorIncomplete::delete_all_by_user(&self.uuid, conn).await?;
+        LoginEvent::delete_all_by_user(&self.uuid, conn).await?;
+        super::BreachFinding::delete_all_by_user(&self.uuid, conn).await?;
+        super::QuarantinedFile::delete_all_by_user(&self.uuid, conn).await?;
+        super::Report::delete_all_by_user(&self.uuid, conn).await?;
+        super::ReportMember::delete_all_by_user(&self.uuid, conn).await?;
+        super::KeyConnectorKey::delete_by_user(&self.uuid, conn).await?;
+        super::UserAlias::delete_all_by_user(&self.uuid, conn).await?;
+        super::PersonalApiToken::delete_all_by_user(&self.uuid, conn).await?;
+        super::PolicyViolation::delete_all_by_user(&self.uuid, conn).await?;
+        ProviderUser::delete_all_by_user(&self.uuid, conn).await?;
         Invitation::take(&self.email, conn).await; // Delete invitation if any
+        Change::record(ChangeEntity::User, &self.uuid, ChangeAction::Delete, conn).await;
 
         db_run! {conn: {
             diesel::delete(users::table.filter(users::uuid.eq(self.uuid)))
@@ -357,7 +574,15 @@ impl User {
                     .execute(conn)
             }, 10)
             .map_res("Error updating revision date for all users")
-        }}
+        }}?;
+
+        // The change stream only has changes of single users
+        if CONFIG.replication_token().is_some() {
+            for (user, _) in Self::get_all(conn).await {
+                Change::record(ChangeEntity::User, &user.uuid, ChangeAction::Upsert, conn).await;
+            }
+        }
+        Ok(())
     }
 
     pub async fn update_revision(&mut self, conn: &mut DbConn) -> EmptyResult {
@@ -366,7 +591,18 @@ impl User {
         Self::_update_revision(&self.uuid, &self.updated_at, conn).await
     }
 
+    pub async fn clear_password_policy_pending(uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! {conn: {
+            diesel::update(users::table.filter(users::uuid.eq(uuid)))
+                .set(users::password_policy_pending.eq(false))
+                .execute(conn)
+                .map_res("Error updating user")
+        }}
+    }
+
     async fn _update_revision(uuid: &UserId, date: &NaiveDateTime, conn: &mut DbConn) -> EmptyResult {
+        Change::record(ChangeEntity::User, uuid, ChangeAction::Upsert, conn).await;
+
         db_run! {conn: {
             retry(|| {
                 diesel::update(users::table.filter(users::uuid.eq(uuid)))
@@ -377,23 +613,75 @@ impl User {
         }}
     }
 
+    /// The verified aliases resolve to their account as well, see `UserAlias`.
     pub async fn find_by_mail(mail: &str, conn: &mut DbConn) -> Option<Self> {
         let lower_mail = mail.to_lowercase();
         db_run! {conn: {
             users::table
-                .filter(users::email.eq(lower_mail))
+                .filter(users::email.eq(&lower_mail).or(users::uuid.eq_any(
+                    user_aliases::table
+                        .filter(user_aliases::email.eq(&lower_mail))
+                        .filter(user_aliases::verification_token.is_null())
+                        .select(user_aliases::user_uuid),
+                )))
                 .first::<UserDb>(conn)
                 .ok()
                 .from_db()
         }}
     }
 
+    pub async fn find_by_email_domain(domain_name: &str, conn: &mut DbConn) -> Vec<Self> {
+        let pattern = format!("%@{}", domain_name.to_lowercase());
+        db_run! {conn: {
+            users::table
+                .filter(users::email.like(&pattern).or(users::uuid.eq_any(
+                    user_aliases::table
+                        .filter(user_aliases::email.like(&pattern))
+                        .filter(user_aliases::verification_token.is_null())
+                        .select(user_aliases::user_uuid),
+                )))
+                .load::<UserDb>(conn)
+                .expect("Error loading users")
+                .from_db()
+        }}
+    }
+
     pub async fn find_by_uuid(uuid: &UserId, conn: &mut DbConn) -> Option<Self> {
         db_run! {conn: {
             users::table.filter(users::uuid.eq(uuid)).first::<UserDb>(conn).ok().from_db()
         }}
     }
 
+    /// Returns the user as JSON for the replication stream. Only the listed columns are part of it, the credentials
+    /// like the password hash, the recovery code, the security stamp and the API key never leave the server.
+    pub async fn find_row_json(uuid: &UserId, conn: &mut DbConn) -> Option<Value> {
+        let user = Self::find_by_uuid(uuid, conn).await?;
+        Some(json!({
+            "uuid": user.uuid,
+            "enabled": user.enabled,
+            "created_at": format_date(&user.created_at),
+            "updated_at": format_date(&user.updated_at),
+            "verified_at": user.verified_at.as_ref().map(format_date),
+            "email": user.email,
+            "name": user.name,
+            "akey": user.akey,
+            "private_key": user.private_key,
+            "public_key": user.public_key,
+            "equivalent_domains": user.equivalent_domains,
+            "excluded_globals": user.excluded_globals,
+            "client_kdf_type": user.client_kdf_type,
+            "client_kdf_iter": user.client_kdf_iter,
+            "client_kdf_memory": user.client_kdf_memory,
+            "client_kdf_parallelism": user.client_kdf_parallelism,
+            "avatar_color": user.avatar_color,
+            "attachment_limit": user.attachment_limit,
+            "send_limit": user.send_limit,
+            "deleted_at": user.deleted_at.as_ref().map(format_date),
+            "uses_key_connector": user.uses_key_connector,
+            "trash_retention_days": user.trash_retention_days,
+        }))
+    }
+
     pub async fn find_by_device_id(device_uuid: &DeviceId, conn: &mut DbConn) -> Option<Self> {
         db_run! { conn: {
             users::table
@@ -419,6 +707,51 @@ impl User {
         }}
     }
 
+    /// Whether the address is the primary address of a user which is marked as undeliverable, aliases aren't.
+    pub async fn is_email_undeliverable(mail: &str, conn: &mut DbConn) -> bool {
+        let lower_mail = mail.to_lowercase();
+        db_run! {conn: {
+            users::table
+                .filter(users::email.eq(&lower_mail))
+                .filter(users::email_undeliverable_at.is_not_null())
+                .count()
+                .first::<i64>(conn)
+                .unwrap_or(0)
+                > 0
+        }}
+    }
+
+    pub async fn count_all(conn: &mut DbConn) -> i64 {
+        db_run! {conn: {
+            users::table
+                .filter(users::deleted_at.is_null())
+                .count()
+                .first::<i64>(conn)
+                .unwrap_or(0)
+        }}
+    }
+
+    pub async fn find_with_password_hint(conn: &mut DbConn) -> Vec<Self> {
+        db_run! {conn: {
+            users::table
+                .filter(users::password_hint.is_not_null())
+                .order_by(users::email)
+                .load::<UserDb>(conn)
+                .expect("Error loading users with a password hint")
+                .from_db()
+        }}
+    }
+
+    pub async fn find_soft_deleted_before(dt: &NaiveDateTime, conn: &mut DbConn) -> Vec<Self> {
+        db_run! {conn: {
+            users::table
+                .filter(users::deleted_at.lt(dt))
+                .load::<UserDb>(conn)
+                .expect("Error loading deleted users")
+                .from_db()
+        }}
+    }
+
     pub async fn last_active(&self, conn: &mut DbConn) -> Option<NaiveDateTime> {
         match Device::find_latest_active_by_user(&self.uuid, conn).await {
             Some(device) => Some(device.updated_at),
@@ -538,6 +871,7 @@ impl SsoUser {
         }}
     }
 
+    /// The verified aliases of the accounts match as well, so an account merged into another one keeps its SSO login.
     pub async fn find_by_mail(mail: &str, conn: &DbConn) -> Option<(User, Option<SsoUser>)> {
         let lower_mail = mail.to_lowercase();
 
@@ -545,7 +879,12 @@ impl SsoUser {
             users::table
                 .left_join(sso_users::table)
                 .select(<(UserDb, Option<SsoUserDb>)>::as_select())
-                .filter(users::email.eq(lower_mail))
+                .filter(users::email.eq(&lower_mail).or(users::uuid.eq_any(
+                    user_aliases::table
+                        .filter(user_aliases::email.eq(&lower_mail))
+                        .filter(user_aliases::verification_token.is_null())
+                        .select(user_aliases::user_uuid),
+                )))
                 .first::<(UserDb, Option<SsoUserDb>)>(conn)
                 .ok()
                 .map(|(user, sso_user)| { (user.from_db(), sso_user.from_db()) })
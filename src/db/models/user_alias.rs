@@ -0,0 +1,167 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use serde_json::Value;
+
+use super::{User, UserId};
+use crate::{api::EmptyResult, crypto, db::DbConn, error::MapResult, util::format_date, CONFIG};
+
+db_object! {
+    // Another email address of an account, e.g. the address of an account which was merged into it
+    #[derive(Identifiable, Queryable, Insertable, AsChangeset)]
+    #[diesel(table_name = user_aliases)]
+    #[diesel(treat_none_as_null = true)]
+    #[diesel(primary_key(email))]
+    pub struct UserAlias {
+        pub email: String,
+        pub user_uuid: UserId,
+        pub created_at: NaiveDateTime,
+        // Set until the address is verified, only verified aliases resolve to the account
+        pub verification_token: Option<String>,
+        pub verification_attempts: i32,
+    }
+}
+
+/// Local methods
+impl UserAlias {
+    /// A verified alias, for the addresses added by an admin or kept from a merged account.
+    pub fn new(email: &str, user_uuid: UserId) -> Self {
+        Self {
+            email: email.to_lowercase(),
+            user_uuid,
+            created_at: Utc::now().naive_utc(),
+            verification_token: None,
+            verification_attempts: 0,
+        }
+    }
+
+    /// An alias added by the user, it needs the code sent to the address before it is used.
+    pub fn new_unverified(email: &str, user_uuid: UserId) -> Self {
+        Self {
+            verification_token: Some(crypto::generate_email_token(6)),
+            ..Self::new(email, user_uuid)
+        }
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.verification_token.is_none()
+    }
+
+    /// The code is sent when the alias is added, so it expires like the email 2FA codes from that moment.
+    pub fn is_verification_expired(&self) -> bool {
+        let max_time = TimeDelta::try_seconds(CONFIG.email_expiration_time() as i64).unwrap();
+        self.created_at + max_time < Utc::now().naive_utc()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "email": self.email,
+            "userId": self.user_uuid,
+            "verified": self.is_verified(),
+            "creationDate": format_date(&self.created_at),
+            "object": "userAlias",
+        })
+    }
+}
+
+/// Database methods
+impl UserAlias {
+    pub async fn save(&self, conn: &mut DbConn) -> EmptyResult {
+        if !crate::util::is_valid_email(&self.email) {
+            err!(format!("Alias email {} is not a valid email address", self.email))
+        }
+
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(user_aliases::table)
+                    .values(UserAliasDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving user alias")
+            }
+            postgresql {
+                let value = UserAliasDb::to_db(self);
+                diesel::insert_into(user_aliases::table)
+                    .values(&value)
+                    .on_conflict(user_aliases::email)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving user alias")
+            }
+        }
+    }
+
+    pub async fn delete(self, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(user_aliases::table.filter(user_aliases::email.eq(self.email)))
+                .execute(conn)
+                .map_res("Error deleting user alias")
+        }}
+    }
+
+    /// Checks that the address isn't used by another account yet, as its email or as a verified alias.
+    /// An unverified alias of another account doesn't block the address, it is replaced by the new alias.
+    pub async fn check_available(email: &str, conn: &mut DbConn) -> EmptyResult {
+        if !crate::util::is_valid_email(email) {
+            err!("Invalid email address")
+        }
+        if User::find_by_mail(email, conn).await.is_some() {
+            err!("Email already in use")
+        }
+        if Self::find_by_mail(email, conn).await.is_some_and(|alias| alias.is_verified()) {
+            err!("Email already in use")
+        }
+        Ok(())
+    }
+
+    /// Returns the alias in any state, `User::find_by_mail` only resolves the verified ones.
+    pub async fn find_by_mail(mail: &str, conn: &mut DbConn) -> Option<Self> {
+        let lower_mail = mail.to_lowercase();
+        db_run! { conn: {
+            user_aliases::table
+                .filter(user_aliases::email.eq(lower_mail))
+                .first::<UserAliasDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_mail_and_user(mail: &str, user_uuid: &UserId, conn: &mut DbConn) -> Option<Self> {
+        let lower_mail = mail.to_lowercase();
+        db_run! { conn: {
+            user_aliases::table
+                .filter(user_aliases::email.eq(lower_mail))
+                .filter(user_aliases::user_uuid.eq(user_uuid))
+                .first::<UserAliasDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub async fn find_by_user(user_uuid: &UserId, conn: &mut DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            user_aliases::table
+                .filter(user_aliases::user_uuid.eq(user_uuid))
+                .order_by(user_aliases::email.asc())
+                .load::<UserAliasDb>(conn)
+                .expect("Error loading user aliases")
+                .from_db()
+        }}
+    }
+
+    /// Gives all the aliases of an account to another one, used when merging accounts.
+    pub async fn transfer_all(from_user_uuid: &UserId, to_user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::update(user_aliases::table.filter(user_aliases::user_uuid.eq(from_user_uuid)))
+                .set(user_aliases::user_uuid.eq(to_user_uuid))
+                .execute(conn)
+                .map_res("Error transferring user aliases")
+        }}
+    }
+
+    pub async fn delete_all_by_user(user_uuid: &UserId, conn: &mut DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(user_aliases::table.filter(user_aliases::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting user aliases")
+        }}
+    }
+}
@@ -24,6 +24,7 @@ table! {
         password_history -> Nullable<Text>,
         deleted_at -> Nullable<Datetime>,
         reprompt -> Nullable<Integer>,
+        password_last_changed -> Nullable<Datetime>,
     }
 }
 
@@ -55,6 +56,7 @@ table! {
         push_token -> Nullable<Text>,
         refresh_token -> Text,
         twofactor_remember -> Nullable<Text>,
+        last_ip -> Nullable<Text>,
     }
 }
 
@@ -126,6 +128,16 @@ table! {
         billing_email -> Text,
         private_key -> Nullable<Text>,
         public_key -> Nullable<Text>,
+        attachment_limit -> Nullable<BigInt>,
+        max_seats -> Nullable<Integer>,
+        trash_retention_days -> Nullable<Integer>,
+        events_days_retain -> Nullable<Integer>,
+        ip_allowlist -> Nullable<Text>,
+        ip_denylist -> Nullable<Text>,
+        invite_subject -> Nullable<Text>,
+        invite_body -> Nullable<Text>,
+        invite_reply_to -> Nullable<Text>,
+        archived_at -> Nullable<Datetime>,
     }
 }
 
@@ -150,6 +162,7 @@ table! {
         deletion_date -> Datetime,
         disabled -> Bool,
         hide_email -> Nullable<Bool>,
+        expiration_notified -> Bool,
     }
 }
 
@@ -206,6 +219,8 @@ table! {
         public_key -> Nullable<Text>,
         totp_secret -> Nullable<Text>,
         totp_recover -> Nullable<Text>,
+        totp_recover_viewed_at -> Nullable<Datetime>,
+        totp_recover_used_at -> Nullable<Datetime>,
         security_stamp -> Text,
         stamp_exception -> Nullable<Text>,
         equivalent_domains -> Text,
@@ -217,6 +232,19 @@ table! {
         api_key -> Nullable<Text>,
         avatar_color -> Nullable<Text>,
         external_id -> Nullable<Text>,
+        attachment_limit -> Nullable<BigInt>,
+        send_limit -> Nullable<BigInt>,
+        deleted_at -> Nullable<Datetime>,
+        kdf_campaign_at -> Nullable<Datetime>,
+        kdf_upgrade_required -> Bool,
+        self_deleted -> Bool,
+        uses_key_connector -> Bool,
+        trash_retention_days -> Nullable<Integer>,
+        force_password_reset -> Bool,
+        new_device_email -> Bool,
+        email_undeliverable_at -> Nullable<Datetime>,
+        email_undeliverable_reason -> Nullable<Text>,
+        password_policy_pending -> Bool,
     }
 }
 
@@ -242,6 +270,10 @@ table! {
         atype -> Integer,
         reset_password_key -> Nullable<Text>,
         external_id -> Nullable<Text>,
+        sensitive_notifications -> Integer,
+        permissions -> Integer,
+        ldap_dn -> Nullable<Text>,
+        ldap_revoked -> Bool,
     }
 }
 
@@ -346,6 +378,425 @@ table! {
         exposed_count -> Integer,
         created_at -> Timestamp,
         last_updated_at -> Timestamp,
+        threshold_exceeded_since -> Nullable<Datetime>,
+        delta_notified_at -> Nullable<Datetime>,
+        collection_uuid -> Nullable<Text>,
+        authenticated -> Bool,
+    }
+}
+
+table! {
+    login_events (uuid) {
+        uuid -> Text,
+        user_uuid -> Nullable<Text>,
+        username -> Nullable<Text>,
+        grant_type -> Text,
+        ip_address -> Text,
+        device_type -> Nullable<Integer>,
+        device_name -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        country -> Nullable<Text>,
+        success -> Bool,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    mail_queue (uuid) {
+        uuid -> Text,
+        address -> Text,
+        subject -> Text,
+        body_html -> Text,
+        body_text -> Text,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        created_at -> Datetime,
+        next_attempt_at -> Datetime,
+        failed_at -> Nullable<Datetime>,
+        reply_to -> Nullable<Text>,
+    }
+}
+
+table! {
+    send_accesses (uuid) {
+        uuid -> Text,
+        send_uuid -> Text,
+        ip_hash -> Text,
+        success -> Bool,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    breach_findings (uuid) {
+        uuid -> Text,
+        org_uuid -> Text,
+        user_uuid -> Text,
+        breach_name -> Text,
+        title -> Text,
+        domain -> Text,
+        breach_date -> Nullable<Text>,
+        data_classes -> Text,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    quarantined_files (uuid) {
+        uuid -> Text,
+        user_uuid -> Text,
+        org_uuid -> Nullable<Text>,
+        cipher_uuid -> Nullable<Text>,
+        send_uuid -> Nullable<Text>,
+        file_size -> BigInt,
+        signature -> Text,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    projects (uuid) {
+        uuid -> Text,
+        org_uuid -> Text,
+        name -> Text,
+        creation_date -> Datetime,
+        revision_date -> Datetime,
+    }
+}
+
+table! {
+    secrets (uuid) {
+        uuid -> Text,
+        org_uuid -> Text,
+        akey -> Text,
+        value -> Text,
+        note -> Text,
+        creation_date -> Datetime,
+        revision_date -> Datetime,
+    }
+}
+
+table! {
+    projects_secrets (project_uuid, secret_uuid) {
+        project_uuid -> Text,
+        secret_uuid -> Text,
+    }
+}
+
+table! {
+    secrets_revisions (org_uuid) {
+        org_uuid -> Text,
+        revision_date -> Timestamp,
+    }
+}
+
+table! {
+    service_accounts (uuid) {
+        uuid -> Text,
+        org_uuid -> Text,
+        name -> Text,
+        creation_date -> Datetime,
+        revision_date -> Datetime,
+    }
+}
+
+table! {
+    projects_service_accounts (project_uuid, service_account_uuid) {
+        project_uuid -> Text,
+        service_account_uuid -> Text,
+        can_read -> Bool,
+        can_write -> Bool,
+    }
+}
+
+table! {
+    service_account_tokens (uuid) {
+        uuid -> Text,
+        service_account_uuid -> Text,
+        name -> Text,
+        client_secret_hash -> Text,
+        encrypted_payload -> Text,
+        akey -> Text,
+        expire_at -> Nullable<Datetime>,
+        creation_date -> Datetime,
+        revision_date -> Datetime,
+    }
+}
+
+table! {
+    key_connector_keys (user_uuid) {
+        user_uuid -> Text,
+        akey -> Text,
+        creation_date -> Datetime,
+        revision_date -> Datetime,
+    }
+}
+
+table! {
+    icon_cache (domain) {
+        domain -> Text,
+        icon_type -> Nullable<Text>,
+        etag -> Nullable<Text>,
+        upstream_url -> Nullable<Text>,
+        upstream_etag -> Nullable<Text>,
+        upstream_last_modified -> Nullable<Text>,
+        miss -> Bool,
+        fetched_at -> Datetime,
+        file_size -> BigInt,
+        accessed_at -> Datetime,
+    }
+}
+
+table! {
+    daily_stats (day) {
+        day -> Date,
+        users -> BigInt,
+        active_users -> BigInt,
+        ciphers -> BigInt,
+        attachments_size -> BigInt,
+        exposed_personal -> BigInt,
+        exposed_org -> BigInt,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    signup_rules (uuid) {
+        uuid -> Text,
+        pattern -> Text,
+        action -> Integer,
+        position -> Integer,
+        org_uuid -> Nullable<Text>,
+        collection_uuid -> Nullable<Text>,
+        bypass_invitation -> Bool,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    cipher_history (uuid) {
+        uuid -> Text,
+        cipher_uuid -> Text,
+        user_uuid -> Nullable<Text>,
+        atype -> Integer,
+        akey -> Nullable<Text>,
+        name -> Text,
+        notes -> Nullable<Text>,
+        fields -> Nullable<Text>,
+        data -> Text,
+        password_history -> Nullable<Text>,
+        reprompt -> Nullable<Integer>,
+        revision_date -> Datetime,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    user_aliases (email) {
+        email -> Text,
+        user_uuid -> Text,
+        created_at -> Datetime,
+        verification_token -> Nullable<Text>,
+        verification_attempts -> Integer,
+    }
+}
+
+table! {
+    org_templates (name) {
+        name -> Text,
+        data -> Text,
+        created_at -> Datetime,
+        updated_at -> Datetime,
+    }
+}
+
+table! {
+    cipher_shares (uuid) {
+        uuid -> Text,
+        cipher_uuid -> Text,
+        owner_uuid -> Text,
+        grantee_uuid -> Text,
+        akey -> Text,
+        data -> Text,
+        created_at -> Datetime,
+        revision_date -> Datetime,
+    }
+}
+
+table! {
+    org_twofactor (uuid) {
+        uuid -> Text,
+        org_uuid -> Text,
+        atype -> Integer,
+        enabled -> Bool,
+        data -> Text,
+        revision_date -> Datetime,
+    }
+}
+
+table! {
+    providers (uuid) {
+        uuid -> Text,
+        name -> Text,
+        business_name -> Nullable<Text>,
+        billing_email -> Text,
+        enabled -> Bool,
+        created_at -> Datetime,
+        updated_at -> Datetime,
+    }
+}
+
+table! {
+    provider_users (uuid) {
+        uuid -> Text,
+        provider_uuid -> Text,
+        user_uuid -> Text,
+        atype -> Integer,
+        status -> Integer,
+        akey -> Nullable<Text>,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    provider_organizations (uuid) {
+        uuid -> Text,
+        provider_uuid -> Text,
+        org_uuid -> Text,
+        akey -> Nullable<Text>,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    report_members (org_uuid, user_uuid) {
+        org_uuid -> Text,
+        user_uuid -> Text,
+        exposed_count -> Integer,
+        weak_count -> Integer,
+        reused_count -> Integer,
+        updated_at -> Datetime,
+    }
+}
+
+table! {
+    report_history (org_uuid, day) {
+        org_uuid -> Text,
+        day -> Date,
+        exposed_count -> Integer,
+        weak_count -> Integer,
+        reused_count -> Integer,
+    }
+}
+
+table! {
+    admin_twofactor (atype) {
+        atype -> Integer,
+        data -> Text,
+        last_used -> BigInt,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    admin_recovery_codes (uuid) {
+        uuid -> Text,
+        code_hash -> Text,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    refresh_token_families (user_uuid, device_uuid) {
+        user_uuid -> Text,
+        device_uuid -> Text,
+        family_uuid -> Text,
+        generation -> Integer,
+        created_at -> Datetime,
+        rotated_at -> Datetime,
+        last_ip -> Nullable<Text>,
+    }
+}
+
+table! {
+    personal_api_tokens (uuid) {
+        uuid -> Text,
+        user_uuid -> Text,
+        name -> Text,
+        scopes -> Text,
+        token_hash -> Text,
+        expire_at -> Nullable<Datetime>,
+        last_used_at -> Nullable<Datetime>,
+        creation_date -> Datetime,
+        security_stamp -> Text,
+    }
+}
+
+table! {
+    attachment_uploads (attachment_id) {
+        attachment_id -> Text,
+        cipher_uuid -> Text,
+        user_uuid -> Text,
+        file_size -> BigInt,
+        created_at -> Datetime,
+        updated_at -> Datetime,
+    }
+}
+
+table! {
+    policy_violations (uuid) {
+        uuid -> Text,
+        org_uuid -> Text,
+        user_uuid -> Text,
+        policy_type -> Integer,
+        action -> Integer,
+        reason -> Text,
+        actor_uuid -> Text,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    org_risk_scores (org_uuid, day) {
+        org_uuid -> Text,
+        day -> Date,
+        score -> Integer,
+        exposure_points -> Integer,
+        trend_points -> Integer,
+        two_factor_points -> Integer,
+        staleness_points -> Integer,
+        exposed_count -> Integer,
+        previous_exposed_count -> Nullable<Integer>,
+        member_count -> Integer,
+        two_factor_count -> Integer,
+        report_age_days -> Nullable<Integer>,
+        created_at -> Datetime,
+    }
+}
+
+table! {
+    org_domains (uuid) {
+        uuid -> Text,
+        org_uuid -> Text,
+        domain_name -> Text,
+        verified_at -> Nullable<Datetime>,
+        member_type -> Integer,
+        collections -> Text,
+        auto_confirm -> Bool,
+        created_at -> Datetime,
+        txt -> Nullable<Text>,
+        last_checked_at -> Nullable<Datetime>,
+        check_count -> Integer,
+    }
+}
+
+table! {
+    changes (seq) {
+        seq -> BigInt,
+        entity_type -> Text,
+        entity_uuid -> Text,
+        action -> Text,
+        created_at -> Datetime,
     }
 }
 
@@ -366,6 +817,16 @@ joinable!(twofactor -> users (user_uuid));
 joinable!(users_collections -> collections (collection_uuid));
 joinable!(users_collections -> users (user_uuid));
 joinable!(users_organizations -> organizations (org_uuid));
+joinable!(org_domains -> organizations (org_uuid));
+joinable!(signup_rules -> organizations (org_uuid));
+joinable!(cipher_shares -> ciphers (cipher_uuid));
+joinable!(cipher_history -> ciphers (cipher_uuid));
+joinable!(user_aliases -> users (user_uuid));
+joinable!(org_twofactor -> organizations (org_uuid));
+joinable!(provider_users -> providers (provider_uuid));
+joinable!(provider_users -> users (user_uuid));
+joinable!(provider_organizations -> providers (provider_uuid));
+joinable!(provider_organizations -> organizations (org_uuid));
 joinable!(users_organizations -> users (user_uuid));
 joinable!(users_organizations -> ciphers (org_uuid));
 joinable!(organization_api_key -> organizations (org_uuid));
@@ -380,6 +841,21 @@ joinable!(auth_requests -> users (user_uuid));
 joinable!(sso_users -> users (user_uuid));
 joinable!(reports -> users (user_uuid));
 joinable!(reports -> organizations (org_uuid));
+joinable!(report_members -> users (user_uuid));
+joinable!(report_members -> organizations (org_uuid));
+joinable!(report_history -> organizations (org_uuid));
+joinable!(send_accesses -> sends (send_uuid));
+joinable!(projects_secrets -> projects (project_uuid));
+joinable!(projects_secrets -> secrets (secret_uuid));
+joinable!(projects_service_accounts -> projects (project_uuid));
+joinable!(projects_service_accounts -> service_accounts (service_account_uuid));
+joinable!(service_account_tokens -> service_accounts (service_account_uuid));
+joinable!(key_connector_keys -> users (user_uuid));
+joinable!(refresh_token_families -> users (user_uuid));
+joinable!(personal_api_tokens -> users (user_uuid));
+joinable!(policy_violations -> organizations (org_uuid));
+joinable!(policy_violations -> users (user_uuid));
+joinable!(org_risk_scores -> organizations (org_uuid));
 
 allow_tables_to_appear_in_same_query!(
     attachments,
@@ -406,4 +882,39 @@ allow_tables_to_appear_in_same_query!(
     event,
     auth_requests,
     reports,
+    login_events,
+    mail_queue,
+    org_domains,
+    changes,
+    send_accesses,
+    breach_findings,
+    quarantined_files,
+    projects,
+    secrets,
+    projects_secrets,
+    secrets_revisions,
+    service_accounts,
+    projects_service_accounts,
+    service_account_tokens,
+    key_connector_keys,
+    icon_cache,
+    daily_stats,
+    signup_rules,
+    cipher_shares,
+    cipher_history,
+    user_aliases,
+    org_templates,
+    org_twofactor,
+    providers,
+    provider_users,
+    provider_organizations,
+    report_members,
+    report_history,
+    admin_twofactor,
+    admin_recovery_codes,
+    refresh_token_families,
+    personal_api_tokens,
+    attachment_uploads,
+    policy_violations,
+    org_risk_scores,
 );
@@ -43,6 +43,7 @@ use diesel::result::Error as DieselErr;
 use diesel::ConnectionError as DieselConErr;
 use handlebars::RenderError as HbErr;
 use jsonwebtoken::errors::Error as JwtErr;
+use ldap3::LdapError as LdapErr;
 use lettre::address::AddressError as AddrErr;
 use lettre::error::Error as LettreErr;
 use lettre::transport::smtp::Error as SmtpErr;
@@ -101,6 +102,7 @@ make_error! {
     Webauthn(WebauthnErr):   _has_source, _api_error,
 
     OpenDAL(OpenDALErr): _has_source, _api_error,
+    Ldap(LdapErr):       _has_source, _api_error,
 }
 
 impl std::fmt::Debug for Error {
@@ -238,14 +240,22 @@ use rocket::request::Request;
 use rocket::response::{self, Responder, Response};
 
 impl Responder<'_, 'static> for Error {
-    fn respond_to(self, _: &Request<'_>) -> response::Result<'static> {
+    fn respond_to(self, req: &Request<'_>) -> response::Result<'static> {
+        let request_id = crate::util::RequestId::get(req);
         match self.error {
             ErrorKind::Empty(_) | ErrorKind::Simple(_) | ErrorKind::Compact(_) => {} // Don't print the error in this situation
-            _ => error!(target: "error", "{self:#?}"),
+            _ => error!(target: "error", "[{request_id}] {self:#?}"),
         };
 
         let code = Status::from_code(self.error_code).unwrap_or(Status::BadRequest);
-        let body = self.to_string();
+        // Include the request ID, so users can reference it when reporting an issue
+        let body = match serde_json::from_str::<Value>(&self.to_string()) {
+            Ok(Value::Object(mut body)) => {
+                body.insert(String::from("requestId"), Value::String(request_id.to_string()));
+                Value::Object(body).to_string()
+            }
+            _ => self.to_string(),
+        };
         Response::build().status(code).header(ContentType::JSON).sized_body(Some(body.len()), Cursor::new(body)).ok()
     }
 }
@@ -1,12 +1,18 @@
 use std::{
+    collections::HashMap,
     fmt,
     net::{IpAddr, SocketAddr},
     str::FromStr,
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use hickory_resolver::{name_server::TokioConnectionProvider, TokioResolver};
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig},
+    name_server::TokioConnectionProvider,
+    TokioResolver,
+};
+use ipnet::IpNet;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::{
@@ -33,10 +39,21 @@ pub fn make_http_request(method: reqwest::Method, url: &str) -> Result<reqwest::
 }
 
 pub fn get_reqwest_client_builder() -> ClientBuilder {
+    build_client(false)
+}
+
+/// Returns a client builder for the icon service. Next to the checks of the default client, it resolves domains with
+/// DNS over HTTPS when configured, refuses to connect to the `ICON_BLOCKED_IP_RANGES`, also after a redirect,
+/// and stops contacting hosts which failed too often.
+pub fn get_icon_client_builder() -> ClientBuilder {
+    build_client(true)
+}
+
+fn build_client(icons: bool) -> ClientBuilder {
     let mut headers = header::HeaderMap::new();
     headers.insert(header::USER_AGENT, header::HeaderValue::from_static("Vaultwarden"));
 
-    let redirect_policy = reqwest::redirect::Policy::custom(|attempt| {
+    let redirect_policy = reqwest::redirect::Policy::custom(move |attempt| {
         if attempt.previous().len() >= 5 {
             return attempt.error("Too many redirects");
         }
@@ -45,6 +62,12 @@ pub fn get_reqwest_client_builder() -> ClientBuilder {
             return attempt.error("Invalid host");
         };
 
+        if icons {
+            if let Err(e) = check_icon_host(&host) {
+                return attempt.error(e);
+            }
+        }
+
         if let Err(e) = should_block_host(host) {
             return attempt.error(e);
         }
@@ -55,7 +78,11 @@ pub fn get_reqwest_client_builder() -> ClientBuilder {
     Client::builder()
         .default_headers(headers)
         .redirect(redirect_policy)
-        .dns_resolver(CustomDnsResolver::instance())
+        .dns_resolver(if icons {
+            CustomDnsResolver::icon_instance()
+        } else {
+            CustomDnsResolver::instance()
+        })
         .timeout(Duration::from_secs(10))
 }
 
@@ -125,6 +152,129 @@ fn should_block_host(host: Host<&str>) -> Result<(), CustomHttpClientError> {
     Ok(())
 }
 
+/// Checks a host the icon service is about to contact. IP literals don't go through the DNS resolver,
+/// so they are checked against the `ICON_BLOCKED_IP_RANGES` here, for the first request as well as for redirects.
+pub fn check_icon_host(host: &Host<&str>) -> Result<(), CustomHttpClientError> {
+    let ip = match host {
+        Host::Ipv4(ip) => IpAddr::V4(*ip),
+        Host::Ipv6(ip) => IpAddr::V6(*ip),
+        Host::Domain(domain) => return check_icon_circuit(domain),
+    };
+
+    if should_block_icon_ip(ip) {
+        return Err(CustomHttpClientError::BlockedIpRange {
+            domain: None,
+            ip,
+        });
+    }
+
+    check_icon_circuit(&host.to_string())
+}
+
+fn should_block_icon_ip(ip: IpAddr) -> bool {
+    static PARSED_RANGES: Mutex<Option<(String, Vec<IpNet>)>> = Mutex::new(None);
+    let block_ranges = CONFIG.icon_blocked_ip_ranges();
+    let mut guard = PARSED_RANGES.lock().unwrap();
+
+    // If we don't have the ranges parsed, or they are not up to date, parse them again
+    if guard.as_ref().is_none_or(|(value, _)| value != &block_ranges) {
+        let ranges = parse_ip_ranges(&block_ranges).unwrap_or_default();
+        *guard = Some((block_ranges, ranges));
+    }
+
+    // IPv4-mapped IPv6 addresses are checked as the IPv4 address they map to
+    let ip = ip.to_canonical();
+    guard.as_ref().is_some_and(|(_, ranges)| ranges.iter().any(|range| range.contains(&ip)))
+}
+
+/// Parses a comma separated list of IP ranges in CIDR notation.
+pub fn parse_ip_ranges(ranges: &str) -> Result<Vec<IpNet>, ipnet::AddrParseError> {
+    ranges.split(',').map(str::trim).filter(|range| !range.is_empty()).map(IpNet::from_str).collect()
+}
+
+/// Returns the resolver configuration of the `ICON_DNS_OVER_HTTPS` setting.
+pub fn parse_dns_over_https(value: &str) -> Result<ResolverConfig, String> {
+    match value {
+        "cloudflare" => Ok(ResolverConfig::cloudflare_https()),
+        "google" => Ok(ResolverConfig::google_https()),
+        "quad9" => Ok(ResolverConfig::quad9_https()),
+        custom => {
+            let Some((ips, tls_name)) = custom.split_once('@') else {
+                return Err(format!("`{custom}` is not a known provider, nor in the `<ip>[,<ip>]@<tls name>` format"));
+            };
+            let ips = ips
+                .split(',')
+                .map(|ip| IpAddr::from_str(ip.trim()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("`{custom}` contains an invalid IP: {e}"))?;
+            if tls_name.is_empty() {
+                return Err(format!("`{custom}` has no TLS name"));
+            }
+
+            let name_servers = NameServerConfigGroup::from_ips_https(&ips, 443, tls_name.to_string(), true);
+            Ok(ResolverConfig::from_parts(None, vec![], name_servers))
+        }
+    }
+}
+
+struct HostCircuit {
+    failures: u32,
+    opened_at: Option<Instant>,
+}
+
+static ICON_CIRCUITS: Lazy<Mutex<HashMap<String, HostCircuit>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Returns an error while the circuit breaker of the host is open, after too many failed icon requests.
+pub fn check_icon_circuit(host: &str) -> Result<(), CustomHttpClientError> {
+    if CONFIG.icon_circuit_breaker_threshold() == 0 {
+        return Ok(());
+    }
+
+    let cooldown = Duration::from_secs(CONFIG.icon_circuit_breaker_cooldown());
+    let circuits = ICON_CIRCUITS.lock().unwrap();
+    if circuits.get(host).and_then(|c| c.opened_at).is_some_and(|opened_at| opened_at.elapsed() < cooldown) {
+        return Err(CustomHttpClientError::CircuitOpen {
+            domain: host.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Records the outcome of an icon request to a host, its circuit breaker opens after
+/// `ICON_CIRCUIT_BREAKER_THRESHOLD` consecutive failures. After the cooldown one request is let through again,
+/// and another failure opens the circuit breaker right away.
+pub fn record_icon_request(host: &str, success: bool) {
+    let threshold = CONFIG.icon_circuit_breaker_threshold();
+    if threshold == 0 {
+        return;
+    }
+
+    let mut circuits = ICON_CIRCUITS.lock().unwrap();
+    if success {
+        circuits.remove(host);
+        return;
+    }
+
+    // Don't let the failures of hosts which are never contacted again pile up
+    if circuits.len() >= 1024 && !circuits.contains_key(host) {
+        let cooldown = Duration::from_secs(CONFIG.icon_circuit_breaker_cooldown());
+        circuits.retain(|_, c| c.opened_at.is_some_and(|opened_at| opened_at.elapsed() < cooldown));
+    }
+
+    let circuit = circuits.entry(host.to_string()).or_insert(HostCircuit {
+        failures: 0,
+        opened_at: None,
+    });
+    circuit.failures = circuit.failures.saturating_add(1);
+    if circuit.failures >= threshold {
+        if circuit.failures == threshold {
+            warn!("Icon requests to {host} failed {threshold} times in a row, pausing requests to it");
+        }
+        circuit.opened_at = Some(Instant::now());
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum CustomHttpClientError {
     Blocked {
@@ -134,6 +284,13 @@ pub enum CustomHttpClientError {
         domain: Option<String>,
         ip: IpAddr,
     },
+    BlockedIpRange {
+        domain: Option<String>,
+        ip: IpAddr,
+    },
+    CircuitOpen {
+        domain: String,
+    },
 }
 
 impl CustomHttpClientError {
@@ -164,6 +321,17 @@ impl fmt::Display for CustomHttpClientError {
                 domain: None,
                 ip,
             } => write!(f, "IP {ip} is not a global IP!"),
+            Self::BlockedIpRange {
+                domain: Some(domain),
+                ip,
+            } => write!(f, "IP {ip} for domain '{domain}' matched ICON_BLOCKED_IP_RANGES"),
+            Self::BlockedIpRange {
+                domain: None,
+                ip,
+            } => write!(f, "IP {ip} matched ICON_BLOCKED_IP_RANGES"),
+            Self::CircuitOpen {
+                domain,
+            } => write!(f, "Requests to '{domain}' are paused after too many failures"),
         }
     }
 }
@@ -171,67 +339,98 @@ impl fmt::Display for CustomHttpClientError {
 impl std::error::Error for CustomHttpClientError {}
 
 #[derive(Debug, Clone)]
-enum CustomDnsResolver {
+enum DnsResolver {
     Default(),
     Hickory(Arc<TokioResolver>),
 }
+
+#[derive(Debug, Clone)]
+struct CustomDnsResolver {
+    resolver: DnsResolver,
+    // The resolver of the icon service applies the additional checks of `get_icon_client_builder`
+    icons: bool,
+}
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 impl CustomDnsResolver {
     fn instance() -> Arc<Self> {
-        static INSTANCE: Lazy<Arc<CustomDnsResolver>> = Lazy::new(CustomDnsResolver::new);
+        static INSTANCE: Lazy<Arc<CustomDnsResolver>> = Lazy::new(|| CustomDnsResolver::new(false));
         Arc::clone(&*INSTANCE)
     }
 
-    fn new() -> Arc<Self> {
-        match TokioResolver::builder(TokioConnectionProvider::default()) {
-            Ok(builder) => {
-                let resolver = builder.build();
-                Arc::new(Self::Hickory(Arc::new(resolver)))
-            }
+    fn icon_instance() -> Arc<Self> {
+        static INSTANCE: Lazy<Arc<CustomDnsResolver>> = Lazy::new(|| CustomDnsResolver::new(true));
+        Arc::clone(&*INSTANCE)
+    }
+
+    fn new(icons: bool) -> Arc<Self> {
+        let resolver = match CONFIG.icon_dns_over_https().filter(|_| icons) {
+            Some(dns_over_https) => parse_dns_over_https(&dns_over_https)
+                .map(|config| TokioResolver::builder_with_config(config, TokioConnectionProvider::default()).build()),
+            None => TokioResolver::builder(TokioConnectionProvider::default())
+                .map(|builder| builder.build())
+                .map_err(|e| format!("{e:?}")),
+        };
+
+        let resolver = match resolver {
+            Ok(resolver) => DnsResolver::Hickory(Arc::new(resolver)),
             Err(e) => {
-                warn!("Error creating Hickory resolver, falling back to default: {e:?}");
-                Arc::new(Self::Default())
+                warn!("Error creating Hickory resolver, falling back to default: {e}");
+                DnsResolver::Default()
             }
-        }
+        };
+
+        Arc::new(Self {
+            resolver,
+            icons,
+        })
     }
 
     // Note that we get an iterator of addresses, but we only grab the first one for convenience
     async fn resolve_domain(&self, name: &str) -> Result<Option<SocketAddr>, BoxError> {
-        pre_resolve(name)?;
+        self.pre_resolve(name)?;
 
-        let result = match self {
-            Self::Default() => tokio::net::lookup_host(name).await?.next(),
-            Self::Hickory(r) => r.lookup_ip(name).await?.iter().next().map(|a| SocketAddr::new(a, 0)),
+        let result = match &self.resolver {
+            DnsResolver::Default() => tokio::net::lookup_host(name).await?.next(),
+            DnsResolver::Hickory(r) => r.lookup_ip(name).await?.iter().next().map(|a| SocketAddr::new(a, 0)),
         };
 
         if let Some(addr) = &result {
-            post_resolve(name, addr.ip())?;
+            self.post_resolve(name, addr.ip())?;
         }
 
         Ok(result)
     }
-}
 
-fn pre_resolve(name: &str) -> Result<(), CustomHttpClientError> {
-    if should_block_address(name) {
-        return Err(CustomHttpClientError::Blocked {
-            domain: name.to_string(),
-        });
-    }
+    fn pre_resolve(&self, name: &str) -> Result<(), CustomHttpClientError> {
+        if should_block_address(name) {
+            return Err(CustomHttpClientError::Blocked {
+                domain: name.to_string(),
+            });
+        }
 
-    Ok(())
-}
+        if self.icons {
+            check_icon_circuit(name)?;
+        }
 
-fn post_resolve(name: &str, ip: IpAddr) -> Result<(), CustomHttpClientError> {
-    if should_block_ip(ip) {
-        Err(CustomHttpClientError::NonGlobalIp {
-            domain: Some(name.to_string()),
-            ip,
-        })
-    } else {
         Ok(())
     }
+
+    fn post_resolve(&self, name: &str, ip: IpAddr) -> Result<(), CustomHttpClientError> {
+        if should_block_ip(ip) {
+            Err(CustomHttpClientError::NonGlobalIp {
+                domain: Some(name.to_string()),
+                ip,
+            })
+        } else if self.icons && should_block_icon_ip(ip) {
+            Err(CustomHttpClientError::BlockedIpRange {
+                domain: Some(name.to_string()),
+                ip,
+            })
+        } else {
+            Ok(())
+        }
+    }
 }
 
 impl Resolve for CustomDnsResolver {
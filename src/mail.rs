@@ -1,98 +1,50 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Utc};
+use once_cell::sync::OnceCell;
 use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
-use std::{env::consts::EXE_SUFFIX, str::FromStr};
-
-use lettre::{
-    message::{Attachment, Body, Mailbox, Message, MultiPart, SinglePart},
-    transport::smtp::authentication::{Credentials, Mechanism as SmtpAuthMechanism},
-    transport::smtp::client::{Tls, TlsParameters},
-    transport::smtp::extension::ClientId,
-    Address, AsyncSendmailTransport, AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
-};
+use std::str::FromStr;
+
+use lettre::Address;
 
 use crate::{
     api::EmptyResult,
     auth::{
-        encode_jwt, generate_delete_claims, generate_emergency_access_invite_claims, generate_invite_claims,
-        generate_verify_email_claims,
+        encode_jwt, generate_delete_claims, generate_device_deny_claims, generate_emergency_access_invite_claims,
+        generate_invite_claims, generate_provider_invite_claims, generate_verify_email_claims,
+    },
+    db::{
+        models::{
+            Device, DeviceType, EmergencyAccessId, MembershipId, Organization, OrganizationId, ProviderId,
+            ProviderUserId, QueuedMail, User, UserId,
+        },
+        DbPool,
     },
-    db::models::{Device, DeviceType, EmergencyAccessId, MembershipId, OrganizationId, User, UserId},
     error::Error,
+    mailer::{mailer, OutgoingMail},
     CONFIG,
 };
 
-fn sendmail_transport() -> AsyncSendmailTransport<Tokio1Executor> {
-    if let Some(command) = CONFIG.sendmail_command() {
-        AsyncSendmailTransport::new_with_command(command)
-    } else {
-        AsyncSendmailTransport::new_with_command(format!("sendmail{EXE_SUFFIX}"))
+// Used to look up the addresses which are marked as undeliverable
+static MAIL_POOL: OnceCell<DbPool> = OnceCell::new();
+// Used to store the mails in the queue, the mails are sent right away when it isn't set
+static MAIL_QUEUE_POOL: OnceCell<DbPool> = OnceCell::new();
+// Held while the queued mails are sent, so a mail isn't sent twice by the job and the shutdown
+static MAIL_QUEUE_RUNNING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Enables the suppression of undeliverable addresses, and the mail queue unless the job which sends
+/// the queued mails never runs.
+pub fn init_mail(pool: DbPool) {
+    if MAIL_POOL.set(pool.clone()).is_err() {
+        warn!("The mail pool was already initialized");
     }
-}
-
-fn smtp_transport() -> AsyncSmtpTransport<Tokio1Executor> {
-    use std::time::Duration;
-    let host = CONFIG.smtp_host().unwrap();
-
-    let smtp_client = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host.as_str())
-        .port(CONFIG.smtp_port())
-        .timeout(Some(Duration::from_secs(CONFIG.smtp_timeout())));
-
-    // Determine security
-    let smtp_client = if CONFIG.smtp_security() != *"off" {
-        let mut tls_parameters = TlsParameters::builder(host);
-        if CONFIG.smtp_accept_invalid_hostnames() {
-            tls_parameters = tls_parameters.dangerous_accept_invalid_hostnames(true);
+    if CONFIG.job_poll_interval_ms() == 0 || CONFIG.mail_queue_schedule().is_empty() {
+        if CONFIG.mail_queue_enabled() {
+            warn!("The mail queue job is disabled, mails are sent right away");
         }
-        if CONFIG.smtp_accept_invalid_certs() {
-            tls_parameters = tls_parameters.dangerous_accept_invalid_certs(true);
-        }
-        let tls_parameters = tls_parameters.build().unwrap();
-
-        if CONFIG.smtp_security() == *"force_tls" {
-            smtp_client.tls(Tls::Wrapper(tls_parameters))
-        } else {
-            smtp_client.tls(Tls::Required(tls_parameters))
-        }
-    } else {
-        smtp_client
-    };
-
-    let smtp_client = match (CONFIG.smtp_username(), CONFIG.smtp_password()) {
-        (Some(user), Some(pass)) => smtp_client.credentials(Credentials::new(user, pass)),
-        _ => smtp_client,
-    };
-
-    let smtp_client = match CONFIG.helo_name() {
-        Some(helo_name) => smtp_client.hello_name(ClientId::Domain(helo_name)),
-        None => smtp_client,
-    };
-
-    let smtp_client = match CONFIG.smtp_auth_mechanism() {
-        Some(mechanism) => {
-            let allowed_mechanisms = [SmtpAuthMechanism::Plain, SmtpAuthMechanism::Login, SmtpAuthMechanism::Xoauth2];
-            let mut selected_mechanisms = vec![];
-            for wanted_mechanism in mechanism.split(',') {
-                for m in &allowed_mechanisms {
-                    if m.to_string().to_lowercase()
-                        == wanted_mechanism.trim_matches(|c| c == '"' || c == '\'' || c == ' ').to_lowercase()
-                    {
-                        selected_mechanisms.push(*m);
-                    }
-                }
-            }
-
-            if !selected_mechanisms.is_empty() {
-                smtp_client.authentication(selected_mechanisms)
-            } else {
-                // Only show a warning, and return without setting an actual authentication mechanism
-                warn!("No valid SMTP Auth mechanism found for '{mechanism}', using default values");
-                smtp_client
-            }
-        }
-        _ => smtp_client,
-    };
-
-    smtp_client.build()
+        return;
+    }
+    if MAIL_QUEUE_POOL.set(pool).is_err() {
+        warn!("The mail queue was already initialized");
+    }
 }
 
 // This will sanitize the string values by stripping all the html tags to prevent XSS and HTML Injections
@@ -117,6 +69,40 @@ fn sanitize_data(data: &mut serde_json::Value) {
     }
 }
 
+/// Converts the markdown of a customized invitation text to HTML, only paragraphs, line breaks, bold, italic and links
+/// are supported. The text is escaped first, so it can't contain any other HTML.
+fn markdown_to_html(text: &str) -> String {
+    use regex::Regex;
+    use std::sync::LazyLock;
+    static PARAGRAPH_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\n\s*\n").unwrap());
+    static LINK_RE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"\[([^\]]+)\]\(((?:https?://|mailto:)[^\s)]+)\)").unwrap());
+    static BOLD_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\*\*([^*]+)\*\*").unwrap());
+    static ITALIC_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\*([^*]+)\*").unwrap());
+
+    let escaped = text
+        .replace("\r\n", "\n")
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;");
+
+    PARAGRAPH_RE
+        .split(&escaped)
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| {
+            let paragraph =
+                LINK_RE.replace_all(paragraph, r#"<a href="$2" target="_blank" style="color: #3c8dbc;">$1</a>"#);
+            let paragraph = BOLD_RE.replace_all(&paragraph, "<b>$1</b>");
+            let paragraph = ITALIC_RE.replace_all(&paragraph, "<i>$1</i>");
+            format!("<p style=\"margin: 0 0 10px;\">{}</p>", paragraph.replace('\n', "<br>\n"))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
 fn get_text(template_name: &'static str, data: serde_json::Value) -> Result<(String, String, String), Error> {
     let mut data = data;
     sanitize_data(&mut data);
@@ -125,6 +111,29 @@ fn get_text(template_name: &'static str, data: serde_json::Value) -> Result<(Str
     Ok((subject_html, body_html, body_text))
 }
 
+/// Like `get_text`, with the invitation subject and text customized by the organization instead of the default ones.
+/// The templates show the custom text in place of their default text, the link to join stays the same.
+fn get_invite_text(
+    template_name: &'static str,
+    data: serde_json::Value,
+    org: Option<&Organization>,
+) -> Result<(String, String, String), Error> {
+    let mut data = data;
+    sanitize_data(&mut data);
+    if let Some(body) = org.and_then(|org| org.invite_body.as_deref()) {
+        // Added after the sanitization, the markdown is escaped by the conversion and the text body isn't HTML
+        data["custom_body_html"] = json!(markdown_to_html(body));
+        data["custom_body_text"] = json!(body);
+    }
+
+    let (mut subject, body_html) = get_template(&format!("{template_name}.html"), &data)?;
+    let (_subject_text, body_text) = get_template(template_name, &data)?;
+    if let Some(custom_subject) = org.and_then(|org| org.invite_subject.as_ref()) {
+        subject.clone_from(custom_subject);
+    }
+    Ok((subject, body_html, body_text))
+}
+
 fn get_template(template_name: &str, data: &serde_json::Value) -> Result<(String, String), Error> {
     let text = CONFIG.render_template(template_name, data)?;
     let mut text_split = text.split("<!---------------->");
@@ -162,7 +171,7 @@ pub async fn send_password_hint(address: &str, hint: Option<String>) -> EmptyRes
         }),
     )?;
 
-    send_email(address, &subject, body_html, body_text).await
+    send_security_email(address, &subject, body_html, body_text).await
 }
 
 pub async fn send_delete_account(address: &str, user_id: &UserId) -> EmptyResult {
@@ -180,6 +189,19 @@ pub async fn send_delete_account(address: &str, user_id: &UserId) -> EmptyResult
         }),
     )?;
 
+    send_security_email(address, &subject, body_html, body_text).await
+}
+
+pub async fn send_delete_account_scheduled(address: &str, purge_date: &NaiveDateTime) -> EmptyResult {
+    let (subject, body_html, body_text) = get_text(
+        "email/delete_account_scheduled",
+        json!({
+            "url": CONFIG.domain(),
+            "img_src": CONFIG._smtp_img_src(),
+            "purge_date": crate::util::format_naive_datetime_local(purge_date, "%A, %B %_d, %Y"),
+        }),
+    )?;
+
     send_email(address, &subject, body_html, body_text).await
 }
 
@@ -198,7 +220,7 @@ pub async fn send_verify_email(address: &str, user_id: &UserId) -> EmptyResult {
         }),
     )?;
 
-    send_email(address, &subject, body_html, body_text).await
+    send_security_email(address, &subject, body_html, body_text).await
 }
 
 pub async fn send_register_verify_email(email: &str, token: &str) -> EmptyResult {
@@ -219,7 +241,7 @@ pub async fn send_register_verify_email(email: &str, token: &str) -> EmptyResult
         }),
     )?;
 
-    send_email(email, &subject, body_html, body_text).await
+    send_security_email(email, &subject, body_html, body_text).await
 }
 
 pub async fn send_welcome(address: &str) -> EmptyResult {
@@ -248,7 +270,7 @@ pub async fn send_welcome_must_verify(address: &str, user_id: &UserId) -> EmptyR
         }),
     )?;
 
-    send_email(address, &subject, body_html, body_text).await
+    send_security_email(address, &subject, body_html, body_text).await
 }
 
 pub async fn send_2fa_removed_from_org(address: &str, org_name: &str) -> EmptyResult {
@@ -264,6 +286,21 @@ pub async fn send_2fa_removed_from_org(address: &str, org_name: &str) -> EmptyRe
     send_email(address, &subject, body_html, body_text).await
 }
 
+pub async fn send_2fa_deadline_warning(address: &str, org_name: &str, deadline: &str, days_left: i64) -> EmptyResult {
+    let (subject, body_html, body_text) = get_text(
+        "email/send_2fa_deadline_warning",
+        json!({
+            "url": CONFIG.domain(),
+            "img_src": CONFIG._smtp_img_src(),
+            "org_name": org_name,
+            "deadline": deadline,
+            "days_left": days_left,
+        }),
+    )?;
+
+    send_email(address, &subject, body_html, body_text).await
+}
+
 pub async fn send_single_org_removed_from_org(address: &str, org_name: &str) -> EmptyResult {
     let (subject, body_html, body_text) = get_text(
         "email/send_single_org_removed_from_org",
@@ -277,13 +314,18 @@ pub async fn send_single_org_removed_from_org(address: &str, org_name: &str) ->
     send_email(address, &subject, body_html, body_text).await
 }
 
+/// `org` is the organization which customizes the invitation text, if any.
 pub async fn send_invite(
     user: &User,
     org_id: OrganizationId,
     member_id: MembershipId,
     org_name: &str,
     invited_by_email: Option<String>,
+    org: Option<&Organization>,
 ) -> EmptyResult {
+    if user.email_undeliverable_at.is_some() {
+        err!("The email address of the user is undeliverable, it has to be corrected before inviting the user again")
+    }
     let claims = generate_invite_claims(
         user.uuid.clone(),
         user.email.clone(),
@@ -314,7 +356,7 @@ pub async fn send_invite(
         err!("Failed to build invite URL query parameters")
     };
 
-    let (subject, body_html, body_text) = get_text(
+    let (subject, body_html, body_text) = get_invite_text(
         "email/send_org_invite",
         json!({
             // `url.Url` would place the anchor `#` after the query parameters
@@ -322,18 +364,25 @@ pub async fn send_invite(
             "img_src": CONFIG._smtp_img_src(),
             "org_name": org_name,
         }),
+        org,
     )?;
 
-    send_email(&user.email, &subject, body_html, body_text).await
+    let reply_to = org.and_then(|org| org.invite_reply_to.as_deref());
+    send_email_with_reply_to(&user.email, &subject, body_html, body_text, reply_to).await
 }
 
+/// `org` is the organization which customizes the invitation text, if any.
 pub async fn send_admin_invite(
     user: &User,
     org_id: OrganizationId,
     member_id: MembershipId,
     org_name: &str,
     invited_by_email: Option<String>,
+    org: Option<&Organization>,
 ) -> EmptyResult {
+    if user.email_undeliverable_at.is_some() {
+        err!("The email address of the user is undeliverable, it has to be corrected before inviting the user again")
+    }
     let claims = generate_invite_claims(
         user.uuid.clone(),
         user.email.clone(),
@@ -363,7 +412,7 @@ pub async fn send_admin_invite(
         err!("Failed to build invite URL query parameters")
     };
 
-    let (subject, body_html, body_text) = get_text(
+    let (subject, body_html, body_text) = get_invite_text(
         "email/send_admin_invite",
         json!({
             // `url.Url` would place the anchor `#` after the query parameters
@@ -371,6 +420,66 @@ pub async fn send_admin_invite(
             "img_src": CONFIG._smtp_img_src(),
             "org_name": org_name,
         }),
+        org,
+    )?;
+
+    let reply_to = org.and_then(|org| org.invite_reply_to.as_deref());
+    send_email_with_reply_to(&user.email, &subject, body_html, body_text, reply_to).await
+}
+
+/// The link to accept an invitation to a provider, or with `setup` set the link to set up a new provider.
+pub fn provider_invite_url(
+    user: &User,
+    provider_id: ProviderId,
+    provider_user_id: ProviderUserId,
+    provider_name: &str,
+    setup: bool,
+) -> Result<String, Error> {
+    let claims = generate_provider_invite_claims(
+        user.uuid.clone(),
+        user.email.clone(),
+        provider_id.clone(),
+        provider_user_id.clone(),
+    );
+    let mut query = url::Url::parse("https://query.builder").unwrap();
+    {
+        let mut query_params = query.query_pairs_mut();
+        query_params
+            .append_pair("providerId", &provider_id)
+            .append_pair("providerUserId", &provider_user_id)
+            .append_pair("providerName", provider_name)
+            .append_pair("email", &user.email)
+            .append_pair("token", &encode_jwt(&claims));
+    }
+
+    let Some(query_string) = query.query() else {
+        err!("Failed to build provider invite URL query parameters")
+    };
+
+    let path = if setup {
+        "setup-provider"
+    } else {
+        "accept-provider"
+    };
+    // `url.Url` would place the anchor `#` after the query parameters
+    Ok(format!("{}/#/providers/{path}?{query_string}", CONFIG.domain()))
+}
+
+pub async fn send_provider_invite(
+    user: &User,
+    provider_id: ProviderId,
+    provider_user_id: ProviderUserId,
+    provider_name: &str,
+    setup: bool,
+) -> EmptyResult {
+    let url = provider_invite_url(user, provider_id, provider_user_id, provider_name, setup)?;
+    let (subject, body_html, body_text) = get_text(
+        "email/send_provider_invite",
+        json!({
+            "url": url,
+            "img_src": CONFIG._smtp_img_src(),
+            "provider_name": provider_name,
+        }),
     )?;
 
     send_email(&user.email, &subject, body_html, body_text).await
@@ -552,9 +661,12 @@ pub async fn send_invite_confirmed(address: &str, org_name: &str) -> EmptyResult
     send_email(address, &subject, body_html, body_text).await
 }
 
-pub async fn send_new_device_logged_in(address: &str, ip: &str, dt: &NaiveDateTime, device: &Device) -> EmptyResult {
+pub async fn send_new_device_logged_in(user: &User, ip: &str, dt: &NaiveDateTime, device: &Device) -> EmptyResult {
     use crate::util::upcase_first;
 
+    let claims = generate_device_deny_claims(user.uuid.clone(), device.uuid.clone(), user.security_stamp.clone());
+    let deny_token = encode_jwt(&claims);
+
     let fmt = "%A, %B %_d, %Y at %r %Z";
     let (subject, body_html, body_text) = get_text(
         "email/new_device_logged_in",
@@ -565,10 +677,11 @@ pub async fn send_new_device_logged_in(address: &str, ip: &str, dt: &NaiveDateTi
             "device_name": upcase_first(&device.name),
             "device_type": DeviceType::from_i32(device.atype).to_string(),
             "datetime": crate::util::format_naive_datetime_local(dt, fmt),
+            "token": deny_token,
         }),
     )?;
 
-    send_email(address, &subject, body_html, body_text).await
+    send_email(&user.email, &subject, body_html, body_text).await
 }
 
 pub async fn send_incomplete_2fa_login(
@@ -597,6 +710,22 @@ pub async fn send_incomplete_2fa_login(
     send_email(address, &subject, body_html, body_text).await
 }
 
+pub async fn send_failed_logins(address: &str, count: u32, ip: &str, dt: &NaiveDateTime) -> EmptyResult {
+    let fmt = "%A, %B %_d, %Y at %r %Z";
+    let (subject, body_html, body_text) = get_text(
+        "email/failed_logins",
+        json!({
+            "url": CONFIG.domain(),
+            "img_src": CONFIG._smtp_img_src(),
+            "count": count,
+            "ip": ip,
+            "datetime": crate::util::format_naive_datetime_local(dt, fmt),
+        }),
+    )?;
+
+    send_email(address, &subject, body_html, body_text).await
+}
+
 pub async fn send_token(address: &str, token: &str) -> EmptyResult {
     let (subject, body_html, body_text) = get_text(
         "email/twofactor_email",
@@ -607,7 +736,7 @@ pub async fn send_token(address: &str, token: &str) -> EmptyResult {
         }),
     )?;
 
-    send_email(address, &subject, body_html, body_text).await
+    send_security_email(address, &subject, body_html, body_text).await
 }
 
 pub async fn send_change_email(address: &str, token: &str) -> EmptyResult {
@@ -620,7 +749,21 @@ pub async fn send_change_email(address: &str, token: &str) -> EmptyResult {
         }),
     )?;
 
-    send_email(address, &subject, body_html, body_text).await
+    send_security_email(address, &subject, body_html, body_text).await
+}
+
+pub async fn send_verify_alias(address: &str, account_address: &str, token: &str) -> EmptyResult {
+    let (subject, body_html, body_text) = get_text(
+        "email/verify_alias",
+        json!({
+            "url": CONFIG.domain(),
+            "img_src": CONFIG._smtp_img_src(),
+            "account_address": account_address,
+            "token": token,
+        }),
+    )?;
+
+    send_security_email(address, &subject, body_html, body_text).await
 }
 
 pub async fn send_change_email_existing(address: &str, acting_address: &str) -> EmptyResult {
@@ -658,7 +801,8 @@ pub async fn send_test(address: &str) -> EmptyResult {
         }),
     )?;
 
-    send_email(address, &subject, body_html, body_text).await
+    // Never queued, the admin wants to know if the mail settings work
+    send_email_with_attachment(address, &subject, body_html, body_text, None, None).await
 }
 
 pub async fn send_admin_reset_password(address: &str, user_name: &str, org_name: &str) -> EmptyResult {
@@ -674,6 +818,122 @@ pub async fn send_admin_reset_password(address: &str, user_name: &str, org_name:
     send_email(address, &subject, body_html, body_text).await
 }
 
+pub async fn send_report_policies_enabled(
+    address: &str,
+    org_name: &str,
+    exposed_count: i32,
+    days: i64,
+    policies: &[String],
+) -> EmptyResult {
+    let (subject, body_html, body_text) = get_text(
+        "email/report_policies_enabled",
+        json!({
+            "url": CONFIG.domain(),
+            "img_src": CONFIG._smtp_img_src(),
+            "org_name": org_name,
+            "exposed_count": exposed_count,
+            "days": days,
+            "policies": policies.join(", "),
+        }),
+    )?;
+
+    send_email(address, &subject, body_html, body_text).await
+}
+
+pub async fn send_report_delta(
+    address: &str,
+    org_name: &str,
+    previous_count: i32,
+    exposed_count: i32,
+    updated_at: &NaiveDateTime,
+) -> EmptyResult {
+    let (subject, body_html, body_text) = get_text(
+        "email/report_delta",
+        json!({
+            "url": CONFIG.domain(),
+            "img_src": CONFIG._smtp_img_src(),
+            "org_name": org_name,
+            "previous_count": previous_count,
+            "exposed_count": exposed_count,
+            "datetime": crate::util::format_naive_datetime_local(updated_at, "%A, %B %_d, %Y at %r %Z"),
+        }),
+    )?;
+
+    send_email(address, &subject, body_html, body_text).await
+}
+
+pub async fn send_member_offboarded(
+    address: &str,
+    org_name: &str,
+    member_email: &str,
+    acting_email: &str,
+    new_manager_email: &str,
+    summary: (bool, usize, usize),
+) -> EmptyResult {
+    let (revoked, collections, groups) = summary;
+    let (subject, body_html, body_text) = get_text(
+        "email/member_offboarded",
+        json!({
+            "url": CONFIG.domain(),
+            "img_src": CONFIG._smtp_img_src(),
+            "org_name": org_name,
+            "member_email": member_email,
+            "acting_email": acting_email,
+            "new_manager_email": new_manager_email,
+            "revoked": revoked,
+            "collections": collections,
+            "groups": groups,
+            "datetime": crate::util::format_naive_datetime_local(&Utc::now().naive_utc(), "%A, %B %_d, %Y at %r %Z"),
+        }),
+    )?;
+
+    send_email(address, &subject, body_html, body_text).await
+}
+
+pub async fn send_org_ownership_transferred(
+    address: &str,
+    org_name: &str,
+    new_owner_email: &str,
+    demoted: bool,
+    removed: bool,
+) -> EmptyResult {
+    let (subject, body_html, body_text) = get_text(
+        "email/org_ownership_transferred",
+        json!({
+            "url": CONFIG.domain(),
+            "img_src": CONFIG._smtp_img_src(),
+            "org_name": org_name,
+            "new_owner_email": new_owner_email,
+            "demoted": demoted,
+            "removed": removed,
+        }),
+    )?;
+
+    send_email(address, &subject, body_html, body_text).await
+}
+
+pub async fn send_sensitive_action(
+    address: &str,
+    org_name: &str,
+    action: &str,
+    details: &str,
+    actor: &str,
+) -> EmptyResult {
+    let (subject, body_html, body_text) = get_text(
+        "email/sensitive_action",
+        json!({
+            "url": CONFIG.domain(),
+            "img_src": CONFIG._smtp_img_src(),
+            "org_name": org_name,
+            "action": action,
+            "details": details,
+            "actor": actor,
+        }),
+    )?;
+
+    send_email(address, &subject, body_html, body_text).await
+}
+
 pub async fn send_protected_action_token(address: &str, token: &str) -> EmptyResult {
     let (subject, body_html, body_text) = get_text(
         "email/protected_action",
@@ -684,89 +944,175 @@ pub async fn send_protected_action_token(address: &str, token: &str) -> EmptyRes
         }),
     )?;
 
+    send_security_email(address, &subject, body_html, body_text).await
+}
+
+pub async fn send_kdf_migration(address: &str, current_kdf: &str, minimum_kdf: &str) -> EmptyResult {
+    let (subject, body_html, body_text) = get_text(
+        "email/kdf_migration",
+        json!({
+            "url": CONFIG.domain(),
+            "img_src": CONFIG._smtp_img_src(),
+            "current_kdf": current_kdf,
+            "minimum_kdf": minimum_kdf,
+        }),
+    )?;
+
     send_email(address, &subject, body_html, body_text).await
 }
 
-async fn send_with_selected_transport(email: Message) -> EmptyResult {
-    if CONFIG.use_sendmail() {
-        match sendmail_transport().send(email).await {
-            Ok(_) => Ok(()),
-            // Match some common errors and make them more user friendly
-            Err(e) => {
-                if e.is_client() {
-                    debug!("Sendmail client error: {e:?}");
-                    err!(format!("Sendmail client error: {e}"));
-                } else if e.is_response() {
-                    debug!("Sendmail response error: {e:?}");
-                    err!(format!("Sendmail response error: {e}"));
-                } else {
-                    debug!("Sendmail error: {e:?}");
-                    err!(format!("Sendmail error: {e}"));
-                }
+pub async fn send_data_report(address: &str, report: Vec<u8>) -> EmptyResult {
+    let (subject, body_html, body_text) = get_text(
+        "email/data_report",
+        json!({
+            "url": CONFIG.domain(),
+            "img_src": CONFIG._smtp_img_src(),
+        }),
+    )?;
+
+    let attachment = Some(("vaultwarden_data_report.json", report));
+    send_email_with_attachment(address, &subject, body_html, body_text, attachment, None).await
+}
+
+/// Whether mails to the address are dropped, see `User::mark_email_undeliverable`. The test mail, the
+/// mails with an attachment and the security mails are still sent, the admin or the user asked for them.
+async fn is_undeliverable(address: &str) -> bool {
+    let Some(pool) = MAIL_POOL.get() else {
+        return false;
+    };
+    match pool.get().await {
+        Ok(mut conn) => User::is_email_undeliverable(address, &mut conn).await,
+        Err(_) => false,
+    }
+}
+
+async fn send_email(address: &str, subject: &str, body_html: String, body_text: String) -> EmptyResult {
+    send_email_with_reply_to(address, subject, body_html, body_text, None).await
+}
+
+async fn send_email_with_reply_to(
+    address: &str,
+    subject: &str,
+    body_html: String,
+    body_text: String,
+    reply_to: Option<&str>,
+) -> EmptyResult {
+    if is_undeliverable(address).await {
+        info!("Not sending the mail \"{subject}\" to {address}, the address is marked as undeliverable");
+        return Ok(());
+    }
+    if let (true, Some(pool)) = (CONFIG.mail_queue_enabled(), MAIL_QUEUE_POOL.get()) {
+        // Checked here, so an invalid address still fails the request instead of the queued mail
+        Address::from_str(address)?;
+        let mut conn = pool.get().await?;
+        let reply_to = reply_to.map(str::to_string);
+        QueuedMail::new(address.to_string(), subject.to_string(), &body_html, &body_text, reply_to)?
+            .save(&mut conn)
+            .await?;
+        // Sent right away, the scheduled job only picks up the retries
+        tokio::spawn(mail_queue_job(pool.clone()));
+        return Ok(());
+    }
+    send_email_with_attachment(address, subject, body_html, body_text, None, reply_to).await
+}
+
+/// Sends a mail with a code or a link the user is waiting for right away, these are never queued.
+/// A retry hours later is of no use, the user rather requests a new one.
+/// They are sent even if the address is marked as undeliverable, dropping them would leave the user
+/// waiting for a code which never comes, and a failure is returned to the user like any other.
+async fn send_security_email(address: &str, subject: &str, body_html: String, body_text: String) -> EmptyResult {
+    send_email_with_attachment(address, subject, body_html, body_text, None, None).await
+}
+
+/// Sends the queued mails which are due, the ones which fail again are retried later with an increasing delay.
+/// Mails with an attachment and the security mails are never queued. A mail is claimed before it is sent,
+/// so instances sharing the database don't send it twice.
+pub async fn mail_queue_job(pool: DbPool) {
+    let _running = MAIL_QUEUE_RUNNING.lock().await;
+    debug!("Start sending queued mails");
+    let Ok(mut conn) = pool.get().await else {
+        error!("Failed to get DB connection while sending queued mails");
+        return;
+    };
+
+    let now = Utc::now().naive_utc();
+    for mut mail in QueuedMail::find_due(&now, &mut conn).await {
+        // Another instance sharing the database is sending it
+        if !mail.claim(&now, &mut conn).await {
+            continue;
+        }
+        // The address can be marked after the mail was queued
+        if User::is_email_undeliverable(&mail.address, &mut conn).await {
+            info!(
+                "Dropping the queued mail \"{}\" to {}, the address is marked as undeliverable",
+                mail.subject, mail.address
+            );
+            if let Err(e) = mail.delete(&mut conn).await {
+                error!("Error updating the mail queue: {e:?}");
             }
+            continue;
         }
-    } else {
-        match smtp_transport().send(email).await {
-            Ok(_) => Ok(()),
-            // Match some common errors and make them more user friendly
+        let result = match mail.bodies() {
+            Ok((body_html, body_text)) => {
+                send_email_with_attachment(
+                    &mail.address,
+                    &mail.subject,
+                    body_html,
+                    body_text,
+                    None,
+                    mail.reply_to.as_deref(),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        let saved = match result {
+            Ok(()) => mail.delete(&mut conn).await,
             Err(e) => {
-                if e.is_client() {
-                    debug!("SMTP client error: {e:#?}");
-                    err!(format!("SMTP client error: {e}"));
-                } else if e.is_transient() {
-                    debug!("SMTP 4xx error: {e:#?}");
-                    err!(format!("SMTP 4xx error: {e}"));
-                } else if e.is_permanent() {
-                    debug!("SMTP 5xx error: {e:#?}");
-                    let mut msg = e.to_string();
-                    // Add a special check for 535 to add a more descriptive message
-                    if msg.contains("(535)") {
-                        msg = format!("{msg} - Authentication credentials invalid");
-                    }
-                    err!(format!("SMTP 5xx error: {msg}"));
-                } else if e.is_timeout() {
-                    debug!("SMTP timeout error: {e:#?}");
-                    err!(format!("SMTP timeout error: {e}"));
-                } else if e.is_tls() {
-                    debug!("SMTP encryption error: {e:#?}");
-                    err!(format!("SMTP encryption error: {e}"));
+                mail.register_failure(e.to_string());
+                if mail.failed_at.is_some() {
+                    error!(
+                        "Giving up on the mail \"{}\" to {} after {} attempts: {e}",
+                        mail.subject, mail.address, mail.attempts
+                    );
                 } else {
-                    debug!("SMTP error: {e:#?}");
-                    err!(format!("SMTP error: {e}"));
+                    warn!("Sending the mail \"{}\" to {} failed, it will be retried: {e}", mail.subject, mail.address);
                 }
+                mail.save(&mut conn).await
             }
+        };
+        if let Err(e) = saved {
+            error!("Error updating the mail queue: {e:?}");
         }
     }
 }
 
-async fn send_email(address: &str, subject: &str, body_html: String, body_text: String) -> EmptyResult {
-    let smtp_from = &CONFIG.smtp_from();
-
-    let body = if CONFIG.smtp_embed_images() {
-        let logo_gray_body = Body::new(crate::api::static_files("logo-gray.png").unwrap().1.to_vec());
-        let mail_github_body = Body::new(crate::api::static_files("mail-github.png").unwrap().1.to_vec());
-        MultiPart::alternative().singlepart(SinglePart::plain(body_text)).multipart(
-            MultiPart::related()
-                .singlepart(SinglePart::html(body_html))
-                .singlepart(
-                    Attachment::new_inline(String::from("logo-gray.png"))
-                        .body(logo_gray_body, "image/png".parse().unwrap()),
-                )
-                .singlepart(
-                    Attachment::new_inline(String::from("mail-github.png"))
-                        .body(mail_github_body, "image/png".parse().unwrap()),
-                ),
-        )
-    } else {
-        MultiPart::alternative_plain_html(body_text, body_html)
-    };
+/// Sends the queued mails which are due before the server exits, the others stay queued until the next start.
+pub async fn flush_mail_queue() {
+    if let (true, Some(pool)) = (CONFIG.mail_queue_enabled(), MAIL_QUEUE_POOL.get()) {
+        mail_queue_job(pool.clone()).await;
+    }
+}
 
-    let email = Message::builder()
-        .message_id(Some(format!("<{}@{}>", crate::util::get_uuid(), smtp_from.split('@').collect::<Vec<&str>>()[1])))
-        .to(Mailbox::new(None, Address::from_str(address)?))
-        .from(Mailbox::new(Some(CONFIG.smtp_from_name()), Address::from_str(smtp_from)?))
-        .subject(subject)
-        .multipart(body)?;
+async fn send_email_with_attachment(
+    address: &str,
+    subject: &str,
+    body_html: String,
+    body_text: String,
+    attachment: Option<(&str, Vec<u8>)>,
+    reply_to: Option<&str>,
+) -> EmptyResult {
+    let mail = OutgoingMail {
+        address,
+        subject,
+        body_html,
+        body_text,
+        attachment,
+        reply_to,
+    };
 
-    send_with_selected_transport(email).await
+    let start = std::time::Instant::now();
+    let result = mailer().send(mail).await;
+    debug!(target: "mail", "Sending the email \"{subject}\" took {:?}", start.elapsed());
+    result
 }
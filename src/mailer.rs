@@ -0,0 +1,452 @@
+//
+// The providers which deliver the mails built by `mail.rs`, selected with `MAIL_PROVIDER`.
+// SMTP and sendmail send the MIME message built with lettre, AWS SES and SendGrid are called through their HTTP APIs.
+//
+use std::{env::consts::EXE_SUFFIX, str::FromStr, time::Duration};
+
+use chrono::Utc;
+use data_encoding::{BASE64, HEXLOWER};
+use lettre::{
+    message::{Attachment, Body, Mailbox, Message, MultiPart, SinglePart},
+    transport::smtp::authentication::{Credentials, Mechanism as SmtpAuthMechanism},
+    transport::smtp::client::{Tls, TlsParameters},
+    transport::smtp::extension::ClientId,
+    Address, AsyncSendmailTransport, AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+use reqwest::{header, Method, Response, StatusCode};
+use ring::{digest, hmac};
+use serde_json::Value;
+
+use crate::{api::EmptyResult, error::Error, http_client::make_http_request, CONFIG};
+
+// The images referenced by the templates when `SMTP_EMBED_IMAGES` is enabled
+const EMBEDDED_IMAGES: [&str; 2] = ["logo-gray.png", "mail-github.png"];
+
+/// A mail ready to be delivered, each provider turns it into the format it sends.
+pub struct OutgoingMail<'a> {
+    pub address: &'a str,
+    pub subject: &'a str,
+    pub body_html: String,
+    pub body_text: String,
+    // A JSON file attached to the mail
+    pub attachment: Option<(&'a str, Vec<u8>)>,
+    pub reply_to: Option<&'a str>,
+}
+
+#[rocket::async_trait]
+pub trait Mailer: Send + Sync {
+    /// Delivers the mail, the errors explain the failure in the terms of the provider.
+    async fn send(&self, mail: OutgoingMail<'_>) -> EmptyResult;
+}
+
+/// Returns the provider of `MAIL_PROVIDER`, `smtp` uses the sendmail command when `USE_SENDMAIL` is enabled.
+pub fn mailer() -> Box<dyn Mailer> {
+    match CONFIG.mail_provider().as_str() {
+        "ses" => Box::new(SesMailer),
+        "sendgrid" => Box::new(SendGridMailer),
+        _ if CONFIG.use_sendmail() => Box::new(SendmailMailer),
+        _ => Box::new(SmtpMailer),
+    }
+}
+
+fn embedded_image(name: &str) -> Vec<u8> {
+    crate::api::static_files(name).unwrap().1.to_vec()
+}
+
+/// Builds the MIME message, which SMTP, sendmail and SES send as is.
+fn build_message(mail: OutgoingMail<'_>) -> Result<Message, Error> {
+    let smtp_from = &CONFIG.smtp_from();
+
+    let body = if CONFIG.smtp_embed_images() {
+        let mut related = MultiPart::related().singlepart(SinglePart::html(mail.body_html));
+        for name in EMBEDDED_IMAGES {
+            related = related.singlepart(
+                Attachment::new_inline(String::from(name))
+                    .body(Body::new(embedded_image(name)), "image/png".parse().unwrap()),
+            );
+        }
+        MultiPart::alternative().singlepart(SinglePart::plain(mail.body_text)).multipart(related)
+    } else {
+        MultiPart::alternative_plain_html(mail.body_text, mail.body_html)
+    };
+
+    let body = match mail.attachment {
+        Some((filename, content)) => MultiPart::mixed()
+            .multipart(body)
+            .singlepart(Attachment::new(filename.to_string()).body(content, "application/json".parse().unwrap())),
+        None => body,
+    };
+
+    let mut builder = Message::builder()
+        .message_id(Some(format!("<{}@{}>", crate::util::get_uuid(), smtp_from.split('@').collect::<Vec<&str>>()[1])))
+        .to(Mailbox::new(None, Address::from_str(mail.address)?))
+        .from(Mailbox::new(Some(CONFIG.smtp_from_name()), Address::from_str(smtp_from)?));
+    if let Some(reply_to) = mail.reply_to {
+        builder = builder.reply_to(Mailbox::new(None, Address::from_str(reply_to)?));
+    }
+    Ok(builder.subject(mail.subject).multipart(body)?)
+}
+
+struct SmtpMailer;
+
+fn smtp_transport() -> AsyncSmtpTransport<Tokio1Executor> {
+    let host = CONFIG.smtp_host().unwrap();
+
+    let smtp_client = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host.as_str())
+        .port(CONFIG.smtp_port())
+        .timeout(Some(Duration::from_secs(CONFIG.smtp_timeout())));
+
+    // Determine security
+    let smtp_client = if CONFIG.smtp_security() != *"off" {
+        let mut tls_parameters = TlsParameters::builder(host);
+        if CONFIG.smtp_accept_invalid_hostnames() {
+            tls_parameters = tls_parameters.dangerous_accept_invalid_hostnames(true);
+        }
+        if CONFIG.smtp_accept_invalid_certs() {
+            tls_parameters = tls_parameters.dangerous_accept_invalid_certs(true);
+        }
+        let tls_parameters = tls_parameters.build().unwrap();
+
+        if CONFIG.smtp_security() == *"force_tls" {
+            smtp_client.tls(Tls::Wrapper(tls_parameters))
+        } else {
+            smtp_client.tls(Tls::Required(tls_parameters))
+        }
+    } else {
+        smtp_client
+    };
+
+    let smtp_client = match (CONFIG.smtp_username(), CONFIG.smtp_password()) {
+        (Some(user), Some(pass)) => smtp_client.credentials(Credentials::new(user, pass)),
+        _ => smtp_client,
+    };
+
+    let smtp_client = match CONFIG.helo_name() {
+        Some(helo_name) => smtp_client.hello_name(ClientId::Domain(helo_name)),
+        None => smtp_client,
+    };
+
+    let smtp_client = match CONFIG.smtp_auth_mechanism() {
+        Some(mechanism) => {
+            let allowed_mechanisms = [SmtpAuthMechanism::Plain, SmtpAuthMechanism::Login, SmtpAuthMechanism::Xoauth2];
+            let mut selected_mechanisms = vec![];
+            for wanted_mechanism in mechanism.split(',') {
+                for m in &allowed_mechanisms {
+                    if m.to_string().to_lowercase()
+                        == wanted_mechanism.trim_matches(|c| c == '"' || c == '\'' || c == ' ').to_lowercase()
+                    {
+                        selected_mechanisms.push(*m);
+                    }
+                }
+            }
+
+            if !selected_mechanisms.is_empty() {
+                smtp_client.authentication(selected_mechanisms)
+            } else {
+                // Only show a warning, and return without setting an actual authentication mechanism
+                warn!("No valid SMTP Auth mechanism found for '{mechanism}', using default values");
+                smtp_client
+            }
+        }
+        _ => smtp_client,
+    };
+
+    smtp_client.build()
+}
+
+#[rocket::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: OutgoingMail<'_>) -> EmptyResult {
+        let email = build_message(mail)?;
+        match smtp_transport().send(email).await {
+            Ok(_) => Ok(()),
+            // Match some common errors and make them more user friendly
+            Err(e) => {
+                if e.is_client() {
+                    debug!("SMTP client error: {e:#?}");
+                    err!(format!("SMTP client error: {e}"));
+                } else if e.is_transient() {
+                    debug!("SMTP 4xx error: {e:#?}");
+                    err!(format!("SMTP 4xx error: {e}"));
+                } else if e.is_permanent() {
+                    debug!("SMTP 5xx error: {e:#?}");
+                    let mut msg = e.to_string();
+                    // Add a special check for 535 to add a more descriptive message
+                    if msg.contains("(535)") {
+                        msg = format!("{msg} - Authentication credentials invalid");
+                    }
+                    err!(format!("SMTP 5xx error: {msg}"));
+                } else if e.is_timeout() {
+                    debug!("SMTP timeout error: {e:#?}");
+                    err!(format!("SMTP timeout error: {e}"));
+                } else if e.is_tls() {
+                    debug!("SMTP encryption error: {e:#?}");
+                    err!(format!("SMTP encryption error: {e}"));
+                } else {
+                    debug!("SMTP error: {e:#?}");
+                    err!(format!("SMTP error: {e}"));
+                }
+            }
+        }
+    }
+}
+
+struct SendmailMailer;
+
+fn sendmail_transport() -> AsyncSendmailTransport<Tokio1Executor> {
+    if let Some(command) = CONFIG.sendmail_command() {
+        AsyncSendmailTransport::new_with_command(command)
+    } else {
+        AsyncSendmailTransport::new_with_command(format!("sendmail{EXE_SUFFIX}"))
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for SendmailMailer {
+    async fn send(&self, mail: OutgoingMail<'_>) -> EmptyResult {
+        let email = build_message(mail)?;
+        match sendmail_transport().send(email).await {
+            Ok(_) => Ok(()),
+            // Match some common errors and make them more user friendly
+            Err(e) => {
+                if e.is_client() {
+                    debug!("Sendmail client error: {e:?}");
+                    err!(format!("Sendmail client error: {e}"));
+                } else if e.is_response() {
+                    debug!("Sendmail response error: {e:?}");
+                    err!(format!("Sendmail response error: {e}"));
+                } else {
+                    debug!("Sendmail error: {e:?}");
+                    err!(format!("Sendmail error: {e}"));
+                }
+            }
+        }
+    }
+}
+
+/// Sends the raw MIME message with the SES v2 API, the request is signed with AWS Signature Version 4.
+/// https://docs.aws.amazon.com/ses/latest/APIReference-V2/API_SendEmail.html
+struct SesMailer;
+
+impl SesMailer {
+    const PATH: &'static str = "/v2/email/outbound-emails";
+
+    fn hmac_sha256(key: &[u8], data: &str) -> hmac::Tag {
+        hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data.as_bytes())
+    }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        HEXLOWER.encode(digest::digest(&digest::SHA256, data).as_ref())
+    }
+
+    /// Returns the `Authorization` header of the request, the signed headers are sent with these exact values.
+    fn authorization(host: &str, region: &str, amz_date: &str, body: &[u8]) -> String {
+        let access_key_id = CONFIG.ses_access_key_id().unwrap_or_default();
+        let secret_access_key = CONFIG.ses_secret_access_key().unwrap_or_default();
+        let date = &amz_date[..8];
+        let scope = format!("{date}/{region}/ses/aws4_request");
+        let signed_headers = "content-type;host;x-amz-date";
+
+        let canonical_request = format!(
+            "POST\n{}\n\ncontent-type:application/json\nhost:{host}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{}",
+            Self::PATH,
+            Self::sha256_hex(body)
+        );
+        let string_to_sign =
+            format!("AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}", Self::sha256_hex(canonical_request.as_bytes()));
+
+        let key = Self::hmac_sha256(format!("AWS4{secret_access_key}").as_bytes(), date);
+        let key = Self::hmac_sha256(key.as_ref(), region);
+        let key = Self::hmac_sha256(key.as_ref(), "ses");
+        let key = Self::hmac_sha256(key.as_ref(), "aws4_request");
+        let signature = HEXLOWER.encode(Self::hmac_sha256(key.as_ref(), &string_to_sign).as_ref());
+
+        format!("AWS4-HMAC-SHA256 Credential={access_key_id}/{scope}, SignedHeaders={signed_headers}, Signature={signature}")
+    }
+
+    async fn map_error(res: Response) -> Error {
+        let status = res.status();
+        // Like `MessageRejected`, some endpoints add the namespace after a colon
+        let error_type = res
+            .headers()
+            .get("x-amzn-errortype")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(':').next())
+            .unwrap_or_default()
+            .to_string();
+        let message = res
+            .json::<Value>()
+            .await
+            .ok()
+            .and_then(|body| body["message"].as_str().or(body["Message"].as_str()).map(str::to_string))
+            .unwrap_or_default();
+        debug!("SES error {status} {error_type}: {message}");
+
+        let msg = match error_type.as_str() {
+            "MessageRejected" => format!("SES rejected the mail: {message}"),
+            "MailFromDomainNotVerifiedException" => {
+                "SES error: the domain of SMTP_FROM isn't verified in this region".to_string()
+            }
+            "AccountSuspendedException" | "SendingPausedException" => {
+                "SES error: sending is paused for this account or configuration set".to_string()
+            }
+            "TooManyRequestsException" | "LimitExceededException" => {
+                format!("SES error: the sending quota or rate was exceeded: {message}")
+            }
+            "NotFoundException" => format!("SES error: the configuration set wasn't found: {message}"),
+            _ if status == StatusCode::FORBIDDEN => {
+                format!("SES error: the credentials were refused, check SES_ACCESS_KEY_ID and SES_SECRET_ACCESS_KEY: {message}")
+            }
+            _ if status.is_server_error() => format!("SES is unavailable ({status}): {message}"),
+            _ => format!("SES error ({status}): {message}"),
+        };
+        Error::new(msg.clone(), msg)
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for SesMailer {
+    async fn send(&self, mail: OutgoingMail<'_>) -> EmptyResult {
+        let raw = build_message(mail)?.formatted();
+        let mut payload = json!({
+            "Content": {
+                "Raw": {
+                    "Data": BASE64.encode(&raw),
+                },
+            },
+        });
+        // The configuration set publishes the bounces, see `api::mail_webhooks`
+        if let Some(configuration_set) = CONFIG.ses_configuration_set() {
+            payload["ConfigurationSetName"] = Value::String(configuration_set);
+        }
+        let body = serde_json::to_vec(&payload)?;
+
+        let region = CONFIG.ses_region().unwrap_or_default();
+        let host = format!("email.{region}.amazonaws.com");
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = Self::authorization(&host, &region, &amz_date, &body);
+
+        let res = make_http_request(Method::POST, &format!("https://{host}{}", Self::PATH))?
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-amz-date", &amz_date)
+            .header(header::AUTHORIZATION, authorization)
+            .timeout(Duration::from_secs(CONFIG.smtp_timeout()))
+            .body(body)
+            .send()
+            .await;
+
+        match res {
+            Ok(res) if res.status().is_success() => Ok(()),
+            Ok(res) => Err(Self::map_error(res).await),
+            Err(e) if e.is_timeout() => err!(format!("SES timeout error: {e}")),
+            Err(e) => err!(format!("SES connection error: {e}")),
+        }
+    }
+}
+
+/// Sends the mail with the SendGrid v3 API, which takes the parts of the mail instead of a MIME message.
+/// https://www.twilio.com/docs/sendgrid/api-reference/mail-send/mail-send
+struct SendGridMailer;
+
+impl SendGridMailer {
+    const URL: &'static str = "https://api.sendgrid.com/v3/mail/send";
+
+    fn payload(mail: OutgoingMail<'_>) -> Value {
+        let mut attachments = Vec::new();
+        if CONFIG.smtp_embed_images() {
+            for name in EMBEDDED_IMAGES {
+                attachments.push(json!({
+                    "content": BASE64.encode(&embedded_image(name)),
+                    "filename": name,
+                    "type": "image/png",
+                    "disposition": "inline",
+                    "content_id": name,
+                }));
+            }
+        }
+        if let Some((filename, content)) = mail.attachment {
+            attachments.push(json!({
+                "content": BASE64.encode(&content),
+                "filename": filename,
+                "type": "application/json",
+                "disposition": "attachment",
+            }));
+        }
+
+        let mut payload = json!({
+            "personalizations": [{
+                "to": [{ "email": mail.address }],
+            }],
+            "from": {
+                "email": CONFIG.smtp_from(),
+                "name": CONFIG.smtp_from_name(),
+            },
+            "subject": mail.subject,
+            "content": [
+                { "type": "text/plain", "value": mail.body_text },
+                { "type": "text/html", "value": mail.body_html },
+            ],
+        });
+        if !attachments.is_empty() {
+            payload["attachments"] = Value::Array(attachments);
+        }
+        if let Some(reply_to) = mail.reply_to {
+            payload["reply_to"] = json!({ "email": reply_to });
+        }
+        payload
+    }
+
+    async fn map_error(res: Response) -> Error {
+        let status = res.status();
+        let message = res
+            .json::<Value>()
+            .await
+            .ok()
+            .and_then(|body| {
+                let messages: Vec<String> = body["errors"]
+                    .as_array()?
+                    .iter()
+                    .filter_map(|error| error["message"].as_str().map(str::to_string))
+                    .collect();
+                Some(messages.join(", "))
+            })
+            .unwrap_or_default();
+        debug!("SendGrid error {status}: {message}");
+
+        let msg = match status {
+            StatusCode::UNAUTHORIZED => "SendGrid error: the API key was refused, check SENDGRID_API_KEY".to_string(),
+            StatusCode::FORBIDDEN => format!(
+                "SendGrid error: the API key can't send mails, or the sender identity of SMTP_FROM isn't verified: {message}"
+            ),
+            StatusCode::PAYLOAD_TOO_LARGE => "SendGrid error: the mail is too large".to_string(),
+            StatusCode::TOO_MANY_REQUESTS => format!("SendGrid error: the rate limit was exceeded: {message}"),
+            _ if status.is_server_error() => format!("SendGrid is unavailable ({status}): {message}"),
+            _ => format!("SendGrid error ({status}): {message}"),
+        };
+        Error::new(msg.clone(), msg)
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for SendGridMailer {
+    async fn send(&self, mail: OutgoingMail<'_>) -> EmptyResult {
+        // Checked here like the other providers do when the message is built
+        Address::from_str(mail.address)?;
+        let payload = Self::payload(mail);
+        let api_key = CONFIG.sendgrid_api_key().unwrap_or_default();
+
+        let res = make_http_request(Method::POST, Self::URL)?
+            .bearer_auth(api_key)
+            .timeout(Duration::from_secs(CONFIG.smtp_timeout()))
+            .json(&payload)
+            .send()
+            .await;
+
+        match res {
+            Ok(res) if res.status().is_success() => Ok(()),
+            Ok(res) => Err(Self::map_error(res).await),
+            Err(e) if e.is_timeout() => err!(format!("SendGrid timeout error: {e}")),
+            Err(e) => err!(format!("SendGrid connection error: {e}")),
+        }
+    }
+}
@@ -47,18 +47,23 @@ use tokio::signal::unix::SignalKind;
 
 #[macro_use]
 mod error;
+mod acme;
 mod api;
 mod auth;
+mod cli;
 mod config;
 mod crypto;
 #[macro_use]
 mod db;
 mod http_client;
 mod mail;
+mod mailer;
 mod ratelimit;
+mod report_export;
 mod sso;
 mod sso_client;
 mod util;
+mod virus_scan;
 
 use crate::api::core::two_factor::duo_oidc::purge_duo_contexts;
 use crate::api::purge_auth_requests;
@@ -66,7 +71,13 @@ use crate::api::{WS_ANONYMOUS_SUBSCRIPTIONS, WS_USERS};
 pub use config::{PathType, CONFIG};
 pub use error::{Error, MapResult};
 use rocket::data::{Limits, ToByteUnit};
-use std::sync::{atomic::Ordering, Arc};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 pub use util::is_running_in_container;
 
 #[rocket::main]
@@ -86,12 +97,37 @@ async fn main() -> Result<(), Error> {
     create_dir(&CONFIG.tmp_folder(), "tmp folder");
 
     let pool = create_db_pool().await;
+    if CONFIG.db_integrity_check_on_startup() {
+        log_db_integrity(&pool).await;
+    }
+    mail::init_mail(pool.clone());
     schedule_jobs(pool.clone());
     db::models::TwoFactor::migrate_u2f_to_webauthn(&mut pool.get().await.unwrap()).await.unwrap();
     db::models::TwoFactor::migrate_credential_to_passkey(&mut pool.get().await.unwrap()).await.unwrap();
+    db::models::SignupRule::refresh_allows_signups(&mut pool.get().await.unwrap()).await;
+    db::models::KeyConnectorKey::encrypt_plaintext_keys(&mut pool.get().await.unwrap()).await.unwrap();
 
     let extra_debug = matches!(level, log::LevelFilter::Trace | log::LevelFilter::Debug);
-    launch_rocket(pool, extra_debug).await // Blocks until program termination.
+    spawn_signal_handlers();
+    loop {
+        acme::ensure_certificate().await.unwrap_or_else(|e| {
+            error!("Error obtaining the certificate with ACME\n{e:?}\nExiting Vaultwarden!");
+            exit(1);
+        });
+        if SHUTDOWN_REQUESTED.load(Ordering::Relaxed) {
+            break;
+        }
+        launch_rocket(pool.clone(), extra_debug).await?; // Blocks until the server is shut down.
+
+        if !acme::take_restart() {
+            break;
+        }
+        info!("Restarting Vaultwarden to serve the renewed certificate");
+    }
+
+    finish_shutdown(&pool).await;
+    info!("Vaultwarden process exited!");
+    Ok(())
 }
 
 const HELP: &str = "\
@@ -108,6 +144,15 @@ COMMAND:
     hash [--preset {bitwarden|owasp}]  Generate an Argon2id PHC ADMIN_TOKEN
     backup                             Create a backup of the SQLite database
                                        You can also send the USR1 signal to trigger a backup
+    migrate-db --from <PATH> [--dry-run]
+                                       Copy the SQLite database at PATH into the empty PostgreSQL
+                                       or MySQL database of DATABASE_URL, --dry-run only runs the checks
+                                       The files in the data folder, like attachments, stay where they are
+    check-db [--repair]                List the rows pointing to users, organizations or items which don't exist
+                                       --repair removes them, SQLite databases are backed up first
+    admin <COMMAND>                    Manage the server without the admin panel, like creating the first
+                                       admin, inviting users or rotating the x-vaultwarden-api key
+                                       Run `vaultwarden admin` to list the commands
 
 PRESETS:                  m=         t=          p=
     bitwarden (default) 64MiB, 3 Iterations, 4 Threads
@@ -135,28 +180,10 @@ async fn parse_args() {
 
     if let Some(command) = pargs.subcommand().unwrap_or_default() {
         if command == "hash" {
-            use argon2::{
-                password_hash::SaltString, Algorithm::Argon2id, Argon2, ParamsBuilder, PasswordHasher, Version::V0x13,
-            };
+            use argon2::{password_hash::SaltString, PasswordHasher};
 
-            let mut argon2_params = ParamsBuilder::new();
             let preset: Option<String> = pargs.opt_value_from_str(["-p", "--preset"]).unwrap_or_default();
-            let selected_preset;
-            match preset.as_deref() {
-                Some("owasp") => {
-                    selected_preset = "owasp";
-                    argon2_params.m_cost(19456);
-                    argon2_params.t_cost(2);
-                    argon2_params.p_cost(1);
-                }
-                _ => {
-                    // Bitwarden preset is the default
-                    selected_preset = "bitwarden";
-                    argon2_params.m_cost(65540);
-                    argon2_params.t_cost(3);
-                    argon2_params.p_cost(4);
-                }
-            }
+            let (argon2, selected_preset) = cli::argon2_hasher(preset.as_deref());
 
             println!("Generate an Argon2id PHC string using the '{selected_preset}' preset:\n");
 
@@ -172,7 +199,6 @@ async fn parse_args() {
                 exit(1);
             }
 
-            let argon2 = Argon2::new(Argon2id, V0x13, argon2_params.build().unwrap());
             let salt = SaltString::encode_b64(&crypto::get_random_bytes::<32>()).unwrap();
 
             let argon2_timer = tokio::time::Instant::now();
@@ -198,6 +224,38 @@ async fn parse_args() {
                     exit(1);
                 }
             }
+        } else if command == "migrate-db" {
+            let dry_run = pargs.contains("--dry-run");
+            let source: Option<String> = pargs.opt_value_from_str("--from").unwrap_or_default();
+            match migrate_sqlite(source, dry_run).await {
+                Ok(summary) => {
+                    for (table, rows) in &summary {
+                        println!("{table:<28}{rows:>10} rows");
+                    }
+                    let total: usize = summary.iter().map(|(_, rows)| rows).sum();
+                    if dry_run {
+                        println!("Dry-run successful, {total} rows would be copied");
+                    } else {
+                        println!("Migration successful, {total} rows were copied");
+                    }
+                    exit(0);
+                }
+                Err(e) => {
+                    println!("Migration failed. {e:?}");
+                    exit(1);
+                }
+            }
+        } else if command == "check-db" {
+            let repair = pargs.contains("--repair");
+            if let Err(e) = check_db(repair).await {
+                println!("Database check failed. {e:?}");
+                exit(1);
+            }
+        } else if command == "admin" {
+            if let Err(e) = cli::run_admin(pargs).await {
+                println!("Admin command failed. {e:?}");
+                exit(1);
+            }
         }
         exit(0);
     }
@@ -220,6 +278,55 @@ async fn backup_sqlite() -> Result<String, Error> {
     }
 }
 
+async fn migrate_sqlite(source: Option<String>, dry_run: bool) -> Result<db::models::CopySummary, Error> {
+    use crate::db::{models::copy_database, DbConnType, DbPool};
+
+    let Some(source) = source else {
+        err_silent!("The SQLite database to copy has to be set with `--from`")
+    };
+    if DbConnType::from_url(&source)? != DbConnType::sqlite || !Path::new(&source).is_file() {
+        err_silent!("`--from` has to be the path of an existing SQLite database")
+    }
+    if DbConnType::from_url(&CONFIG.database_url())? == DbConnType::sqlite {
+        err_silent!("`DATABASE_URL` has to be the PostgreSQL or MySQL database the data is copied into")
+    }
+
+    // Both databases are migrated to the current schema first, so the tables of both sides match
+    let mut source = DbPool::from_url(&source)?.get().await?;
+    let mut target = DbPool::from_config()?.get().await?;
+    copy_database(&mut source, &mut target, dry_run).await
+}
+
+async fn check_db(repair: bool) -> Result<(), Error> {
+    use crate::db::{
+        backup_database,
+        models::{check_integrity, repair_integrity},
+        DbConnType, DbPool,
+    };
+
+    let mut conn = DbPool::from_config()?.get().await?;
+    let issues = check_integrity(&mut conn).await?;
+    if issues.is_empty() {
+        println!("No rows pointing to missing users, organizations or items were found");
+        return Ok(());
+    }
+    for issue in &issues {
+        println!("{:<36}{:>10} rows  {}", issue.check.name(), issue.rows, issue.check.description());
+    }
+    if !repair {
+        println!("Run `check-db --repair` to remove these rows");
+        return Ok(());
+    }
+
+    if DbConnType::from_url(&CONFIG.database_url())? == DbConnType::sqlite {
+        let backup_file = backup_database(&mut conn).await?;
+        println!("Backup to '{backup_file}' was successful");
+    }
+    let repaired = repair_integrity(&mut conn).await?;
+    println!("Repair successful, {repaired} rows and the data depending on them were removed");
+    Ok(())
+}
+
 fn launch_info() {
     println!(
         "\
@@ -364,18 +471,33 @@ fn init_logging() -> Result<log::LevelFilter, Error> {
         logger = logger.level_for(path.to_string(), level);
     }
 
-    if CONFIG.extended_logging() {
+    // The lines logged while handling a request are tagged with its ID
+    if CONFIG.log_format() == "json" {
         logger = logger.format(|out, message, record| {
             out.finish(format_args!(
-                "[{}][{}][{}] {}",
+                "{}",
+                json!({
+                    "timestamp": chrono::Local::now().format(&CONFIG.log_timestamp_format()).to_string(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "requestId": util::RequestId::current(),
+                    "message": message.to_string(),
+                })
+            ))
+        });
+    } else if CONFIG.extended_logging() {
+        logger = logger.format(|out, message, record| {
+            out.finish(format_args!(
+                "[{}][{}][{}] {}{}",
                 chrono::Local::now().format(&CONFIG.log_timestamp_format()),
                 record.target(),
                 record.level(),
+                request_id_prefix(),
                 message
             ))
         });
     } else {
-        logger = logger.format(|out, message, _| out.finish(format_args!("{message}")));
+        logger = logger.format(|out, message, _| out.finish(format_args!("{}{message}", request_id_prefix())));
     }
 
     if let Some(log_file) = CONFIG.log_file() {
@@ -438,6 +560,10 @@ fn init_logging() -> Result<log::LevelFilter, Error> {
     Ok(level)
 }
 
+fn request_id_prefix() -> String {
+    util::RequestId::current().map(|id| format!("[{id}] ")).unwrap_or_default()
+}
+
 #[cfg(unix)]
 fn chain_syslog(logger: fern::Dispatch) -> fern::Dispatch {
     let syslog_fmt = syslog::Formatter3164 {
@@ -573,6 +699,23 @@ async fn create_db_pool() -> db::DbPool {
     }
 }
 
+async fn log_db_integrity(pool: &db::DbPool) {
+    let issues = match pool.get().await {
+        Ok(mut conn) => db::models::check_integrity(&mut conn).await,
+        Err(e) => Err(e),
+    };
+    match issues {
+        Ok(issues) if issues.is_empty() => info!("The database integrity check found no problems"),
+        Ok(issues) => {
+            for issue in issues {
+                warn!("Database integrity check: {} ({} rows)", issue.check.description(), issue.rows);
+            }
+            warn!("Run `vaultwarden check-db --repair` or use `/admin/db-integrity` to remove these rows");
+        }
+        Err(e) => error!("Error checking the database integrity: {e:?}"),
+    }
+}
+
 async fn launch_rocket(pool: db::DbPool, extra_debug: bool) -> Result<(), Error> {
     let basepath = &CONFIG.domain_path();
 
@@ -583,6 +726,14 @@ async fn launch_rocket(pool: db::DbPool, extra_debug: bool) -> Result<(), Error>
         .limit("json", 20.megabytes()) // 20MB should be enough for very large imports, something like 5000+ vault entries
         .limit("data-form", 525.megabytes()) // This needs to match the maximum allowed file size for Send
         .limit("file", 525.megabytes()); // This needs to match the maximum allowed file size for attachments
+    if let Some(tls) = acme::tls_config() {
+        config.tls = Some(tls);
+    }
+    // The signals are handled by `spawn_signal_handlers`, which closes the websockets before draining the connections
+    config.shutdown.ctrlc = false;
+    #[cfg(unix)]
+    config.shutdown.signals.clear();
+    config.shutdown.grace = CONFIG.shutdown_grace_period();
 
     // If adding more paths here, consider also adding them to
     // crate::utils::LOGGED_ROUTES to make sure they appear in the log
@@ -595,6 +746,8 @@ async fn launch_rocket(pool: db::DbPool, extra_debug: bool) -> Result<(), Error>
         .mount([basepath, "/identity"].concat(), api::identity_routes())
         .mount([basepath, "/icons"].concat(), api::icons_routes())
         .mount([basepath, "/notifications"].concat(), api::notifications_routes())
+        .mount([basepath, "/replication"].concat(), api::replication_routes())
+        .mount([basepath, "/mail"].concat(), api::mail_webhooks_routes())
         .register([basepath, "/"].concat(), api::web_catchers())
         .register([basepath, "/api"].concat(), api::core_catchers())
         .register([basepath, "/admin"].concat(), api::admin_catchers())
@@ -604,19 +757,77 @@ async fn launch_rocket(pool: db::DbPool, extra_debug: bool) -> Result<(), Error>
         .attach(util::AppHeaders())
         .attach(util::Cors())
         .attach(util::BetterLogging(extra_debug))
+        .attach(util::MaintenanceMode())
         .ignite()
         .await?;
 
     CONFIG.set_rocket_shutdown_handle(instance.shutdown());
 
+    // Rocket binds the port itself and can't take over an inherited socket, a restart within the process only starts
+    // once the previous instance has stopped and released it
+    instance.launch().await?;
+    Ok(())
+}
+
+// Set once a shutdown signal is received, a second signal then exits right away
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Stops the server gracefully. New connections are refused, the websocket clients are told to reconnect later and
+/// the open requests get `SHUTDOWN_GRACE_PERIOD` seconds to finish.
+pub fn shutdown_rocket() {
+    WS_USERS.close_all();
+    WS_ANONYMOUS_SUBSCRIPTIONS.close_all();
+    CONFIG.shutdown();
+}
+
+fn handle_shutdown_signal() {
+    if SHUTDOWN_REQUESTED.swap(true, Ordering::Relaxed) {
+        warn!("Received a second shutdown signal, exiting right away!");
+        exit(1);
+    }
+    info!("Exiting Vaultwarden! Waiting up to {} seconds for the open requests", CONFIG.shutdown_grace_period());
+    shutdown_rocket();
+}
+
+/// Finishes the work which was still pending when the server stopped: the due queued mails, the background tasks
+/// like webhooks and imports, and the SQLite write-ahead log.
+async fn finish_shutdown(pool: &db::DbPool) {
+    let grace_period = Duration::from_secs(CONFIG.shutdown_grace_period().into());
+    if tokio::time::timeout(grace_period, mail::flush_mail_queue()).await.is_err() {
+        warn!("Sending the queued mails took too long, the remaining ones are sent after the next start");
+    }
+    if !util::wait_for_background_tasks(grace_period).await {
+        warn!("Exiting while some background tasks, like webhooks or imports, are still running");
+    }
+
+    match pool.get().await {
+        Ok(mut conn) => {
+            if let Err(e) = db::checkpoint_database(&mut conn).await {
+                error!("Error checkpointing the database: {e:?}");
+            }
+        }
+        Err(e) => error!("Error getting a database connection to checkpoint the database: {e:?}"),
+    }
+}
+
+fn spawn_signal_handlers() {
     tokio::spawn(async move {
-        tokio::signal::ctrl_c().await.expect("Error setting Ctrl-C handler");
-        info!("Exiting Vaultwarden!");
-        CONFIG.shutdown();
+        loop {
+            tokio::signal::ctrl_c().await.expect("Error setting Ctrl-C handler");
+            handle_shutdown_signal();
+        }
     });
 
     #[cfg(unix)]
     {
+        tokio::spawn(async move {
+            let mut signal_term = tokio::signal::unix::signal(SignalKind::terminate()).unwrap();
+            loop {
+                let _ = signal_term.recv().await;
+                handle_shutdown_signal();
+            }
+        });
+
         tokio::spawn(async move {
             let mut signal_user1 = tokio::signal::unix::signal(SignalKind::user_defined1()).unwrap();
             loop {
@@ -630,11 +841,6 @@ async fn launch_rocket(pool: db::DbPool, extra_debug: bool) -> Result<(), Error>
             }
         });
     }
-
-    instance.launch().await?;
-
-    info!("Vaultwarden process exited!");
-    Ok(())
 }
 
 fn schedule_jobs(pool: db::DbPool) {
@@ -667,6 +873,34 @@ fn schedule_jobs(pool: db::DbPool) {
                 }));
             }
 
+            // Purge item revisions that are older than their retention.
+            if !CONFIG.cipher_history_purge_schedule().is_empty() {
+                sched.add(Job::new(CONFIG.cipher_history_purge_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::purge_cipher_history(pool.clone()));
+                }));
+            }
+
+            // Keep the icon cache below its maximum size.
+            if !CONFIG.icon_cache_eviction_schedule().is_empty() && CONFIG.icon_cache_max_size().is_some() {
+                sched.add(Job::new(CONFIG.icon_cache_eviction_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::icon_cache_eviction_job(pool.clone()));
+                }));
+            }
+
+            // Mirror the attachments and Sends to the replica storage.
+            if !CONFIG.file_replication_schedule().is_empty() && CONFIG.replica_folder().is_some() {
+                sched.add(Job::new(CONFIG.file_replication_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::file_replication_job(pool.clone()));
+                }));
+            }
+
+            // Remove the resumable attachment uploads which were abandoned, and their received chunks.
+            if !CONFIG.resumable_upload_purge_schedule().is_empty() {
+                sched.add(Job::new(CONFIG.resumable_upload_purge_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::purge_attachment_uploads(pool.clone()));
+                }));
+            }
+
             // Send email notifications about incomplete 2FA logins, which potentially
             // indicates that a user's master password has been compromised.
             if !CONFIG.incomplete_2fa_schedule().is_empty() {
@@ -706,10 +940,7 @@ fn schedule_jobs(pool: db::DbPool) {
             }
 
             // Cleanup the event table of records x days old.
-            if CONFIG.org_events_enabled()
-                && !CONFIG.event_cleanup_schedule().is_empty()
-                && CONFIG.events_days_retain().is_some()
-            {
+            if CONFIG.org_events_enabled() && !CONFIG.event_cleanup_schedule().is_empty() {
                 sched.add(Job::new(CONFIG.event_cleanup_schedule().parse().unwrap(), || {
                     runtime.spawn(api::event_cleanup_job(pool.clone()));
                 }));
@@ -722,6 +953,95 @@ fn schedule_jobs(pool: db::DbPool) {
                 }));
             }
 
+            // Purge soft-deleted users once their grace period has passed.
+            if !CONFIG.user_purge_schedule().is_empty() {
+                sched.add(Job::new(CONFIG.user_purge_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::purge_deleted_users(pool.clone()));
+                }));
+            }
+
+            // Delete archived organizations once their retention has passed.
+            if !CONFIG.org_archive_purge_schedule().is_empty() {
+                sched.add(Job::new(CONFIG.org_archive_purge_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::purge_archived_organizations(pool.clone()));
+                }));
+            }
+
+            // Cleanup the login audit table of records x days old.
+            if !CONFIG.login_event_cleanup_schedule().is_empty() && CONFIG.login_events_days_retain().is_some() {
+                sched.add(Job::new(CONFIG.login_event_cleanup_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::login_event_cleanup_job(pool.clone()));
+                }));
+            }
+
+            // Cleanup the replication change stream of records x days old.
+            if !CONFIG.replication_cleanup_schedule().is_empty() {
+                sched.add(Job::new(CONFIG.replication_cleanup_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::replication_cleanup_job(pool.clone()));
+                }));
+            }
+
+            // Sync the users of the LDAP directory.
+            if !CONFIG.ldap_sync_schedule().is_empty() && CONFIG.ldap_sync_enabled() {
+                sched.add(Job::new(CONFIG.ldap_sync_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::ldap_sync_job(pool.clone()));
+                }));
+            }
+
+            // Renew the certificate obtained with ACME once it is due.
+            if !CONFIG.acme_renew_schedule().is_empty() && CONFIG.acme_enabled() {
+                sched.add(Job::new(CONFIG.acme_renew_schedule().parse().unwrap(), || {
+                    runtime.spawn(acme::acme_renew_job());
+                }));
+            }
+
+            // Check the members of the organizations with breach monitoring against HIBP.
+            if !CONFIG.breach_check_schedule().is_empty() && CONFIG.hibp_api_key().is_some() {
+                sched.add(Job::new(CONFIG.breach_check_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::breach_check_job(pool.clone()));
+                }));
+            }
+
+            // Warn and revoke the members without two-step login of organizations with a deadline.
+            if !CONFIG.two_factor_deadline_schedule().is_empty() {
+                sched.add(Job::new(CONFIG.two_factor_deadline_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::two_factor_deadline_job(pool.clone()));
+                }));
+            }
+
+            if !CONFIG.org_domain_verification_schedule().is_empty() {
+                sched.add(Job::new(CONFIG.org_domain_verification_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::org_domain_verification_job(pool.clone()));
+                }));
+            }
+
+            if !CONFIG.daily_stats_schedule().is_empty() {
+                sched.add(Job::new(CONFIG.daily_stats_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::daily_stats_job(pool.clone()));
+                }));
+            }
+
+            // Send the queued mails, and retry the ones which failed before.
+            if !CONFIG.mail_queue_schedule().is_empty() {
+                sched.add(Job::new(CONFIG.mail_queue_schedule().parse().unwrap(), || {
+                    runtime.spawn(mail::mail_queue_job(pool.clone()));
+                }));
+            }
+
+            // Evaluate the organization reports and enable the configured policies when needed.
+            if !CONFIG.report_evaluation_schedule().is_empty() && CONFIG.report_policy_threshold().is_some() {
+                sched.add(Job::new(CONFIG.report_evaluation_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::report_evaluation_job(pool.clone()));
+                }));
+            }
+
+            // Calculate the daily risk score of the organizations.
+            if !CONFIG.risk_score_schedule().is_empty() {
+                sched.add(Job::new(CONFIG.risk_score_schedule().parse().unwrap(), || {
+                    runtime.spawn(api::risk_score_job(pool.clone()));
+                }));
+            }
+
             // Periodically check for jobs to run. We probably won't need any
             // jobs that run more often than once a minute, so a default poll
             // interval of 30 seconds should be sufficient. Users who want to
@@ -1,9 +1,14 @@
+use dashmap::DashMap;
 use once_cell::sync::Lazy;
-use std::{net::IpAddr, num::NonZeroU32, time::Duration};
+use std::{
+    net::IpAddr,
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
 
 use governor::{clock::DefaultClock, state::keyed::DashMapStateStore, Quota, RateLimiter};
 
-use crate::{Error, CONFIG};
+use crate::{db::models::UserId, Error, CONFIG};
 
 type Limiter<T = IpAddr> = RateLimiter<T, DashMapStateStore<T>, DefaultClock>;
 
@@ -19,6 +24,19 @@ static LIMITER_ADMIN: Lazy<Limiter> = Lazy::new(|| {
     RateLimiter::keyed(Quota::with_period(seconds).expect("Non-zero admin ratelimit seconds").allow_burst(burst))
 });
 
+static LIMITER_REGISTER: Lazy<Limiter> = Lazy::new(|| {
+    let seconds = Duration::from_secs(CONFIG.register_ratelimit_seconds());
+    let burst = NonZeroU32::new(CONFIG.register_ratelimit_max_burst()).expect("Non-zero register ratelimit burst");
+    RateLimiter::keyed(Quota::with_period(seconds).expect("Non-zero register ratelimit seconds").allow_burst(burst))
+});
+
+static LIMITER_FINGERPRINT: Lazy<Limiter> = Lazy::new(|| {
+    let seconds = Duration::from_secs(CONFIG.fingerprint_ratelimit_seconds());
+    let burst =
+        NonZeroU32::new(CONFIG.fingerprint_ratelimit_max_burst()).expect("Non-zero fingerprint ratelimit burst");
+    RateLimiter::keyed(Quota::with_period(seconds).expect("Non-zero fingerprint ratelimit seconds").allow_burst(burst))
+});
+
 pub fn check_limit_login(ip: &IpAddr) -> Result<(), Error> {
     match LIMITER_LOGIN.check_key(ip) {
         Ok(_) => Ok(()),
@@ -36,3 +54,216 @@ pub fn check_limit_admin(ip: &IpAddr) -> Result<(), Error> {
         }
     }
 }
+
+pub fn check_limit_register(ip: &IpAddr) -> Result<(), Error> {
+    match LIMITER_REGISTER.check_key(ip) {
+        Ok(_) => Ok(()),
+        Err(_e) => {
+            err_code!("Too many registration requests", 429);
+        }
+    }
+}
+
+pub fn check_limit_fingerprint(ip: &IpAddr) -> Result<(), Error> {
+    match LIMITER_FINGERPRINT.check_key(ip) {
+        Ok(_) => Ok(()),
+        Err(_e) => {
+            err_code!("Too many fingerprint requests", 429);
+        }
+    }
+}
+
+// The IP addresses which used up the admin rate limit with failed requests to the admin API, and until when
+static ADMIN_API_BLOCKED: Lazy<DashMap<IpAddr, Instant>> = Lazy::new(DashMap::new);
+
+pub fn check_admin_api_blocked(ip: &IpAddr) -> Result<(), Error> {
+    if ADMIN_API_BLOCKED.get(ip).is_some_and(|until| *until > Instant::now()) {
+        err_code!("Too many admin requests", 429);
+    }
+    Ok(())
+}
+
+/// Counts a failed request to the admin API against the admin rate limit. Once that is used up,
+/// the IP address is blocked for `ADMIN_RATELIMIT_SECONDS`.
+pub fn register_failed_admin_api(ip: &IpAddr) {
+    if check_limit_admin(ip).is_err() {
+        let now = Instant::now();
+        // Forget the expired blocks once in a while, so the map doesn't grow without a bound
+        if ADMIN_API_BLOCKED.len() > 10_000 {
+            ADMIN_API_BLOCKED.retain(|_, until| *until > now);
+        }
+        ADMIN_API_BLOCKED.insert(*ip, now + Duration::from_secs(CONFIG.admin_ratelimit_seconds()));
+    }
+}
+
+// Consecutive failed logins of an account or from an IP address, they are forgotten after `LOGIN_LOCKOUT_MINUTES`
+// without another failure, or once the account logged in successfully.
+struct LoginFailures {
+    count: u32,
+    last_failure: Instant,
+}
+
+impl LoginFailures {
+    fn is_expired(&self) -> bool {
+        self.last_failure.elapsed() >= Duration::from_secs(CONFIG.login_lockout_minutes() * 60)
+    }
+
+    /// The seconds until the next login attempt is allowed. Accounts are delayed progressively before they are locked,
+    /// IP addresses are only locked.
+    fn wait_seconds(&self, limits: &LoginLimits) -> u64 {
+        let wait = if limits.lockout_failures > 0 && self.count >= limits.lockout_failures {
+            limits.lockout_seconds
+        } else {
+            let delay_after = limits.delay_after_failures;
+            if delay_after == 0 || self.count < delay_after {
+                return 0;
+            }
+            // Starts at 1 second and doubles with every further failure
+            (1u64 << (self.count - delay_after).min(16)).min(limits.delay_max_seconds)
+        };
+        wait.saturating_sub(self.last_failure.elapsed().as_secs())
+    }
+}
+
+// The limits of the config for the failed logins of an account or from an IP address
+struct LoginLimits {
+    lockout_failures: u32,
+    lockout_seconds: u64,
+    // 0 when there are no delays before the lockout
+    delay_after_failures: u32,
+    delay_max_seconds: u64,
+}
+
+impl LoginLimits {
+    fn user() -> Self {
+        Self {
+            lockout_failures: CONFIG.login_lockout_failures(),
+            lockout_seconds: CONFIG.login_lockout_minutes() * 60,
+            delay_after_failures: CONFIG.login_delay_after_failures(),
+            delay_max_seconds: CONFIG.login_delay_max_seconds(),
+        }
+    }
+
+    fn ip() -> Self {
+        Self {
+            lockout_failures: CONFIG.login_lockout_ip_failures(),
+            delay_after_failures: 0,
+            ..Self::user()
+        }
+    }
+}
+
+static LOGIN_FAILURES_BY_USER: Lazy<DashMap<UserId, LoginFailures>> = Lazy::new(DashMap::new);
+static LOGIN_FAILURES_BY_IP: Lazy<DashMap<IpAddr, LoginFailures>> = Lazy::new(DashMap::new);
+
+fn too_many_failures(wait: u64) -> Result<(), Error> {
+    if wait > 0 {
+        err_code!(format!("Too many failed login attempts. Try again in {wait} seconds"), 429);
+    }
+    Ok(())
+}
+
+pub fn check_login_failures_ip(ip: &IpAddr) -> Result<(), Error> {
+    let wait = LOGIN_FAILURES_BY_IP.get(ip).map_or(0, |f| f.wait_seconds(&LoginLimits::ip()));
+    too_many_failures(wait)
+}
+
+pub fn check_login_failures_user(user_id: &UserId) -> Result<(), Error> {
+    too_many_failures(login_lockout_seconds(user_id))
+}
+
+/// The seconds the account still has to wait before it can try to log in again.
+pub fn login_lockout_seconds(user_id: &UserId) -> u64 {
+    LOGIN_FAILURES_BY_USER.get(user_id).map_or(0, |f| f.wait_seconds(&LoginLimits::user()))
+}
+
+/// Records a failed login of the account, when it is known, and of the IP address.
+/// Returns the number of consecutive failed logins of the account.
+pub fn register_failed_login(user_id: Option<&UserId>, ip: &IpAddr) -> u32 {
+    fn register<K: std::hash::Hash + Eq>(failures: &DashMap<K, LoginFailures>, key: K) -> u32 {
+        // Forget the expired failures once in a while, so the maps don't grow without a bound
+        if failures.len() > 10_000 {
+            failures.retain(|_, f| !f.is_expired());
+        }
+
+        let mut entry = failures.entry(key).or_insert(LoginFailures {
+            count: 0,
+            last_failure: Instant::now(),
+        });
+        if entry.is_expired() {
+            entry.count = 0;
+        }
+        entry.count += 1;
+        entry.last_failure = Instant::now();
+        entry.count
+    }
+
+    register(&LOGIN_FAILURES_BY_IP, *ip);
+    user_id.map_or(0, |user_id| register(&LOGIN_FAILURES_BY_USER, user_id.clone()))
+}
+
+/// Forgets the failed logins of the account, after a successful login or when an admin clears the lockout.
+/// The failures of the IP address are kept, otherwise a valid account could be used to reset them.
+pub fn clear_failed_logins(user_id: &UserId) {
+    LOGIN_FAILURES_BY_USER.remove(user_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: LoginLimits = LoginLimits {
+        lockout_failures: 10,
+        lockout_seconds: 900,
+        delay_after_failures: 3,
+        delay_max_seconds: 60,
+    };
+
+    fn failures(count: u32, seconds_ago: u64) -> LoginFailures {
+        LoginFailures {
+            count,
+            last_failure: Instant::now().checked_sub(Duration::from_secs(seconds_ago)).unwrap(),
+        }
+    }
+
+    #[test]
+    fn wait_seconds_doubles_the_delay() {
+        assert_eq!(failures(2, 0).wait_seconds(&LIMITS), 0);
+        assert_eq!(failures(3, 0).wait_seconds(&LIMITS), 1);
+        assert_eq!(failures(4, 0).wait_seconds(&LIMITS), 2);
+        assert_eq!(failures(5, 0).wait_seconds(&LIMITS), 4);
+        assert_eq!(failures(8, 0).wait_seconds(&LIMITS), 32);
+        // Capped at the maximum delay until the lockout
+        assert_eq!(failures(9, 0).wait_seconds(&LIMITS), 60);
+    }
+
+    #[test]
+    fn wait_seconds_locks_after_the_failures() {
+        assert_eq!(failures(10, 0).wait_seconds(&LIMITS), 900);
+        assert_eq!(failures(25, 0).wait_seconds(&LIMITS), 900);
+
+        let no_lockout = LoginLimits {
+            lockout_failures: 0,
+            ..LIMITS
+        };
+        assert_eq!(failures(100, 0).wait_seconds(&no_lockout), 60);
+    }
+
+    #[test]
+    fn wait_seconds_only_locks_without_delays() {
+        let ip_limits = LoginLimits {
+            lockout_failures: 50,
+            delay_after_failures: 0,
+            ..LIMITS
+        };
+        assert_eq!(failures(49, 0).wait_seconds(&ip_limits), 0);
+        assert_eq!(failures(50, 0).wait_seconds(&ip_limits), 900);
+    }
+
+    #[test]
+    fn wait_seconds_counts_from_the_last_failure() {
+        assert_eq!(failures(8, 30).wait_seconds(&LIMITS), 2);
+        assert_eq!(failures(8, 40).wait_seconds(&LIMITS), 0);
+        assert_eq!(failures(10, 600).wait_seconds(&LIMITS), 300);
+    }
+}
@@ -0,0 +1,312 @@
+//
+// Signed exports of the organization reports, for auditors who need a tamper-evident copy instead of raw JSON.
+// Both formats are signed with a detached PKCS#7 signature made with the report signing key
+// (`REPORT_SIGNING_KEY_FILENAME`), see `auth::sign_report`.
+//
+use chrono::NaiveDateTime;
+use data_encoding::{BASE64, HEXUPPER};
+
+use crate::{auth::sign_report, db::models::ReportHistory, error::Error};
+
+pub struct ExportMember {
+    pub email: String,
+    pub name: String,
+    pub exposed_count: i32,
+    pub weak_count: i32,
+    pub reused_count: i32,
+    pub updated_at: NaiveDateTime,
+}
+
+pub struct ReportExport {
+    pub org_name: String,
+    pub org_id: String,
+    pub generated_at: NaiveDateTime,
+    pub exposed_count: i32,
+    pub weak_count: i64,
+    pub reused_count: i64,
+    pub members: Vec<ExportMember>,
+    pub history: Vec<ReportHistory>,
+}
+
+fn format_datetime(dt: &NaiveDateTime) -> String {
+    dt.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+//
+// CSV
+//
+const CSV_HEADER: &str = "section,date,email,name,exposed,weak,reused";
+
+/// Quotes the field when needed, and defuses values a spreadsheet would run as a formula.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+impl ReportExport {
+    /// One row per line, the `section` column tells the summary, member and history rows apart.
+    /// The last line is `signature,<base64>`, the signature of all the bytes before that line.
+    pub fn to_csv(&self) -> Result<Vec<u8>, Error> {
+        let mut rows = vec![CSV_HEADER.to_string()];
+        rows.push(format!(
+            "summary,{},,{},{},{},{}",
+            format_datetime(&self.generated_at),
+            csv_field(&self.org_name),
+            self.exposed_count,
+            self.weak_count,
+            self.reused_count
+        ));
+        for member in &self.members {
+            rows.push(format!(
+                "member,{},{},{},{},{},{}",
+                format_datetime(&member.updated_at),
+                csv_field(&member.email),
+                csv_field(&member.name),
+                member.exposed_count,
+                member.weak_count,
+                member.reused_count
+            ));
+        }
+        for day in &self.history {
+            rows.push(format!(
+                "history,{},,,{},{},{}",
+                day.day.format("%Y-%m-%d"),
+                day.exposed_count,
+                day.weak_count,
+                day.reused_count
+            ));
+        }
+
+        let mut csv = rows.join("\r\n").into_bytes();
+        csv.extend_from_slice(b"\r\n");
+        let signature = sign_report(&csv)?;
+        csv.extend_from_slice(format!("signature,{}\r\n", BASE64.encode(&signature)).as_bytes());
+        Ok(csv)
+    }
+}
+
+//
+// PDF
+//
+// A4 in points, the text is set in Courier so the columns line up without measuring the text
+const PAGE_WIDTH: usize = 595;
+const PAGE_HEIGHT: usize = 842;
+const MARGIN: usize = 50;
+const LINE_HEIGHT: usize = 14;
+const LINES_PER_PAGE: usize = (PAGE_HEIGHT - 2 * MARGIN) / LINE_HEIGHT - 2;
+const LINE_CHARS: usize = 82;
+// Room for the DER encoded PKCS#7 signature, which is written as hex in the signature dictionary
+const SIGNATURE_SIZE: usize = 8192;
+const BYTE_RANGE_PLACEHOLDER: &str = "[0 0000000000 0000000000 0000000000]";
+
+enum Line {
+    Heading(String),
+    Text(String),
+}
+
+/// Escapes a PDF literal string, characters outside of ASCII can't be shown by the standard fonts.
+fn pdf_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('(');
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            _ => escaped.push('?'),
+        }
+    }
+    escaped.push(')');
+    escaped
+}
+
+fn fit(text: &str, width: usize) -> String {
+    if text.chars().count() > width {
+        format!("{}~", text.chars().take(width - 1).collect::<String>())
+    } else {
+        format!("{text:<width$}")
+    }
+}
+
+struct PdfWriter {
+    buffer: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl PdfWriter {
+    fn new() -> Self {
+        let mut buffer = b"%PDF-1.7\n".to_vec();
+        // Marks the file as binary for the tools which check this
+        buffer.extend_from_slice(b"%\xE2\xE3\xCF\xD3\n");
+        Self {
+            buffer,
+            offsets: Vec::new(),
+        }
+    }
+
+    /// Writes the object with the given number, the objects have to be written in order.
+    fn object(&mut self, id: usize, body: &[u8]) {
+        debug_assert_eq!(id, self.offsets.len() + 1);
+        self.offsets.push(self.buffer.len());
+        self.buffer.extend_from_slice(format!("{id} 0 obj\n").as_bytes());
+        self.buffer.extend_from_slice(body);
+        self.buffer.extend_from_slice(b"\nendobj\n");
+    }
+
+    fn finish(mut self, root: usize) -> Vec<u8> {
+        let xref = self.buffer.len();
+        let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            trailer.push_str(&format!("{offset:010} 00000 n \n"));
+        }
+        trailer.push_str(&format!(
+            "trailer\n<< /Size {} /Root {root} 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            self.offsets.len() + 1
+        ));
+        self.buffer.extend_from_slice(trailer.as_bytes());
+        self.buffer
+    }
+}
+
+impl ReportExport {
+    fn pdf_lines(&self) -> Vec<Line> {
+        let mut lines = vec![
+            Line::Heading(format!("Vault health report: {}", self.org_name)),
+            Line::Text(format!("Organization: {}", self.org_id)),
+            Line::Text(format!("Generated:    {}", format_datetime(&self.generated_at))),
+            Line::Text(String::new()),
+            Line::Text(format!("Exposed passwords: {}", self.exposed_count)),
+            Line::Text(format!("Weak passwords:    {}", self.weak_count)),
+            Line::Text(format!("Reused passwords:  {}", self.reused_count)),
+            Line::Text(String::new()),
+            Line::Heading("Findings per member".to_string()),
+            Line::Text(format!("{}{:>8}{:>8}{:>8}  {}", fit("Member", 38), "Exposed", "Weak", "Reused", "Updated")),
+        ];
+        if self.members.is_empty() {
+            lines.push(Line::Text("No findings were reported for the members.".to_string()));
+        }
+        for member in &self.members {
+            lines.push(Line::Text(format!(
+                "{}{:>8}{:>8}{:>8}  {}",
+                fit(&member.email, 38),
+                member.exposed_count,
+                member.weak_count,
+                member.reused_count,
+                member.updated_at.format("%Y-%m-%d")
+            )));
+        }
+
+        lines.push(Line::Text(String::new()));
+        lines.push(Line::Heading("History".to_string()));
+        lines.push(Line::Text(format!("{}{:>8}{:>8}{:>8}", fit("Date", 38), "Exposed", "Weak", "Reused")));
+        if self.history.is_empty() {
+            lines.push(Line::Text("No history was recorded during this period.".to_string()));
+        }
+        for day in &self.history {
+            lines.push(Line::Text(format!(
+                "{}{:>8}{:>8}{:>8}",
+                fit(&day.day.format("%Y-%m-%d").to_string(), 38),
+                day.exposed_count,
+                day.weak_count,
+                day.reused_count
+            )));
+        }
+        lines
+    }
+
+    /// A PDF with an embedded `adbe.pkcs7.detached` signature, which PDF readers validate.
+    /// The signing certificate is self-signed, so readers show the signer as unknown until it is trusted.
+    pub fn to_pdf(&self) -> Result<Vec<u8>, Error> {
+        let lines = self.pdf_lines();
+        let pages: Vec<&[Line]> = lines.chunks(LINES_PER_PAGE).collect();
+        let page_count = pages.len();
+
+        // 1: catalog, 2: pages, 3 and 4: fonts, 5: signature field, 6: signature, then a page and its content each
+        let page_id = |index: usize| 7 + 2 * index;
+        let mut pdf = PdfWriter::new();
+        pdf.object(1, b"<< /Type /Catalog /Pages 2 0 R /AcroForm << /Fields [5 0 R] /SigFlags 3 >> >>");
+        let kids = (0..page_count).map(|i| format!("{} 0 R", page_id(i))).collect::<Vec<_>>().join(" ");
+        pdf.object(2, format!("<< /Type /Pages /Kids [{kids}] /Count {page_count} >>").as_bytes());
+        pdf.object(3, b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>");
+        pdf.object(4, b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>");
+        pdf.object(
+            5,
+            format!(
+                "<< /Type /Annot /Subtype /Widget /FT /Sig /T (Signature) /V 6 0 R /Rect [0 0 0 0] /F 132 /P {} 0 R >>",
+                page_id(0)
+            )
+            .as_bytes(),
+        );
+
+        let signature_object = format!(
+            "<< /Type /Sig /Filter /Adobe.PPKLite /SubFilter /adbe.pkcs7.detached /Name (Vaultwarden) \
+             /M (D:{}Z) /Reason (Vault health report export) /ByteRange {BYTE_RANGE_PLACEHOLDER} /Contents <{}> >>",
+            self.generated_at.format("%Y%m%d%H%M%S"),
+            "0".repeat(2 * SIGNATURE_SIZE)
+        );
+        let signature_offset = pdf.buffer.len() + "6 0 obj\n".len();
+        pdf.object(6, signature_object.as_bytes());
+        let byte_range_start = signature_offset + signature_object.find(BYTE_RANGE_PLACEHOLDER).unwrap_or_default();
+        let contents_start = signature_offset + signature_object.rfind('<').unwrap_or_default();
+        let contents_end = contents_start + 2 * SIGNATURE_SIZE + 2;
+
+        for (index, page_lines) in pages.iter().enumerate() {
+            let mut content = String::new();
+            let mut y = PAGE_HEIGHT - MARGIN;
+            for line in page_lines.iter() {
+                let (font, size, text) = match line {
+                    Line::Heading(text) => ("F1", 13, text.clone()),
+                    Line::Text(text) => ("F2", 10, text.chars().take(LINE_CHARS).collect()),
+                };
+                content.push_str(&format!("BT /{font} {size} Tf {MARGIN} {y} Td {} Tj ET\n", pdf_string(&text)));
+                y -= LINE_HEIGHT;
+            }
+            let footer = format!("Page {} of {page_count} - signed by the Vaultwarden server", index + 1);
+            content.push_str(&format!("BT /F2 8 Tf {MARGIN} {} Td {} Tj ET\n", MARGIN / 2, pdf_string(&footer)));
+
+            let annots = if index == 0 {
+                " /Annots [5 0 R]"
+            } else {
+                ""
+            };
+            pdf.object(
+                page_id(index),
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {PAGE_WIDTH} {PAGE_HEIGHT}] \
+                     /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R{annots} >>",
+                    page_id(index) + 1
+                )
+                .as_bytes(),
+            );
+            pdf.object(
+                page_id(index) + 1,
+                format!("<< /Length {} >>\nstream\n{content}endstream", content.len()).as_bytes(),
+            );
+        }
+        let mut buffer = pdf.finish(1);
+
+        // The signature covers the whole file except the hex string of the signature itself
+        let byte_range = format!("[0 {contents_start:010} {contents_end:010} {:010}]", buffer.len() - contents_end);
+        buffer[byte_range_start..byte_range_start + byte_range.len()].copy_from_slice(byte_range.as_bytes());
+        let mut signed = buffer[..contents_start].to_vec();
+        signed.extend_from_slice(&buffer[contents_end..]);
+        let signature = sign_report(&signed)?;
+        if signature.len() > SIGNATURE_SIZE {
+            err!("The report signature is too large")
+        }
+        let hex = HEXUPPER.encode(&signature);
+        buffer[contents_start + 1..contents_start + 1 + hex.len()].copy_from_slice(hex.as_bytes());
+
+        Ok(buffer)
+    }
+}
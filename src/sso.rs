@@ -362,8 +362,7 @@ pub fn create_auth_tokens(
             _ => err!("Non jwt access_token and empty expires_in"),
         };
 
-        let access_claims =
-            auth::LoginJwtClaims::new(device, user, ap_nbf, ap_exp, AuthMethod::Sso.scope_vec(), client_id, now);
+        let access_claims = auth::LoginJwtClaims::new(device, user, ap_nbf, ap_exp, &AuthMethod::Sso, client_id, now);
 
         _create_auth_tokens(device, refresh_token, access_claims, access_token)
     } else {
@@ -402,6 +401,9 @@ fn _create_auth_tokens(
         sub: AuthMethod::Sso,
         device_token: device.refresh_token.clone(),
         token: Some(token),
+        family: None,
+        generation: None,
+        auth_time: Some(Utc::now().timestamp()),
     };
 
     Ok(AuthTokens {
@@ -445,15 +447,8 @@ pub async fn exchange_refresh_token(
 
             Client::check_validity(access_token.clone()).await?;
 
-            let access_claims = auth::LoginJwtClaims::new(
-                device,
-                user,
-                now.timestamp(),
-                exp,
-                AuthMethod::Sso.scope_vec(),
-                client_id,
-                now,
-            );
+            let access_claims =
+                auth::LoginJwtClaims::new(device, user, now.timestamp(), exp, &AuthMethod::Sso, client_id, now);
 
             _create_auth_tokens(device, None, access_claims, access_token)
         }
@@ -1,6 +1,6 @@
 "use strict";
 /* eslint-env es2017, browser */
-/* global BASE_URL:readable, bootstrap:readable */
+/* global _post:readable, _delete:readable, BASE_URL:readable, bootstrap:readable */
 
 var dnsCheck = false;
 var timeCheck = false;
@@ -278,6 +278,116 @@ function checkSecurityHeaders(headers, omit) {
     return messages;
 }
 
+// ================================
+// Daily statistics charts
+const statsCharts = [
+    ["users", "Users"],
+    ["activeUsers", "Active users (30 days)"],
+    ["ciphers", "Items"],
+    ["attachmentsSize", "Attachment storage (MiB)", (v) => Math.round(v / 1048576)],
+    ["exposedPersonal", "Exposed passwords (personal)"],
+    ["exposedOrg", "Exposed passwords (organizations)"],
+];
+
+function drawStatsChart(title, dates, values) {
+    const width = 300, height = 80;
+    const max = Math.max(...values), min = Math.min(...values);
+    const range = (max - min) || 1;
+    const step = values.length > 1 ? width / (values.length - 1) : 0;
+    const points = values.map((v, i) => `${(i * step).toFixed(1)},${(height - ((v - min) / range) * (height - 4) - 2).toFixed(1)}`);
+
+    const col = document.createElement("div");
+    col.className = "col";
+    col.innerHTML = `<div class="border rounded p-2">
+        <div class="d-flex justify-content-between"><b></b><span></span></div>
+        <svg viewBox="0 0 ${width} ${height}" preserveAspectRatio="none" class="w-100" style="height: ${height}px">
+            <polyline fill="none" stroke="currentColor" stroke-width="2" vector-effect="non-scaling-stroke" points="${points.join(" ")}"/>
+        </svg>
+        <div class="d-flex justify-content-between small text-muted"><span></span><span></span></div>
+    </div>`;
+    col.querySelector("b").textContent = title;
+    col.querySelector(".d-flex span").textContent = values[values.length - 1];
+    const [first, last] = col.querySelectorAll(".small span");
+    first.textContent = dates[0];
+    last.textContent = dates[dates.length - 1];
+    return col;
+}
+
+async function loadStats() {
+    const response = await fetch(`${BASE_URL}/admin/api/stats?days=90`);
+    if (!response.ok) {
+        return;
+    }
+    const stats = (await response.json()).data;
+    if (stats.length === 0) {
+        document.getElementById("stats-empty").classList.remove("d-none");
+        return;
+    }
+
+    const container = document.getElementById("stats-charts");
+    const dates = stats.map((s) => s.date);
+    statsCharts.forEach(([key, title, convert = (v) => v]) => {
+        container.appendChild(drawStatsChart(title, dates, stats.map((s) => convert(s[key]))));
+    });
+}
+
+function retryQueuedMail(event) {
+    event.preventDefault();
+    event.stopPropagation();
+    _post(`${BASE_URL}/admin/mail-queue/${event.target.dataset.vwMailId}/retry`,
+        "The mail will be sent by the next run of the mail queue job",
+        "Error retrying the mail"
+    );
+}
+
+function deleteQueuedMail(event) {
+    event.preventDefault();
+    event.stopPropagation();
+    const confirmed = confirm("Are you sure you want to delete this mail from the queue?");
+    if (confirmed) {
+        _delete(`${BASE_URL}/admin/mail-queue/${event.target.dataset.vwMailId}`,
+            "Mail deleted",
+            "Error deleting the mail"
+        );
+    }
+}
+
+async function loadMailQueue() {
+    const status = document.getElementById("mail-queue-status");
+    try {
+        const resp = await fetch(`${BASE_URL}/admin/mail-queue`);
+        if (!resp.ok) {
+            throw new Error(resp.statusText);
+        }
+        const queue = await resp.json();
+        status.textContent = `${queue.enabled ? "Enabled" : "Disabled"}, ${queue.pending} mail(s) waiting to be sent, ${queue.failed.length} failed.`;
+
+        const list = document.getElementById("mail-queue-failed-list");
+        queue.failed.forEach((mail) => {
+            const row = document.createElement("tr");
+            row.innerHTML = `<td></td><td></td><td></td><td></td><td class="text-break"></td>
+                <td class="vw-actions">
+                    <button type="button" class="btn btn-sm btn-link p-0 border-0" vw-retry-mail>Retry</button>
+                    <button type="button" class="btn btn-sm btn-link p-0 border-0" vw-delete-mail>Delete</button>
+                </td>`;
+            const cells = row.querySelectorAll("td");
+            [mail.failedDate, mail.address, mail.subject, mail.attempts, mail.lastError].forEach((value, i) => {
+                cells[i].textContent = value ?? "";
+            });
+            row.querySelector("[vw-retry-mail]").dataset.vwMailId = mail.id;
+            row.querySelector("[vw-retry-mail]").addEventListener("click", retryQueuedMail);
+            row.querySelector("[vw-delete-mail]").dataset.vwMailId = mail.id;
+            row.querySelector("[vw-delete-mail]").addEventListener("click", deleteQueuedMail);
+            list.appendChild(row);
+        });
+        if (queue.failed.length > 0) {
+            document.getElementById("mail-queue-failed").classList.remove("d-none");
+        }
+    } catch (err) {
+        status.textContent = `Error loading the mail queue: ${err.message}`;
+    }
+}
+
 async function checkHttpResponse() {
     const [apiConfig, webauthnConnector, notFound, notFoundApi, badRequest, unauthorized, forbidden] = await Promise.all([
         fetchCheckUrl(`${BASE_URL}/api/config`),
@@ -410,6 +520,10 @@ function init(dj) {
 
     checkHttpResponse();
 
+    loadStats();
+
+    loadMailQueue();
+
     if (dj.enable_websocket) {
         checkWebsocketConnection();
     }
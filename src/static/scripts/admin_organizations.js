@@ -30,10 +30,103 @@ function deleteOrganization(event) {
     }
 }
 
+function setOrganizationSeats(event) {
+    event.preventDefault();
+    event.stopPropagation();
+    const org_uuid = event.target.dataset.vwOrgUuid;
+    const org_name = event.target.dataset.vwOrgName;
+    if (!org_uuid) {
+        alert("Required parameters not found!");
+        return false;
+    }
+
+    const input_seats = prompt(`Seat limit for "${org_name}".\nLeave empty to use the global limit.`, event.target.dataset.vwMaxSeats);
+    if (input_seats == null) {
+        return false;
+    }
+    const max_seats = input_seats.trim() === "" ? null : parseInt(input_seats, 10);
+    if (max_seats !== null && (isNaN(max_seats) || max_seats < 1)) {
+        alert("The seat limit must be a number of at least 1");
+        return false;
+    }
+    _post(`${BASE_URL}/admin/organizations/${org_uuid}/seats`,
+        "Seat limit updated correctly",
+        "Error updating the seat limit",
+        JSON.stringify({ "max_seats": max_seats })
+    );
+}
+
+function archiveOrganization(event) {
+    event.preventDefault();
+    event.stopPropagation();
+    const org_uuid = event.target.dataset.vwOrgUuid;
+    const org_name = event.target.dataset.vwOrgName;
+    const archived = event.target.dataset.vwArchived === "true";
+    if (!org_uuid) {
+        alert("Required parameters not found!");
+        return false;
+    }
+
+    const message = archived
+        ? `Archive "${org_name}"?\nIts members can't use or change its items until it is unarchived, and it is deleted once the archive retention passed.`
+        : `Unarchive "${org_name}"?\nIts members can use its items again.`;
+    if (confirm(message)) {
+        _post(`${BASE_URL}/admin/organizations/${org_uuid}/archive`,
+            archived ? "Organization archived correctly" : "Organization unarchived correctly",
+            archived ? "Error archiving the organization" : "Error unarchiving the organization",
+            JSON.stringify({ "archived": archived })
+        );
+    }
+}
+
+function updateOrganizationInviteText(event) {
+    event.preventDefault();
+    event.stopPropagation();
+    const org_uuid = document.getElementById("orgInviteTextOrgUuid").value;
+    if (!org_uuid) {
+        alert("Required parameters not found!");
+        return false;
+    }
+
+    const data = {
+        "invite_subject": document.getElementById("orgInviteTextSubject").value,
+        "invite_reply_to": document.getElementById("orgInviteTextReplyTo").value,
+        "invite_body": document.getElementById("orgInviteTextBody").value,
+    };
+    _post(`${BASE_URL}/admin/organizations/${org_uuid}/invite-text`,
+        "Invitation text updated correctly",
+        "Error updating the invitation text",
+        JSON.stringify(data)
+    );
+}
+
+const orgInviteTextDialog = document.getElementById("orgInviteTextDialog");
+// Fill the form with the current text of the organization
+orgInviteTextDialog.addEventListener("show.bs.modal", function(event) {
+    const dataset = event.relatedTarget.dataset;
+    document.getElementById("orgInviteTextDialogOrgName").textContent = dataset.vwOrgName;
+    document.getElementById("orgInviteTextOrgUuid").value = dataset.vwOrgUuid;
+    document.getElementById("orgInviteTextSubject").value = dataset.vwInviteSubject;
+    document.getElementById("orgInviteTextReplyTo").value = dataset.vwInviteReplyTo;
+    document.getElementById("orgInviteTextBody").value = dataset.vwInviteBody;
+}, false);
+
+// Prevent accidental submission of the form with valid elements after the modal has been hidden.
+orgInviteTextDialog.addEventListener("hide.bs.modal", function() {
+    document.getElementById("orgInviteTextDialogOrgName").textContent = "";
+    document.getElementById("orgInviteTextOrgUuid").value = "";
+}, false);
+
 function initActions() {
     document.querySelectorAll("button[vw-delete-organization]").forEach(btn => {
         btn.addEventListener("click", deleteOrganization);
     });
+    document.querySelectorAll("button[vw-set-org-seats]").forEach(btn => {
+        btn.addEventListener("click", setOrganizationSeats);
+    });
+    document.querySelectorAll("button[vw-archive-organization]").forEach(btn => {
+        btn.addEventListener("click", archiveOrganization);
+    });
 
     if (jdenticon) {
         jdenticon();
@@ -63,6 +156,11 @@ document.addEventListener("DOMContentLoaded", (/*event*/) => {
     // Add click events for organization actions
     initActions();
 
+    const orgInviteTextForm = document.getElementById("orgInviteTextForm");
+    if (orgInviteTextForm) {
+        orgInviteTextForm.addEventListener("submit", updateOrganizationInviteText);
+    }
+
     const btnReload = document.getElementById("reload");
     if (btnReload) {
         btnReload.addEventListener("click", reload);
@@ -0,0 +1,92 @@
+"use strict";
+/* eslint-env es2017, browser */
+/* global BASE_URL:readable */
+
+function fromBase64Url(value) {
+    const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+    return Uint8Array.from(atob(base64), c => c.charCodeAt(0));
+}
+
+function toBase64Url(buffer) {
+    const bytes = String.fromCharCode(...new Uint8Array(buffer));
+    return btoa(bytes).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+}
+
+async function fetchChallenge(path) {
+    const resp = await fetch(`${BASE_URL}/admin/two-factor/${path}`, {
+        method: "POST",
+        mode: "same-origin",
+        credentials: "same-origin",
+        headers: { "Content-Type": "application/json" }
+    });
+    const json = await resp.json();
+    if (!resp.ok) {
+        throw new Error(json.errorModel && json.errorModel.message ? json.errorModel.message : resp.statusText);
+    }
+    return json;
+}
+
+// The response is posted as json with the field names the server side expects
+async function registerSecurityKey(event) {
+    event.preventDefault();
+    const form = document.getElementById("webauthn-setup-form");
+    try {
+        const options = await fetchChallenge("webauthn-challenge");
+        options.challenge = fromBase64Url(options.challenge);
+        options.user.id = fromBase64Url(options.user.id);
+        (options.excludeCredentials || []).forEach(c => c.id = fromBase64Url(c.id));
+
+        const credential = await navigator.credentials.create({ publicKey: options });
+        form.elements["webauthn"].value = JSON.stringify({
+            id: credential.id,
+            rawId: toBase64Url(credential.rawId),
+            type: credential.type,
+            response: {
+                attestationObject: toBase64Url(credential.response.attestationObject),
+                clientDataJson: toBase64Url(credential.response.clientDataJSON)
+            }
+        });
+        form.submit();
+    } catch (e) {
+        alert(`Error registering the security key\n${e.message}`);
+    }
+}
+
+async function useSecurityKey(event) {
+    event.preventDefault();
+    const form = document.getElementById("webauthn-login-form");
+    try {
+        const options = await fetchChallenge("webauthn-login");
+        options.challenge = fromBase64Url(options.challenge);
+        (options.allowCredentials || []).forEach(c => c.id = fromBase64Url(c.id));
+
+        const assertion = await navigator.credentials.get({ publicKey: options });
+        form.elements["webauthn"].value = JSON.stringify({
+            id: assertion.id,
+            rawId: toBase64Url(assertion.rawId),
+            type: assertion.type,
+            extensions: {},
+            response: {
+                authenticatorData: toBase64Url(assertion.response.authenticatorData),
+                clientDataJson: toBase64Url(assertion.response.clientDataJSON),
+                signature: toBase64Url(assertion.response.signature),
+                userHandle: assertion.response.userHandle ? toBase64Url(assertion.response.userHandle) : null
+            }
+        });
+        form.submit();
+    } catch (e) {
+        alert(`Error using the security key\n${e.message}`);
+    }
+}
+
+// onLoad events
+document.addEventListener("DOMContentLoaded", (/*event*/) => {
+    const setup = document.getElementById("webauthn-setup");
+    if (setup) {
+        setup.addEventListener("click", registerSecurityKey);
+    }
+    const login = document.getElementById("webauthn-login");
+    if (login) {
+        login.addEventListener("click", useSecurityKey);
+    }
+});
@@ -24,6 +24,24 @@ function deleteUser(event) {
     }
 }
 
+function restoreUser(event) {
+    event.preventDefault();
+    event.stopPropagation();
+    const id = event.target.parentNode.dataset.vwUserUuid;
+    const email = event.target.parentNode.dataset.vwUserEmail;
+    if (!id || !email) {
+        alert("Required parameters not found!");
+        return false;
+    }
+    const confirmed = confirm(`Are you sure you want to restore user "${email}"?`);
+    if (confirmed) {
+        _post(`${BASE_URL}/admin/users/${id}/restore`,
+            "User restored successfully",
+            "Error restoring user"
+        );
+    }
+}
+
 function deleteSSOUser(event) {
     event.preventDefault();
     event.stopPropagation();
@@ -82,6 +100,42 @@ function deauthUser(event) {
     }
 }
 
+function unlockUser(event) {
+    event.preventDefault();
+    event.stopPropagation();
+    const id = event.target.parentNode.dataset.vwUserUuid;
+    const email = event.target.parentNode.dataset.vwUserEmail;
+    if (!id || !email) {
+        alert("Required parameters not found!");
+        return false;
+    }
+    const confirmed = confirm(`Are you sure you want to clear the login lockout of "${email}"?`);
+    if (confirmed) {
+        _post(`${BASE_URL}/admin/users/${id}/unlock`,
+            "Login lockout cleared correctly",
+            "Error clearing the login lockout"
+        );
+    }
+}
+
+function clearEmailUndeliverable(event) {
+    event.preventDefault();
+    event.stopPropagation();
+    const id = event.target.parentNode.dataset.vwUserUuid;
+    const email = event.target.parentNode.dataset.vwUserEmail;
+    if (!id || !email) {
+        alert("Required parameters not found!");
+        return false;
+    }
+    const confirmed = confirm(`Are you sure you want to send mails to "${email}" again?`);
+    if (confirmed) {
+        _post(`${BASE_URL}/admin/users/${id}/email-deliverable`,
+            "Email marked as deliverable correctly",
+            "Error marking the email as deliverable"
+        );
+    }
+}
+
 function disableUser(event) {
     event.preventDefault();
     event.stopPropagation();
@@ -235,6 +289,139 @@ userOrgTypeDialog.addEventListener("hide.bs.modal", function() {
     document.getElementById("userOrgTypeOrgUuid").value = "";
 }, false);
 
+async function loadKdfCampaign() {
+    try {
+        const resp = await fetch(`${BASE_URL}/admin/kdf-campaign`);
+        if (!resp.ok) {
+            throw new Error(resp.statusText);
+        }
+        const campaign = await resp.json();
+        document.getElementById("kdfCampaignMinimum").textContent = campaign.minimum;
+        document.getElementById("kdfCampaignBelow").textContent = campaign.below_minimum;
+        document.getElementById("kdfCampaignTotal").textContent = campaign.total_users;
+        document.getElementById("kdfCampaignMigrated").textContent = campaign.migrated;
+        document.getElementById("kdfCampaignNotified").textContent = campaign.notified;
+        document.getElementById("kdfCampaignLast").textContent = campaign.last_campaign ?? "Never";
+    } catch (err) {
+        document.getElementById("kdfCampaignMinimum").textContent = `Error loading KDF migration status: ${err.message}`;
+    }
+}
+
+function startKdfCampaign(event) {
+    event.preventDefault();
+    event.stopPropagation();
+    const confirmed = confirm("Are you sure you want to email all users below the KDF minimum?");
+    if (confirmed) {
+        _post(`${BASE_URL}/admin/kdf-campaign`,
+            "KDF migration campaign started",
+            "Error starting the KDF migration campaign"
+        );
+    }
+}
+
+function showLdapSyncReport(report) {
+    const list = (values) => values.length ? values.join(", ") : "None";
+    document.getElementById("ldapSyncDate").textContent = report.dry_run ? `${report.date} (dry run)` : report.date;
+    document.getElementById("ldapSyncUsers").textContent = report.directory_users;
+    document.getElementById("ldapSyncInvited").textContent = list(report.invited);
+    document.getElementById("ldapSyncLinked").textContent = list(report.linked);
+    document.getElementById("ldapSyncRestored").textContent = list(report.restored);
+    document.getElementById("ldapSyncRevoked").textContent = list(report.revoked);
+    document.getElementById("ldapSyncErrors").textContent = list(report.errors);
+}
+
+async function loadLdapSync() {
+    try {
+        const resp = await fetch(`${BASE_URL}/admin/ldap-sync`);
+        if (!resp.ok) {
+            throw new Error(resp.statusText);
+        }
+        const sync = await resp.json();
+        if (!sync.enabled) {
+            return;
+        }
+        document.getElementById("ldapSyncBlock").classList.remove("d-none");
+        if (sync.report) {
+            showLdapSyncReport(sync.report);
+        } else {
+            document.getElementById("ldapSyncDate").textContent = sync.running ? "Running" : "Never";
+        }
+    } catch (err) {
+        document.getElementById("ldapSyncBlock").classList.remove("d-none");
+        document.getElementById("ldapSyncDate").textContent = `Error loading LDAP sync status: ${err.message}`;
+    }
+}
+
+async function runLdapSync(dryRun) {
+    try {
+        const resp = await fetch(`${BASE_URL}/admin/ldap-sync?dry_run=${dryRun}`, {
+            method: "POST",
+            headers: { "Content-Type": "application/json" }
+        });
+        const body = await resp.json();
+        if (!resp.ok) {
+            throw new Error(body.message ?? resp.statusText);
+        }
+        showLdapSyncReport(body);
+    } catch (err) {
+        alert(`Error running the LDAP sync: ${err.message}`);
+    }
+}
+
+const userLoginsDialog = document.getElementById("userLoginsDialog");
+// Load the recorded login attempts of the user
+userLoginsDialog.addEventListener("show.bs.modal", async function(event) {
+    const userEmail = event.relatedTarget.parentNode.dataset.vwUserEmail;
+    const userUuid = event.relatedTarget.parentNode.dataset.vwUserUuid;
+    document.getElementById("userLoginsDialogUserEmail").textContent = userEmail;
+
+    const tbody = document.getElementById("userLoginsTableBody");
+    tbody.replaceChildren();
+    try {
+        const resp = await fetch(`${BASE_URL}/admin/users/${userUuid}/logins`);
+        if (!resp.ok) {
+            throw new Error(resp.statusText);
+        }
+        const logins = await resp.json();
+        for (const login of logins) {
+            const row = tbody.insertRow();
+            const device = login.deviceName ? `${login.deviceName} (${login.deviceType})` : (login.deviceType ?? "");
+            for (const value of [login.date, login.success ? "Success" : "Failed", login.grantType, login.ipAddress, login.country, device, login.userAgent]) {
+                row.insertCell().textContent = value ?? "";
+            }
+        }
+    } catch (err) {
+        tbody.insertRow().insertCell().textContent = `Error loading login history: ${err.message}`;
+    }
+}, false);
+
+const userTokenFamiliesDialog = document.getElementById("userTokenFamiliesDialog");
+// Load the refresh token families of the devices of the user
+userTokenFamiliesDialog.addEventListener("show.bs.modal", async function(event) {
+    const userEmail = event.relatedTarget.parentNode.dataset.vwUserEmail;
+    const userUuid = event.relatedTarget.parentNode.dataset.vwUserUuid;
+    document.getElementById("userTokenFamiliesDialogUserEmail").textContent = userEmail;
+
+    const tbody = document.getElementById("userTokenFamiliesTableBody");
+    tbody.replaceChildren();
+    try {
+        const resp = await fetch(`${BASE_URL}/admin/users/${userUuid}/token-families`);
+        if (!resp.ok) {
+            throw new Error(resp.statusText);
+        }
+        const families = await resp.json();
+        for (const family of families) {
+            const row = tbody.insertRow();
+            const device = family.deviceName ? `${family.deviceName} (${family.deviceType})` : family.deviceId;
+            for (const value of [device, family.familyId, family.generation, family.creationDate, family.rotationDate, family.lastIpAddress]) {
+                row.insertCell().textContent = value ?? "";
+            }
+        }
+    } catch (err) {
+        tbody.insertRow().insertCell().textContent = `Error loading refresh token families: ${err.message}`;
+    }
+}, false);
+
 function updateUserOrgType(event) {
     event.preventDefault();
     event.stopPropagation();
@@ -265,9 +452,18 @@ function initUserTable() {
     document.querySelectorAll("button[vw-deauth-user]").forEach(btn => {
         btn.addEventListener("click", deauthUser);
     });
+    document.querySelectorAll("button[vw-unlock-user]").forEach(btn => {
+        btn.addEventListener("click", unlockUser);
+    });
+    document.querySelectorAll("button[vw-clear-undeliverable]").forEach(btn => {
+        btn.addEventListener("click", clearEmailUndeliverable);
+    });
     document.querySelectorAll("button[vw-delete-user]").forEach(btn => {
         btn.addEventListener("click", deleteUser);
     });
+    document.querySelectorAll("button[vw-restore-user]").forEach(btn => {
+        btn.addEventListener("click", restoreUser);
+    });
     document.querySelectorAll("button[vw-delete-sso-user]").forEach(btn => {
         btn.addEventListener("click", deleteSSOUser);
     });
@@ -326,6 +522,22 @@ document.addEventListener("DOMContentLoaded", (/*event*/) => {
     if (btnUserOrgTypeForm) {
         btnUserOrgTypeForm.addEventListener("submit", updateUserOrgType);
     }
+    const btnStartKdfCampaign = document.getElementById("startKdfCampaign");
+    if (btnStartKdfCampaign) {
+        btnStartKdfCampaign.addEventListener("click", startKdfCampaign);
+    }
+    loadKdfCampaign();
+
+    const btnRunLdapSync = document.getElementById("runLdapSync");
+    if (btnRunLdapSync) {
+        btnRunLdapSync.addEventListener("click", () => runLdapSync(false));
+    }
+    const btnDryRunLdapSync = document.getElementById("dryRunLdapSync");
+    if (btnDryRunLdapSync) {
+        btnDryRunLdapSync.addEventListener("click", () => runLdapSync(true));
+    }
+    loadLdapSync();
+
     const btnInviteUserForm = document.getElementById("inviteUserForm");
     if (btnInviteUserForm) {
         btnInviteUserForm.addEventListener("submit", inviteUser);
@@ -1,23 +1,66 @@
 //
 // Web Headers and caching
 //
-use std::{collections::HashMap, io::Cursor, path::Path};
+use std::{collections::HashMap, io::Cursor, path::Path, sync::Mutex};
 
 use num_traits::ToPrimitive;
+use once_cell::sync::Lazy;
 use rocket::{
     fairing::{Fairing, Info, Kind},
-    http::{ContentType, Header, HeaderMap, Method, Status},
+    http::{uri::Origin, ContentType, Header, HeaderMap, Method, Status},
     response::{self, Responder},
     Data, Orbit, Request, Response, Rocket,
 };
 
 use tokio::{
     runtime::Handle,
+    task,
     time::{sleep, Duration},
 };
 
 use crate::{config::PathType, CONFIG};
 
+/// Correlation ID of a request, echoed in the `X-Request-Id` response header, the request logs and error responses.
+/// A valid `X-Request-Id` header of the request is honored, e.g. one set by a reverse proxy.
+pub struct RequestId(String);
+
+// The ID of the request each task is handling, Rocket handles every request in a task of its own
+static REQUEST_TASKS: Lazy<Mutex<HashMap<task::Id, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+impl RequestId {
+    /// Returns the ID of the request the current task is handling, the logger adds it to every line logged while
+    /// handling the request. Tasks spawned by the handlers don't have it.
+    pub fn current() -> Option<String> {
+        let task_id = task::try_id()?;
+        REQUEST_TASKS.lock().ok()?.get(&task_id).cloned()
+    }
+
+    fn enter(request: &Request<'_>) {
+        if let (Some(task_id), Ok(mut tasks)) = (task::try_id(), REQUEST_TASKS.lock()) {
+            tasks.insert(task_id, Self::get(request).to_string());
+        }
+    }
+
+    fn leave() {
+        if let (Some(task_id), Ok(mut tasks)) = (task::try_id(), REQUEST_TASKS.lock()) {
+            tasks.remove(&task_id);
+        }
+    }
+
+    pub fn get<'r>(request: &'r Request<'_>) -> &'r str {
+        &request
+            .local_cache(|| {
+                let id = request.headers().get_one("X-Request-Id").filter(|id| {
+                    !id.is_empty()
+                        && id.len() <= 64
+                        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                });
+                RequestId(id.map_or_else(get_uuid, str::to_string))
+            })
+            .0
+    }
+}
+
 pub struct AppHeaders();
 
 #[rocket::async_trait]
@@ -50,6 +93,8 @@ impl Fairing for AppHeaders {
             }
         }
 
+        res.set_raw_header("X-Request-Id", RequestId::get(req).to_string());
+
         // NOTE: When modifying or adding security headers be sure to also update the diagnostic checks in `src/static/scripts/admin_diagnostics.js` in `checkSecurityHeaders`
         res.set_raw_header("Permissions-Policy", "accelerometer=(), ambient-light-sensor=(), autoplay=(), battery=(), camera=(), display-capture=(), document-domain=(), encrypted-media=(), execution-while-not-rendered=(), execution-while-out-of-viewport=(), fullscreen=(), geolocation=(), gyroscope=(), keyboard-map=(), magnetometer=(), microphone=(), midi=(), payment=(), picture-in-picture=(), screen-wake-lock=(), sync-xhr=(), usb=(), web-share=(), xr-spatial-tracking=()");
         res.set_raw_header("Referrer-Policy", "same-origin");
@@ -199,6 +244,7 @@ pub struct Cached<R> {
     response: R,
     is_immutable: bool,
     ttl: u64,
+    etag: Option<String>,
 }
 
 impl<R> Cached<R> {
@@ -207,6 +253,7 @@ impl<R> Cached<R> {
             response,
             is_immutable,
             ttl: 604800, // 7 days
+            etag: None,
         }
     }
 
@@ -215,6 +262,7 @@ impl<R> Cached<R> {
             response,
             is_immutable,
             ttl: 600, // 10 minutes
+            etag: None,
         }
     }
 
@@ -223,13 +271,35 @@ impl<R> Cached<R> {
             response,
             is_immutable,
             ttl,
+            etag: None,
         }
     }
+
+    /// Sets the `ETag` of the response, a matching `If-None-Match` of the request is answered with a 304 Not Modified.
+    pub fn etag(mut self, etag: String) -> Cached<R> {
+        self.etag = Some(etag);
+        self
+    }
 }
 
 impl<'r, R: 'r + Responder<'r, 'static> + Send> Responder<'r, 'static> for Cached<R> {
     fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
-        let mut res = self.response.respond_to(request)?;
+        let etag = self.etag.map(|etag| format!("\"{etag}\""));
+        let not_modified = etag.as_ref().is_some_and(|etag| {
+            request.headers().get("If-None-Match").flat_map(|v| v.split(',')).any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") == etag
+            })
+        });
+
+        let mut res = if not_modified {
+            Response::build().status(Status::NotModified).finalize()
+        } else {
+            self.response.respond_to(request)?
+        };
+        if let Some(etag) = etag {
+            res.set_raw_header("ETag", etag);
+        }
 
         let cache_control_header = if self.is_immutable {
             format!("public, immutable, max-age={}", self.ttl)
@@ -285,6 +355,7 @@ impl Fairing for BetterLogging {
     }
 
     async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        RequestId::enter(request);
         let method = request.method();
         if !self.0 && method == Method::Options {
             return;
@@ -302,6 +373,13 @@ impl Fairing for BetterLogging {
     }
 
     async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        self.log_response(request, response);
+        RequestId::leave();
+    }
+}
+
+impl BetterLogging {
+    fn log_response(&self, request: &Request<'_>, response: &Response<'_>) {
         if !self.0 && request.method() == Method::Options {
             return;
         }
@@ -319,6 +397,63 @@ impl Fairing for BetterLogging {
     }
 }
 
+// These stay available during a maintenance lockout, so the admin can still turn it off
+const MAINTENANCE_EXEMPT_ROUTES: [&str; 5] = ["/admin", "/custom", "/alive", "/vw_static", "/vw_maintenance"];
+
+/// Enforces `MAINTENANCE_MODE` by rerouting the refused requests to the maintenance endpoint.
+/// - `read_only`: changes via the API are refused, logins and syncing still work
+/// - `auth_only`: existing sessions keep working and can refresh their tokens, but there are no new logins
+/// - `lockout`: only the admin panel and the custom API are available
+pub struct MaintenanceMode();
+
+#[rocket::async_trait]
+impl Fairing for MaintenanceMode {
+    fn info(&self) -> Info {
+        Info {
+            name: "Maintenance Mode",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        let mode = CONFIG.maintenance_mode();
+        let method = request.method();
+        if mode == "off" || method == Method::Options {
+            return;
+        }
+
+        let uri_path_str = request.uri().path().url_decode_lossy();
+        let uri_subpath = uri_path_str.strip_prefix(&CONFIG.domain_path()).unwrap_or(&uri_path_str);
+        let is_write = !matches!(method, Method::Get | Method::Head);
+        let blocked = match mode.as_str() {
+            "read_only" => uri_subpath.starts_with("/api/") && is_write && uri_subpath != "/api/accounts/prelogin",
+            "auth_only" => {
+                (uri_subpath.starts_with("/identity/") && !is_refresh_grant(data).await)
+                    || (uri_subpath == "/api/auth-requests" && is_write)
+                    || uri_subpath.starts_with("/api/accounts/register")
+            }
+            _ => !MAINTENANCE_EXEMPT_ROUTES.iter().any(|r| uri_subpath.starts_with(r)),
+        };
+
+        if blocked {
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse_owned(format!("{}/vw_maintenance", CONFIG.domain_path())).unwrap());
+        }
+    }
+}
+
+/// Whether the token request is a refresh, by the `grant_type` field of the form.
+/// Only the start of the body is peeked, a field which is cut off there isn't compared.
+async fn is_refresh_grant(data: &mut Data<'_>) -> bool {
+    let mut body = data.peek(512).await.to_vec();
+    if !data.peek_complete() {
+        body.truncate(body.iter().rposition(|b| *b == b'&').unwrap_or(0));
+    }
+    url::form_urlencoded::parse(&body)
+        .find(|(key, _)| key == "grant_type")
+        .is_some_and(|(_, value)| value == "refresh_token")
+}
+
 pub fn get_display_size(size: i64) -> String {
     const UNITS: [&str; 6] = ["bytes", "KB", "MB", "GB", "TB", "PB"];
 
@@ -719,6 +854,47 @@ where
     }
 }
 
+//
+// Background tasks
+//
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::Notify;
+
+// The number of tasks spawned with `spawn_background`, which a graceful shutdown waits for
+static BACKGROUND_TASKS: AtomicUsize = AtomicUsize::new(0);
+static BACKGROUND_TASKS_DONE: Notify = Notify::const_new();
+
+/// Spawns a task which isn't awaited by the request that started it, like a webhook or a large import.
+/// A graceful shutdown waits for these tasks to finish before the process exits.
+pub fn spawn_background<F>(task: F)
+where
+    F: std::future::Future + Send + 'static,
+{
+    BACKGROUND_TASKS.fetch_add(1, Ordering::SeqCst);
+    tokio::spawn(async move {
+        task.await;
+        if BACKGROUND_TASKS.fetch_sub(1, Ordering::SeqCst) == 1 {
+            BACKGROUND_TASKS_DONE.notify_waiters();
+        }
+    });
+}
+
+/// Waits until the background tasks finished, returns false when some were still running after the timeout.
+pub async fn wait_for_background_tasks(timeout: Duration) -> bool {
+    tokio::time::timeout(timeout, async {
+        loop {
+            let done = BACKGROUND_TASKS_DONE.notified();
+            if BACKGROUND_TASKS.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            done.await;
+        }
+    })
+    .await
+    .is_ok()
+}
+
 pub fn convert_json_key_lcase_first(src_json: Value) -> Value {
     match src_json {
         Value::Array(elm) => {
@@ -859,6 +1035,26 @@ pub async fn save_temp_file(
     Ok(())
 }
 
+/// Stores a file of the local file system, like an upload assembled from chunks, see `save_temp_file`.
+pub async fn save_local_file(
+    path_type: PathType,
+    path: &str,
+    local_path: &std::path::Path,
+    overwrite: bool,
+) -> Result<(), crate::Error> {
+    use futures::AsyncWriteExt as _;
+    use tokio_util::compat::TokioAsyncReadCompatExt as _;
+
+    let operator = CONFIG.opendal_operator_for_path_type(path_type)?;
+
+    let mut read_stream = tokio::fs::File::open(local_path).await?.compat();
+    let mut writer = operator.writer_with(path).if_not_exists(!overwrite).await?.into_futures_async_write();
+    futures::io::copy(&mut read_stream, &mut writer).await?;
+    writer.close().await?;
+
+    Ok(())
+}
+
 /// These are some tests to check that the implementations match
 /// The IPv4 can be all checked in 30 seconds or so and they are correct as of nightly 2023-07-17
 /// The IPV6 can't be checked in a reasonable time, so we check over a hundred billion random ones, so far correct
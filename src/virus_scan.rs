@@ -0,0 +1,157 @@
+//
+// Scans uploaded attachments and Send files before they are persisted.
+// Supports clamd (`clamd://host:3310`) via its INSTREAM command and ICAP servers (`icap://host:1344/service`) via RESPMOD.
+//
+use std::{path::Path, time::Duration};
+
+use rocket::fs::TempFile;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use url::Url;
+
+use crate::{api::ApiResult, error::Error, CONFIG};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Scans the uploaded file with the scanner of `VIRUS_SCAN_URL`.
+/// Returns the signature name when the file is infected, or None when it is clean or no scanner is configured.
+/// When the scanner can't be reached the upload is rejected, unless `VIRUS_SCAN_FAIL_OPEN` is enabled.
+pub async fn scan_temp_file(file: &TempFile<'_>) -> ApiResult<Option<String>> {
+    if CONFIG.virus_scan_url().is_none() {
+        return Ok(None);
+    }
+    scan_reader(Box::pin(file.open().await?)).await
+}
+
+/// Scans a file of the local file system, like an upload assembled from chunks, see `scan_temp_file`.
+pub async fn scan_path(path: &Path) -> ApiResult<Option<String>> {
+    if CONFIG.virus_scan_url().is_none() {
+        return Ok(None);
+    }
+    scan_reader(tokio::fs::File::open(path).await?).await
+}
+
+async fn scan_reader(reader: impl AsyncRead + Unpin) -> ApiResult<Option<String>> {
+    let Some(scan_url) = CONFIG.virus_scan_url() else {
+        return Ok(None);
+    };
+
+    let timeout = Duration::from_secs(CONFIG.virus_scan_timeout());
+    let result = match tokio::time::timeout(timeout, scan(&scan_url, reader)).await {
+        Ok(result) => result,
+        Err(_) => Err(Error::new("Virus scan timed out", format!("No answer within {} seconds", timeout.as_secs()))),
+    };
+
+    match result {
+        Ok(signature) => Ok(signature),
+        Err(e) if CONFIG.virus_scan_fail_open() => {
+            warn!("Virus scan failed, accepting the upload without a scan: {e:?}");
+            Ok(None)
+        }
+        Err(e) => err!("The file could not be scanned for viruses", format!("Virus scan failed: {e:?}")),
+    }
+}
+
+async fn scan(scan_url: &str, reader: impl AsyncRead + Unpin) -> ApiResult<Option<String>> {
+    let url = Url::parse(scan_url).map_err(|e| Error::new("Invalid VIRUS_SCAN_URL", e.to_string()))?;
+    let Some(host) = url.host_str() else {
+        err!("VIRUS_SCAN_URL has no host")
+    };
+
+    match url.scheme() {
+        "clamd" => {
+            let stream = TcpStream::connect((host, url.port().unwrap_or(3310))).await?;
+            scan_clamd(stream, reader).await
+        }
+        "icap" => {
+            let stream = TcpStream::connect((host, url.port().unwrap_or(1344))).await?;
+            scan_icap(stream, reader, &url).await
+        }
+        scheme => err!(format!("Unsupported virus scanner scheme `{scheme}`")),
+    }
+}
+
+/// https://docs.clamav.net/manual/Usage/Scanning.html#instream
+async fn scan_clamd(mut stream: TcpStream, mut reader: impl AsyncRead + Unpin) -> ApiResult<Option<String>> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = reader.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&(read as u32).to_be_bytes()).await?;
+        stream.write_all(&buf[..read]).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    BufReader::new(stream).read_until(b'\0', &mut response).await?;
+    let response = String::from_utf8_lossy(&response).trim_end_matches('\0').trim().to_string();
+
+    // The response is either `stream: OK`, `stream: <signature> FOUND` or `<reason> ERROR`
+    match response.strip_prefix("stream: ") {
+        Some("OK") => Ok(None),
+        Some(found) if found.ends_with(" FOUND") => Ok(Some(found.trim_end_matches(" FOUND").to_string())),
+        _ => err!(format!("Unexpected clamd response `{response}`")),
+    }
+}
+
+/// https://www.rfc-editor.org/rfc/rfc3507#section-4.9.2
+async fn scan_icap(mut stream: TcpStream, mut reader: impl AsyncRead + Unpin, url: &Url) -> ApiResult<Option<String>> {
+    let http_headers = "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\r\n";
+    let request = format!(
+        "RESPMOD {url} ICAP/1.0\r\nHost: {}\r\nAllow: 204\r\nEncapsulated: res-hdr=0, res-body={}\r\n\r\n{http_headers}",
+        url.host_str().unwrap_or_default(),
+        http_headers.len()
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = reader.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(format!("{read:x}\r\n").as_bytes()).await?;
+        stream.write_all(&buf[..read]).await?;
+        stream.write_all(b"\r\n").await?;
+    }
+    stream.write_all(b"0\r\n\r\n").await?;
+    stream.flush().await?;
+
+    let mut stream = BufReader::new(stream);
+    let mut status_line = String::new();
+    stream.read_line(&mut status_line).await?;
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    // 204 means the server didn't modify the content, so nothing was found
+    match status_line.split_whitespace().nth(1) {
+        Some("204") => Ok(None),
+        Some("200") => {
+            let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone());
+            let signature = header("x-virus-id")
+                .or_else(|| {
+                    // Formatted like `Type=0; Resolution=2; Threat=<signature>;`
+                    header("x-infection-found")?
+                        .split(';')
+                        .find_map(|part| part.trim().strip_prefix("Threat=").map(str::to_string))
+                })
+                .unwrap_or_else(|| String::from("Unknown"));
+            Ok(Some(signature))
+        }
+        _ => err!(format!("Unexpected ICAP response `{}`", status_line.trim())),
+    }
+}